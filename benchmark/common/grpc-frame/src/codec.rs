@@ -0,0 +1,137 @@
+//! Message compression for frames with the compression flag set.
+//!
+//! The codec is chosen per stream by the `grpc-encoding` header; the flag in
+//! each frame only says whether that codec was applied to the message.
+
+use std::io::{self, Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// Message encodings understood by the filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Identity,
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Encoding {
+    /// Parses a `grpc-encoding` header value. Returns `None` for codecs the
+    /// filters cannot handle.
+    pub fn from_header(value: &str) -> Option<Encoding> {
+        match value.trim() {
+            "" | "identity" => Some(Encoding::Identity),
+            "gzip" => Some(Encoding::Gzip),
+            #[cfg(feature = "zstd")]
+            "zstd" => Some(Encoding::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The `grpc-encoding` header value for this encoding.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// Whether a `grpc-accept-encoding` list advertises this encoding.
+    /// Identity is always accepted.
+    pub fn accepted_by(&self, accept: &str) -> bool {
+        *self == Encoding::Identity || accept.split(',').any(|e| e.trim() == self.as_str())
+    }
+}
+
+/// Appends an encoding to a `grpc-accept-encoding` list unless it is already
+/// advertised.
+pub fn advertise(accept: Option<&str>, encoding: Encoding) -> String {
+    match accept.map(str::trim).filter(|a| !a.is_empty()) {
+        Some(accept) if encoding.accepted_by(accept) => accept.to_string(),
+        Some(accept) => format!("{},{}", accept, encoding.as_str()),
+        None => encoding.as_str().to_string(),
+    }
+}
+
+/// Decompresses a message payload.
+pub fn decompress(encoding: Encoding, payload: &[u8]) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Identity => Ok(payload.to_vec()),
+        Encoding::Gzip => {
+            let mut out = Vec::with_capacity(payload.len() * 2);
+            GzDecoder::new(payload).read_to_end(&mut out)?;
+            Ok(out)
+        }
+        #[cfg(feature = "zstd")]
+        Encoding::Zstd => {
+            let mut out = Vec::with_capacity(payload.len() * 2);
+            ruzstd::decoding::StreamingDecoder::new(payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+                .read_to_end(&mut out)?;
+            Ok(out)
+        }
+    }
+}
+
+/// Compresses a message payload.
+pub fn compress(encoding: Encoding, payload: &[u8]) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Identity => Ok(payload.to_vec()),
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(payload)?;
+            encoder.finish()
+        }
+        #[cfg(feature = "zstd")]
+        Encoding::Zstd => Ok(ruzstd::encoding::compress_to_vec(
+            payload,
+            ruzstd::encoding::CompressionLevel::Fastest,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_roundtrip() {
+        let payload = b"hello hello hello hello";
+        let compressed = compress(Encoding::Gzip, payload).unwrap();
+        assert_ne!(compressed, payload);
+        assert_eq!(decompress(Encoding::Gzip, &compressed).unwrap(), payload);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_roundtrip() {
+        let payload = b"value ".repeat(64);
+        let compressed = compress(Encoding::Zstd, &payload).unwrap();
+        assert!(compressed.len() < payload.len());
+        assert_eq!(decompress(Encoding::Zstd, &compressed).unwrap(), payload);
+        assert!(decompress(Encoding::Zstd, b"not zstd").is_err());
+    }
+
+    #[test]
+    fn accept_encoding() {
+        assert!(Encoding::Gzip.accepted_by("identity, gzip"));
+        assert!(!Encoding::Gzip.accepted_by("identity,deflate"));
+        assert!(Encoding::Identity.accepted_by(""));
+        assert_eq!(advertise(Some("gzip"), Encoding::Gzip), "gzip");
+        assert_eq!(advertise(Some("identity"), Encoding::Gzip), "identity,gzip");
+        assert_eq!(advertise(None, Encoding::Gzip), "gzip");
+    }
+
+    #[test]
+    fn parse_header() {
+        assert_eq!(Encoding::from_header("gzip"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::from_header("identity"), Some(Encoding::Identity));
+        assert_eq!(Encoding::from_header("snappy"), None);
+    }
+}
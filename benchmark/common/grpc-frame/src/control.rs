@@ -0,0 +1,304 @@
+//! Runtime configuration updates over a shared queue.
+//!
+//! Envoy only reconfigures a WASM filter by pushing a new listener, which
+//! drains its connections. Instead, a control singleton (a `WasmService` with
+//! the filter's `vm_id`) registers the queue `<filter>.config` and receives
+//! full configuration documents from an admin WASM service or an HTTP poller
+//! via [`ConfigChannel::push`]. It validates each update and publishes it to
+//! shared data as `<filter>.config`, bumping `<filter>.config.version`.
+//!
+//! The control singleton can also poll an HTTP endpoint for the document:
+//! with a [`Poll`] configured it calls [`ConfigChannel::poll`] from `on_tick`
+//! and hands the response to [`ConfigChannel::poll_response`], which
+//! publishes the document when it changed.
+//!
+//! Worker roots keep their configuration in a [`Watched`] and check the
+//! version before every new stream, reparsing only when it changed. Streams
+//! already in flight finish with the configuration they started with.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use proxy_wasm::traits::Context;
+use proxy_wasm::types::Status;
+use serde::Deserialize;
+
+/// Endpoint the control singleton polls for the configuration document,
+/// under `poll` in its plugin configuration:
+///
+/// ```json
+/// {"cluster": "outbound|8080||config-server.default.svc.cluster.local",
+///  "path": "/mutation", "interval_ms": 10000}
+/// ```
+///
+/// The endpoint answers a GET with the full document and status 200.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Poll {
+    pub cluster: String,
+    pub path: String,
+    pub authority: String,
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for Poll {
+    fn default() -> Self {
+        Poll {
+            cluster: String::new(),
+            path: "/".to_string(),
+            authority: "config-server".to_string(),
+            interval_ms: 10_000,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+impl Poll {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cluster.is_empty() {
+            return Err("poll.cluster is required".to_string());
+        }
+        // A slower response would overlap the next poll.
+        if self.timeout_ms == 0 || self.timeout_ms > self.interval_ms {
+            return Err(format!(
+                "poll.timeout_ms must be within 1..=interval_ms ({}): {}",
+                self.interval_ms, self.timeout_ms
+            ));
+        }
+        Ok(())
+    }
+
+    /// The tick period to set on the control singleton.
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+/// The queue and shared data keys of one filter's configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigChannel {
+    filter: &'static str,
+}
+
+impl ConfigChannel {
+    pub const fn new(filter: &'static str) -> ConfigChannel {
+        ConfigChannel { filter }
+    }
+
+    /// Name of both the update queue and the shared data key.
+    pub fn name(&self) -> String {
+        format!("{}.config", self.filter)
+    }
+
+    fn version_key(&self) -> String {
+        format!("{}.config.version", self.filter)
+    }
+
+    /// Registers the update queue. Called by the control singleton.
+    pub fn register<C: Context + ?Sized>(&self, ctx: &C) -> u32 {
+        ctx.register_shared_queue(&self.name())
+    }
+
+    /// Drains the update queue. Each update is checked with `validate`;
+    /// the last valid one is published. Returns the new version, if any.
+    pub fn drain<C, V>(&self, ctx: &C, queue_id: u32, validate: V) -> Option<u64>
+    where
+        C: Context + ?Sized,
+        V: Fn(&[u8]) -> Result<(), String>,
+    {
+        let mut latest = None;
+        loop {
+            match ctx.dequeue_shared_queue(queue_id) {
+                Ok(Some(update)) => match validate(&update) {
+                    Ok(()) => latest = Some(update),
+                    Err(e) => log::warn!("rejected {} update: {}", self.name(), e),
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("failed to dequeue {} update: {:?}", self.name(), e);
+                    break;
+                }
+            }
+        }
+        self.publish_update(ctx, &latest?)
+    }
+
+    /// Requests the configuration document from the poll endpoint. Called
+    /// by the control singleton from `on_tick`.
+    pub fn poll<C: Context + ?Sized>(&self, ctx: &C, poll: &Poll) -> Option<u32> {
+        match ctx.dispatch_http_call(
+            &poll.cluster,
+            vec![
+                (":method", "GET"),
+                (":path", &poll.path),
+                (":authority", &poll.authority),
+                ("accept", "application/json"),
+            ],
+            None,
+            vec![],
+            Duration::from_millis(poll.timeout_ms),
+        ) {
+            Ok(token) => Some(token),
+            Err(e) => {
+                log::warn!("failed to poll {}: {:?}", self.name(), e);
+                None
+            }
+        }
+    }
+
+    /// Handles the response to [`ConfigChannel::poll`] in
+    /// `on_http_call_response`. The document is checked with `validate` and
+    /// published if it differs from the published one, so an unchanged
+    /// endpoint does not make the workers reparse. Returns the new version,
+    /// if any.
+    pub fn poll_response<C, V>(&self, ctx: &C, body_size: usize, validate: V) -> Option<u64>
+    where
+        C: Context + ?Sized,
+        V: Fn(&[u8]) -> Result<(), String>,
+    {
+        let status = ctx.get_http_call_response_header(":status");
+        if status.as_deref() != Some("200") {
+            log::warn!("{} poll failed: status {:?}", self.name(), status);
+            return None;
+        }
+        let update = ctx
+            .get_http_call_response_body(0, body_size)
+            .unwrap_or_default();
+        let (published, _) = ctx.get_shared_data(&self.name());
+        if published.as_deref() == Some(&update[..]) {
+            return None;
+        }
+        if let Err(e) = validate(&update) {
+            log::warn!("rejected polled {}: {}", self.name(), e);
+            return None;
+        }
+        self.publish_update(ctx, &update)
+    }
+
+    fn publish_update<C: Context + ?Sized>(&self, ctx: &C, update: &[u8]) -> Option<u64> {
+        match self.publish(ctx, update) {
+            Ok(version) => {
+                log::warn!("published {} version {}", self.name(), version);
+                Some(version)
+            }
+            Err(e) => {
+                log::warn!("failed to publish {} update: {:?}", self.name(), e);
+                None
+            }
+        }
+    }
+
+    /// Stores a configuration and bumps its version. The configuration is
+    /// written first, so a worker never sees a version without its data.
+    fn publish<C: Context + ?Sized>(&self, ctx: &C, config: &[u8]) -> Result<u64, Status> {
+        let version = self.version(ctx) + 1;
+        ctx.set_shared_data(&self.name(), Some(config), None)?;
+        ctx.set_shared_data(&self.version_key(), Some(&version.to_be_bytes()), None)?;
+        Ok(version)
+    }
+
+    /// The published version; 0 if nothing was published yet.
+    pub fn version<C: Context + ?Sized>(&self, ctx: &C) -> u64 {
+        let (data, _) = ctx.get_shared_data(&self.version_key());
+        data.as_deref().map_or(0, decode_version)
+    }
+
+    /// Sends a configuration update to the control singleton of `vm_id`.
+    pub fn push<C: Context + ?Sized>(
+        &self,
+        ctx: &C,
+        vm_id: &str,
+        config: &[u8],
+    ) -> Result<(), Status> {
+        let queue_id = ctx
+            .resolve_shared_queue(vm_id, &self.name())
+            .ok_or(Status::NotFound)?;
+        ctx.enqueue_shared_queue(queue_id, Some(config))
+    }
+}
+
+fn decode_version(data: &[u8]) -> u64 {
+    data.try_into().map_or(0, u64::from_be_bytes)
+}
+
+/// A worker's configuration, reloaded when the control singleton publishes
+/// a newer version.
+pub struct Watched<T> {
+    channel: ConfigChannel,
+    // Version `current` was loaded from; 0 for the plugin configuration.
+    version: Cell<u64>,
+    current: RefCell<Rc<T>>,
+}
+
+impl<T> Watched<T> {
+    pub fn new(channel: ConfigChannel, initial: T) -> Watched<T> {
+        Watched {
+            channel,
+            version: Cell::new(0),
+            current: RefCell::new(Rc::new(initial)),
+        }
+    }
+
+    /// Replaces the configuration, e.g. from `on_configure`. A published
+    /// update the worker has not loaded yet still takes precedence.
+    pub fn set(&self, config: T) {
+        *self.current.borrow_mut() = Rc::new(config);
+    }
+
+    /// Returns the configuration without checking for updates.
+    pub fn current(&self) -> Rc<T> {
+        self.current.borrow().clone()
+    }
+
+    /// Returns the configuration, reloading it first if a newer version was
+    /// published. An update that fails to parse is skipped.
+    pub fn get<C, P>(&self, ctx: &C, parse: P) -> Rc<T>
+    where
+        C: Context + ?Sized,
+        P: Fn(&[u8]) -> Result<T, String>,
+    {
+        let version = self.channel.version(ctx);
+        if version > self.version.get() {
+            self.version.set(version);
+            let (data, _) = ctx.get_shared_data(&self.channel.name());
+            match data.as_deref().map(parse) {
+                Some(Ok(config)) => {
+                    log::warn!("loaded {} version {}", self.channel.name(), version);
+                    self.set(config);
+                }
+                Some(Err(e)) => {
+                    log::warn!("invalid {} version {}: {}", self.channel.name(), version, e)
+                }
+                None => log::warn!("{} version {} has no data", self.channel.name(), version),
+            }
+        }
+        self.current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        assert_eq!(ConfigChannel::new("acl").name(), "acl.config");
+        assert_eq!(decode_version(&7u64.to_be_bytes()), 7);
+        // Anything but 8 bytes reads as unpublished.
+        assert_eq!(decode_version(b"7"), 0);
+        assert_eq!(decode_version(&[]), 0);
+    }
+
+    #[test]
+    fn poll_validation() {
+        let mut poll = Poll::default();
+        assert!(poll.validate().is_err());
+        poll.cluster = "config-server".to_string();
+        assert_eq!(poll.validate(), Ok(()));
+        assert_eq!(poll.interval(), Duration::from_secs(10));
+        poll.timeout_ms = poll.interval_ms + 1;
+        assert!(poll.validate().is_err());
+    }
+}
@@ -0,0 +1,300 @@
+//! gRPC length-prefixed message framing shared by the Envoy WASM filters.
+//!
+//! Every gRPC message on the wire is preceded by a 5-byte header:
+//! `[compressed flag (1B)][message length (4B, big-endian)][message]`.
+//! A buffered HTTP body may hold several such frames back to back
+//! (streaming RPCs), and the last one may still be incomplete.
+
+use std::fmt;
+
+#[cfg(feature = "gzip")]
+pub mod codec;
+#[cfg(feature = "proxy-wasm")]
+pub mod control;
+pub mod record;
+#[cfg(feature = "proxy-wasm")]
+pub mod reply;
+#[cfg(feature = "proxy-wasm")]
+pub mod route;
+pub mod status;
+pub mod summary;
+pub mod trace;
+pub mod web;
+
+/// Size of the gRPC frame header (compression flag + length prefix).
+pub const HEADER_LEN: usize = 5;
+
+/// A single length-prefixed gRPC message borrowed from a body buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// Whether the compression flag is set, i.e. the payload is encoded
+    /// with the stream's `grpc-encoding`.
+    pub compressed: bool,
+    /// The serialized message, without the frame header.
+    pub payload: &'a [u8],
+}
+
+impl Frame<'_> {
+    /// Number of bytes the frame occupies on the wire, including the header.
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.payload.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer ends before the frame does; `needed` is the total number
+    /// of bytes required to hold the complete frame.
+    Incomplete { needed: usize },
+    /// The compression flag is neither 0 nor 1.
+    InvalidFlag(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Incomplete { needed } => {
+                write!(f, "incomplete gRPC frame: need {} bytes", needed)
+            }
+            Error::InvalidFlag(flag) => write!(f, "invalid gRPC compression flag: {}", flag),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Parses the frame at the start of `buf`.
+pub fn parse(buf: &[u8]) -> Result<Frame<'_>, Error> {
+    if buf.len() < HEADER_LEN {
+        return Err(Error::Incomplete { needed: HEADER_LEN });
+    }
+    let compressed = match buf[0] {
+        0 => false,
+        1 => true,
+        flag => return Err(Error::InvalidFlag(flag)),
+    };
+    let length = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+    let needed = HEADER_LEN + length;
+    if buf.len() < needed {
+        return Err(Error::Incomplete { needed });
+    }
+    Ok(Frame {
+        compressed,
+        payload: &buf[HEADER_LEN..needed],
+    })
+}
+
+/// Returns an iterator over the complete frames at the start of `buf`.
+pub fn frames(buf: &[u8]) -> Frames<'_> {
+    Frames { buf, offset: 0 }
+}
+
+/// Iterator over consecutive frames in a body buffer.
+///
+/// Iteration stops at the first incomplete frame; the unconsumed bytes are
+/// available through [`Frames::remainder`]. A malformed header is yielded
+/// once as an error and ends the iteration.
+pub struct Frames<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Frames<'a> {
+    /// Bytes that have not been consumed as complete frames.
+    pub fn remainder(&self) -> &'a [u8] {
+        &self.buf[self.offset..]
+    }
+
+    /// Offset of the first unconsumed byte in the original buffer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Frame<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.buf.len() {
+            return None;
+        }
+        match parse(&self.buf[self.offset..]) {
+            Ok(frame) => {
+                self.offset += frame.encoded_len();
+                Some(Ok(frame))
+            }
+            Err(Error::Incomplete { .. }) => None,
+            Err(e) => {
+                // Nothing after a corrupt header can be trusted.
+                self.offset = self.buf.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// An owned message produced by a [`Decoder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub compressed: bool,
+    pub payload: Vec<u8>,
+}
+
+/// Incremental decoder for a body that arrives in chunks.
+///
+/// Callers push only the bytes they have not seen yet and take messages out
+/// as soon as their frame is complete, so a body is scanned once no matter
+/// how many callbacks it spans. Only the unconsumed tail is kept.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    // Start of the unconsumed bytes in `buf`.
+    start: usize,
+    // Total bytes pushed so far.
+    received: usize,
+    failed: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Appends newly arrived body bytes.
+    pub fn push(&mut self, chunk: &[u8]) {
+        if self.start > 0 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        self.buf.extend_from_slice(chunk);
+        self.received += chunk.len();
+    }
+
+    /// Total number of bytes pushed, i.e. the offset of the next unread byte
+    /// in the body.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Bytes of an incomplete frame waiting for more data.
+    pub fn pending(&self) -> usize {
+        self.buf.len() - self.start
+    }
+}
+
+impl Iterator for Decoder {
+    type Item = Result<Message, Error>;
+
+    /// Returns the next complete message. A malformed header is returned
+    /// once, after which the decoder yields nothing.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match parse(&self.buf[self.start..]) {
+            Ok(frame) => {
+                let message = Message {
+                    compressed: frame.compressed,
+                    payload: frame.payload.to_vec(),
+                };
+                self.start += frame.encoded_len();
+                Some(Ok(message))
+            }
+            Err(Error::Incomplete { .. }) => None,
+            Err(e) => {
+                self.failed = true;
+                self.buf.clear();
+                self.start = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Appends `payload` to `buf` as a single frame.
+pub fn encode_into(buf: &mut Vec<u8>, payload: &[u8], compressed: bool) {
+    buf.reserve(HEADER_LEN + payload.len());
+    buf.push(compressed as u8);
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(payload);
+}
+
+/// Encodes `payload` as a single frame.
+pub fn encode(payload: &[u8], compressed: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+    encode_into(&mut buf, payload, compressed);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_roundtrip() {
+        let buf = encode(b"hello", false);
+        assert_eq!(buf, [0, 0, 0, 0, 5, b'h', b'e', b'l', b'l', b'o']);
+        let frame = parse(&buf).unwrap();
+        assert!(!frame.compressed);
+        assert_eq!(frame.payload, b"hello");
+        assert_eq!(frame.encoded_len(), buf.len());
+    }
+
+    #[test]
+    fn parse_incomplete_and_invalid() {
+        assert_eq!(parse(&[0, 0]), Err(Error::Incomplete { needed: 5 }));
+        assert_eq!(
+            parse(&[1, 0, 0, 0, 3, b'a']),
+            Err(Error::Incomplete { needed: 8 })
+        );
+        assert_eq!(parse(&[7, 0, 0, 0, 0]), Err(Error::InvalidFlag(7)));
+    }
+
+    #[test]
+    fn frames_stop_at_partial_frame() {
+        let mut buf = Vec::new();
+        encode_into(&mut buf, b"one", false);
+        encode_into(&mut buf, b"two", true);
+        buf.extend_from_slice(&[0, 0, 0, 0, 9, b'x']);
+
+        let mut iter = frames(&buf);
+        let first = iter.next().unwrap().unwrap();
+        let second = iter.next().unwrap().unwrap();
+        assert_eq!((first.compressed, first.payload), (false, &b"one"[..]));
+        assert_eq!((second.compressed, second.payload), (true, &b"two"[..]));
+        assert!(iter.next().is_none());
+        assert_eq!(iter.remainder(), &[0, 0, 0, 0, 9, b'x']);
+        assert_eq!(iter.offset(), 16);
+    }
+
+    #[test]
+    fn decoder_yields_frames_across_chunks() {
+        let mut body = encode(b"first", false);
+        encode_into(&mut body, b"second", true);
+        let mut decoder = Decoder::new();
+
+        decoder.push(&body[..3]);
+        assert_eq!(decoder.next(), None);
+        decoder.push(&body[3..12]);
+        let first = decoder.next().unwrap().unwrap();
+        assert_eq!(
+            (first.compressed, first.payload.as_slice()),
+            (false, &b"first"[..])
+        );
+        assert_eq!(decoder.next(), None);
+        assert_eq!(decoder.pending(), 2);
+
+        decoder.push(&body[12..]);
+        let second = decoder.next().unwrap().unwrap();
+        assert_eq!(
+            (second.compressed, second.payload.as_slice()),
+            (true, &b"second"[..])
+        );
+        assert_eq!(decoder.next(), None);
+        assert_eq!((decoder.received(), decoder.pending()), (body.len(), 0));
+
+        decoder.push(&[9, 0, 0, 0, 0]);
+        assert_eq!(decoder.next(), Some(Err(Error::InvalidFlag(9))));
+        assert_eq!(decoder.next(), None);
+    }
+}
@@ -0,0 +1,258 @@
+//! Binary records of captured RPCs.
+//!
+//! The capture filter pushes batches of records to a collector, which only
+//! has to append them to a file: records are self-delimiting, so batches
+//! concatenate into a valid capture. All integers are big-endian.
+//!
+//! ```text
+//! [record length (4B)][timestamp, µs since the epoch (8B)]
+//! [method length (2B)][method]
+//! [metadata entries (2B)] { [name length (2B)][name][value length (2B)][value] }
+//! [payload (rest of the record)]
+//! ```
+//!
+//! The record length counts everything after itself. The payload is the
+//! request body as received, i.e. gRPC frames, so it replays as-is along
+//! with its `grpc-encoding`.
+
+use std::fmt;
+
+/// Size of the record length prefix.
+pub const LEN_PREFIX: usize = 4;
+
+/// One captured request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub timestamp_us: u64,
+    /// The request path without the leading slash, e.g. `kv.KVStore/Get`.
+    pub method: String,
+    /// Request headers, without pseudo-headers.
+    pub metadata: Vec<(String, String)>,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer ends before the record does; `needed` is the total number
+    /// of bytes required to hold the complete record.
+    Incomplete { needed: usize },
+    /// The record's fields do not add up to its length, or a string is not
+    /// UTF-8.
+    Malformed(&'static str),
+    /// A field does not fit its length prefix.
+    TooLong(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Incomplete { needed } => {
+                write!(f, "incomplete record: need {} bytes", needed)
+            }
+            Error::Malformed(what) => write!(f, "malformed record: {}", what),
+            Error::TooLong(what) => write!(f, "record {} too long", what),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Record {
+    /// Appends the encoded record to `buf`; on error `buf` is unchanged.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        let start = buf.len();
+        buf.extend_from_slice(&[0; LEN_PREFIX]);
+        buf.extend_from_slice(&self.timestamp_us.to_be_bytes());
+        let result = put_str(buf, &self.method, "method").and_then(|()| {
+            let count =
+                u16::try_from(self.metadata.len()).map_err(|_| Error::TooLong("metadata"))?;
+            buf.extend_from_slice(&count.to_be_bytes());
+            for (name, value) in &self.metadata {
+                put_str(buf, name, "metadata name")?;
+                put_str(buf, value, "metadata value")?;
+            }
+            buf.extend_from_slice(&self.payload);
+            u32::try_from(buf.len() - start - LEN_PREFIX).map_err(|_| Error::TooLong("payload"))
+        });
+        match result {
+            Ok(len) => {
+                buf[start..start + LEN_PREFIX].copy_from_slice(&len.to_be_bytes());
+                Ok(())
+            }
+            Err(e) => {
+                buf.truncate(start);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str, what: &'static str) -> Result<(), Error> {
+    let len = u16::try_from(s.len()).map_err(|_| Error::TooLong(what))?;
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// Reads the fields of a record body in order.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, what: &'static str) -> Result<&'a [u8], Error> {
+        if self.buf.len() < len {
+            return Err(Error::Malformed(what));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self, what: &'static str) -> Result<u16, Error> {
+        let bytes = self.take(2, what)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn str(&mut self, what: &'static str) -> Result<String, Error> {
+        let len = self.u16(what)? as usize;
+        let bytes = self.take(len, what)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::Malformed(what))
+    }
+}
+
+/// Parses the record at the start of `buf`, returning it with the number of
+/// bytes it occupies.
+pub fn parse(buf: &[u8]) -> Result<(Record, usize), Error> {
+    if buf.len() < LEN_PREFIX {
+        return Err(Error::Incomplete { needed: LEN_PREFIX });
+    }
+    let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    let needed = LEN_PREFIX + len;
+    if buf.len() < needed {
+        return Err(Error::Incomplete { needed });
+    }
+    let mut reader = Reader {
+        buf: &buf[LEN_PREFIX..needed],
+    };
+    let timestamp = reader.take(8, "timestamp")?;
+    let timestamp_us = u64::from_be_bytes(timestamp.try_into().unwrap());
+    let method = reader.str("method")?;
+    let count = reader.u16("metadata")?;
+    let mut metadata = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let name = reader.str("metadata name")?;
+        let value = reader.str("metadata value")?;
+        metadata.push((name, value));
+    }
+    let record = Record {
+        timestamp_us,
+        method,
+        metadata,
+        payload: reader.buf.to_vec(),
+    };
+    Ok((record, needed))
+}
+
+/// Returns an iterator over the complete records at the start of `buf`.
+pub fn records(buf: &[u8]) -> Records<'_> {
+    Records { buf, offset: 0 }
+}
+
+/// Iterator over consecutive records in a capture.
+///
+/// Like [`Frames`](crate::Frames), iteration stops at the first incomplete
+/// record, whose bytes are available through [`Records::remainder`]. A
+/// malformed record is yielded as an error and skipped, since its length
+/// prefix still delimits it.
+pub struct Records<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Records<'a> {
+    /// Bytes that have not been consumed as complete records.
+    pub fn remainder(&self) -> &'a [u8] {
+        &self.buf[self.offset..]
+    }
+}
+
+impl Iterator for Records<'_> {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let buf = &self.buf[self.offset..];
+        match parse(buf) {
+            Ok((record, len)) => {
+                self.offset += len;
+                Some(Ok(record))
+            }
+            Err(Error::Incomplete { .. }) => None,
+            Err(e) => {
+                let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+                self.offset += LEN_PREFIX + len;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(method: &str) -> Record {
+        Record {
+            timestamp_us: 1_700_000_000_000_000,
+            method: method.to_string(),
+            metadata: vec![("x-user-id".to_string(), "alice".to_string())],
+            payload: crate::encode(b"key", false),
+        }
+    }
+
+    #[test]
+    fn records_roundtrip() {
+        let mut buf = Vec::new();
+        record("kv.KVStore/Get").encode_into(&mut buf).unwrap();
+        record("kv.KVStore/Set").encode_into(&mut buf).unwrap();
+        let partial = record("kv.KVStore/Del").encode().unwrap();
+        buf.extend_from_slice(&partial[..partial.len() - 1]);
+
+        let mut iter = records(&buf);
+        assert_eq!(iter.next(), Some(Ok(record("kv.KVStore/Get"))));
+        assert_eq!(iter.next(), Some(Ok(record("kv.KVStore/Set"))));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.remainder(), &partial[..partial.len() - 1]);
+        assert_eq!(
+            parse(&partial[..3]),
+            Err(Error::Incomplete { needed: LEN_PREFIX })
+        );
+    }
+
+    #[test]
+    fn malformed_and_oversized_records() {
+        // Claims one metadata entry but ends after the method.
+        let mut buf = vec![0, 0, 0, 13];
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&[0, 1, b'm', 0, 1]);
+        buf.extend_from_slice(&record("next").encode().unwrap());
+        let mut iter = records(&buf);
+        assert_eq!(iter.next(), Some(Err(Error::Malformed("metadata name"))));
+        assert_eq!(iter.next(), Some(Ok(record("next"))));
+
+        let mut long = record("m");
+        long.metadata[0].1 = "v".repeat(1 << 16);
+        let mut out = vec![1];
+        assert_eq!(
+            long.encode_into(&mut out),
+            Err(Error::TooLong("metadata value"))
+        );
+        assert_eq!(out, [1]);
+    }
+}
@@ -0,0 +1,37 @@
+//! Local gRPC error replies for the proxy-wasm filters.
+
+use proxy_wasm::traits::HttpContext;
+
+use crate::status::{self, Code};
+
+/// Sends gRPC error replies from an HTTP context.
+///
+/// The reply is a trailers-only response: HTTP 200 with `grpc-status` and
+/// `grpc-message` in the headers and no body, which is how gRPC reports an
+/// RPC that failed before any message was sent. Filters return
+/// `Action::Pause` after calling it so the request never reaches the upstream.
+pub trait LocalReply {
+    fn send_grpc_error(&self, code: Code, message: &str) {
+        self.send_grpc_error_with_headers(code, message, &[]);
+    }
+
+    /// Like `send_grpc_error`, with extra response headers such as a
+    /// retry hint.
+    fn send_grpc_error_with_headers(&self, code: Code, message: &str, extra: &[(&str, &str)]);
+}
+
+impl<T: HttpContext + ?Sized> LocalReply for T {
+    fn send_grpc_error_with_headers(&self, code: Code, message: &str, extra: &[(&str, &str)]) {
+        let status = code.to_header();
+        let message = status::encode_message(message);
+        let mut headers = vec![
+            ("content-type", "application/grpc"),
+            ("grpc-status", status.as_str()),
+        ];
+        if !message.is_empty() {
+            headers.push(("grpc-message", message.as_str()));
+        }
+        headers.extend_from_slice(extra);
+        self.send_http_response(200, headers, None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Per-route filter settings from Envoy route metadata.
+//!
+//! A route turns a filter off, or picks one of its named rule sets, under
+//! the `appnet` filter metadata namespace:
+//!
+//! ```yaml
+//! routes:
+//! - match: { prefix: "/kv.KVService/get" }
+//!   route: { cluster: kvservice }
+//!   metadata:
+//!     filter_metadata:
+//!       appnet:
+//!         mutation: { enabled: true, rule_set: strict }
+//!         buffer: { enabled: false }
+//! ```
+//!
+//! The filters read these values through the property API
+//! (`xds.route_metadata`), so one `.wasm` build behaves differently per route
+//! without a separate plugin configuration for each.
+
+use proxy_wasm::traits::Context;
+
+/// Filter metadata namespace holding the per-route settings.
+pub const NAMESPACE: &str = "appnet";
+
+/// Settings of one filter on the route selected for a stream.
+///
+/// Routes without metadata leave the filter enabled with its default rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteSettings {
+    pub enabled: bool,
+    /// Name of the rule set to use instead of the default rules.
+    pub rule_set: Option<String>,
+}
+
+impl Default for RouteSettings {
+    fn default() -> Self {
+        RouteSettings {
+            enabled: true,
+            rule_set: None,
+        }
+    }
+}
+
+impl RouteSettings {
+    /// Builds the settings from the serialized `enabled` and `rule_set`
+    /// property values. Envoy serializes a bool as a single byte; the strings
+    /// `true` and `false` are accepted too. Unrecognized values are ignored.
+    pub fn from_properties(enabled: Option<&[u8]>, rule_set: Option<&[u8]>) -> RouteSettings {
+        let enabled = match enabled {
+            Some([0]) | Some(b"false") => false,
+            Some(_) | None => true,
+        };
+        let rule_set = rule_set
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .filter(|name| !name.is_empty())
+            .map(str::to_string);
+        RouteSettings { enabled, rule_set }
+    }
+}
+
+/// Reads per-route settings from a stream context.
+///
+/// Only valid once the route is known, i.e. from `on_http_request_headers`
+/// onwards.
+pub trait RouteConfig {
+    fn route_settings(&self, filter: &str) -> RouteSettings;
+}
+
+impl<T: Context + ?Sized> RouteConfig for T {
+    fn route_settings(&self, filter: &str) -> RouteSettings {
+        let property = |key| {
+            self.get_property(vec![
+                "xds",
+                "route_metadata",
+                "filter_metadata",
+                NAMESPACE,
+                filter,
+                key,
+            ])
+        };
+        RouteSettings::from_properties(
+            property("enabled").as_deref(),
+            property("rule_set").as_deref(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_from_properties() {
+        assert_eq!(
+            RouteSettings::from_properties(None, None),
+            RouteSettings::default()
+        );
+        let settings = RouteSettings::from_properties(Some(&[0]), Some(b"strict"));
+        assert!(!settings.enabled);
+        assert_eq!(settings.rule_set.as_deref(), Some("strict"));
+        assert!(RouteSettings::from_properties(Some(&[1]), None).enabled);
+        assert!(!RouteSettings::from_properties(Some(b"false"), None).enabled);
+        // Unrecognized values leave the filter enabled.
+        assert!(RouteSettings::from_properties(Some(b"off"), Some(b"")).enabled);
+        assert_eq!(
+            RouteSettings::from_properties(None, Some(b"")).rule_set,
+            None
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! gRPC status trailers: `grpc-status`, `grpc-message` and
+//! `grpc-status-details-bin`.
+//!
+//! The filters use [`TrailerPolicy`] to remap upstream status codes, replace or
+//! normalize the status message and attach a serialized `google.rpc.Status`.
+
+use base64::Engine;
+
+/// Canonical gRPC status codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+    Unauthenticated = 16,
+}
+
+const CODES: [(Code, &str); 17] = [
+    (Code::Ok, "OK"),
+    (Code::Cancelled, "CANCELLED"),
+    (Code::Unknown, "UNKNOWN"),
+    (Code::InvalidArgument, "INVALID_ARGUMENT"),
+    (Code::DeadlineExceeded, "DEADLINE_EXCEEDED"),
+    (Code::NotFound, "NOT_FOUND"),
+    (Code::AlreadyExists, "ALREADY_EXISTS"),
+    (Code::PermissionDenied, "PERMISSION_DENIED"),
+    (Code::ResourceExhausted, "RESOURCE_EXHAUSTED"),
+    (Code::FailedPrecondition, "FAILED_PRECONDITION"),
+    (Code::Aborted, "ABORTED"),
+    (Code::OutOfRange, "OUT_OF_RANGE"),
+    (Code::Unimplemented, "UNIMPLEMENTED"),
+    (Code::Internal, "INTERNAL"),
+    (Code::Unavailable, "UNAVAILABLE"),
+    (Code::DataLoss, "DATA_LOSS"),
+    (Code::Unauthenticated, "UNAUTHENTICATED"),
+];
+
+impl Code {
+    /// Maps a numeric code; values outside the canonical range are `Unknown`.
+    pub fn from_i32(value: i32) -> Code {
+        CODES
+            .iter()
+            .find(|(code, _)| *code as i32 == value)
+            .map_or(Code::Unknown, |(code, _)| *code)
+    }
+
+    /// Parses a `grpc-status` header value. Unparseable values are `Unknown`,
+    /// as the gRPC spec requires.
+    pub fn from_header(value: &str) -> Code {
+        value.trim().parse().map_or(Code::Unknown, Code::from_i32)
+    }
+
+    /// Parses a code from its canonical name (`UNAVAILABLE`) or its number.
+    pub fn from_name(name: &str) -> Option<Code> {
+        if let Ok(value) = name.parse::<i32>() {
+            return CODES
+                .iter()
+                .find(|(code, _)| *code as i32 == value)
+                .map(|(code, _)| *code);
+        }
+        CODES
+            .iter()
+            .find(|(_, n)| n.eq_ignore_ascii_case(name))
+            .map(|(code, _)| *code)
+    }
+
+    /// Maps the HTTP status of a response that is not gRPC, following the
+    /// gRPC HTTP-to-status mapping.
+    pub fn from_http_status(status: u16) -> Code {
+        match status {
+            400 => Code::Internal,
+            401 => Code::Unauthenticated,
+            403 => Code::PermissionDenied,
+            404 => Code::Unimplemented,
+            429 | 502 | 503 | 504 => Code::Unavailable,
+            _ => Code::Unknown,
+        }
+    }
+
+    /// The HTTP status a JSON gateway reports for this code.
+    pub fn to_http_status(self) -> u16 {
+        match self {
+            Code::Ok => 200,
+            Code::Cancelled => 499,
+            Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => 400,
+            Code::Unauthenticated => 401,
+            Code::PermissionDenied => 403,
+            Code::NotFound => 404,
+            Code::AlreadyExists | Code::Aborted => 409,
+            Code::ResourceExhausted => 429,
+            Code::Unimplemented => 501,
+            Code::Unavailable => 503,
+            Code::DeadlineExceeded => 504,
+            Code::Unknown | Code::Internal | Code::DataLoss => 500,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        CODES[self as usize].1
+    }
+
+    /// The `grpc-status` header value.
+    pub fn to_header(self) -> String {
+        (self as i32).to_string()
+    }
+}
+
+/// Percent-encodes a status message for `grpc-message`.
+pub fn encode_message(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    for &b in message.as_bytes() {
+        if (0x20..=0x7e).contains(&b) && b != b'%' {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// Decodes a `grpc-message` value. Malformed escapes are kept verbatim.
+pub fn decode_message(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                out.push(hi << 4 | lo);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Serializes a `google.rpc.Status` without details and base64-encodes it
+/// for `grpc-status-details-bin`.
+pub fn status_details_bin(code: Code, message: &str) -> String {
+    let mut buf = Vec::with_capacity(message.len() + 8);
+    if code != Code::Ok {
+        // field 1 (code), varint
+        buf.push(0x08);
+        put_varint(&mut buf, code as u64);
+    }
+    if !message.is_empty() {
+        // field 2 (message), length-delimited
+        buf.push(0x12);
+        put_varint(&mut buf, message.len() as u64);
+        buf.extend_from_slice(message.as_bytes());
+    }
+    base64::engine::general_purpose::STANDARD_NO_PAD.encode(buf)
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// How a filter rewrites the status trailers of a response.
+///
+/// The default policy keeps the status and only re-encodes `grpc-message`.
+#[derive(Debug, Clone, Default)]
+pub struct TrailerPolicy {
+    /// Upstream code → code reported downstream.
+    pub status_map: Vec<(Code, Code)>,
+    /// Replaces the message of non-OK responses.
+    pub message: Option<String>,
+    /// Attach `grpc-status-details-bin` to non-OK responses.
+    pub details: bool,
+}
+
+/// Trailer values produced by [`TrailerPolicy::apply`]. `None` means the
+/// trailer should be removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trailers {
+    pub status: Code,
+    pub message: Option<String>,
+    pub details: Option<String>,
+}
+
+impl TrailerPolicy {
+    /// Applies the policy to the upstream `grpc-status` and raw
+    /// `grpc-message` values.
+    pub fn apply(&self, status: Code, message: Option<&str>) -> Trailers {
+        let status = self
+            .status_map
+            .iter()
+            .find(|(from, _)| *from == status)
+            .map_or(status, |(_, to)| *to);
+        let message = match &self.message {
+            Some(message) if status != Code::Ok => Some(message.clone()),
+            _ => message.map(decode_message),
+        };
+        let details = if self.details && status != Code::Ok {
+            Some(status_details_bin(status, message.as_deref().unwrap_or("")))
+        } else {
+            None
+        };
+        Trailers {
+            status,
+            message: message.filter(|m| !m.is_empty()).map(|m| encode_message(&m)),
+            details,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        assert_eq!(Code::from_header("14"), Code::Unavailable);
+        assert_eq!(Code::from_header("bogus"), Code::Unknown);
+        assert_eq!(Code::from_header("99"), Code::Unknown);
+        assert_eq!(Code::from_name("resource_exhausted"), Some(Code::ResourceExhausted));
+        assert_eq!(Code::from_name("8"), Some(Code::ResourceExhausted));
+        assert_eq!(Code::from_name("99"), None);
+        assert_eq!(Code::Unauthenticated.name(), "UNAUTHENTICATED");
+        assert_eq!(Code::Internal.to_header(), "13");
+        assert_eq!(Code::from_http_status(503), Code::Unavailable);
+        assert_eq!(Code::from_http_status(404), Code::Unimplemented);
+        assert_eq!(Code::from_http_status(200), Code::Unknown);
+        assert_eq!(Code::Unavailable.to_http_status(), 503);
+        assert_eq!(Code::NotFound.to_http_status(), 404);
+    }
+
+    #[test]
+    fn message_roundtrip() {
+        let encoded = encode_message("50% off: café\n");
+        assert_eq!(encoded, "50%25 off: caf%C3%A9%0A");
+        assert_eq!(decode_message(&encoded), "50% off: café\n");
+        assert_eq!(decode_message("bad %zz escape %4"), "bad %zz escape %4");
+    }
+
+    #[test]
+    fn policy_maps_and_attaches_details() {
+        let policy = TrailerPolicy {
+            status_map: vec![(Code::Unavailable, Code::ResourceExhausted)],
+            message: Some("try later".to_string()),
+            details: true,
+        };
+        let trailers = policy.apply(Code::Unavailable, Some("upstream%20reset"));
+        assert_eq!(trailers.status, Code::ResourceExhausted);
+        assert_eq!(trailers.message.as_deref(), Some("try later"));
+        // google.rpc.Status{code: 8, message: "try later"}
+        let expected = [&[0x08, 8, 0x12, 9][..], b"try later"].concat();
+        let expected = base64::engine::general_purpose::STANDARD_NO_PAD.encode(expected);
+        assert_eq!(trailers.details, Some(expected));
+
+        let ok = policy.apply(Code::Ok, None);
+        assert_eq!(ok, Trailers { status: Code::Ok, message: None, details: None });
+    }
+}
@@ -0,0 +1,96 @@
+//! Per-stream message counts and size distributions.
+//!
+//! Filters record every decoded message and log the summary from `on_log`,
+//! which characterizes streaming RPCs as well as unary ones.
+
+use std::fmt;
+
+/// Number of power-of-two size classes; the last one holds everything from
+/// 2^31 bytes up.
+const BUCKETS: usize = 32;
+
+/// Message statistics of one direction of a stream.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Sizes {
+    pub messages: u64,
+    pub bytes: u64,
+    pub max: u64,
+    /// Messages per size class: bucket `i` counts sizes up to `2^i` bytes.
+    buckets: [u64; BUCKETS],
+}
+
+impl Sizes {
+    /// Records a message of `len` payload bytes.
+    pub fn record(&mut self, len: usize) {
+        let len = len as u64;
+        self.messages += 1;
+        self.bytes += len;
+        self.max = self.max.max(len);
+        let class = (u64::BITS - len.saturating_sub(1).leading_zeros()) as usize;
+        self.buckets[class.min(BUCKETS - 1)] += 1;
+    }
+
+    /// Non-empty size classes as (upper bound in bytes, messages).
+    pub fn histogram(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(i, &count)| (1 << i, count))
+    }
+}
+
+impl fmt::Display for Sizes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} messages, {} bytes, max {}",
+            self.messages, self.bytes, self.max
+        )?;
+        if self.messages > 0 {
+            f.write_str(", sizes")?;
+            for (bound, count) in self.histogram() {
+                write!(f, " <={}:{}", bound, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Both directions of one stream.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StreamSummary {
+    pub request: Sizes,
+    pub response: Sizes,
+}
+
+impl fmt::Display for StreamSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request: {}; response: {}", self.request, self.response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_size_classes() {
+        let mut summary = StreamSummary::default();
+        for len in [0, 1, 64, 65, 1000] {
+            summary.request.record(len);
+        }
+        summary.response.record(3);
+        assert_eq!(summary.request.messages, 5);
+        assert_eq!(summary.request.bytes, 1130);
+        assert_eq!(summary.request.max, 1000);
+        let histogram: Vec<_> = summary.request.histogram().collect();
+        assert_eq!(histogram, [(1, 2), (64, 1), (128, 1), (1024, 1)]);
+        assert_eq!(
+            summary.to_string(),
+            "request: 5 messages, 1130 bytes, max 1000, sizes <=1:2 <=64:1 <=128:1 <=1024:1; \
+             response: 1 messages, 3 bytes, max 3, sizes <=4:1"
+        );
+        assert_eq!(Sizes::default().to_string(), "0 messages, 0 bytes, max 0");
+    }
+}
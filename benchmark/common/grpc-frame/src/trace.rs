@@ -0,0 +1,196 @@
+//! W3C Trace Context headers (`traceparent` and `tracestate`).
+//!
+//! `traceparent` is `{version}-{trace id}-{parent id}-{flags}` in lowercase
+//! hex, e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+
+use std::fmt::Write;
+
+/// Bit of the trace flags that marks a sampled trace.
+pub const SAMPLED: u8 = 0x01;
+
+/// Maximum number of list members in `tracestate`.
+const MAX_TRACESTATE_MEMBERS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: [u8; 16],
+    pub parent_id: [u8; 8],
+    pub flags: u8,
+}
+
+fn parse_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 || !s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let mut out = [0; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
+
+impl TraceParent {
+    /// Parses a `traceparent` header. Unknown future versions are accepted
+    /// as long as they start with the version 00 fields; all-zero IDs and
+    /// version `ff` are invalid.
+    pub fn parse(value: &str) -> Option<TraceParent> {
+        let value = value.trim();
+        let mut parts = value.split('-');
+        let version = parse_hex::<1>(parts.next()?)?[0];
+        let trace_id = parse_hex::<16>(parts.next()?)?;
+        let parent_id = parse_hex::<8>(parts.next()?)?;
+        let flags = parse_hex::<1>(parts.next()?)?[0];
+        if version == 0xff || (version == 0 && parts.next().is_some()) {
+            return None;
+        }
+        if trace_id == [0; 16] || parent_id == [0; 8] {
+            return None;
+        }
+        Some(TraceParent {
+            trace_id,
+            parent_id,
+            flags,
+        })
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        to_hex(&self.trace_id)
+    }
+
+    pub fn parent_id_hex(&self) -> String {
+        to_hex(&self.parent_id)
+    }
+
+    /// The same trace with this hop's span as the parent.
+    pub fn child(&self, span_id: [u8; 8]) -> TraceParent {
+        TraceParent {
+            parent_id: span_id,
+            ..*self
+        }
+    }
+
+    /// The `traceparent` header value, always at version 00.
+    pub fn to_header(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            self.trace_id_hex(),
+            self.parent_id_hex(),
+            self.flags
+        )
+    }
+}
+
+/// Decides sampling from the trace ID, so every hop that samples at the same
+/// rate makes the same decision for a trace.
+pub fn sample(trace_id: &[u8; 16], percent: f64) -> bool {
+    let bucket = u64::from_be_bytes(trace_id[8..].try_into().unwrap());
+    (bucket as f64 / u64::MAX as f64) * 100.0 < percent
+}
+
+/// Like [`sample`], for requests identified by an opaque ID such as
+/// `x-request-id` rather than a trace.
+pub fn sample_id(id: &str, percent: f64) -> bool {
+    (hash_id(id) as f64 / u64::MAX as f64) * 100.0 < percent
+}
+
+/// Hashes an opaque ID with FNV-1a, which is stable across builds, so every
+/// proxy maps an ID to the same value.
+pub fn hash_id(id: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in id.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    // FNV's low bits mix poorly; finish with the splitmix64 finalizer.
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+/// Puts `key=value` at the front of a `tracestate` list, replacing any
+/// previous entry for `key` and dropping members beyond the limit.
+pub fn update_tracestate(tracestate: Option<&str>, key: &str, value: &str) -> String {
+    let mut members = vec![format!("{}={}", key, value)];
+    for member in tracestate.unwrap_or("").split(',') {
+        let member = member.trim();
+        if member.is_empty() || member.split('=').next() == Some(key) {
+            continue;
+        }
+        if members.len() == MAX_TRACESTATE_MEMBERS {
+            break;
+        }
+        members.push(member.to_string());
+    }
+    members.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn traceparent_roundtrip() {
+        let parent = TraceParent::parse(HEADER).unwrap();
+        assert!(parent.sampled());
+        assert_eq!(parent.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(parent.to_header(), HEADER);
+        let child = parent.child([1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(
+            child.to_header(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0102030405060708-01"
+        );
+    }
+
+    #[test]
+    fn traceparent_rejects_invalid() {
+        assert!(
+            TraceParent::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(
+            TraceParent::parse("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(
+            TraceParent::parse("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(TraceParent::parse(&format!("{}-extra", HEADER)).is_none());
+        // Later versions may append fields.
+        assert!(TraceParent::parse(&format!("01{}-extra", &HEADER[2..])).is_some());
+    }
+
+    #[test]
+    fn sampling_and_tracestate() {
+        let id = TraceParent::parse(HEADER).unwrap().trace_id;
+        assert!(sample(&id, 100.0));
+        assert!(!sample(&id, 0.0));
+        assert_eq!(
+            update_tracestate(Some("arpc=old, vendor=1"), "arpc", "new"),
+            "arpc=new,vendor=1"
+        );
+        assert_eq!(update_tracestate(None, "arpc", "x"), "arpc=x");
+    }
+
+    #[test]
+    fn sampling_by_id() {
+        assert!(sample_id("req-1", 100.0));
+        assert!(!sample_id("req-1", 0.0));
+        assert_eq!(sample_id("req-1", 50.0), sample_id("req-1", 50.0));
+        let selected = (0..10_000)
+            .filter(|i| sample_id(&format!("req-{}", i), 10.0))
+            .count();
+        assert!((800..1200).contains(&selected), "{} selected", selected);
+    }
+}
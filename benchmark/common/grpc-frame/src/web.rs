@@ -0,0 +1,144 @@
+//! gRPC-Web framing (`application/grpc-web` and `application/grpc-web-text`).
+//!
+//! gRPC-Web bodies use the same length-prefixed messages as gRPC, but carry
+//! the trailers in a final frame with the high bit of the flag set, whose
+//! payload is an HTTP/1 style header block (`grpc-status:0\r\n`). The `-text`
+//! variant base64-encodes the whole body.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// Flag of the frame that carries the trailers.
+pub const TRAILER_FLAG: u8 = 0x80;
+
+/// Body encoding of a gRPC-Web request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Binary,
+    Text,
+}
+
+impl Mode {
+    /// Classifies a `content-type`. Returns `None` for anything that is not
+    /// gRPC-Web, including native gRPC.
+    pub fn from_content_type(content_type: &str) -> Option<Mode> {
+        let base = content_type.split(';').next().unwrap_or("").trim();
+        let base = base.split('+').next().unwrap_or("");
+        match base {
+            "application/grpc-web" => Some(Mode::Binary),
+            "application/grpc-web-text" => Some(Mode::Text),
+            _ => None,
+        }
+    }
+}
+
+/// Maps a gRPC-Web `content-type` to the native one, keeping the message
+/// format suffix: `application/grpc-web-text+proto` → `application/grpc+proto`.
+pub fn to_grpc_content_type(content_type: &str) -> String {
+    let base = content_type.split(';').next().unwrap_or("").trim();
+    match base.split_once('+') {
+        Some((_, format)) => format!("application/grpc+{}", format),
+        None => "application/grpc".to_string(),
+    }
+}
+
+/// The response `content-type` for a native gRPC response to a request in
+/// `mode`.
+pub fn from_grpc_content_type(content_type: &str, mode: Mode) -> String {
+    let prefix = match mode {
+        Mode::Binary => "application/grpc-web",
+        Mode::Text => "application/grpc-web-text",
+    };
+    match content_type.trim().split_once('+') {
+        Some((_, format)) => format!("{}+{}", prefix, format),
+        None => prefix.to_string(),
+    }
+}
+
+/// Encodes trailers as the final gRPC-Web frame. Names are lowercased, as
+/// the spec requires.
+pub fn encode_trailers<K: AsRef<str>, V: AsRef<str>>(trailers: &[(K, V)]) -> Vec<u8> {
+    let mut block = String::new();
+    for (name, value) in trailers {
+        block.push_str(&name.as_ref().to_ascii_lowercase());
+        block.push(':');
+        block.push_str(value.as_ref());
+        block.push_str("\r\n");
+    }
+    let mut frame = Vec::with_capacity(crate::HEADER_LEN + block.len());
+    frame.push(TRAILER_FLAG);
+    frame.extend_from_slice(&(block.len() as u32).to_be_bytes());
+    frame.extend_from_slice(block.as_bytes());
+    frame
+}
+
+/// Decodes a `grpc-web-text` body. Clients may send several independently
+/// padded base64 chunks, so each 4-byte group is decoded on its own.
+pub fn decode_text(body: &[u8]) -> Result<Vec<u8>, base64::DecodeError> {
+    let body: Vec<u8> = body
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    let mut out = Vec::with_capacity(body.len() / 4 * 3);
+    for group in body.chunks(4) {
+        out.extend_from_slice(&STANDARD.decode(group)?);
+    }
+    Ok(out)
+}
+
+/// Encodes a `grpc-web-text` body.
+pub fn encode_text(body: &[u8]) -> Vec<u8> {
+    STANDARD.encode(body).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_types() {
+        assert_eq!(
+            Mode::from_content_type("application/grpc-web+proto"),
+            Some(Mode::Binary)
+        );
+        assert_eq!(
+            Mode::from_content_type("application/grpc-web-text; charset=utf-8"),
+            Some(Mode::Text)
+        );
+        assert_eq!(Mode::from_content_type("application/grpc"), None);
+        assert_eq!(
+            to_grpc_content_type("application/grpc-web-text+proto"),
+            "application/grpc+proto"
+        );
+        assert_eq!(
+            to_grpc_content_type("application/grpc-web"),
+            "application/grpc"
+        );
+        assert_eq!(
+            from_grpc_content_type("application/grpc+proto", Mode::Text),
+            "application/grpc-web-text+proto"
+        );
+    }
+
+    #[test]
+    fn trailer_frame() {
+        let frame = encode_trailers(&[("Grpc-Status", "0"), ("grpc-message", "ok")]);
+        assert_eq!(frame[0], TRAILER_FLAG);
+        assert_eq!(&frame[1..5], &32u32.to_be_bytes());
+        assert_eq!(&frame[5..], b"grpc-status:0\r\ngrpc-message:ok\r\n");
+    }
+
+    #[test]
+    fn text_roundtrip() {
+        let body = crate::encode(b"hello", false);
+        let text = encode_text(&body);
+        assert_eq!(decode_text(&text).unwrap(), body);
+        // Two separately padded chunks, as streamed by grpc-web clients.
+        let mut chunked = encode_text(b"a");
+        chunked.extend_from_slice(&encode_text(b"bc"));
+        chunked.push(b'\n');
+        assert_eq!(decode_text(&chunked).unwrap(), b"abc");
+        assert!(decode_text(b"####").is_err());
+    }
+}
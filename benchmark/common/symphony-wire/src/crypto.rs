@@ -0,0 +1,364 @@
+//! AES-256-GCM encryption of Symphony messages (feature `aes-gcm`),
+//! matching `pkg/transport/encryption.go`, and ChaCha20-Poly1305 (feature
+//! `chacha20poly1305`) for hosts without AES instructions. Either feature
+//! builds this module on its own.
+//!
+//! The 13-byte header stays in the clear. The public segment
+//! (`[13..offset_to_private]`) and the private segment (`[offset_to_private..]`)
+//! are sealed separately with their own keys, each as
+//! `[nonce(12B)][ciphertext][tag(16B)]`, and `offset_to_private` is rewritten
+//! to point at the sealed private segment.
+
+use aead::generic_array::GenericArray;
+use aead::{Aead, KeyInit};
+#[cfg(feature = "aes-gcm")]
+use aes_gcm::aead;
+#[cfg(feature = "aes-gcm")]
+use aes_gcm::Aes256Gcm;
+#[cfg(not(feature = "aes-gcm"))]
+use chacha20poly1305::aead;
+#[cfg(feature = "chacha20poly1305")]
+use chacha20poly1305::ChaCha20Poly1305;
+
+use crate::{Error, MESSAGE_HEADER_LEN, SYMPHONY_VERSION};
+
+pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+
+/// Development keys hardcoded in the Go transport (`DefaultPublicKey`).
+pub const DEFAULT_PUBLIC_KEY: [u8; 32] = [
+    0x27, 0xe1, 0xfa, 0x17, 0xd7, 0x2b, 0x1f, 0xaf, 0x72, 0x23, 0x62, 0xde, 0xb1, 0x97, 0x4a, 0x76,
+    0x75, 0x05, 0x8d, 0xb9, 0x88, 0x43, 0x70, 0x51, 0x24, 0xa0, 0x74, 0xc6, 0x11, 0x72, 0xf7, 0x96,
+];
+/// Development keys hardcoded in the Go transport (`DefaultPrivateKey`).
+pub const DEFAULT_PRIVATE_KEY: [u8; 32] = [
+    0x9b, 0x53, 0x00, 0x67, 0x84, 0x20, 0x67, 0x8a, 0x31, 0x57, 0xa4, 0xbc, 0xac, 0xdc, 0x3e, 0x86,
+    0x46, 0x93, 0x97, 0x1f, 0x8a, 0x3f, 0xab, 0x05, 0xb0, 0x69, 0x13, 0xfb, 0x43, 0xc7, 0xeb, 0xf9,
+];
+
+/// The AEAD sealing the segments. Both take 32-byte keys and 12-byte
+/// nonces, so the segment layout is the same for either. The default is
+/// AES-256-GCM when it is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CipherSuite {
+    /// AES-256-GCM, the only suite of the Go transport.
+    #[cfg(feature = "aes-gcm")]
+    #[default]
+    Aes256Gcm,
+    /// ChaCha20-Poly1305, faster where AES has no hardware support.
+    #[cfg(feature = "chacha20poly1305")]
+    #[cfg_attr(not(feature = "aes-gcm"), default)]
+    ChaCha20Poly1305,
+}
+
+impl CipherSuite {
+    /// The suite's ID on the wire, as sent in the cipher suite extension.
+    pub fn id(self) -> u8 {
+        match self {
+            #[cfg(feature = "aes-gcm")]
+            CipherSuite::Aes256Gcm => 1,
+            #[cfg(feature = "chacha20poly1305")]
+            CipherSuite::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    /// The suite with wire ID `id`, if it is built.
+    pub fn from_id(id: u8) -> Option<CipherSuite> {
+        match id {
+            #[cfg(feature = "aes-gcm")]
+            1 => Some(CipherSuite::Aes256Gcm),
+            #[cfg(feature = "chacha20poly1305")]
+            2 => Some(CipherSuite::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+}
+
+enum Cipher {
+    // Boxed for its expanded key schedule, which dwarfs ChaCha's key.
+    #[cfg(feature = "aes-gcm")]
+    Aes256Gcm(Box<Aes256Gcm>),
+    #[cfg(feature = "chacha20poly1305")]
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+impl Cipher {
+    fn new(suite: CipherSuite, key: &[u8]) -> Result<Cipher, Error> {
+        let cipher = match suite {
+            #[cfg(feature = "aes-gcm")]
+            CipherSuite::Aes256Gcm => {
+                Aes256Gcm::new_from_slice(key).map(|c| Cipher::Aes256Gcm(Box::new(c)))
+            }
+            #[cfg(feature = "chacha20poly1305")]
+            CipherSuite::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new_from_slice(key).map(Cipher::ChaCha20Poly1305)
+            }
+        };
+        cipher.map_err(|_| Error::InvalidKey)
+    }
+
+    fn encrypt(&self, nonce: &[u8; NONCE_LEN], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = GenericArray::from_slice(nonce);
+        // Encryption only fails for plaintexts beyond the AEAD's limit
+        // (64 GiB for GCM, 256 GiB for ChaCha20-Poly1305).
+        match self {
+            #[cfg(feature = "aes-gcm")]
+            Cipher::Aes256Gcm(cipher) => cipher.encrypt(nonce, plaintext).unwrap(),
+            #[cfg(feature = "chacha20poly1305")]
+            Cipher::ChaCha20Poly1305(cipher) => cipher.encrypt(nonce, plaintext).unwrap(),
+        }
+    }
+
+    fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        let nonce = GenericArray::from_slice(nonce);
+        match self {
+            #[cfg(feature = "aes-gcm")]
+            Cipher::Aes256Gcm(cipher) => cipher.decrypt(nonce, ciphertext),
+            #[cfg(feature = "chacha20poly1305")]
+            Cipher::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce, ciphertext),
+        }
+        .map_err(|_| Error::Decrypt)
+    }
+}
+
+/// Ciphers for the two segments.
+pub struct Keys {
+    suite: CipherSuite,
+    public: Cipher,
+    private: Cipher,
+}
+
+impl Keys {
+    /// Builds the ciphers from 32-byte AES-256 keys.
+    #[cfg(feature = "aes-gcm")]
+    pub fn new(public: &[u8], private: &[u8]) -> Result<Keys, Error> {
+        Keys::with_suite(CipherSuite::Aes256Gcm, public, private)
+    }
+
+    /// Builds the ciphers of `suite` from 32-byte keys.
+    pub fn with_suite(suite: CipherSuite, public: &[u8], private: &[u8]) -> Result<Keys, Error> {
+        Ok(Keys {
+            suite,
+            public: Cipher::new(suite, public)?,
+            private: Cipher::new(suite, private)?,
+        })
+    }
+
+    pub fn suite(&self) -> CipherSuite {
+        self.suite
+    }
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Keys::with_suite(
+            CipherSuite::default(),
+            &DEFAULT_PUBLIC_KEY,
+            &DEFAULT_PRIVATE_KEY,
+        )
+        .unwrap()
+    }
+}
+
+/// Builds a nonce from the RPC ID, for tests and known-answer vectors.
+///
+/// It is not unique under a key: clients sharing keys start from the same
+/// RPC IDs, and a retransmitted or rewritten message gets the same nonce
+/// again, which under AES-GCM leaks both plaintexts and the
+/// authentication key. Never seal traffic with it.
+pub fn derived_nonce(rpc_id: u64, response: bool, private: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..8].copy_from_slice(&rpc_id.to_le_bytes());
+    nonce[8] = response as u8;
+    nonce[9] = private as u8;
+    nonce
+}
+
+fn offset_to_private(data: &[u8]) -> Result<usize, Error> {
+    if data.len() < MESSAGE_HEADER_LEN {
+        return Err(Error::Incomplete {
+            needed: MESSAGE_HEADER_LEN,
+        });
+    }
+    if data[0] != SYMPHONY_VERSION {
+        return Err(Error::InvalidVersion(data[0]));
+    }
+    Ok(u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as usize)
+}
+
+fn open(cipher: &Cipher, sealed: &[u8]) -> Result<Vec<u8>, Error> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err(Error::Decrypt);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher.decrypt(nonce, ciphertext)
+}
+
+fn seal(cipher: &Cipher, nonce: &[u8; NONCE_LEN], plaintext: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(nonce);
+    out.extend_from_slice(&cipher.encrypt(nonce, plaintext));
+}
+
+fn assemble(header: &[u8], public: &[u8], private: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(MESSAGE_HEADER_LEN + public.len() + private.len());
+    out.extend_from_slice(&header[..MESSAGE_HEADER_LEN]);
+    out[1..5].copy_from_slice(&((MESSAGE_HEADER_LEN + public.len()) as u32).to_le_bytes());
+    out.extend_from_slice(public);
+    out.extend_from_slice(private);
+    out
+}
+
+/// Seals the body of a control packet under the public segment key, as
+/// `[nonce(12B)][ciphertext][tag(16B)]`.
+pub fn seal_packet(keys: &Keys, nonce: &[u8; NONCE_LEN], plaintext: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(NONCE_LEN + plaintext.len() + TAG_LEN);
+    seal(&keys.public, nonce, plaintext, &mut out);
+    out
+}
+
+/// Opens a control packet body sealed by [`seal_packet`].
+pub fn open_packet(keys: &Keys, sealed: &[u8]) -> Result<Vec<u8>, Error> {
+    open(&keys.public, sealed)
+}
+
+/// Decrypts a sealed Symphony message.
+pub fn decrypt(keys: &Keys, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let offset = offset_to_private(data)?;
+    if offset < MESSAGE_HEADER_LEN + NONCE_LEN + TAG_LEN || offset > data.len() {
+        return Err(Error::InvalidOffset(offset as u32));
+    }
+    let public = open(&keys.public, &data[MESSAGE_HEADER_LEN..offset])?;
+    let private = if offset < data.len() {
+        let private = open(&keys.private, &data[offset..])?;
+        if private.first() != Some(&SYMPHONY_VERSION) {
+            return Err(Error::InvalidVersion(private.first().copied().unwrap_or(0)));
+        }
+        private
+    } else {
+        Vec::new()
+    };
+    Ok(assemble(data, &public, &private))
+}
+
+/// Encrypts a plaintext Symphony message with the given segment nonces.
+pub fn encrypt(
+    keys: &Keys,
+    data: &[u8],
+    public_nonce: &[u8; NONCE_LEN],
+    private_nonce: &[u8; NONCE_LEN],
+) -> Result<Vec<u8>, Error> {
+    let offset = offset_to_private(data)?;
+    if offset < MESSAGE_HEADER_LEN || offset > data.len() {
+        return Err(Error::InvalidOffset(offset as u32));
+    }
+    let mut public = Vec::new();
+    seal(
+        &keys.public,
+        public_nonce,
+        &data[MESSAGE_HEADER_LEN..offset],
+        &mut public,
+    );
+    let mut private = Vec::new();
+    if offset < data.len() {
+        seal(&keys.private, private_nonce, &data[offset..], &mut private);
+    }
+    Ok(assemble(data, &public, &private))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Vec<u8> {
+        // header, 4 public bytes, then a private segment
+        let mut msg = vec![SYMPHONY_VERSION];
+        msg.extend_from_slice(&17u32.to_le_bytes());
+        msg.extend_from_slice(&1u32.to_le_bytes());
+        msg.extend_from_slice(&2u32.to_le_bytes());
+        msg.extend_from_slice(b"pub!");
+        msg.push(SYMPHONY_VERSION);
+        msg.extend_from_slice(b"private data");
+        msg
+    }
+
+    #[test]
+    fn roundtrip() {
+        let keys = Keys::default();
+        let msg = message();
+        let sealed = encrypt(
+            &keys,
+            &msg,
+            &derived_nonce(9, false, false),
+            &derived_nonce(9, false, true),
+        )
+        .unwrap();
+        assert_eq!(sealed.len(), msg.len() + 2 * (NONCE_LEN + TAG_LEN));
+        assert_eq!(&sealed[5..13], &msg[5..13]);
+        assert_eq!(decrypt(&keys, &sealed).unwrap(), msg);
+    }
+
+    #[test]
+    fn public_only_and_tampering() {
+        let keys = Keys::default();
+        let mut msg = message();
+        msg.truncate(17);
+        msg[1..5].copy_from_slice(&17u32.to_le_bytes());
+        let nonce = derived_nonce(1, true, false);
+        let mut sealed = encrypt(&keys, &msg, &nonce, &nonce).unwrap();
+        assert_eq!(decrypt(&keys, &sealed).unwrap(), msg);
+
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert_eq!(decrypt(&keys, &sealed), Err(Error::Decrypt));
+    }
+
+    #[test]
+    fn packet_roundtrip() {
+        let keys = Keys::default();
+        let mut sealed = seal_packet(&keys, &derived_nonce(3, false, false), b"rekey");
+        assert_eq!(sealed.len(), 5 + NONCE_LEN + TAG_LEN);
+        assert_eq!(open_packet(&keys, &sealed).unwrap(), b"rekey");
+
+        sealed[NONCE_LEN] ^= 1;
+        assert_eq!(open_packet(&keys, &sealed), Err(Error::Decrypt));
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn chacha20poly1305_roundtrip() {
+        let keys = Keys::with_suite(
+            CipherSuite::ChaCha20Poly1305,
+            &DEFAULT_PUBLIC_KEY,
+            &DEFAULT_PRIVATE_KEY,
+        )
+        .unwrap();
+        let msg = message();
+        let public = derived_nonce(3, false, false);
+        let private = derived_nonce(3, false, true);
+        let sealed = encrypt(&keys, &msg, &public, &private).unwrap();
+        assert_eq!(decrypt(&keys, &sealed).unwrap(), msg);
+    }
+
+    #[cfg(all(feature = "aes-gcm", feature = "chacha20poly1305"))]
+    #[test]
+    fn suites_do_not_open_each_other() {
+        let chacha = Keys::with_suite(
+            CipherSuite::ChaCha20Poly1305,
+            &DEFAULT_PUBLIC_KEY,
+            &DEFAULT_PRIVATE_KEY,
+        )
+        .unwrap();
+        let nonce = derived_nonce(3, false, false);
+        let sealed = encrypt(&chacha, &message(), &nonce, &nonce).unwrap();
+        assert_eq!(decrypt(&Keys::default(), &sealed), Err(Error::Decrypt));
+    }
+
+    #[test]
+    fn suite_ids_round_trip() {
+        assert_eq!(
+            CipherSuite::from_id(CipherSuite::default().id()),
+            Some(CipherSuite::default())
+        );
+        assert_eq!(CipherSuite::from_id(0), None);
+    }
+}
@@ -0,0 +1,497 @@
+//! aRPC packet and Symphony message framing, for filters that inspect aRPC
+//! traffic instead of gRPC.
+//!
+//! Data packets (`pkg/packet/builtin_packets.go`) carry one fragment of a
+//! serialized message behind a 31-byte little-endian header:
+//! `[type(1B)][rpc id(8B)][total packets(2B)][seq(2B)][more fragments(1B)]`
+//! `[fragment index(1B)][dst ip(4B)][dst port(2B)][src ip(4B)][src port(2B)]`
+//! `[payload length(4B)][payload]`. Error packets replace the fragmentation
+//! fields and carry a message: `[type][rpc id][dst ip][dst port][src ip]`
+//! `[src port][message length(4B)][message]`.
+//!
+//! The top bit of a data packet's type byte is its key phase: the parity of
+//! the key epoch its message is sealed under, flipped when the Rust
+//! transport rotates its keys. The Go transport never rotates, so it always
+//! sends the bit clear.
+//!
+//! The next bit marks a data packet carrying an extension block between
+//! its header and payload, `[extensions length(2B)][extensions]`, for
+//! per-message fields the Go header has no room for. Only the first packet
+//! of a message carries one, and only Rust endpoints understand it: the Go
+//! transport rejects the type byte, so the Rust transport sends blocks only
+//! to peers configured or seen to read them.
+//!
+//! The first fragment of a Symphony message starts with the public segment
+//! header `[version(1B)][offset to private(4B)][service id(4B)][method id(4B)]`.
+
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+#[cfg(any(feature = "aes-gcm", feature = "chacha20poly1305"))]
+pub mod crypto;
+
+/// Type byte bit carrying a data packet's key phase.
+pub const KEY_PHASE_BIT: u8 = 0x80;
+/// Type byte bit marking a data packet with an extension block.
+pub const EXTENSIONS_BIT: u8 = 0x40;
+/// Size of the data packet header.
+pub const DATA_HEADER_LEN: usize = 31;
+/// Size of the error packet header.
+pub const ERROR_HEADER_LEN: usize = 25;
+/// Size of the Symphony public segment header.
+pub const MESSAGE_HEADER_LEN: usize = 13;
+/// The only Symphony segment version in use.
+pub const SYMPHONY_VERSION: u8 = 0x01;
+/// Largest packet the Go transport puts in one datagram
+/// (`MaxUDPPayloadSize`).
+pub const MAX_PACKET_LEN: usize = 1400;
+
+/// Builtin packet type IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Unknown,
+    Request,
+    Response,
+    Error,
+}
+
+impl PacketType {
+    pub fn from_id(id: u8) -> Option<PacketType> {
+        match id {
+            0 => Some(PacketType::Unknown),
+            1 => Some(PacketType::Request),
+            2 => Some(PacketType::Response),
+            3 => Some(PacketType::Error),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            PacketType::Unknown => 0,
+            PacketType::Request => 1,
+            PacketType::Response => 2,
+            PacketType::Error => 3,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PacketType::Unknown => "Unknown",
+            PacketType::Request => "Request",
+            PacketType::Response => "Response",
+            PacketType::Error => "Error",
+        }
+    }
+}
+
+/// A request or response fragment borrowed from a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataPacket<'a> {
+    pub kind: PacketType,
+    pub rpc_id: u64,
+    pub total_packets: u16,
+    pub seq: u16,
+    pub more_fragments: bool,
+    pub fragment_index: u8,
+    /// Parity of the key epoch the message is sealed under.
+    pub key_phase: bool,
+    pub dst: SocketAddrV4,
+    pub src: SocketAddrV4,
+    /// The extension block, empty if the packet has none.
+    pub extensions: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+impl DataPacket<'_> {
+    /// Whether this fragment starts the message, i.e. holds its header.
+    pub fn is_first(&self) -> bool {
+        self.seq == 0 && self.fragment_index == 0
+    }
+
+    /// Appends the packet in wire format to `buf`.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        let phase = if self.key_phase { KEY_PHASE_BIT } else { 0 };
+        let extended = if self.extensions.is_empty() {
+            0
+        } else {
+            EXTENSIONS_BIT
+        };
+        buf.push(self.kind.id() | phase | extended);
+        buf.extend_from_slice(&self.rpc_id.to_le_bytes());
+        buf.extend_from_slice(&self.total_packets.to_le_bytes());
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+        buf.push(self.more_fragments as u8);
+        buf.push(self.fragment_index);
+        put_addr(buf, self.dst);
+        put_addr(buf, self.src);
+        buf.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        if !self.extensions.is_empty() {
+            buf.extend_from_slice(&(self.extensions.len() as u16).to_le_bytes());
+            buf.extend_from_slice(self.extensions);
+        }
+        buf.extend_from_slice(self.payload);
+    }
+
+    /// Bytes the extension block adds to the packet.
+    pub fn extensions_len(&self) -> usize {
+        if self.extensions.is_empty() {
+            0
+        } else {
+            2 + self.extensions.len()
+        }
+    }
+}
+
+/// An error (or unknown) packet borrowed from a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorPacket<'a> {
+    pub kind: PacketType,
+    pub rpc_id: u64,
+    pub dst: SocketAddrV4,
+    pub src: SocketAddrV4,
+    pub message: &'a [u8],
+}
+
+impl ErrorPacket<'_> {
+    /// Appends the packet in wire format to `buf`.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.push(self.kind.id());
+        buf.extend_from_slice(&self.rpc_id.to_le_bytes());
+        put_addr(buf, self.dst);
+        put_addr(buf, self.src);
+        buf.extend_from_slice(&(self.message.len() as u32).to_le_bytes());
+        buf.extend_from_slice(self.message);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet<'a> {
+    Data(DataPacket<'a>),
+    Error(ErrorPacket<'a>),
+}
+
+impl Packet<'_> {
+    pub fn rpc_id(&self) -> u64 {
+        match self {
+            Packet::Data(p) => p.rpc_id,
+            Packet::Error(p) => p.rpc_id,
+        }
+    }
+
+    /// Number of bytes the packet occupies on the wire.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Packet::Data(p) => DATA_HEADER_LEN + p.extensions_len() + p.payload.len(),
+            Packet::Error(p) => ERROR_HEADER_LEN + p.message.len(),
+        }
+    }
+
+    /// Appends the packet in wire format to `buf`.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            Packet::Data(p) => p.encode_into(buf),
+            Packet::Error(p) => p.encode_into(buf),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer ends before the packet does; `needed` is the total number
+    /// of bytes required to hold it.
+    Incomplete { needed: usize },
+    /// The packet type is not one of the builtin types.
+    UnknownType(u8),
+    /// The Symphony segment version is not supported.
+    InvalidVersion(u8),
+    /// `offset_to_private` points outside the message.
+    InvalidOffset(u32),
+    /// An encryption key is not 32 bytes long.
+    InvalidKey,
+    /// A segment failed AES-GCM authentication.
+    Decrypt,
+    /// The peer seals in a cipher suite, by wire ID, that is not taken.
+    UnsupportedSuite(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Incomplete { needed } => {
+                write!(f, "incomplete aRPC packet: need {} bytes", needed)
+            }
+            Error::UnknownType(id) => write!(f, "unknown aRPC packet type: {}", id),
+            Error::InvalidVersion(v) => write!(f, "unsupported Symphony version: {}", v),
+            Error::InvalidOffset(offset) => {
+                write!(f, "invalid offset to private segment: {}", offset)
+            }
+            Error::InvalidKey => write!(f, "encryption keys must be 32 bytes"),
+            Error::Decrypt => write!(f, "segment failed authentication"),
+            Error::UnsupportedSuite(id) => write!(f, "unsupported cipher suite: {}", id),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn u16_at(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+fn addr_at(buf: &[u8], at: usize) -> SocketAddrV4 {
+    let ip = Ipv4Addr::new(buf[at], buf[at + 1], buf[at + 2], buf[at + 3]);
+    SocketAddrV4::new(ip, u16_at(buf, at + 4))
+}
+
+fn put_addr(buf: &mut Vec<u8>, addr: SocketAddrV4) {
+    buf.extend_from_slice(&addr.ip().octets());
+    buf.extend_from_slice(&addr.port().to_le_bytes());
+}
+
+/// Parses the packet at the start of `buf`.
+pub fn parse(buf: &[u8]) -> Result<Packet<'_>, Error> {
+    let Some(&id) = buf.first() else {
+        return Err(Error::Incomplete { needed: 1 });
+    };
+    let key_phase = id & KEY_PHASE_BIT != 0;
+    let extended = id & EXTENSIONS_BIT != 0;
+    let kind = PacketType::from_id(id & !(KEY_PHASE_BIT | EXTENSIONS_BIT))
+        .ok_or(Error::UnknownType(id))?;
+    match kind {
+        PacketType::Request | PacketType::Response => {
+            if buf.len() < DATA_HEADER_LEN {
+                return Err(Error::Incomplete {
+                    needed: DATA_HEADER_LEN,
+                });
+            }
+            let mut start = DATA_HEADER_LEN;
+            let extensions = if extended {
+                if buf.len() < start + 2 {
+                    return Err(Error::Incomplete { needed: start + 2 });
+                }
+                let end = start + 2 + u16_at(buf, start) as usize;
+                if buf.len() < end {
+                    return Err(Error::Incomplete { needed: end });
+                }
+                let extensions = &buf[start + 2..end];
+                start = end;
+                extensions
+            } else {
+                &[]
+            };
+            let needed = start + u32_at(buf, 27) as usize;
+            if buf.len() < needed {
+                return Err(Error::Incomplete { needed });
+            }
+            Ok(Packet::Data(DataPacket {
+                kind,
+                rpc_id: u64_at(buf, 1),
+                total_packets: u16_at(buf, 9),
+                seq: u16_at(buf, 11),
+                more_fragments: buf[13] != 0,
+                fragment_index: buf[14],
+                key_phase,
+                dst: addr_at(buf, 15),
+                src: addr_at(buf, 21),
+                extensions,
+                payload: &buf[start..needed],
+            }))
+        }
+        // Error packets are never sealed or extended.
+        PacketType::Error | PacketType::Unknown if key_phase || extended => {
+            Err(Error::UnknownType(id))
+        }
+        PacketType::Error | PacketType::Unknown => {
+            if buf.len() < ERROR_HEADER_LEN {
+                return Err(Error::Incomplete {
+                    needed: ERROR_HEADER_LEN,
+                });
+            }
+            let needed = ERROR_HEADER_LEN + u32_at(buf, 21) as usize;
+            if buf.len() < needed {
+                return Err(Error::Incomplete { needed });
+            }
+            Ok(Packet::Error(ErrorPacket {
+                kind,
+                rpc_id: u64_at(buf, 1),
+                dst: addr_at(buf, 9),
+                src: addr_at(buf, 15),
+                message: &buf[ERROR_HEADER_LEN..needed],
+            }))
+        }
+    }
+}
+
+/// The reserved header at the start of every Symphony message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageHeader {
+    pub offset_to_private: u32,
+    pub service_id: u32,
+    pub method_id: u32,
+}
+
+/// Parses the Symphony header from the first fragment of a message.
+pub fn parse_message_header(payload: &[u8]) -> Result<MessageHeader, Error> {
+    if payload.len() < MESSAGE_HEADER_LEN {
+        return Err(Error::Incomplete {
+            needed: MESSAGE_HEADER_LEN,
+        });
+    }
+    if payload[0] != SYMPHONY_VERSION {
+        return Err(Error::InvalidVersion(payload[0]));
+    }
+    Ok(MessageHeader {
+        offset_to_private: u32_at(payload, 1),
+        service_id: u32_at(payload, 5),
+        method_id: u32_at(payload, 9),
+    })
+}
+
+/// Stamps the service and method IDs into the header of a marshaled
+/// message. Returns false if `message` is too short to hold the header.
+pub fn set_message_ids(message: &mut [u8], service_id: u32, method_id: u32) -> bool {
+    if message.len() < MESSAGE_HEADER_LEN {
+        return false;
+    }
+    message[5..9].copy_from_slice(&service_id.to_le_bytes());
+    message[9..13].copy_from_slice(&method_id.to_le_bytes());
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_packet(payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![1];
+        buf.extend_from_slice(&42u64.to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&[10, 0, 0, 2]);
+        buf.extend_from_slice(&9000u16.to_le_bytes());
+        buf.extend_from_slice(&[10, 0, 0, 1]);
+        buf.extend_from_slice(&5000u16.to_le_bytes());
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn parse_data_packet_and_message_header() {
+        let mut msg = vec![SYMPHONY_VERSION];
+        msg.extend_from_slice(&13u32.to_le_bytes());
+        msg.extend_from_slice(&1u32.to_le_bytes());
+        msg.extend_from_slice(&2u32.to_le_bytes());
+        msg.push(SYMPHONY_VERSION);
+        let buf = data_packet(&msg);
+
+        let Packet::Data(p) = parse(&buf).unwrap() else {
+            panic!("expected a data packet");
+        };
+        assert_eq!(p.kind, PacketType::Request);
+        assert_eq!(p.rpc_id, 42);
+        assert!(p.is_first());
+        assert_eq!(p.dst, "10.0.0.2:9000".parse().unwrap());
+        assert_eq!(p.src, "10.0.0.1:5000".parse().unwrap());
+        assert_eq!(p.payload, &msg[..]);
+
+        let header = parse_message_header(p.payload).unwrap();
+        assert_eq!(
+            header,
+            MessageHeader {
+                offset_to_private: 13,
+                service_id: 1,
+                method_id: 2
+            }
+        );
+    }
+
+    #[test]
+    fn parse_error_packet() {
+        let mut buf = vec![3];
+        buf.extend_from_slice(&7u64.to_le_bytes());
+        buf.extend_from_slice(&[0; 12]);
+        buf.extend_from_slice(&4u32.to_le_bytes());
+        buf.extend_from_slice(b"oops");
+        let packet = parse(&buf).unwrap();
+        assert_eq!(packet.rpc_id(), 7);
+        assert_eq!(packet.encoded_len(), buf.len());
+        let Packet::Error(p) = packet else {
+            panic!("expected an error packet");
+        };
+        assert_eq!(p.message, b"oops");
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        let buf = data_packet(b"payload");
+        let packet = parse(&buf).unwrap();
+        let mut encoded = Vec::new();
+        packet.encode_into(&mut encoded);
+        assert_eq!(encoded, buf);
+
+        let mut rotated = buf.clone();
+        rotated[0] |= KEY_PHASE_BIT;
+        let Packet::Data(p) = parse(&rotated).unwrap() else {
+            panic!("expected a data packet");
+        };
+        assert!(p.key_phase);
+        assert_eq!(p.kind, PacketType::Request);
+        let mut encoded = Vec::new();
+        p.encode_into(&mut encoded);
+        assert_eq!(encoded, rotated);
+
+        let extended = Packet::Data(DataPacket {
+            extensions: b"\x01\x00",
+            ..p
+        });
+        let mut encoded = Vec::new();
+        extended.encode_into(&mut encoded);
+        assert_eq!(encoded[0], 0x81 | EXTENSIONS_BIT);
+        assert_eq!(encoded.len(), extended.encoded_len());
+        assert_eq!(parse(&encoded).unwrap(), extended);
+        assert_eq!(
+            parse(&encoded[..encoded.len() - 1]),
+            Err(Error::Incomplete {
+                needed: encoded.len()
+            })
+        );
+
+        let error = Packet::Error(ErrorPacket {
+            kind: PacketType::Error,
+            rpc_id: 9,
+            dst: "10.0.0.2:9000".parse().unwrap(),
+            src: "10.0.0.1:5000".parse().unwrap(),
+            message: b"unknown service",
+        });
+        let mut encoded = Vec::new();
+        error.encode_into(&mut encoded);
+        assert_eq!(encoded.len(), error.encoded_len());
+        assert_eq!(parse(&encoded).unwrap(), error);
+    }
+
+    #[test]
+    fn parse_rejects_short_and_unknown() {
+        let buf = data_packet(b"abc");
+        assert_eq!(
+            parse(&buf[..buf.len() - 1]),
+            Err(Error::Incomplete { needed: buf.len() })
+        );
+        assert_eq!(parse(&[9]), Err(Error::UnknownType(9)));
+        assert_eq!(parse(&[0x83]), Err(Error::UnknownType(0x83)));
+        assert_eq!(
+            parse_message_header(&[2; 13]),
+            Err(Error::InvalidVersion(2))
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Access to the body of the current stream.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{BufferType, Bytes};
+
+use grpc_frame::{Decoder, Error, Message};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+impl Direction {
+    pub(crate) fn buffer_type(self) -> BufferType {
+        match self {
+            Direction::Request => BufferType::HttpRequestBody,
+            Direction::Response => BufferType::HttpResponseBody,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::Request => "request",
+            Direction::Response => "response",
+        }
+    }
+
+    /// Reads `len` bytes of the body from `start`. In a body callback the
+    /// body holds everything buffered so far if the earlier calls paused,
+    /// and only the new chunk otherwise.
+    pub fn read(self, start: usize, len: usize) -> Option<Bytes> {
+        match hostcalls::get_buffer(self.buffer_type(), start, len) {
+            Ok(body) => body,
+            Err(e) => {
+                log::warn!("failed to read the {} body: {:?}", self.name(), e);
+                None
+            }
+        }
+    }
+
+    /// Replaces `len` bytes of the body from `start` with `data`.
+    pub fn write(self, start: usize, len: usize, data: &[u8]) {
+        if let Err(e) = hostcalls::set_buffer(self.buffer_type(), start, len, data) {
+            log::warn!("failed to write the {} body: {:?}", self.name(), e);
+        }
+    }
+}
+
+/// What a body callback has to act on.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Messages completed by the bytes that arrived since the last call.
+    Messages(Vec<Result<Message, Error>>),
+    /// The body grew past the limit; nothing was read.
+    Overflow,
+    /// The body passes through uninspected.
+    Skip,
+}
+
+/// One direction of a stream that is buffered, i.e. whose body callbacks
+/// return `Action::Pause` until `end_of_stream`, and decoded as it grows.
+///
+/// Each call reads only the bytes after the previous one, so a body is
+/// scanned once however many callbacks it spans. Past `max_bytes` the
+/// filter decides between failing the stream and [`pass_through`].
+///
+/// [`pass_through`]: BufferedBody::pass_through
+#[derive(Debug)]
+pub struct BufferedBody {
+    direction: Direction,
+    max_bytes: usize,
+    decoder: Decoder,
+    passthrough: bool,
+}
+
+impl BufferedBody {
+    pub fn new(direction: Direction, max_bytes: usize) -> BufferedBody {
+        BufferedBody {
+            direction,
+            max_bytes,
+            decoder: Decoder::new(),
+            passthrough: false,
+        }
+    }
+
+    /// Stops inspecting the body; later calls return [`Step::Skip`].
+    pub fn pass_through(&mut self) {
+        self.passthrough = true;
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.passthrough
+    }
+
+    /// Bytes of an incomplete message; non-zero at `end_of_stream` means the
+    /// body was truncated.
+    pub fn pending(&self) -> usize {
+        self.decoder.pending()
+    }
+
+    /// Handles a body callback for `body_size` buffered bytes.
+    pub fn poll(&mut self, body_size: usize) -> Step {
+        if let Some(step) = self.check(body_size) {
+            return step;
+        }
+        let read = self.decoder.received();
+        if body_size <= read {
+            return self.advance(&[]);
+        }
+        let chunk = self.direction.read(read, body_size - read);
+        self.advance(chunk.as_deref().unwrap_or_default())
+    }
+
+    /// The step for a body that is not read at all.
+    fn check(&self, body_size: usize) -> Option<Step> {
+        if self.passthrough {
+            Some(Step::Skip)
+        } else if body_size > self.max_bytes {
+            Some(Step::Overflow)
+        } else {
+            None
+        }
+    }
+
+    fn advance(&mut self, chunk: &[u8]) -> Step {
+        self.decoder.push(chunk);
+        Step::Messages(self.decoder.by_ref().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffered_body_steps() {
+        let mut body = BufferedBody::new(Direction::Request, 16);
+        let frame = grpc_frame::encode(b"abc", false);
+        assert_eq!(body.advance(&frame[..4]), Step::Messages(Vec::new()));
+        assert_eq!(body.pending(), 4);
+        let message = Message {
+            compressed: false,
+            payload: b"abc".to_vec(),
+        };
+        assert_eq!(body.advance(&frame[4..]), Step::Messages(vec![Ok(message)]));
+        assert_eq!(body.pending(), 0);
+        assert_eq!(body.check(16), None);
+        assert_eq!(body.check(17), Some(Step::Overflow));
+        body.pass_through();
+        assert_eq!(body.check(0), Some(Step::Skip));
+    }
+}
@@ -0,0 +1,48 @@
+//! Typed helpers for the proxy-wasm gRPC filters.
+//!
+//! Filters otherwise repeat the same raw host calls: fetch the buffered body,
+//! split it into frames, decode each payload with prost, re-frame the result
+//! and write it back, or answer with a trailers-only error. [`message`] wraps
+//! those steps for a complete body, [`BufferedBody`] decodes a body while it
+//! is being buffered, [`HeldResponse`] holds a response body until the
+//! response completes, and [`local_grpc_reply`] ends a stream from any
+//! callback. [`Rng`] draws the sampling and fault decisions.
+//!
+//! Everything acts on the stream whose callback is running, like the
+//! `HttpContext` methods it wraps.
+
+use proxy_wasm::traits::{Context, HttpContext};
+use proxy_wasm::types::Action;
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+
+pub mod body;
+pub mod message;
+pub mod response;
+pub mod rng;
+#[cfg(feature = "test-host")]
+pub mod test_host;
+
+pub use body::{BufferedBody, Direction, Step};
+pub use message::{
+    read_grpc_message, read_grpc_messages, write_grpc_message, write_grpc_messages, ReadError,
+};
+pub use response::HeldResponse;
+pub use rng::Rng;
+
+/// The stream whose callback is running. The `HttpContext` methods only
+/// issue host calls, so this borrows them without a context at hand.
+struct Current;
+
+impl Context for Current {}
+
+impl HttpContext for Current {}
+
+/// Fails the current stream with a trailers-only gRPC error. Returns
+/// `Action::Pause` for the callback to return, so the request never
+/// reaches the upstream.
+pub fn local_grpc_reply(code: Code, message: &str) -> Action {
+    Current.send_grpc_error(code, message);
+    Action::Pause
+}
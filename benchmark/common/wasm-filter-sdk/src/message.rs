@@ -0,0 +1,138 @@
+//! Typed reads and writes of whole gRPC bodies.
+//!
+//! These expect the complete body, i.e. a callback at `end_of_stream` after
+//! the earlier ones paused. Compressed messages are reported, not decoded;
+//! filters that handle them go through `grpc_frame::codec`.
+
+use std::fmt;
+
+use prost::Message;
+
+use crate::body::Direction;
+
+#[derive(Debug)]
+pub enum ReadError {
+    /// The host returned no body.
+    NoBody,
+    /// The body holds no message.
+    Empty,
+    /// The body is not valid gRPC framing, or ends inside a frame.
+    Frame(grpc_frame::Error),
+    /// The message is compressed with the stream's `grpc-encoding`.
+    Compressed,
+    Decode(prost::DecodeError),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::NoBody => f.write_str("no body"),
+            ReadError::Empty => f.write_str("no gRPC message in the body"),
+            ReadError::Frame(e) => e.fmt(f),
+            ReadError::Compressed => f.write_str("compressed gRPC message"),
+            ReadError::Decode(e) => write!(f, "decode error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+fn decode<T: Message + Default>(frame: grpc_frame::Frame<'_>) -> Result<T, ReadError> {
+    if frame.compressed {
+        return Err(ReadError::Compressed);
+    }
+    T::decode(frame.payload).map_err(ReadError::Decode)
+}
+
+/// Decodes every message of a complete body.
+pub fn decode_messages<T: Message + Default>(body: &[u8]) -> Result<Vec<T>, ReadError> {
+    let mut frames = grpc_frame::frames(body);
+    let mut messages = Vec::new();
+    for frame in frames.by_ref() {
+        messages.push(decode(frame.map_err(ReadError::Frame)?)?);
+    }
+    let remainder = frames.remainder();
+    if !remainder.is_empty() {
+        if let Err(e) = grpc_frame::parse(remainder) {
+            return Err(ReadError::Frame(e));
+        }
+    }
+    Ok(messages)
+}
+
+/// Frames each message, back to back.
+pub fn encode_messages<T: Message>(messages: &[T]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for message in messages {
+        grpc_frame::encode_into(&mut body, &message.encode_to_vec(), false);
+    }
+    body
+}
+
+/// Reads and decodes the first message of the body; any later ones are
+/// ignored.
+pub fn read_grpc_message<T: Message + Default>(
+    direction: Direction,
+    body_size: usize,
+) -> Result<T, ReadError> {
+    let body = direction.read(0, body_size).ok_or(ReadError::NoBody)?;
+    match grpc_frame::parse(&body) {
+        Ok(frame) => decode(frame),
+        Err(_) if body.is_empty() => Err(ReadError::Empty),
+        Err(e) => Err(ReadError::Frame(e)),
+    }
+}
+
+/// Reads and decodes every message of the body.
+pub fn read_grpc_messages<T: Message + Default>(
+    direction: Direction,
+    body_size: usize,
+) -> Result<Vec<T>, ReadError> {
+    let body = direction.read(0, body_size).ok_or(ReadError::NoBody)?;
+    decode_messages(&body)
+}
+
+/// Replaces the `body_size` bytes of the body with `message`.
+pub fn write_grpc_message<T: Message>(direction: Direction, body_size: usize, message: &T) {
+    write_grpc_messages(direction, body_size, std::slice::from_ref(message));
+}
+
+/// Replaces the `body_size` bytes of the body with `messages`.
+pub fn write_grpc_messages<T: Message>(direction: Direction, body_size: usize, messages: &[T]) {
+    direction.write(0, body_size, &encode_messages(messages));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Key {
+        #[prost(string, tag = "1")]
+        key: String,
+    }
+
+    #[test]
+    fn messages_roundtrip() {
+        let keys = vec![
+            Key {
+                key: "a".to_string(),
+            },
+            Key {
+                key: "b".to_string(),
+            },
+        ];
+        let body = encode_messages(&keys);
+        assert_eq!(decode_messages::<Key>(&body).unwrap(), keys);
+        assert!(decode_messages::<Key>(&[]).unwrap().is_empty());
+        assert!(matches!(
+            decode_messages::<Key>(&body[..body.len() - 1]),
+            Err(ReadError::Frame(grpc_frame::Error::Incomplete { .. }))
+        ));
+        let compressed = grpc_frame::encode(b"", true);
+        assert!(matches!(
+            decode_messages::<Key>(&compressed),
+            Err(ReadError::Compressed)
+        ));
+    }
+}
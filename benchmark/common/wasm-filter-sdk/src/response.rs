@@ -0,0 +1,66 @@
+//! Holding a response body until the response is complete.
+
+/// The response body of a stream, held until the response completes.
+///
+/// A filter that acts on the whole response pauses its body callbacks, but
+/// it cannot wait for `end_of_stream` there: gRPC responses end with
+/// trailers, so the body callbacks of a complete response never see it and
+/// the body is still buffered when `on_http_response_trailers` runs. Feed
+/// both callbacks through here and act on the size they return, which
+/// comes once per stream from whichever callback completes the response.
+#[derive(Debug, Default)]
+pub struct HeldResponse {
+    size: usize,
+    complete: bool,
+}
+
+impl HeldResponse {
+    /// Handles `on_http_response_body`. Returns the size of the whole body
+    /// at `end_of_stream`, and None while the callback should return
+    /// `Action::Pause`.
+    pub fn body(&mut self, body_size: usize, end_of_stream: bool) -> Option<usize> {
+        self.size = body_size;
+        if !end_of_stream {
+            return None;
+        }
+        self.complete = true;
+        Some(body_size)
+    }
+
+    /// Handles `on_http_response_trailers`. Returns the size of the held
+    /// body, or None if no body was held or it was already completed.
+    pub fn trailers(&mut self) -> Option<usize> {
+        if self.complete || self.size == 0 {
+            return None;
+        }
+        self.complete = true;
+        Some(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_on_trailers() {
+        let mut response = HeldResponse::default();
+        assert_eq!(response.body(5, false), None);
+        assert_eq!(response.body(12, false), None);
+        assert_eq!(response.trailers(), Some(12));
+        assert_eq!(response.trailers(), None);
+    }
+
+    #[test]
+    fn completes_on_end_of_stream() {
+        let mut response = HeldResponse::default();
+        assert_eq!(response.body(5, false), None);
+        assert_eq!(response.body(12, true), Some(12));
+        assert_eq!(response.trailers(), None);
+    }
+
+    #[test]
+    fn nothing_held() {
+        assert_eq!(HeldResponse::default().trailers(), None);
+    }
+}
@@ -0,0 +1,69 @@
+//! A cheap random number generator for sampling and fault injection.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An xorshift64 generator, not fit for anything security related. Clones
+/// share the state, so a root context hands one to each of its streams.
+#[derive(Debug, Clone)]
+pub struct Rng(Rc<Cell<u64>>);
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng(Rc::new(Cell::new(1)))
+    }
+}
+
+impl Rng {
+    /// Seeds the generator from `now`, typically the host's current time in
+    /// `on_vm_start`.
+    pub fn seed(&self, now: SystemTime) {
+        let seed = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        // xorshift state must be non-zero.
+        self.0.set(seed | 1);
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        let mut x = self.0.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0.set(x);
+        x
+    }
+
+    /// Returns true with the given probability, in percent.
+    pub fn roll(&self, percent: f64) -> bool {
+        if percent <= 0.0 {
+            return false;
+        }
+        (self.next_u64() % 10_000) as f64 / 100.0 < percent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_by_percent() {
+        let rng = Rng::default();
+        rng.seed(UNIX_EPOCH);
+        assert!((0..1000).all(|_| rng.roll(100.0)));
+        assert!((0..1000).all(|_| !rng.roll(0.0)));
+        let hits = (0..10_000).filter(|_| rng.roll(25.0)).count();
+        assert!((2000..3000).contains(&hits), "{} hits", hits);
+    }
+
+    #[test]
+    fn clones_share_the_state() {
+        let rng = Rng::default();
+        let other = rng.clone();
+        let first = rng.next_u64();
+        assert_ne!(other.next_u64(), first);
+        assert_ne!(first, 0);
+    }
+}
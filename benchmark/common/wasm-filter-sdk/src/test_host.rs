@@ -0,0 +1,414 @@
+//! A stand-in for the proxy-wasm host, for filter unit tests.
+//!
+//! Behind the `test-host` feature, this defines the host functions the
+//! filters reach, backed by thread-local state, so a test can call a
+//! context's callbacks natively and then inspect the bodies, headers and
+//! shared data they left behind. Each test runs on its own thread and so
+//! starts from an empty host.
+//!
+//! A filter crate enables the feature in its dev-dependencies. Its native
+//! test binary has a C runtime `_start` of its own, so the filter marks its
+//! entry point `#[cfg_attr(not(test), no_mangle)]`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::slice;
+
+use proxy_wasm::types::{MapType, Status};
+
+use crate::body::Direction;
+
+/// A reply sent with `send_http_response`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalResponse {
+    pub status: u32,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Host {
+    buffers: HashMap<u32, Vec<u8>>,
+    maps: HashMap<u32, Vec<(String, String)>>,
+    // Value and CAS token of each shared data key.
+    shared: HashMap<String, (Vec<u8>, u32)>,
+    now: u64,
+    reply: Option<LocalResponse>,
+    // Name and value of each metric, by ID.
+    metrics: Vec<(String, u64)>,
+}
+
+thread_local! {
+    static HOST: RefCell<Host> = RefCell::default();
+}
+
+fn with<T>(f: impl FnOnce(&mut Host) -> T) -> T {
+    HOST.with(|host| f(&mut host.borrow_mut()))
+}
+
+/// Appends a chunk to the body of `direction`, as the host does before a
+/// body callback, and returns the size now buffered.
+pub fn push_body(direction: Direction, chunk: &[u8]) -> usize {
+    with(|host| {
+        let body = host
+            .buffers
+            .entry(direction.buffer_type() as u32)
+            .or_default();
+        body.extend_from_slice(chunk);
+        body.len()
+    })
+}
+
+/// The body of `direction` as the filter left it.
+pub fn body(direction: Direction) -> Vec<u8> {
+    with(|host| {
+        host.buffers
+            .get(&(direction.buffer_type() as u32))
+            .cloned()
+            .unwrap_or_default()
+    })
+}
+
+pub fn set_header(map: MapType, name: &str, value: &str) {
+    with(|host| {
+        let map = host.maps.entry(map as u32).or_default();
+        map.retain(|(key, _)| key != name);
+        map.push((name.to_string(), value.to_string()));
+    })
+}
+
+pub fn header(map: MapType, name: &str) -> Option<String> {
+    with(|host| {
+        let map = host.maps.get(&(map as u32))?;
+        map.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    })
+}
+
+/// Sets the host's current time, in nanoseconds since the epoch.
+pub fn set_time(nanos: u64) {
+    with(|host| host.now = nanos)
+}
+
+/// The reply the filter sent in place of the upstream's, if any.
+pub fn local_response() -> Option<LocalResponse> {
+    with(|host| host.reply.clone())
+}
+
+/// The value of the metric defined as `name`, if any.
+pub fn metric(name: &str) -> Option<u64> {
+    with(|host| {
+        host.metrics
+            .iter()
+            .find(|(metric, _)| metric == name)
+            .map(|(_, value)| *value)
+    })
+}
+
+fn update_metric(metric_id: u32, update: impl FnOnce(&mut u64)) -> Status {
+    with(|host| match host.metrics.get_mut(metric_id as usize) {
+        Some((_, value)) => {
+            update(value);
+            Status::Ok
+        }
+        None => Status::NotFound,
+    })
+}
+
+unsafe fn input<'a>(data: *const u8, size: usize) -> &'a [u8] {
+    if size == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, size)
+    }
+}
+
+unsafe fn input_string(data: *const u8, size: usize) -> String {
+    String::from_utf8_lossy(input(data, size)).into_owned()
+}
+
+/// Hands `data` to the SDK, which takes ownership of it as a `Vec`.
+unsafe fn output(data: Vec<u8>, return_data: *mut *mut u8, return_size: *mut usize) -> Status {
+    let data = Box::leak(data.into_boxed_slice());
+    *return_size = data.len();
+    *return_data = data.as_mut_ptr();
+    Status::Ok
+}
+
+/// Reverses proxy-wasm's header map serialization: a count, a pair of
+/// lengths per entry, then each key and value NUL-terminated.
+fn deserialize_map(bytes: &[u8]) -> Vec<(String, String)> {
+    let word = |at: usize| {
+        bytes
+            .get(at..at + 4)
+            .map_or(0, |b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+    };
+    let count = word(0);
+    let mut at = 4 + count * 8;
+    let mut map = Vec::with_capacity(count);
+    for i in 0..count {
+        let (key_size, value_size) = (word(4 + i * 8), word(8 + i * 8));
+        let key = String::from_utf8_lossy(&bytes[at..at + key_size]).into_owned();
+        at += key_size + 1;
+        let value = String::from_utf8_lossy(&bytes[at..at + value_size]).into_owned();
+        at += value_size + 1;
+        map.push((key, value));
+    }
+    map
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_log(
+    _level: u32,
+    message_data: *const u8,
+    message_size: usize,
+) -> Status {
+    eprintln!("{}", input_string(message_data, message_size));
+    Status::Ok
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_get_current_time_nanoseconds(return_time: *mut u64) -> Status {
+    *return_time = with(|host| host.now);
+    Status::Ok
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_get_buffer_bytes(
+    buffer_type: u32,
+    start: usize,
+    max_size: usize,
+    return_buffer_data: *mut *mut u8,
+    return_buffer_size: *mut usize,
+) -> Status {
+    let data = with(|host| {
+        let body = host.buffers.get(&buffer_type)?;
+        let end = start.saturating_add(max_size).min(body.len());
+        body.get(start..end)
+            .filter(|data| !data.is_empty())
+            .map(<[u8]>::to_vec)
+    });
+    match data {
+        Some(data) => output(data, return_buffer_data, return_buffer_size),
+        None => Status::NotFound,
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_set_buffer_bytes(
+    buffer_type: u32,
+    start: usize,
+    size: usize,
+    buffer_data: *const u8,
+    buffer_size: usize,
+) -> Status {
+    let data = input(buffer_data, buffer_size);
+    with(|host| {
+        let body = host.buffers.entry(buffer_type).or_default();
+        let end = start.saturating_add(size).min(body.len());
+        let start = start.min(end);
+        body.splice(start..end, data.iter().copied());
+    });
+    Status::Ok
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_get_header_map_value(
+    map_type: u32,
+    key_data: *const u8,
+    key_size: usize,
+    return_value_data: *mut *mut u8,
+    return_value_size: *mut usize,
+) -> Status {
+    let key = input_string(key_data, key_size);
+    let value = with(|host| {
+        let map = host.maps.get(&map_type)?;
+        map.iter().find(|(k, _)| *k == key).map(|(_, v)| v.clone())
+    });
+    match value {
+        Some(value) => output(value.into_bytes(), return_value_data, return_value_size),
+        None => Status::NotFound,
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_replace_header_map_value(
+    map_type: u32,
+    key_data: *const u8,
+    key_size: usize,
+    value_data: *const u8,
+    value_size: usize,
+) -> Status {
+    let key = input_string(key_data, key_size);
+    let value = input_string(value_data, value_size);
+    with(|host| {
+        let map = host.maps.entry(map_type).or_default();
+        map.retain(|(k, _)| *k != key);
+        map.push((key, value));
+    });
+    Status::Ok
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_add_header_map_value(
+    map_type: u32,
+    key_data: *const u8,
+    key_size: usize,
+    value_data: *const u8,
+    value_size: usize,
+) -> Status {
+    let key = input_string(key_data, key_size);
+    let value = input_string(value_data, value_size);
+    with(|host| host.maps.entry(map_type).or_default().push((key, value)));
+    Status::Ok
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_remove_header_map_value(
+    map_type: u32,
+    key_data: *const u8,
+    key_size: usize,
+) -> Status {
+    let key = input_string(key_data, key_size);
+    with(|host| {
+        if let Some(map) = host.maps.get_mut(&map_type) {
+            map.retain(|(k, _)| *k != key);
+        }
+    });
+    Status::Ok
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_get_property(
+    _path_data: *const u8,
+    _path_size: usize,
+    _return_value_data: *mut *mut u8,
+    _return_value_size: *mut usize,
+) -> Status {
+    // No route metadata: filters see their defaults.
+    Status::NotFound
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_get_shared_data(
+    key_data: *const u8,
+    key_size: usize,
+    return_value_data: *mut *mut u8,
+    return_value_size: *mut usize,
+    return_cas: *mut u32,
+) -> Status {
+    let key = input_string(key_data, key_size);
+    match with(|host| host.shared.get(&key).cloned()) {
+        Some((value, cas)) => {
+            *return_cas = cas;
+            output(value, return_value_data, return_value_size)
+        }
+        None => Status::NotFound,
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_set_shared_data(
+    key_data: *const u8,
+    key_size: usize,
+    value_data: *const u8,
+    value_size: usize,
+    cas: u32,
+) -> Status {
+    let key = input_string(key_data, key_size);
+    let value = input(value_data, value_size).to_vec();
+    with(|host| {
+        let current = host.shared.get(&key).map_or(0, |(_, cas)| *cas);
+        if cas != 0 && cas != current {
+            return Status::CasMismatch;
+        }
+        host.shared.insert(key, (value, current + 1));
+        Status::Ok
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_define_metric(
+    _metric_type: u32,
+    name_data: *const u8,
+    name_size: usize,
+    return_id: *mut u32,
+) -> Status {
+    let name = input_string(name_data, name_size);
+    *return_id = with(|host| {
+        let id = host.metrics.iter().position(|(metric, _)| *metric == name);
+        id.unwrap_or_else(|| {
+            host.metrics.push((name, 0));
+            host.metrics.len() - 1
+        }) as u32
+    });
+    Status::Ok
+}
+
+#[no_mangle]
+unsafe extern "C" fn proxy_get_metric(metric_id: u32, return_value: *mut u64) -> Status {
+    let value = with(|host| host.metrics.get(metric_id as usize).map(|(_, v)| *v));
+    match value {
+        Some(value) => {
+            *return_value = value;
+            Status::Ok
+        }
+        None => Status::NotFound,
+    }
+}
+
+#[no_mangle]
+extern "C" fn proxy_increment_metric(metric_id: u32, offset: i64) -> Status {
+    update_metric(metric_id, |value| {
+        *value = value.saturating_add_signed(offset)
+    })
+}
+
+#[no_mangle]
+extern "C" fn proxy_record_metric(metric_id: u32, value: u64) -> Status {
+    update_metric(metric_id, |current| *current = value)
+}
+
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+unsafe extern "C" fn proxy_send_local_response(
+    status_code: u32,
+    _status_code_details_data: *const u8,
+    _status_code_details_size: usize,
+    body_data: *const u8,
+    body_size: usize,
+    headers_data: *const u8,
+    headers_size: usize,
+    _grpc_status: i32,
+) -> Status {
+    let reply = LocalResponse {
+        status: status_code,
+        headers: deserialize_map(input(headers_data, headers_size)),
+        body: input(body_data, body_size).to_vec(),
+    };
+    with(|host| host.reply = Some(reply));
+    Status::Ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserializes_header_maps() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        for size in [1u32, 2, 3, 0] {
+            bytes.extend_from_slice(&size.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"a\0bc\0def\0\0");
+        assert_eq!(
+            deserialize_map(&bytes),
+            vec![
+                ("a".to_string(), "bc".to_string()),
+                ("def".to_string(), String::new()),
+            ]
+        );
+    }
+}
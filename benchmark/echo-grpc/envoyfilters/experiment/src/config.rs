@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+
+use grpc_frame::trace;
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"header": "x-user-id", "variant_header": "x-variant", "salt": "exp1",
+///  "variants": [{"name": "control", "weight": 50},
+///               {"name": "shout", "weight": 25, "transform": {"op": "uppercase"}},
+///               {"name": "tagged", "weight": 25, "transform": {"op": "prefix", "value": "[b] "}}]}
+/// ```
+///
+/// Requests are assigned to a variant by hashing `salt` and the value of
+/// `header`, in proportion to the weights, so a user stays in one variant
+/// for as long as the salt is unchanged. A new salt reshuffles everyone for
+/// the next experiment. Requests without the header are not part of the
+/// experiment.
+///
+/// The variant's `transform` rewrites the `message` of each `EchoRequest`:
+/// `uppercase`, `lowercase`, `prefix` and `suffix` (with `value`), or
+/// `replace` (with `match` and `replace`). The variant name is stamped on
+/// the request and the response as `variant_header`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub header: String,
+    pub variant_header: String,
+    pub salt: String,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Variant {
+    pub name: String,
+    pub weight: u64,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Transform {
+    Uppercase,
+    Lowercase,
+    Prefix {
+        value: String,
+    },
+    Suffix {
+        value: String,
+    },
+    Replace {
+        #[serde(rename = "match")]
+        pattern: String,
+        replace: String,
+    },
+}
+
+impl Transform {
+    pub fn apply(&self, message: &str) -> String {
+        match self {
+            Transform::Uppercase => message.to_uppercase(),
+            Transform::Lowercase => message.to_lowercase(),
+            Transform::Prefix { value } => format!("{}{}", value, message),
+            Transform::Suffix { value } => format!("{}{}", message, value),
+            Transform::Replace { pattern, replace } => message.replace(pattern, replace),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            header: "x-user-id".to_string(),
+            variant_header: "x-variant".to_string(),
+            salt: String::new(),
+            variants: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.variants.is_empty() {
+            return Err("at least one variant is required".to_string());
+        }
+        if config.variants.iter().map(|v| v.weight).sum::<u64>() == 0 {
+            return Err("variant weights add up to 0".to_string());
+        }
+        let mut names = HashSet::new();
+        for variant in &config.variants {
+            if variant.name.is_empty() || !variant.name.bytes().all(|b| b.is_ascii_graphic()) {
+                return Err(format!("invalid variant name {:?}", variant.name));
+            }
+            if !names.insert(&variant.name) {
+                return Err(format!("duplicate variant {}", variant.name));
+            }
+            if let Some(Transform::Replace { pattern, .. }) = &variant.transform {
+                if pattern.is_empty() {
+                    return Err(format!("empty match pattern in variant {}", variant.name));
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Index of the variant for the request identified by `id`.
+    pub fn assign(&self, id: &str) -> usize {
+        let total: u64 = self.variants.iter().map(|v| v.weight).sum();
+        let mut point = trace::hash_id(&format!("{}:{}", self.salt, id)) % total;
+        for (i, variant) in self.variants.iter().enumerate() {
+            if point < variant.weight {
+                return i;
+            }
+            point -= variant.weight;
+        }
+        self.variants.len() - 1
+    }
+}
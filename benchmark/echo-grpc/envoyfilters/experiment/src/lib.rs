@@ -0,0 +1,158 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use wasm_filter_sdk::{read_grpc_messages, write_grpc_messages, Direction};
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/pb.rs"));
+}
+
+mod config;
+mod metrics;
+use config::Config;
+use metrics::Metrics;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(ExperimentRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+        })
+    });
+}
+
+struct ExperimentRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+}
+
+impl Context for ExperimentRoot {}
+
+impl RootContext for ExperimentRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded experiment configuration: {:?}", config);
+                // The metric names depend on the variants.
+                match Metrics::define(&config) {
+                    Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+                    Err(e) => log::error!("failed to define metrics: {:?}", e),
+                }
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Experiment {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            variant: None,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Experiment {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    // Index of the assigned variant; None outside the experiment.
+    variant: Option<usize>,
+}
+
+impl Context for Experiment {}
+
+impl Experiment {
+    /// Rewrites every `EchoRequest` of the buffered body.
+    fn transform(&self, body_size: usize) {
+        let Some(transform) = self
+            .variant
+            .and_then(|i| self.config.variants[i].transform.as_ref())
+        else {
+            return;
+        };
+        match read_grpc_messages::<echo::EchoRequest>(Direction::Request, body_size) {
+            Ok(mut requests) => {
+                for req in &mut requests {
+                    req.message = transform.apply(&req.message);
+                }
+                write_grpc_messages(Direction::Request, body_size, &requests);
+            }
+            Err(e) => {
+                // The request still counts for its variant, untransformed.
+                log::warn!("cannot transform the request: {}", e);
+                if let Some(metrics) = &self.metrics {
+                    metrics.transform_failure();
+                }
+            }
+        }
+    }
+}
+
+impl HttpContext for Experiment {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        if self.config.variants.is_empty() {
+            return Action::Continue;
+        }
+        let Some(id) = self.get_http_request_header(&self.config.header) else {
+            return Action::Continue;
+        };
+        let variant = self.config.assign(&id);
+        self.variant = Some(variant);
+        self.set_http_request_header(
+            &self.config.variant_header,
+            Some(&self.config.variants[variant].name),
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.assigned(variant);
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if self.variant.is_none() {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        self.transform(body_size);
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        if let Some(variant) = self.variant {
+            self.set_http_response_header(
+                &self.config.variant_header,
+                Some(&self.config.variants[variant].name),
+            );
+        }
+
+        Action::Continue
+    }
+}
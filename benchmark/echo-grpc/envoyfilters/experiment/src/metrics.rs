@@ -0,0 +1,55 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.experiment.control.requests`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+use crate::config::Config;
+
+pub struct Metrics {
+    /// Counters of assigned requests, in the order of the variants.
+    requests: Vec<u32>,
+    /// Counter of requests whose body could not be transformed.
+    transform_failures: u32,
+}
+
+impl Metrics {
+    /// Defines the metrics of the configured variants.
+    pub fn define(config: &Config) -> Result<Metrics, Status> {
+        let requests = config
+            .variants
+            .iter()
+            .map(|variant| {
+                hostcalls::define_metric(
+                    MetricType::Counter,
+                    &format!("experiment.{}.requests", variant.name),
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Metrics {
+            requests,
+            transform_failures: hostcalls::define_metric(
+                MetricType::Counter,
+                "experiment.transform_failures",
+            )?,
+        })
+    }
+
+    pub fn assigned(&self, variant: usize) {
+        if let Some(&metric_id) = self.requests.get(variant) {
+            increment(metric_id);
+        }
+    }
+
+    pub fn transform_failure(&self) {
+        increment(self.transform_failures);
+    }
+}
+
+fn increment(metric_id: u32) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, 1) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
@@ -2,7 +2,9 @@ use proxy_wasm::traits::{Context, HttpContext};
 use proxy_wasm::types::{Action, LogLevel};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use prost::Message;
+use grpc_frame::status::Code;
+use wasm_filter_sdk::{local_grpc_reply, read_grpc_message, Direction, ReadError};
+
 pub mod echo {
     include!(concat!(env!("OUT_DIR"), "/pb.rs"));
 }
@@ -41,33 +43,24 @@ impl HttpContext for Firewall {
             return Action::Pause;
         }
 
-        // Replace the message body if it contains the text "secret".
-        // Since we returned "Pause" previuously, this will return the whole body.
-        if let Some(body) = self.get_http_request_body(0, body_size) {
-            // log::warn!("body: {:?}", body);
-            // Parse grpc payload, skip the first 5 bytes
-            match echo::Msg::decode(&body[5..]) {
-                Ok(req) => {
-                    // log::info!("req: {:?}", req);
-                    // log::warn!("body.len(): {}", req.body.len());
-                    log::warn!("body : {}", req.body);
-                    if req.body == "test" {
-                        // self.abort_count += 1; // Increment the counter
-                        // log::warn!("Aborting a request!!!! Abort Count: {}", self.abort_count);
-                        let counter_val = GLOBAL_COUNTER.fetch_add(1, Ordering::SeqCst) + 1;
-                        log::warn!("Global counter value: {}", counter_val);
-                        self.send_http_response(
-                            403,
-                            vec![
-                                ("grpc-status", "7"),
-                                // ("grpc-message", "Access forbidden.\n"),
-                            ],
-                            None,
-                        );
-                        return Action::Pause;
-                    }
+        // Since we returned "Pause" previously, the body is complete.
+        match read_grpc_message::<echo::EchoRequest>(Direction::Request, body_size) {
+            Ok(req) => {
+                log::warn!("body : {}", req.message);
+                if req.message == "test" {
+                    let counter_val = GLOBAL_COUNTER.fetch_add(1, Ordering::SeqCst) + 1;
+                    log::warn!("Global counter value: {}", counter_val);
+                    return local_grpc_reply(Code::PermissionDenied, "Access forbidden.");
                 }
-                Err(e) => log::warn!("decode error: {}", e),
+            }
+            Err(ReadError::NoBody) => {}
+            Err(e @ (ReadError::Empty | ReadError::Frame(_))) => {
+                log::warn!("frame error: {}", e);
+                return local_grpc_reply(Code::InvalidArgument, "malformed gRPC frame");
+            }
+            Err(e) => {
+                log::warn!("decode error: {}", e);
+                return local_grpc_reply(Code::InvalidArgument, "malformed EchoRequest");
             }
         }
 
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+
+use base64::Engine;
+use grpc_frame::control::Poll;
+use grpc_frame::status::{Code, TrailerPolicy};
+use serde::Deserialize;
+
+use crate::echo;
+use crate::reflect::Schema;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"rules": [{"field": "message", "match": "Bob", "replace": "Alice"}]}
+/// ```
+///
+/// When `descriptor_set` holds a base64-encoded `FileDescriptorSet`
+/// (`protoc --include_imports --descriptor_set_out=...`), messages are decoded
+/// through reflection and rule fields are paths such as `EchoRequest.message`.
+///
+/// Rules apply to whichever direction carries the named message type: a bare
+/// `message` or `EchoRequest.message` rewrites requests, `EchoResponse.message`
+/// rewrites responses.
+///
+/// `rule_sets` holds named alternatives to `rules`, which a route selects
+/// with its `rule_set` metadata (see `grpc_frame::route`):
+/// `{"rule_sets": {"strict": [{"field": "message", "match": "Bob", "replace": ""}]}}`.
+///
+/// `trailers` rewrites the response status, e.g.
+/// `{"status_map": {"UNAVAILABLE": "RESOURCE_EXHAUSTED"}, "message": "overloaded", "details": true}`.
+///
+/// `canary` limits the rules to a share of the traffic, e.g.
+/// `{"percent": 5, "id_header": "x-request-id", "tag_header": "x-mutation-canary"}`.
+/// Streams are selected by the trace ID of their `traceparent`, or else by
+/// `id_header`, so retries and every hop of a trace land on the same side;
+/// streams without either ID are left alone. Selected requests carry
+/// `tag_header: true` upstream.
+///
+/// With `"control": true` the root is the control singleton: it takes new
+/// configurations from the `mutation.config` shared queue and publishes them
+/// to the workers (see `grpc_frame::control`). With `poll` set as well, e.g.
+/// `{"control": true, "poll": {"cluster": "outbound|8080||config-server.default.svc.cluster.local", "path": "/mutation"}}`,
+/// it also fetches the configuration from that endpoint every `interval_ms`.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub rules: Vec<Rule>,
+    pub rule_sets: BTreeMap<String, Vec<Rule>>,
+    pub descriptor_set: Option<String>,
+    pub control: bool,
+    pub poll: Option<Poll>,
+    pub canary: Option<Canary>,
+    trailers: TrailerConfig,
+    #[serde(skip)]
+    pub schema: Option<Schema>,
+    #[serde(skip)]
+    rule_set_schemas: BTreeMap<String, Schema>,
+    #[serde(skip)]
+    pub trailer_policy: TrailerPolicy,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct TrailerConfig {
+    status_map: BTreeMap<String, String>,
+    message: Option<String>,
+    details: bool,
+}
+
+/// Share of the streams the rules apply to.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Canary {
+    /// Percentage of the streams, from 0 to 100.
+    pub percent: f64,
+    /// Request ID header used when there is no `traceparent`.
+    pub id_header: String,
+    /// Request header marking the selected streams.
+    pub tag_header: String,
+}
+
+impl Default for Canary {
+    fn default() -> Self {
+        Canary {
+            percent: 0.0,
+            id_header: "x-request-id".to_string(),
+            tag_header: "x-mutation-canary".to_string(),
+        }
+    }
+}
+
+/// Replaces every occurrence of `pattern` in a string field with `replace`.
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    #[serde(default = "default_field")]
+    pub field: String,
+    #[serde(rename = "match")]
+    pub pattern: String,
+    pub replace: String,
+}
+
+fn default_field() -> String {
+    "message".to_string()
+}
+
+impl Config {
+    /// The built-in rule set used when no configuration is supplied.
+    pub fn builtin() -> Config {
+        Config {
+            rules: vec![Rule {
+                field: default_field(),
+                pattern: "Bob".to_string(),
+                replace: "Alice".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        let all_rules = config
+            .rules
+            .iter()
+            .chain(config.rule_sets.values().flatten());
+        for rule in all_rules.clone() {
+            if rule.pattern.is_empty() {
+                return Err(format!("empty match pattern for field {}", rule.field));
+            }
+        }
+        if let Some(poll) = &config.poll {
+            poll.validate()?;
+        }
+        if let Some(canary) = &config.canary {
+            if !(0.0..=100.0).contains(&canary.percent) {
+                return Err(format!(
+                    "canary percent {} is not between 0 and 100",
+                    canary.percent
+                ));
+            }
+        }
+        config.trailer_policy = config.trailers.policy()?;
+        if let Some(encoded) = &config.descriptor_set {
+            let descriptor_set = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| format!("invalid descriptor_set: {}", e))?;
+            config.schema = Some(Schema::new(&descriptor_set, &config.rules)?);
+            for (name, rules) in &config.rule_sets {
+                let schema = Schema::new(&descriptor_set, rules)
+                    .map_err(|e| format!("rule set {}: {}", name, e))?;
+                config.rule_set_schemas.insert(name.clone(), schema);
+            }
+            return Ok(config);
+        }
+        for rule in all_rules {
+            let mut req = echo::EchoRequest::default();
+            let mut resp = echo::EchoResponse::default();
+            if request_field_mut(&mut req, &rule.field).is_none()
+                && response_field_mut(&mut resp, &rule.field).is_none()
+            {
+                return Err(format!("unknown echo field: {}", rule.field));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Returns the rule set called `name`, or the top-level rules for None.
+    /// Returns None if there is no such set.
+    pub fn rule_set(&self, name: Option<&str>) -> Option<RuleSet<'_>> {
+        let Some(name) = name else {
+            return Some(RuleSet {
+                rules: &self.rules,
+                schema: self.schema.as_ref(),
+            });
+        };
+        Some(RuleSet {
+            rules: self.rule_sets.get(name)?,
+            schema: self.rule_set_schemas.get(name),
+        })
+    }
+}
+
+/// The rules in effect for one stream.
+pub struct RuleSet<'a> {
+    rules: &'a [Rule],
+    /// Set in descriptor mode.
+    pub schema: Option<&'a Schema>,
+}
+
+impl RuleSet<'_> {
+    /// Applies the request rules to `req`. Returns true if any field changed.
+    pub fn apply_request(&self, req: &mut echo::EchoRequest) -> bool {
+        let mut changed = false;
+        for rule in self.rules {
+            changed |= rule.rewrite(request_field_mut(req, &rule.field));
+        }
+        changed
+    }
+
+    /// Applies the response rules to `resp`. Returns true if any field changed.
+    pub fn apply_response(&self, resp: &mut echo::EchoResponse) -> bool {
+        let mut changed = false;
+        for rule in self.rules {
+            changed |= rule.rewrite(response_field_mut(resp, &rule.field));
+        }
+        changed
+    }
+}
+
+impl TrailerConfig {
+    fn policy(&self) -> Result<TrailerPolicy, String> {
+        let code = |name: &str| {
+            Code::from_name(name).ok_or_else(|| format!("unknown gRPC status code: {}", name))
+        };
+        let status_map = self
+            .status_map
+            .iter()
+            .map(|(from, to)| Ok((code(from)?, code(to)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(TrailerPolicy {
+            status_map,
+            message: self.message.clone(),
+            details: self.details,
+        })
+    }
+}
+
+impl Rule {
+    fn rewrite(&self, value: Option<&mut String>) -> bool {
+        match value {
+            Some(value) if value.contains(&self.pattern) => {
+                *value = value.replace(&self.pattern, &self.replace);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Maps a rule's target field name to the request field it rewrites.
+fn request_field_mut<'a>(req: &'a mut echo::EchoRequest, field: &str) -> Option<&'a mut String> {
+    match field {
+        "message" | "EchoRequest.message" => Some(&mut req.message),
+        _ => None,
+    }
+}
+
+/// Maps a rule's target field name to the response field it rewrites.
+fn response_field_mut<'a>(resp: &'a mut echo::EchoResponse, field: &str) -> Option<&'a mut String> {
+    match field {
+        "EchoResponse.message" => Some(&mut resp.message),
+        _ => None,
+    }
+}
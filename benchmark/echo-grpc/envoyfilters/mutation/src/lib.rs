@@ -1,37 +1,313 @@
-use proxy_wasm::traits::{Context, HttpContext};
-use proxy_wasm::types::{Action, LogLevel};
+use std::rc::Rc;
+
 use proxy_wasm::traits::RootContext;
+use proxy_wasm::traits::{Context, HttpContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
 
+use grpc_frame::codec::{self, Encoding};
+use grpc_frame::control::{ConfigChannel, Watched};
+use grpc_frame::route::RouteConfig;
+use grpc_frame::status::Code;
+use grpc_frame::trace::{self, TraceParent};
 use prost::Message;
+use wasm_filter_sdk::{local_grpc_reply, Direction, HeldResponse};
 pub mod echo {
     include!(concat!(env!("OUT_DIR"), "/pb.rs"));
 }
 
-#[no_mangle]
+mod config;
+mod reflect;
+use config::{Canary, Config, RuleSet};
+use prost_reflect::MethodDescriptor;
+
+const CHANNEL: ConfigChannel = ConfigChannel::new("mutation");
+
+#[cfg_attr(not(test), no_mangle)]
 pub fn _start() {
     proxy_wasm::set_log_level(LogLevel::Trace);
-    proxy_wasm::set_http_context(|context_id, _| -> Box<dyn HttpContext> {
-        Box::new(Mutation { context_id })
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(MutationRoot {
+            config: Watched::new(CHANNEL, Config::builtin()),
+        })
     });
 }
 
+struct MutationRoot {
+    // Plugin configuration, replaced by updates from the control singleton.
+    config: Watched<Config>,
+}
+
+impl Context for MutationRoot {
+    fn on_http_call_response(&mut self, _: u32, _: usize, body_size: usize, _: usize) {
+        CHANNEL.poll_response(self, body_size, |update| Config::parse(update).map(drop));
+    }
+}
+
+impl RootContext for MutationRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        log::warn!("executing on_vm_start");
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            // No configuration: keep the default rules.
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!(
+                    "loaded {} mutation rules, {} rule sets (descriptor mode: {})",
+                    config.rules.len(),
+                    config.rule_sets.len(),
+                    config.schema.is_some()
+                );
+                if config.control {
+                    let queue_id = CHANNEL.register(self);
+                    log::warn!("registered {} queue {}", CHANNEL.name(), queue_id);
+                    if let Some(poll) = &config.poll {
+                        self.set_tick_period(poll.interval());
+                    }
+                }
+                self.config.set(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn on_queue_ready(&mut self, queue_id: u32) {
+        CHANNEL.drain(self, queue_id, |update| Config::parse(update).map(drop));
+    }
+
+    fn on_tick(&mut self) {
+        let config = self.config.current();
+        if let Some(poll) = &config.poll {
+            CHANNEL.poll(self, poll);
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        if self.config.current().control {
+            return None;
+        }
+        Some(Box::new(Mutation {
+            context_id,
+            encoding: None,
+            response_encoding: None,
+            method: None,
+            enabled: true,
+            rule_set: None,
+            response: HeldResponse::default(),
+            config: self.config.get(self, Config::parse),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
 struct Mutation {
     #[allow(unused)]
     context_id: u32,
+    // Request `grpc-encoding`; None if the peer used a codec we cannot handle.
+    encoding: Option<Encoding>,
+    // Response `grpc-encoding`, with the same convention.
+    response_encoding: Option<Encoding>,
+    // Method resolved from `:path` in descriptor mode.
+    method: Option<MethodDescriptor>,
+    // Per-route settings from the route metadata.
+    enabled: bool,
+    rule_set: Option<String>,
+    // Response body, held until the response completes.
+    response: HeldResponse,
+    config: Rc<Config>,
 }
 
 impl Context for Mutation {}
 
-impl RootContext for Mutation {
-    fn on_vm_start(&mut self, _: usize) -> bool {
-        log::warn!("executing on_vm_start");
-        true
+impl Mutation {
+    /// Whether the stream is among the canary's share, by trace ID or else
+    /// by request ID.
+    fn in_canary(&self, canary: &Canary) -> bool {
+        let parent = self
+            .get_http_request_header("traceparent")
+            .and_then(|value| TraceParent::parse(&value));
+        if let Some(parent) = parent {
+            return trace::sample(&parent.trace_id, canary.percent);
+        }
+        self.get_http_request_header(&canary.id_header)
+            .is_some_and(|id| trace::sample_id(&id, canary.percent))
+    }
+
+    /// Rewrites the buffered response body, `body_size` bytes long.
+    fn mutate_response(&self, body_size: usize) {
+        let config = self.config.clone();
+        let Some(rules) = config.rule_set(self.rule_set.as_deref()) else {
+            return;
+        };
+        if let Some(body) = Direction::Response.read(0, body_size) {
+            if let Some(new_body) = mutate_body(
+                &body,
+                Direction::Response,
+                self.response_encoding,
+                self.method.as_ref(),
+                &rules,
+            ) {
+                // Replace the whole response body
+                Direction::Response.write(0, body.len(), &new_body);
+            }
+        }
+    }
+}
+
+/// Decodes one message, applies the mutation and re-encodes it.
+/// Compressed messages are decompressed first and recompressed afterwards.
+fn mutate_message(
+    payload: &[u8],
+    direction: Direction,
+    encoding: Option<Encoding>,
+    method: Option<&MethodDescriptor>,
+    rules: &RuleSet,
+) -> Option<Vec<u8>> {
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => {
+            log::warn!("Unsupported grpc-encoding, passing the message through");
+            return None;
+        }
+    };
+    let payload = match codec::decompress(encoding, payload) {
+        Ok(payload) => payload,
+        Err(e) => {
+            log::warn!("Failed to decompress the {:?} body: {}", direction, e);
+            return None;
+        }
+    };
+    let mutated = match rules.schema {
+        Some(schema) => {
+            let desc = match direction {
+                Direction::Request => method?.input(),
+                Direction::Response => method?.output(),
+            };
+            schema.mutate(&desc, &payload)?
+        }
+        None => match direction {
+            Direction::Request => {
+                let mut req = echo::EchoRequest::decode(payload.as_slice()).ok()?;
+                // Modify the body here
+                rules.apply_request(&mut req);
+                req.encode_to_vec()
+            }
+            Direction::Response => {
+                let mut resp = echo::EchoResponse::decode(payload.as_slice()).ok()?;
+                rules.apply_response(&mut resp);
+                resp.encode_to_vec()
+            }
+        },
+    };
+
+    // Re-encode the modified message
+    match codec::compress(encoding, &mutated) {
+        Ok(payload) => Some(payload),
+        Err(e) => {
+            log::warn!("Failed to compress the {:?} body: {}", direction, e);
+            None
+        }
+    }
+}
+
+/// Mutates every gRPC message in a buffered body and re-frames it, so each
+/// length prefix matches its rewritten message. Returns None if the body is
+/// not valid gRPC framing.
+fn mutate_body(
+    body: &[u8],
+    direction: Direction,
+    encoding: Option<Encoding>,
+    method: Option<&MethodDescriptor>,
+    rules: &RuleSet,
+) -> Option<Vec<u8>> {
+    // A streaming RPC may carry several length-prefixed messages in one
+    // buffered body, so every frame is mutated and re-framed on its own.
+    let mut new_body = Vec::with_capacity(body.len());
+    let mut frames = grpc_frame::frames(body);
+    let mut count = 0;
+    for frame in frames.by_ref() {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::warn!("Received body is not a valid gRPC message: {}", e);
+                return None;
+            }
+        };
+        count += 1;
+        log::warn!("gRPC message {} length: {}", count, frame.encoded_len());
+        // Uncompressed frames are always plain protobuf, whatever the
+        // stream's grpc-encoding says.
+        let encoding = if frame.compressed {
+            encoding
+        } else {
+            Some(Encoding::Identity)
+        };
+        match mutate_message(frame.payload, direction, encoding, method, rules) {
+            Some(payload) => grpc_frame::encode_into(&mut new_body, &payload, frame.compressed),
+            None => {
+                log::warn!("Failed to decode the {:?} body", direction);
+                grpc_frame::encode_into(&mut new_body, frame.payload, frame.compressed);
+            }
+        }
+    }
+    // Keep any trailing bytes that do not form a complete message.
+    new_body.extend_from_slice(frames.remainder());
+    Some(new_body)
+}
+
+fn encoding_from_header(value: Option<String>) -> Option<Encoding> {
+    match value {
+        Some(value) => Encoding::from_header(&value),
+        None => Some(Encoding::Identity),
     }
 }
 
 impl HttpContext for Mutation {
     fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
         log::warn!("executing on_http_request_headers");
+        let route = self.route_settings("mutation");
+        if !route.enabled {
+            log::warn!("mutation disabled on this route");
+            self.enabled = false;
+            return Action::Continue;
+        }
+        if let Some(canary) = &self.config.canary {
+            if !self.in_canary(canary) {
+                // Outside the canary: no transform in either direction.
+                self.enabled = false;
+                return Action::Continue;
+            }
+            self.set_http_request_header(&canary.tag_header, Some("true"));
+        }
+        if let Some(name) = route.rule_set {
+            if self.config.rule_set(Some(&name)).is_some() {
+                self.rule_set = Some(name);
+            } else {
+                log::warn!("unknown rule set {}, using the default rules", name);
+            }
+        }
+        self.encoding = encoding_from_header(self.get_http_request_header("grpc-encoding"));
+        let rules = self.config.rule_set(self.rule_set.as_deref());
+        if let Some(schema) = rules.and_then(|rules| rules.schema) {
+            self.method = self
+                .get_http_request_header(":path")
+                .and_then(|path| schema.method_for_path(&path));
+            if self.method.is_none() {
+                log::warn!("method not found in descriptor set, messages will not be mutated");
+            }
+        }
         if !end_of_stream {
             return Action::Continue;
         }
@@ -42,45 +318,32 @@ impl HttpContext for Mutation {
 
     fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
         log::warn!("executing on_http_request_body");
+        if !self.enabled {
+            return Action::Continue;
+        }
         if !end_of_stream {
             return Action::Pause;
         }
 
-        if let Some(body) = self.get_http_request_body(0, body_size) {
+        let config = self.config.clone();
+        let Some(rules) = config.rule_set(self.rule_set.as_deref()) else {
+            return Action::Continue;
+        };
+        if let Some(body) = Direction::Request.read(0, body_size) {
             // log::warn!("Original body size: {}", body.len());
-            if body.len() > 5 {
-                // The gRPC message may be changed/compressed - better use the new length. 
-                // This step is required as the body_size will be inaccurate.
-                let message_length = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize + 5;
-                log::warn!("gRPC message length: {}", message_length);
-                if let Ok(mut req) = echo::EchoRequest::decode(&body[5..message_length]) {
-                    // Modify the body here
-                    req.message = req.message.replace("Bob", "Alice");
-
-                    // Re-encode the modified message
-                    let mut new_body = Vec::new();
-                    req.encode(&mut new_body).expect("Failed to encode");
-
+            match mutate_body(
+                &body,
+                Direction::Request,
+                self.encoding,
+                self.method.as_ref(),
+                &rules,
+            ) {
+                Some(new_body) => {
                     // log::warn!("Modified body size: {}", new_body.len());
-
-                    // Construct the gRPC header
-                    let new_body_length = new_body.len() as u32;
-                    let mut grpc_header = Vec::new();
-                    grpc_header.push(0); // Compression flag
-                    grpc_header.extend_from_slice(&new_body_length.to_be_bytes());
-
-                    // log::warn!("Header size: {}", grpc_header.len());
-
-                    // Combine header and body
-                    grpc_header.append(&mut new_body);
-
-                    // Replace the request body
-                    self.set_http_request_body(0, grpc_header.len(), &grpc_header);
-                } else {
-                    log::warn!("Failed to decode the request body");
+                    // Replace the whole request body
+                    Direction::Request.write(0, body.len(), &new_body);
                 }
-            } else {
-                log::warn!("Received body is too short to be a valid gRPC message");
+                None => return local_grpc_reply(Code::InvalidArgument, "malformed gRPC frame"),
             }
         }
 
@@ -89,19 +352,141 @@ impl HttpContext for Mutation {
 
     fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
         log::warn!("executing on_http_response_headers");
+        if !self.enabled {
+            return Action::Continue;
+        }
+        self.response_encoding =
+            encoding_from_header(self.get_http_response_header("grpc-encoding"));
         if !end_of_stream {
+            // The rewritten body may differ in size.
+            self.set_http_response_header("content-length", None);
             return Action::Continue;
         }
 
         Action::Continue
     }
 
-    fn on_http_response_body(&mut self, _body_size: usize, end_of_stream: bool) -> Action {
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
         log::warn!("executing on_http_response_body");
-        if !end_of_stream {
+        if !self.enabled {
+            return Action::Continue;
+        }
+        let Some(body_size) = self.response.body(body_size, end_of_stream) else {
             return Action::Pause;
+        };
+
+        self.mutate_response(body_size);
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        if !self.enabled {
+            return Action::Continue;
+        }
+        if let Some(body_size) = self.response.trailers() {
+            self.mutate_response(body_size);
+        }
+        let Some(status) = self.get_http_response_trailer("grpc-status") else {
+            return Action::Continue;
+        };
+        let status = Code::from_header(&status);
+        let message = self.get_http_response_trailer("grpc-message");
+        let policy = &self.config.trailer_policy;
+        let trailers = policy.apply(status, message.as_deref());
+        let replaced = policy.message.is_some() && trailers.status != Code::Ok;
+        if trailers.status == status && !replaced && trailers.details.is_none() {
+            // Nothing to rewrite; the upstream trailers pass unchanged.
+            return Action::Continue;
+        }
+        if trailers.status != status {
+            log::warn!(
+                "rewriting grpc-status {} -> {}",
+                status.name(),
+                trailers.status.name()
+            );
         }
 
+        self.set_http_response_trailer("grpc-status", Some(&trailers.status.to_header()));
+        self.set_http_response_trailer("grpc-message", trailers.message.as_deref());
+        if let Some(details) = &trailers.details {
+            self.set_http_response_trailer("grpc-status-details-bin", Some(details));
+        } else if trailers.status != status {
+            // Upstream details would contradict the rewritten status.
+            self.set_http_response_trailer("grpc-status-details-bin", None);
+        }
         Action::Continue
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use proxy_wasm::types::MapType;
+    use wasm_filter_sdk::test_host;
+
+    use super::*;
+
+    fn mutation(config: &str) -> Mutation {
+        Mutation {
+            context_id: 0,
+            encoding: Some(Encoding::Identity),
+            response_encoding: Some(Encoding::Identity),
+            method: None,
+            enabled: true,
+            rule_set: None,
+            response: HeldResponse::default(),
+            config: Rc::new(Config::parse(config.as_bytes()).unwrap()),
+        }
+    }
+
+    fn response(message: &str) -> Vec<u8> {
+        let resp = echo::EchoResponse {
+            message: message.to_string(),
+        };
+        grpc_frame::encode(&resp.encode_to_vec(), false)
+    }
+
+    /// Delivers `body` in two chunks and then `trailers`, which is how a
+    /// gRPC response ends.
+    fn respond(filter: &mut Mutation, body: &[u8], trailers: &[(&str, &str)]) {
+        let (head, tail) = body.split_at(body.len() / 2);
+        for chunk in [head, tail] {
+            let size = test_host::push_body(Direction::Response, chunk);
+            assert_eq!(filter.on_http_response_body(size, false), Action::Pause);
+        }
+        for (name, value) in trailers {
+            test_host::set_header(MapType::HttpResponseTrailers, name, value);
+        }
+        assert_eq!(
+            filter.on_http_response_trailers(trailers.len()),
+            Action::Continue
+        );
+    }
+
+    fn trailer(name: &str) -> Option<String> {
+        test_host::header(MapType::HttpResponseTrailers, name)
+    }
+
+    #[test]
+    fn mutates_a_response_ended_by_trailers() {
+        let mut filter = mutation(
+            r#"{"rules": [{"field": "EchoResponse.message", "match": "Bob", "replace": "Alice"}]}"#,
+        );
+        let trailers = [("grpc-status", "2"), ("grpc-message", "100%")];
+        respond(&mut filter, &response("Hi Bob"), &trailers);
+        assert_eq!(test_host::body(Direction::Response), response("Hi Alice"));
+        // Without a trailer policy the upstream trailers are left as sent.
+        assert_eq!(trailer("grpc-status").as_deref(), Some("2"));
+        assert_eq!(trailer("grpc-message").as_deref(), Some("100%"));
+    }
+
+    #[test]
+    fn rewrites_trailers_by_policy() {
+        let mut filter = mutation(r#"{"trailers": {"status_map": {"UNKNOWN": "UNAVAILABLE"}}}"#);
+        let trailers = [("grpc-status", "2"), ("grpc-message", "boom")];
+        respond(&mut filter, &response("Hi Bob"), &trailers);
+        assert_eq!(test_host::body(Direction::Response), response("Hi Bob"));
+        assert_eq!(trailer("grpc-status").as_deref(), Some("14"));
+        assert_eq!(trailer("grpc-message").as_deref(), Some("boom"));
+    }
+}
@@ -0,0 +1,131 @@
+//! Descriptor-set driven mutation.
+//!
+//! Instead of the compiled-in `echo` types, messages are decoded with
+//! prost-reflect against a `FileDescriptorSet` shipped in the plugin
+//! configuration. Rules address fields by path, e.g. `EchoRequest.message`
+//! or `pb.EchoRequest.message`; nested message fields may follow.
+
+use prost::Message;
+use prost_reflect::{
+    DescriptorPool, DynamicMessage, FieldDescriptor, MessageDescriptor, MethodDescriptor, Value,
+};
+
+use crate::config::Rule;
+
+pub struct Schema {
+    pool: DescriptorPool,
+    rules: Vec<FieldRule>,
+}
+
+/// A rule whose path has been resolved against the descriptor pool.
+struct FieldRule {
+    message: String,
+    path: Vec<FieldDescriptor>,
+    pattern: String,
+    replace: String,
+}
+
+impl Schema {
+    pub fn new(descriptor_set: &[u8], rules: &[Rule]) -> Result<Schema, String> {
+        let pool = DescriptorPool::decode(descriptor_set).map_err(|e| e.to_string())?;
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let (message, path) = resolve(&pool, &rule.field)?;
+                Ok(FieldRule {
+                    message: message.full_name().to_string(),
+                    path,
+                    pattern: rule.pattern.clone(),
+                    replace: rule.replace.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Schema { pool, rules })
+    }
+
+    /// Returns the method named by a gRPC `:path` header
+    /// (`/<package>.<Service>/<method>`).
+    pub fn method_for_path(&self, path: &str) -> Option<MethodDescriptor> {
+        let (service, method) = path.trim_start_matches('/').split_once('/')?;
+        let service = self.pool.get_service_by_name(service)?;
+        let method = service.methods().find(|m| m.name() == method)?;
+        Some(method)
+    }
+
+    /// Decodes `payload` as `desc`, applies the matching rules and re-encodes it.
+    pub fn mutate(&self, desc: &MessageDescriptor, payload: &[u8]) -> Option<Vec<u8>> {
+        let mut msg = DynamicMessage::decode(desc.clone(), payload).ok()?;
+        for rule in self.rules.iter().filter(|r| r.message == desc.full_name()) {
+            apply(&mut msg, &rule.path, &rule.pattern, &rule.replace);
+        }
+        Some(msg.encode_to_vec())
+    }
+}
+
+/// Splits a rule path into its message type and the chain of fields below it.
+fn resolve(pool: &DescriptorPool, path: &str) -> Result<(MessageDescriptor, Vec<FieldDescriptor>), String> {
+    let parts: Vec<&str> = path.split('.').collect();
+    for split in (1..parts.len()).rev() {
+        let name = parts[..split].join(".");
+        let message = pool
+            .get_message_by_name(&name)
+            .or_else(|| pool.all_messages().find(|m| m.name() == name));
+        let Some(message) = message else {
+            continue;
+        };
+
+        let mut fields = Vec::new();
+        let mut current = message.clone();
+        for (i, part) in parts[split..].iter().enumerate() {
+            let field = current
+                .get_field_by_name(part)
+                .ok_or_else(|| format!("{} has no field {}", current.full_name(), part))?;
+            let last = i == parts.len() - split - 1;
+            match field.kind().as_message() {
+                Some(nested) if !last && !field.is_list() && !field.is_map() => {
+                    current = nested.clone();
+                }
+                _ if !last => return Err(format!("{} is not a singular message field", part)),
+                _ => {}
+            }
+            fields.push(field);
+        }
+        return Ok((message, fields));
+    }
+    Err(format!("no message type found for field path {}", path))
+}
+
+fn apply(msg: &mut DynamicMessage, path: &[FieldDescriptor], pattern: &str, replace: &str) -> bool {
+    let Some((field, rest)) = path.split_first() else {
+        return false;
+    };
+    if !rest.is_empty() {
+        // Only descend into nested messages that are actually present.
+        if !msg.has_field(field) {
+            return false;
+        }
+        return match msg.get_field_mut(field) {
+            Value::Message(nested) => apply(nested, rest, pattern, replace),
+            _ => false,
+        };
+    }
+    match msg.get_field_mut(field) {
+        Value::String(value) if value.contains(pattern) => {
+            *value = value.replace(pattern, replace);
+            true
+        }
+        Value::List(values) => {
+            let mut changed = false;
+            for value in values.iter_mut() {
+                if let Value::String(value) = value {
+                    if value.contains(pattern) {
+                        *value = value.replace(pattern, replace);
+                        changed = true;
+                    }
+                }
+            }
+            changed
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,11 @@
+// Specify the location of proto files here
+const PROTO: &str = "../../proto/echo.proto";
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed={PROTO}");
+    // The messages map to proto3 JSON field for field.
+    prost_build::Config::new()
+        .type_attribute(".", "#[derive(serde::Deserialize, serde::Serialize)]")
+        .type_attribute(".", "#[serde(default)]")
+        .compile_protos(&[PROTO], &["../../proto"])?;
+    Ok(())
+}
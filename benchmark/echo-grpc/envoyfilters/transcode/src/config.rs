@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"routes": {"/echo": "/pb.EchoService/echo"}}
+/// ```
+///
+/// `routes` maps the HTTP paths accepting JSON to the gRPC method they call.
+/// JSON posted to a gRPC method path directly is transcoded too. Requests
+/// with any other content type pass through untouched.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub routes: HashMap<String, String>,
+}
+
+pub const ECHO_PATH: &str = "/pb.EchoService/echo";
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            routes: HashMap::from([("/echo".to_string(), ECHO_PATH.to_string())]),
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if let Some((path, method)) = config.routes.iter().find(|(_, m)| m.as_str() != ECHO_PATH) {
+            return Err(format!(
+                "route {} targets unsupported method {}",
+                path, method
+            ));
+        }
+        Ok(config)
+    }
+
+    /// The gRPC method a JSON request to `path` calls.
+    pub fn method(&self, path: &str) -> Option<&str> {
+        if path == ECHO_PATH {
+            return Some(ECHO_PATH);
+        }
+        self.routes.get(path).map(String::as_str)
+    }
+}
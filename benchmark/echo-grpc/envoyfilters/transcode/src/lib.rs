@@ -0,0 +1,236 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::status::{self, Code};
+use prost::Message;
+use serde::Serialize;
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/pb.rs"));
+}
+
+mod config;
+use config::Config;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(TranscodeRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct TranscodeRoot {
+    config: Rc<Config>,
+}
+
+impl Context for TranscodeRoot {}
+
+impl RootContext for TranscodeRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded transcoding configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Transcode {
+            context_id,
+            config: self.config.clone(),
+            active: false,
+            response_body_size: 0,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Error body returned to JSON clients.
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: i32,
+    message: &'a str,
+}
+
+fn error_body(code: Code, message: &str) -> Vec<u8> {
+    serde_json::to_vec(&ErrorBody {
+        code: code as i32,
+        message,
+    })
+    .unwrap_or_default()
+}
+
+/// Translates JSON requests into `EchoRequest` gRPC calls and the responses
+/// back into JSON.
+///
+/// The response headers are held until the gRPC status is known, so a failed
+/// call reaches the JSON client with a matching HTTP status.
+struct Transcode {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // Set for JSON requests.
+    active: bool,
+    // Size of the buffered response body.
+    response_body_size: usize,
+}
+
+impl Context for Transcode {}
+
+impl Transcode {
+    fn send_json_error(&self, code: Code, message: &str) {
+        self.send_http_response(
+            code.to_http_status() as u32,
+            vec![("content-type", "application/json")],
+            Some(&error_body(code, message)),
+        );
+    }
+
+    /// Replaces the buffered gRPC response with its JSON form.
+    fn finish_response(&self, status: Code, message: Option<&str>) {
+        let body = self
+            .get_http_response_body(0, self.response_body_size)
+            .unwrap_or_default();
+        let (status, json) = if status != Code::Ok {
+            (status, error_body(status, message.unwrap_or("")))
+        } else {
+            let resp = match grpc_frame::parse(&body) {
+                Ok(frame) if !frame.compressed => echo::EchoResponse::decode(frame.payload).ok(),
+                _ => None,
+            };
+            match resp.map(|resp| serde_json::to_vec(&resp)) {
+                Some(Ok(json)) => (Code::Ok, json),
+                _ => {
+                    log::warn!("cannot decode the EchoResponse");
+                    let code = Code::Internal;
+                    (code, error_body(code, "invalid response from the upstream"))
+                }
+            }
+        };
+        self.set_http_response_header(":status", Some(&status.to_http_status().to_string()));
+        self.set_http_response_body(0, self.response_body_size, &json);
+    }
+}
+
+impl HttpContext for Transcode {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let is_json = self
+            .get_http_request_header("content-type")
+            .is_some_and(|ct| ct.starts_with("application/json"));
+        let path = self.get_http_request_header(":path").unwrap_or_default();
+        let Some(method) = self.config.method(&path).filter(|_| is_json) else {
+            return Action::Continue;
+        };
+        if end_of_stream {
+            self.send_json_error(Code::InvalidArgument, "missing request body");
+            return Action::Pause;
+        }
+
+        log::warn!("transcoding JSON request {} to {}", path, method);
+        self.active = true;
+        self.set_http_request_header(":path", Some(method));
+        self.set_http_request_header(":method", Some("POST"));
+        self.set_http_request_header("content-type", Some("application/grpc"));
+        self.set_http_request_header("te", Some("trailers"));
+        self.set_http_request_header("content-length", None);
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.active {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let body = self.get_http_request_body(0, body_size).unwrap_or_default();
+        let req: echo::EchoRequest = match serde_json::from_slice(&body) {
+            Ok(req) => req,
+            Err(e) => {
+                log::warn!("invalid JSON request: {}", e);
+                self.send_json_error(Code::InvalidArgument, &format!("invalid JSON: {}", e));
+                return Action::Pause;
+            }
+        };
+        self.set_http_request_body(
+            0,
+            body_size,
+            &grpc_frame::encode(&req.encode_to_vec(), false),
+        );
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        if !self.active {
+            return Action::Continue;
+        }
+        if end_of_stream {
+            // Trailers-only: the call failed before any message was sent.
+            let code = self
+                .get_http_response_header("grpc-status")
+                .map_or(Code::Unknown, |s| Code::from_header(&s));
+            let message = self
+                .get_http_response_header("grpc-message")
+                .map(|m| status::decode_message(&m))
+                .unwrap_or_default();
+            self.send_json_error(code, &message);
+            return Action::Pause;
+        }
+
+        self.set_http_response_header("content-type", Some("application/json"));
+        self.set_http_response_header("content-length", None);
+        // Hold the headers until the trailers carry the status.
+        Action::Pause
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        if !self.active {
+            return Action::Continue;
+        }
+        self.response_body_size = body_size;
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        // The upstream ended the stream without trailers.
+        self.finish_response(Code::Unknown, Some("missing grpc-status"));
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        if !self.active {
+            return Action::Continue;
+        }
+        let code = self
+            .get_http_response_trailer("grpc-status")
+            .map_or(Code::Unknown, |s| Code::from_header(&s));
+        let message = self
+            .get_http_response_trailer("grpc-message")
+            .map(|m| status::decode_message(&m));
+        self.finish_response(code, message.as_deref());
+        Action::Continue
+    }
+}
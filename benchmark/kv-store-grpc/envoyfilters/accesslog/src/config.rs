@@ -0,0 +1,78 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// Each entry names a top-level field of the first request or response
+/// message and how to render it. For the echo proto:
+///
+/// ```json
+/// {"fields": [{"name": "message", "direction": "request", "number": 1, "format": "string"},
+///  {"name": "reply_len", "direction": "response", "number": 1, "format": "length"}]}
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Length-delimited field rendered as UTF-8 text.
+    String,
+    /// Byte length of a length-delimited field.
+    Length,
+    /// Numeric value of a varint or fixed-width field.
+    Int,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Field {
+    pub name: String,
+    pub direction: Direction,
+    pub number: u32,
+    pub format: Format,
+}
+
+impl Default for Config {
+    /// Logs the kv `key` and the value sizes.
+    fn default() -> Self {
+        let field = |name: &str, direction, number, format| Field {
+            name: name.to_string(),
+            direction,
+            number,
+            format,
+        };
+        Config {
+            fields: vec![
+                field("key", Direction::Request, 1, Format::String),
+                field("request_value_len", Direction::Request, 2, Format::Length),
+                field("response_value_len", Direction::Response, 1, Format::Length),
+            ],
+        }
+    }
+}
+
+const RESERVED: [&str; 5] = ["method", "status", "duration_ms", "request_bytes", "response_bytes"];
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        for field in &config.fields {
+            if field.number == 0 {
+                return Err(format!("invalid field number for {}", field.name));
+            }
+            if RESERVED.contains(&field.name.as_str()) {
+                return Err(format!("field name {} is reserved", field.name));
+            }
+        }
+        Ok(config)
+    }
+}
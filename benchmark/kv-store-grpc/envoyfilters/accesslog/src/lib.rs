@@ -0,0 +1,196 @@
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+use serde_json::{Map, Value};
+
+use grpc_frame::status::Code;
+use wasm_filter_sdk::HeldResponse;
+
+mod config;
+mod wire;
+use config::{Config, Direction, Format};
+
+#[cfg_attr(not(test), no_mangle)]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(AccessLogRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct AccessLogRoot {
+    config: Rc<Config>,
+}
+
+impl Context for AccessLogRoot {}
+
+impl RootContext for AccessLogRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, _context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(AccessLog {
+            config: self.config.clone(),
+            start: None,
+            response: HeldResponse::default(),
+            entry: Map::new(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Collects one JSON object per RPC and logs it when the stream completes.
+struct AccessLog {
+    config: Rc<Config>,
+    start: Option<SystemTime>,
+    // Response body, held until the response completes.
+    response: HeldResponse,
+    entry: Map<String, Value>,
+}
+
+impl Context for AccessLog {}
+
+impl AccessLog {
+    /// Records the configured fields of the first message in `body`.
+    fn capture(&mut self, direction: Direction, body: &[u8]) {
+        let key = match direction {
+            Direction::Request => "request_bytes",
+            Direction::Response => "response_bytes",
+        };
+        self.entry.insert(key.to_string(), body.len().into());
+
+        let Ok(frame) = grpc_frame::parse(body) else {
+            return;
+        };
+        if frame.compressed {
+            return;
+        }
+        for field in self.config.fields.iter().filter(|f| f.direction == direction) {
+            let value = match (field.format, wire::find(frame.payload, field.number)) {
+                (_, None) => continue,
+                (Format::String, Some(wire::Value::Bytes(bytes))) => {
+                    String::from_utf8_lossy(bytes).into_owned().into()
+                }
+                (Format::Length, Some(wire::Value::Bytes(bytes))) => bytes.len().into(),
+                (Format::Int, Some(wire::Value::Varint(v) | wire::Value::Fixed64(v))) => v.into(),
+                (Format::Int, Some(wire::Value::Fixed32(v))) => v.into(),
+                _ => {
+                    log::warn!("field {} has an unexpected wire type", field.name);
+                    continue;
+                }
+            };
+            self.entry.insert(field.name.clone(), value);
+        }
+    }
+}
+
+impl HttpContext for AccessLog {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        self.start = Some(self.get_current_time());
+        if let Some(path) = self.get_http_request_header(":path") {
+            self.entry
+                .insert("method".to_string(), path.trim_start_matches('/').into());
+        }
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        if !end_of_stream {
+            return Action::Pause;
+        }
+        if let Some(body) = self.get_http_request_body(0, body_size) {
+            self.capture(Direction::Request, &body);
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        let Some(body_size) = self.response.body(body_size, end_of_stream) else {
+            return Action::Pause;
+        };
+        if let Some(body) = self.get_http_response_body(0, body_size) {
+            self.capture(Direction::Response, &body);
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        if let Some(body_size) = self.response.trailers() {
+            if let Some(body) = self.get_http_response_body(0, body_size) {
+                self.capture(Direction::Response, &body);
+            }
+        }
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        // Trailers-only responses carry the status in the headers.
+        let status = self
+            .get_http_response_trailer("grpc-status")
+            .or_else(|| self.get_http_response_header("grpc-status"));
+        if let Some(status) = status {
+            self.entry
+                .insert("status".to_string(), Code::from_header(&status).name().into());
+        }
+        if let Some(start) = self.start {
+            let elapsed = self
+                .get_current_time()
+                .duration_since(start)
+                .unwrap_or_default();
+            self.entry.insert(
+                "duration_ms".to_string(),
+                (elapsed.as_secs_f64() * 1000.0).into(),
+            );
+        }
+        log::info!("{}", Value::Object(std::mem::take(&mut self.entry)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proxy_wasm::types::MapType;
+    use wasm_filter_sdk::test_host;
+
+    use super::*;
+
+    #[test]
+    fn captures_a_response_ended_by_trailers() {
+        let mut log = AccessLog {
+            config: Rc::new(Config::default()),
+            start: None,
+            response: HeldResponse::default(),
+            entry: Map::new(),
+        };
+        // GetResponse { value: "value" }
+        let body = grpc_frame::encode(b"\x0a\x05value", false);
+        let (head, tail) = body.split_at(body.len() / 2);
+        for chunk in [head, tail] {
+            let size = test_host::push_body(wasm_filter_sdk::Direction::Response, chunk);
+            assert_eq!(log.on_http_response_body(size, false), Action::Pause);
+        }
+        test_host::set_header(MapType::HttpResponseTrailers, "grpc-status", "0");
+        assert_eq!(log.on_http_response_trailers(1), Action::Continue);
+        assert_eq!(log.entry["response_bytes"], body.len());
+        assert_eq!(log.entry["response_value_len"], 5);
+    }
+}
@@ -0,0 +1,59 @@
+//! Minimal protobuf wire-format reader.
+//!
+//! The access log only needs a handful of top-level fields by number, so it
+//! walks the encoded message directly instead of depending on generated types.
+//! This keeps one filter binary usable for both the echo and kv protos.
+
+pub enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Returns the last occurrence of field `number` in `msg`, following the
+/// protobuf rule that the last value wins for singular fields.
+pub fn find(msg: &[u8], number: u32) -> Option<Value<'_>> {
+    let mut buf = msg;
+    let mut found = None;
+    while !buf.is_empty() {
+        let key = varint(&mut buf)?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(varint(&mut buf)?),
+            1 => Value::Fixed64(u64::from_le_bytes(take(&mut buf, 8)?.try_into().ok()?)),
+            2 => {
+                let len = varint(&mut buf)? as usize;
+                Value::Bytes(take(&mut buf, len)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(take(&mut buf, 4)?.try_into().ok()?)),
+            // Groups are deprecated and never used by our protos.
+            _ => return found,
+        };
+        if (key >> 3) as u32 == number {
+            found = Some(value);
+        }
+    }
+    found
+}
+
+fn varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if buf.len() < len {
+        return None;
+    }
+    let (head, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(head)
+}
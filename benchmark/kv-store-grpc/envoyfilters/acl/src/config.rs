@@ -0,0 +1,101 @@
+use grpc_frame::control::Poll;
+use regex::Regex;
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"default": "allow", "rules": [{"action": "deny", "prefix": "admin/"},
+///  {"action": "deny", "regex": "^tmp-[0-9]+$"}, {"action": "allow", "exact": "admin/motd"}]}
+/// ```
+///
+/// Each rule sets exactly one of `exact`, `prefix` or `regex`. Rules are
+/// evaluated in order and the first match decides; keys that match no rule
+/// get the `default` action.
+///
+/// With `"control": true` the root is the control singleton instead: it
+/// takes new rule sets from the `acl.config` shared queue and publishes them
+/// to the workers (see `grpc_frame::control`), and with `poll` set also
+/// fetches them from an HTTP endpoint every `poll.interval_ms`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub default: Verdict,
+    pub control: bool,
+    pub poll: Option<Poll>,
+    rules: Vec<RuleConfig>,
+    #[serde(skip)]
+    compiled: Vec<(Matcher, Verdict)>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    #[default]
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleConfig {
+    action: Verdict,
+    exact: Option<String>,
+    prefix: Option<String>,
+    regex: Option<String>,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Matcher::Exact(exact) => key == exact,
+            Matcher::Prefix(prefix) => key.starts_with(prefix.as_str()),
+            Matcher::Regex(regex) => regex.is_match(key),
+        }
+    }
+}
+
+impl RuleConfig {
+    fn compile(&self, index: usize) -> Result<Matcher, String> {
+        match (&self.exact, &self.prefix, &self.regex) {
+            (Some(exact), None, None) => Ok(Matcher::Exact(exact.clone())),
+            (None, Some(prefix), None) => Ok(Matcher::Prefix(prefix.clone())),
+            (None, None, Some(regex)) => Regex::new(regex)
+                .map(Matcher::Regex)
+                .map_err(|e| format!("rule {}: {}", index, e)),
+            _ => Err(format!(
+                "rule {} must set exactly one of exact, prefix or regex",
+                index
+            )),
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if let Some(poll) = &config.poll {
+            poll.validate()?;
+        }
+        config.compiled = config
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| Ok((rule.compile(i)?, rule.action)))
+            .collect::<Result<_, String>>()?;
+        Ok(config)
+    }
+
+    pub fn evaluate(&self, key: &str) -> Verdict {
+        self.compiled
+            .iter()
+            .find(|(matcher, _)| matcher.matches(key))
+            .map_or(self.default, |(_, verdict)| *verdict)
+    }
+}
@@ -0,0 +1,153 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::control::{ConfigChannel, Watched};
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+use prost::Message;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+use config::{Config, Verdict};
+
+const SET_PATH: &str = "/kv.KVService/set";
+const CHANNEL: ConfigChannel = ConfigChannel::new("acl");
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(AclRoot {
+            config: Watched::new(CHANNEL, Config::default()),
+        })
+    });
+}
+
+struct AclRoot {
+    // Plugin configuration, replaced by updates from the control singleton.
+    config: Watched<Config>,
+}
+
+impl Context for AclRoot {
+    fn on_http_call_response(&mut self, _: u32, _: usize, body_size: usize, _: usize) {
+        CHANNEL.poll_response(self, body_size, |update| Config::parse(update).map(drop));
+    }
+}
+
+impl RootContext for AclRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded ACL configuration: {:?}", config);
+                if config.control {
+                    let queue_id = CHANNEL.register(self);
+                    log::warn!("registered {} queue {}", CHANNEL.name(), queue_id);
+                    if let Some(poll) = &config.poll {
+                        self.set_tick_period(poll.interval());
+                    }
+                }
+                self.config.set(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn on_queue_ready(&mut self, queue_id: u32) {
+        CHANNEL.drain(self, queue_id, |update| Config::parse(update).map(drop));
+    }
+
+    fn on_tick(&mut self) {
+        let config = self.config.current();
+        if let Some(poll) = &config.poll {
+            CHANNEL.poll(self, poll);
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        if self.config.current().control {
+            return None;
+        }
+        Some(Box::new(Acl {
+            context_id,
+            config: self.config.get(self, Config::parse),
+            is_set: false,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Acl {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    is_set: bool,
+}
+
+impl Context for Acl {}
+
+impl HttpContext for Acl {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.is_set = self.get_http_request_header(":path").as_deref() == Some(SET_PATH);
+        if self.is_set && !end_of_stream {
+            // Hold the headers so a denied write never reaches the upstream.
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.is_set {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let Some(body) = self.get_http_request_body(0, body_size) else {
+            return Action::Continue;
+        };
+        // Every message in the body must be allowed, so a streamed batch
+        // cannot smuggle a denied key behind an allowed one.
+        for frame in grpc_frame::frames(&body) {
+            let req = match frame {
+                Ok(frame) if frame.compressed => None,
+                Ok(frame) => kv::SetRequest::decode(frame.payload).ok(),
+                Err(_) => None,
+            };
+            let Some(req) = req else {
+                // Fail closed: a write we cannot inspect is not allowed.
+                log::warn!("cannot decode SetRequest, rejecting");
+                self.send_grpc_error(Code::PermissionDenied, "unable to inspect the request");
+                return Action::Pause;
+            };
+            if self.config.evaluate(&req.key) == Verdict::Deny {
+                log::warn!("write to {} denied", req.key);
+                self.send_grpc_error(
+                    Code::PermissionDenied,
+                    &format!("write to key {} is not allowed", req.key),
+                );
+                return Action::Pause;
+            }
+        }
+
+        Action::Continue
+    }
+}
@@ -0,0 +1,55 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"slo_ms": 50, "percentile": 99, "window": 1000, "tick_ms": 1000,
+///  "step": 0.05, "max_reject": 0.9}
+/// ```
+///
+/// Every `tick_ms` the root context takes the `percentile` latency over the
+/// last `window` responses. While it exceeds `slo_ms` the rejection
+/// probability grows by `step` per tick, up to `max_reject`; once latency is
+/// back under the SLO it shrinks by `step` per tick. Ticks with fewer than
+/// `min_samples` new responses leave the probability unchanged.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub slo_ms: u64,
+    pub percentile: f64,
+    pub window: usize,
+    pub tick_ms: u64,
+    pub step: f64,
+    pub max_reject: f64,
+    pub min_samples: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            slo_ms: 50,
+            percentile: 99.0,
+            window: 1000,
+            tick_ms: 1000,
+            step: 0.05,
+            max_reject: 0.9,
+            min_samples: 20,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if !(0.0..=100.0).contains(&config.percentile) {
+            return Err(format!("percentile out of range: {}", config.percentile));
+        }
+        if !(0.0..=1.0).contains(&config.max_reject) || !(0.0..=1.0).contains(&config.step) {
+            return Err("step and max_reject must be between 0 and 1".to_string());
+        }
+        if config.window == 0 || config.tick_ms == 0 {
+            return Err("window and tick_ms must be at least 1".to_string());
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,160 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+use wasm_filter_sdk::Rng;
+
+mod config;
+mod metrics;
+mod window;
+use config::Config;
+use metrics::Metrics;
+use window::Window;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        let config = Config::default();
+        Box::new(AdmissionRoot {
+            window: Rc::new(RefCell::new(Window::new(config.window))),
+            config: Rc::new(config),
+            metrics: None,
+            reject: Rc::new(Cell::new(0.0)),
+            rng: Rng::default(),
+        })
+    });
+}
+
+/// Owns the latency controller. Root contexts are per worker thread, so each
+/// worker admits against the latencies of the requests it served.
+struct AdmissionRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+    window: Rc<RefCell<Window>>,
+    // Probability of rejecting a new request, updated on every tick.
+    reject: Rc<Cell<f64>>,
+    rng: Rng,
+}
+
+impl Context for AdmissionRoot {}
+
+impl RootContext for AdmissionRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        self.rng.seed(self.get_current_time());
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        if let Some(bytes) = self.get_plugin_configuration() {
+            match Config::parse(&bytes) {
+                Ok(config) => {
+                    log::warn!("loaded admission configuration: {:?}", config);
+                    *self.window.borrow_mut() = Window::new(config.window);
+                    self.config = Rc::new(config);
+                }
+                Err(e) => {
+                    log::error!("invalid plugin configuration: {}", e);
+                    return false;
+                }
+            }
+        }
+        self.set_tick_period(Duration::from_millis(self.config.tick_ms));
+        true
+    }
+
+    fn on_tick(&mut self) {
+        let mut window = self.window.borrow_mut();
+        if window.take_new() < self.config.min_samples {
+            return;
+        }
+        let Some(latency_us) = window.percentile(self.config.percentile) else {
+            return;
+        };
+        let reject = if latency_us > self.config.slo_ms * 1000 {
+            (self.reject.get() + self.config.step).min(self.config.max_reject)
+        } else {
+            (self.reject.get() - self.config.step).max(0.0)
+        };
+        if reject != self.reject.get() {
+            log::warn!(
+                "p{} latency {}us, rejecting {:.1}% of requests",
+                self.config.percentile,
+                latency_us,
+                reject * 100.0
+            );
+        }
+        self.reject.set(reject);
+        if let Some(metrics) = &self.metrics {
+            metrics.latency(latency_us);
+            metrics.reject_rate(reject);
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Admission {
+            context_id,
+            metrics: self.metrics.clone(),
+            window: self.window.clone(),
+            reject: self.reject.clone(),
+            rng: self.rng.clone(),
+            start: None,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Admission {
+    #[allow(unused)]
+    context_id: u32,
+    metrics: Option<Rc<Metrics>>,
+    window: Rc<RefCell<Window>>,
+    reject: Rc<Cell<f64>>,
+    rng: Rng,
+    // Set once the request is admitted.
+    start: Option<SystemTime>,
+}
+
+impl Context for Admission {}
+
+impl HttpContext for Admission {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        if self.rng.roll(self.reject.get() * 100.0) {
+            if let Some(metrics) = &self.metrics {
+                metrics.rejected();
+            }
+            self.send_grpc_error(Code::Unavailable, "rejected by admission control");
+            return Action::Pause;
+        }
+
+        self.start = Some(self.get_current_time());
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        // Rejected requests never reached the upstream, so they say nothing
+        // about its latency.
+        let Some(start) = self.start else {
+            return;
+        };
+        let latency = self
+            .get_current_time()
+            .duration_since(start)
+            .unwrap_or_default();
+        self.window.borrow_mut().push(latency.as_micros() as u64);
+    }
+}
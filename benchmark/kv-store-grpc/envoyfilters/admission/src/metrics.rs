@@ -0,0 +1,50 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.admission.reject_permille`. Each worker thread runs its
+//! own controller, so the gauges reflect whichever worker ticked last.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Gauge of the current rejection probability, in permille.
+    reject_permille: u32,
+    /// Gauge of the latency percentile computed at the last tick, in µs.
+    latency_us: u32,
+    /// Counter of rejected requests.
+    rejected: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            reject_permille: hostcalls::define_metric(
+                MetricType::Gauge,
+                "admission.reject_permille",
+            )?,
+            latency_us: hostcalls::define_metric(MetricType::Gauge, "admission.latency_us")?,
+            rejected: hostcalls::define_metric(MetricType::Counter, "admission.rejected")?,
+        })
+    }
+
+    pub fn reject_rate(&self, probability: f64) {
+        record(self.reject_permille, (probability * 1000.0).round() as u64);
+    }
+
+    pub fn latency(&self, latency_us: u64) {
+        record(self.latency_us, latency_us);
+    }
+
+    pub fn rejected(&self) {
+        if let Err(e) = hostcalls::increment_metric(self.rejected, 1) {
+            log::warn!("failed to increment metric: {:?}", e);
+        }
+    }
+}
+
+fn record(metric_id: u32, value: u64) {
+    if let Err(e) = hostcalls::record_metric(metric_id, value) {
+        log::warn!("failed to record metric: {:?}", e);
+    }
+}
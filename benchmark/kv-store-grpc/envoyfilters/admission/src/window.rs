@@ -0,0 +1,43 @@
+use std::collections::VecDeque;
+
+/// The most recent response latencies, in microseconds.
+pub struct Window {
+    samples: VecDeque<u64>,
+    capacity: usize,
+    // Samples added since the last call to `take_new`.
+    new: usize,
+}
+
+impl Window {
+    pub fn new(capacity: usize) -> Window {
+        Window {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            new: 0,
+        }
+    }
+
+    pub fn push(&mut self, latency_us: u64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency_us);
+        self.new += 1;
+    }
+
+    /// Returns the number of samples pushed since the previous call.
+    pub fn take_new(&mut self) -> usize {
+        std::mem::take(&mut self.new)
+    }
+
+    /// Nearest-rank percentile of the window, or None if it is empty.
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<u64> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+}
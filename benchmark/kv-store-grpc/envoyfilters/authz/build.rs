@@ -0,0 +1,9 @@
+// Specify the location of proto files here
+const PROTOS: &[&str] = &["../../proto/kv.proto", "../../proto/authz.proto"];
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    for proto in PROTOS {
+        println!("cargo:rerun-if-changed={proto}");
+    }
+    prost_build::compile_protos(PROTOS, &["../../proto"])?;
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"cluster": "outbound|9000||authz.default.svc.cluster.local", "timeout_ms": 200,
+///  "ttl_ms": 5000, "failure_mode_allow": false}
+/// ```
+///
+/// `cluster` is the Envoy cluster of the `authz.Authz` service. Verdicts are
+/// cached for `ttl_ms` unless the service returns its own TTL. When the
+/// service fails or times out, requests are rejected with `UNAVAILABLE`
+/// unless `failure_mode_allow` is set.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub cluster: String,
+    pub timeout_ms: u64,
+    pub ttl_ms: u64,
+    pub failure_mode_allow: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            cluster: String::new(),
+            timeout_ms: 200,
+            ttl_ms: 5000,
+            failure_mode_allow: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.cluster.is_empty() {
+            return Err("cluster is required".to_string());
+        }
+        Ok(config)
+    }
+}
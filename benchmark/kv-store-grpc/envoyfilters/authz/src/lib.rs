@@ -0,0 +1,275 @@
+use std::rc::Rc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+use prost::Message;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+pub mod authz {
+    include!(concat!(env!("OUT_DIR"), "/authz.rs"));
+}
+
+mod config;
+use config::Config;
+
+const GET_PATH: &str = "/kv.KVService/get";
+const SET_PATH: &str = "/kv.KVService/set";
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(AuthzRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct AuthzRoot {
+    config: Rc<Config>,
+}
+
+impl Context for AuthzRoot {}
+
+impl RootContext for AuthzRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded authz configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Authz {
+            context_id,
+            config: self.config.clone(),
+            method: String::new(),
+            key: String::new(),
+            wait_for_body: false,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// A cached verdict for one method and key.
+///
+/// Entries are `[expiry, ns since epoch (u64 LE)][allowed (u8)][reason]`.
+struct Verdict {
+    allowed: bool,
+    reason: String,
+}
+
+fn verdict_key(method: &str, key: &str) -> String {
+    format!("authz.{}.{}", method, key)
+}
+
+/// Asks an external `authz.Authz` service whether each request may proceed.
+///
+/// kv requests are held until the body arrives so the verdict can depend on
+/// the key; other methods are checked on their headers alone.
+struct Authz {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // `:path` without the leading slash.
+    method: String,
+    key: String,
+    // Set while the check waits for the kv request body.
+    wait_for_body: bool,
+}
+
+impl Context for Authz {
+    fn on_grpc_call_response(&mut self, token_id: u32, status_code: u32, response_size: usize) {
+        log::warn!("authz call {} returned status {}", token_id, status_code);
+        let response = if status_code == Code::Ok as u32 {
+            self.get_grpc_call_response_body(0, response_size)
+                .and_then(|body| authz::CheckResponse::decode(body.as_slice()).ok())
+        } else {
+            None
+        };
+        let Some(response) = response else {
+            if self.config.failure_mode_allow {
+                log::warn!("authz service unavailable, allowing {}", self.method);
+                self.resume_http_request();
+            } else {
+                self.send_grpc_error(Code::Unavailable, "authorization service unavailable");
+            }
+            return;
+        };
+
+        let ttl_ms = match response.ttl_ms {
+            0 => self.config.ttl_ms,
+            ttl_ms => ttl_ms as u64,
+        };
+        let verdict = Verdict {
+            allowed: response.allowed,
+            reason: response.reason,
+        };
+        self.store(&verdict, self.now() + ttl_ms * 1_000_000);
+        self.enforce(&verdict);
+    }
+}
+
+impl Authz {
+    fn now(&self) -> u64 {
+        self.get_current_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    }
+
+    fn lookup(&self) -> Option<Verdict> {
+        let (data, _) = self.get_shared_data(&verdict_key(&self.method, &self.key));
+        let data = data?;
+        if data.len() < 9 {
+            return None;
+        }
+        let expiry = u64::from_le_bytes(data[..8].try_into().ok()?);
+        if expiry <= self.now() {
+            return None;
+        }
+        Some(Verdict {
+            allowed: data[8] != 0,
+            reason: String::from_utf8_lossy(&data[9..]).into_owned(),
+        })
+    }
+
+    fn store(&self, verdict: &Verdict, expiry: u64) {
+        let mut data = Vec::with_capacity(9 + verdict.reason.len());
+        data.extend_from_slice(&expiry.to_le_bytes());
+        data.push(verdict.allowed as u8);
+        data.extend_from_slice(verdict.reason.as_bytes());
+        let key = verdict_key(&self.method, &self.key);
+        // Last writer wins; concurrent checks return the same verdict.
+        if let Err(e) = self.set_shared_data(&key, Some(&data), None) {
+            log::warn!("failed to cache verdict {}: {:?}", key, e);
+        }
+    }
+
+    /// Resumes or rejects the paused request.
+    fn enforce(&self, verdict: &Verdict) {
+        if verdict.allowed {
+            self.resume_http_request();
+            return;
+        }
+        log::warn!(
+            "denied {} for key {:?}: {}",
+            self.method,
+            self.key,
+            verdict.reason
+        );
+        let reason = match verdict.reason.as_str() {
+            "" => "request denied by authorization service",
+            reason => reason,
+        };
+        self.send_grpc_error(Code::PermissionDenied, reason);
+    }
+
+    /// Checks the request against the cache or the authz service. Returns
+    /// the action for the current callback.
+    fn check(&self) -> Action {
+        if let Some(verdict) = self.lookup() {
+            if verdict.allowed {
+                return Action::Continue;
+            }
+            self.enforce(&verdict);
+            return Action::Pause;
+        }
+
+        let request = authz::CheckRequest {
+            method: self.method.clone(),
+            key: self.key.clone(),
+        };
+        match self.dispatch_grpc_call(
+            &self.config.cluster,
+            "authz.Authz",
+            "Check",
+            vec![],
+            Some(&request.encode_to_vec()),
+            Duration::from_millis(self.config.timeout_ms),
+        ) {
+            Ok(_) => Action::Pause,
+            Err(e) => {
+                log::warn!("failed to call authz service: {:?}", e);
+                if self.config.failure_mode_allow {
+                    return Action::Continue;
+                }
+                self.send_grpc_error(Code::Unavailable, "authorization service unavailable");
+                Action::Pause
+            }
+        }
+    }
+}
+
+impl HttpContext for Authz {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        if self.config.cluster.is_empty() {
+            return Action::Continue;
+        }
+        let Some(path) = self.get_http_request_header(":path") else {
+            return Action::Continue;
+        };
+        self.method = path.trim_start_matches('/').to_string();
+        if matches!(path.as_str(), GET_PATH | SET_PATH) && !end_of_stream {
+            // Hold the headers until the key is known.
+            self.wait_for_body = true;
+            return Action::Pause;
+        }
+
+        self.check()
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.wait_for_body {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+        self.wait_for_body = false;
+
+        let Some(body) = self.get_http_request_body(0, body_size) else {
+            return self.check();
+        };
+        let key = match grpc_frame::parse(&body) {
+            Ok(frame) if !frame.compressed => match self.method.as_str() {
+                m if m == &GET_PATH[1..] => kv::GetRequest::decode(frame.payload).map(|r| r.key),
+                _ => kv::SetRequest::decode(frame.payload).map(|r| r.key),
+            },
+            Ok(_) => {
+                log::warn!("compressed request, checking without the key");
+                Ok(String::new())
+            }
+            Err(e) => {
+                log::warn!("frame error: {}", e);
+                self.send_grpc_error(Code::InvalidArgument, "malformed gRPC frame");
+                return Action::Pause;
+            }
+        };
+        match key {
+            Ok(key) => self.key = key,
+            Err(e) => log::warn!("decode error: {}", e),
+        }
+        self.check()
+    }
+}
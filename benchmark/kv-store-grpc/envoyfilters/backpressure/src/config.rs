@@ -0,0 +1,62 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"max_inflight": 256, "max_buffered_bytes": 16777216, "header": "retry-after-ms",
+///  "retry_after_ms": 50, "max_retry_after_ms": 1000}
+/// ```
+///
+/// Limits are per worker thread. A request arriving while either limit is
+/// reached gets a trailers-only `UNAVAILABLE` carrying `header`; the hint
+/// grows with the overload, from `retry_after_ms` at the limit up to
+/// `max_retry_after_ms`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub max_inflight: u64,
+    /// Request body bytes received for streams the upstream has not
+    /// answered yet.
+    pub max_buffered_bytes: u64,
+    pub header: String,
+    pub retry_after_ms: u64,
+    pub max_retry_after_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_inflight: 256,
+            max_buffered_bytes: 16 * 1024 * 1024,
+            header: "retry-after-ms".to_string(),
+            retry_after_ms: 50,
+            max_retry_after_ms: 1000,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.max_inflight == 0 || config.max_buffered_bytes == 0 {
+            return Err("max_inflight and max_buffered_bytes must be at least 1".to_string());
+        }
+        if config.max_retry_after_ms < config.retry_after_ms {
+            return Err("max_retry_after_ms must not be below retry_after_ms".to_string());
+        }
+        Ok(config)
+    }
+
+    /// Returns the retry hint if the worker is overloaded.
+    pub fn retry_after(&self, inflight: u64, buffered: u64) -> Option<u64> {
+        let load = f64::max(
+            inflight as f64 / self.max_inflight as f64,
+            buffered as f64 / self.max_buffered_bytes as f64,
+        );
+        if load < 1.0 {
+            return None;
+        }
+        let retry = (self.retry_after_ms as f64 * load) as u64;
+        Some(retry.min(self.max_retry_after_ms))
+    }
+}
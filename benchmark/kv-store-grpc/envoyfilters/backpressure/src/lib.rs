@@ -0,0 +1,159 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+
+mod config;
+mod metrics;
+use config::Config;
+use metrics::Metrics;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(BackpressureRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+            load: Rc::new(Load::default()),
+        })
+    });
+}
+
+/// Work the worker has accepted and not finished.
+#[derive(Default)]
+struct Load {
+    inflight: Cell<u64>,
+    buffered: Cell<u64>,
+}
+
+struct BackpressureRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+    load: Rc<Load>,
+}
+
+impl Context for BackpressureRoot {}
+
+impl RootContext for BackpressureRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded backpressure configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Backpressure {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            load: self.load.clone(),
+            admitted: false,
+            buffered: 0,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Backpressure {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    load: Rc<Load>,
+    admitted: bool,
+    // This stream's share of `load.buffered`.
+    buffered: u64,
+}
+
+impl Context for Backpressure {}
+
+impl Backpressure {
+    /// Returns this stream's request bytes once the upstream answers.
+    fn release_buffered(&mut self) {
+        let buffered = &self.load.buffered;
+        buffered.set(buffered.get().saturating_sub(self.buffered));
+        self.buffered = 0;
+    }
+}
+
+impl HttpContext for Backpressure {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let (inflight, buffered) = (self.load.inflight.get(), self.load.buffered.get());
+        if let Some(retry_after) = self.config.retry_after(inflight, buffered) {
+            log::warn!(
+                "overloaded ({} in flight, {} bytes buffered), retry after {}ms",
+                inflight,
+                buffered,
+                retry_after
+            );
+            if let Some(metrics) = &self.metrics {
+                metrics.rejected(retry_after);
+            }
+            let retry_after = retry_after.to_string();
+            self.send_grpc_error_with_headers(
+                Code::Unavailable,
+                "proxy overloaded",
+                &[(self.config.header.as_str(), retry_after.as_str())],
+            );
+            return Action::Pause;
+        }
+
+        self.admitted = true;
+        self.load.inflight.set(inflight + 1);
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, _end_of_stream: bool) -> Action {
+        if self.admitted {
+            // The body streams through, so each call reports new bytes.
+            self.buffered += body_size as u64;
+            let buffered = &self.load.buffered;
+            buffered.set(buffered.get() + body_size as u64);
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
+        self.release_buffered();
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        if !self.admitted {
+            return;
+        }
+        self.release_buffered();
+        let inflight = &self.load.inflight;
+        inflight.set(inflight.get().saturating_sub(1));
+    }
+}
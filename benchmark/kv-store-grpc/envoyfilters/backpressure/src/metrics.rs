@@ -0,0 +1,35 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.backpressure.rejected`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Counter of requests turned away.
+    rejected: u32,
+    /// Histogram of the retry hints sent, in milliseconds.
+    retry_after_ms: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            rejected: hostcalls::define_metric(MetricType::Counter, "backpressure.rejected")?,
+            retry_after_ms: hostcalls::define_metric(
+                MetricType::Histogram,
+                "backpressure.retry_after_ms",
+            )?,
+        })
+    }
+
+    pub fn rejected(&self, retry_after_ms: u64) {
+        if let Err(e) = hostcalls::increment_metric(self.rejected, 1) {
+            log::warn!("failed to increment metric: {:?}", e);
+        }
+        if let Err(e) = hostcalls::record_metric(self.retry_after_ms, retry_after_ms) {
+            log::warn!("failed to record metric: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"cluster": "outbound|11000||kvstore.default.svc.cluster.local",
+///  "max_entries": 128, "timeout_ms": 500}
+/// ```
+///
+/// `cluster` is the Envoy cluster of the kv store the entries are written
+/// to. Batches with more than `max_entries` entries are rejected, and each
+/// `set` call gives up after `timeout_ms`. The `forward_headers` of the
+/// batch request are copied into every call.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub cluster: String,
+    pub max_entries: usize,
+    pub timeout_ms: u64,
+    pub forward_headers: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            cluster: String::new(),
+            max_entries: 128,
+            timeout_ms: 1000,
+            forward_headers: vec![
+                "authorization".to_string(),
+                "x-request-id".to_string(),
+                "traceparent".to_string(),
+            ],
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.cluster.is_empty() {
+            return Err("cluster is required".to_string());
+        }
+        if config.max_entries == 0 {
+            return Err("max_entries must be at least 1".to_string());
+        }
+        // Header names arrive lowercased.
+        for name in config.forward_headers.iter_mut() {
+            *name = name.to_ascii_lowercase();
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::status::Code;
+use prost::Message;
+use wasm_filter_sdk::message::encode_messages;
+use wasm_filter_sdk::{local_grpc_reply, read_grpc_message, Direction, ReadError};
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+mod metrics;
+use config::Config;
+use metrics::Metrics;
+
+const BATCH_PATH: &str = "/kv.KVBatchService/setBatch";
+/// Response header with the number of entries a batch was split into.
+const SPLIT_HEADER: &str = "x-batch-split";
+/// Content type the request is switched to before the reply; see
+/// `Batch::reply`.
+const LOCAL_CONTENT_TYPE: &str = "application/x-batch";
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(BatchRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+        })
+    });
+}
+
+struct BatchRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+}
+
+impl Context for BatchRoot {}
+
+impl RootContext for BatchRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded batch configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Batch {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            is_batch: false,
+            calls: HashMap::new(),
+            results: Vec::new(),
+            replied: None,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Answers `kv.KVBatchService/setBatch` by splitting the batch into one
+/// `kv.KVService/set` call per entry.
+///
+/// The calls run concurrently; the batch is answered once all of them
+/// returned, with one result per entry. A failed entry does not fail the
+/// batch, so the response status is OK unless the batch itself is invalid.
+struct Batch {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    is_batch: bool,
+    // Entry index of each call in flight, by token.
+    calls: HashMap<u32, usize>,
+    results: Vec<kv::SetResult>,
+    // grpc-status of the reply, moved into trailers once its body has
+    // passed.
+    replied: Option<String>,
+}
+
+impl Context for Batch {
+    fn on_grpc_call_response(&mut self, token_id: u32, status_code: u32, response_size: usize) {
+        let Some(index) = self.calls.remove(&token_id) else {
+            return;
+        };
+        let (code, message, value) = if status_code == Code::Ok as u32 {
+            // An empty body is a default SetResponse.
+            let body = self
+                .get_grpc_call_response_body(0, response_size)
+                .unwrap_or_default();
+            match kv::SetResponse::decode(body.as_slice()) {
+                Ok(response) => (status_code, String::new(), response.value),
+                Err(e) => (Code::Internal as u32, e.to_string(), String::new()),
+            }
+        } else {
+            let message = hostcalls::get_grpc_status()
+                .ok()
+                .and_then(|(_, message)| message)
+                .unwrap_or_default();
+            (status_code, message, String::new())
+        };
+        let result = &mut self.results[index];
+        result.code = code;
+        result.message = message;
+        result.value = value;
+        if self.calls.is_empty() {
+            self.reply();
+        }
+    }
+}
+
+impl Batch {
+    /// Issues one `set` call per entry. Entries that cannot be dispatched
+    /// fail with UNAVAILABLE right away.
+    fn split(&mut self, entries: Vec<kv::SetRequest>) {
+        let headers: Vec<(String, Vec<u8>)> = self
+            .config
+            .forward_headers
+            .iter()
+            .filter_map(|name| {
+                let value = self.get_http_request_header(name)?;
+                Some((name.clone(), value.into_bytes()))
+            })
+            .collect();
+        for (index, entry) in entries.into_iter().enumerate() {
+            let metadata = headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_slice()))
+                .collect();
+            match self.dispatch_grpc_call(
+                &self.config.cluster,
+                "kv.KVService",
+                "set",
+                metadata,
+                Some(&entry.encode_to_vec()),
+                Duration::from_millis(self.config.timeout_ms),
+            ) {
+                Ok(token) => {
+                    self.calls.insert(token, index);
+                    self.results.push(kv::SetResult {
+                        key: entry.key,
+                        ..Default::default()
+                    });
+                }
+                Err(e) => {
+                    log::warn!("failed to dispatch set of {}: {:?}", entry.key, e);
+                    self.results.push(kv::SetResult {
+                        key: entry.key,
+                        code: Code::Unavailable as u32,
+                        message: "failed to dispatch the set call".to_string(),
+                        value: String::new(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Answers the batch with the results.
+    ///
+    /// Envoy sends the local reply of a gRPC request trailers-only, with the
+    /// body moved into grpc-message. The reply therefore goes out after the
+    /// request content type no longer reads as gRPC, with grpc-status in the
+    /// headers; the encoder callbacks then move it into trailers.
+    fn reply(&self) {
+        let failures = self
+            .results
+            .iter()
+            .filter(|result| result.code != Code::Ok as u32)
+            .count();
+        if let Some(metrics) = &self.metrics {
+            metrics.batch(self.results.len(), failures);
+        }
+        let response = kv::SetBatchResponse {
+            results: self.results.clone(),
+        };
+        self.set_http_request_header("content-type", Some(LOCAL_CONTENT_TYPE));
+        let split = self.results.len().to_string();
+        let status = Code::Ok.to_header();
+        self.send_http_response(
+            200,
+            vec![
+                ("content-type", "application/grpc"),
+                ("grpc-status", status.as_str()),
+                (SPLIT_HEADER, split.as_str()),
+            ],
+            Some(&encode_messages(&[response])),
+        );
+    }
+}
+
+impl HttpContext for Batch {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.is_batch = self.get_http_request_header(":path").as_deref() == Some(BATCH_PATH);
+        if !self.is_batch {
+            return Action::Continue;
+        }
+        if end_of_stream {
+            return local_grpc_reply(Code::InvalidArgument, "missing SetBatchRequest");
+        }
+        // The store does not serve batches; the request never goes upstream.
+        Action::Pause
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.is_batch {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let request = match read_grpc_message::<kv::SetBatchRequest>(Direction::Request, body_size)
+        {
+            Ok(request) => request,
+            Err(ReadError::Compressed) => {
+                return local_grpc_reply(
+                    Code::Unimplemented,
+                    "compressed batches are not supported",
+                )
+            }
+            Err(e) => {
+                log::warn!("cannot read SetBatchRequest: {}", e);
+                return local_grpc_reply(Code::InvalidArgument, "malformed SetBatchRequest");
+            }
+        };
+        if request.entries.len() > self.config.max_entries {
+            return local_grpc_reply(
+                Code::InvalidArgument,
+                &format!(
+                    "batch of {} entries exceeds the limit of {}",
+                    request.entries.len(),
+                    self.config.max_entries
+                ),
+            );
+        }
+        log::warn!("splitting batch of {} entries", request.entries.len());
+        self.split(request.entries);
+        if self.calls.is_empty() {
+            // Empty, or nothing could be dispatched.
+            self.reply();
+        }
+        Action::Pause
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        if !self.is_batch || end_of_stream || self.get_http_response_header(SPLIT_HEADER).is_none()
+        {
+            return Action::Continue;
+        }
+        self.replied = self.get_http_response_header("grpc-status");
+        for name in ["grpc-status", "content-length"] {
+            self.set_http_response_header(name, None);
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, _body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        if !end_of_stream {
+            return Action::Continue;
+        }
+        // Envoy adds trailers to a response whose last body chunk is being
+        // processed.
+        if let Some(status) = self.replied.take() {
+            self.set_http_response_trailer("grpc-status", Some(&status));
+        }
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        // Calls still in flight when the client went away.
+        for token in self.calls.keys() {
+            if let Err(e) = hostcalls::cancel_grpc_call(*token) {
+                log::warn!("failed to cancel set call {}: {:?}", token, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.batch.entries`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Counter of batches split.
+    batches: u32,
+    /// Counter of `set` calls issued for batch entries.
+    entries: u32,
+    /// Counter of entries whose `set` call did not return OK.
+    failures: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            batches: hostcalls::define_metric(MetricType::Counter, "batch.batches")?,
+            entries: hostcalls::define_metric(MetricType::Counter, "batch.entries")?,
+            failures: hostcalls::define_metric(MetricType::Counter, "batch.failures")?,
+        })
+    }
+
+    /// Records a split batch and its outcome.
+    pub fn batch(&self, entries: usize, failures: usize) {
+        increment(self.batches, 1);
+        increment(self.entries, entries as i64);
+        if failures > 0 {
+            increment(self.failures, failures as i64);
+        }
+    }
+}
+
+fn increment(metric_id: u32, offset: i64) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, offset) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
@@ -0,0 +1,74 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"max_buffer_bytes": 4194304, "overflow": "passthrough", "metadata": true, "hold_headers": false, "sample_percent": 100}
+/// ```
+///
+/// With `metadata`, the method, key and value length of the first request
+/// message are published through the property API. Envoy keeps them as
+/// filter state `wasm.kv.method`, `wasm.kv.key` and `wasm.kv.value_length`,
+/// which access logs (`%FILTER_STATE(wasm.kv.key:PLAIN)%`), RBAC and CEL
+/// expressions can read. Filters that decide on the request headers only see
+/// them with `hold_headers`, which keeps the headers until the first message
+/// is decoded.
+///
+/// Only `sample_percent` of the streams are decoded and inspected; the rest
+/// pass through untouched, which bounds the filter's cost at high QPS.
+///
+/// Messages with the compression flag set are decompressed with the
+/// stream's `grpc-encoding` when `decompress` is on and the codec is
+/// supported; otherwise they pass through undecoded and are counted as
+/// `buffer.compressed_skipped` rather than as decode failures.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Largest request or response body the filter buffers for inspection.
+    pub max_buffer_bytes: usize,
+    pub overflow: Overflow,
+    pub metadata: bool,
+    pub hold_headers: bool,
+    /// Share of streams to decode, in percent.
+    pub sample_percent: f64,
+    pub decompress: bool,
+}
+
+/// What to do with a body that grows past `max_buffer_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    /// Stop inspecting and stream the rest of the body through.
+    Passthrough,
+    /// Fail the RPC locally with `RESOURCE_EXHAUSTED`.
+    Reject,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_buffer_bytes: 4 * 1024 * 1024,
+            overflow: Overflow::Passthrough,
+            metadata: true,
+            hold_headers: false,
+            sample_percent: 100.0,
+            decompress: true,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.max_buffer_bytes == 0 {
+            return Err("max_buffer_bytes must be at least 1".to_string());
+        }
+        if !(0.0..=100.0).contains(&config.sample_percent) {
+            return Err(format!(
+                "sample_percent {} is not between 0 and 100",
+                config.sample_percent
+            ));
+        }
+        Ok(config)
+    }
+}
@@ -1,23 +1,235 @@
-use proxy_wasm::traits::{Context, HttpContext};
-use proxy_wasm::types::{Action, LogLevel};
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::codec::{self, Encoding};
+use grpc_frame::route::RouteConfig;
+use grpc_frame::status::{Code, TrailerPolicy};
+use grpc_frame::summary::StreamSummary;
+use wasm_filter_sdk::{local_grpc_reply, BufferedBody, Direction, Rng, Step};
 
 use prost::Message;
 pub mod kv {
     include!(concat!(env!("OUT_DIR"), "/kv.rs"));
 }
 
+mod config;
+mod metrics;
+use config::{Config, Overflow};
+use metrics::Metrics;
+
+const GET_METHOD: &str = "kv.KVService/get";
 
 #[no_mangle]
 pub fn _start() {
     proxy_wasm::set_log_level(LogLevel::Trace);
-    proxy_wasm::set_http_context(|context_id, _| -> Box<dyn HttpContext> {
-        Box::new(Buffer { context_id })
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(BufferRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+            rng: Rng::default(),
+        })
     });
 }
 
+struct BufferRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+    rng: Rng,
+}
+
+impl Context for BufferRoot {}
+
+impl RootContext for BufferRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        self.rng.seed(self.get_current_time());
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded buffer configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Buffer {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            rng: self.rng.clone(),
+            frames: 0,
+            summary: StreamSummary::default(),
+            method: String::new(),
+            published: false,
+            enabled: true,
+            request_encoding: Some(Encoding::Identity),
+            response_encoding: Some(Encoding::Identity),
+            request: BufferedBody::new(Direction::Request, self.config.max_buffer_bytes),
+            response: BufferedBody::new(Direction::Response, self.config.max_buffer_bytes),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
 struct Buffer {
     #[allow(unused)]
     context_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    rng: Rng,
+    // gRPC messages seen on this stream, both directions.
+    frames: usize,
+    // Message counts and sizes per direction, logged from on_log.
+    summary: StreamSummary,
+    // gRPC method, `:path` without the leading slash.
+    method: String,
+    // Set once the request attributes were published.
+    published: bool,
+    // Cleared when the route metadata turns the filter off, or when the
+    // stream is not sampled.
+    enabled: bool,
+    // `grpc-encoding` of each direction; None if the codec is unsupported.
+    request_encoding: Option<Encoding>,
+    response_encoding: Option<Encoding>,
+    // Bodies decoded as they are buffered; an overflowed body is streamed
+    // through uninspected.
+    request: BufferedBody,
+    response: BufferedBody,
+}
+
+impl Buffer {
+    /// Stops inspecting the stream; both bodies stream through untouched.
+    fn pass_through(&mut self) {
+        self.enabled = false;
+        self.request.pass_through();
+        self.response.pass_through();
+    }
+
+    /// Publishes the decoded attributes of the first request message as
+    /// filter state for the filters after this one.
+    fn publish(&mut self, key: &str, value_len: Option<usize>) {
+        if !self.config.metadata || self.published {
+            return;
+        }
+        self.published = true;
+        let value_len = value_len.map(|len| len.to_string());
+        let properties = [
+            ("kv.method", Some(self.method.as_str())),
+            ("kv.key", Some(key)),
+            ("kv.value_length", value_len.as_deref()),
+        ];
+        for (name, value) in properties {
+            let Some(value) = value else {
+                continue;
+            };
+            if let Err(e) = hostcalls::set_property(vec![name], Some(value.as_bytes())) {
+                log::warn!("failed to set property {}: {:?}", name, e);
+            }
+        }
+    }
+
+    /// Returns the uncompressed payload of a message, or None if it is left
+    /// undecoded: compressed with a codec the filter cannot or may not
+    /// decompress, or flagged as compressed on an uncompressed stream.
+    fn payload<'a>(
+        &self,
+        direction: Direction,
+        message: &'a grpc_frame::Message,
+    ) -> Option<Cow<'a, [u8]>> {
+        if !message.compressed {
+            return Some(Cow::Borrowed(&message.payload));
+        }
+        let encoding = match direction {
+            Direction::Request => self.request_encoding,
+            Direction::Response => self.response_encoding,
+        };
+        match encoding {
+            Some(Encoding::Identity) => {
+                log::warn!("compressed {} message without grpc-encoding", direction.name());
+                self.decode_failure();
+                None
+            }
+            Some(encoding) if self.config.decompress => {
+                match codec::decompress(encoding, &message.payload) {
+                    Ok(payload) => Some(Cow::Owned(payload)),
+                    Err(e) => {
+                        log::warn!("failed to decompress {} message: {}", direction.name(), e);
+                        self.decode_failure();
+                        None
+                    }
+                }
+            }
+            _ => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.compressed_skipped();
+                }
+                None
+            }
+        }
+    }
+
+    fn decode_failure(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.decode_failure();
+        }
+    }
+
+    /// Applies the overflow policy to a body of `body_size` buffered bytes
+    /// that exceeds the limit. Returns the action to take.
+    fn overflow(&mut self, direction: Direction, body_size: usize) -> Action {
+        log::warn!(
+            "{} body of {} bytes exceeds max_buffer_bytes {}",
+            direction.name(),
+            body_size,
+            self.config.max_buffer_bytes
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.overflow();
+        }
+        match self.config.overflow {
+            Overflow::Passthrough => {
+                match direction {
+                    Direction::Request => self.request.pass_through(),
+                    Direction::Response => self.response.pass_through(),
+                }
+                Action::Continue
+            }
+            Overflow::Reject => local_grpc_reply(
+                Code::ResourceExhausted,
+                "message exceeds the proxy buffer limit",
+            ),
+        }
+    }
+}
+
+fn encoding_from_header(value: Option<String>) -> Option<Encoding> {
+    match value {
+        Some(value) => Encoding::from_header(&value),
+        None => Some(Encoding::Identity),
+    }
 }
 
 impl Context for Buffer {}
@@ -25,7 +237,32 @@ impl Context for Buffer {}
 impl HttpContext for Buffer {
     fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
         log::warn!("executing on_http_request_headers");
+        if !self.route_settings("buffer").enabled {
+            log::warn!("buffer disabled on this route");
+            self.pass_through();
+            return Action::Continue;
+        }
+        let sampled = self.rng.roll(self.config.sample_percent);
+        if let Some(metrics) = &self.metrics {
+            metrics.sample(sampled);
+        }
+        if !sampled {
+            self.pass_through();
+            return Action::Continue;
+        }
+        self.method = self
+            .get_http_request_header(":path")
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_string();
+        self.request_encoding =
+            encoding_from_header(self.get_http_request_header("grpc-encoding"));
         if !end_of_stream {
+            if self.config.metadata && self.config.hold_headers {
+                // Released by the body callback once the first message is
+                // decoded and published.
+                return Action::Pause;
+            }
             return Action::Continue;
         }
 
@@ -35,29 +272,67 @@ impl HttpContext for Buffer {
 
     fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
         log::warn!("executing on_http_request_body");
-        if !end_of_stream {
-            return Action::Pause;
-        }
-
-        // Replace the message body if it contains the text "secret".
-        // Since we returned "Pause" previuously, this will return the whole body.
-        if let Some(body) = self.get_http_request_body(0, body_size) {
-            // log::warn!("body: {:?}", body);
-            // Parse grpc payload, skip the first 5 bytes
-            match kv::SetRequest::decode(&body[5..]) {
+        let messages = match self.request.poll(body_size) {
+            Step::Messages(messages) => messages,
+            Step::Overflow => return self.overflow(Direction::Request, body_size),
+            Step::Skip => return Action::Continue,
+        };
+        for message in messages {
+            self.frames += 1;
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    // A corrupt frame would only fail upstream.
+                    log::warn!("frame error: {}", e);
+                    self.decode_failure();
+                    return local_grpc_reply(Code::InvalidArgument, "malformed gRPC frame");
+                }
+            };
+            self.summary.request.record(message.payload.len());
+            let Some(payload) = self.payload(Direction::Request, &message) else {
+                continue;
+            };
+            if self.method == GET_METHOD {
+                match kv::GetRequest::decode(&payload[..]) {
+                    Ok(req) => self.publish(&req.key, None),
+                    Err(e) => {
+                        log::warn!("decode error: {}", e);
+                        self.decode_failure();
+                    }
+                }
+                continue;
+            }
+            match kv::SetRequest::decode(&payload[..]) {
                 Ok(req) => {
                     // log::info!("req: {:?}", req);
                     log::warn!("Requestvalue.len(): {}", req.value.len());
+                    self.publish(&req.key, Some(req.value.len()));
+                }
+                Err(e) => {
+                    log::warn!("decode error: {}", e);
+                    self.decode_failure();
                 }
-                Err(e) => log::warn!("decode error: {}", e),
             }
         }
+        if !end_of_stream {
+            return Action::Pause;
+        }
 
+        if self.request.pending() > 0 {
+            log::warn!("request ends inside a frame, {} bytes pending", self.request.pending());
+            self.decode_failure();
+            return local_grpc_reply(Code::InvalidArgument, "malformed gRPC frame");
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.request_bytes(body_size);
+        }
         Action::Continue
     }
 
     fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
         log::warn!("executing on_http_response_headers");
+        self.response_encoding =
+            encoding_from_header(self.get_http_response_header("grpc-encoding"));
         if !end_of_stream {
             return Action::Continue;
         }
@@ -65,45 +340,83 @@ impl HttpContext for Buffer {
         Action::Continue
     }
 
-    fn on_http_response_body(&mut self, _body_size: usize, end_of_stream: bool) -> Action {
-        log::warn!("executing on_http_response_body, body_size: {}, end_of_stream: {}", _body_size, end_of_stream);
-
-        // Try to get the full body even if end_of_stream is false
-        // Use a large size to get all available buffered data
-        let max_size = if end_of_stream { _body_size } else { usize::MAX };
-        
-        if let Some(body) = self.get_http_response_body(0, max_size) {
-            log::warn!("got response body, body.len(): {}, end_of_stream: {}", body.len(), end_of_stream);
-            if body.len() < 5 {
-                log::warn!("body too short ({} bytes), need at least 5 bytes for gRPC header", body.len());
-                if !end_of_stream {
-                    return Action::Pause;
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body, body_size: {}, end_of_stream: {}", body_size, end_of_stream);
+        // Each message is decoded as soon as its frame is complete.
+        let messages = match self.response.poll(body_size) {
+            Step::Messages(messages) => messages,
+            Step::Overflow => return self.overflow(Direction::Response, body_size),
+            Step::Skip => return Action::Continue,
+        };
+        for message in messages {
+            self.frames += 1;
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    log::warn!("frame error: {}", e);
+                    self.decode_failure();
+                    continue;
                 }
-                return Action::Continue;
-            }
-            // log::warn!("body: {:?}", body);
-            // Parse grpc payload, skip the first 5 bytes
-            match kv::GetResponse::decode(&body[5..]) {
+            };
+            self.summary.response.record(message.payload.len());
+            let Some(payload) = self.payload(Direction::Response, &message) else {
+                continue;
+            };
+            match kv::GetResponse::decode(&payload[..]) {
                 Ok(req) => {
                     // log::info!("req: {:?}", req);
                     log::warn!("Response value.len(): {}", req.value.len());
-                    // log::warn!("body : {}", req.value);
                 }
-                Err(e) => log::warn!("decode error: {}", e),
-            }
-        } else {
-            log::warn!("get_http_response_body returned None");
-            if !end_of_stream {
-                return Action::Pause;
+                Err(e) => {
+                    log::warn!("decode error: {}", e);
+                    self.decode_failure();
+                }
             }
         }
 
         // If we haven't seen the end of stream yet, pause to wait for more data
         if !end_of_stream {
-            log::warn!("end_of_stream is false, pausing to wait for more data");
             return Action::Pause;
         }
 
+        if self.response.pending() > 0 {
+            log::warn!("response ends inside a frame, {} bytes pending", self.response.pending());
+            self.decode_failure();
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.response_bytes(body_size);
+        }
         Action::Continue
     }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        if !self.enabled {
+            return Action::Continue;
+        }
+        let Some(status) = self.get_http_response_trailer("grpc-status") else {
+            return Action::Continue;
+        };
+        let status = Code::from_header(&status);
+        let message = self.get_http_response_trailer("grpc-message");
+        // The default policy keeps the status and normalizes grpc-message.
+        let trailers = TrailerPolicy::default().apply(status, message.as_deref());
+        if status != Code::Ok {
+            log::warn!("response failed with {}: {:?}", status.name(), trailers.message);
+        }
+
+        self.set_http_response_trailer("grpc-message", trailers.message.as_deref());
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        log::warn!("stream {}: {}", self.method, self.summary);
+        if let Some(metrics) = &self.metrics {
+            metrics.frames_per_stream(self.frames);
+            metrics.summary(&self.summary);
+        }
+    }
 }
@@ -0,0 +1,123 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.buffer.request_bytes`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+use grpc_frame::summary::StreamSummary;
+
+pub struct Metrics {
+    /// Histogram of buffered request body sizes.
+    request_bytes: u32,
+    /// Histogram of buffered response body sizes.
+    response_bytes: u32,
+    /// Counter of frames or messages that could not be decoded.
+    decode_failures: u32,
+    /// Histogram of gRPC messages seen per stream, both directions.
+    frames_per_stream: u32,
+    /// Counter of compressed messages passed through undecoded.
+    compressed_skipped: u32,
+    /// Counter of bodies that exceeded `max_buffer_bytes`.
+    overflows: u32,
+    /// Histograms of messages per stream, by direction.
+    request_messages: u32,
+    response_messages: u32,
+    /// Histogram of message payload sizes, both directions.
+    message_bytes: u32,
+    /// Counters of streams decoded and of streams passed through unsampled.
+    sampled: u32,
+    passed: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            request_bytes: hostcalls::define_metric(MetricType::Histogram, "buffer.request_bytes")?,
+            response_bytes: hostcalls::define_metric(
+                MetricType::Histogram,
+                "buffer.response_bytes",
+            )?,
+            decode_failures: hostcalls::define_metric(
+                MetricType::Counter,
+                "buffer.decode_failures",
+            )?,
+            frames_per_stream: hostcalls::define_metric(
+                MetricType::Histogram,
+                "buffer.frames_per_stream",
+            )?,
+            compressed_skipped: hostcalls::define_metric(
+                MetricType::Counter,
+                "buffer.compressed_skipped",
+            )?,
+            overflows: hostcalls::define_metric(MetricType::Counter, "buffer.overflows")?,
+            request_messages: hostcalls::define_metric(
+                MetricType::Histogram,
+                "buffer.request_messages",
+            )?,
+            response_messages: hostcalls::define_metric(
+                MetricType::Histogram,
+                "buffer.response_messages",
+            )?,
+            message_bytes: hostcalls::define_metric(MetricType::Histogram, "buffer.message_bytes")?,
+            sampled: hostcalls::define_metric(MetricType::Counter, "buffer.sampled")?,
+            passed: hostcalls::define_metric(MetricType::Counter, "buffer.passed")?,
+        })
+    }
+
+    pub fn request_bytes(&self, bytes: usize) {
+        record(self.request_bytes, bytes as u64);
+    }
+
+    pub fn response_bytes(&self, bytes: usize) {
+        record(self.response_bytes, bytes as u64);
+    }
+
+    pub fn decode_failure(&self) {
+        increment(self.decode_failures);
+    }
+
+    pub fn compressed_skipped(&self) {
+        increment(self.compressed_skipped);
+    }
+
+    pub fn overflow(&self) {
+        increment(self.overflows);
+    }
+
+    /// Counts a stream as decoded or passed through.
+    pub fn sample(&self, sampled: bool) {
+        increment(if sampled { self.sampled } else { self.passed });
+    }
+
+    pub fn frames_per_stream(&self, frames: usize) {
+        record(self.frames_per_stream, frames as u64);
+    }
+
+    /// Records a finished stream. Message sizes are recorded per size class,
+    /// at the class's upper bound.
+    pub fn summary(&self, summary: &StreamSummary) {
+        record(self.request_messages, summary.request.messages);
+        record(self.response_messages, summary.response.messages);
+        for sizes in [&summary.request, &summary.response] {
+            for (bound, count) in sizes.histogram() {
+                for _ in 0..count {
+                    record(self.message_bytes, bound);
+                }
+            }
+        }
+    }
+}
+
+fn increment(metric_id: u32) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, 1) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
+
+fn record(metric_id: u32, value: u64) {
+    if let Err(e) = hostcalls::record_metric(metric_id, value) {
+        log::warn!("failed to record metric: {:?}", e);
+    }
+}
@@ -0,0 +1,30 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"ttl_ms": 1000, "max_value_size": 65536}
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// How long a cached `GetResponse` is served, in milliseconds.
+    pub ttl_ms: u64,
+    /// Values larger than this are never cached.
+    pub max_value_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ttl_ms: 1000,
+            max_value_size: 64 * 1024,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
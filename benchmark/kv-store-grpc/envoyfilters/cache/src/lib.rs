@@ -0,0 +1,298 @@
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::status::Code;
+use prost::Message;
+use wasm_filter_sdk::HeldResponse;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+use config::Config;
+
+const GET_PATH: &str = "/kv.KVService/get";
+const SET_PATH: &str = "/kv.KVService/set";
+
+#[cfg_attr(not(test), no_mangle)]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(CacheRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct CacheRoot {
+    config: Rc<Config>,
+}
+
+impl Context for CacheRoot {}
+
+impl RootContext for CacheRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded cache configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Cache {
+            context_id,
+            config: self.config.clone(),
+            method: Method::Other,
+            key: None,
+            response: HeldResponse::default(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Get,
+    Set,
+    Other,
+}
+
+struct Cache {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    method: Method,
+    // Key of a GetRequest that missed the cache.
+    key: Option<String>,
+    // The upstream GetResponse, held until its status is known.
+    response: HeldResponse,
+}
+
+impl Context for Cache {}
+
+/// Cache entries are `[expiry, ns since epoch (u64 LE)][GetResponse.value]`.
+/// Shared data cannot be deleted, so invalidation stores an expired entry.
+fn cache_key(key: &str) -> String {
+    format!("cache.{}", key)
+}
+
+impl Cache {
+    fn now(&self) -> u64 {
+        self.get_current_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    }
+
+    fn lookup(&self, key: &str) -> Option<String> {
+        let (data, _) = self.get_shared_data(&cache_key(key));
+        let data = data?;
+        if data.len() < 8 {
+            return None;
+        }
+        let expiry = u64::from_le_bytes(data[..8].try_into().ok()?);
+        if expiry <= self.now() {
+            return None;
+        }
+        String::from_utf8(data[8..].to_vec()).ok()
+    }
+
+    fn store(&self, key: &str, value: &str, expiry: u64) {
+        let mut data = Vec::with_capacity(8 + value.len());
+        data.extend_from_slice(&expiry.to_le_bytes());
+        data.extend_from_slice(value.as_bytes());
+        // Last writer wins; a concurrent fill carries the same value.
+        if let Err(e) = self.set_shared_data(&cache_key(key), Some(&data), None) {
+            log::warn!("failed to update cache entry {}: {:?}", key, e);
+        }
+    }
+
+    /// The value of the buffered `GetResponse`, `body_size` bytes long, if
+    /// it can be cached.
+    fn response_value(&self, body_size: usize) -> Option<String> {
+        let body = self.get_http_response_body(0, body_size)?;
+        match grpc_frame::parse(&body) {
+            Ok(frame) if !frame.compressed => match kv::GetResponse::decode(frame.payload) {
+                Ok(resp) if resp.value.len() <= self.config.max_value_size => {
+                    return Some(resp.value);
+                }
+                Ok(_) => log::warn!("value too large to cache"),
+                Err(e) => log::warn!("decode error: {}", e),
+            },
+            Ok(_) => log::warn!("compressed response, not caching"),
+            Err(e) => log::warn!("frame error: {}", e),
+        }
+        None
+    }
+
+    /// Replies with a cached `GetResponse` without contacting the upstream.
+    ///
+    /// Envoy turns local replies to gRPC requests into trailers-only
+    /// responses on some versions, which drops the body; check the `x-cache`
+    /// header when benchmarking against a new Envoy build.
+    fn serve(&self, value: String) {
+        let resp = kv::GetResponse { value };
+        let body = grpc_frame::encode(&resp.encode_to_vec(), false);
+        self.send_http_response(
+            200,
+            vec![
+                ("content-type", "application/grpc"),
+                ("grpc-status", &Code::Ok.to_header()),
+                ("x-cache", "hit"),
+            ],
+            Some(&body),
+        );
+    }
+}
+
+impl HttpContext for Cache {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.method = match self.get_http_request_header(":path").as_deref() {
+            Some(GET_PATH) => Method::Get,
+            Some(SET_PATH) => Method::Set,
+            _ => Method::Other,
+        };
+        if self.method == Method::Get && !end_of_stream {
+            // Hold the headers until the key is known, so a hit never
+            // reaches the upstream.
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if self.method == Method::Other {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let Some(body) = self.get_http_request_body(0, body_size) else {
+            return Action::Continue;
+        };
+        let frame = match grpc_frame::parse(&body) {
+            Ok(frame) if !frame.compressed => frame,
+            Ok(_) => {
+                log::warn!("compressed request, bypassing the cache");
+                return Action::Continue;
+            }
+            Err(e) => {
+                log::warn!("frame error: {}", e);
+                return Action::Continue;
+            }
+        };
+
+        match self.method {
+            Method::Get => {
+                let key = match kv::GetRequest::decode(frame.payload) {
+                    Ok(req) => req.key,
+                    Err(e) => {
+                        log::warn!("decode error: {}", e);
+                        return Action::Continue;
+                    }
+                };
+                if let Some(value) = self.lookup(&key) {
+                    log::warn!("cache hit for {}", key);
+                    self.serve(value);
+                    return Action::Pause;
+                }
+                log::warn!("cache miss for {}", key);
+                self.key = Some(key);
+            }
+            Method::Set => match kv::SetRequest::decode(frame.payload) {
+                // Invalidate before the write reaches the store.
+                Ok(req) => self.store(&req.key, "", 0),
+                Err(e) => log::warn!("decode error: {}", e),
+            },
+            Method::Other => {}
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        if self.key.is_none() {
+            return Action::Continue;
+        }
+        if self.response.body(body_size, end_of_stream).is_some() {
+            // Without trailers there is no status to cache it by.
+            return Action::Continue;
+        }
+
+        Action::Pause
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        // Only successful responses are cached.
+        let ok = self
+            .get_http_response_trailer("grpc-status")
+            .is_some_and(|status| Code::from_header(&status) == Code::Ok);
+        let Some(key) = self.key.take().filter(|_| ok) else {
+            return Action::Continue;
+        };
+        let size = self.response.trailers();
+        if let Some(value) = size.and_then(|size| self.response_value(size)) {
+            let expiry = self.now() + self.config.ttl_ms * 1_000_000;
+            self.store(&key, &value, expiry);
+        }
+
+        Action::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proxy_wasm::types::MapType;
+    use wasm_filter_sdk::{test_host, Direction};
+
+    use super::*;
+
+    fn get_response(value: &str) -> Vec<u8> {
+        let resp = kv::GetResponse {
+            value: value.to_string(),
+        };
+        grpc_frame::encode(&resp.encode_to_vec(), false)
+    }
+
+    #[test]
+    fn caches_a_response_ended_by_trailers() {
+        let mut cache = Cache {
+            context_id: 0,
+            config: Rc::new(Config::default()),
+            method: Method::Get,
+            key: Some("k".to_string()),
+            response: HeldResponse::default(),
+        };
+        let body = get_response("v");
+        let (head, tail) = body.split_at(body.len() / 2);
+        for chunk in [head, tail] {
+            let size = test_host::push_body(Direction::Response, chunk);
+            assert_eq!(cache.on_http_response_body(size, false), Action::Pause);
+        }
+        test_host::set_header(MapType::HttpResponseTrailers, "grpc-status", "0");
+        assert_eq!(cache.on_http_response_trailers(1), Action::Continue);
+        assert_eq!(cache.lookup("k").as_deref(), Some("v"));
+    }
+}
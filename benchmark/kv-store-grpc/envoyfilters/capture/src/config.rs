@@ -0,0 +1,79 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"cluster": "outbound|8080||capture-collector.default.svc.cluster.local",
+///  "path": "/capture", "methods": ["kv.KVService/get"], "percent": 10}
+/// ```
+///
+/// Each worker batches the records of captured requests and POSTs them to
+/// `path` on `cluster` once the batch reaches `batch_bytes`, or every
+/// `flush_ms`. `methods` lists the captured gRPC `:path`s without the
+/// leading slash; empty captures every method. Requests with bodies over
+/// `max_body_bytes` are not captured, and the `redact` headers are left out
+/// of the recorded metadata.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub cluster: String,
+    pub path: String,
+    pub authority: String,
+    pub percent: f64,
+    pub methods: Vec<String>,
+    pub redact: Vec<String>,
+    pub max_body_bytes: usize,
+    pub batch_bytes: usize,
+    pub flush_ms: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            cluster: String::new(),
+            path: "/capture".to_string(),
+            authority: "capture-collector".to_string(),
+            percent: 100.0,
+            methods: Vec::new(),
+            redact: vec!["authorization".to_string(), "cookie".to_string()],
+            max_body_bytes: 1024 * 1024,
+            batch_bytes: 64 * 1024,
+            flush_ms: 1000,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.cluster.is_empty() {
+            return Err("cluster is required".to_string());
+        }
+        if !(0.0..=100.0).contains(&config.percent) {
+            return Err(format!(
+                "percent must be within 0..=100: {}",
+                config.percent
+            ));
+        }
+        if config.flush_ms == 0 {
+            return Err("flush_ms must be positive".to_string());
+        }
+        // Header names arrive lowercased.
+        for name in config.redact.iter_mut() {
+            *name = name.to_ascii_lowercase();
+        }
+        Ok(config)
+    }
+
+    pub fn captures(&self, method: &str) -> bool {
+        !self.cluster.is_empty()
+            && (self.methods.is_empty() || self.methods.iter().any(|m| m == method))
+    }
+
+    /// Whether the header goes into the recorded metadata.
+    pub fn records_header(&self, name: &str) -> bool {
+        !name.starts_with(':') && name != "content-length" && !self.redact.iter().any(|r| r == name)
+    }
+}
@@ -0,0 +1,263 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::record::Record;
+use wasm_filter_sdk::Rng;
+
+mod config;
+mod metrics;
+use config::Config;
+use metrics::Metrics;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|context_id| -> Box<dyn RootContext> {
+        Box::new(CaptureRoot {
+            context_id,
+            config: Rc::new(Config::default()),
+            metrics: None,
+            rng: Rng::default(),
+            batch: Rc::new(RefCell::new(Vec::new())),
+        })
+    });
+}
+
+fn epoch_us(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+/// POSTs the batch to the collector. A call made from a stream context is
+/// attributed to the root context, so it outlives the stream that filled
+/// the batch.
+fn flush(
+    current: u32,
+    root_id: u32,
+    config: &Config,
+    metrics: Option<&Metrics>,
+    batch: &RefCell<Vec<u8>>,
+) {
+    let body = std::mem::take(&mut *batch.borrow_mut());
+    if body.is_empty() {
+        return;
+    }
+    if current != root_id {
+        let _ = hostcalls::set_effective_context(root_id);
+    }
+    let result = hostcalls::dispatch_http_call(
+        &config.cluster,
+        vec![
+            (":method", "POST"),
+            (":path", &config.path),
+            (":authority", &config.authority),
+            ("content-type", "application/octet-stream"),
+        ],
+        Some(&body),
+        vec![],
+        Duration::from_millis(config.timeout_ms),
+    );
+    if current != root_id {
+        let _ = hostcalls::set_effective_context(current);
+    }
+    if let Err(e) = result {
+        log::warn!("failed to push {} capture bytes: {:?}", body.len(), e);
+        if let Some(metrics) = metrics {
+            metrics.push_failed();
+        }
+    }
+}
+
+struct CaptureRoot {
+    context_id: u32,
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+    rng: Rng,
+    // Encoded records not pushed yet; per worker.
+    batch: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Context for CaptureRoot {
+    fn on_http_call_response(&mut self, token_id: u32, _: usize, _: usize, _: usize) {
+        let status = self.get_http_call_response_header(":status");
+        log::warn!("capture push {} finished: status={:?}", token_id, status);
+    }
+}
+
+impl RootContext for CaptureRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        self.rng.seed(self.get_current_time());
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded capture configuration: {:?}", config);
+                self.set_tick_period(Duration::from_millis(config.flush_ms));
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn on_tick(&mut self) {
+        flush(
+            self.context_id,
+            self.context_id,
+            &self.config,
+            self.metrics.as_deref(),
+            &self.batch,
+        );
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Capture {
+            context_id,
+            root_id: self.context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            rng: self.rng.clone(),
+            batch: self.batch.clone(),
+            record: None,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Records each sampled request for offline replay.
+///
+/// The body is copied chunk by chunk as it streams upstream, so capturing
+/// adds no latency. At the end of the stream the body must decode as
+/// complete gRPC frames; the record then joins the worker's batch.
+struct Capture {
+    context_id: u32,
+    root_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    rng: Rng,
+    batch: Rc<RefCell<Vec<u8>>>,
+    // The request being captured; None if it is not.
+    record: Option<Record>,
+}
+
+impl Context for Capture {}
+
+impl Capture {
+    fn skip(&mut self, reason: &str) {
+        log::warn!("not capturing request: {}", reason);
+        self.record = None;
+        if let Some(metrics) = &self.metrics {
+            metrics.skipped();
+        }
+    }
+
+    fn finish(&mut self) {
+        let Some(record) = self.record.take() else {
+            return;
+        };
+        let mut frames = grpc_frame::frames(&record.payload);
+        if let Some(Err(e)) = frames.by_ref().find(|frame| frame.is_err()) {
+            return self.skip(&e.to_string());
+        }
+        if !frames.remainder().is_empty() {
+            return self.skip("truncated gRPC body");
+        }
+        let full = {
+            let mut batch = self.batch.borrow_mut();
+            if let Err(e) = record.encode_into(&mut batch) {
+                drop(batch);
+                return self.skip(&e.to_string());
+            }
+            batch.len() >= self.config.batch_bytes
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.recorded();
+        }
+        if full {
+            flush(
+                self.context_id,
+                self.root_id,
+                &self.config,
+                self.metrics.as_deref(),
+                &self.batch,
+            );
+        }
+    }
+}
+
+impl HttpContext for Capture {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let Some(path) = self.get_http_request_header(":path") else {
+            return Action::Continue;
+        };
+        let method = path.trim_start_matches('/');
+        if !self.config.captures(method) || !self.rng.roll(self.config.percent) {
+            return Action::Continue;
+        }
+
+        let metadata = self
+            .get_http_request_headers()
+            .into_iter()
+            .filter(|(name, _)| self.config.records_header(name))
+            .collect();
+        self.record = Some(Record {
+            timestamp_us: epoch_us(self.get_current_time()),
+            method: method.to_string(),
+            metadata,
+            payload: Vec::new(),
+        });
+        if end_of_stream {
+            self.finish();
+        }
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        let Some(captured) = self.record.as_ref().map(|r| r.payload.len()) else {
+            return Action::Continue;
+        };
+        if let Some(chunk) = self.get_http_request_body(0, body_size) {
+            if captured + chunk.len() > self.config.max_body_bytes {
+                self.skip("request body too large");
+                return Action::Continue;
+            }
+            if let Some(record) = &mut self.record {
+                record.payload.extend_from_slice(&chunk);
+            }
+        }
+        if end_of_stream {
+            self.finish();
+        }
+        Action::Continue
+    }
+
+    fn on_http_request_trailers(&mut self, _num_trailers: usize) -> Action {
+        // gRPC requests rarely carry trailers, but they end the stream.
+        self.finish();
+        Action::Continue
+    }
+}
@@ -0,0 +1,44 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.capture.records`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Counter of requests added to a batch.
+    records: u32,
+    /// Counter of sampled requests that could not be recorded.
+    skipped: u32,
+    /// Counter of batches that could not be dispatched, and were dropped.
+    push_failures: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            records: hostcalls::define_metric(MetricType::Counter, "capture.records")?,
+            skipped: hostcalls::define_metric(MetricType::Counter, "capture.skipped")?,
+            push_failures: hostcalls::define_metric(MetricType::Counter, "capture.push_failures")?,
+        })
+    }
+
+    pub fn recorded(&self) {
+        increment(self.records);
+    }
+
+    pub fn skipped(&self) {
+        increment(self.skipped);
+    }
+
+    pub fn push_failed(&self) {
+        increment(self.push_failures);
+    }
+}
+
+fn increment(metric_id: u32) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, 1) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
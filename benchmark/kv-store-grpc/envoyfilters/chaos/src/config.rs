@@ -0,0 +1,88 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"target": "both", "truncate_percent": 1, "corrupt_percent": 1, "corrupt_bytes": 2,
+///  "drop_trailers_percent": 1, "methods": ["kv.KVService/get"]}
+/// ```
+///
+/// Each fault hits the given share of streams, independently:
+///
+/// - `truncate_percent` cuts the body inside the length prefix of its last
+///   gRPC frame, so the peer sees a message that never completes.
+/// - `corrupt_percent` flips `corrupt_bytes` payload bytes; the framing
+///   stays valid, so the damage surfaces when the message is decoded.
+/// - `drop_trailers_percent` removes `grpc-status` and `grpc-message` from
+///   the response trailers.
+///
+/// Body faults apply to the request, the response or both, per `target`.
+/// `methods` lists the gRPC `:path`s without the leading slash; empty
+/// targets every method. Bodies over `max_body_bytes` are left alone.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub target: Target,
+    pub truncate_percent: f64,
+    pub corrupt_percent: f64,
+    pub corrupt_bytes: usize,
+    pub drop_trailers_percent: f64,
+    pub methods: Vec<String>,
+    pub max_body_bytes: usize,
+}
+
+/// The bodies that body faults apply to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Request,
+    Response,
+    Both,
+}
+
+impl Target {
+    pub fn request(self) -> bool {
+        self != Target::Response
+    }
+
+    pub fn response(self) -> bool {
+        self != Target::Request
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            target: Target::Both,
+            truncate_percent: 0.0,
+            corrupt_percent: 0.0,
+            corrupt_bytes: 1,
+            drop_trailers_percent: 0.0,
+            methods: Vec::new(),
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        for (field, percent) in [
+            ("truncate_percent", config.truncate_percent),
+            ("corrupt_percent", config.corrupt_percent),
+            ("drop_trailers_percent", config.drop_trailers_percent),
+        ] {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!("{} must be within 0..=100: {}", field, percent));
+            }
+        }
+        if config.corrupt_bytes == 0 {
+            return Err("corrupt_bytes must be at least 1".to_string());
+        }
+        Ok(config)
+    }
+
+    pub fn targets(&self, method: &str) -> bool {
+        self.methods.is_empty() || self.methods.iter().any(|m| m == method)
+    }
+}
@@ -0,0 +1,46 @@
+//! Rewrites that break the framing or the payloads of a gRPC body.
+
+use grpc_frame::HEADER_LEN;
+
+/// Offset and payload length of each complete frame of `body`.
+fn frames(body: &[u8]) -> Vec<(usize, usize)> {
+    let mut frames = grpc_frame::frames(body);
+    let mut found = Vec::new();
+    loop {
+        let offset = frames.offset();
+        match frames.next() {
+            Some(Ok(frame)) => found.push((offset, frame.payload.len())),
+            _ => return found,
+        }
+    }
+}
+
+/// Cuts `body` inside the length prefix of its last frame. The flag byte
+/// and `keep % 4` length bytes remain. Returns None for a body without a
+/// complete frame.
+pub fn truncate(body: &[u8], keep: usize) -> Option<Vec<u8>> {
+    let &(offset, _) = frames(body).last()?;
+    Some(body[..offset + 1 + keep % (HEADER_LEN - 1)].to_vec())
+}
+
+/// XORs `count` payload bytes with non-zero values, at positions drawn from
+/// `next`. Frame headers are left intact. Returns false if the body holds
+/// no payload bytes.
+pub fn corrupt(body: &mut [u8], count: usize, mut next: impl FnMut() -> u64) -> bool {
+    let frames = frames(body);
+    let total: usize = frames.iter().map(|(_, len)| len).sum();
+    if total == 0 {
+        return false;
+    }
+    for _ in 0..count {
+        let mut pos = (next() % total as u64) as usize;
+        for &(offset, len) in &frames {
+            if pos < len {
+                body[offset + HEADER_LEN + pos] ^= (next() % 255 + 1) as u8;
+                break;
+            }
+            pos -= len;
+        }
+    }
+    true
+}
@@ -0,0 +1,294 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use wasm_filter_sdk::{Direction, HeldResponse, Rng};
+
+mod config;
+mod damage;
+mod metrics;
+use config::Config;
+use metrics::Metrics;
+
+#[cfg_attr(not(test), no_mangle)]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(ChaosRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+            rng: Rng::default(),
+        })
+    });
+}
+
+struct ChaosRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+    rng: Rng,
+}
+
+impl Context for ChaosRoot {}
+
+impl RootContext for ChaosRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        self.rng.seed(self.get_current_time());
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded chaos configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Chaos {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            rng: self.rng.clone(),
+            targeted: false,
+            request: Plan::default(),
+            response: Plan::default(),
+            response_body: HeldResponse::default(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Body faults picked for one direction of a stream.
+#[derive(Debug, Default, Clone, Copy)]
+struct Plan {
+    truncate: bool,
+    corrupt: bool,
+}
+
+impl Plan {
+    fn any(self) -> bool {
+        self.truncate || self.corrupt
+    }
+}
+
+/// Damages a share of the streams on purpose, to exercise the error paths
+/// of gRPC clients and servers.
+///
+/// Faults are picked when a direction's headers arrive. A damaged body is
+/// buffered whole and rewritten at the end of the stream, and its
+/// `content-length` is dropped since the length changes.
+struct Chaos {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    rng: Rng,
+    // Whether the method is among the configured ones.
+    targeted: bool,
+    request: Plan,
+    response: Plan,
+    // Response body, held until the response completes.
+    response_body: HeldResponse,
+}
+
+impl Context for Chaos {}
+
+impl Chaos {
+    fn plan(&self) -> Plan {
+        Plan {
+            truncate: self.rng.roll(self.config.truncate_percent),
+            corrupt: self.rng.roll(self.config.corrupt_percent),
+        }
+    }
+
+    /// Applies `plan` to the complete body.
+    fn damage(&self, direction: Direction, plan: Plan, body_size: usize) {
+        if body_size > self.config.max_body_bytes {
+            log::warn!(
+                "{} body of {} bytes left intact",
+                direction.name(),
+                body_size
+            );
+            return;
+        }
+        let Some(mut body) = direction.read(0, body_size) else {
+            return;
+        };
+        if plan.corrupt
+            && damage::corrupt(&mut body, self.config.corrupt_bytes, || self.rng.next_u64())
+        {
+            log::warn!("corrupted the {} body", direction.name());
+            if let Some(metrics) = &self.metrics {
+                metrics.corrupted();
+            }
+        }
+        if plan.truncate {
+            if let Some(truncated) = damage::truncate(&body, self.rng.next_u64() as usize) {
+                log::warn!(
+                    "truncated the {} body from {} to {} bytes",
+                    direction.name(),
+                    body.len(),
+                    truncated.len()
+                );
+                body = truncated;
+                if let Some(metrics) = &self.metrics {
+                    metrics.truncated();
+                }
+            }
+        }
+        direction.write(0, body_size, &body);
+    }
+
+    /// Removes the status from the response headers or trailers.
+    fn drop_status(&self, trailers: bool) {
+        log::warn!("dropping grpc-status");
+        for name in ["grpc-status", "grpc-message"] {
+            if trailers {
+                self.set_http_response_trailer(name, None);
+            } else {
+                self.set_http_response_header(name, None);
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.trailers_dropped();
+        }
+    }
+}
+
+impl HttpContext for Chaos {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let Some(path) = self.get_http_request_header(":path") else {
+            return Action::Continue;
+        };
+        self.targeted = self.config.targets(path.trim_start_matches('/'));
+        if !self.targeted || !self.config.target.request() || end_of_stream {
+            return Action::Continue;
+        }
+        self.request = self.plan();
+        if self.request.any() {
+            self.set_http_request_header("content-length", None);
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.request.any() {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        self.damage(Direction::Request, self.request, body_size);
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        if !self.targeted {
+            return Action::Continue;
+        }
+        if end_of_stream {
+            // Trailers-only: the status is in the headers.
+            if self.rng.roll(self.config.drop_trailers_percent) {
+                self.drop_status(false);
+            }
+            return Action::Continue;
+        }
+        if self.config.target.response() {
+            self.response = self.plan();
+            if self.response.any() {
+                self.set_http_response_header("content-length", None);
+            }
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        if !self.response.any() {
+            return Action::Continue;
+        }
+        let Some(body_size) = self.response_body.body(body_size, end_of_stream) else {
+            return Action::Pause;
+        };
+
+        self.damage(Direction::Response, self.response, body_size);
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        if let Some(body_size) = self.response_body.trailers() {
+            self.damage(Direction::Response, self.response, body_size);
+        }
+        if self.targeted && self.rng.roll(self.config.drop_trailers_percent) {
+            self.drop_status(true);
+        }
+
+        Action::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proxy_wasm::types::MapType;
+    use wasm_filter_sdk::test_host;
+
+    use super::*;
+
+    #[test]
+    fn damages_a_response_ended_by_trailers() {
+        let mut chaos = Chaos {
+            context_id: 0,
+            config: Rc::new(Config::default()),
+            metrics: None,
+            rng: Rng::default(),
+            targeted: true,
+            request: Plan::default(),
+            response: Plan {
+                truncate: true,
+                corrupt: false,
+            },
+            response_body: HeldResponse::default(),
+        };
+        let mut body = grpc_frame::encode(b"first", false);
+        grpc_frame::encode_into(&mut body, b"second", false);
+        let (head, tail) = body.split_at(body.len() / 2);
+        for chunk in [head, tail] {
+            let size = test_host::push_body(Direction::Response, chunk);
+            assert_eq!(chaos.on_http_response_body(size, false), Action::Pause);
+        }
+        test_host::set_header(MapType::HttpResponseTrailers, "grpc-status", "0");
+        assert_eq!(chaos.on_http_response_trailers(1), Action::Continue);
+        // Cut inside the length prefix of the second message.
+        let damaged = test_host::body(Direction::Response);
+        let first = grpc_frame::encode(b"first", false).len();
+        assert!(damaged.len() > first && damaged.len() < first + grpc_frame::HEADER_LEN);
+        assert!(body.starts_with(&damaged));
+    }
+}
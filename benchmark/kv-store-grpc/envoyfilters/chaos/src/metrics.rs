@@ -0,0 +1,47 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.chaos.truncated`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Counter of bodies cut inside a length prefix.
+    truncated: u32,
+    /// Counter of bodies with flipped payload bytes.
+    corrupted: u32,
+    /// Counter of responses whose status trailers were removed.
+    trailers_dropped: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            truncated: hostcalls::define_metric(MetricType::Counter, "chaos.truncated")?,
+            corrupted: hostcalls::define_metric(MetricType::Counter, "chaos.corrupted")?,
+            trailers_dropped: hostcalls::define_metric(
+                MetricType::Counter,
+                "chaos.trailers_dropped",
+            )?,
+        })
+    }
+
+    pub fn truncated(&self) {
+        increment(self.truncated);
+    }
+
+    pub fn corrupted(&self) {
+        increment(self.corrupted);
+    }
+
+    pub fn trailers_dropped(&self) {
+        increment(self.trailers_dropped);
+    }
+}
+
+fn increment(metric_id: u32) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, 1) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
@@ -0,0 +1,135 @@
+//! Per-method circuit state kept in proxy-wasm shared data.
+//!
+//! The state is `[state (u8)][since, ns since epoch (u64 LE)]`
+//! `[requests (u32 LE)][failures (u32 LE)][probes in flight (u32 LE)]`.
+//! `since` is the start of the counting window while closed and the time the
+//! circuit opened otherwise; in half-open state `requests` counts successful
+//! probes.
+
+use crate::config::Config;
+
+pub const ENCODED_LEN: usize = 21;
+
+const MS: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Closed = 0,
+    Open = 1,
+    HalfOpen = 2,
+}
+
+/// Decision for a new request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admit {
+    Allow,
+    /// Allowed as a half-open probe; its outcome decides the circuit.
+    Probe,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Circuit {
+    pub state: State,
+    since: u64,
+    requests: u32,
+    failures: u32,
+    probes: u32,
+}
+
+impl Circuit {
+    pub fn closed(now: u64) -> Circuit {
+        Circuit {
+            state: State::Closed,
+            since: now,
+            requests: 0,
+            failures: 0,
+            probes: 0,
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Circuit> {
+        if bytes.len() != ENCODED_LEN {
+            return None;
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        Some(Circuit {
+            state: match bytes[0] {
+                0 => State::Closed,
+                1 => State::Open,
+                2 => State::HalfOpen,
+                _ => return None,
+            },
+            since: u64::from_le_bytes(bytes[1..9].try_into().ok()?),
+            requests: u32_at(9),
+            failures: u32_at(13),
+            probes: u32_at(17),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut buf = [0; ENCODED_LEN];
+        buf[0] = self.state as u8;
+        buf[1..9].copy_from_slice(&self.since.to_le_bytes());
+        buf[9..13].copy_from_slice(&self.requests.to_le_bytes());
+        buf[13..17].copy_from_slice(&self.failures.to_le_bytes());
+        buf[17..21].copy_from_slice(&self.probes.to_le_bytes());
+        buf
+    }
+
+    fn open(&mut self, now: u64) {
+        *self = Circuit {
+            state: State::Open,
+            ..Circuit::closed(now)
+        };
+    }
+
+    /// Decides whether a new request may go upstream.
+    pub fn admit(&mut self, config: &Config, now: u64) -> Admit {
+        if self.state == State::Open && now >= self.since + config.cooldown_ms * MS {
+            self.state = State::HalfOpen;
+            self.requests = 0;
+            self.probes = 0;
+        }
+        match self.state {
+            State::Closed => Admit::Allow,
+            State::Open => Admit::Reject,
+            State::HalfOpen if self.probes < config.probes => {
+                self.probes += 1;
+                Admit::Probe
+            }
+            State::HalfOpen => Admit::Reject,
+        }
+    }
+
+    /// Records the outcome of an admitted request.
+    pub fn record(&mut self, config: &Config, now: u64, probe: bool, failed: bool) {
+        match self.state {
+            State::Closed => {
+                if now >= self.since + config.window_ms * MS {
+                    *self = Circuit::closed(now);
+                }
+                self.requests += 1;
+                self.failures += failed as u32;
+                if self.requests >= config.min_requests
+                    && self.failures as f64 >= self.requests as f64 * config.error_rate
+                {
+                    self.open(now);
+                }
+            }
+            State::HalfOpen if probe => {
+                self.probes = self.probes.saturating_sub(1);
+                if failed {
+                    self.open(now);
+                    return;
+                }
+                self.requests += 1;
+                if self.requests >= config.probes {
+                    *self = Circuit::closed(now);
+                }
+            }
+            // Requests admitted before the circuit opened.
+            _ => {}
+        }
+    }
+}
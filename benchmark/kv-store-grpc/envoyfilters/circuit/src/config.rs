@@ -0,0 +1,68 @@
+use grpc_frame::status::Code;
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"window_ms": 10000, "min_requests": 20, "error_rate": 0.5, "cooldown_ms": 5000,
+///  "probes": 3, "failure_statuses": ["UNAVAILABLE", "DEADLINE_EXCEEDED"]}
+/// ```
+///
+/// Each method has its own circuit. It opens once at least `min_requests`
+/// responses in the current `window_ms` have an `error_rate` share of
+/// `failure_statuses` (or resets), rejects everything for `cooldown_ms`, then
+/// lets up to `probes` requests through and closes after as many successes.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub window_ms: u64,
+    pub min_requests: u32,
+    pub error_rate: f64,
+    pub cooldown_ms: u64,
+    pub probes: u32,
+    pub failure_statuses: Vec<String>,
+    #[serde(skip)]
+    failures: Vec<Code>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let failures = vec![
+            Code::Unknown,
+            Code::DeadlineExceeded,
+            Code::Internal,
+            Code::Unavailable,
+        ];
+        Config {
+            window_ms: 10_000,
+            min_requests: 20,
+            error_rate: 0.5,
+            cooldown_ms: 5_000,
+            probes: 3,
+            failure_statuses: failures.iter().map(|c| c.name().to_string()).collect(),
+            failures,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if !(0.0..=1.0).contains(&config.error_rate) {
+            return Err(format!("error_rate out of range: {}", config.error_rate));
+        }
+        if config.window_ms == 0 || config.probes == 0 {
+            return Err("window_ms and probes must be at least 1".to_string());
+        }
+        config.failures = config
+            .failure_statuses
+            .iter()
+            .map(|name| Code::from_name(name).ok_or_else(|| format!("unknown status: {}", name)))
+            .collect::<Result<_, _>>()?;
+        Ok(config)
+    }
+
+    pub fn is_failure(&self, status: Code) -> bool {
+        self.failures.contains(&status)
+    }
+}
@@ -0,0 +1,176 @@
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel, Status};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+
+mod circuit;
+mod config;
+use circuit::{Admit, Circuit};
+use config::Config;
+
+// Attempts to update a circuit before giving up on a contended key.
+const CAS_RETRIES: usize = 8;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(CircuitRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct CircuitRoot {
+    config: Rc<Config>,
+}
+
+impl Context for CircuitRoot {}
+
+impl RootContext for CircuitRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded circuit breaker configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(CircuitBreaker {
+            context_id,
+            config: self.config.clone(),
+            method: None,
+            probe: false,
+            status: None,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct CircuitBreaker {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // Set once the request is admitted.
+    method: Option<String>,
+    probe: bool,
+    // Final grpc-status, from the trailers or a trailers-only response.
+    status: Option<Code>,
+}
+
+impl Context for CircuitBreaker {}
+
+impl CircuitBreaker {
+    fn now(&self) -> u64 {
+        self.get_current_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    }
+
+    /// Applies `f` to the method's circuit in shared data. The circuit is
+    /// shared by all workers of the VM, so updates go through CAS. Returns
+    /// None if the update kept failing.
+    fn update<T>(&self, method: &str, mut f: impl FnMut(&mut Circuit, u64) -> T) -> Option<T> {
+        let key = format!("circuit.{}", method);
+        for _ in 0..CAS_RETRIES {
+            let now = self.now();
+            let (data, cas) = self.get_shared_data(&key);
+            let mut circuit = data
+                .as_deref()
+                .and_then(Circuit::decode)
+                .unwrap_or_else(|| Circuit::closed(now));
+            let before = circuit;
+            let result = f(&mut circuit, now);
+            if circuit.state != before.state {
+                log::warn!(
+                    "circuit for {}: {:?} -> {:?}",
+                    method,
+                    before.state,
+                    circuit.state
+                );
+            }
+            match self.set_shared_data(&key, Some(&circuit.encode()), cas) {
+                Ok(()) => return Some(result),
+                Err(Status::CasMismatch) => continue,
+                Err(e) => {
+                    log::warn!("failed to update circuit {}: {:?}", key, e);
+                    break;
+                }
+            }
+        }
+        None
+    }
+}
+
+impl HttpContext for CircuitBreaker {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let Some(path) = self.get_http_request_header(":path") else {
+            return Action::Continue;
+        };
+        let method = path.trim_start_matches('/').to_string();
+        let config = self.config.clone();
+        // Fail open rather than reject traffic we could not account for.
+        let admit = self
+            .update(&method, |circuit, now| circuit.admit(&config, now))
+            .unwrap_or(Admit::Allow);
+        if admit == Admit::Reject {
+            self.send_grpc_error(Code::Unavailable, &format!("circuit open for {}", method));
+            return Action::Pause;
+        }
+
+        self.probe = admit == Admit::Probe;
+        self.method = Some(method);
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        if end_of_stream {
+            self.status = self
+                .get_http_response_header("grpc-status")
+                .map(|status| Code::from_header(&status));
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        self.status = self
+            .get_http_response_trailer("grpc-status")
+            .map(|status| Code::from_header(&status));
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        let Some(method) = self.method.take() else {
+            return;
+        };
+        // A stream without a grpc-status was reset, which counts as a failure.
+        let failed = self
+            .status
+            .is_none_or(|status| self.config.is_failure(status));
+        let (config, probe) = (self.config.clone(), self.probe);
+        self.update(&method, |circuit, now| {
+            circuit.record(&config, now, probe, failed)
+        });
+    }
+}
@@ -0,0 +1,33 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"max_waiters": 64, "max_response_bytes": 1048576}
+/// ```
+///
+/// At most `max_waiters` requests wait on one in-flight `GetRequest`; later
+/// duplicates go upstream themselves. A response longer than
+/// `max_response_bytes` is not buffered for fan-out: its waiters are released
+/// to the upstream instead.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub max_waiters: usize,
+    pub max_response_bytes: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_waiters: 64,
+            max_response_bytes: 1024 * 1024,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,375 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::{self, Code};
+use prost::Message;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+mod metrics;
+use config::Config;
+use metrics::Metrics;
+
+const GET_PATH: &str = "/kv.KVService/get";
+/// Response header marking a reply fanned out to a waiter.
+const HIT_HEADER: &str = "x-coalesced";
+/// Content type a waiter's request is switched to before its reply; see
+/// `Coalesce::reply`.
+const LOCAL_CONTENT_TYPE: &str = "application/x-coalesced";
+
+/// Contexts waiting on the in-flight `GetRequest` for each key.
+///
+/// Flights are per worker: a response can only be fanned out to streams of
+/// the VM that received it, so duplicates arriving on other workers go
+/// upstream on their own.
+type Flights = HashMap<String, Vec<u32>>;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(CoalesceRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+            flights: Rc::new(RefCell::new(HashMap::new())),
+        })
+    });
+}
+
+struct CoalesceRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+    flights: Rc<RefCell<Flights>>,
+}
+
+impl Context for CoalesceRoot {}
+
+impl RootContext for CoalesceRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded coalesce configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Coalesce {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            flights: self.flights.clone(),
+            is_get: false,
+            role: Role::None,
+            response_size: 0,
+            replay: None,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Part a stream plays in the flight of its key.
+enum Role {
+    None,
+    /// Sent upstream; answers the waiters of the key when its response ends.
+    Leader(String),
+    /// Paused until the leader of the key answers or releases it.
+    Waiter(String),
+}
+
+/// What the waiters of a flight get once it ends.
+enum Outcome {
+    /// The leader's response, replayed to every waiter.
+    Response {
+        status: Code,
+        message: Option<String>,
+        body: Vec<u8>,
+    },
+    /// No response to share; the waiters go upstream themselves.
+    Release,
+}
+
+struct Coalesce {
+    context_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    flights: Rc<RefCell<Flights>>,
+    is_get: bool,
+    role: Role,
+    // Response body bytes buffered by a leader.
+    response_size: usize,
+    // grpc-status and grpc-message of a replayed response, moved into
+    // trailers once its body has passed.
+    replay: Option<(String, Option<String>)>,
+}
+
+impl Context for Coalesce {}
+
+/// Returns the key of a body holding exactly one uncompressed `GetRequest`.
+fn get_key(body: &[u8]) -> Option<String> {
+    let mut frames = grpc_frame::frames(body);
+    let frame = frames.next()?.ok()?;
+    if frame.compressed || frames.next().is_some() || !frames.remainder().is_empty() {
+        return None;
+    }
+    kv::GetRequest::decode(frame.payload)
+        .ok()
+        .map(|req| req.key)
+}
+
+impl Coalesce {
+    /// Ends the flight led by this stream and hands `outcome` to its
+    /// waiters. Does nothing unless this stream still leads a flight.
+    fn finish(&mut self, outcome: Outcome) {
+        let Role::Leader(key) = std::mem::replace(&mut self.role, Role::None) else {
+            return;
+        };
+        let waiters = self.flights.borrow_mut().remove(&key).unwrap_or_default();
+        if waiters.is_empty() {
+            return;
+        }
+        log::warn!("flight of {} ends with {} waiters", key, waiters.len());
+        for context_id in waiters {
+            // The stream may have been reset while it waited.
+            if hostcalls::set_effective_context(context_id).is_err() {
+                continue;
+            }
+            match &outcome {
+                Outcome::Response {
+                    status,
+                    message,
+                    body,
+                } => {
+                    self.reply(*status, message.as_deref(), body);
+                    if let Some(metrics) = &self.metrics {
+                        metrics.coalesced();
+                    }
+                }
+                Outcome::Release => {
+                    if let Err(e) = hostcalls::resume_http_request() {
+                        log::warn!("failed to resume context {}: {:?}", context_id, e);
+                    }
+                    if let Some(metrics) = &self.metrics {
+                        metrics.released();
+                    }
+                }
+            }
+        }
+        if let Err(e) = hostcalls::set_effective_context(self.context_id) {
+            log::warn!("failed to restore context {}: {:?}", self.context_id, e);
+        }
+    }
+
+    /// Answers the effective context, a waiter, with the leader's response.
+    ///
+    /// Envoy sends the local reply of a gRPC request trailers-only, with the
+    /// body moved into grpc-message. A successful response therefore goes out
+    /// after the waiter's request content type no longer reads as gRPC, with
+    /// grpc-status in the headers; the waiter's own encoder callbacks then
+    /// move it into trailers.
+    fn reply(&self, status: Code, message: Option<&str>, body: &[u8]) {
+        if status != Code::Ok || body.is_empty() {
+            let message = message.map(status::decode_message).unwrap_or_default();
+            self.send_grpc_error_with_headers(status, &message, &[(HIT_HEADER, "hit")]);
+            return;
+        }
+        self.set_http_request_header("content-type", Some(LOCAL_CONTENT_TYPE));
+        let status = status.to_header();
+        let mut headers = vec![
+            ("content-type", "application/grpc"),
+            ("grpc-status", status.as_str()),
+            (HIT_HEADER, "hit"),
+        ];
+        if let Some(message) = message {
+            headers.push(("grpc-message", message));
+        }
+        self.send_http_response(200, headers, Some(body));
+    }
+}
+
+impl HttpContext for Coalesce {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.is_get = self.get_http_request_header(":path").as_deref() == Some(GET_PATH);
+        if self.is_get && !end_of_stream {
+            // A waiter never reaches the upstream, and its headers stay
+            // writable for the reply.
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.is_get {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let Some(key) = self
+            .get_http_request_body(0, body_size)
+            .and_then(|body| get_key(&body))
+        else {
+            return Action::Continue;
+        };
+        let mut flights = self.flights.borrow_mut();
+        match flights.get_mut(&key) {
+            Some(waiters) if waiters.len() < self.config.max_waiters => {
+                log::warn!("get of {} waits for the request in flight", key);
+                waiters.push(self.context_id);
+                self.role = Role::Waiter(key);
+                Action::Pause
+            }
+            Some(_) => {
+                log::warn!("flight of {} is full", key);
+                Action::Continue
+            }
+            None => {
+                flights.insert(key.clone(), Vec::new());
+                self.role = Role::Leader(key);
+                if let Some(metrics) = &self.metrics {
+                    metrics.leader();
+                }
+                Action::Continue
+            }
+        }
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        match self.role {
+            Role::Leader(_) => {
+                let encoding = self.get_http_response_header("grpc-encoding");
+                if encoding.is_some_and(|encoding| encoding != "identity") {
+                    // A waiter may not accept the encoding.
+                    self.finish(Outcome::Release);
+                } else if end_of_stream {
+                    // Trailers-only: the status is all there is to share.
+                    let outcome = match self.get_http_response_header("grpc-status") {
+                        Some(status) => Outcome::Response {
+                            status: Code::from_header(&status),
+                            message: self.get_http_response_header("grpc-message"),
+                            body: Vec::new(),
+                        },
+                        None => Outcome::Release,
+                    };
+                    self.finish(outcome);
+                }
+            }
+            Role::Waiter(_) if !end_of_stream => {
+                if self.get_http_response_header(HIT_HEADER).is_none() {
+                    return Action::Continue;
+                }
+                self.replay = self
+                    .get_http_response_header("grpc-status")
+                    .map(|status| (status, self.get_http_response_header("grpc-message")));
+                for name in ["grpc-status", "grpc-message", "content-length"] {
+                    self.set_http_response_header(name, None);
+                }
+            }
+            _ => {}
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        if self.replay.is_some() {
+            if !end_of_stream {
+                return Action::Continue;
+            }
+            // Envoy adds trailers to a response whose last body chunk is
+            // being processed.
+            if let Some((status, message)) = self.replay.take() {
+                self.set_http_response_trailer("grpc-status", Some(&status));
+                if let Some(message) = message {
+                    self.set_http_response_trailer("grpc-message", Some(&message));
+                }
+            }
+            return Action::Continue;
+        }
+        if !matches!(self.role, Role::Leader(_)) {
+            return Action::Continue;
+        }
+        if body_size > self.config.max_response_bytes {
+            log::warn!("response of {} bytes is too long to share", body_size);
+            self.finish(Outcome::Release);
+            return Action::Continue;
+        }
+        if end_of_stream {
+            // A gRPC response without trailers has no status to share.
+            self.finish(Outcome::Release);
+            return Action::Continue;
+        }
+
+        // Buffered until the trailers complete the response.
+        self.response_size = body_size;
+        Action::Pause
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        if !matches!(self.role, Role::Leader(_)) {
+            return Action::Continue;
+        }
+        let outcome = match self.get_http_response_trailer("grpc-status") {
+            Some(status) => Outcome::Response {
+                status: Code::from_header(&status),
+                message: self.get_http_response_trailer("grpc-message"),
+                body: self
+                    .get_http_response_body(0, self.response_size)
+                    .unwrap_or_default(),
+            },
+            None => Outcome::Release,
+        };
+        self.finish(outcome);
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        match &self.role {
+            // A reset leader sends its waiters upstream.
+            Role::Leader(_) => self.finish(Outcome::Release),
+            Role::Waiter(key) => {
+                if let Some(waiters) = self.flights.borrow_mut().get_mut(key) {
+                    waiters.retain(|&id| id != self.context_id);
+                }
+            }
+            Role::None => {}
+        }
+    }
+}
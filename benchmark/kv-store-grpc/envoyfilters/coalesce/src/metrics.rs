@@ -0,0 +1,44 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.coalesce.coalesced`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Counter of `GetRequest`s sent upstream on behalf of waiters.
+    leaders: u32,
+    /// Counter of requests answered with another request's response.
+    coalesced: u32,
+    /// Counter of waiters released to the upstream after all.
+    released: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            leaders: hostcalls::define_metric(MetricType::Counter, "coalesce.leaders")?,
+            coalesced: hostcalls::define_metric(MetricType::Counter, "coalesce.coalesced")?,
+            released: hostcalls::define_metric(MetricType::Counter, "coalesce.released")?,
+        })
+    }
+
+    pub fn leader(&self) {
+        increment(self.leaders, 1);
+    }
+
+    pub fn coalesced(&self) {
+        increment(self.coalesced, 1);
+    }
+
+    pub fn released(&self) {
+        increment(self.released, 1);
+    }
+}
+
+fn increment(metric_id: u32, offset: i64) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, offset) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
@@ -0,0 +1,44 @@
+use grpc_frame::codec::Encoding;
+use serde::{Deserialize, Deserializer};
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"encoding": "zstd", "min_size": 64}
+/// ```
+///
+/// `encoding` is `zstd` or `gzip`. Messages smaller than `min_size` bytes are
+/// left uncompressed, since the codec framing would outweigh the savings.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(deserialize_with = "encoding")]
+    pub encoding: Encoding,
+    pub min_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            encoding: Encoding::Zstd,
+            min_size: 64,
+        }
+    }
+}
+
+fn encoding<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Encoding, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    match Encoding::from_header(&name) {
+        Some(Encoding::Identity) | None => Err(serde::de::Error::custom(format!(
+            "unsupported encoding: {}",
+            name
+        ))),
+        Some(encoding) => Ok(encoding),
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
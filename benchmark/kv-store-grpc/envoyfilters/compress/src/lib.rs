@@ -0,0 +1,365 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::codec::{self, Encoding};
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+use wasm_filter_sdk::HeldResponse;
+
+mod config;
+mod metrics;
+use config::Config;
+use metrics::Metrics;
+
+#[cfg_attr(not(test), no_mangle)]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(CompressRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+        })
+    });
+}
+
+struct CompressRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+}
+
+impl Context for CompressRoot {}
+
+impl RootContext for CompressRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded compress configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Compress {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            authority: String::new(),
+            client_accept: String::new(),
+            request: Transform::None,
+            response: Transform::None,
+            response_body: HeldResponse::default(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// What the filter does to the messages of one direction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transform {
+    None,
+    /// Compress uncompressed messages with the configured encoding.
+    Compress,
+    /// Decompress messages the receiver cannot decode.
+    Decompress,
+}
+
+/// Compresses messages for peers that accept the configured encoding.
+///
+/// Deployed on both sidecars, the filter negotiates on behalf of the
+/// applications: it adds the encoding to `grpc-accept-encoding` in both
+/// directions and decompresses anything its own application did not ask
+/// for. Request compression needs the upstream's list, which only arrives on
+/// responses, so it is remembered per `:authority` in shared data and the
+/// first requests to a new upstream go out uncompressed.
+struct Compress {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+    authority: String,
+    // `grpc-accept-encoding` sent by the downstream application.
+    client_accept: String,
+    request: Transform,
+    response: Transform,
+    // Response body, held until the response completes.
+    response_body: HeldResponse,
+}
+
+impl Context for Compress {}
+
+fn accept_key(authority: &str) -> String {
+    format!("compress.accept.{}", authority)
+}
+
+impl Compress {
+    /// The `grpc-accept-encoding` last seen from the upstream application.
+    fn upstream_accept(&self) -> Option<String> {
+        let (data, _) = self.get_shared_data(&accept_key(&self.authority));
+        data.and_then(|data| String::from_utf8(data).ok())
+    }
+
+    fn learn_upstream_accept(&self, accept: &str) {
+        if self.upstream_accept().as_deref() == Some(accept) {
+            return;
+        }
+        let key = accept_key(&self.authority);
+        if let Err(e) = self.set_shared_data(&key, Some(accept.as_bytes()), None) {
+            log::warn!("failed to store {}: {:?}", key, e);
+        }
+    }
+
+    /// Picks the transform for a direction from its `grpc-encoding` and
+    /// whether the receiver accepts the configured encoding.
+    fn transform(&self, encoding: Option<String>, receiver_accepts: bool) -> Transform {
+        let encoding = encoding.as_deref().map(Encoding::from_header);
+        match encoding {
+            None | Some(Some(Encoding::Identity)) if receiver_accepts => Transform::Compress,
+            Some(Some(e)) if e == self.config.encoding && !receiver_accepts => {
+                Transform::Decompress
+            }
+            _ => Transform::None,
+        }
+    }
+
+    /// Rewrites every message in a buffered body. Returns None if a message
+    /// could not be decompressed.
+    fn transform_body(&self, body: &[u8], transform: Transform) -> Option<Vec<u8>> {
+        let encoding = self.config.encoding;
+        let mut new_body = Vec::with_capacity(body.len());
+        let mut frames = grpc_frame::frames(body);
+        let (mut before, mut after) = (0, 0);
+        for frame in frames.by_ref() {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::warn!("frame error: {}", e);
+                    return None;
+                }
+            };
+            match transform {
+                Transform::Compress
+                    if !frame.compressed && frame.payload.len() >= self.config.min_size =>
+                {
+                    match codec::compress(encoding, frame.payload) {
+                        Ok(payload) => {
+                            before += frame.payload.len();
+                            after += payload.len();
+                            grpc_frame::encode_into(&mut new_body, &payload, true);
+                        }
+                        Err(e) => {
+                            // An uncompressed frame is valid under any grpc-encoding.
+                            log::warn!("compress error: {}", e);
+                            self.failure();
+                            grpc_frame::encode_into(&mut new_body, frame.payload, false);
+                        }
+                    }
+                }
+                Transform::Decompress if frame.compressed => {
+                    match codec::decompress(encoding, frame.payload) {
+                        Ok(payload) => grpc_frame::encode_into(&mut new_body, &payload, false),
+                        Err(e) => {
+                            log::warn!("decompress error: {}", e);
+                            self.failure();
+                            return None;
+                        }
+                    }
+                }
+                _ => grpc_frame::encode_into(&mut new_body, frame.payload, frame.compressed),
+            }
+        }
+        new_body.extend_from_slice(frames.remainder());
+        if before > 0 {
+            log::warn!(
+                "compressed {} -> {} bytes with {}",
+                before,
+                after,
+                encoding.as_str()
+            );
+            if let Some(metrics) = &self.metrics {
+                metrics.compressed(before, after);
+            }
+        }
+        Some(new_body)
+    }
+
+    fn failure(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.failure();
+        }
+    }
+
+    /// Transforms the buffered response body, `body_size` bytes long.
+    fn transform_response(&self, body_size: usize) {
+        if let Some(body) = self.get_http_response_body(0, body_size) {
+            // The headers are already downstream, so a bad message can only
+            // be forwarded as is.
+            if let Some(new_body) = self.transform_body(&body, self.response) {
+                self.set_http_response_body(0, body.len(), &new_body);
+            }
+        }
+    }
+
+    /// Sets `grpc-encoding` for the chosen transform.
+    fn encoding_header(&self, transform: Transform) -> Option<&'static str> {
+        match transform {
+            Transform::Compress => Some(self.config.encoding.as_str()),
+            _ => None,
+        }
+    }
+}
+
+impl HttpContext for Compress {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.authority = self
+            .get_http_request_header(":authority")
+            .unwrap_or_default();
+        let accept = self.get_http_request_header("grpc-accept-encoding");
+        self.client_accept = accept.clone().unwrap_or_default();
+        // We decompress whatever the downstream application cannot decode.
+        let advertised = codec::advertise(accept.as_deref(), self.config.encoding);
+        self.set_http_request_header("grpc-accept-encoding", Some(&advertised));
+        if end_of_stream {
+            return Action::Continue;
+        }
+
+        let upstream_accepts = self
+            .upstream_accept()
+            .is_some_and(|accept| self.config.encoding.accepted_by(&accept));
+        let encoding = self.get_http_request_header("grpc-encoding");
+        self.request = self.transform(encoding, upstream_accepts);
+        if self.request != Transform::None {
+            log::warn!("request transform: {:?}", self.request);
+            let header = self.encoding_header(self.request);
+            self.set_http_request_header("grpc-encoding", header);
+            self.set_http_request_header("content-length", None);
+        }
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if self.request == Transform::None {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        if let Some(body) = self.get_http_request_body(0, body_size) {
+            match self.transform_body(&body, self.request) {
+                Some(new_body) => self.set_http_request_body(0, body.len(), &new_body),
+                None => {
+                    self.send_grpc_error(Code::Internal, "failed to decompress message");
+                    return Action::Pause;
+                }
+            }
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        let accept = self.get_http_response_header("grpc-accept-encoding");
+        if let Some(accept) = &accept {
+            self.learn_upstream_accept(accept);
+        }
+        let advertised = codec::advertise(accept.as_deref(), self.config.encoding);
+        self.set_http_response_header("grpc-accept-encoding", Some(&advertised));
+        if end_of_stream {
+            // Trailers-only response.
+            return Action::Continue;
+        }
+
+        let client_accepts = self.config.encoding.accepted_by(&self.client_accept);
+        let encoding = self.get_http_response_header("grpc-encoding");
+        self.response = self.transform(encoding, client_accepts);
+        if self.response != Transform::None {
+            log::warn!("response transform: {:?}", self.response);
+            let header = self.encoding_header(self.response);
+            self.set_http_response_header("grpc-encoding", header);
+            self.set_http_response_header("content-length", None);
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        if self.response == Transform::None {
+            return Action::Continue;
+        }
+        let Some(body_size) = self.response_body.body(body_size, end_of_stream) else {
+            return Action::Pause;
+        };
+
+        self.transform_response(body_size);
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        if let Some(body_size) = self.response_body.trailers() {
+            self.transform_response(body_size);
+        }
+        Action::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proxy_wasm::types::MapType;
+    use wasm_filter_sdk::{test_host, Direction};
+
+    use super::*;
+
+    #[test]
+    fn compresses_a_response_ended_by_trailers() {
+        let mut compress = Compress {
+            context_id: 0,
+            config: Rc::new(Config::default()),
+            metrics: None,
+            authority: String::new(),
+            client_accept: String::new(),
+            request: Transform::None,
+            response: Transform::Compress,
+            response_body: HeldResponse::default(),
+        };
+        let message = vec![b'a'; 256];
+        let body = grpc_frame::encode(&message, false);
+        let (head, tail) = body.split_at(body.len() / 2);
+        for chunk in [head, tail] {
+            let size = test_host::push_body(Direction::Response, chunk);
+            assert_eq!(compress.on_http_response_body(size, false), Action::Pause);
+        }
+        test_host::set_header(MapType::HttpResponseTrailers, "grpc-status", "0");
+        assert_eq!(compress.on_http_response_trailers(1), Action::Continue);
+        let body = test_host::body(Direction::Response);
+        let frame = grpc_frame::parse(&body).unwrap();
+        assert!(frame.compressed);
+        let encoding = compress.config.encoding;
+        assert_eq!(codec::decompress(encoding, frame.payload).unwrap(), message);
+    }
+}
@@ -0,0 +1,42 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.compress.bytes_in`. The compression ratio is
+//! `bytes_out / bytes_in`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Counter of message bytes before compression.
+    bytes_in: u32,
+    /// Counter of message bytes after compression.
+    bytes_out: u32,
+    /// Counter of messages that failed to compress or decompress.
+    failures: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            bytes_in: hostcalls::define_metric(MetricType::Counter, "compress.bytes_in")?,
+            bytes_out: hostcalls::define_metric(MetricType::Counter, "compress.bytes_out")?,
+            failures: hostcalls::define_metric(MetricType::Counter, "compress.failures")?,
+        })
+    }
+
+    pub fn compressed(&self, before: usize, after: usize) {
+        increment(self.bytes_in, before as i64);
+        increment(self.bytes_out, after as i64);
+    }
+
+    pub fn failure(&self) {
+        increment(self.failures, 1);
+    }
+}
+
+fn increment(metric_id: u32, offset: i64) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, offset) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
@@ -0,0 +1,33 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"max_concurrent": 256}
+/// ```
+///
+/// At most `max_concurrent` RPCs are in flight across all workers of the
+/// VM; new ones beyond that fail with `RESOURCE_EXHAUSTED`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub max_concurrent: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_concurrent: 256,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.max_concurrent == 0 {
+            return Err("max_concurrent must be at least 1".to_string());
+        }
+        Ok(config)
+    }
+}
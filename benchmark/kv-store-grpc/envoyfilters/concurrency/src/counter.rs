@@ -0,0 +1,93 @@
+//! A counter in shared data, updated with compare-and-swap.
+//!
+//! Shared data is visible to every worker of the VM, but a read and the
+//! following write are separate host calls. Each update therefore writes
+//! with the CAS value of its read and starts over on `CasMismatch`.
+
+use proxy_wasm::traits::Context;
+use proxy_wasm::types::Status;
+
+/// Attempts per update before giving up on a contended counter.
+const CAS_RETRIES: usize = 64;
+
+/// CAS for the first write of a missing key. Envoy only checks the CAS of a
+/// key that exists, so a worker that lost the race to create the counter
+/// gets `CasMismatch` unless the winner's entry happens to carry this value.
+const CREATE_CAS: u32 = u32::MAX;
+
+pub struct SharedCounter {
+    key: &'static str,
+}
+
+/// The result of an update.
+pub struct Update<T> {
+    pub result: T,
+    /// The counter after the update.
+    pub value: u64,
+    /// Writes lost to other workers before this one succeeded.
+    pub conflicts: usize,
+}
+
+impl SharedCounter {
+    pub const fn new(key: &'static str) -> SharedCounter {
+        SharedCounter { key }
+    }
+
+    /// Applies `f` to the counter until the write goes through. `f` returns
+    /// the new value, or None to leave the counter as it is.
+    fn update<C, T, F>(&self, ctx: &C, f: F) -> Result<Update<T>, Status>
+    where
+        C: Context + ?Sized,
+        F: Fn(u64) -> (Option<u64>, T),
+    {
+        for conflicts in 0..CAS_RETRIES {
+            let (data, cas) = ctx.get_shared_data(self.key);
+            let current = data
+                .as_deref()
+                .and_then(|data| data.try_into().ok())
+                .map_or(0, u64::from_be_bytes);
+            let (next, result) = f(current);
+            let Some(next) = next else {
+                return Ok(Update {
+                    result,
+                    value: current,
+                    conflicts,
+                });
+            };
+            let cas = cas.or(Some(CREATE_CAS));
+            match ctx.set_shared_data(self.key, Some(&next.to_be_bytes()), cas) {
+                Ok(()) => {
+                    return Ok(Update {
+                        result,
+                        value: next,
+                        conflicts,
+                    })
+                }
+                Err(Status::CasMismatch) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Status::CasMismatch)
+    }
+
+    /// Increments the counter if it is below `limit`. The result tells
+    /// whether it was incremented.
+    pub fn try_acquire<C: Context + ?Sized>(
+        &self,
+        ctx: &C,
+        limit: u64,
+    ) -> Result<Update<bool>, Status> {
+        self.update(ctx, |current| {
+            if current < limit {
+                (Some(current + 1), true)
+            } else {
+                (None, false)
+            }
+        })
+    }
+
+    /// Decrements the counter.
+    pub fn release<C: Context + ?Sized>(&self, ctx: &C) -> Result<Update<()>, Status> {
+        self.update(ctx, |current| (Some(current.saturating_sub(1)), ()))
+    }
+}
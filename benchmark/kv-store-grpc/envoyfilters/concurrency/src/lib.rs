@@ -0,0 +1,137 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+
+mod config;
+mod counter;
+mod metrics;
+use config::Config;
+use counter::SharedCounter;
+use metrics::Metrics;
+
+/// RPCs in flight across all workers of the VM.
+const INFLIGHT: SharedCounter = SharedCounter::new("concurrency.inflight");
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(ConcurrencyRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+        })
+    });
+}
+
+struct ConcurrencyRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+}
+
+impl Context for ConcurrencyRoot {}
+
+impl RootContext for ConcurrencyRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded concurrency configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Concurrency {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            acquired: false,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Concurrency {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    // Set once the stream holds a slot, which on_log gives back.
+    acquired: bool,
+}
+
+impl Context for Concurrency {}
+
+impl HttpContext for Concurrency {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let update = match INFLIGHT.try_acquire(self, self.config.max_concurrent) {
+            Ok(update) => update,
+            Err(e) => {
+                // Fail open rather than reject traffic we could not account for.
+                log::warn!("failed to update the in-flight counter: {:?}", e);
+                return Action::Continue;
+            }
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.updated(update.value, update.conflicts);
+        }
+        if update.result {
+            self.acquired = true;
+            return Action::Continue;
+        }
+
+        log::warn!("{} RPCs in flight, rejecting", update.value);
+        if let Some(metrics) = &self.metrics {
+            metrics.rejected();
+        }
+        self.send_grpc_error(
+            Code::ResourceExhausted,
+            &format!(
+                "concurrency limit of {} reached",
+                self.config.max_concurrent
+            ),
+        );
+        Action::Pause
+    }
+
+    fn on_log(&mut self) {
+        if !self.acquired {
+            return;
+        }
+        match INFLIGHT.release(self) {
+            Ok(update) => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.updated(update.value, update.conflicts);
+                }
+            }
+            // The slot leaks: the counter stays one too high.
+            Err(e) => log::error!("failed to release an in-flight slot: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.concurrency.inflight`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Gauge of in-flight RPCs across all workers, as of the last update.
+    inflight: u32,
+    /// Counter of rejected RPCs.
+    rejected: u32,
+    /// Counter of counter writes lost to another worker and retried.
+    cas_conflicts: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            inflight: hostcalls::define_metric(MetricType::Gauge, "concurrency.inflight")?,
+            rejected: hostcalls::define_metric(MetricType::Counter, "concurrency.rejected")?,
+            cas_conflicts: hostcalls::define_metric(
+                MetricType::Counter,
+                "concurrency.cas_conflicts",
+            )?,
+        })
+    }
+
+    /// Records the counter after an update that took `conflicts` retries.
+    pub fn updated(&self, inflight: u64, conflicts: usize) {
+        if let Err(e) = hostcalls::record_metric(self.inflight, inflight) {
+            log::warn!("failed to record metric: {:?}", e);
+        }
+        if conflicts > 0 {
+            increment(self.cas_conflicts, conflicts as i64);
+        }
+    }
+
+    pub fn rejected(&self) {
+        increment(self.rejected, 1);
+    }
+}
+
+fn increment(metric_id: u32, offset: i64) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, offset) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
@@ -0,0 +1,42 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"header": "x-rpc-id", "derive": true, "ttl_ms": 10000, "max_response_bytes": 65536}
+/// ```
+///
+/// Requests without `header` are only deduplicated when `derive` is set, in
+/// which case the ID is a hash of the method and the serialized request. That
+/// treats identical requests within `ttl_ms` as retries of one another.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub header: String,
+    pub derive: bool,
+    /// How long a completed response is replayed for duplicates.
+    pub ttl_ms: u64,
+    /// Responses larger than this are not recorded.
+    pub max_response_bytes: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            header: "x-rpc-id".to_string(),
+            derive: false,
+            ttl_ms: 10_000,
+            max_response_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.header.is_empty() {
+            return Err("header must not be empty".to_string());
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,52 @@
+//! Per-ID state kept in proxy-wasm shared data.
+//!
+//! `[state(1B)][expiry, ns since epoch (u64 LE)][grpc-status(1B)][body]`:
+//! an in-flight marker while the first request is outstanding, then the
+//! recorded response. Shared data cannot be deleted, so entries are
+//! invalidated by letting them expire.
+
+use grpc_frame::status::Code;
+
+const IN_FLIGHT: u8 = 0;
+const DONE: u8 = 1;
+const HEADER_LEN: usize = 10;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Entry {
+    InFlight,
+    Done { status: Code, body: Vec<u8> },
+}
+
+impl Entry {
+    /// Decodes an entry, returning None if it is missing, corrupt or expired.
+    pub fn decode(data: &[u8], now: u64) -> Option<Entry> {
+        if data.len() < HEADER_LEN {
+            return None;
+        }
+        let expiry = u64::from_le_bytes(data[1..9].try_into().ok()?);
+        if expiry <= now {
+            return None;
+        }
+        match data[0] {
+            IN_FLIGHT => Some(Entry::InFlight),
+            DONE => Some(Entry::Done {
+                status: Code::from_i32(data[9] as i32),
+                body: data[HEADER_LEN..].to_vec(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, expiry: u64) -> Vec<u8> {
+        let (state, status, body) = match self {
+            Entry::InFlight => (IN_FLIGHT, Code::Ok, &[][..]),
+            Entry::Done { status, body } => (DONE, *status, body.as_slice()),
+        };
+        let mut data = Vec::with_capacity(HEADER_LEN + body.len());
+        data.push(state);
+        data.extend_from_slice(&expiry.to_le_bytes());
+        data.push(status as u8);
+        data.extend_from_slice(body);
+        data
+    }
+}
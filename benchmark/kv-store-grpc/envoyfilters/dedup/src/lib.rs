@@ -0,0 +1,294 @@
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+use wasm_filter_sdk::HeldResponse;
+
+mod config;
+mod entry;
+use config::Config;
+use entry::Entry;
+
+// How long an in-flight marker blocks duplicates if the first request never
+// completes (e.g. the proxy restarts mid-stream).
+const IN_FLIGHT_TTL_NS: u64 = 30_000_000_000;
+
+#[cfg_attr(not(test), no_mangle)]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(DedupRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct DedupRoot {
+    config: Rc<Config>,
+}
+
+impl Context for DedupRoot {}
+
+impl RootContext for DedupRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded dedup configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Dedup {
+            context_id,
+            config: self.config.clone(),
+            path: String::new(),
+            id: None,
+            body: None,
+            response: HeldResponse::default(),
+            recorded: false,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Dedup {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    path: String,
+    // ID this stream holds the in-flight marker for.
+    id: Option<String>,
+    // Buffered response message, recorded once the status is known.
+    body: Option<Vec<u8>>,
+    // Response body, held until the response completes.
+    response: HeldResponse,
+    recorded: bool,
+}
+
+impl Context for Dedup {}
+
+fn fnv1a(data: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in data.iter().flat_map(|d| d.iter()) {
+        hash = (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+impl Dedup {
+    fn now(&self) -> u64 {
+        self.get_current_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    }
+
+    fn key(id: &str) -> String {
+        format!("dedup.{}", id)
+    }
+
+    /// Claims `id` for this stream, or answers the request as a duplicate.
+    /// Returns the action for the current callback.
+    fn claim(&mut self, id: String) -> Action {
+        let key = Dedup::key(&id);
+        let now = self.now();
+        let (data, cas) = self.get_shared_data(&key);
+        match data.as_deref().and_then(|d| Entry::decode(d, now)) {
+            Some(Entry::Done { status, body }) => {
+                log::warn!("duplicate {} answered from the recorded response", id);
+                self.replay(status, &body);
+                return Action::Pause;
+            }
+            Some(Entry::InFlight) => {
+                log::warn!("duplicate {} while the original is in flight", id);
+                self.send_grpc_error(Code::Aborted, "duplicate request in flight");
+                return Action::Pause;
+            }
+            None => {}
+        }
+        let marker = Entry::InFlight.encode(now + IN_FLIGHT_TTL_NS);
+        if self.set_shared_data(&key, Some(&marker), cas).is_err() {
+            // Another worker claimed it between our read and write.
+            log::warn!("duplicate {} raced with the original", id);
+            self.send_grpc_error(Code::Aborted, "duplicate request in flight");
+            return Action::Pause;
+        }
+        self.id = Some(id);
+        Action::Continue
+    }
+
+    fn replay(&self, status: Code, body: &[u8]) {
+        if status != Code::Ok {
+            self.send_grpc_error(status, "replayed from an earlier attempt");
+            return;
+        }
+        self.send_http_response(
+            200,
+            vec![
+                ("content-type", "application/grpc"),
+                ("grpc-status", &status.to_header()),
+                ("x-dedup", "hit"),
+            ],
+            Some(body),
+        );
+    }
+
+    fn record(&mut self, status: Code) {
+        let Some(id) = self.id.as_deref() else {
+            return;
+        };
+        let body = self.body.take();
+        if status == Code::Ok && body.is_none() {
+            // A duplicate must never get an empty OK in place of the reply.
+            log::warn!("no response body for {}, not recording it", id);
+            self.release();
+            return;
+        }
+        let entry = Entry::Done {
+            status,
+            body: body.unwrap_or_default(),
+        };
+        let expiry = self.now() + self.config.ttl_ms * 1_000_000;
+        if let Err(e) = self.set_shared_data(&Dedup::key(id), Some(&entry.encode(expiry)), None) {
+            log::warn!("failed to record response for {}: {:?}", id, e);
+        }
+        self.recorded = true;
+    }
+
+    /// Releases the ID, so a retry is not rejected as a duplicate.
+    fn release(&mut self) {
+        if let Some(id) = self.id.take() {
+            let _ = self.set_shared_data(&Dedup::key(&id), Some(&Entry::InFlight.encode(0)), None);
+        }
+    }
+}
+
+impl HttpContext for Dedup {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.path = self.get_http_request_header(":path").unwrap_or_default();
+        if let Some(id) = self.get_http_request_header(&self.config.header) {
+            return self.claim(format!("{}:{}", self.path, id));
+        }
+        if self.config.derive && !end_of_stream {
+            // Hold the headers until the body yields an ID.
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if self.id.is_some() || !self.config.derive {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+        let Some(body) = self.get_http_request_body(0, body_size) else {
+            return Action::Continue;
+        };
+        let id = format!("{:016x}", fnv1a(&[self.path.as_bytes(), &body]));
+        self.claim(id)
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
+        // Trailers-only responses carry the status in the headers.
+        if let Some(status) = self.get_http_response_header("grpc-status") {
+            self.record(Code::from_header(&status));
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        if self.id.is_none() || self.recorded {
+            return Action::Continue;
+        }
+        if body_size > self.config.max_response_bytes {
+            // Too large to record; duplicates will see the in-flight marker
+            // until it expires.
+            self.id = None;
+            return Action::Continue;
+        }
+        let Some(body_size) = self.response.body(body_size, end_of_stream) else {
+            return Action::Pause;
+        };
+        self.body = self.get_http_response_body(0, body_size);
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        if self.id.is_none() || self.recorded {
+            return Action::Continue;
+        }
+        if let Some(body_size) = self.response.trailers() {
+            self.body = self.get_http_response_body(0, body_size);
+        }
+        if let Some(status) = self.get_http_response_trailer("grpc-status") {
+            self.record(Code::from_header(&status));
+        }
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        if !self.recorded {
+            // The stream ended without a status.
+            self.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proxy_wasm::types::MapType;
+    use wasm_filter_sdk::{test_host, Direction};
+
+    use super::*;
+
+    #[test]
+    fn records_a_response_ended_by_trailers() {
+        let mut dedup = Dedup {
+            context_id: 0,
+            config: Rc::new(Config::default()),
+            path: String::new(),
+            id: Some("a".to_string()),
+            body: None,
+            response: HeldResponse::default(),
+            recorded: false,
+        };
+        let body = grpc_frame::encode(b"reply", false);
+        let (head, tail) = body.split_at(body.len() / 2);
+        for chunk in [head, tail] {
+            let size = test_host::push_body(Direction::Response, chunk);
+            assert_eq!(dedup.on_http_response_body(size, false), Action::Pause);
+        }
+        test_host::set_header(MapType::HttpResponseTrailers, "grpc-status", "0");
+        assert_eq!(dedup.on_http_response_trailers(1), Action::Continue);
+        let (data, _) = dedup.get_shared_data(&Dedup::key("a"));
+        assert_eq!(
+            data.and_then(|data| Entry::decode(&data, dedup.now())),
+            Some(Entry::Done {
+                status: Code::Ok,
+                body,
+            })
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use grpc_frame::status::Code;
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"tick_ms": 5, "methods": {"kv.KVService/get": {"delay_ms": 20, "delay_percent": 50,
+///  "abort_percent": 5, "abort_status": "UNAVAILABLE"}}}
+/// ```
+///
+/// Methods are keyed by the gRPC `:path` without the leading slash; `default`
+/// applies to every other method. Delays are released from the root context's
+/// timer, so they are rounded up to `tick_ms`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub tick_ms: u64,
+    pub default: Option<FaultConfig>,
+    pub methods: HashMap<String, FaultConfig>,
+    #[serde(skip)]
+    faults: HashMap<String, Fault>,
+    #[serde(skip)]
+    default_fault: Option<Fault>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct FaultConfig {
+    pub delay_ms: u64,
+    pub delay_percent: f64,
+    pub abort_percent: f64,
+    pub abort_status: String,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig {
+            delay_ms: 0,
+            delay_percent: 0.0,
+            abort_percent: 0.0,
+            abort_status: "UNAVAILABLE".to_string(),
+        }
+    }
+}
+
+/// A validated fault for one method.
+#[derive(Debug, Clone, Copy)]
+pub struct Fault {
+    pub delay: Duration,
+    pub delay_percent: f64,
+    pub abort_percent: f64,
+    pub abort_status: Code,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tick_ms: 5,
+            default: None,
+            methods: HashMap::new(),
+            faults: HashMap::new(),
+            default_fault: None,
+        }
+    }
+}
+
+impl FaultConfig {
+    fn validate(&self, name: &str) -> Result<Fault, String> {
+        for (field, percent) in [
+            ("delay_percent", self.delay_percent),
+            ("abort_percent", self.abort_percent),
+        ] {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!("{} for {} must be within 0..=100", field, name));
+            }
+        }
+        let abort_status = Code::from_name(&self.abort_status)
+            .filter(|code| *code != Code::Ok)
+            .ok_or_else(|| format!("invalid abort_status for {}: {}", name, self.abort_status))?;
+        Ok(Fault {
+            delay: Duration::from_millis(self.delay_ms),
+            delay_percent: self.delay_percent,
+            abort_percent: self.abort_percent,
+            abort_status,
+        })
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.tick_ms == 0 {
+            return Err("tick_ms must be at least 1".to_string());
+        }
+        for (method, fault) in &config.methods {
+            let fault = fault.validate(method)?;
+            config.faults.insert(method.clone(), fault);
+        }
+        config.default_fault = config
+            .default
+            .as_ref()
+            .map(|fault| fault.validate("default"))
+            .transpose()?;
+        Ok(config)
+    }
+
+    pub fn fault(&self, method: &str) -> Option<Fault> {
+        self.faults.get(method).copied().or(self.default_fault)
+    }
+}
@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use wasm_filter_sdk::Rng;
+
+mod config;
+use config::Config;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(FaultRoot {
+            config: Rc::new(Config::default()),
+            delayed: Rc::new(RefCell::new(Vec::new())),
+            rng: Rng::default(),
+        })
+    });
+}
+
+/// Requests paused for an injected delay: (release time, http context id).
+type DelayQueue = Rc<RefCell<Vec<(SystemTime, u32)>>>;
+
+struct FaultRoot {
+    config: Rc<Config>,
+    delayed: DelayQueue,
+    rng: Rng,
+}
+
+impl Context for FaultRoot {}
+
+impl RootContext for FaultRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        self.rng.seed(self.get_current_time());
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        if let Some(bytes) = self.get_plugin_configuration() {
+            match Config::parse(&bytes) {
+                Ok(config) => {
+                    log::warn!("loaded fault configuration: {:?}", config);
+                    self.config = Rc::new(config);
+                }
+                Err(e) => {
+                    log::error!("invalid plugin configuration: {}", e);
+                    return false;
+                }
+            }
+        }
+        self.set_tick_period(Duration::from_millis(self.config.tick_ms));
+        true
+    }
+
+    fn on_tick(&mut self) {
+        let now = self.get_current_time();
+        let due: Vec<u32> = {
+            let mut delayed = self.delayed.borrow_mut();
+            let (due, pending) = delayed.drain(..).partition(|(at, _)| *at <= now);
+            *delayed = pending;
+            due.into_iter().map(|(_, id)| id).collect()
+        };
+        for context_id in due {
+            // The stream may have been reset while it was delayed.
+            if hostcalls::set_effective_context(context_id).is_ok() {
+                if let Err(e) = hostcalls::resume_http_request() {
+                    log::warn!("failed to resume context {}: {:?}", context_id, e);
+                }
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Fault {
+            context_id,
+            config: self.config.clone(),
+            delayed: self.delayed.clone(),
+            rng: self.rng.clone(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Fault {
+    context_id: u32,
+    config: Rc<Config>,
+    delayed: DelayQueue,
+    rng: Rng,
+}
+
+impl Context for Fault {}
+
+impl HttpContext for Fault {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let Some(path) = self.get_http_request_header(":path") else {
+            return Action::Continue;
+        };
+        let method = path.trim_start_matches('/');
+        let Some(fault) = self.config.fault(method) else {
+            return Action::Continue;
+        };
+
+        if self.rng.roll(fault.abort_percent) {
+            log::warn!("aborting {} with {}", method, fault.abort_status.name());
+            self.send_grpc_error(fault.abort_status, "fault injected by the fault filter");
+            return Action::Pause;
+        }
+        if !fault.delay.is_zero() && self.rng.roll(fault.delay_percent) {
+            log::warn!("delaying {} by {:?}", method, fault.delay);
+            let at = self.get_current_time() + fault.delay;
+            self.delayed.borrow_mut().push((at, self.context_id));
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+}
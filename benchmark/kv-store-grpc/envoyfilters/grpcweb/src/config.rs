@@ -0,0 +1,33 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"allow_origin": "*"}
+/// ```
+///
+/// Browsers only call the kv store cross-origin after a CORS preflight;
+/// `allow_origin` is returned in `access-control-allow-origin`, and `null`
+/// leaves CORS to another filter.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub allow_origin: Option<String>,
+    /// `access-control-max-age` for preflight responses, in seconds.
+    pub max_age: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            allow_origin: Some("*".to_string()),
+            max_age: 86400,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
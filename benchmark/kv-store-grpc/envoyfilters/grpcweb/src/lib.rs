@@ -0,0 +1,228 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+use grpc_frame::web::{self, Mode};
+
+mod config;
+use config::Config;
+
+const EXPOSE_HEADERS: &str = "grpc-status,grpc-message,grpc-status-details-bin";
+const ALLOW_HEADERS: &str =
+    "content-type,x-grpc-web,x-user-agent,grpc-timeout,grpc-accept-encoding,authorization";
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(GrpcWebRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct GrpcWebRoot {
+    config: Rc<Config>,
+}
+
+impl Context for GrpcWebRoot {}
+
+impl RootContext for GrpcWebRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded grpc-web configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(GrpcWeb {
+            context_id,
+            config: self.config.clone(),
+            mode: None,
+            response_body_size: 0,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Translates gRPC-Web requests to native gRPC and the responses back.
+///
+/// Native gRPC requests pass through untouched. The response body is held
+/// until the trailers arrive, since gRPC-Web sends them as the last frame of
+/// the body; Envoy lets the buffered body be rewritten from the trailers
+/// callback, and the HTTP trailers themselves are emptied.
+struct GrpcWeb {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // Set for gRPC-Web requests.
+    mode: Option<Mode>,
+    // Size of the buffered response body.
+    response_body_size: usize,
+}
+
+impl Context for GrpcWeb {}
+
+impl GrpcWeb {
+    /// Answers a CORS preflight. Returns false if the request is not one.
+    fn preflight(&self) -> bool {
+        let Some(origin) = &self.config.allow_origin else {
+            return false;
+        };
+        if self.get_http_request_header(":method").as_deref() != Some("OPTIONS")
+            || self
+                .get_http_request_header("access-control-request-method")
+                .is_none()
+        {
+            return false;
+        }
+        let max_age = self.config.max_age.to_string();
+        self.send_http_response(
+            204,
+            vec![
+                ("access-control-allow-origin", origin),
+                ("access-control-allow-methods", "POST,OPTIONS"),
+                ("access-control-allow-headers", ALLOW_HEADERS),
+                ("access-control-expose-headers", EXPOSE_HEADERS),
+                ("access-control-max-age", &max_age),
+            ],
+            None,
+        );
+        true
+    }
+
+    /// Appends the trailer frame to the buffered body and encodes it for
+    /// the client.
+    fn finish_body(&self, mode: Mode, trailers: &[(String, String)]) {
+        let mut body = self
+            .get_http_response_body(0, self.response_body_size)
+            .unwrap_or_default();
+        if !trailers.is_empty() {
+            body.extend_from_slice(&web::encode_trailers(trailers));
+        }
+        let body = match mode {
+            Mode::Binary => body,
+            Mode::Text => web::encode_text(&body),
+        };
+        self.set_http_response_body(0, self.response_body_size, &body);
+    }
+}
+
+impl HttpContext for GrpcWeb {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        if self.preflight() {
+            return Action::Pause;
+        }
+        let Some(content_type) = self.get_http_request_header("content-type") else {
+            return Action::Continue;
+        };
+        self.mode = Mode::from_content_type(&content_type);
+        let Some(mode) = self.mode else {
+            return Action::Continue;
+        };
+
+        log::warn!("translating {:?} gRPC-Web request", mode);
+        self.set_http_request_header(
+            "content-type",
+            Some(&web::to_grpc_content_type(&content_type)),
+        );
+        self.set_http_request_header("te", Some("trailers"));
+        self.set_http_request_header("x-grpc-web", None);
+        if mode == Mode::Text {
+            self.set_http_request_header("content-length", None);
+        }
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        // Binary gRPC-Web messages are already native gRPC frames.
+        if self.mode != Some(Mode::Text) {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        if let Some(body) = self.get_http_request_body(0, body_size) {
+            match web::decode_text(&body) {
+                Ok(decoded) => self.set_http_request_body(0, body_size, &decoded),
+                Err(e) => {
+                    log::warn!("invalid grpc-web-text body: {}", e);
+                    self.send_grpc_error(Code::InvalidArgument, "invalid grpc-web-text body");
+                    return Action::Pause;
+                }
+            }
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        let Some(mode) = self.mode else {
+            return Action::Continue;
+        };
+        let content_type = self
+            .get_http_response_header("content-type")
+            .unwrap_or_else(|| "application/grpc".to_string());
+        self.set_http_response_header(
+            "content-type",
+            Some(&web::from_grpc_content_type(&content_type, mode)),
+        );
+        self.set_http_response_header("content-length", None);
+        if let Some(origin) = &self.config.allow_origin {
+            self.set_http_response_header("access-control-allow-origin", Some(origin));
+            self.set_http_response_header("access-control-expose-headers", Some(EXPOSE_HEADERS));
+        }
+        // A trailers-only response is valid gRPC-Web as is.
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        let Some(mode) = self.mode else {
+            return Action::Continue;
+        };
+        self.response_body_size = body_size;
+        if !end_of_stream {
+            // Hold the body until the trailers can be appended.
+            return Action::Pause;
+        }
+
+        // The upstream ended the stream without trailers.
+        self.finish_body(mode, &[]);
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        let Some(mode) = self.mode else {
+            return Action::Continue;
+        };
+        let trailers = self.get_http_response_trailers();
+        self.finish_body(mode, &trailers);
+        for (name, _) in &trailers {
+            self.set_http_response_trailer(name, None);
+        }
+        Action::Continue
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use grpc_frame::status::Code;
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"status_map": {"500": "INTERNAL"}, "include_body": true, "max_message_bytes": 256}
+/// ```
+///
+/// HTTP statuses missing from `status_map` use the gRPC HTTP-to-status
+/// mapping (503 → UNAVAILABLE, 404 → UNIMPLEMENTED, ...). With
+/// `include_body` the start of the upstream body, e.g. Envoy's
+/// `upstream connect error or disconnect/reset before headers` text, is
+/// appended to `grpc-message`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    status_map: HashMap<u16, String>,
+    pub include_body: bool,
+    pub max_message_bytes: usize,
+    #[serde(skip)]
+    codes: HashMap<u16, Code>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            status_map: HashMap::new(),
+            include_body: true,
+            max_message_bytes: 256,
+            codes: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        config.codes = config
+            .status_map
+            .iter()
+            .map(|(status, name)| {
+                let code = Code::from_name(name)
+                    .ok_or_else(|| format!("unknown gRPC status code: {}", name))?;
+                Ok((*status, code))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(config)
+    }
+
+    /// The gRPC status reported for an upstream HTTP status.
+    pub fn code(&self, status: u16) -> Code {
+        self.codes
+            .get(&status)
+            .copied()
+            .unwrap_or_else(|| Code::from_http_status(status))
+    }
+}
@@ -0,0 +1,138 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+
+mod config;
+use config::Config;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(HttpStatusRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct HttpStatusRoot {
+    config: Rc<Config>,
+}
+
+impl Context for HttpStatusRoot {}
+
+impl RootContext for HttpStatusRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded HTTP status configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(HttpStatus {
+            context_id,
+            config: self.config.clone(),
+            rewrite: None,
+            body_size: 0,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Turns non-gRPC upstream responses into trailers-only gRPC responses.
+struct HttpStatus {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // Set while a non-gRPC response body is buffered: (HTTP status, code).
+    rewrite: Option<(u16, Code)>,
+    // Response body bytes buffered so far.
+    body_size: usize,
+}
+
+impl Context for HttpStatus {}
+
+impl HttpStatus {
+    fn reply(&self, status: u16, code: Code, body: Option<&[u8]>) {
+        let mut message = format!("upstream returned HTTP {}", status);
+        if let Some(body) = body {
+            let body = &body[..body.len().min(self.config.max_message_bytes)];
+            let body = String::from_utf8_lossy(body);
+            if !body.trim().is_empty() {
+                message.push_str(": ");
+                message.push_str(body.trim());
+            }
+        }
+        log::warn!("rewriting HTTP {} as {}: {}", status, code.name(), message);
+        self.send_grpc_error(code, &message);
+    }
+
+    /// Replies with the buffered body once the upstream response is complete.
+    fn finish(&mut self, body_size: usize) -> Action {
+        let Some((status, code)) = self.rewrite.take() else {
+            return Action::Continue;
+        };
+        let len = body_size.min(self.config.max_message_bytes);
+        let body = self.get_http_response_body(0, len);
+        self.reply(status, code, body.as_deref());
+        Action::Pause
+    }
+}
+
+impl HttpContext for HttpStatus {
+    fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        let content_type = self.get_http_response_header("content-type");
+        if content_type.is_some_and(|ct| ct.starts_with("application/grpc")) {
+            return Action::Continue;
+        }
+        let status = self
+            .get_http_response_header(":status")
+            .and_then(|status| status.parse().ok())
+            .unwrap_or(0);
+        let code = self.config.code(status);
+        if end_of_stream || !self.config.include_body {
+            self.reply(status, code, None);
+            return Action::Pause;
+        }
+        // Hold the headers until the body can go into grpc-message.
+        self.rewrite = Some((status, code));
+        Action::Pause
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        if self.rewrite.is_none() {
+            return Action::Continue;
+        }
+        self.body_size = body_size;
+        if !end_of_stream {
+            return Action::Pause;
+        }
+        self.finish(body_size)
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        self.finish(self.body_size)
+    }
+}
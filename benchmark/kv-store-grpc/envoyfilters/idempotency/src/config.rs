@@ -0,0 +1,45 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"header": "x-idempotency-key", "methods": ["kv.KVService/set"], "seed": 0, "overwrite": false}
+/// ```
+///
+/// `methods` lists the gRPC `:path`s, without the leading slash, that get a
+/// key; empty means every method. A key the client already sent is kept
+/// unless `overwrite` is set. Point the dedup filter's `header` at the same
+/// name to deduplicate on it.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub header: String,
+    pub methods: Vec<String>,
+    pub seed: u64,
+    pub overwrite: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            header: "x-idempotency-key".to_string(),
+            methods: Vec::new(),
+            seed: 0,
+            overwrite: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.header.is_empty() {
+            return Err("header must not be empty".to_string());
+        }
+        Ok(config)
+    }
+
+    pub fn applies_to(&self, method: &str) -> bool {
+        self.methods.is_empty() || self.methods.iter().any(|m| m == method)
+    }
+}
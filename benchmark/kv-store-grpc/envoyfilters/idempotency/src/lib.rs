@@ -0,0 +1,147 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use prost::Message;
+use xxhash_rust::xxh3::Xxh3;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+use config::Config;
+
+const GET_PATH: &str = "/kv.KVService/get";
+const SET_PATH: &str = "/kv.KVService/set";
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(IdempotencyRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct IdempotencyRoot {
+    config: Rc<Config>,
+}
+
+impl Context for IdempotencyRoot {}
+
+impl RootContext for IdempotencyRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded idempotency configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Idempotency {
+            context_id,
+            config: self.config.clone(),
+            path: None,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Idempotency {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // Set while the headers wait for the body to be hashed.
+    path: Option<String>,
+}
+
+impl Context for Idempotency {}
+
+/// Re-encodes a kv request so the key does not depend on how the client
+/// serialized it (field order, unknown fields). Other payloads hash as sent.
+fn canonical(path: &str, payload: &[u8]) -> Option<Vec<u8>> {
+    match path {
+        GET_PATH => kv::GetRequest::decode(payload)
+            .ok()
+            .map(|r| r.encode_to_vec()),
+        SET_PATH => kv::SetRequest::decode(payload)
+            .ok()
+            .map(|r| r.encode_to_vec()),
+        _ => None,
+    }
+}
+
+impl Idempotency {
+    /// Hashes the method and every message of the body. Compressed messages
+    /// hash their compressed bytes, so the key changes with the codec.
+    fn key(&self, path: &str, body: &[u8]) -> String {
+        let mut hasher = Xxh3::with_seed(self.config.seed);
+        hasher.update(path.as_bytes());
+        for frame in grpc_frame::frames(body) {
+            let Ok(frame) = frame else {
+                break;
+            };
+            let canonical = if frame.compressed {
+                None
+            } else {
+                canonical(path, frame.payload)
+            };
+            let payload = canonical.as_deref().unwrap_or(frame.payload);
+            hasher.update(&[frame.compressed as u8]);
+            hasher.update(&(payload.len() as u32).to_be_bytes());
+            hasher.update(payload);
+        }
+        format!("{:016x}", hasher.digest())
+    }
+}
+
+impl HttpContext for Idempotency {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let Some(path) = self.get_http_request_header(":path") else {
+            return Action::Continue;
+        };
+        if end_of_stream || !self.config.applies_to(path.trim_start_matches('/')) {
+            return Action::Continue;
+        }
+        if !self.config.overwrite && self.get_http_request_header(&self.config.header).is_some() {
+            return Action::Continue;
+        }
+        // Hold the headers until the body yields the key.
+        self.path = Some(path);
+        Action::Pause
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if self.path.is_none() {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let path = self.path.take().unwrap_or_default();
+        let body = self.get_http_request_body(0, body_size).unwrap_or_default();
+        let key = self.key(&path, &body);
+        log::warn!("{}: {}", self.config.header, key);
+        self.set_http_request_header(&self.config.header, Some(&key));
+        Action::Continue
+    }
+}
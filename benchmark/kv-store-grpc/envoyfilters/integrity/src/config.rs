@@ -0,0 +1,48 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"header": "x-body-crc32", "sign": "request", "require": true}
+/// ```
+///
+/// Each side of the pair signs one direction and verifies the other: the
+/// client sidecar signs requests and checks responses, the server sidecar
+/// does the opposite. Request checksums travel in a header, so requests are
+/// buffered; response checksums travel in a trailer and are computed while
+/// the body streams through. With `require`, a missing checksum counts as a
+/// mismatch.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub header: String,
+    pub sign: Direction,
+    pub require: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            header: "x-body-crc32".to_string(),
+            sign: Direction::Request,
+            require: true,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.header.is_empty() {
+            return Err("header must not be empty".to_string());
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,213 @@
+use std::rc::Rc;
+
+use crc32fast::Hasher;
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+
+mod config;
+mod metrics;
+use config::{Config, Direction};
+use metrics::Metrics;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(IntegrityRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+        })
+    });
+}
+
+struct IntegrityRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+}
+
+impl Context for IntegrityRoot {}
+
+impl RootContext for IntegrityRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded integrity configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Integrity {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            request_pending: false,
+            request_checksum: None,
+            request_size: 0,
+            response: Hasher::new(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Integrity {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    // Set while the request headers wait for the whole body.
+    request_pending: bool,
+    // Checksum the client sent, when verifying requests.
+    request_checksum: Option<String>,
+    // Request body bytes buffered so far.
+    request_size: usize,
+    // Running checksum of the response body.
+    response: Hasher,
+}
+
+impl Context for Integrity {}
+
+fn checksum(body: &[u8]) -> String {
+    format!("{:08x}", crc32fast::hash(body))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Check {
+    Valid,
+    Missing,
+    Mismatch,
+}
+
+impl Integrity {
+    fn check(&self, expected: Option<&str>, actual: &str) -> Check {
+        let check = match expected {
+            Some(expected) if expected.eq_ignore_ascii_case(actual) => Check::Valid,
+            Some(_) => Check::Mismatch,
+            None if self.config.require => Check::Missing,
+            None => Check::Valid,
+        };
+        if let Some(metrics) = &self.metrics {
+            match check {
+                Check::Valid => {}
+                Check::Missing => metrics.missing(),
+                Check::Mismatch => metrics.mismatch(),
+            }
+        }
+        if check != Check::Valid {
+            log::warn!(
+                "body checksum {:?}: expected {:?}, got {}",
+                check,
+                expected,
+                actual
+            );
+        }
+        check
+    }
+
+    /// Signs or verifies the buffered request once it is complete.
+    fn finish_request(&mut self) -> Action {
+        self.request_pending = false;
+        let body = self
+            .get_http_request_body(0, self.request_size)
+            .unwrap_or_default();
+        let actual = checksum(&body);
+        if self.config.sign == Direction::Request {
+            self.set_http_request_header(&self.config.header, Some(&actual));
+            return Action::Continue;
+        }
+        if self.check(self.request_checksum.as_deref(), &actual) != Check::Valid {
+            self.send_grpc_error(Code::DataLoss, "request body checksum mismatch");
+            return Action::Pause;
+        }
+        Action::Continue
+    }
+}
+
+impl HttpContext for Integrity {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        if self.config.sign == Direction::Response {
+            self.request_checksum = self.get_http_request_header(&self.config.header);
+            self.set_http_request_header(&self.config.header, None);
+        }
+        // Hold the headers: the checksum goes into them, or must be checked
+        // before anything reaches the upstream.
+        self.request_pending = true;
+        if end_of_stream {
+            return self.finish_request();
+        }
+        Action::Pause
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.request_pending {
+            return Action::Continue;
+        }
+        self.request_size = body_size;
+        if !end_of_stream {
+            return Action::Pause;
+        }
+        self.finish_request()
+    }
+
+    fn on_http_request_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_request_trailers");
+        if !self.request_pending {
+            return Action::Continue;
+        }
+        self.finish_request()
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        // The body streams through, so each call sees only the new bytes.
+        if let Some(chunk) = self.get_http_response_body(0, body_size) {
+            self.response.update(&chunk);
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        let actual = format!("{:08x}", std::mem::take(&mut self.response).finalize());
+        if self.config.sign == Direction::Response {
+            self.set_http_response_trailer(&self.config.header, Some(&actual));
+            return Action::Continue;
+        }
+
+        let expected = self.get_http_response_trailer(&self.config.header);
+        self.set_http_response_trailer(&self.config.header, None);
+        if self.check(expected.as_deref(), &actual) != Check::Valid {
+            // The body is already downstream; fail the RPC through its status.
+            self.set_http_response_trailer("grpc-status", Some(&Code::DataLoss.to_header()));
+            self.set_http_response_trailer("grpc-message", Some("response body checksum mismatch"));
+        }
+        Action::Continue
+    }
+}
@@ -0,0 +1,37 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.integrity.mismatches`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Counter of bodies whose checksum did not match.
+    mismatches: u32,
+    /// Counter of bodies that arrived without a checksum.
+    missing: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            mismatches: hostcalls::define_metric(MetricType::Counter, "integrity.mismatches")?,
+            missing: hostcalls::define_metric(MetricType::Counter, "integrity.missing")?,
+        })
+    }
+
+    pub fn mismatch(&self) {
+        increment(self.mismatches);
+    }
+
+    pub fn missing(&self) {
+        increment(self.missing);
+    }
+}
+
+fn increment(metric_id: u32) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, 1) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
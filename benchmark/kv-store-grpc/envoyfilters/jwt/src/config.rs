@@ -0,0 +1,92 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::token::{Algorithm, Key};
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"keys": [{"kid": "bench", "alg": "HS256", "key": "c2VjcmV0"},
+///           {"alg": "RS256", "key": "-----BEGIN PUBLIC KEY-----\n..."}],
+///  "issuer": "appnet", "audience": "kvstore", "leeway_s": 60,
+///  "rules": [{"method": "set", "claim": "scope", "contains": "kv.write"}]}
+/// ```
+///
+/// The bearer token in the `authorization` header must be signed by one of
+/// `keys`: a base64 secret for HS256, a PEM public key for RS256. `issuer`
+/// and `audience` are only checked when set. Requests without a valid token
+/// fail with `UNAUTHENTICATED`. Every rule for the `KVService` method (`get`
+/// or `set`) must then hold, else the request fails with `PERMISSION_DENIED`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(rename = "keys")]
+    key_configs: Vec<KeyConfig>,
+    /// Keys built from `keys` by `parse`.
+    #[serde(skip)]
+    pub keys: Vec<Key>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// Allowed clock skew for `exp` and `nbf`, in seconds.
+    pub leeway_s: u64,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+struct KeyConfig {
+    kid: Option<String>,
+    alg: Algorithm,
+    key: String,
+}
+
+// Secrets stay out of the configuration log.
+impl fmt::Debug for KeyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyConfig")
+            .field("kid", &self.kid)
+            .field("alg", &self.alg)
+            .finish()
+    }
+}
+
+/// Requires claim `claim` of tokens for `method` to contain `contains`.
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    pub method: String,
+    pub claim: String,
+    pub contains: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            key_configs: Vec::new(),
+            keys: Vec::new(),
+            issuer: None,
+            audience: None,
+            leeway_s: 60,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.key_configs.is_empty() {
+            return Err("at least one key is required".to_string());
+        }
+        config.keys = config
+            .key_configs
+            .iter()
+            .map(|key| Key::new(key.kid.clone(), key.alg, &key.key))
+            .collect::<Result<_, _>>()?;
+        Ok(config)
+    }
+
+    /// Rules that apply to `method`.
+    pub fn rules_for<'a>(&'a self, method: &'a str) -> impl Iterator<Item = &'a Rule> {
+        self.rules.iter().filter(move |rule| rule.method == method)
+    }
+}
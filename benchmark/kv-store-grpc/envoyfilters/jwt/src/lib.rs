@@ -0,0 +1,128 @@
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+
+mod config;
+mod token;
+use config::Config;
+
+const SERVICE_PREFIX: &str = "/kv.KVService/";
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(JwtRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct JwtRoot {
+    config: Rc<Config>,
+}
+
+impl Context for JwtRoot {}
+
+impl RootContext for JwtRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded jwt configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Jwt {
+            context_id,
+            config: self.config.clone(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Jwt {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+}
+
+impl Context for Jwt {}
+
+impl Jwt {
+    /// Verifies the bearer token and the rules for `method`. Returns the
+    /// status and message to fail the request with, if any.
+    fn check(&self, method: &str) -> Result<(), (Code, String)> {
+        let header = self.get_http_request_header("authorization");
+        let Some(token) = header.as_deref().and_then(|h| h.strip_prefix("Bearer ")) else {
+            return Err((Code::Unauthenticated, "missing bearer token".to_string()));
+        };
+        let now = self
+            .get_current_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let claims = token::decode(token.trim(), &self.config.keys)
+            .and_then(|claims| {
+                claims
+                    .validate(
+                        now,
+                        self.config.leeway_s,
+                        self.config.issuer.as_deref(),
+                        self.config.audience.as_deref(),
+                    )
+                    .map(|()| claims)
+            })
+            .map_err(|e| (Code::Unauthenticated, e.to_string()))?;
+        for rule in self.config.rules_for(method) {
+            if !claims.contains(&rule.claim, &rule.contains) {
+                log::warn!(
+                    "{:?} lacks {} {} for {}",
+                    claims.subject(),
+                    rule.claim,
+                    rule.contains,
+                    method
+                );
+                return Err((
+                    Code::PermissionDenied,
+                    format!("{} requires {} {}", method, rule.claim, rule.contains),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HttpContext for Jwt {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let path = self.get_http_request_header(":path").unwrap_or_default();
+        let method = path.strip_prefix(SERVICE_PREFIX).unwrap_or(&path);
+        match self.check(method) {
+            Ok(()) => Action::Continue,
+            Err((code, message)) => {
+                log::warn!("rejecting {}: {}", path, message);
+                self.send_grpc_error(code, &message);
+                Action::Pause
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Compact JWS decoding and verification for HS256 and RS256 tokens.
+
+use std::fmt;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use rsa::RsaPublicKey;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::Sha256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Algorithm {
+    HS256,
+    RS256,
+}
+
+/// A verification key.
+pub struct Key {
+    pub kid: Option<String>,
+    material: Material,
+}
+
+enum Material {
+    Hmac(Vec<u8>),
+    Rsa(VerifyingKey<Sha256>),
+}
+
+// Secrets stay out of the configuration log.
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("kid", &self.kid)
+            .field("alg", &self.alg())
+            .finish()
+    }
+}
+
+impl Key {
+    /// Builds a key from its configuration: a base64 secret for HS256, a PEM
+    /// public key (SubjectPublicKeyInfo or PKCS#1) for RS256.
+    pub fn new(kid: Option<String>, alg: Algorithm, key: &str) -> Result<Key, String> {
+        let material = match alg {
+            Algorithm::HS256 => {
+                let secret = STANDARD
+                    .decode(key.trim())
+                    .map_err(|e| format!("invalid HS256 secret: {}", e))?;
+                if secret.is_empty() {
+                    return Err("HS256 secret is empty".to_string());
+                }
+                Material::Hmac(secret)
+            }
+            Algorithm::RS256 => {
+                let key = RsaPublicKey::from_public_key_pem(key)
+                    .or_else(|_| RsaPublicKey::from_pkcs1_pem(key))
+                    .map_err(|e| format!("invalid RS256 public key: {}", e))?;
+                Material::Rsa(VerifyingKey::new(key))
+            }
+        };
+        Ok(Key { kid, material })
+    }
+
+    pub fn alg(&self) -> Algorithm {
+        match self.material {
+            Material::Hmac(_) => Algorithm::HS256,
+            Material::Rsa(_) => Algorithm::RS256,
+        }
+    }
+
+    fn verify(&self, input: &[u8], signature: &[u8]) -> bool {
+        match &self.material {
+            Material::Hmac(secret) => {
+                let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret) else {
+                    return false;
+                };
+                mac.update(input);
+                mac.verify_slice(signature).is_ok()
+            }
+            Material::Rsa(key) => Signature::try_from(signature)
+                .map(|signature| key.verify(input, &signature).is_ok())
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Why a token was not accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Malformed,
+    UnsupportedAlgorithm,
+    UnknownKey,
+    BadSignature,
+    Expired,
+    NotYetValid,
+    WrongIssuer,
+    WrongAudience,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::Malformed => "malformed token",
+            Error::UnsupportedAlgorithm => "unsupported token algorithm",
+            Error::UnknownKey => "unknown signing key",
+            Error::BadSignature => "invalid token signature",
+            Error::Expired => "token expired",
+            Error::NotYetValid => "token not yet valid",
+            Error::WrongIssuer => "unexpected token issuer",
+            Error::WrongAudience => "unexpected token audience",
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// The claims of a token whose signature was verified.
+#[derive(Debug)]
+pub struct Claims(Map<String, Value>);
+
+/// Decodes `token` and verifies its signature with a key of the token's
+/// algorithm. A token naming a `kid` is only checked against that key.
+pub fn decode(token: &str, keys: &[Key]) -> Result<Claims, Error> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Error::Malformed);
+    };
+    // The signature covers the encoded header and payload.
+    let input = &token.as_bytes()[..header.len() + 1 + payload.len()];
+    let decode_part = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| Error::Malformed);
+    let header: Header =
+        serde_json::from_slice(&decode_part(header)?).map_err(|_| Error::Malformed)?;
+    let alg = match header.alg.as_str() {
+        "HS256" => Algorithm::HS256,
+        "RS256" => Algorithm::RS256,
+        _ => return Err(Error::UnsupportedAlgorithm),
+    };
+    let signature = decode_part(signature)?;
+    let mut candidates = keys
+        .iter()
+        .filter(|key| key.alg() == alg)
+        .filter(|key| header.kid.is_none() || key.kid == header.kid)
+        .peekable();
+    if candidates.peek().is_none() {
+        return Err(Error::UnknownKey);
+    }
+    if !candidates.any(|key| key.verify(input, &signature)) {
+        return Err(Error::BadSignature);
+    }
+    match serde_json::from_slice(&decode_part(payload)?) {
+        Ok(Value::Object(claims)) => Ok(Claims(claims)),
+        _ => Err(Error::Malformed),
+    }
+}
+
+impl Claims {
+    /// Checks `exp`, `nbf`, `iss` and `aud` at `now` (seconds since the
+    /// epoch), allowing `leeway` seconds of clock skew.
+    pub fn validate(
+        &self,
+        now: u64,
+        leeway: u64,
+        issuer: Option<&str>,
+        audience: Option<&str>,
+    ) -> Result<(), Error> {
+        let time = |name| self.0.get(name).and_then(Value::as_u64);
+        if time("exp").is_some_and(|exp| now > exp.saturating_add(leeway)) {
+            return Err(Error::Expired);
+        }
+        if time("nbf").is_some_and(|nbf| now.saturating_add(leeway) < nbf) {
+            return Err(Error::NotYetValid);
+        }
+        if let Some(issuer) = issuer {
+            if self.0.get("iss").and_then(Value::as_str) != Some(issuer) {
+                return Err(Error::WrongIssuer);
+            }
+        }
+        if let Some(audience) = audience {
+            let matches = match self.0.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(aud)) => aud.iter().any(|v| v.as_str() == Some(audience)),
+                _ => false,
+            };
+            if !matches {
+                return Err(Error::WrongAudience);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether claim `name` holds `value`: equal to a string claim, one of
+    /// the words of a space-separated one such as `scope`, or an element of
+    /// an array claim.
+    pub fn contains(&self, name: &str, value: &str) -> bool {
+        match self.0.get(name) {
+            Some(Value::String(claim)) => claim.split(' ').any(|word| word == value),
+            Some(Value::Array(claim)) => claim.iter().any(|v| v.as_str() == Some(value)),
+            _ => false,
+        }
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get("sub").and_then(Value::as_str)
+    }
+}
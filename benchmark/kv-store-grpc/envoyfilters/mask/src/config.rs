@@ -0,0 +1,116 @@
+use regex::Regex;
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"rules": [{"regex": "[0-9]{3}-[0-9]{2}-[0-9]{4}", "replace": "***-**-****"},
+///  {"regex": "[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+", "keep_last": 4},
+///  {"key_prefix": "secret/"}]}
+/// ```
+///
+/// A rule with a `regex` redacts each match: with `replace` (which may use
+/// `$1` style capture references), or by overwriting every character with
+/// `mask` except the last `keep_last`. A rule without `regex` masks the whole
+/// value. `key_prefix` limits a rule to values read under matching keys.
+/// Rules are applied in order.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    rules: Vec<RuleConfig>,
+    #[serde(skip)]
+    compiled: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleConfig {
+    regex: Option<String>,
+    replace: Option<String>,
+    #[serde(default = "default_mask")]
+    mask: char,
+    #[serde(default)]
+    keep_last: usize,
+    #[serde(default)]
+    key_prefix: String,
+}
+
+fn default_mask() -> char {
+    '*'
+}
+
+#[derive(Debug)]
+struct Rule {
+    regex: Option<Regex>,
+    replace: Option<String>,
+    mask: char,
+    keep_last: usize,
+    key_prefix: String,
+}
+
+/// Overwrites all but the last `keep` characters.
+fn mask(text: &str, mask: char, keep: usize) -> String {
+    let len = text.chars().count();
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| if i + keep < len { mask } else { c })
+        .collect()
+}
+
+impl Rule {
+    fn apply(&self, value: &str) -> String {
+        match (&self.regex, &self.replace) {
+            (Some(regex), Some(replace)) => regex.replace_all(value, replace.as_str()).into_owned(),
+            (Some(regex), None) => regex
+                .replace_all(value, |caps: &regex::Captures| {
+                    mask(&caps[0], self.mask, self.keep_last)
+                })
+                .into_owned(),
+            (None, _) => mask(value, self.mask, self.keep_last),
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        config.compiled = config
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                let regex = rule
+                    .regex
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|e| format!("rule {}: {}", i, e))?;
+                if regex.is_none() && rule.replace.is_some() {
+                    return Err(format!("rule {}: replace requires a regex", i));
+                }
+                Ok(Rule {
+                    regex,
+                    replace: rule.replace.clone(),
+                    mask: rule.mask,
+                    keep_last: rule.keep_last,
+                    key_prefix: rule.key_prefix.clone(),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(config)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.compiled.is_empty()
+    }
+
+    /// Redacts a value read under `key`. Returns None if nothing changed.
+    pub fn redact(&self, key: &str, value: &str) -> Option<String> {
+        let mut redacted = value.to_string();
+        for rule in &self.compiled {
+            if key.starts_with(rule.key_prefix.as_str()) {
+                redacted = rule.apply(&redacted);
+            }
+        }
+        (redacted != value).then_some(redacted)
+    }
+}
@@ -0,0 +1,233 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use prost::Message;
+use wasm_filter_sdk::HeldResponse;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+use config::Config;
+
+const GET_PATH: &str = "/kv.KVService/get";
+
+#[cfg_attr(not(test), no_mangle)]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(MaskRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct MaskRoot {
+    config: Rc<Config>,
+}
+
+impl Context for MaskRoot {}
+
+impl RootContext for MaskRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded masking configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Mask {
+            context_id,
+            config: self.config.clone(),
+            get: false,
+            key: String::new(),
+            response: HeldResponse::default(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Redacts the value of every `GetResponse` before it leaves the sidecar.
+struct Mask {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    get: bool,
+    // Key of the GetRequest, for rules scoped by key prefix.
+    key: String,
+    // Response body, held until the response completes.
+    response: HeldResponse,
+}
+
+impl Context for Mask {}
+
+impl Mask {
+    /// Redacts every message in a buffered response body. Returns None if
+    /// nothing changed. Fails closed: messages it cannot read lose their
+    /// value, and a body it cannot frame is dropped.
+    fn redact_body(&self, body: &[u8]) -> Option<Vec<u8>> {
+        let mut new_body = Vec::with_capacity(body.len());
+        let mut frames = grpc_frame::frames(body);
+        let mut changed = false;
+        for frame in frames.by_ref() {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::warn!("frame error, dropping the response body: {}", e);
+                    return Some(Vec::new());
+                }
+            };
+            let redacted = if frame.compressed {
+                // Deploy the compress filter after this one so values are
+                // masked before they are compressed.
+                log::warn!("compressed response, stripping the value");
+                Some(String::new())
+            } else {
+                match kv::GetResponse::decode(frame.payload) {
+                    Ok(resp) => self.config.redact(&self.key, &resp.value),
+                    Err(e) => {
+                        log::warn!("decode error, stripping the value: {}", e);
+                        Some(String::new())
+                    }
+                }
+            };
+            match redacted {
+                Some(value) => {
+                    changed = true;
+                    let resp = kv::GetResponse { value };
+                    grpc_frame::encode_into(&mut new_body, &resp.encode_to_vec(), false);
+                }
+                None => grpc_frame::encode_into(&mut new_body, frame.payload, false),
+            }
+        }
+        if !frames.remainder().is_empty() {
+            // A truncated message could still hold part of a value.
+            log::warn!("dropping {} trailing bytes", frames.remainder().len());
+            changed = true;
+        }
+        changed.then_some(new_body)
+    }
+
+    /// Redacts the buffered response body, `body_size` bytes long.
+    fn redact_response(&self, body_size: usize) {
+        if let Some(body) = self.get_http_response_body(0, body_size) {
+            if let Some(new_body) = self.redact_body(&body) {
+                self.set_http_response_body(0, body.len(), &new_body);
+            }
+        }
+    }
+}
+
+impl HttpContext for Mask {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.get = !self.config.is_empty()
+            && self.get_http_request_header(":path").as_deref() == Some(GET_PATH);
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.get {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        if let Some(body) = self.get_http_request_body(0, body_size) {
+            match grpc_frame::parse(&body) {
+                Ok(frame) if !frame.compressed => match kv::GetRequest::decode(frame.payload) {
+                    Ok(req) => self.key = req.key,
+                    Err(e) => log::warn!("decode error: {}", e),
+                },
+                Ok(_) => log::warn!("compressed request, key-scoped rules will not match"),
+                Err(e) => log::warn!("frame error: {}", e),
+            }
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        if self.get {
+            // The masked body may differ in size.
+            self.set_http_response_header("content-length", None);
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        if !self.get {
+            return Action::Continue;
+        }
+        let Some(body_size) = self.response.body(body_size, end_of_stream) else {
+            return Action::Pause;
+        };
+
+        self.redact_response(body_size);
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        if let Some(body_size) = self.response.trailers() {
+            self.redact_response(body_size);
+        }
+        Action::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proxy_wasm::types::MapType;
+    use wasm_filter_sdk::{test_host, Direction};
+
+    use super::*;
+
+    fn get_response(value: &str) -> Vec<u8> {
+        let resp = kv::GetResponse {
+            value: value.to_string(),
+        };
+        grpc_frame::encode(&resp.encode_to_vec(), false)
+    }
+
+    #[test]
+    fn redacts_a_response_ended_by_trailers() {
+        let config = Config::parse(br#"{"rules": [{"keep_last": 2}]}"#).unwrap();
+        let mut mask = Mask {
+            context_id: 0,
+            config: Rc::new(config),
+            get: true,
+            key: "k".to_string(),
+            response: HeldResponse::default(),
+        };
+        let body = get_response("secret");
+        let (head, tail) = body.split_at(body.len() / 2);
+        for chunk in [head, tail] {
+            let size = test_host::push_body(Direction::Response, chunk);
+            assert_eq!(mask.on_http_response_body(size, false), Action::Pause);
+        }
+        test_host::set_header(MapType::HttpResponseTrailers, "grpc-status", "0");
+        assert_eq!(mask.on_http_response_trailers(1), Action::Continue);
+        assert_eq!(test_host::body(Direction::Response), get_response("****et"));
+    }
+}
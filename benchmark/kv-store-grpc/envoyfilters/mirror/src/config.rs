@@ -0,0 +1,57 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"cluster": "outbound|11000||kvstore-shadow.default.svc.cluster.local",
+///  "percent": 100, "methods": ["kv.KVService/get"]}
+/// ```
+///
+/// `cluster` is the Envoy cluster name of the shadow server. `methods` lists
+/// the mirrored gRPC `:path`s without the leading slash; empty mirrors every
+/// method. Requests with bodies over `max_body_bytes` are not mirrored.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub cluster: String,
+    /// `:authority` of mirrored requests; defaults to the original one.
+    pub authority: Option<String>,
+    pub percent: f64,
+    pub methods: Vec<String>,
+    pub timeout_ms: u64,
+    pub max_body_bytes: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            cluster: String::new(),
+            authority: None,
+            percent: 100.0,
+            methods: Vec::new(),
+            timeout_ms: 1000,
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.cluster.is_empty() {
+            return Err("cluster is required".to_string());
+        }
+        if !(0.0..=100.0).contains(&config.percent) {
+            return Err(format!(
+                "percent must be within 0..=100: {}",
+                config.percent
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn mirrors(&self, method: &str) -> bool {
+        !self.cluster.is_empty()
+            && (self.methods.is_empty() || self.methods.iter().any(|m| m == method))
+    }
+}
@@ -0,0 +1,179 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use wasm_filter_sdk::Rng;
+
+mod config;
+use config::Config;
+
+// Marks mirrored requests, so a filter on the shadow path never mirrors
+// them again.
+const SHADOW_HEADER: &str = "x-shadow";
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(MirrorRoot {
+            config: Rc::new(Config::default()),
+            rng: Rng::default(),
+        })
+    });
+}
+
+struct MirrorRoot {
+    config: Rc<Config>,
+    rng: Rng,
+}
+
+impl Context for MirrorRoot {}
+
+impl RootContext for MirrorRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        self.rng.seed(self.get_current_time());
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded mirror configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Mirror {
+            context_id,
+            config: self.config.clone(),
+            rng: self.rng.clone(),
+            headers: None,
+            body: Vec::new(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Sends a copy of each sampled request to a shadow cluster.
+///
+/// The body is copied chunk by chunk as it streams to the primary upstream,
+/// so mirroring adds no latency to the real request. The shadow response is
+/// only logged.
+struct Mirror {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    rng: Rng,
+    // Headers of the shadow request; None if this request is not mirrored.
+    headers: Option<Vec<(String, String)>>,
+    body: Vec<u8>,
+}
+
+impl Context for Mirror {
+    fn on_http_call_response(&mut self, token_id: u32, _: usize, _: usize, _: usize) {
+        let status = self.get_http_call_response_header(":status");
+        let grpc_status = self
+            .get_http_call_response_trailer("grpc-status")
+            .or_else(|| self.get_http_call_response_header("grpc-status"));
+        log::warn!(
+            "shadow call {} finished: status={:?} grpc-status={:?}",
+            token_id,
+            status,
+            grpc_status
+        );
+    }
+}
+
+impl Mirror {
+    fn dispatch(&mut self) {
+        let Some(headers) = self.headers.take() else {
+            return;
+        };
+        let body = std::mem::take(&mut self.body);
+        let headers: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        match self.dispatch_http_call(
+            &self.config.cluster,
+            headers,
+            Some(&body),
+            vec![],
+            Duration::from_millis(self.config.timeout_ms),
+        ) {
+            Ok(token) => log::warn!("mirrored request as shadow call {}", token),
+            Err(e) => log::warn!("failed to mirror request: {:?}", e),
+        }
+    }
+}
+
+impl HttpContext for Mirror {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        if self.get_http_request_header(SHADOW_HEADER).is_some() {
+            return Action::Continue;
+        }
+        let Some(path) = self.get_http_request_header(":path") else {
+            return Action::Continue;
+        };
+        if !self.config.mirrors(path.trim_start_matches('/')) || !self.rng.roll(self.config.percent)
+        {
+            return Action::Continue;
+        }
+
+        let mut headers: Vec<(String, String)> = self
+            .get_http_request_headers()
+            .into_iter()
+            .filter(|(name, _)| name != "content-length")
+            .collect();
+        if let Some(authority) = &self.config.authority {
+            for (name, value) in headers.iter_mut() {
+                if name == ":authority" {
+                    *value = authority.clone();
+                }
+            }
+        }
+        headers.push((SHADOW_HEADER.to_string(), "true".to_string()));
+        self.headers = Some(headers);
+        if end_of_stream {
+            self.dispatch();
+        }
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if self.headers.is_none() {
+            return Action::Continue;
+        }
+        if let Some(chunk) = self.get_http_request_body(0, body_size) {
+            if self.body.len() + chunk.len() > self.config.max_body_bytes {
+                log::warn!("request body too large to mirror");
+                self.headers = None;
+                self.body = Vec::new();
+                return Action::Continue;
+            }
+            self.body.extend_from_slice(&chunk);
+        }
+        if end_of_stream {
+            self.dispatch();
+        }
+        Action::Continue
+    }
+}
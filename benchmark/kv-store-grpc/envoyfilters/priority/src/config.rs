@@ -0,0 +1,99 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"header": "x-priority", "default": "normal",
+///  "rules": [{"method": "get", "priority": "high"},
+///            {"method": "set", "min_value_bytes": 65536, "priority": "low"}]}
+/// ```
+///
+/// Rules are evaluated in order for the `KVService` method (`get` or `set`)
+/// and the first match decides. A rule with `min_value_bytes` only matches
+/// sets whose value is at least that long, so those requests are held until
+/// their first message is decoded. The class is also published as filter
+/// state `wasm.kv.priority`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub header: String,
+    pub default: Priority,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    Normal,
+    Low,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    pub method: String,
+    pub min_value_bytes: Option<usize>,
+    pub priority: Priority,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            header: "x-priority".to_string(),
+            default: Priority::Normal,
+            rules: vec![
+                Rule {
+                    method: "get".to_string(),
+                    min_value_bytes: None,
+                    priority: Priority::High,
+                },
+                Rule {
+                    method: "set".to_string(),
+                    min_value_bytes: Some(64 * 1024),
+                    priority: Priority::Low,
+                },
+            ],
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.header.is_empty() {
+            return Err("header must not be empty".to_string());
+        }
+        for rule in &config.rules {
+            match (rule.method.as_str(), rule.min_value_bytes) {
+                ("get", None) | ("set", _) => {}
+                ("get", Some(_)) => return Err("min_value_bytes only applies to set".to_string()),
+                (method, _) => return Err(format!("unknown KVService method: {}", method)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Classifies a request. `value_len` is the set value length, once
+    /// known; returns None if a rule needs it first.
+    pub fn classify(&self, method: &str, value_len: Option<usize>) -> Option<Priority> {
+        for rule in self.rules.iter().filter(|r| r.method == method) {
+            match (rule.min_value_bytes, value_len) {
+                (None, _) => return Some(rule.priority),
+                (Some(_), None) => return None,
+                (Some(min), Some(len)) if len >= min => return Some(rule.priority),
+                (Some(_), Some(_)) => {}
+            }
+        }
+        Some(self.default)
+    }
+}
@@ -0,0 +1,148 @@
+use std::rc::Rc;
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::Decoder;
+use prost::Message;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+use config::{Config, Priority};
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(PriorityRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct PriorityRoot {
+    config: Rc<Config>,
+}
+
+impl Context for PriorityRoot {}
+
+impl RootContext for PriorityRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded priority configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Classify {
+            context_id,
+            config: self.config.clone(),
+            pending: false,
+            request: Decoder::new(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Classify {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // Set while the headers wait for the first set message.
+    pending: bool,
+    request: Decoder,
+}
+
+impl Context for Classify {}
+
+impl Classify {
+    fn mark(&mut self, priority: Priority) {
+        self.pending = false;
+        log::warn!("classified request as {}", priority.as_str());
+        self.set_http_request_header(&self.config.header, Some(priority.as_str()));
+        if let Err(e) =
+            hostcalls::set_property(vec!["kv.priority"], Some(priority.as_str().as_bytes()))
+        {
+            log::warn!("failed to set property kv.priority: {:?}", e);
+        }
+    }
+}
+
+impl HttpContext for Classify {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let method = match self.get_http_request_header(":path").as_deref() {
+            Some("/kv.KVService/get") => "get",
+            Some("/kv.KVService/set") => "set",
+            _ => return Action::Continue,
+        };
+        match self.config.classify(method, None) {
+            Some(priority) => {
+                self.mark(priority);
+                Action::Continue
+            }
+            None if end_of_stream => {
+                self.mark(self.config.default);
+                Action::Continue
+            }
+            None => {
+                // Hold the headers until the value length is known.
+                self.pending = true;
+                Action::Pause
+            }
+        }
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.pending {
+            return Action::Continue;
+        }
+
+        let read = self.request.received();
+        if body_size > read {
+            if let Some(chunk) = self.get_http_request_body(read, body_size - read) {
+                self.request.push(&chunk);
+            }
+        }
+        let value_len = match self.request.next() {
+            Some(Ok(message)) if !message.compressed => {
+                kv::SetRequest::decode(message.payload.as_slice())
+                    .map(|req| req.value.len())
+                    .ok()
+            }
+            // Compressed messages are classified by their compressed size.
+            Some(Ok(message)) => Some(message.payload.len()),
+            Some(Err(_)) => None,
+            None if end_of_stream => None,
+            None => return Action::Pause,
+        };
+        let priority = match value_len {
+            Some(len) => self
+                .config
+                .classify("set", Some(len))
+                .unwrap_or(self.config.default),
+            None => self.config.default,
+        };
+        self.mark(priority);
+        Action::Continue
+    }
+}
@@ -0,0 +1,65 @@
+//! Token bucket state kept in proxy-wasm shared data.
+//!
+//! The state is `[tokens (u64 LE)][last refill, ns since epoch (u64 LE)]`,
+//! with tokens counted in thousandths so slow refill rates do not round away.
+
+use crate::config::Limit;
+
+const MILLI: u64 = 1000;
+pub const ENCODED_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bucket {
+    tokens: u64,
+    last: u64,
+}
+
+impl Bucket {
+    pub fn full(limit: Limit, now: u64) -> Bucket {
+        Bucket {
+            tokens: limit.burst * MILLI,
+            last: now,
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Bucket> {
+        if bytes.len() != ENCODED_LEN {
+            return None;
+        }
+        Some(Bucket {
+            tokens: u64::from_le_bytes(bytes[..8].try_into().ok()?),
+            last: u64::from_le_bytes(bytes[8..].try_into().ok()?),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut buf = [0; ENCODED_LEN];
+        buf[..8].copy_from_slice(&self.tokens.to_le_bytes());
+        buf[8..].copy_from_slice(&self.last.to_le_bytes());
+        buf
+    }
+
+    /// Refills the bucket up to `now` and takes one token if available.
+    pub fn take(&mut self, limit: Limit, now: u64) -> bool {
+        let elapsed = now.saturating_sub(self.last) as u128;
+        // rate tokens/s == rate * MILLI thousandths per 1e9 ns
+        let per_second = limit.rate as u128 * MILLI as u128;
+        let refill = elapsed * per_second / 1_000_000_000;
+        let capacity = limit.burst * MILLI;
+        if self.tokens as u128 + refill >= capacity as u128 {
+            // A full bucket banks no time.
+            self.tokens = capacity;
+            self.last = now;
+        } else if refill > 0 {
+            self.tokens += refill as u64;
+            // Only by the time the credited thousandths account for, so the
+            // remainder still counts toward the next one.
+            self.last += (refill * 1_000_000_000 / per_second) as u64;
+        }
+        if self.tokens < MILLI {
+            return false;
+        }
+        self.tokens -= MILLI;
+        true
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use grpc_frame::control::Poll;
+use serde::Deserialize;
+
+/// The bucket of the methods without a limit of their own; not a valid
+/// method name, so it cannot clash with one.
+const DEFAULT_BUCKET: &str = "*";
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"rate": 1000, "burst": 1000, "methods": {"kv.KVService/set": {"rate": 200, "burst": 400}}}
+/// ```
+///
+/// `rate` is in requests per second and `burst` is the bucket capacity.
+/// `methods` gives methods, keyed by the gRPC `:path` without the leading
+/// slash, a bucket and limit of their own; all other methods share one
+/// bucket under the default limit, so clients cannot mint buckets.
+///
+/// With `"control": true` the root is the control singleton: it takes new
+/// limits from the `ratelimit.config` shared queue, and with `poll` set
+/// fetches them from an HTTP endpoint every `poll.interval_ms`, then
+/// publishes them to the workers (see `grpc_frame::control`).
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub rate: u64,
+    pub burst: u64,
+    pub methods: HashMap<String, Limit>,
+    pub control: bool,
+    pub poll: Option<Poll>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Limit {
+    pub rate: u64,
+    pub burst: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            rate: 1000,
+            burst: 1000,
+            methods: HashMap::new(),
+            control: false,
+            poll: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.burst == 0 {
+            return Err("burst must be at least 1".to_string());
+        }
+        if let Some((method, _)) = config.methods.iter().find(|(_, l)| l.burst == 0) {
+            return Err(format!("burst must be at least 1 for {}", method));
+        }
+        if let Some(poll) = &config.poll {
+            poll.validate()?;
+        }
+        Ok(config)
+    }
+
+    /// The bucket `method` takes from, named for the shared data key, and
+    /// its limit.
+    pub fn bucket(&self, method: &str) -> (&str, Limit) {
+        match self.methods.get_key_value(method) {
+            Some((method, limit)) => (method, *limit),
+            None => (
+                DEFAULT_BUCKET,
+                Limit {
+                    rate: self.rate,
+                    burst: self.burst,
+                },
+            ),
+        }
+    }
+}
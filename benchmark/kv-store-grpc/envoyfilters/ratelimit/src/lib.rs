@@ -0,0 +1,150 @@
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel, Status};
+
+use grpc_frame::control::{ConfigChannel, Watched};
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+
+mod bucket;
+mod config;
+use bucket::Bucket;
+use config::Config;
+
+// Attempts to update a bucket before giving up on a contended key.
+const CAS_RETRIES: usize = 8;
+const CHANNEL: ConfigChannel = ConfigChannel::new("ratelimit");
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(RateLimitRoot {
+            config: Watched::new(CHANNEL, Config::default()),
+        })
+    });
+}
+
+struct RateLimitRoot {
+    // Plugin configuration, replaced by updates from the control singleton.
+    config: Watched<Config>,
+}
+
+impl Context for RateLimitRoot {
+    fn on_http_call_response(&mut self, _: u32, _: usize, body_size: usize, _: usize) {
+        CHANNEL.poll_response(self, body_size, |update| Config::parse(update).map(drop));
+    }
+}
+
+impl RootContext for RateLimitRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded rate limit configuration: {:?}", config);
+                if config.control {
+                    let queue_id = CHANNEL.register(self);
+                    log::warn!("registered {} queue {}", CHANNEL.name(), queue_id);
+                    if let Some(poll) = &config.poll {
+                        self.set_tick_period(poll.interval());
+                    }
+                }
+                self.config.set(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn on_queue_ready(&mut self, queue_id: u32) {
+        CHANNEL.drain(self, queue_id, |update| Config::parse(update).map(drop));
+    }
+
+    fn on_tick(&mut self) {
+        let config = self.config.current();
+        if let Some(poll) = &config.poll {
+            CHANNEL.poll(self, poll);
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        if self.config.current().control {
+            return None;
+        }
+        Some(Box::new(RateLimit {
+            context_id,
+            config: self.config.get(self, Config::parse),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct RateLimit {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+}
+
+impl Context for RateLimit {}
+
+impl RateLimit {
+    /// Takes a token from the method's bucket in shared data. The bucket is
+    /// shared by all workers of the VM, so updates go through CAS.
+    fn acquire(&self, method: &str) -> bool {
+        let (bucket, limit) = self.config.bucket(method);
+        let key = format!("ratelimit.{}", bucket);
+        for _ in 0..CAS_RETRIES {
+            let now = self
+                .get_current_time()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64);
+            let (data, cas) = self.get_shared_data(&key);
+            let mut bucket = data
+                .as_deref()
+                .and_then(Bucket::decode)
+                .unwrap_or_else(|| Bucket::full(limit, now));
+            let allowed = bucket.take(limit, now);
+            match self.set_shared_data(&key, Some(&bucket.encode()), cas) {
+                Ok(()) => return allowed,
+                Err(Status::CasMismatch) => continue,
+                Err(e) => {
+                    log::warn!("failed to update bucket {}: {:?}", key, e);
+                    break;
+                }
+            }
+        }
+        // Fail open rather than reject traffic we could not account for.
+        true
+    }
+}
+
+impl HttpContext for RateLimit {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let Some(path) = self.get_http_request_header(":path") else {
+            return Action::Continue;
+        };
+        let method = path.trim_start_matches('/');
+        if self.acquire(method) {
+            return Action::Continue;
+        }
+
+        log::warn!("rate limit exceeded for {}", method);
+        self.send_grpc_error(
+            Code::ResourceExhausted,
+            &format!("rate limit exceeded for {}", method),
+        );
+        Action::Pause
+    }
+}
@@ -0,0 +1,39 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"header": "x-shard", "shards": 4}
+/// ```
+///
+/// Pair it with a route `hash_policy` on the same header, e.g.
+/// `hash_policy: [{header: {header_name: x-shard}}]`, so Envoy's ring-hash or
+/// Maglev load balancer pins each shard to one upstream host.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub header: String,
+    pub shards: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            header: "x-shard".to_string(),
+            shards: 4,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.shards == 0 {
+            return Err("shards must be at least 1".to_string());
+        }
+        if config.header.is_empty() || config.header.starts_with(':') {
+            return Err(format!("invalid header name: {:?}", config.header));
+        }
+        Ok(config)
+    }
+}
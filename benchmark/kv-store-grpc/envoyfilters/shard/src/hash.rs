@@ -0,0 +1,26 @@
+//! Key → shard mapping.
+//!
+//! Keys are hashed with 64-bit FNV-1a and mapped with jump consistent hash
+//! (Lamping & Veach), so growing the shard count only moves ~1/n of the keys.
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(key: &[u8]) -> u64 {
+    key.iter()
+        .fold(FNV_OFFSET, |hash, &b| (hash ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn jump(mut key: u64, buckets: u32) -> u32 {
+    let (mut b, mut j) = (-1i64, 0i64);
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as u32
+}
+
+pub fn shard(key: &str, shards: u32) -> u32 {
+    jump(fnv1a(key.as_bytes()), shards)
+}
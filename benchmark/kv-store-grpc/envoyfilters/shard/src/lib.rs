@@ -0,0 +1,128 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use prost::Message;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+mod hash;
+use config::Config;
+
+const GET_PATH: &str = "/kv.KVService/get";
+const SET_PATH: &str = "/kv.KVService/set";
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(ShardRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct ShardRoot {
+    config: Rc<Config>,
+}
+
+impl Context for ShardRoot {}
+
+impl RootContext for ShardRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded shard configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Shard {
+            context_id,
+            config: self.config.clone(),
+            path: None,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Shard {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // kv method path, if this is a keyed RPC.
+    path: Option<&'static str>,
+}
+
+impl Context for Shard {}
+
+impl HttpContext for Shard {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.path = match self.get_http_request_header(":path").as_deref() {
+            Some(GET_PATH) => Some(GET_PATH),
+            Some(SET_PATH) => Some(SET_PATH),
+            _ => None,
+        };
+        if self.path.is_some() && !end_of_stream {
+            // The routing header can only be added while the headers are
+            // still held back from the router.
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        let Some(path) = self.path else {
+            return Action::Continue;
+        };
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let Some(body) = self.get_http_request_body(0, body_size) else {
+            return Action::Continue;
+        };
+        let key = match grpc_frame::parse(&body) {
+            Ok(frame) if frame.compressed => {
+                log::warn!("compressed request, not sharding");
+                return Action::Continue;
+            }
+            Ok(frame) if path == GET_PATH => kv::GetRequest::decode(frame.payload).map(|r| r.key),
+            Ok(frame) => kv::SetRequest::decode(frame.payload).map(|r| r.key),
+            Err(e) => {
+                log::warn!("frame error: {}", e);
+                return Action::Continue;
+            }
+        };
+        match key {
+            Ok(key) => {
+                let shard = hash::shard(&key, self.config.shards);
+                log::warn!("key {} -> shard {}", key, shard);
+                self.set_http_request_header(&self.config.header, Some(&shard.to_string()));
+            }
+            Err(e) => log::warn!("decode error: {}", e),
+        }
+
+        Action::Continue
+    }
+}
@@ -0,0 +1,37 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"max_value_bytes": 65536, "max_response_value_bytes": 4096}
+/// ```
+///
+/// `SetRequest`s with a longer value are rejected with `RESOURCE_EXHAUSTED`
+/// before they reach the upstream. `GetResponse` values longer than
+/// `max_response_value_bytes` are truncated; leave it unset to forward
+/// responses unchanged.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub max_value_bytes: usize,
+    pub max_response_value_bytes: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_value_bytes: 64 * 1024,
+            max_response_value_bytes: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.max_value_bytes == 0 {
+            return Err("max_value_bytes must be at least 1".to_string());
+        }
+        Ok(config)
+    }
+}
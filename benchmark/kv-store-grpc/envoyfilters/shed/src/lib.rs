@@ -0,0 +1,293 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+use grpc_frame::Decoder;
+use prost::Message;
+use wasm_filter_sdk::HeldResponse;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+mod metrics;
+use config::Config;
+use metrics::Metrics;
+
+const GET_PATH: &str = "/kv.KVService/get";
+const SET_PATH: &str = "/kv.KVService/set";
+
+#[cfg_attr(not(test), no_mangle)]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(ShedRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+        })
+    });
+}
+
+struct ShedRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+}
+
+impl Context for ShedRoot {}
+
+impl RootContext for ShedRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded shed configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Shed {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            is_set: false,
+            truncate: None,
+            response: HeldResponse::default(),
+            request: Decoder::new(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Shed {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    is_set: bool,
+    // Value limit for the responses of a get, if they are truncated.
+    truncate: Option<usize>,
+    // Response body, held until the response completes.
+    response: HeldResponse,
+    // Decodes the set request as its body arrives, so an elephant write is
+    // shed as soon as its first message is complete.
+    request: Decoder,
+}
+
+impl Context for Shed {}
+
+/// Truncates `value` to at most `max` bytes, on a character boundary.
+fn truncate(value: &mut String, max: usize) {
+    let mut len = max;
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    value.truncate(len);
+}
+
+impl Shed {
+    /// Truncates the values of the buffered response body, `body_size`
+    /// bytes long.
+    fn truncate_response(&self, body_size: usize, max: usize) {
+        if let Some(body) = self.get_http_response_body(0, body_size) {
+            if let Some(new_body) = self.truncate_body(&body, max) {
+                self.set_http_response_body(0, body.len(), &new_body);
+            }
+        }
+    }
+
+    /// Truncates the `GetResponse` values of a buffered body. Returns None
+    /// if nothing changed.
+    fn truncate_body(&self, body: &[u8], max: usize) -> Option<Vec<u8>> {
+        let mut new_body = Vec::with_capacity(body.len());
+        let mut changed = false;
+        let mut frames = grpc_frame::frames(body);
+        for frame in frames.by_ref() {
+            let frame = frame.ok()?;
+            let resp = if frame.compressed {
+                None
+            } else {
+                kv::GetResponse::decode(frame.payload).ok()
+            };
+            match resp {
+                Some(mut resp) if resp.value.len() > max => {
+                    log::warn!("truncating value of {} bytes", resp.value.len());
+                    truncate(&mut resp.value, max);
+                    grpc_frame::encode_into(&mut new_body, &resp.encode_to_vec(), false);
+                    changed = true;
+                    if let Some(metrics) = &self.metrics {
+                        metrics.truncated();
+                    }
+                }
+                _ => grpc_frame::encode_into(&mut new_body, frame.payload, frame.compressed),
+            }
+        }
+        new_body.extend_from_slice(frames.remainder());
+        changed.then_some(new_body)
+    }
+}
+
+impl HttpContext for Shed {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        let path = self.get_http_request_header(":path");
+        self.is_set = path.as_deref() == Some(SET_PATH);
+        if path.as_deref() == Some(GET_PATH) {
+            self.truncate = self.config.max_response_value_bytes;
+        }
+        if self.is_set && !end_of_stream {
+            // Hold the headers so a shed write never reaches the upstream.
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if !self.is_set {
+            return Action::Continue;
+        }
+
+        let read = self.request.received();
+        if body_size > read {
+            if let Some(chunk) = self.get_http_request_body(read, body_size - read) {
+                self.request.push(&chunk);
+            }
+        }
+        while let Some(message) = self.request.next() {
+            let req = match message {
+                Ok(message) if message.compressed => {
+                    log::warn!("compressed request, not checked");
+                    continue;
+                }
+                Ok(message) => kv::SetRequest::decode(message.payload.as_slice()),
+                Err(e) => {
+                    // Malformed framing is left for the upstream to reject.
+                    log::warn!("frame error: {}", e);
+                    self.is_set = false;
+                    return Action::Continue;
+                }
+            };
+            let Ok(req) = req else {
+                continue;
+            };
+            if req.value.len() > self.config.max_value_bytes {
+                log::warn!(
+                    "shedding set of {} with a {} byte value",
+                    req.key,
+                    req.value.len()
+                );
+                if let Some(metrics) = &self.metrics {
+                    metrics.shed(req.value.len());
+                }
+                self.send_grpc_error(
+                    Code::ResourceExhausted,
+                    &format!(
+                        "value of {} bytes exceeds the limit of {}",
+                        req.value.len(),
+                        self.config.max_value_bytes
+                    ),
+                );
+                return Action::Pause;
+            }
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_headers");
+        if self.truncate.is_some() && !end_of_stream {
+            // The truncated body is shorter than the original.
+            self.set_http_response_header("content-length", None);
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_response_body");
+        let Some(max) = self.truncate else {
+            return Action::Continue;
+        };
+        let Some(body_size) = self.response.body(body_size, end_of_stream) else {
+            return Action::Pause;
+        };
+
+        self.truncate_response(body_size, max);
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        if let (Some(max), Some(body_size)) = (self.truncate, self.response.trailers()) {
+            self.truncate_response(body_size, max);
+        }
+        Action::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proxy_wasm::types::MapType;
+    use wasm_filter_sdk::{test_host, Direction};
+
+    use super::*;
+
+    fn get_response(value: &str) -> Vec<u8> {
+        let resp = kv::GetResponse {
+            value: value.to_string(),
+        };
+        grpc_frame::encode(&resp.encode_to_vec(), false)
+    }
+
+    #[test]
+    fn truncates_a_response_ended_by_trailers() {
+        let mut shed = Shed {
+            context_id: 0,
+            config: Rc::new(Config::default()),
+            metrics: None,
+            is_set: false,
+            truncate: Some(3),
+            response: HeldResponse::default(),
+            request: Decoder::new(),
+        };
+        let body = get_response("abcdef");
+        let (head, tail) = body.split_at(body.len() / 2);
+        for chunk in [head, tail] {
+            let size = test_host::push_body(Direction::Response, chunk);
+            assert_eq!(shed.on_http_response_body(size, false), Action::Pause);
+        }
+        test_host::set_header(MapType::HttpResponseTrailers, "grpc-status", "0");
+        assert_eq!(shed.on_http_response_trailers(1), Action::Continue);
+        assert_eq!(test_host::body(Direction::Response), get_response("abc"));
+    }
+}
@@ -0,0 +1,41 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.shed.requests`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Counter of rejected `SetRequest`s.
+    requests: u32,
+    /// Counter of value bytes in rejected requests.
+    bytes: u32,
+    /// Counter of truncated `GetResponse` values.
+    truncated: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            requests: hostcalls::define_metric(MetricType::Counter, "shed.requests")?,
+            bytes: hostcalls::define_metric(MetricType::Counter, "shed.bytes")?,
+            truncated: hostcalls::define_metric(MetricType::Counter, "shed.truncated")?,
+        })
+    }
+
+    pub fn shed(&self, value_bytes: usize) {
+        increment(self.requests, 1);
+        increment(self.bytes, value_bytes as i64);
+    }
+
+    pub fn truncated(&self) {
+        increment(self.truncated, 1);
+    }
+}
+
+fn increment(metric_id: u32, offset: i64) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, offset) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
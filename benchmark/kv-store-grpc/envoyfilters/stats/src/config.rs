@@ -0,0 +1,62 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// The same module runs in two roles. HTTP filters on the workers are
+/// configured as
+///
+/// ```json
+/// {"role": "worker", "vm_id": "vm.sentinel.stats", "queue": "stats"}
+/// ```
+///
+/// and the singleton WASM service that aggregates their records as
+///
+/// ```json
+/// {"role": "aggregator", "queue": "stats", "flush_ms": 5000,
+///  "cluster": "outbound|8080||stats-collector.default.svc.cluster.local", "path": "/stats"}
+/// ```
+///
+/// `vm_id` must match the `vm_id` of the singleton, which owns the queue.
+/// Without a `cluster` the aggregator only logs its summaries.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub role: Role,
+    pub vm_id: String,
+    pub queue: String,
+    pub flush_ms: u64,
+    pub cluster: Option<String>,
+    pub path: String,
+    pub timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Worker,
+    Aggregator,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            role: Role::Worker,
+            vm_id: "vm.sentinel.stats".to_string(),
+            queue: "stats".to_string(),
+            flush_ms: 5000,
+            cluster: None,
+            path: "/stats".to_string(),
+            timeout_ms: 1000,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.role == Role::Aggregator && config.flush_ms == 0 {
+            return Err("flush_ms must be at least 1".to_string());
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,252 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel, Status};
+
+use grpc_frame::status::Code;
+
+mod config;
+mod stats;
+use config::{Config, Role};
+use stats::{Record, Summary};
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(StatsRoot {
+            config: Rc::new(Config::default()),
+            queue: Rc::new(Cell::new(None)),
+            summary: Summary::default(),
+        })
+    });
+}
+
+fn epoch_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Either a worker root that hands out HTTP contexts, or the singleton
+/// service that owns the queue and aggregates what the workers send.
+struct StatsRoot {
+    config: Rc<Config>,
+    // Worker: the resolved queue, shared with the HTTP contexts.
+    // Aggregator: the registered queue.
+    queue: Rc<Cell<Option<u32>>>,
+    summary: Summary,
+}
+
+impl Context for StatsRoot {
+    fn on_http_call_response(&mut self, token_id: u32, _: usize, _: usize, _: usize) {
+        let status = self.get_http_call_response_header(":status");
+        log::warn!("stats push {} finished: status={:?}", token_id, status);
+    }
+}
+
+impl StatsRoot {
+    fn flush(&mut self) {
+        let now = epoch_ms(self.get_current_time());
+        let mut summary = std::mem::take(&mut self.summary);
+        self.summary.start_ms = now;
+        if summary.is_empty() {
+            return;
+        }
+        summary.end_ms = now;
+        let body = match serde_json::to_vec(&summary) {
+            Ok(body) => body,
+            Err(e) => {
+                log::warn!("failed to serialize stats: {}", e);
+                return;
+            }
+        };
+        log::info!("{}", String::from_utf8_lossy(&body));
+        let Some(cluster) = &self.config.cluster else {
+            return;
+        };
+        if let Err(e) = self.dispatch_http_call(
+            cluster,
+            vec![
+                (":method", "POST"),
+                (":path", &self.config.path),
+                (":authority", "stats-collector"),
+                ("content-type", "application/json"),
+            ],
+            Some(&body),
+            vec![],
+            Duration::from_millis(self.config.timeout_ms),
+        ) {
+            log::warn!("failed to push stats: {:?}", e);
+        }
+    }
+}
+
+impl RootContext for StatsRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        if let Some(bytes) = self.get_plugin_configuration() {
+            match Config::parse(&bytes) {
+                Ok(config) => {
+                    log::warn!("loaded stats configuration: {:?}", config);
+                    self.config = Rc::new(config);
+                }
+                Err(e) => {
+                    log::error!("invalid plugin configuration: {}", e);
+                    return false;
+                }
+            }
+        }
+        if self.config.role == Role::Aggregator {
+            let queue_id = self.register_shared_queue(&self.config.queue);
+            self.queue.set(Some(queue_id));
+            self.summary.start_ms = epoch_ms(self.get_current_time());
+            self.set_tick_period(Duration::from_millis(self.config.flush_ms));
+        }
+        true
+    }
+
+    fn on_queue_ready(&mut self, queue_id: u32) {
+        loop {
+            match self.dequeue_shared_queue(queue_id) {
+                Ok(Some(bytes)) => match serde_json::from_slice::<Record>(&bytes) {
+                    Ok(record) => self.summary.add(record),
+                    Err(e) => log::warn!("invalid stats record: {}", e),
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("failed to dequeue stats: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn on_tick(&mut self) {
+        self.flush();
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        if self.config.role != Role::Worker {
+            return None;
+        }
+        Some(Box::new(Stats {
+            context_id,
+            config: self.config.clone(),
+            queue: self.queue.clone(),
+            method: String::new(),
+            start: None,
+            status: None,
+            request_bytes: 0,
+            response_bytes: 0,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Records one stream and sends it to the aggregator when the stream ends.
+struct Stats {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    queue: Rc<Cell<Option<u32>>>,
+    method: String,
+    start: Option<SystemTime>,
+    status: Option<Code>,
+    request_bytes: u64,
+    response_bytes: u64,
+}
+
+impl Context for Stats {}
+
+impl Stats {
+    /// Enqueues a record. The queue is resolved lazily, since the singleton
+    /// may start after the workers, and again if it was restarted.
+    fn send(&self, record: &Record) {
+        let Ok(bytes) = serde_json::to_vec(record) else {
+            return;
+        };
+        for _ in 0..2 {
+            let queue_id = match self.queue.get() {
+                Some(queue_id) => queue_id,
+                None => match self.resolve_shared_queue(&self.config.vm_id, &self.config.queue) {
+                    Some(queue_id) => {
+                        self.queue.set(Some(queue_id));
+                        queue_id
+                    }
+                    None => {
+                        log::warn!("stats queue {} not registered yet", self.config.queue);
+                        return;
+                    }
+                },
+            };
+            match self.enqueue_shared_queue(queue_id, Some(&bytes)) {
+                Ok(()) => return,
+                Err(Status::NotFound) => self.queue.set(None),
+                Err(e) => {
+                    log::warn!("failed to enqueue stats: {:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl HttpContext for Stats {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        self.method = self
+            .get_http_request_header(":path")
+            .map(|path| path.trim_start_matches('/').to_string())
+            .unwrap_or_default();
+        self.start = Some(self.get_current_time());
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, _end_of_stream: bool) -> Action {
+        self.request_bytes += body_size as u64;
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, end_of_stream: bool) -> Action {
+        if end_of_stream {
+            self.status = self
+                .get_http_response_header("grpc-status")
+                .map(|status| Code::from_header(&status));
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_body(&mut self, body_size: usize, _end_of_stream: bool) -> Action {
+        self.response_bytes += body_size as u64;
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        self.status = self
+            .get_http_response_trailer("grpc-status")
+            .map(|status| Code::from_header(&status));
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        let Some(start) = self.start else {
+            return;
+        };
+        let latency = self
+            .get_current_time()
+            .duration_since(start)
+            .unwrap_or_default();
+        let record = Record {
+            method: std::mem::take(&mut self.method),
+            status: self.status.map(|status| status as i32),
+            latency_us: latency.as_micros() as u64,
+            request_bytes: self.request_bytes,
+            response_bytes: self.response_bytes,
+        };
+        self.send(&record);
+    }
+}
@@ -0,0 +1,53 @@
+//! Per-stream records sent over the shared queue and their aggregate.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// What one HTTP context reports when its stream ends.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    pub method: String,
+    /// Final `grpc-status`; None if the stream was reset.
+    pub status: Option<i32>,
+    pub latency_us: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+}
+
+/// Totals for one method since the last flush.
+#[derive(Debug, Default, Serialize)]
+pub struct MethodStats {
+    pub requests: u64,
+    /// Streams that ended with a non-OK status or were reset.
+    pub errors: u64,
+    pub latency_sum_us: u64,
+    pub latency_max_us: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+}
+
+/// Aggregate over every worker, keyed by method.
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    /// Start and end of the interval, in ms since epoch.
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub methods: BTreeMap<String, MethodStats>,
+}
+
+impl Summary {
+    pub fn add(&mut self, record: Record) {
+        let stats = self.methods.entry(record.method).or_default();
+        stats.requests += 1;
+        stats.errors += (record.status != Some(0)) as u64;
+        stats.latency_sum_us += record.latency_us;
+        stats.latency_max_us = stats.latency_max_us.max(record.latency_us);
+        stats.request_bytes += record.request_bytes;
+        stats.response_bytes += record.response_bytes;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.methods.is_empty()
+    }
+}
@@ -1,35 +1,180 @@
-use proxy_wasm::traits::{Context, HttpContext};
-use proxy_wasm::types::{Action, LogLevel};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
 
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::status::{Code, TrailerPolicy};
+use grpc_frame::summary::StreamSummary;
+use grpc_frame::{Decoder, Message as Frame};
+
+use prost::Message;
 pub mod kv {
     include!(concat!(env!("OUT_DIR"), "/kv.rs"));
 }
 
+mod metrics;
+use metrics::Metrics;
 
 #[no_mangle]
 pub fn _start() {
     proxy_wasm::set_log_level(LogLevel::Trace);
-    proxy_wasm::set_http_context(|context_id, _| -> Box<dyn HttpContext> {
-        Box::new(Stream { context_id })
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(StreamRoot { metrics: None })
     });
 }
 
+struct StreamRoot {
+    metrics: Option<Rc<Metrics>>,
+}
+
+impl Context for StreamRoot {}
+
+impl RootContext for StreamRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        true
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Stream {
+            context_id,
+            metrics: self.metrics.clone(),
+            is_get: false,
+            request: Inspector::default(),
+            response: Inspector::default(),
+            completed: Vec::new(),
+            summary: StreamSummary::default(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+/// Decodes one direction of a stream chunk by chunk.
+#[derive(Default)]
+struct Inspector {
+    decoder: Decoder,
+    // When the first byte of the message being assembled arrived, in µs.
+    started: Option<u64>,
+    messages: usize,
+}
+
+impl Inspector {
+    /// Feeds a chunk received at `now` and returns the messages it completed
+    /// with their assembly time.
+    fn push(&mut self, chunk: &[u8], now: u64) -> Vec<(Frame, u64)> {
+        if self.decoder.pending() == 0 && !chunk.is_empty() {
+            self.started = Some(now);
+        }
+        self.decoder.push(chunk);
+        let mut completed = Vec::new();
+        for message in self.decoder.by_ref() {
+            match message {
+                Ok(message) => {
+                    let assembly = now - self.started.unwrap_or(now);
+                    completed.push((message, assembly));
+                }
+                Err(e) => log::warn!("frame error: {}", e),
+            }
+            // Any bytes left in this chunk start the next message.
+            self.started = Some(now);
+        }
+        if self.decoder.pending() == 0 {
+            self.started = None;
+        }
+        self.messages += completed.len();
+        completed
+    }
+}
+
+/// Inspects every message as soon as its bytes are complete, without ever
+/// pausing the stream. Each body callback only sees the newly arrived chunk,
+/// since nothing is buffered; partial frames are carried across calls by the
+/// decoders.
 struct Stream {
     #[allow(unused)]
     context_id: u32,
+    // None if the host refused to define the metrics.
+    metrics: Option<Rc<Metrics>>,
+    is_get: bool,
+    request: Inspector,
+    response: Inspector,
+    // Completion time of each request message, in µs, to pair with the
+    // response message of the same index.
+    completed: Vec<u64>,
+    // Message counts and sizes per direction, logged from on_log.
+    summary: StreamSummary,
 }
 
 impl Context for Stream {}
 
+impl Stream {
+    fn now_us(&self) -> u64 {
+        self.get_current_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64)
+    }
+
+    fn describe(&self, message: &Frame, response: bool) -> String {
+        if message.compressed {
+            return format!("compressed, {} bytes", message.payload.len());
+        }
+        let payload = message.payload.as_slice();
+        let decoded =
+            match (response, self.is_get) {
+                (false, true) => kv::GetRequest::decode(payload).map(|m| format!("key {}", m.key)),
+                (false, false) => kv::SetRequest::decode(payload)
+                    .map(|m| format!("key {}, value.len(): {}", m.key, m.value.len())),
+                (true, true) => kv::GetResponse::decode(payload)
+                    .map(|m| format!("value.len(): {}", m.value.len())),
+                (true, false) => kv::SetResponse::decode(payload)
+                    .map(|m| format!("value.len(): {}", m.value.len())),
+            };
+        decoded.unwrap_or_else(|e| format!("decode error: {}", e))
+    }
+}
+
 impl HttpContext for Stream {
     fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
         log::warn!("executing on_http_request_headers");
+        self.is_get = self.get_http_request_header(":path").as_deref() == Some("/kv.KVService/get");
 
         Action::Continue
     }
 
     fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
-        log::warn!("executing on_http_request_body, body_size: {}, end_of_stream: {}", body_size, end_of_stream);
+        log::warn!(
+            "executing on_http_request_body, body_size: {}, end_of_stream: {}",
+            body_size,
+            end_of_stream
+        );
+        let now = self.now_us();
+        let chunk = self.get_http_request_body(0, body_size).unwrap_or_default();
+        for (message, assembly) in self.request.push(&chunk, now) {
+            log::warn!(
+                "request message {}: {}, assembled in {}us",
+                self.completed.len(),
+                self.describe(&message, false),
+                assembly
+            );
+            self.completed.push(now);
+            self.summary.request.record(message.payload.len());
+            if let Some(metrics) = &self.metrics {
+                metrics.message(false, assembly);
+            }
+        }
+        if end_of_stream && self.request.decoder.pending() > 0 {
+            log::warn!(
+                "request ends inside a frame, {} bytes pending",
+                self.request.decoder.pending()
+            );
+        }
 
         Action::Continue
     }
@@ -40,9 +185,75 @@ impl HttpContext for Stream {
         Action::Continue
     }
 
-    fn on_http_response_body(&mut self, _body_size: usize, end_of_stream: bool) -> Action {
-        log::warn!("executing on_http_response_body, body_size: {}, end_of_stream: {}", _body_size, end_of_stream);
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!(
+            "executing on_http_response_body, body_size: {}, end_of_stream: {}",
+            body_size,
+            end_of_stream
+        );
+        let now = self.now_us();
+        let index = self.response.messages;
+        let chunk = self
+            .get_http_response_body(0, body_size)
+            .unwrap_or_default();
+        for (i, (message, assembly)) in self.response.push(&chunk, now).into_iter().enumerate() {
+            // A unary response pairs with the last request message.
+            let sent = self
+                .completed
+                .get(index + i)
+                .or(self.completed.last())
+                .copied();
+            let rtt = sent.map(|sent| now.saturating_sub(sent));
+            self.summary.response.record(message.payload.len());
+            log::warn!(
+                "response message {}: {}, assembled in {}us, rtt {:?}us",
+                index + i,
+                self.describe(&message, true),
+                assembly,
+                rtt
+            );
+            if let Some(metrics) = &self.metrics {
+                metrics.message(true, assembly);
+                if let Some(rtt) = rtt {
+                    metrics.rtt(rtt);
+                }
+            }
+        }
 
         Action::Continue
     }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        log::warn!("executing on_http_response_trailers");
+        if self.response.decoder.pending() > 0 {
+            log::warn!(
+                "response ends inside a frame, {} bytes pending",
+                self.response.decoder.pending()
+            );
+        }
+        let Some(status) = self.get_http_response_trailer("grpc-status") else {
+            return Action::Continue;
+        };
+        let status = Code::from_header(&status);
+        let message = self.get_http_response_trailer("grpc-message");
+        // The default policy keeps the status and normalizes grpc-message.
+        let trailers = TrailerPolicy::default().apply(status, message.as_deref());
+        if status != Code::Ok {
+            log::warn!(
+                "response failed with {}: {:?}",
+                status.name(),
+                trailers.message
+            );
+        }
+
+        self.set_http_response_trailer("grpc-message", trailers.message.as_deref());
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        log::warn!("stream summary: {}", self.summary);
+        if let Some(metrics) = &self.metrics {
+            metrics.summary(&self.summary);
+        }
+    }
 }
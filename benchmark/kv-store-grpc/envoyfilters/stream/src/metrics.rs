@@ -0,0 +1,91 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.stream.message_rtt_us`.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+use grpc_frame::summary::StreamSummary;
+
+pub struct Metrics {
+    /// Counter of decoded messages, both directions.
+    messages: u32,
+    /// Histogram of the time from a request message's first byte to its last.
+    request_assembly_us: u32,
+    /// Histogram of the time from a response message's first byte to its last.
+    response_assembly_us: u32,
+    /// Histogram of the time from a request message completing to its
+    /// response message completing.
+    message_rtt_us: u32,
+    /// Histograms of messages per stream, by direction.
+    request_messages: u32,
+    response_messages: u32,
+    /// Histogram of message payload sizes, both directions.
+    message_bytes: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            messages: hostcalls::define_metric(MetricType::Counter, "stream.messages")?,
+            request_assembly_us: hostcalls::define_metric(
+                MetricType::Histogram,
+                "stream.request_assembly_us",
+            )?,
+            response_assembly_us: hostcalls::define_metric(
+                MetricType::Histogram,
+                "stream.response_assembly_us",
+            )?,
+            message_rtt_us: hostcalls::define_metric(
+                MetricType::Histogram,
+                "stream.message_rtt_us",
+            )?,
+            request_messages: hostcalls::define_metric(
+                MetricType::Histogram,
+                "stream.request_messages",
+            )?,
+            response_messages: hostcalls::define_metric(
+                MetricType::Histogram,
+                "stream.response_messages",
+            )?,
+            message_bytes: hostcalls::define_metric(MetricType::Histogram, "stream.message_bytes")?,
+        })
+    }
+
+    pub fn message(&self, response: bool, assembly_us: u64) {
+        if let Err(e) = hostcalls::increment_metric(self.messages, 1) {
+            log::warn!("failed to increment metric: {:?}", e);
+        }
+        let metric = if response {
+            self.response_assembly_us
+        } else {
+            self.request_assembly_us
+        };
+        record(metric, assembly_us);
+    }
+
+    pub fn rtt(&self, rtt_us: u64) {
+        record(self.message_rtt_us, rtt_us);
+    }
+
+    /// Records a finished stream. Message sizes are recorded per size class,
+    /// at the class's upper bound.
+    pub fn summary(&self, summary: &StreamSummary) {
+        record(self.request_messages, summary.request.messages);
+        record(self.response_messages, summary.response.messages);
+        for sizes in [&summary.request, &summary.response] {
+            for (bound, count) in sizes.histogram() {
+                for _ in 0..count {
+                    record(self.message_bytes, bound);
+                }
+            }
+        }
+    }
+}
+
+fn record(metric_id: u32, value: u64) {
+    if let Err(e) = hostcalls::record_metric(metric_id, value) {
+        log::warn!("failed to record metric: {:?}", e);
+    }
+}
@@ -0,0 +1,52 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"header": "x-tenant-id", "default_tenant": null, "strip_header": true}
+/// ```
+///
+/// Requests without the header use `default_tenant`, or are rejected with
+/// `UNAUTHENTICATED` if it is not set. Tenant IDs may only hold ASCII
+/// letters, digits, `-` and `_`, so one tenant cannot address another's
+/// keys through a crafted ID.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub header: String,
+    pub default_tenant: Option<String>,
+    /// Remove the tenant header before forwarding the request.
+    pub strip_header: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            header: "x-tenant-id".to_string(),
+            default_tenant: None,
+            strip_header: true,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if config.header.is_empty() {
+            return Err("header must not be empty".to_string());
+        }
+        if let Some(tenant) = &config.default_tenant {
+            if !valid_tenant(tenant) {
+                return Err(format!("invalid default_tenant: {}", tenant));
+            }
+        }
+        Ok(config)
+    }
+}
+
+pub fn valid_tenant(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
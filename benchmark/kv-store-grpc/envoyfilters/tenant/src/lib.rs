@@ -0,0 +1,183 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+use prost::Message;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+use config::{valid_tenant, Config};
+
+const GET_PATH: &str = "/kv.KVService/get";
+const SET_PATH: &str = "/kv.KVService/set";
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(TenantRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct TenantRoot {
+    config: Rc<Config>,
+}
+
+impl Context for TenantRoot {}
+
+impl RootContext for TenantRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded tenant configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Tenant {
+            context_id,
+            config: self.config.clone(),
+            method: Method::Other,
+            prefix: String::new(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Get,
+    Set,
+    Other,
+}
+
+/// Moves every kv request into its tenant's key space.
+struct Tenant {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    method: Method,
+    // `tenant/<id>/`
+    prefix: String,
+}
+
+impl Context for Tenant {}
+
+impl Tenant {
+    /// Prefixes the key of one request message and re-encodes it.
+    fn rewrite(&self, payload: &[u8]) -> Result<Vec<u8>, prost::DecodeError> {
+        match self.method {
+            Method::Get => {
+                let mut req = kv::GetRequest::decode(payload)?;
+                req.key.insert_str(0, &self.prefix);
+                Ok(req.encode_to_vec())
+            }
+            Method::Set => {
+                let mut req = kv::SetRequest::decode(payload)?;
+                req.key.insert_str(0, &self.prefix);
+                Ok(req.encode_to_vec())
+            }
+            Method::Other => Ok(payload.to_vec()),
+        }
+    }
+}
+
+impl HttpContext for Tenant {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.method = match self.get_http_request_header(":path").as_deref() {
+            Some(GET_PATH) => Method::Get,
+            Some(SET_PATH) => Method::Set,
+            _ => Method::Other,
+        };
+        if self.method == Method::Other {
+            return Action::Continue;
+        }
+
+        let tenant = self
+            .get_http_request_header(&self.config.header)
+            .or_else(|| self.config.default_tenant.clone());
+        let tenant = match tenant {
+            Some(tenant) if valid_tenant(&tenant) => tenant,
+            Some(tenant) => {
+                log::warn!("rejecting invalid tenant {:?}", tenant);
+                self.send_grpc_error(Code::InvalidArgument, "invalid tenant id");
+                return Action::Pause;
+            }
+            None => {
+                log::warn!("rejecting request without {}", self.config.header);
+                self.send_grpc_error(Code::Unauthenticated, "missing tenant id");
+                return Action::Pause;
+            }
+        };
+        self.prefix = format!("tenant/{}/", tenant);
+        if self.config.strip_header {
+            self.set_http_request_header(&self.config.header, None);
+        }
+        // The rewritten body is longer than the original.
+        self.set_http_request_header("content-length", None);
+        if !end_of_stream {
+            // Hold the headers so no request reaches the upstream unprefixed.
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if self.method == Method::Other {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let Some(body) = self.get_http_request_body(0, body_size) else {
+            return Action::Continue;
+        };
+        let mut new_body = Vec::with_capacity(body.len() + grpc_frame::HEADER_LEN);
+        for frame in grpc_frame::frames(&body) {
+            let rewritten = match frame {
+                // Fail closed: a key we cannot prefix would escape the tenant.
+                Ok(frame) if frame.compressed => {
+                    Err("compressed requests are not supported".to_string())
+                }
+                Ok(frame) => self.rewrite(frame.payload).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match rewritten {
+                Ok(payload) => grpc_frame::encode_into(&mut new_body, &payload, false),
+                Err(e) => {
+                    log::warn!("rejecting request: {}", e);
+                    self.send_grpc_error(Code::InvalidArgument, &e);
+                    return Action::Pause;
+                }
+            }
+        }
+        self.set_http_request_body(0, body.len(), &new_body);
+
+        Action::Continue
+    }
+}
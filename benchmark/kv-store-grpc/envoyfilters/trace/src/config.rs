@@ -0,0 +1,42 @@
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"sample_percent": 10, "respect_parent": true, "tracestate_key": "arpc"}
+/// ```
+///
+/// New traces are sampled with probability `sample_percent`, decided from the
+/// trace ID so every hop agrees. With `respect_parent`, an incoming
+/// `traceparent` keeps its own sampled flag. `tracestate_key`, when set, adds
+/// this hop's span ID to `tracestate`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub sample_percent: f64,
+    pub respect_parent: bool,
+    pub tracestate_key: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sample_percent: 100.0,
+            respect_parent: true,
+            tracestate_key: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if !(0.0..=100.0).contains(&config.sample_percent) {
+            return Err(format!(
+                "sample_percent must be within 0..=100: {}",
+                config.sample_percent
+            ));
+        }
+        Ok(config)
+    }
+}
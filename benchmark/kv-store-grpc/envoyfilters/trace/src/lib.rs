@@ -0,0 +1,196 @@
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::trace::{self, TraceParent, SAMPLED};
+use wasm_filter_sdk::Rng;
+
+mod config;
+mod metrics;
+use config::Config;
+use metrics::Metrics;
+
+/// Logs with the stream's trace ID in front, so log lines from every hop of a
+/// request can be joined.
+macro_rules! trace_log {
+    ($ctx:expr, $($arg:tt)+) => {
+        log::warn!("[trace {}] {}", $ctx.trace_id, format_args!($($arg)+))
+    };
+}
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(TraceRoot {
+            config: Rc::new(Config::default()),
+            metrics: None,
+            rng: Rng::default(),
+        })
+    });
+}
+
+struct TraceRoot {
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+    rng: Rng,
+}
+
+impl Context for TraceRoot {}
+
+impl RootContext for TraceRoot {
+    fn on_vm_start(&mut self, _: usize) -> bool {
+        match Metrics::define() {
+            Ok(metrics) => self.metrics = Some(Rc::new(metrics)),
+            Err(e) => log::error!("failed to define metrics: {:?}", e),
+        }
+        self.rng.seed(self.get_current_time());
+        true
+    }
+
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded trace configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Trace {
+            context_id,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            rng: self.rng.clone(),
+            trace_id: "-".to_string(),
+            sampled: false,
+            start: None,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+struct Trace {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    metrics: Option<Rc<Metrics>>,
+    rng: Rng,
+    // Hex trace ID, "-" until the request headers arrive.
+    trace_id: String,
+    sampled: bool,
+    start: Option<SystemTime>,
+}
+
+impl Context for Trace {}
+
+impl Trace {
+    /// A random non-zero span ID.
+    fn span_id(&self) -> [u8; 8] {
+        (self.rng.next_u64() | 1).to_be_bytes()
+    }
+
+    /// Starts a trace for a request without a valid `traceparent`.
+    fn new_trace(&self) -> TraceParent {
+        let mut trace_id = [0; 16];
+        trace_id[..8].copy_from_slice(&self.rng.next_u64().to_be_bytes());
+        trace_id[8..].copy_from_slice(&(self.rng.next_u64() | 1).to_be_bytes());
+        let sampled = trace::sample(&trace_id, self.config.sample_percent);
+        TraceParent {
+            trace_id,
+            parent_id: self.span_id(),
+            flags: if sampled { SAMPLED } else { 0 },
+        }
+    }
+}
+
+impl HttpContext for Trace {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, _end_of_stream: bool) -> Action {
+        let incoming = self
+            .get_http_request_header("traceparent")
+            .and_then(|value| TraceParent::parse(&value));
+        let propagated = incoming.is_some();
+        let mut parent = match incoming {
+            Some(parent) => parent.child(self.span_id()),
+            None => self.new_trace(),
+        };
+        if propagated && !self.config.respect_parent {
+            let sampled = trace::sample(&parent.trace_id, self.config.sample_percent);
+            parent.flags = (parent.flags & !SAMPLED) | if sampled { SAMPLED } else { 0 };
+        }
+        self.trace_id = parent.trace_id_hex();
+        self.sampled = parent.sampled();
+        self.start = Some(self.get_current_time());
+        trace_log!(
+            self,
+            "executing on_http_request_headers: {} span={} sampled={}",
+            if propagated {
+                "propagated"
+            } else {
+                "generated"
+            },
+            parent.parent_id_hex(),
+            self.sampled
+        );
+
+        self.set_http_request_header("traceparent", Some(&parent.to_header()));
+        if let Some(key) = &self.config.tracestate_key {
+            let tracestate = self.get_http_request_header("tracestate");
+            let tracestate =
+                trace::update_tracestate(tracestate.as_deref(), key, &parent.parent_id_hex());
+            self.set_http_request_header("tracestate", Some(&tracestate));
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.started(propagated, self.sampled);
+        }
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
+        trace_log!(self, "executing on_http_response_headers");
+        Action::Continue
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        let status = self.get_http_response_trailer("grpc-status");
+        trace_log!(
+            self,
+            "executing on_http_response_trailers: grpc-status={:?}",
+            status
+        );
+        Action::Continue
+    }
+
+    fn on_log(&mut self) {
+        let Some(start) = self.start else {
+            return;
+        };
+        if !self.sampled {
+            return;
+        }
+        let latency_us = self
+            .get_current_time()
+            .duration_since(start)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        trace_log!(self, "stream finished in {}us", latency_us);
+        if let Some(metrics) = &self.metrics {
+            metrics.latency(latency_us);
+        }
+    }
+}
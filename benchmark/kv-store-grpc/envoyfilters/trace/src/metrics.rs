@@ -0,0 +1,53 @@
+//! Stats exported through Envoy's `/stats` endpoint.
+//!
+//! Envoy prefixes custom WASM metrics with `wasmcustom.`, so these show up as
+//! e.g. `wasmcustom.trace.sampled`. Metric names cannot carry trace IDs, so
+//! the latency of each sampled stream is also logged with its trace ID.
+
+use proxy_wasm::hostcalls;
+use proxy_wasm::types::{MetricType, Status};
+
+pub struct Metrics {
+    /// Counter of requests that started a new trace.
+    generated: u32,
+    /// Counter of requests that continued an incoming trace.
+    propagated: u32,
+    /// Counter of sampled requests.
+    sampled: u32,
+    /// Histogram of sampled stream latencies, in µs.
+    latency_us: u32,
+}
+
+impl Metrics {
+    pub fn define() -> Result<Metrics, Status> {
+        Ok(Metrics {
+            generated: hostcalls::define_metric(MetricType::Counter, "trace.generated")?,
+            propagated: hostcalls::define_metric(MetricType::Counter, "trace.propagated")?,
+            sampled: hostcalls::define_metric(MetricType::Counter, "trace.sampled")?,
+            latency_us: hostcalls::define_metric(MetricType::Histogram, "trace.latency_us")?,
+        })
+    }
+
+    pub fn started(&self, propagated: bool, sampled: bool) {
+        increment(if propagated {
+            self.propagated
+        } else {
+            self.generated
+        });
+        if sampled {
+            increment(self.sampled);
+        }
+    }
+
+    pub fn latency(&self, latency_us: u64) {
+        if let Err(e) = hostcalls::record_metric(self.latency_us, latency_us) {
+            log::warn!("failed to record metric: {:?}", e);
+        }
+    }
+}
+
+fn increment(metric_id: u32) {
+    if let Err(e) = hostcalls::increment_metric(metric_id, 1) {
+        log::warn!("failed to increment metric: {:?}", e);
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::kv;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// ```json
+/// {"max_key_length": 256, "max_value_bytes": 1048576,
+///  "required": {"get": ["key"], "set": ["key", "value"]}}
+/// ```
+///
+/// `required` lists, per `KVService` method, the request fields that must be
+/// non-empty. Lengths are in bytes of the UTF-8 encoding.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub max_key_length: usize,
+    pub max_value_bytes: usize,
+    pub required: HashMap<String, Vec<String>>,
+}
+
+const FIELDS: [(&str, &[&str]); 2] = [("get", &["key"]), ("set", &["key", "value"])];
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_key_length: 256,
+            max_value_bytes: 1024 * 1024,
+            required: FIELDS
+                .iter()
+                .map(|(method, _)| (method.to_string(), vec!["key".to_string()]))
+                .collect(),
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        for (method, fields) in &config.required {
+            let (_, known) = FIELDS
+                .iter()
+                .find(|(name, _)| name == method)
+                .ok_or_else(|| format!("unknown method: {}", method))?;
+            if let Some(field) = fields.iter().find(|f| !known.contains(&f.as_str())) {
+                return Err(format!("unknown field for {}: {}", method, field));
+            }
+        }
+        Ok(config)
+    }
+
+    fn requires(&self, method: &str, field: &str) -> bool {
+        self.required
+            .get(method)
+            .is_some_and(|fields| fields.iter().any(|f| f == field))
+    }
+
+    fn check_key(&self, method: &str, key: &str) -> Result<(), String> {
+        if key.is_empty() && self.requires(method, "key") {
+            return Err("key must not be empty".to_string());
+        }
+        if key.len() > self.max_key_length {
+            return Err(format!(
+                "key is {} bytes, limit is {}",
+                key.len(),
+                self.max_key_length
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_get(&self, req: &kv::GetRequest) -> Result<(), String> {
+        self.check_key("get", &req.key)
+    }
+
+    pub fn check_set(&self, req: &kv::SetRequest) -> Result<(), String> {
+        self.check_key("set", &req.key)?;
+        if req.value.is_empty() && self.requires("set", "value") {
+            return Err("value must not be empty".to_string());
+        }
+        if req.value.len() > self.max_value_bytes {
+            return Err(format!(
+                "value is {} bytes, limit is {}",
+                req.value.len(),
+                self.max_value_bytes
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, HttpContext, RootContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use grpc_frame::reply::LocalReply;
+use grpc_frame::status::Code;
+use prost::Message;
+pub mod kv {
+    include!(concat!(env!("OUT_DIR"), "/kv.rs"));
+}
+
+mod config;
+use config::Config;
+
+const GET_PATH: &str = "/kv.KVService/get";
+const SET_PATH: &str = "/kv.KVService/set";
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(ValidateRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct ValidateRoot {
+    config: Rc<Config>,
+}
+
+impl Context for ValidateRoot {}
+
+impl RootContext for ValidateRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                log::warn!("loaded validation configuration: {:?}", config);
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(Validate {
+            context_id,
+            config: self.config.clone(),
+            method: Method::Other,
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Get,
+    Set,
+    Other,
+}
+
+struct Validate {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    method: Method,
+}
+
+impl Context for Validate {}
+
+impl Validate {
+    /// Validates one request message.
+    fn check(&self, payload: &[u8]) -> Result<(), String> {
+        match self.method {
+            Method::Get => {
+                let req = kv::GetRequest::decode(payload).map_err(|e| e.to_string())?;
+                self.config.check_get(&req)
+            }
+            Method::Set => {
+                let req = kv::SetRequest::decode(payload).map_err(|e| e.to_string())?;
+                self.config.check_set(&req)
+            }
+            Method::Other => Ok(()),
+        }
+    }
+}
+
+impl HttpContext for Validate {
+    fn on_http_request_headers(&mut self, _num_of_headers: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_headers");
+        self.method = match self.get_http_request_header(":path").as_deref() {
+            Some(GET_PATH) => Method::Get,
+            Some(SET_PATH) => Method::Set,
+            _ => Method::Other,
+        };
+        if self.method != Method::Other && !end_of_stream {
+            // Hold the headers so an invalid request never reaches the upstream.
+            return Action::Pause;
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        log::warn!("executing on_http_request_body");
+        if self.method == Method::Other {
+            return Action::Continue;
+        }
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let Some(body) = self.get_http_request_body(0, body_size) else {
+            return Action::Continue;
+        };
+        for frame in grpc_frame::frames(&body) {
+            let result = match frame {
+                Ok(frame) if frame.compressed => {
+                    log::warn!("compressed request, skipping validation");
+                    Ok(())
+                }
+                Ok(frame) => self.check(frame.payload),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
+                log::warn!("rejecting invalid request: {}", e);
+                self.send_grpc_error(Code::InvalidArgument, &e);
+                return Action::Pause;
+            }
+        }
+
+        Action::Continue
+    }
+}
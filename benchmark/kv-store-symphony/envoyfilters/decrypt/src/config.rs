@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use symphony_wire::crypto::{Keys, DEFAULT_PRIVATE_KEY, DEFAULT_PUBLIC_KEY};
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// Keys are hex-encoded AES-256 keys and default to the development keys
+/// built into the Go transport. `rules` rewrite the decrypted message before
+/// it is re-encrypted:
+///
+/// ```json
+/// {
+///   "public_key": "27e1fa17...",
+///   "private_key": "9b530067...",
+///   "services": {"1": {"name": "KVService", "methods": {"1": "Get", "2": "Set"}}},
+///   "rules": [{"match": "secret", "replace": "XXXXXX"}]
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub public_key: String,
+    pub private_key: String,
+    pub services: HashMap<u32, Service>,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Service {
+    pub name: String,
+    #[serde(default)]
+    pub methods: HashMap<u32, String>,
+}
+
+/// Replaces every occurrence of `match` in a decrypted message.
+///
+/// Symphony messages address fields by offset, so a replacement must have
+/// the same length as the bytes it replaces.
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    #[serde(rename = "match")]
+    pub pattern: String,
+    pub replace: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            public_key: to_hex(&DEFAULT_PUBLIC_KEY),
+            private_key: to_hex(&DEFAULT_PRIVATE_KEY),
+            services: HashMap::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("odd-length hex key: {}", s.len()));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| format!("invalid hex key at offset {}", i))
+        })
+        .collect()
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        let config: Config = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        for rule in &config.rules {
+            if rule.pattern.is_empty() {
+                return Err("empty match in rule".to_string());
+            }
+            if rule.pattern.len() != rule.replace.len() {
+                return Err(format!(
+                    "replacement for {:?} must be {} bytes long",
+                    rule.pattern,
+                    rule.pattern.len()
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn keys(&self) -> Result<Keys, String> {
+        Keys::new(&from_hex(&self.public_key)?, &from_hex(&self.private_key)?)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Renders `<service>/<method>`, falling back to the numeric IDs.
+    pub fn method_name(&self, service_id: u32, method_id: u32) -> String {
+        match self.services.get(&service_id) {
+            Some(service) => match service.methods.get(&method_id) {
+                Some(method) => format!("{}/{}", service.name, method),
+                None => format!("{}/{}", service.name, method_id),
+            },
+            None => format!("{}/{}", service_id, method_id),
+        }
+    }
+
+    /// Applies the rules to a decrypted message; returns the number of
+    /// replacements. The 13-byte header is never rewritten.
+    pub fn apply(&self, message: &mut [u8]) -> usize {
+        let mut count = 0;
+        for rule in &self.rules {
+            let (pattern, replace) = (rule.pattern.as_bytes(), rule.replace.as_bytes());
+            let mut i = symphony_wire::MESSAGE_HEADER_LEN;
+            while i + pattern.len() <= message.len() {
+                if &message[i..i + pattern.len()] == pattern {
+                    message[i..i + pattern.len()].copy_from_slice(replace);
+                    count += 1;
+                    i += pattern.len();
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        count
+    }
+}
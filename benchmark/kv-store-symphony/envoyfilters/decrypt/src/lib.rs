@@ -0,0 +1,230 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, RootContext, StreamContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use symphony_wire::crypto::{self, Keys, NONCE_LEN};
+use symphony_wire::{DataPacket, Error, Packet, DATA_HEADER_LEN};
+
+mod config;
+use config::Config;
+
+// Largest chunk held while waiting for a packet boundary. aRPC packets are
+// sized for one UDP datagram, so anything larger means we lost the boundary.
+const MAX_PENDING: usize = 64 * 1024;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(DecryptRoot {
+            config: Rc::new(Config::default()),
+            keys: Rc::new(Keys::default()),
+        })
+    });
+}
+
+struct DecryptRoot {
+    config: Rc<Config>,
+    keys: Rc<Keys>,
+}
+
+impl Context for DecryptRoot {}
+
+impl RootContext for DecryptRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        let loaded = Config::parse(&bytes).and_then(|config| Ok((config.keys()?, config)));
+        match loaded {
+            Ok((keys, config)) => {
+                log::warn!(
+                    "loaded decrypt configuration with {} rules",
+                    config.rules.len()
+                );
+                self.config = Rc::new(config);
+                self.keys = Rc::new(keys);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_stream_context(&self, context_id: u32) -> Option<Box<dyn StreamContext>> {
+        Some(Box::new(Decrypt {
+            context_id,
+            config: self.config.clone(),
+            keys: self.keys.clone(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::StreamContext)
+    }
+}
+
+/// Network filter that decrypts aRPC messages, rewrites them with the
+/// configured rules and re-encrypts them before forwarding.
+///
+/// Only messages that fit in one packet are rewritten; fragments of larger
+/// messages are forwarded untouched. Re-encryption draws fresh nonces from
+/// the host's random source; sealing the rewritten message under the
+/// sender's nonces would reuse them under the same key. Without randomness
+/// the message is forwarded unchanged.
+struct Decrypt {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    keys: Rc<Keys>,
+}
+
+impl Context for Decrypt {}
+
+/// Decrypts, rewrites and re-encrypts the message in a single-packet RPC.
+/// Returns None if the payload should be forwarded unchanged.
+fn rewrite(config: &Config, keys: &Keys, direction: &str, packet: &DataPacket) -> Option<Vec<u8>> {
+    if !packet.is_first() || packet.more_fragments || packet.total_packets != 1 {
+        log::warn!(
+            "{}: rpc_id={} is fragmented, forwarding unchanged",
+            direction,
+            packet.rpc_id
+        );
+        return None;
+    }
+    let mut message = match crypto::decrypt(keys, packet.payload) {
+        Ok(message) => message,
+        Err(e) => {
+            log::warn!(
+                "{}: rpc_id={} decrypt failed: {}",
+                direction,
+                packet.rpc_id,
+                e
+            );
+            return None;
+        }
+    };
+    let header = symphony_wire::parse_message_header(&message).ok()?;
+    let public_len = header.offset_to_private as usize - symphony_wire::MESSAGE_HEADER_LEN;
+    log::warn!(
+        "{}: {} rpc_id={} method={} public_len={} private_len={}",
+        direction,
+        packet.kind.name(),
+        packet.rpc_id,
+        config.method_name(header.service_id, header.method_id),
+        public_len,
+        message.len() - header.offset_to_private as usize
+    );
+
+    let replaced = config.apply(&mut message);
+    if replaced == 0 {
+        return None;
+    }
+    log::warn!(
+        "{}: rpc_id={} applied {} replacements",
+        direction,
+        packet.rpc_id,
+        replaced
+    );
+    let mut public = [0; NONCE_LEN];
+    let mut private = [0; NONCE_LEN];
+    let drawn = getrandom::getrandom(&mut public).and_then(|_| getrandom::getrandom(&mut private));
+    if let Err(e) = drawn {
+        log::warn!(
+            "{}: rpc_id={} no random nonce, forwarding unchanged: {}",
+            direction,
+            packet.rpc_id,
+            e
+        );
+        return None;
+    }
+    crypto::encrypt(keys, &message, &public, &private).ok()
+}
+
+/// Rewrites every packet in `data`. Returns None if `data` does not end on a
+/// packet boundary.
+fn process(config: &Config, keys: &Keys, direction: &str, data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut offset = 0;
+    while offset < data.len() {
+        let raw = &data[offset..];
+        let packet = match symphony_wire::parse(raw) {
+            Ok(packet) => packet,
+            Err(Error::Incomplete { .. }) => return None,
+            Err(e) => {
+                log::warn!("{}: {}, forwarding {} bytes", direction, e, raw.len());
+                out.extend_from_slice(raw);
+                break;
+            }
+        };
+        let len = packet.encoded_len();
+        match packet {
+            Packet::Data(p) => match rewrite(config, keys, direction, &p) {
+                Some(payload) => {
+                    // Same header with the new payload length, then the
+                    // extension block, if any, as it was.
+                    let header_len = len - p.payload.len();
+                    out.extend_from_slice(&raw[..DATA_HEADER_LEN - 4]);
+                    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                    out.extend_from_slice(&raw[DATA_HEADER_LEN..header_len]);
+                    out.extend_from_slice(&payload);
+                }
+                None => out.extend_from_slice(&raw[..len]),
+            },
+            Packet::Error(_) => out.extend_from_slice(&raw[..len]),
+        }
+        offset += len;
+    }
+    Some(out)
+}
+
+impl Decrypt {
+    /// Shared handling for both directions. Pausing keeps the data in Envoy's
+    /// connection buffer, so the next call sees it again with more bytes.
+    fn on_data(
+        &self,
+        direction: &str,
+        data: Option<Vec<u8>>,
+        end_of_stream: bool,
+    ) -> (Action, Option<Vec<u8>>) {
+        let Some(data) = data else {
+            return (Action::Continue, None);
+        };
+        match process(&self.config, &self.keys, direction, &data) {
+            Some(out) => (Action::Continue, Some(out)),
+            None if end_of_stream || data.len() > MAX_PENDING => {
+                log::warn!(
+                    "{}: no packet boundary in {} bytes, forwarding",
+                    direction,
+                    data.len()
+                );
+                (Action::Continue, None)
+            }
+            None => (Action::Pause, None),
+        }
+    }
+}
+
+impl StreamContext for Decrypt {
+    fn on_downstream_data(&mut self, data_size: usize, end_of_stream: bool) -> Action {
+        let data = self.get_downstream_data(0, data_size);
+        let (action, out) = self.on_data("downstream", data, end_of_stream);
+        if let Some(out) = out {
+            self.set_downstream_data(0, data_size, &out);
+        }
+        action
+    }
+
+    fn on_upstream_data(&mut self, data_size: usize, end_of_stream: bool) -> Action {
+        let data = self.get_upstream_data(0, data_size);
+        let (action, out) = self.on_data("upstream", data, end_of_stream);
+        if let Some(out) = out {
+            self.set_upstream_data(0, data_size, &out);
+        }
+        action
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// Plugin configuration, passed as JSON through the Envoy WASM `configuration`.
+///
+/// Maps the numeric IDs in the Symphony header back to the names from the
+/// generated `*_arpc.syn.go` stubs, so logs read like gRPC method paths:
+///
+/// ```json
+/// {"services": {"1": {"name": "KVService", "methods": {"1": "Get", "2": "Set"}}}}
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub services: HashMap<u32, Service>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Service {
+    pub name: String,
+    #[serde(default)]
+    pub methods: HashMap<u32, String>,
+}
+
+impl Config {
+    pub fn parse(bytes: &[u8]) -> Result<Config, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+
+    /// Renders `<service>/<method>`, falling back to the numeric IDs.
+    pub fn method_name(&self, service_id: u32, method_id: u32) -> String {
+        match self.services.get(&service_id) {
+            Some(service) => match service.methods.get(&method_id) {
+                Some(method) => format!("{}/{}", service.name, method),
+                None => format!("{}/{}", service.name, method_id),
+            },
+            None => format!("{}/{}", service_id, method_id),
+        }
+    }
+}
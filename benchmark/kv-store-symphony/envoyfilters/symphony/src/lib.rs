@@ -0,0 +1,154 @@
+use std::rc::Rc;
+
+use proxy_wasm::traits::{Context, RootContext, StreamContext};
+use proxy_wasm::types::{Action, ContextType, LogLevel};
+
+use symphony_wire::{Error, Packet};
+
+mod config;
+use config::Config;
+
+// Bytes kept while waiting for the rest of a packet. aRPC packets are sized
+// for one UDP datagram, so anything larger means we lost the packet boundary.
+const MAX_PENDING: usize = 64 * 1024;
+
+#[no_mangle]
+pub fn _start() {
+    proxy_wasm::set_log_level(LogLevel::Trace);
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(SymphonyRoot {
+            config: Rc::new(Config::default()),
+        })
+    });
+}
+
+struct SymphonyRoot {
+    config: Rc<Config>,
+}
+
+impl Context for SymphonyRoot {}
+
+impl RootContext for SymphonyRoot {
+    fn on_configure(&mut self, _: usize) -> bool {
+        log::warn!("executing on_configure");
+        let Some(bytes) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match Config::parse(&bytes) {
+            Ok(config) => {
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                log::error!("invalid plugin configuration: {}", e);
+                false
+            }
+        }
+    }
+
+    fn create_stream_context(&self, context_id: u32) -> Option<Box<dyn StreamContext>> {
+        Some(Box::new(Symphony {
+            context_id,
+            config: self.config.clone(),
+            downstream: Vec::new(),
+            upstream: Vec::new(),
+        }))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::StreamContext)
+    }
+}
+
+/// Network filter that logs every aRPC packet on the connection.
+///
+/// Envoy cannot run WASM on UDP listeners, so this applies wherever aRPC
+/// packets travel over a stream (TCP transport or a tunnel); packets are
+/// self-delimiting, so a chunk may hold several of them or end mid-packet.
+struct Symphony {
+    #[allow(unused)]
+    context_id: u32,
+    config: Rc<Config>,
+    // Unparsed tail of the data seen in each direction.
+    downstream: Vec<u8>,
+    upstream: Vec<u8>,
+}
+
+impl Context for Symphony {}
+
+/// Logs the complete packets in `pending` and drops them from the buffer.
+fn inspect(config: &Config, direction: &str, pending: &mut Vec<u8>) {
+    let mut offset = 0;
+    while offset < pending.len() {
+        let packet = match symphony_wire::parse(&pending[offset..]) {
+            Ok(packet) => packet,
+            Err(Error::Incomplete { .. }) => break,
+            Err(e) => {
+                log::warn!("{}: {}, dropping {} bytes", direction, e, pending.len() - offset);
+                offset = pending.len();
+                break;
+            }
+        };
+        offset += packet.encoded_len();
+        match packet {
+            Packet::Data(p) => {
+                let method = if p.is_first() {
+                    match symphony_wire::parse_message_header(p.payload) {
+                        Ok(header) => config.method_name(header.service_id, header.method_id),
+                        Err(e) => format!("<{}>", e),
+                    }
+                } else {
+                    "-".to_string()
+                };
+                log::warn!(
+                    "{}: {} rpc_id={} method={} seq={}/{} frag={} more={} {} -> {} payload_len={}",
+                    direction,
+                    p.kind.name(),
+                    p.rpc_id,
+                    method,
+                    p.seq,
+                    p.total_packets,
+                    p.fragment_index,
+                    p.more_fragments,
+                    p.src,
+                    p.dst,
+                    p.payload.len()
+                );
+            }
+            Packet::Error(p) => {
+                log::warn!(
+                    "{}: {} rpc_id={} {} -> {} message={:?}",
+                    direction,
+                    p.kind.name(),
+                    p.rpc_id,
+                    p.src,
+                    p.dst,
+                    String::from_utf8_lossy(p.message)
+                );
+            }
+        }
+    }
+    pending.drain(..offset);
+    if pending.len() > MAX_PENDING {
+        log::warn!("{}: no packet boundary in {} bytes, resetting", direction, pending.len());
+        pending.clear();
+    }
+}
+
+impl StreamContext for Symphony {
+    fn on_downstream_data(&mut self, data_size: usize, _end_of_stream: bool) -> Action {
+        if let Some(data) = self.get_downstream_data(0, data_size) {
+            self.downstream.extend_from_slice(&data);
+            inspect(&self.config, "downstream", &mut self.downstream);
+        }
+        Action::Continue
+    }
+
+    fn on_upstream_data(&mut self, data_size: usize, _end_of_stream: bool) -> Action {
+        if let Some(data) = self.get_upstream_data(0, data_size) {
+            self.upstream.extend_from_slice(&data);
+            inspect(&self.config, "upstream", &mut self.upstream);
+        }
+        Action::Continue
+    }
+}
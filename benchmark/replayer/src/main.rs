@@ -0,0 +1,321 @@
+//! Replays RPCs recorded by the `capture` filter against a gRPC server.
+//!
+//! ```text
+//! replayer capture.bin --target 10.0.0.5:11000 --concurrency 32 --speed 2
+//! ```
+//!
+//! The capture is the concatenation of the batches the filter pushed, as a
+//! collector that appends request bodies to a file stores them. Requests go
+//! out over one plaintext HTTP/2 connection with their recorded metadata and
+//! body. With `--speed` the original spacing between requests is kept,
+//! scaled by the factor; without it requests are sent as fast as
+//! `--concurrency` allows. A summary of gRPC statuses and latencies is
+//! printed at the end.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use h2::client::SendRequest;
+use http::{HeaderMap, Request};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+
+use grpc_frame::record::{self, Record};
+use grpc_frame::status::Code;
+
+const USAGE: &str = "usage: replayer <capture> --target HOST:PORT [--authority NAME] \
+                     [--concurrency N] [--speed FACTOR] [--repeat N]";
+
+// Headers HTTP/2 forbids, which may have been recorded from HTTP/1 clients.
+const CONNECTION_HEADERS: [&str; 6] = [
+    "connection",
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    capture: PathBuf,
+    target: String,
+    /// `:authority` of the requests; defaults to the target.
+    authority: Option<String>,
+    concurrency: usize,
+    /// Replay speed relative to the capture; None replays back to back.
+    speed: Option<f64>,
+    repeat: usize,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut capture = None;
+    let mut target = None;
+    let mut parsed = Args {
+        capture: PathBuf::new(),
+        target: String::new(),
+        authority: None,
+        concurrency: 16,
+        speed: None,
+        repeat: 1,
+    };
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if capture.replace(PathBuf::from(arg)).is_some() {
+                return Err("more than one capture given".to_string());
+            }
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        let number = |value: &str| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("{} must be a positive integer: {}", arg, value))
+        };
+        match arg.as_str() {
+            "--target" => target = Some(value),
+            "--authority" => parsed.authority = Some(value),
+            "--concurrency" => parsed.concurrency = number(&value)?,
+            "--repeat" => parsed.repeat = number(&value)?,
+            "--speed" => match value.parse::<f64>() {
+                Ok(speed) if speed > 0.0 && speed.is_finite() => parsed.speed = Some(speed),
+                _ => return Err(format!("--speed must be a positive number: {}", value)),
+            },
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    parsed.capture = capture.ok_or("no capture given")?;
+    parsed.target = target.ok_or("--target is required")?;
+    Ok(parsed)
+}
+
+/// Result of one replayed RPC.
+struct Outcome {
+    latency: Duration,
+    /// None if the server sent no `grpc-status`.
+    status: Option<Code>,
+}
+
+fn grpc_status(headers: &HeaderMap) -> Option<Code> {
+    let value = headers.get("grpc-status")?.to_str().ok()?;
+    Some(Code::from_header(value))
+}
+
+async fn call(
+    client: SendRequest<Bytes>,
+    authority: &str,
+    record: &Record,
+) -> Result<Outcome, h2::Error> {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri(format!("http://{}/{}", authority, record.method));
+    for (name, value) in &record.metadata {
+        if !CONNECTION_HEADERS.contains(&name.as_str()) {
+            builder = builder.header(name, value);
+        }
+    }
+    let request = match builder.body(()) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("skipping {}: {}", record.method, e);
+            return Ok(Outcome {
+                latency: Duration::ZERO,
+                status: None,
+            });
+        }
+    };
+
+    let start = Instant::now();
+    let mut client = client.ready().await?;
+    let (response, mut stream) = client.send_request(request, record.payload.is_empty())?;
+    if !record.payload.is_empty() {
+        stream.send_data(Bytes::copy_from_slice(&record.payload), true)?;
+    }
+    let response = response.await?;
+    // A trailers-only response carries the status in its headers.
+    let mut status = grpc_status(response.headers());
+    let mut body = response.into_body();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+    }
+    if let Some(trailers) = body.trailers().await? {
+        status = grpc_status(&trailers).or(status);
+    }
+    Ok(Outcome {
+        latency: start.elapsed(),
+        status,
+    })
+}
+
+#[derive(Default)]
+struct Report {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<&'static str, u64>,
+    failures: u64,
+}
+
+impl Report {
+    fn add(&mut self, result: Result<Outcome, h2::Error>) {
+        match result {
+            Ok(outcome) => {
+                let name = outcome.status.map_or("NO_STATUS", Code::name);
+                *self.statuses.entry(name).or_default() += 1;
+                self.latencies.push(outcome.latency);
+            }
+            Err(e) => {
+                eprintln!("call failed: {}", e);
+                self.failures += 1;
+            }
+        }
+    }
+
+    fn print(&mut self, elapsed: Duration) {
+        let total = self.latencies.len() as u64 + self.failures;
+        println!(
+            "{} calls in {:.2?} ({:.1}/s), {} failed",
+            total,
+            elapsed,
+            total as f64 / elapsed.as_secs_f64(),
+            self.failures
+        );
+        for (name, count) in &self.statuses {
+            println!("  {}: {}", name, count);
+        }
+        if self.latencies.is_empty() {
+            return;
+        }
+        self.latencies.sort();
+        let at = |q: f64| self.latencies[((self.latencies.len() - 1) as f64 * q) as usize];
+        println!(
+            "latency p50 {:.2?} p90 {:.2?} p99 {:.2?} max {:.2?}",
+            at(0.5),
+            at(0.9),
+            at(0.99),
+            at(1.0)
+        );
+    }
+}
+
+fn load(args: &Args) -> Result<Vec<Record>, Box<dyn Error>> {
+    let data = std::fs::read(&args.capture)?;
+    let mut records = Vec::new();
+    let mut iter = record::records(&data);
+    for result in iter.by_ref() {
+        match result {
+            Ok(record) => records.push(record),
+            Err(e) => eprintln!("skipping record: {}", e),
+        }
+    }
+    if !iter.remainder().is_empty() {
+        eprintln!(
+            "ignoring {} bytes of a truncated record",
+            iter.remainder().len()
+        );
+    }
+    // Workers push their batches independently.
+    records.sort_by_key(|record| record.timestamp_us);
+    Ok(records)
+}
+
+async fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let records = Arc::new(load(&args)?);
+    if records.is_empty() {
+        return Err("the capture holds no records".into());
+    }
+    let tcp = TcpStream::connect(&args.target).await?;
+    tcp.set_nodelay(true)?;
+    let (client, connection) = h2::client::handshake(tcp).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+    let authority: Arc<str> = args.authority.as_deref().unwrap_or(&args.target).into();
+
+    let permits = Arc::new(Semaphore::new(args.concurrency));
+    let first = records[0].timestamp_us;
+    let start = Instant::now();
+    let mut tasks = Vec::new();
+    for _ in 0..args.repeat {
+        let round = Instant::now();
+        for i in 0..records.len() {
+            if let Some(speed) = args.speed {
+                let offset = Duration::from_micros(records[i].timestamp_us - first);
+                tokio::time::sleep_until((round + offset.div_f64(speed)).into()).await;
+            }
+            let permit = permits.clone().acquire_owned().await?;
+            let client = client.clone();
+            let records = records.clone();
+            let authority = authority.clone();
+            tasks.push(tokio::spawn(async move {
+                let result = call(client, &authority, &records[i]).await;
+                drop(permit);
+                result
+            }));
+        }
+    }
+
+    let mut report = Report::default();
+    for task in tasks {
+        report.add(task.await?);
+    }
+    report.print(start.elapsed());
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(args).await {
+        eprintln!("replayer: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        parse_args(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let args = parse("cap.bin --target 127.0.0.1:11000 --speed 2 --concurrency 4").unwrap();
+        assert_eq!(
+            args,
+            Args {
+                capture: PathBuf::from("cap.bin"),
+                target: "127.0.0.1:11000".to_string(),
+                authority: None,
+                concurrency: 4,
+                speed: Some(2.0),
+                repeat: 1,
+            }
+        );
+        assert_eq!(
+            parse("--target x:1").unwrap_err(),
+            "no capture given".to_string()
+        );
+        assert!(parse("cap.bin").is_err());
+        assert!(parse("cap.bin --target x:1 --speed 0").is_err());
+        assert!(parse("cap.bin --target x:1 --repeat").is_err());
+        assert!(parse("cap.bin --target x:1 --bogus 1").is_err());
+    }
+}
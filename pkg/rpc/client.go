@@ -2,6 +2,7 @@
 package rpc
 
 import (
+	"bytes"
 	"context"
 	"encoding/binary"
 	"fmt"
@@ -172,8 +173,13 @@ func (c *Client) unregisterPendingCall(rpcID uint64) {
 }
 
 func (c *Client) handleErrorPacket(ctx context.Context, data []byte, errType packet.PacketType) error {
-	// Convert data to string for error message
-	errMsg := string(data)
+	// Convert data to string for error message. A Rust server may follow
+	// the message with a NUL and a status trailer, which is not part of it.
+	msg := data
+	if i := bytes.IndexByte(msg, 0); i >= 0 {
+		msg = msg[:i]
+	}
+	errMsg := string(msg)
 
 	// Return buffer to pool after converting to string
 	c.transport.GetBufferPool().Put(data)
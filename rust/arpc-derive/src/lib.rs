@@ -0,0 +1,189 @@
+//! `#[derive(SymphonyMessage)]`: implements `arpc::Message` for a struct
+//! with named fields, in the layout `protoc-gen-symphony` generates for the
+//! equivalent proto message.
+//!
+//! Field attributes, all under `#[symphony(...)]`:
+//!
+//! - `tag = N`: the proto field number. Fields are laid out in tag order;
+//!   without a tag a field takes its position in the struct, from 1.
+//! - `public`: the field goes to the public segment, which proxies may
+//!   read without the private key (`is_public` in the proto).
+//! - `message`: the field holds nested messages, as `Option<M>` or
+//!   `Vec<M>`.
+//!
+//! Other field types must implement `arpc::symphony::Value`: `bool`,
+//! `i32`, `u32`, `i64`, `u64`, `f32`, `f64`, `String`, `Vec<u8>`,
+//! `arpc::symphony::ByteString`, `arpc::Bytes`, and `Vec`s of those. The
+//! last two share the buffer of a message unmarshaled with
+//! `unmarshal_symphony_bytes`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt};
+
+#[proc_macro_derive(SymphonyMessage, attributes(symphony))]
+pub fn derive_symphony_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct Field<'a> {
+    ident: &'a syn::Ident,
+    ty: &'a syn::Type,
+    tag: u32,
+    public: bool,
+    message: bool,
+}
+
+impl Field<'_> {
+    /// The trait that encodes the field.
+    fn codec(&self) -> TokenStream2 {
+        let ty = self.ty;
+        if self.message {
+            quote!(<#ty as ::arpc::symphony::Nested>)
+        } else {
+            quote!(<#ty as ::arpc::symphony::Value>)
+        }
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "SymphonyMessage can only be derived for structs",
+        ));
+    };
+    let Fields::Named(named) = &data.fields else {
+        return Err(syn::Error::new(
+            data.fields.span(),
+            "SymphonyMessage needs named fields",
+        ));
+    };
+
+    let mut fields = Vec::new();
+    for (index, field) in named.named.iter().enumerate() {
+        let mut parsed = Field {
+            ident: field.ident.as_ref().unwrap(),
+            ty: &field.ty,
+            tag: index as u32 + 1,
+            public: false,
+            message: false,
+        };
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("symphony")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("tag") {
+                    parsed.tag = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                } else if meta.path.is_ident("public") {
+                    parsed.public = true;
+                } else if meta.path.is_ident("message") {
+                    parsed.message = true;
+                } else {
+                    return Err(meta.error("expected `tag`, `public` or `message`"));
+                }
+                Ok(())
+            })?;
+        }
+        if let Some(other) = fields.iter().find(|f: &&Field| f.tag == parsed.tag) {
+            return Err(syn::Error::new(
+                field.span(),
+                format!("tag {} is already used by `{}`", parsed.tag, other.ident),
+            ));
+        }
+        fields.push(parsed);
+    }
+    fields.sort_by_key(|f| f.tag);
+    let (public, private): (Vec<_>, Vec<_>) = fields.iter().partition(|f| f.public);
+
+    let public_table = table_len(&public);
+    let private_table = table_len(&private);
+    let encode_public = encode(&public);
+    let encode_private = encode(&private);
+    let decode_public = decode(&public, quote!(public));
+    let decode_private = decode(&private, quote!(private));
+
+    let name = &input.ident;
+    // Decodes `message`, read from a slice or shared from `Bytes`.
+    let read = quote! {
+        let public = message.public();
+        let private = message.private();
+        let _ = (&public, &private);
+        ::std::result::Result::Ok(#name {
+            #(#decode_public,)*
+            #(#decode_private,)*
+        })
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::arpc::Message for #name #ty_generics #where_clause {
+            fn marshal_symphony(&self) -> ::std::vec::Vec<u8> {
+                let mut message = ::arpc::symphony::MessageWriter::new(#public_table);
+                {
+                    let segment = &mut message.public();
+                    #(#encode_public)*
+                }
+                {
+                    let segment = &mut message.private(#private_table);
+                    #(#encode_private)*
+                }
+                message.finish()
+            }
+
+            fn unmarshal_symphony(
+                data: &[u8],
+            ) -> ::std::result::Result<Self, ::arpc::DecodeError> {
+                let message = ::arpc::symphony::MessageReader::new(data)?;
+                #read
+            }
+
+            fn unmarshal_symphony_bytes(
+                data: ::arpc::Bytes,
+            ) -> ::std::result::Result<Self, ::arpc::DecodeError> {
+                let message = ::arpc::symphony::MessageReader::shared(&data)?;
+                #read
+            }
+        }
+    })
+}
+
+/// The table length of a segment, as a constant expression.
+fn table_len(fields: &[&Field]) -> TokenStream2 {
+    let lens = fields.iter().map(|f| {
+        let codec = f.codec();
+        quote!(#codec::TABLE_LEN)
+    });
+    quote!(0 #(+ #lens)*)
+}
+
+/// The slot of each field: the table length of the fields before it.
+fn slots<'a>(fields: &'a [&'a Field]) -> impl Iterator<Item = (&'a Field<'a>, TokenStream2)> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (*f, table_len(&fields[..i])))
+}
+
+fn encode(fields: &[&Field]) -> Vec<TokenStream2> {
+    slots(fields)
+        .map(|(f, slot)| {
+            let codec = f.codec();
+            let ident = f.ident;
+            quote!(#codec::encode(&self.#ident, segment, #slot);)
+        })
+        .collect()
+}
+
+fn decode(fields: &[&Field], segment: TokenStream2) -> Vec<TokenStream2> {
+    slots(fields)
+        .map(|(f, slot)| {
+            let codec = f.codec();
+            let ident = f.ident;
+            quote!(#ident: #codec::decode(&#segment, #slot)?)
+        })
+        .collect()
+}
@@ -0,0 +1,706 @@
+//! Calls spread over the endpoints of a service, one [`Client`] each, by a
+//! [`LoadBalancer`] policy: [`RoundRobin`], [`LeastOutstanding`] or
+//! [`PowerOfTwoChoices`].
+//!
+//! A [`Balancer`] counts the calls outstanding on each endpoint, from when
+//! it hands out the endpoint's client until the [`Picked`] guard is
+//! dropped, so that the policies can steer calls away from slow servers.
+//! Its endpoints are set by hand, or refreshed from a [`Resolver`], and
+//! those failing health checks are left out; see [`Balancer::check_health`].
+//!
+//! Calls of idempotent methods may be hedged: when the first endpoint is
+//! slow to answer, the same call goes to another, and the first success
+//! wins; see [`Hedging`].
+
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::net::SocketAddrV4;
+use std::ops::Deref;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::Poll;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use crate::health::{HealthCheck, HealthCheckRequest, HealthClient, ServingStatus};
+use crate::{CallOptions, Client, Error, Message, Refresh, Resolver};
+
+/// What a [`LoadBalancer`] knows of an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointLoad {
+    pub addr: SocketAddrV4,
+    /// Calls handed to the endpoint that have not finished.
+    pub outstanding: usize,
+    /// False while health checks eject the endpoint.
+    pub healthy: bool,
+}
+
+/// A policy choosing the endpoint of each call.
+pub trait LoadBalancer: Send + Sync + 'static {
+    /// The index in `endpoints`, never empty, of the endpoint to call.
+    /// Ejected endpoints are left out, unless all of them are.
+    fn pick(&self, endpoints: &[EndpointLoad]) -> usize;
+}
+
+/// Takes the endpoints in turn.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl LoadBalancer for RoundRobin {
+    fn pick(&self, endpoints: &[EndpointLoad]) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % endpoints.len()
+    }
+}
+
+/// Takes the endpoint with the fewest outstanding calls, the first of
+/// those tied.
+#[derive(Debug, Default)]
+pub struct LeastOutstanding;
+
+impl LoadBalancer for LeastOutstanding {
+    fn pick(&self, endpoints: &[EndpointLoad]) -> usize {
+        (0..endpoints.len())
+            .min_by_key(|&i| endpoints[i].outstanding)
+            .unwrap_or(0)
+    }
+}
+
+/// Takes the less loaded of two endpoints drawn at random, which spreads
+/// calls nearly as well as [`LeastOutstanding`] without all clients of a
+/// service piling onto the same idle endpoint.
+#[derive(Debug)]
+pub struct PowerOfTwoChoices {
+    // xorshift64 state.
+    rng: AtomicU64,
+}
+
+impl PowerOfTwoChoices {
+    pub fn new() -> PowerOfTwoChoices {
+        let mut seed = [0; 8];
+        if let Err(e) = getrandom::getrandom(&mut seed) {
+            log::warn!("no random seed for load balancing: {}", e);
+        }
+        PowerOfTwoChoices {
+            rng: AtomicU64::new(u64::from_le_bytes(seed) | 1),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        let mut x = self.rng.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        // Racing callers may draw the same number, which is no matter here.
+        self.rng.store(x, Ordering::Relaxed);
+        x
+    }
+}
+
+impl Default for PowerOfTwoChoices {
+    fn default() -> Self {
+        PowerOfTwoChoices::new()
+    }
+}
+
+impl LoadBalancer for PowerOfTwoChoices {
+    fn pick(&self, endpoints: &[EndpointLoad]) -> usize {
+        let len = endpoints.len() as u64;
+        if len == 1 {
+            return 0;
+        }
+        let a = (self.next() % len) as usize;
+        // A second draw among the others, so the two differ.
+        let b = (a + 1 + (self.next() % (len - 1)) as usize) % endpoints.len();
+        if endpoints[b].outstanding < endpoints[a].outstanding {
+            b
+        } else {
+            a
+        }
+    }
+}
+
+/// How a [`Balancer`] hedges a call: once `delay` passes without a
+/// response, it sends the call again to an endpoint not tried yet, and
+/// takes the first success, dropping the attempts still running. An
+/// attempt failing sends the next one at once.
+///
+/// Only for idempotent methods, which the hedges may run more than once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hedging {
+    pub delay: Duration,
+    /// Attempts in all, the first included; 1 does not hedge.
+    pub max_attempts: usize,
+}
+
+impl Default for Hedging {
+    fn default() -> Self {
+        Hedging {
+            delay: Duration::from_millis(50),
+            max_attempts: 2,
+        }
+    }
+}
+
+type Setup = Arc<dyn Fn(&Client) + Send + Sync>;
+
+struct Endpoint {
+    addr: SocketAddrV4,
+    client: Arc<Client>,
+    outstanding: AtomicUsize,
+    healthy: AtomicBool,
+    // Health probes in a row that got no answer.
+    misses: AtomicU32,
+}
+
+impl Endpoint {
+    /// Healthy, and not shutting down.
+    fn usable(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed) && !self.client.going_away()
+    }
+
+    /// Checks the health of the endpoint, ejecting or bringing it back.
+    async fn probe(&self, req: &HealthCheckRequest, check: &HealthCheck) {
+        let health = HealthClient::new(self.client.clone());
+        let serving = match tokio::time::timeout(check.timeout, health.check(req)).await {
+            Ok(Ok(resp)) => Some(resp.serving_status() == ServingStatus::Serving),
+            // Answered, e.g. that the service is unknown.
+            Ok(Err(Error::Status(_))) => Some(false),
+            Ok(Err(_)) | Err(_) => None,
+        };
+        let healthy = match serving {
+            Some(serving) => {
+                self.misses.store(0, Ordering::Relaxed);
+                serving
+            }
+            None => self.misses.fetch_add(1, Ordering::Relaxed) + 1 < check.max_failures,
+        };
+        if self.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                log::info!("endpoint {} is healthy again", self.addr);
+            } else {
+                log::warn!("ejecting unhealthy endpoint {}", self.addr);
+            }
+        }
+    }
+}
+
+/// A client of a service served at several endpoints, choosing one for
+/// each call with its [`LoadBalancer`].
+///
+/// New endpoints get a client of their own, through the setup function to
+/// register services or set encryption keys.
+pub struct Balancer {
+    policy: Box<dyn LoadBalancer>,
+    setup: Mutex<Option<Setup>>,
+    endpoints: RwLock<Arc<[Arc<Endpoint>]>>,
+    // By service, then method.
+    hedging: RwLock<HashMap<String, HashMap<String, Hedging>>>,
+}
+
+/// The client of the endpoint chosen for a call, counted as outstanding on
+/// it until dropped.
+pub struct Picked {
+    endpoint: Arc<Endpoint>,
+}
+
+impl Picked {
+    pub fn addr(&self) -> SocketAddrV4 {
+        self.endpoint.addr
+    }
+}
+
+impl Deref for Picked {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.endpoint.client
+    }
+}
+
+impl Drop for Picked {
+    fn drop(&mut self) {
+        self.endpoint.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Balancer {
+    /// A balancer without endpoints; see [`Balancer::set_endpoints`].
+    pub fn new(policy: impl LoadBalancer) -> Balancer {
+        Balancer {
+            policy: Box::new(policy),
+            setup: Mutex::new(None),
+            endpoints: RwLock::new(Arc::new([])),
+            hedging: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `setup` on the client of every endpoint added from now on.
+    pub fn set_setup(&self, setup: impl Fn(&Client) + Send + Sync + 'static) {
+        *self.setup.lock().unwrap() = Some(Arc::new(setup));
+    }
+
+    /// Hedges the calls of `service.method` by `hedging` from now on, or no
+    /// longer with None, unless their options hedge otherwise.
+    pub fn set_hedging(&self, service: &str, method: &str, hedging: Option<Hedging>) {
+        let mut all = self.hedging.write().unwrap();
+        match hedging {
+            Some(hedging) => {
+                let methods = all.entry(service.to_string()).or_default();
+                methods.insert(method.to_string(), hedging);
+            }
+            None => {
+                if let Some(methods) = all.get_mut(service) {
+                    methods.remove(method);
+                }
+            }
+        }
+    }
+
+    /// Balances calls over `addrs` from now on. Endpoints already known
+    /// keep their client and count; the others connect.
+    pub async fn set_endpoints(
+        &self,
+        addrs: impl IntoIterator<Item = SocketAddrV4>,
+    ) -> Result<(), Error> {
+        let current = self.endpoints.read().unwrap().clone();
+        let setup = self.setup.lock().unwrap().clone();
+        let mut endpoints = Vec::new();
+        for addr in addrs {
+            if endpoints.iter().any(|e: &Arc<Endpoint>| e.addr == addr) {
+                continue;
+            }
+            if let Some(known) = current.iter().find(|e| e.addr == addr) {
+                endpoints.push(known.clone());
+                continue;
+            }
+            let client = Client::connect(addr).await?;
+            if let Some(setup) = &setup {
+                setup(&client);
+            }
+            endpoints.push(Arc::new(Endpoint {
+                addr,
+                client: Arc::new(client),
+                outstanding: AtomicUsize::new(0),
+                healthy: AtomicBool::new(true),
+                misses: AtomicU32::new(0),
+            }));
+        }
+        *self.endpoints.write().unwrap() = endpoints.into();
+        Ok(())
+    }
+
+    /// Balances calls over the endpoints `resolver` finds now. On failure
+    /// the endpoints stay as they were.
+    pub async fn resolve(&self, resolver: &dyn Resolver) -> Result<(), Error> {
+        let addrs = resolver.resolve().await?;
+        self.set_endpoints(addrs).await
+    }
+
+    /// Resolves the endpoints now and every `refresh` from now on, until
+    /// the balancer is dropped or the returned task aborted. Failed lookups
+    /// are logged and keep the endpoints as they were.
+    pub fn watch(self: &Arc<Self>, resolver: impl Resolver, refresh: Refresh) -> JoinHandle<()> {
+        let balancer = Arc::downgrade(self);
+        tokio::spawn(async move {
+            while let Some(this) = balancer.upgrade() {
+                if let Err(e) = this.resolve(&resolver).await {
+                    log::warn!("failed to refresh endpoints: {}", e);
+                }
+                drop(this);
+                tokio::time::sleep(refresh.delay()).await;
+            }
+        })
+    }
+
+    /// Probes every endpoint with the health service every `check.interval`
+    /// from now on, until the balancer is dropped or the returned task
+    /// aborted, leaving unhealthy endpoints out of the picks.
+    pub fn check_health(self: &Arc<Self>, check: HealthCheck) -> JoinHandle<()> {
+        let balancer = Arc::downgrade(self);
+        let check = Arc::new(check);
+        tokio::spawn(async move {
+            while let Some(this) = balancer.upgrade() {
+                let endpoints = this.endpoints.read().unwrap().clone();
+                drop(this);
+                let probes: Vec<_> = endpoints
+                    .iter()
+                    .map(|endpoint| {
+                        let (endpoint, check) = (endpoint.clone(), check.clone());
+                        tokio::spawn(async move {
+                            let req = HealthCheckRequest {
+                                service: check.service.clone(),
+                            };
+                            endpoint.probe(&req, &check).await;
+                        })
+                    })
+                    .collect();
+                for probe in probes {
+                    let _ = probe.await;
+                }
+                tokio::time::sleep(check.interval).await;
+            }
+        })
+    }
+
+    /// The endpoints calls are balanced over, with their load.
+    pub fn endpoints(&self) -> Vec<EndpointLoad> {
+        load(&self.endpoints.read().unwrap())
+    }
+
+    /// The client of the endpoint the policy picks for the next call.
+    pub fn pick(&self) -> Result<Picked, Error> {
+        self.pick_except(&[])
+    }
+
+    /// Picks like [`Balancer::pick`], leaving out the endpoints at `tried`.
+    fn pick_except(&self, tried: &[SocketAddrV4]) -> Result<Picked, Error> {
+        let all: Vec<_> = self
+            .endpoints
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|e| !tried.contains(&e.addr))
+            .cloned()
+            .collect();
+        if all.is_empty() {
+            return Err(Error::NoEndpoints);
+        }
+        let healthy: Vec<_> = all.iter().filter(|e| e.usable()).cloned().collect();
+        // With every endpoint ejected, a call still has a chance.
+        let endpoints = if healthy.is_empty() {
+            &all[..]
+        } else {
+            &healthy[..]
+        };
+        let i = self.policy.pick(&load(endpoints));
+        let endpoint = endpoints.get(i).unwrap_or(&endpoints[0]).clone();
+        endpoint.outstanding.fetch_add(1, Ordering::Relaxed);
+        Ok(Picked { endpoint })
+    }
+
+    /// Calls `service.method` with `req` on the endpoint the policy picks,
+    /// and waits for the response.
+    pub async fn call<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+    ) -> Result<Resp, Error> {
+        self.call_with(service, method, req, &CallOptions::default())
+            .await
+    }
+
+    /// Calls like [`Balancer::call`], under `options`. The call is hedged
+    /// by [`CallOptions::hedging`], or else as set for the method with
+    /// [`Balancer::set_hedging`].
+    pub async fn call_with<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+        options: &CallOptions,
+    ) -> Result<Resp, Error> {
+        let hedging = options.hedging.or_else(|| {
+            let all = self.hedging.read().unwrap();
+            all.get(service)?.get(method).copied()
+        });
+        match hedging {
+            Some(hedging) if hedging.max_attempts > 1 => {
+                self.hedge(service, method, req, options, hedging).await
+            }
+            _ => self.pick()?.call_with(service, method, req, options).await,
+        }
+    }
+
+    async fn hedge<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+        options: &CallOptions,
+        hedging: Hedging,
+    ) -> Result<Resp, Error> {
+        let attempt =
+            |picked: Picked| async move { picked.call_with(service, method, req, options).await };
+        let first = self.pick()?;
+        let mut tried = vec![first.addr()];
+        let mut attempts = vec![Box::pin(attempt(first))];
+        let mut timer = pin!(tokio::time::sleep(hedging.delay));
+        let mut failed = None;
+        // Set once no endpoint is left to hedge to.
+        let mut exhausted = false;
+        poll_fn(|cx| loop {
+            let mut i = 0;
+            while i < attempts.len() {
+                match attempts[i].as_mut().poll(cx) {
+                    Poll::Ready(Ok(resp)) => return Poll::Ready(Ok(resp)),
+                    Poll::Ready(Err(e)) => {
+                        drop(attempts.swap_remove(i));
+                        failed = Some(e);
+                    }
+                    Poll::Pending => i += 1,
+                }
+            }
+            let due = !exhausted
+                && tried.len() < hedging.max_attempts
+                && (attempts.is_empty() || timer.as_mut().poll(cx).is_ready());
+            let hedged = if due {
+                self.pick_except(&tried).ok()
+            } else {
+                None
+            };
+            let Some(picked) = hedged else {
+                exhausted |= due;
+                if attempts.is_empty() {
+                    return Poll::Ready(Err(failed.take().unwrap_or(Error::NoEndpoints)));
+                }
+                return Poll::Pending;
+            };
+            log::debug!("hedging {}.{} to {}", service, method, picked.addr());
+            tried.push(picked.addr());
+            attempts.push(Box::pin(attempt(picked)));
+            timer
+                .as_mut()
+                .reset(tokio::time::Instant::now() + hedging.delay);
+        })
+        .await
+    }
+}
+
+fn load(endpoints: &[Arc<Endpoint>]) -> Vec<EndpointLoad> {
+    endpoints
+        .iter()
+        .map(|e| EndpointLoad {
+            addr: e.addr,
+            outstanding: e.outstanding.load(Ordering::Relaxed),
+            healthy: e.usable(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::{HealthReporter, HealthServer};
+    use crate::testing::Text;
+    use crate::{Server, ServiceDesc};
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    fn loads(outstanding: &[usize]) -> Vec<EndpointLoad> {
+        outstanding
+            .iter()
+            .enumerate()
+            .map(|(i, &outstanding)| EndpointLoad {
+                addr: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9000 + i as u16),
+                outstanding,
+                healthy: true,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn policies_pick_by_turn_and_load() {
+        let endpoints = loads(&[3, 1, 2]);
+        let round_robin = RoundRobin::default();
+        let picks: Vec<_> = (0..4).map(|_| round_robin.pick(&endpoints)).collect();
+        assert_eq!(picks, [0, 1, 2, 0]);
+        assert_eq!(LeastOutstanding.pick(&endpoints), 1);
+
+        // Two choices never take the busiest endpoint of three.
+        let two = PowerOfTwoChoices::new();
+        for _ in 0..100 {
+            assert_ne!(two.pick(&endpoints), 0);
+        }
+        assert_eq!(two.pick(&loads(&[5])), 0);
+    }
+
+    #[tokio::test]
+    async fn balances_calls_over_endpoints() {
+        let mut addrs = Vec::new();
+        for name in ["a", "b"] {
+            let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+            server.register(ServiceDesc::new("NameService", 1).method(
+                "Name",
+                1,
+                move |_: Text| async move { Ok::<_, Error>(Text::new(name)) },
+            ));
+            let SocketAddr::V4(addr) = server.local_addr().unwrap() else {
+                unreachable!();
+            };
+            addrs.push(addr);
+            tokio::spawn(server.serve());
+        }
+
+        let balancer = Balancer::new(RoundRobin::default());
+        assert!(matches!(balancer.pick(), Err(Error::NoEndpoints)));
+        balancer.set_setup(|client| client.register_service("NameService", 1, &[("Name", 1)]));
+        balancer.set_endpoints(addrs.clone()).await.unwrap();
+        let mut answered = HashMap::new();
+        for _ in 0..4 {
+            let resp: Text = balancer
+                .call("NameService", "Name", &Text::new(""))
+                .await
+                .unwrap();
+            *answered.entry(resp.0).or_insert(0) += 1;
+        }
+        assert_eq!(answered, HashMap::from([("a".into(), 2), ("b".into(), 2)]));
+
+        // Picked clients count as outstanding until dropped.
+        let picked = balancer.pick().unwrap();
+        let load = balancer.endpoints();
+        let busy = load.iter().find(|e| e.addr == picked.addr()).unwrap();
+        assert_eq!(busy.outstanding, 1);
+        drop(picked);
+        assert!(balancer.endpoints().iter().all(|e| e.outstanding == 0));
+
+        // Endpoints still listed keep their client.
+        balancer.set_endpoints([addrs[1]]).await.unwrap();
+        let resp: Text = balancer
+            .call("NameService", "Name", &Text::new(""))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("b"));
+
+        // A watched resolver moves the endpoints.
+        struct Fixed(Mutex<Vec<SocketAddrV4>>);
+        impl Resolver for Arc<Fixed> {
+            fn resolve(&self) -> crate::BoxFuture<Result<Vec<SocketAddrV4>, Error>> {
+                let addrs = self.0.lock().unwrap().clone();
+                Box::pin(async move { Ok(addrs) })
+            }
+        }
+        let fixed = Arc::new(Fixed(Mutex::new(vec![addrs[0]])));
+        let balancer = Arc::new(balancer);
+        let refresh = Refresh {
+            interval: Duration::from_millis(10),
+            jitter: Duration::from_millis(5),
+        };
+        let watch = balancer.watch(fixed.clone(), refresh);
+        for expected in [addrs[0], addrs[1]] {
+            *fixed.0.lock().unwrap() = vec![expected];
+            while balancer.endpoints()[0].addr != expected {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }
+        watch.abort();
+    }
+
+    #[tokio::test]
+    async fn hedges_slow_calls_to_another_endpoint() {
+        let mut addrs = Vec::new();
+        for (name, millis) in [("slow", 2000), ("fast", 0)] {
+            let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+            server.register(ServiceDesc::new("NameService", 1).method(
+                "Name",
+                1,
+                move |_: Text| async move {
+                    tokio::time::sleep(Duration::from_millis(millis)).await;
+                    Ok::<_, Error>(Text::new(name))
+                },
+            ));
+            let SocketAddr::V4(addr) = server.local_addr().unwrap() else {
+                unreachable!();
+            };
+            addrs.push(addr);
+            tokio::spawn(server.serve());
+        }
+
+        let balancer = Balancer::new(RoundRobin::default());
+        balancer.set_setup(|client| client.register_service("NameService", 1, &[("Name", 1)]));
+        balancer.set_endpoints(addrs).await.unwrap();
+        let hedging = Hedging {
+            delay: Duration::from_millis(20),
+            max_attempts: 2,
+        };
+        balancer.set_hedging("NameService", "Name", Some(hedging));
+        // Whichever endpoint goes first, the fast one answers, and the
+        // slow attempt is dropped.
+        for _ in 0..4 {
+            let resp: Text = tokio::time::timeout(
+                Duration::from_millis(1000),
+                balancer.call("NameService", "Name", &Text::new("")),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            assert_eq!(resp, Text::new("fast"));
+            assert!(balancer.endpoints().iter().all(|e| e.outstanding == 0));
+        }
+
+        // Call options take precedence: without hedging, one of two calls
+        // in turn waits on the slow endpoint.
+        let once = CallOptions {
+            hedging: Some(Hedging {
+                max_attempts: 1,
+                ..hedging
+            }),
+            ..CallOptions::default()
+        };
+        let req = Text::new("");
+        let mut timed_out = 0;
+        for _ in 0..2 {
+            let call = balancer.call_with::<_, Text>("NameService", "Name", &req, &once);
+            if tokio::time::timeout(Duration::from_millis(200), call)
+                .await
+                .is_err()
+            {
+                timed_out += 1;
+            }
+        }
+        assert_eq!(timed_out, 1);
+    }
+
+    #[tokio::test]
+    async fn ejects_unhealthy_endpoints() {
+        let mut addrs = Vec::new();
+        let mut reporters = Vec::new();
+        for name in ["a", "b"] {
+            let reporter = Arc::new(HealthReporter::new());
+            let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+            server.register(ServiceDesc::new("NameService", 1).method(
+                "Name",
+                1,
+                move |_: Text| async move { Ok::<_, Error>(Text::new(name)) },
+            ));
+            server.register_service(HealthServer(reporter.clone()));
+            let SocketAddr::V4(addr) = server.local_addr().unwrap() else {
+                unreachable!();
+            };
+            addrs.push(addr);
+            reporters.push(reporter);
+            tokio::spawn(server.serve());
+        }
+
+        let balancer = Arc::new(Balancer::new(RoundRobin::default()));
+        balancer.set_setup(|client| client.register_service("NameService", 1, &[("Name", 1)]));
+        balancer.set_endpoints(addrs.clone()).await.unwrap();
+        let checks = balancer.check_health(HealthCheck {
+            interval: Duration::from_millis(10),
+            ..HealthCheck::default()
+        });
+        reporters[0].set_status("", ServingStatus::NotServing);
+        while balancer.endpoints()[0].healthy {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        for _ in 0..4 {
+            let resp: Text = balancer
+                .call("NameService", "Name", &Text::new(""))
+                .await
+                .unwrap();
+            assert_eq!(resp, Text::new("b"));
+        }
+
+        // Serving again, it is brought back.
+        reporters[0].set_status("", ServingStatus::Serving);
+        while !balancer.endpoints()[0].healthy {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        checks.abort();
+    }
+}
@@ -0,0 +1,659 @@
+//! Batched datagram I/O: the UDP transport sends the packets of a message,
+//! and of a round of retransmissions, with one `sendmmsg` and takes in
+//! what arrived with one `recvmmsg`, up to [`Batching::max_batch`]
+//! datagrams per system call, instead of one each.
+//!
+//! With [`Batching::offload`], the kernel segments as well: a run of
+//! packets to the same destination goes down as one buffer it splits into
+//! datagrams (`UDP_SEGMENT`), and datagrams of a flow arriving together
+//! come up as one buffer split here (`UDP_GRO`). Where the kernel cannot
+//! segment, sends fall back to one datagram each.
+//!
+//! Batching is on by default on Linux; elsewhere, and with
+//! [`Batching::max_batch`] at 1, datagrams go one per system call. An
+//! io_uring, when set, takes over from both; see [`crate::IoUring`].
+//! [`BatchStats`] count the batches and their datagrams, so that the
+//! average batch shows what batching saves.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Batching settings of a transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batching {
+    /// The most buffers sent or received per system call.
+    pub max_batch: usize,
+    /// Whether the kernel segments runs of packets to one destination and
+    /// coalesces datagrams arriving together (UDP GSO and GRO).
+    pub offload: bool,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Batching {
+            max_batch: 32,
+            offload: false,
+        }
+    }
+}
+
+/// Counts of the batched system calls of a transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatchStats {
+    /// `sendmmsg` calls that sent datagrams.
+    pub send_batches: u64,
+    pub sent: u64,
+    /// Datagrams of `sent` the kernel segmented out of larger buffers.
+    pub segmented: u64,
+    /// `recvmmsg` calls that received datagrams.
+    pub recv_batches: u64,
+    pub received: u64,
+    /// Datagrams of `received` split out of buffers the kernel coalesced.
+    pub coalesced: u64,
+}
+
+impl std::ops::AddAssign for BatchStats {
+    fn add_assign(&mut self, other: BatchStats) {
+        self.send_batches += other.send_batches;
+        self.sent += other.sent;
+        self.segmented += other.segmented;
+        self.recv_batches += other.recv_batches;
+        self.received += other.received;
+        self.coalesced += other.coalesced;
+    }
+}
+
+/// The counters behind [`BatchStats`].
+#[derive(Default)]
+pub(crate) struct Counters {
+    send_batches: AtomicU64,
+    sent: AtomicU64,
+    segmented: AtomicU64,
+    recv_batches: AtomicU64,
+    received: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+impl Counters {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn sent(&self, datagrams: usize, segmented: usize) {
+        self.send_batches.fetch_add(1, Ordering::Relaxed);
+        self.sent.fetch_add(datagrams as u64, Ordering::Relaxed);
+        self.segmented
+            .fetch_add(segmented as u64, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn received(&self, datagrams: usize, coalesced: usize) {
+        self.recv_batches.fetch_add(1, Ordering::Relaxed);
+        self.received.fetch_add(datagrams as u64, Ordering::Relaxed);
+        self.coalesced
+            .fetch_add(coalesced as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> BatchStats {
+        BatchStats {
+            send_batches: self.send_batches.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            segmented: self.segmented.load(Ordering::Relaxed),
+            recv_batches: self.recv_batches.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+use linux::segment;
+#[cfg(target_os = "linux")]
+pub(crate) use linux::{recv, send, set_gro, Inbox};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::borrow::Cow;
+    use std::collections::VecDeque;
+    use std::io;
+    use std::mem::size_of;
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+    use std::os::fd::AsRawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    use tokio::io::Interest;
+    use tokio::net::UdpSocket;
+
+    use super::{Batching, Counters};
+
+    /// The most segments the kernel takes in one buffer.
+    const MAX_SEGMENTS: usize = 64;
+
+    /// The largest buffer the kernel segments or coalesces, a whole IPv4
+    /// datagram's payload.
+    const MAX_OFFLOAD_LEN: usize = 65535 - 20 - 8;
+
+    /// Room for one control message of an int, 8-byte aligned.
+    type Control = [u64; 4];
+
+    /// Datagrams received in a batch and not yet read, and the buffers
+    /// they were received into.
+    pub(crate) struct Inbox {
+        buffers: Vec<Vec<u8>>,
+        /// Buffer, offset, length and source of each datagram waiting.
+        ready: VecDeque<(usize, usize, usize, SocketAddrV4)>,
+    }
+
+    impl Inbox {
+        pub fn new() -> Inbox {
+            Inbox {
+                buffers: Vec::new(),
+                ready: VecDeque::new(),
+            }
+        }
+
+        /// Copies the next datagram waiting into `buf`, if one is.
+        pub fn pop(&mut self, buf: &mut [u8]) -> Option<(usize, SocketAddr)> {
+            let (i, offset, len, src) = self.ready.pop_front()?;
+            let n = len.min(buf.len());
+            buf[..n].copy_from_slice(&self.buffers[i][offset..offset + n]);
+            Some((n, SocketAddr::V4(src)))
+        }
+    }
+
+    /// A buffer to send to one destination, either one datagram or, with
+    /// `segment` set, datagrams of that size (the last maybe shorter) for
+    /// the kernel to split.
+    pub(super) struct Outgoing<'a> {
+        dst: SocketAddrV4,
+        data: Cow<'a, [u8]>,
+        pub segment: Option<u16>,
+    }
+
+    impl Outgoing<'_> {
+        pub fn datagrams(&self) -> usize {
+            match self.segment {
+                Some(segment) => self.data.len().div_ceil(segment as usize),
+                None => 1,
+            }
+        }
+
+        /// The datagrams of a segmented buffer, to send one by one.
+        fn split(&self) -> Vec<Outgoing<'_>> {
+            let segment = self.segment.map_or(self.data.len(), usize::from);
+            self.data
+                .chunks(segment)
+                .map(|datagram| Outgoing {
+                    dst: self.dst,
+                    data: Cow::Borrowed(datagram),
+                    segment: None,
+                })
+                .collect()
+        }
+    }
+
+    /// Joins runs of datagrams to the same destination, all of one size
+    /// but the last, which may be shorter, into buffers to segment.
+    pub(super) fn segment<'a>(datagrams: &[(SocketAddrV4, &'a [u8])]) -> Vec<Outgoing<'a>> {
+        let mut outgoing = Vec::new();
+        let mut rest = datagrams;
+        while let Some(&(dst, first)) = rest.first() {
+            let size = first.len();
+            let mut run = 1;
+            let mut len = size;
+            while let Some(&(next_dst, next)) = rest.get(run) {
+                if next_dst != dst
+                    || next.len() > size
+                    || run == MAX_SEGMENTS
+                    || len + next.len() > MAX_OFFLOAD_LEN
+                {
+                    break;
+                }
+                run += 1;
+                len += next.len();
+                if next.len() < size {
+                    break;
+                }
+            }
+            if run == 1 || size == 0 || size > u16::MAX as usize {
+                outgoing.push(Outgoing {
+                    dst,
+                    data: Cow::Borrowed(first),
+                    segment: None,
+                });
+                rest = &rest[1..];
+                continue;
+            }
+            let mut data = Vec::with_capacity(len);
+            for (_, datagram) in &rest[..run] {
+                data.extend_from_slice(datagram);
+            }
+            outgoing.push(Outgoing {
+                dst,
+                data: Cow::Owned(data),
+                segment: Some(size as u16),
+            });
+            rest = &rest[run..];
+        }
+        outgoing
+    }
+
+    /// Sends `datagrams` in batches of up to `max_batch` buffers,
+    /// segmented with offload on while `gso` holds. A datagram the socket
+    /// refuses is skipped, and the first such error returned once the
+    /// others are sent, as if each had been sent on its own. A segmented
+    /// buffer the socket refuses is sent again one datagram at a time, and
+    /// if that works, `gso` is cleared.
+    pub(crate) async fn send(
+        socket: &UdpSocket,
+        datagrams: &[(SocketAddrV4, &[u8])],
+        batching: Batching,
+        gso: &AtomicBool,
+        counters: &Counters,
+    ) -> io::Result<()> {
+        let outgoing = if batching.offload && gso.load(Ordering::Relaxed) {
+            segment(datagrams)
+        } else {
+            datagrams
+                .iter()
+                .map(|&(dst, datagram)| Outgoing {
+                    dst,
+                    data: Cow::Borrowed(datagram),
+                    segment: None,
+                })
+                .collect()
+        };
+        let mut rest = &outgoing[..];
+        let mut failed = None;
+        while !rest.is_empty() {
+            let batch = &rest[..rest.len().min(batching.max_batch.max(1))];
+            match socket
+                .async_io(Interest::WRITABLE, || sendmmsg(socket, batch))
+                .await
+            {
+                Ok(sent) => {
+                    let sent = &batch[..sent];
+                    let segmented = sent.iter().filter(|o| o.segment.is_some());
+                    counters.sent(
+                        sent.iter().map(Outgoing::datagrams).sum(),
+                        segmented.map(Outgoing::datagrams).sum(),
+                    );
+                    rest = &rest[sent.len()..];
+                }
+                // sendmmsg fails only for the first buffer of a batch.
+                Err(e) if rest[0].segment.is_some() => {
+                    match send_each(socket, &rest[0].split(), counters).await {
+                        Ok(()) => {
+                            if gso.swap(false, Ordering::Relaxed) {
+                                log::warn!(
+                                    "UDP segmentation offload failed, sending datagrams one by one: {}",
+                                    e
+                                );
+                            }
+                        }
+                        Err(e) => {
+                            failed.get_or_insert(e);
+                        }
+                    }
+                    rest = &rest[1..];
+                }
+                Err(e) => {
+                    failed.get_or_insert(e);
+                    rest = &rest[1..];
+                }
+            }
+        }
+        failed.map_or(Ok(()), Err)
+    }
+
+    /// Sends unsegmented datagrams in one batch, as far as the socket
+    /// takes them, skipping those it refuses like [`send`].
+    async fn send_each(
+        socket: &UdpSocket,
+        outgoing: &[Outgoing<'_>],
+        counters: &Counters,
+    ) -> io::Result<()> {
+        let mut rest = outgoing;
+        let mut failed = None;
+        while !rest.is_empty() {
+            match socket
+                .async_io(Interest::WRITABLE, || sendmmsg(socket, rest))
+                .await
+            {
+                Ok(sent) => {
+                    counters.sent(sent, 0);
+                    rest = &rest[sent..];
+                }
+                Err(e) => {
+                    failed.get_or_insert(e);
+                    rest = &rest[1..];
+                }
+            }
+        }
+        failed.map_or(Ok(()), Err)
+    }
+
+    /// Receives a datagram into `buf`, like [`UdpSocket::recv_from`], from
+    /// the inbox, filled with a batch of up to `max_batch` buffers when
+    /// empty, each up to a whole coalesced buffer with offload on.
+    pub(crate) async fn recv(
+        socket: &UdpSocket,
+        inbox: &Mutex<Inbox>,
+        buf: &mut [u8],
+        batching: Batching,
+        counters: &Counters,
+    ) -> io::Result<(usize, SocketAddr)> {
+        let len = match batching.offload {
+            true => buf.len().max(MAX_OFFLOAD_LEN),
+            false => buf.len(),
+        };
+        loop {
+            if let Some(received) = inbox.lock().unwrap().pop(buf) {
+                return Ok(received);
+            }
+            let (datagrams, coalesced) = socket
+                .async_io(Interest::READABLE, || {
+                    let mut inbox = inbox.lock().unwrap();
+                    recvmmsg(socket, &mut inbox, len, batching.max_batch.max(1))
+                })
+                .await?;
+            counters.received(datagrams, coalesced);
+        }
+    }
+
+    /// Has the kernel coalesce datagrams arriving together, or stop.
+    pub(crate) fn set_gro(socket: &UdpSocket, on: bool) -> io::Result<()> {
+        let on = libc::c_int::from(on);
+        // SAFETY: the option value is an int that outlives the call.
+        let set = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_UDP,
+                libc::UDP_GRO,
+                &on as *const libc::c_int as *const libc::c_void,
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if set < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn sockaddr(addr: SocketAddrV4) -> libc::sockaddr_in {
+        libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: addr.port().to_be(),
+            sin_addr: libc::in_addr {
+                s_addr: u32::from_ne_bytes(addr.ip().octets()),
+            },
+            sin_zero: [0; 8],
+        }
+    }
+
+    /// A header pointing to `addr`, `iov` and, when given, `control`.
+    fn mmsghdr(
+        addr: &mut libc::sockaddr_in,
+        iov: &mut libc::iovec,
+        control: Option<&mut Control>,
+    ) -> libc::mmsghdr {
+        // SAFETY: an mmsghdr of null pointers and zero lengths is valid;
+        // some targets pad it with private fields, so it cannot be written
+        // out.
+        let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+        header.msg_hdr.msg_name = addr as *mut libc::sockaddr_in as *mut libc::c_void;
+        header.msg_hdr.msg_namelen = size_of::<libc::sockaddr_in>() as u32;
+        header.msg_hdr.msg_iov = iov;
+        header.msg_hdr.msg_iovlen = 1;
+        if let Some(control) = control {
+            header.msg_hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            header.msg_hdr.msg_controllen = size_of::<Control>() as _;
+        }
+        header
+    }
+
+    /// Sends what of `batch` the socket takes without blocking.
+    fn sendmmsg(socket: &UdpSocket, batch: &[Outgoing<'_>]) -> io::Result<usize> {
+        let mut addrs: Vec<libc::sockaddr_in> = batch.iter().map(|o| sockaddr(o.dst)).collect();
+        let mut iovecs: Vec<libc::iovec> = batch
+            .iter()
+            .map(|o| libc::iovec {
+                iov_base: o.data.as_ptr() as *mut libc::c_void,
+                iov_len: o.data.len(),
+            })
+            .collect();
+        let mut controls = vec![Control::default(); batch.len()];
+        let mut headers: Vec<libc::mmsghdr> = addrs
+            .iter_mut()
+            .zip(&mut iovecs)
+            .zip(&mut controls)
+            .zip(batch)
+            .map(|(((addr, iov), control), outgoing)| {
+                let Some(segment) = outgoing.segment else {
+                    return mmsghdr(addr, iov, None);
+                };
+                let mut header = mmsghdr(addr, iov, Some(control));
+                // SAFETY: the control buffer the header points to has room
+                // for a u16 message, written within it.
+                unsafe {
+                    let len = libc::CMSG_SPACE(size_of::<u16>() as u32);
+                    header.msg_hdr.msg_controllen = len as _;
+                    let cmsg = libc::CMSG_FIRSTHDR(&header.msg_hdr);
+                    (*cmsg).cmsg_level = libc::SOL_UDP;
+                    (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                    (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<u16>() as u32) as _;
+                    std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment);
+                }
+                header
+            })
+            .collect();
+        // SAFETY: the headers point to the addresses, iovecs, control
+        // messages and datagrams above, which outlive the call; the kernel
+        // only reads them, and writes the headers' lengths.
+        let sent = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                libc::MSG_DONTWAIT,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sent as usize)
+    }
+
+    /// The segment size of a coalesced buffer, from the control messages
+    /// of its header.
+    fn gro_segment(header: &libc::msghdr) -> Option<usize> {
+        // SAFETY: the kernel wrote the control messages within the buffer
+        // the header points to, and set its length; the macros stay within
+        // it.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(header);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                    let segment =
+                        std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                    return usize::try_from(segment).ok().filter(|&s| s > 0);
+                }
+                cmsg = libc::CMSG_NXTHDR(header, cmsg);
+            }
+        }
+        None
+    }
+
+    /// Receives what is waiting on the socket, up to `max_batch` buffers
+    /// of up to `len` bytes, into the inbox. Returns how many datagrams
+    /// came, and how many of them coalesced.
+    fn recvmmsg(
+        socket: &UdpSocket,
+        inbox: &mut Inbox,
+        len: usize,
+        max_batch: usize,
+    ) -> io::Result<(usize, usize)> {
+        inbox.buffers.resize_with(max_batch, Vec::new);
+        for buffer in &mut inbox.buffers {
+            buffer.resize(len, 0);
+        }
+        let mut addrs = vec![sockaddr(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)); max_batch];
+        let mut iovecs: Vec<libc::iovec> = inbox
+            .buffers
+            .iter_mut()
+            .map(|buffer| libc::iovec {
+                iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.len(),
+            })
+            .collect();
+        let mut controls = vec![Control::default(); max_batch];
+        let mut headers: Vec<libc::mmsghdr> = addrs
+            .iter_mut()
+            .zip(&mut iovecs)
+            .zip(&mut controls)
+            .map(|((addr, iov), control)| mmsghdr(addr, iov, Some(control)))
+            .collect();
+        // SAFETY: the headers point to the addresses, iovecs, control
+        // buffers and buffers above, which outlive the call, and give their
+        // lengths, which the kernel writes within.
+        let received = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            )
+        };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (mut datagrams, mut coalesced) = (0, 0);
+        for (i, header) in headers.iter().take(received as usize).enumerate() {
+            if header.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                log::debug!("dropping datagram past {} bytes", len);
+                continue;
+            }
+            let addr = &addrs[i];
+            let src = SocketAddrV4::new(
+                Ipv4Addr::from(addr.sin_addr.s_addr.to_ne_bytes()),
+                u16::from_be(addr.sin_port),
+            );
+            let total = header.msg_len as usize;
+            let segment = gro_segment(&header.msg_hdr).unwrap_or(total).max(1);
+            let before = inbox.ready.len();
+            for offset in (0..total).step_by(segment) {
+                let n = segment.min(total - offset);
+                inbox.ready.push_back((i, offset, n, src));
+            }
+            let n = inbox.ready.len() - before;
+            datagrams += n;
+            if n > 1 {
+                coalesced += n;
+            }
+        }
+        Ok((datagrams, coalesced))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::net::SocketAddrV4;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    use tokio::net::UdpSocket;
+
+    async fn pair() -> (UdpSocket, UdpSocket, SocketAddrV4) {
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let std::net::SocketAddr::V4(dst) = receiver.local_addr().unwrap() else {
+            unreachable!();
+        };
+        (sender, receiver, dst)
+    }
+
+    #[tokio::test]
+    async fn sends_and_receives_in_batches() {
+        let (sender, receiver, dst) = pair().await;
+        let counters = Counters::default();
+        let payloads: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 100 + i as usize]).collect();
+        let datagrams: Vec<(SocketAddrV4, &[u8])> =
+            payloads.iter().map(|p| (dst, &p[..])).collect();
+        let batching = Batching {
+            max_batch: 4,
+            ..Batching::default()
+        };
+        let gso = AtomicBool::new(true);
+        send(&sender, &datagrams, batching, &gso, &counters)
+            .await
+            .unwrap();
+        let stats = counters.stats();
+        assert_eq!((stats.send_batches, stats.sent), (3, 10));
+
+        let inbox = Mutex::new(Inbox::new());
+        let mut buf = [0; 1500];
+        let batching = Batching {
+            max_batch: 8,
+            ..Batching::default()
+        };
+        for payload in &payloads {
+            let (n, src) = recv(&receiver, &inbox, &mut buf, batching, &counters)
+                .await
+                .unwrap();
+            assert_eq!(&buf[..n], &payload[..]);
+            assert_eq!(src, sender.local_addr().unwrap());
+        }
+        let stats = counters.stats();
+        assert_eq!(stats.received, 10);
+        assert!(stats.recv_batches >= 2, "{:?}", stats);
+    }
+
+    #[tokio::test]
+    async fn segments_runs_to_one_destination() {
+        let (sender, receiver, dst) = pair().await;
+        let (_, _, other) = pair().await;
+        // A run of full packets and a short last one, then a packet
+        // elsewhere, then one more.
+        let mut payloads: Vec<(SocketAddrV4, Vec<u8>)> =
+            (0..5u8).map(|i| (dst, vec![i; 1000])).collect();
+        payloads.push((dst, vec![5; 300]));
+        payloads.push((other, vec![6; 1000]));
+        payloads.push((dst, vec![7; 1000]));
+        let datagrams: Vec<(SocketAddrV4, &[u8])> =
+            payloads.iter().map(|(dst, p)| (*dst, &p[..])).collect();
+        let outgoing = segment(&datagrams);
+        let segments: Vec<_> = outgoing
+            .iter()
+            .map(|o| (o.segment, o.datagrams()))
+            .collect();
+        assert_eq!(segments, [(Some(1000), 6), (None, 1), (None, 1)]);
+
+        let batching = Batching {
+            offload: true,
+            ..Batching::default()
+        };
+        set_gro(&receiver, true).unwrap();
+        let gso = AtomicBool::new(true);
+        let counters = Counters::default();
+        send(&sender, &datagrams, batching, &gso, &counters)
+            .await
+            .unwrap();
+        let stats = counters.stats();
+        assert_eq!(stats.sent, 8);
+        if gso.load(Ordering::Relaxed) {
+            assert_eq!(stats.segmented, 6);
+        }
+
+        let inbox = Mutex::new(Inbox::new());
+        let mut buf = [0; 1500];
+        for (to, payload) in &payloads {
+            if *to != dst {
+                continue;
+            }
+            let (n, _) = recv(&receiver, &inbox, &mut buf, batching, &counters)
+                .await
+                .unwrap();
+            assert_eq!(&buf[..n], &payload[..]);
+        }
+        assert_eq!(counters.stats().received, 7);
+    }
+}
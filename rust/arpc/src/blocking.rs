@@ -0,0 +1,174 @@
+//! A client whose calls block the calling thread, for programs without an
+//! async runtime of their own.
+
+use std::io;
+use std::net::SocketAddrV4;
+use std::time::Duration;
+
+use tokio::net::ToSocketAddrs;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    CallOptions, CipherSuite, CorrelationStats, Encryption, Error, IdStrategy, Interceptor,
+    Message, ReassemblyLimits, Reliability, ReliabilityStats, ServiceRegistry,
+};
+
+/// A [`crate::Client`] driven by a runtime of its own.
+///
+/// The runtime runs on the calling thread while a call waits, so the
+/// client must not be used from within an async runtime; use
+/// [`crate::Client`] there.
+pub struct Client {
+    // Dropped before the runtime that drives it.
+    client: crate::Client,
+    runtime: Runtime,
+}
+
+impl Client {
+    /// Creates a client for the server at `target`, bound to a port the OS
+    /// picks.
+    pub fn connect(target: impl ToSocketAddrs) -> Result<Client, Error> {
+        let runtime = runtime()?;
+        let client = runtime.block_on(crate::Client::connect(target))?;
+        Ok(Client { client, runtime })
+    }
+
+    /// Creates a client for the server at `target`, bound to `local`.
+    pub fn connect_from(target: impl ToSocketAddrs, local: SocketAddrV4) -> Result<Client, Error> {
+        let runtime = runtime()?;
+        let client = runtime.block_on(crate::Client::connect_from(target, local))?;
+        Ok(Client { client, runtime })
+    }
+
+    /// See [`crate::Client::set_service_registry`].
+    pub fn set_service_registry(&self, registry: ServiceRegistry) {
+        self.client.set_service_registry(registry);
+    }
+
+    /// See [`crate::Client::set_encryption`].
+    pub fn set_encryption(&self, encryption: Encryption) {
+        self.client.set_encryption(encryption);
+    }
+
+    /// See [`crate::Client::set_cipher_suite`].
+    pub fn set_cipher_suite(&self, suite: CipherSuite) {
+        self.client.set_cipher_suite(suite);
+    }
+
+    /// See [`crate::Client::rekey`].
+    pub fn rekey(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.client.rekey())
+    }
+
+    /// See [`crate::Client::set_reliability`].
+    pub fn set_reliability(&self, reliability: Option<Reliability>) {
+        self.client.set_reliability(reliability);
+    }
+
+    pub fn reliability_stats(&self) -> ReliabilityStats {
+        self.client.reliability_stats()
+    }
+
+    /// See [`crate::Client::set_reassembly_limits`].
+    pub fn set_reassembly_limits(&self, limits: ReassemblyLimits) {
+        self.client.set_reassembly_limits(limits);
+    }
+
+    /// See [`crate::Client::set_id_strategy`].
+    pub fn set_id_strategy(&self, strategy: IdStrategy) {
+        self.client.set_id_strategy(strategy);
+    }
+
+    /// See [`crate::Client::set_call_timeout`].
+    pub fn set_call_timeout(&self, timeout: Option<Duration>) {
+        self.client.set_call_timeout(timeout);
+    }
+
+    /// See [`crate::Client::add_interceptor`].
+    pub fn add_interceptor(&self, interceptor: impl Interceptor) {
+        self.client.add_interceptor(interceptor);
+    }
+
+    pub fn correlation_stats(&self) -> CorrelationStats {
+        self.client.correlation_stats()
+    }
+
+    pub fn target(&self) -> SocketAddrV4 {
+        self.client.target()
+    }
+
+    /// See [`crate::Client::failures`].
+    pub fn failures(&self) -> u32 {
+        self.client.failures()
+    }
+
+    /// Adds a service to the registry.
+    pub fn register_service(&self, name: &str, id: u32, methods: &[(&str, u32)]) {
+        self.client.register_service(name, id, methods);
+    }
+
+    /// Calls `service.method` with `req` and waits for the response.
+    pub fn call<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+    ) -> Result<Resp, Error> {
+        self.runtime
+            .block_on(self.client.call(service, method, req))
+    }
+
+    /// Calls `service.method` with `req` under `options` and waits for the
+    /// response.
+    pub fn call_with<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+        options: &CallOptions,
+    ) -> Result<Resp, Error> {
+        self.runtime
+            .block_on(self.client.call_with(service, method, req, options))
+    }
+}
+
+/// A runtime on the calling thread; the client's receive task runs while
+/// a call blocks on it.
+fn runtime() -> io::Result<Runtime> {
+    Builder::new_current_thread().enable_all().build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Server, ServiceDesc};
+
+    #[test]
+    fn calls_without_a_runtime() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            runtime().unwrap().block_on(async {
+                let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+                server.register(ServiceDesc::new("EchoService", 1).method(
+                    "Echo",
+                    1,
+                    |req: Text| async move { Ok::<_, Error>(Text(req.0.to_uppercase())) },
+                ));
+                tx.send(server.local_addr().unwrap()).unwrap();
+                server.serve().await
+            })
+        });
+        let addr = rx.recv().unwrap();
+
+        let client = Client::connect(addr).unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        for text in ["one", "two"] {
+            let resp: Text = client
+                .call("EchoService", "Echo", &Text::new(text))
+                .unwrap();
+            assert_eq!(resp, Text(text.to_uppercase()));
+        }
+        assert_eq!(client.correlation_stats().completed, 2);
+    }
+}
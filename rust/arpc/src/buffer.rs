@@ -0,0 +1,189 @@
+//! Pooled buffers for datagrams and marshaled messages, so that a client
+//! or server in steady state allocates no buffer per packet.
+//!
+//! Buffers come in sized tiers. Each thread keeps a few buffers of each
+//! tier, taken and given back without locking, and passes the rest on to a
+//! pool shared by all threads, bounded in bytes per tier. Buffers given
+//! back past those bounds, or of no tier's size, are freed. See
+//! [`buffer_stats`] for how well the pool is doing.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+/// The capacities buffers are pooled at: a small message, a datagram, and
+/// two sizes of reassembled message.
+const TIERS: [usize; 4] = [512, 2 << 10, 16 << 10, 64 << 10];
+
+/// The buffers of each tier a thread keeps to itself.
+const LOCAL_BUFFERS: usize = 16;
+
+/// The bytes of each tier the shared pool holds.
+const SHARED_BYTES: usize = 4 << 20;
+
+static SHARED: [Mutex<Vec<Vec<u8>>>; TIERS.len()] = [const { Mutex::new(Vec::new()) }; TIERS.len()];
+
+thread_local! {
+    static LOCAL: RefCell<[Vec<Vec<u8>>; TIERS.len()]> =
+        const { RefCell::new([const { Vec::new() }; TIERS.len()]) };
+}
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static RECYCLED: AtomicU64 = AtomicU64::new(0);
+static DISCARDED: AtomicU64 = AtomicU64::new(0);
+
+/// Counters of the buffer pool, across threads, since the process started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferStats {
+    /// Buffers taken from the pool.
+    pub hits: u64,
+    /// Buffers allocated because the pool had none large enough.
+    pub misses: u64,
+    /// Buffers given back and kept.
+    pub recycled: u64,
+    /// Buffers given back and freed, the pool being full or them of no
+    /// tier's size.
+    pub discarded: u64,
+    /// The bytes the shared pool holds now.
+    pub shared_bytes: usize,
+}
+
+/// The counters of the buffer pool.
+pub fn buffer_stats() -> BufferStats {
+    BufferStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        recycled: RECYCLED.load(Ordering::Relaxed),
+        discarded: DISCARDED.load(Ordering::Relaxed),
+        shared_bytes: SHARED.iter().map(|tier| held(&tier.lock().unwrap())).sum(),
+    }
+}
+
+fn held(tier: &[Vec<u8>]) -> usize {
+    tier.iter().map(Vec::capacity).sum()
+}
+
+/// An empty buffer with room for at least `len` bytes.
+pub(crate) fn take(len: usize) -> Vec<u8> {
+    let Some(tier) = TIERS.iter().position(|&size| size >= len) else {
+        MISSES.fetch_add(1, Ordering::Relaxed);
+        return Vec::with_capacity(len);
+    };
+    let local = LOCAL
+        .try_with(|local| local.borrow_mut()[tier].pop())
+        .ok()
+        .flatten();
+    match local.or_else(|| SHARED[tier].lock().unwrap().pop()) {
+        Some(buf) => {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            buf
+        }
+        None => {
+            MISSES.fetch_add(1, Ordering::Relaxed);
+            Vec::with_capacity(TIERS[tier])
+        }
+    }
+}
+
+/// A pooled copy of `data`.
+#[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+pub(crate) fn copy(data: &[u8]) -> Vec<u8> {
+    let mut buf = take(data.len());
+    buf.extend_from_slice(data);
+    buf
+}
+
+/// Gives `buf` back to the pool, in the largest tier it holds.
+pub(crate) fn give(mut buf: Vec<u8>) {
+    let capacity = buf.capacity();
+    // A buffer far larger than the largest tier would hold on to memory
+    // no taker asks for.
+    let tier = match TIERS.iter().rposition(|&size| size <= capacity) {
+        Some(tier) if capacity <= 2 * TIERS[TIERS.len() - 1] => tier,
+        _ => {
+            DISCARDED.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    buf.clear();
+    // Once the thread is exiting its buffers are freed with it.
+    let rest = LOCAL
+        .try_with(move |local| {
+            let local = &mut local.borrow_mut()[tier];
+            if local.len() < LOCAL_BUFFERS {
+                local.push(buf);
+                None
+            } else {
+                Some(buf)
+            }
+        })
+        .unwrap_or(None);
+    let Some(buf) = rest else {
+        RECYCLED.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    let mut shared = SHARED[tier].lock().unwrap();
+    if held(&shared) + capacity > SHARED_BYTES {
+        DISCARDED.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    shared.push(buf);
+    RECYCLED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Shares `buf` as [`Bytes`], giving it back to the pool once the last
+/// view of it is dropped.
+pub(crate) fn share(buf: Vec<u8>) -> Bytes {
+    Bytes::from_owner(Recycle(buf))
+}
+
+struct Recycle(Vec<u8>);
+
+impl AsRef<[u8]> for Recycle {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for Recycle {
+    fn drop(&mut self) {
+        give(std::mem::take(&mut self.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_buffers_given_back() {
+        let buf = copy(b"datagram");
+        assert!(buf.capacity() >= 512);
+        let ptr = buf.as_ptr();
+        let before = buffer_stats();
+        give(buf);
+        // The thread's own buffers come back first.
+        let mut buf = take(100);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert!(buf.is_empty());
+        let after = buffer_stats();
+        assert!(after.recycled > before.recycled);
+        assert!(after.hits > before.hits);
+
+        buf.extend_from_slice(b"again");
+        let shared = share(buf);
+        drop(shared.slice(1..));
+        drop(shared);
+        let buf = take(512);
+        assert_eq!(buf.as_ptr(), ptr);
+
+        // Buffers of no tier's size are freed.
+        let before = buffer_stats().discarded;
+        give(Vec::with_capacity(16));
+        give(Vec::with_capacity(1 << 20));
+        assert!(buffer_stats().discarded >= before + 2);
+    }
+}
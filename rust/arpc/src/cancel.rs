@@ -0,0 +1,144 @@
+//! Cancellation of calls, propagated from client to server.
+//!
+//! A client whose call future is dropped, or whose call times out, before
+//! the reply arrives sends the server a cancel message: an empty request,
+//! under a fresh RPC ID, whose extension block names the call. The server
+//! cancels the [`CancellationToken`] of the handler, which it stops polling
+//! without answering; handlers with work off their task, such as blocking
+//! threads, watch [`current_cancellation`] to stop it too.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddrV4;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub use tokio_util::sync::CancellationToken;
+
+use crate::drain;
+
+tokio::task_local! {
+    static TOKEN: CancellationToken;
+}
+
+/// The cancellation token of the request the current task is handling, if
+/// it is one. It is cancelled when the client gives up on the call.
+pub fn current_cancellation() -> Option<CancellationToken> {
+    TOKEN.try_with(CancellationToken::clone).ok()
+}
+
+/// Runs `f` with `token` as the task's token.
+pub(crate) fn enter<R>(token: &CancellationToken, f: impl FnOnce() -> R) -> R {
+    TOKEN.sync_scope(token.clone(), f)
+}
+
+/// Runs `future` with `token` as the token of the task polling it, until
+/// `token` is cancelled; None if it was first.
+pub(crate) async fn scope<F: Future>(token: CancellationToken, future: F) -> Option<F::Output> {
+    let cancelled = token.clone();
+    let stop = async move {
+        cancelled.cancelled().await;
+        true
+    };
+    drain::race(TOKEN.scope(token, future), stop).await
+}
+
+/// The tokens of the handlers a server is running, by caller and RPC ID.
+#[derive(Default)]
+pub(crate) struct Handlers {
+    running: Mutex<HashMap<(SocketAddrV4, u64), (u64, CancellationToken)>>,
+    next: AtomicU64,
+}
+
+/// Keeps a handler's token until dropped.
+pub(crate) struct Running {
+    handlers: Arc<Handlers>,
+    key: (SocketAddrV4, u64),
+    id: u64,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut running = self.handlers.running.lock().unwrap();
+        // The client may have reused the RPC ID for a newer call.
+        if running.get(&self.key).is_some_and(|(id, _)| *id == self.id) {
+            running.remove(&self.key);
+        }
+    }
+}
+
+impl Handlers {
+    /// Registers the token of the handler answering `key`.
+    pub fn run(self: &Arc<Self>, key: (SocketAddrV4, u64), token: CancellationToken) -> Running {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        self.running.lock().unwrap().insert(key, (id, token));
+        Running {
+            handlers: self.clone(),
+            key,
+            id,
+        }
+    }
+
+    /// Cancels the handler answering `key`, if it is running.
+    pub fn cancel(&self, key: (SocketAddrV4, u64)) {
+        if let Some((_, token)) = self.running.lock().unwrap().remove(&key) {
+            log::debug!("cancelling call {} from {}", key.1, key.0);
+            token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Error, Server, ServiceDesc};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn cancels_handlers_the_client_gave_up_on() {
+        let (cancelled, mut seen) = mpsc::unbounded_channel();
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        // Reports its request once cancelled, from a task of its own.
+        server.register(
+            ServiceDesc::new("WaitService", 1).method("Wait", 1, move |req: Text| {
+                let token = current_cancellation().unwrap();
+                let cancelled = cancelled.clone();
+                tokio::spawn(async move {
+                    token.cancelled().await;
+                    let _ = cancelled.send(req.0);
+                });
+                async move {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    Ok::<_, Error>(Text::new("late"))
+                }
+            }),
+        );
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("WaitService", 1, &[("Wait", 1)]);
+        assert_eq!(current_cancellation().map(|t| t.is_cancelled()), None);
+
+        // A dropped call.
+        let req = Text::new("dropped");
+        let call = client.call::<_, Text>("WaitService", "Wait", &req);
+        assert!(tokio::time::timeout(Duration::from_millis(50), call)
+            .await
+            .is_err());
+        let cancelled = tokio::time::timeout(Duration::from_secs(1), seen.recv());
+        assert_eq!(cancelled.await.unwrap().as_deref(), Some("dropped"));
+
+        // A call timing out.
+        client.set_call_timeout(Some(Duration::from_millis(50)));
+        let err = client
+            .call::<_, Text>("WaitService", "Wait", &Text::new("timed out"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::TimedOut), "unexpected error: {}", err);
+        let cancelled = tokio::time::timeout(Duration::from_secs(1), seen.recv());
+        assert_eq!(cancelled.await.unwrap().as_deref(), Some("timed out"));
+    }
+}
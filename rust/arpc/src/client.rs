@@ -0,0 +1,1146 @@
+use std::future::poll_fn;
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use symphony_wire::{PacketType, MAX_PACKET_LEN, MESSAGE_HEADER_LEN, SYMPHONY_VERSION};
+use tokio::net::{lookup_host, ToSocketAddrs};
+use tokio::task::JoinHandle;
+
+use crate::buffer;
+use crate::compression::{self, Codec, Compression, Encoding};
+use crate::correlation::Correlation;
+use crate::deadline;
+use crate::extensions::{Extensions, StreamFrame};
+use crate::interceptor::{Chain, Interceptor, Next, Request, Response};
+#[cfg(feature = "quic")]
+use crate::quic::QuicTransport;
+use crate::stream::{Inflow, Streaming, Streams};
+use crate::tcp::TcpTransport;
+use crate::trace;
+use crate::transport::{Received, Transport, UdpTransport};
+use crate::window::{WindowUpdate, Windows};
+use crate::{
+    BatchStats, Batching, CipherSuite, CongestionControl, CorrelationStats, Encryption, Error,
+    FlowControl, FlowWindows, Hedging, IdStrategy, Keepalive, Message, MetadataMap, PathMtu,
+    ReassemblyLimits, Reliability, ReliabilityStats, ServiceRegistry, SessionClosed, Status,
+};
+
+/// An aRPC client bound to one target (`Client` in `pkg/rpc/client.go`).
+///
+/// Calls may run concurrently: a background task reads every packet from
+/// the socket and hands it to the call waiting on its RPC ID. Dropping the
+/// client stops that task and fails the calls still in flight.
+pub struct Client {
+    inner: Arc<Inner>,
+    receiver: JoinHandle<()>,
+}
+
+/// Settings of one call.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CallOptions {
+    /// How long the server has to respond. It is sent with the request, and
+    /// the server stops the handler once it passes. A call made while
+    /// handling a request also gets that request's deadline, if earlier.
+    pub timeout: Option<Duration>,
+    /// Sent with the request, or with every frame of a stream of requests;
+    /// see [`crate::metadata`].
+    pub metadata: MetadataMap,
+    /// Compresses the requests with this codec rather than the client's
+    /// preferred one, if the server takes it; [`Codec::Identity`] sends
+    /// them as they are. See [`crate::compression`].
+    pub compression: Option<Codec>,
+    /// Hedges the call when made through a [`crate::Balancer`], rather than
+    /// as set for its method; a client alone has no other endpoint.
+    pub hedging: Option<Hedging>,
+}
+
+struct Inner {
+    transport: Arc<dyn Transport>,
+    // The same transport, for the settings only Symphony over UDP has.
+    udp: Option<Arc<UdpTransport>>,
+    target: SocketAddrV4,
+    registry: RwLock<ServiceRegistry>,
+    calls: Correlation,
+    streams: Streams<u64>,
+    flow: RwLock<Option<Arc<dyn FlowControl>>>,
+    windows: RwLock<Option<Arc<Windows>>>,
+    interceptors: RwLock<Chain>,
+    compression: RwLock<Option<Arc<Compression>>>,
+    // The codecs the server takes, from its last response.
+    accepted: RwLock<Vec<Codec>>,
+    // Calls in a row that got no reply; see `Client::failures`.
+    failures: AtomicU32,
+    // Whether the server sent a GOAWAY; see `Client::going_away`.
+    going_away: AtomicBool,
+}
+
+impl Client {
+    /// Creates a client for the server at `target`, bound to a port the OS
+    /// picks.
+    pub async fn connect(target: impl ToSocketAddrs) -> Result<Client, Error> {
+        Client::connect_from(target, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).await
+    }
+
+    /// Creates a client for the server at `target`, bound to `local`.
+    pub async fn connect_from(
+        target: impl ToSocketAddrs,
+        local: SocketAddrV4,
+    ) -> Result<Client, Error> {
+        let target = resolve(target).await?;
+        let udp = Arc::new(UdpTransport::bind(local, target).await?);
+        Ok(Client::start(udp.clone(), Some(udp), target))
+    }
+
+    /// Creates a client for the server at `target` that sends its calls
+    /// over TCP, connecting on the first call, for networks that block UDP;
+    /// the server must serve TCP too. See [`crate::TcpTransport`].
+    pub async fn connect_tcp(target: impl ToSocketAddrs) -> Result<Client, Error> {
+        let target = resolve(target).await?;
+        Ok(Client::with_transport(
+            Arc::new(TcpTransport::connect()),
+            target,
+        ))
+    }
+
+    /// Creates a client for the server at `target` that sends its calls
+    /// over QUIC streams, connecting on the first call; the server must
+    /// serve QUIC too. See [`crate::Quic`].
+    #[cfg(feature = "quic")]
+    pub async fn connect_quic(
+        target: impl ToSocketAddrs,
+        quic: crate::Quic,
+    ) -> Result<Client, Error> {
+        let target = resolve(target).await?;
+        let local = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0);
+        let transport = QuicTransport::bind(local, quic)?;
+        Ok(Client::with_transport(Arc::new(transport), target))
+    }
+
+    /// Creates a client for the server at `target` over `transport`, such
+    /// as a [`crate::MemoryTransport`] in tests. The settings of the UDP
+    /// transport are ignored.
+    pub fn with_transport(transport: Arc<dyn Transport>, target: SocketAddrV4) -> Client {
+        Client::start(transport, None, target)
+    }
+
+    fn start(
+        transport: Arc<dyn Transport>,
+        udp: Option<Arc<UdpTransport>>,
+        target: SocketAddrV4,
+    ) -> Client {
+        let inner = Arc::new(Inner {
+            transport,
+            udp,
+            target,
+            registry: RwLock::new(ServiceRegistry::new()),
+            calls: Correlation::new(),
+            streams: Streams::new(),
+            flow: RwLock::new(None),
+            windows: RwLock::new(None),
+            interceptors: RwLock::new(Arc::new([])),
+            compression: RwLock::new(None),
+            accepted: RwLock::new(Vec::new()),
+            failures: AtomicU32::new(0),
+            going_away: AtomicBool::new(false),
+        });
+        let receiver = tokio::spawn(receive_loop(inner.clone()));
+        Client { inner, receiver }
+    }
+
+    /// Replaces the registry used to look up service and method IDs.
+    pub fn set_service_registry(&self, registry: ServiceRegistry) {
+        *self.inner.registry.write().unwrap() = registry;
+    }
+
+    /// Encrypts requests and expects encrypted responses; the server must
+    /// use the same keys. Ignored by other transports than UDP.
+    pub fn set_encryption(&self, encryption: Encryption) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            udp.set_encryption(encryption);
+        }
+    }
+
+    /// Seals requests in `suite`, named to the server in the extension
+    /// block until it answers; a server that does not take it fails the
+    /// call with [`crate::Code::Unimplemented`]. Ignored by other
+    /// transports than UDP. See [`Encryption`].
+    pub fn set_cipher_suite(&self, suite: CipherSuite) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            udp.set_cipher_suites(&[suite]);
+        }
+    }
+
+    /// Talks to the server over a DTLS session, connecting on the first
+    /// call; the server must use DTLS too. Ignored by other transports than
+    /// UDP.
+    #[cfg(feature = "dtls")]
+    pub fn set_dtls(&self, dtls: crate::Dtls) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            udp.set_dtls(dtls);
+        }
+    }
+
+    /// Asks the server to rotate encryption keys to the next epoch, ahead
+    /// of the automatic rotation. Does nothing without encryption.
+    pub async fn rekey(&self) -> Result<(), Error> {
+        match self.inner.udp.as_deref() {
+            Some(udp) => udp.rekey(self.inner.target).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Acknowledges and retransmits packets so that calls survive a lossy
+    /// link; None turns it off. The server must enable it too. Ignored by
+    /// other transports than UDP.
+    pub fn set_reliability(&self, reliability: Option<Reliability>) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            udp.set_reliability(reliability);
+        }
+    }
+
+    /// Paces what is sent in reliability mode with a congestion window per
+    /// peer; None sends every packet at once. Only this side needs it set.
+    /// Ignored by other transports than UDP. See [`crate::congestion`].
+    pub fn set_congestion_control(&self, congestion: Option<CongestionControl>) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            udp.set_congestion_control(congestion);
+        }
+    }
+
+    /// Sizes packets by the path MTU probed for, instead of the fixed size
+    /// of the Go transport; None turns it off. Ignored by other transports
+    /// than UDP. See [`crate::pmtu`].
+    pub fn set_path_mtu(&self, path_mtu: Option<PathMtu>) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            udp.set_path_mtu(path_mtu);
+        }
+    }
+
+    /// Whether requests carry their extension block, with the deadline,
+    /// metadata and compression of a call, before the server has sent one;
+    /// on by default. Turn it off for a Go server, which rejects packets
+    /// with one: calls then go without those fields, and streams, which
+    /// need them, do not work. Ignored by other transports than UDP.
+    pub fn set_extensions(&self, on: bool) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            udp.set_extensions(on);
+        }
+    }
+
+    /// Drives the UDP socket with an io_uring instead of tokio, or with
+    /// tokio again with None. Fails, staying on tokio, where the kernel has
+    /// no io_uring. Ignored by other transports than UDP.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn set_io_uring(&self, io_uring: Option<crate::IoUring>) -> Result<(), Error> {
+        match self.inner.udp.as_deref() {
+            Some(udp) => udp.set_io_uring(io_uring),
+            None => Ok(()),
+        }
+    }
+
+    /// The size of the packets sent to the server.
+    pub fn path_mtu(&self) -> usize {
+        self.inner
+            .udp
+            .as_deref()
+            .map_or(MAX_PACKET_LEN, |udp| udp.path_mtu(self.inner.target))
+    }
+
+    /// Pings the server while the session is quiet, keeping NAT bindings
+    /// open and noticing when it stops answering, and tears the session
+    /// down once it goes without calls; None turns it off. Ignored by other
+    /// transports than UDP. See [`crate::keepalive`].
+    pub fn set_keepalive(&self, keepalive: Option<Keepalive>) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            udp.set_keepalive(keepalive);
+        }
+    }
+
+    /// Calls `f` whenever keepalives tear the session with the server down.
+    /// Calls made afterwards start another.
+    pub fn on_session_closed(&self, f: impl Fn(SessionClosed) + Send + Sync + 'static) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            let target = self.inner.target;
+            udp.on_session_closed(move |peer, reason| {
+                if peer == target {
+                    f(reason);
+                }
+            });
+        }
+    }
+
+    /// Sets how datagrams are batched into system calls on Linux, or sends
+    /// and receives them one at a time with None; batching is on by
+    /// default. Ignored by other transports than UDP. See
+    /// [`crate::Batching`].
+    pub fn set_batching(&self, batching: Option<Batching>) {
+        if let Some(udp) = self.inner.udp.as_deref() {
+            udp.set_batching(batching);
+        }
+    }
+
+    /// Counts of the batched system calls.
+    pub fn batch_stats(&self) -> BatchStats {
+        self.inner
+            .udp
+            .as_deref()
+            .map(UdpTransport::batch_stats)
+            .unwrap_or_default()
+    }
+
+    /// Counts of the reliability mode.
+    pub fn reliability_stats(&self) -> ReliabilityStats {
+        self.inner
+            .udp
+            .as_deref()
+            .map(UdpTransport::reliability_stats)
+            .unwrap_or_default()
+    }
+
+    /// Bounds the responses being reassembled from fragments.
+    pub fn set_reassembly_limits(&self, limits: ReassemblyLimits) {
+        self.inner.transport.set_reassembly_limits(limits);
+    }
+
+    /// Chooses how calls get their RPC IDs.
+    pub fn set_id_strategy(&self, strategy: IdStrategy) {
+        self.inner.calls.set_strategy(strategy);
+    }
+
+    /// How long a call waits for its reply before failing with
+    /// [`Error::TimedOut`]; None waits forever. Defaults to 30 seconds.
+    pub fn set_call_timeout(&self, timeout: Option<Duration>) {
+        self.inner.calls.set_timeout(timeout);
+    }
+
+    /// Paces the frames of streams with `control`; None sends and takes
+    /// them as fast as they come.
+    pub fn set_flow_control(&self, control: Option<Arc<dyn FlowControl>>) {
+        *self.inner.flow.write().unwrap() = control;
+    }
+
+    /// Paces the frames of streams with flow-control windows, so that a
+    /// slow reader on either side bounds what is buffered for it; None
+    /// sends them as fast as they come. The server must enable them too.
+    /// See [`crate::window`].
+    pub fn set_flow_windows(&self, windows: Option<FlowWindows>) {
+        *self.inner.windows.write().unwrap() = windows.map(|config| Arc::new(Windows::new(config)));
+    }
+
+    /// Compresses requests with a codec the server takes, and takes
+    /// compressed responses; None sends and takes them as they are. See
+    /// [`crate::compression`].
+    pub fn set_compression(&self, compression: Option<Compression>) {
+        *self.inner.compression.write().unwrap() = compression.map(Arc::new);
+    }
+
+    /// Adds `interceptor` to those wrapping unary calls, after the ones
+    /// added before; see [`crate::interceptor`].
+    pub fn add_interceptor(&self, interceptor: impl Interceptor) {
+        let mut chain = self.inner.interceptors.write().unwrap();
+        let mut interceptors = chain.to_vec();
+        interceptors.push(Arc::new(interceptor));
+        *chain = interceptors.into();
+    }
+
+    /// Counts of calls in flight, and of how earlier ones ended.
+    pub fn correlation_stats(&self) -> CorrelationStats {
+        self.inner.calls.stats()
+    }
+
+    /// The server this client calls.
+    pub fn target(&self) -> SocketAddrV4 {
+        self.inner.target
+    }
+
+    /// How many calls in a row failed without a reply: timed out, or could
+    /// not be sent. A reply, even an error packet, resets it.
+    pub fn failures(&self) -> u32 {
+        self.inner.failures.load(Ordering::Relaxed)
+    }
+
+    /// Whether the server said it is shutting down, turning new calls
+    /// away; a [`crate::Balancer`] leaves it out of its picks from then on.
+    /// See [`crate::Shutdown`].
+    pub fn going_away(&self) -> bool {
+        self.inner.going_away.load(Ordering::Relaxed)
+    }
+
+    /// Adds a service to the registry.
+    pub fn register_service(&self, name: &str, id: u32, methods: &[(&str, u32)]) {
+        self.inner
+            .registry
+            .write()
+            .unwrap()
+            .register_service(name, id, methods);
+    }
+
+    /// Calls `service.method` with `req` and waits for the response.
+    pub async fn call<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+    ) -> Result<Resp, Error> {
+        self.call_with(service, method, req, &CallOptions::default())
+            .await
+    }
+
+    /// Calls `service.method` with `req` under `options` and waits for the
+    /// response.
+    pub async fn call_with<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+        options: &CallOptions,
+    ) -> Result<Resp, Error> {
+        let (resp, _) = self
+            .call_with_metadata(service, method, req, options)
+            .await?;
+        Ok(resp)
+    }
+
+    /// Calls like [`Client::call_with`], also returning the metadata the
+    /// server sent with the response.
+    pub async fn call_with_metadata<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+        options: &CallOptions,
+    ) -> Result<(Resp, MetadataMap), Error> {
+        let request = Request {
+            service: service.to_string(),
+            method: method.to_string(),
+            peer: self.inner.target,
+            message: req.marshal_symphony(),
+            options: options.clone(),
+        };
+        let chain = self.inner.interceptors.read().unwrap().clone();
+        let inner = self.inner.clone();
+        let next = Next::new(chain, move |request| {
+            Box::pin(inner.clone().exchange(request))
+        });
+        let reply = next.run(request).await;
+        self.record(&reply);
+        let reply = reply?;
+        let resp = Resp::unmarshal_symphony_bytes(buffer::share(reply.message))?;
+        Ok((resp, reply.metadata))
+    }
+
+    /// Counts a call that got no reply towards [`Client::failures`], or
+    /// resets the count.
+    fn record(&self, reply: &Result<Response, Error>) {
+        match reply {
+            Err(Error::TimedOut | Error::Closed | Error::Io(_)) => {
+                self.inner.failures.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(_) | Err(Error::Status(_)) => self.inner.failures.store(0, Ordering::Relaxed),
+            Err(_) => {}
+        }
+    }
+
+    /// Calls server-streaming method `service.method` with `req`, returning
+    /// the stream of responses once the request is sent.
+    ///
+    /// The call timeout does not apply; a timeout in `options` bounds the
+    /// whole stream.
+    pub async fn server_streaming<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+        options: &CallOptions,
+    ) -> Result<Streaming<Resp>, Error> {
+        let message = self.request(service, method, req)?;
+        let (deadline, mut extensions) = deadline(options)?;
+        let message = self
+            .inner
+            .encode(message, options.compression, &mut extensions);
+        let rpc_id = self.inner.calls.next_id();
+        let frames = self.inner.streams.open(rpc_id);
+        let inner = self.inner.clone();
+        // Closes the stream if sending fails, too.
+        let inflow = Inflow::new(frames, move || inner.streams.close(rpc_id))
+            .with_flow_control(rpc_id, self.inner.flow());
+        let (inflow, window) = self.inner.open_window(inflow, rpc_id);
+        extensions.window = window;
+        let responses = Streaming::new(inflow, deadline);
+        self.inner
+            .transport
+            .send_message(
+                self.inner.target,
+                PacketType::Request,
+                rpc_id,
+                &message,
+                &extensions,
+            )
+            .await?;
+        Ok(responses)
+    }
+
+    /// Calls client-streaming method `service.method`, sending the requests
+    /// of `requests` as they come, and waits for the response.
+    ///
+    /// The call timeout runs from the end of the stream; a timeout in
+    /// `options` bounds the whole call.
+    pub async fn client_streaming<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        requests: impl Stream<Item = Req>,
+        options: &CallOptions,
+    ) -> Result<Resp, Error> {
+        let reply = self.send_stream(service, method, requests, options).await;
+        self.record(&reply);
+        Ok(Resp::unmarshal_symphony_bytes(buffer::share(
+            reply?.message,
+        ))?)
+    }
+
+    /// Sends the requests as the frames of a stream, then the frame ending
+    /// it, and waits for the reply message.
+    async fn send_stream<Req: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        requests: impl Stream<Item = Req>,
+        options: &CallOptions,
+    ) -> Result<Response, Error> {
+        let ids = self.inner.method_ids(service, method)?;
+        let (deadline, extensions) = deadline(options)?;
+        let calls = &self.inner.calls;
+        let (rpc_id, rx) = calls.register();
+        let _pending = Pending {
+            inner: &self.inner,
+            rpc_id,
+        };
+        let mut sender = StreamSender::new(
+            self.inner.clone(),
+            rpc_id,
+            ids,
+            extensions,
+            options.compression,
+        );
+        let exchange = async {
+            let mut requests = pin!(requests);
+            while let Some(req) = poll_fn(|cx| requests.as_mut().poll_next(cx)).await {
+                sender.send(&req).await?;
+            }
+            sender.finish().await?;
+            let reply = async { rx.await.map_err(|_| Error::Closed)? };
+            match calls.timeout() {
+                Some(timeout) => tokio::time::timeout(timeout, reply)
+                    .await
+                    .unwrap_or(Err(Error::TimedOut)),
+                None => reply.await,
+            }
+        };
+        let reply = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), exchange)
+                .await
+                .unwrap_or(Err(Error::TimedOut)),
+            None => exchange.await,
+        };
+        if let Err(Error::TimedOut) = reply {
+            trace::timed_out(self.inner.target, rpc_id);
+            calls.expire(rpc_id);
+            self.inner.cancel(rpc_id);
+        }
+        reply
+    }
+
+    /// Calls bidi-streaming method `service.method`, returning the half
+    /// sending requests and the stream of responses.
+    ///
+    /// The server starts the handler on the first request frame, so a call
+    /// with no requests must still [`StreamSender::finish`] them. A timeout
+    /// in `options` bounds the stream of responses.
+    pub fn bidi_streaming<Req: Message, Resp: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        options: &CallOptions,
+    ) -> Result<(StreamSender<Req>, Streaming<Resp>), Error> {
+        let ids = self.inner.method_ids(service, method)?;
+        let (deadline, extensions) = deadline(options)?;
+        let rpc_id = self.inner.calls.next_id();
+        let frames = self.inner.streams.open(rpc_id);
+        let inner = self.inner.clone();
+        let inflow = Inflow::new(frames, move || inner.streams.close(rpc_id))
+            .with_flow_control(rpc_id, self.inner.flow());
+        let (inflow, _) = self.inner.open_window(inflow, rpc_id);
+        Ok((
+            StreamSender::new(
+                self.inner.clone(),
+                rpc_id,
+                ids,
+                extensions,
+                options.compression,
+            ),
+            Streaming::new(inflow, deadline),
+        ))
+    }
+
+    /// Marshals `req` for `service.method`, with their IDs in its header.
+    fn request<Req: Message>(
+        &self,
+        service: &str,
+        method: &str,
+        req: &Req,
+    ) -> Result<Vec<u8>, Error> {
+        stamp(
+            req.marshal_symphony(),
+            self.inner.method_ids(service, method)?,
+        )
+    }
+}
+
+impl Inner {
+    /// Sends the request and waits for the reply message.
+    async fn exchange(self: Arc<Self>, request: Request) -> Result<Response, Error> {
+        let ids = self.method_ids(&request.service, &request.method)?;
+        let message = stamp(request.message, ids)?;
+        let (deadline, mut extensions) = deadline(&request.options)?;
+        let message = self.encode(message, request.options.compression, &mut extensions);
+        let calls = &self.calls;
+        let mut timeout = calls.timeout();
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            timeout = Some(timeout.map_or(left, |timeout| timeout.min(left)));
+        }
+        // Registered before sending so that a fast reply is not dropped.
+        let (rpc_id, rx) = calls.register();
+        let _pending = Pending {
+            inner: &self,
+            rpc_id,
+        };
+        let rpc = trace::Rpc::client(
+            &request.service,
+            &request.method,
+            rpc_id,
+            self.target,
+            message.len(),
+        );
+        let reply = async {
+            let sent = self
+                .transport
+                .send_message(
+                    self.target,
+                    PacketType::Request,
+                    rpc_id,
+                    &message,
+                    &extensions,
+                )
+                .await;
+            buffer::give(message);
+            sent?;
+            rx.await.map_err(|_| Error::Closed)?
+        };
+        let reply = rpc
+            .run(async {
+                match timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, reply).await {
+                        Ok(reply) => reply,
+                        Err(_) => {
+                            trace::timed_out(self.target, rpc_id);
+                            calls.expire(rpc_id);
+                            self.cancel(rpc_id);
+                            Err(Error::TimedOut)
+                        }
+                    },
+                    None => reply.await,
+                }
+            })
+            .await;
+        rpc.finish(reply.as_ref().map(|reply| reply.message.len()));
+        reply
+    }
+
+    /// Tells the server that the call `rpc_id` was given up on, so that it
+    /// stops the handler; see [`crate::cancel`].
+    fn cancel(self: &Arc<Self>, rpc_id: u64) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let inner = self.clone();
+        runtime.spawn(async move {
+            let extensions = Extensions {
+                cancel: Some(rpc_id),
+                ..Extensions::default()
+            };
+            if let Err(e) = send_frame(&inner, &[], &extensions).await {
+                log::debug!("failed to send cancel: {}", e);
+            }
+        });
+    }
+
+    /// The service and method IDs of `service.method`.
+    fn method_ids(&self, service: &str, method: &str) -> Result<(u32, u32), Error> {
+        let registry = self.registry.read().unwrap();
+        let service_id = registry
+            .service_id(service)
+            .ok_or_else(|| Error::UnknownService(service.to_string()))?;
+        let method_id =
+            registry
+                .method_id(service, method)
+                .ok_or_else(|| Error::UnknownMethod {
+                    service: service.to_string(),
+                    method: method.to_string(),
+                })?;
+        Ok((service_id, method_id))
+    }
+
+    fn flow(&self) -> Option<Arc<dyn FlowControl>> {
+        self.flow.read().unwrap().clone()
+    }
+
+    fn windows(&self) -> Option<Arc<Windows>> {
+        self.windows.read().unwrap().clone()
+    }
+
+    /// Opens the window of the responses of stream `rpc_id`, if windows
+    /// are on, returning the first credit granted.
+    fn open_window(
+        self: &Arc<Self>,
+        inflow: Inflow,
+        rpc_id: u64,
+    ) -> (Inflow, Option<WindowUpdate>) {
+        let Some(windows) = self.windows() else {
+            return (inflow, None);
+        };
+        let limit = windows.open(self.target, rpc_id);
+        let inner = self.clone();
+        let send = move |update| {
+            let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+                return;
+            };
+            let inner = inner.clone();
+            runtime.spawn(async move {
+                let extensions = Extensions {
+                    window: Some(update),
+                    ..Extensions::default()
+                };
+                if let Err(e) = send_frame(&inner, &[], &extensions).await {
+                    log::debug!("failed to send window update: {}", e);
+                }
+            });
+        };
+        let inflow = inflow.with_window(Some(windows), self.target, rpc_id, send);
+        (inflow, Some(WindowUpdate { id: rpc_id, limit }))
+    }
+
+    /// Compresses a request with `codec`, or the preferred codec, if the
+    /// server takes it.
+    fn encode(
+        &self,
+        message: Vec<u8>,
+        codec: Option<Codec>,
+        extensions: &mut Extensions,
+    ) -> Vec<u8> {
+        let Some(compression) = self.compression.read().unwrap().clone() else {
+            return message;
+        };
+        let accepted = self.accepted.read().unwrap();
+        let codec = match codec {
+            Some(codec) if accepted.contains(&codec) => codec,
+            Some(_) => Codec::Identity,
+            None => compression.negotiate(&accepted),
+        };
+        drop(accepted);
+        compression.encode(message, codec, extensions)
+    }
+
+    /// Decompresses a response, noting the codecs the server takes.
+    fn decode(
+        &self,
+        message: Vec<u8>,
+        encoding: Option<Encoding>,
+        accept: Vec<Codec>,
+    ) -> Result<Vec<u8>, Error> {
+        if !accept.is_empty() && *self.accepted.read().unwrap() != accept {
+            *self.accepted.write().unwrap() = accept;
+        }
+        let compression = self.compression.read().unwrap().clone();
+        compression::decode(compression.as_deref(), message, encoding)
+    }
+}
+
+/// The half of a stream sending requests to the server.
+///
+/// Dropping it before [`StreamSender::finish`] ends the stream all the
+/// same, from a task of its own.
+pub struct StreamSender<T> {
+    inner: Arc<Inner>,
+    stream: u64,
+    ids: (u32, u32),
+    extensions: Extensions,
+    codec: Option<Codec>,
+    windows: Option<Arc<Windows>>,
+    seq: u32,
+    ended: bool,
+    _message: PhantomData<fn(&T)>,
+}
+
+impl<T: Message> StreamSender<T> {
+    fn new(
+        inner: Arc<Inner>,
+        stream: u64,
+        ids: (u32, u32),
+        extensions: Extensions,
+        codec: Option<Codec>,
+    ) -> StreamSender<T> {
+        let windows = inner.windows();
+        if let Some(windows) = &windows {
+            windows.start(inner.target, stream, 0);
+        }
+        StreamSender {
+            inner,
+            stream,
+            ids,
+            extensions,
+            codec,
+            windows,
+            seq: 0,
+            ended: false,
+            _message: PhantomData,
+        }
+    }
+
+    /// Sends `req` as the next frame, once the flow control and the
+    /// stream's window let it.
+    pub async fn send(&mut self, req: &T) -> Result<(), Error> {
+        if self.ended {
+            return Err(Error::Closed);
+        }
+        let message = stamp(req.marshal_symphony(), self.ids)?;
+        if let Some(control) = self.inner.flow() {
+            control.ready(self.stream, self.seq).await;
+        }
+        if let Some(windows) = &self.windows {
+            windows
+                .reserve(self.inner.target, self.stream, message.len())
+                .await;
+        }
+        self.frame(message, false).await
+    }
+
+    /// Ends the stream of requests.
+    pub async fn finish(mut self) -> Result<(), Error> {
+        self.frame(header(self.ids), true).await
+    }
+
+    async fn frame(&mut self, message: Vec<u8>, end: bool) -> Result<(), Error> {
+        // The server dispatches on the header of whichever frame arrives
+        // first, so even the last one has it.
+        self.ended = end;
+        self.extensions.stream = Some(StreamFrame {
+            id: self.stream,
+            seq: self.seq,
+            end,
+        });
+        self.seq += 1;
+        self.extensions.encoding = None;
+        // The credit of the responses of a bidi stream rides along.
+        self.extensions.window = self.windows.as_ref().and_then(|windows| {
+            let limit = windows.limit(self.inner.target, self.stream)?;
+            Some(WindowUpdate {
+                id: self.stream,
+                limit,
+            })
+        });
+        let message = self.inner.encode(message, self.codec, &mut self.extensions);
+        let sent = send_frame(&self.inner, &message, &self.extensions).await;
+        buffer::give(message);
+        sent
+    }
+}
+
+impl<T> Drop for StreamSender<T> {
+    fn drop(&mut self) {
+        if let Some(windows) = &self.windows {
+            windows.finish(self.inner.target, self.stream);
+        }
+        if self.ended {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let inner = self.inner.clone();
+        let message = header(self.ids);
+        let mut extensions = self.extensions.clone();
+        extensions.encoding = None;
+        extensions.window = None;
+        extensions.stream = Some(StreamFrame {
+            id: self.stream,
+            seq: self.seq,
+            end: true,
+        });
+        runtime.spawn(async move {
+            if let Err(e) = send_frame(&inner, &message, &extensions).await {
+                log::debug!("failed to end stream: {}", e);
+            }
+        });
+    }
+}
+
+/// Sends a frame of a request stream under a fresh RPC ID.
+async fn send_frame(inner: &Inner, message: &[u8], extensions: &Extensions) -> Result<(), Error> {
+    inner
+        .transport
+        .send_message(
+            inner.target,
+            PacketType::Request,
+            inner.calls.next_id(),
+            message,
+            extensions,
+        )
+        .await
+}
+
+/// Puts the service and method IDs in the header of `message`.
+fn stamp(mut message: Vec<u8>, (service_id, method_id): (u32, u32)) -> Result<Vec<u8>, Error> {
+    if !symphony_wire::set_message_ids(&mut message, service_id, method_id) {
+        return Err(Error::MissingHeader);
+    }
+    Ok(message)
+}
+
+/// A message with only a header, addressed to `service.method`.
+fn header(ids: (u32, u32)) -> Vec<u8> {
+    let mut message = vec![0; MESSAGE_HEADER_LEN];
+    message[0] = SYMPHONY_VERSION;
+    message[1..5].copy_from_slice(&(MESSAGE_HEADER_LEN as u32).to_le_bytes());
+    stamp(message, ids).unwrap()
+}
+
+/// The deadline of a call under `options`, and the extensions sending it
+/// and the metadata. Fails if the deadline already passed.
+fn deadline(options: &CallOptions) -> Result<(Option<Instant>, Extensions), Error> {
+    let deadline = deadline::inherit(options.timeout.map(|timeout| Instant::now() + timeout));
+    let mut extensions = Extensions {
+        metadata: options.metadata.clone(),
+        ..Extensions::default()
+    };
+    if let Some(deadline) = deadline {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(Error::TimedOut);
+        }
+        extensions.timeout = Some(left);
+    }
+    Ok((deadline, extensions))
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+/// Removes a call from the correlation table if its future is dropped
+/// before the reply arrives, and tells the server.
+struct Pending<'a> {
+    inner: &'a Arc<Inner>,
+    rpc_id: u64,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if self.inner.calls.cancel(self.rpc_id) {
+            self.inner.cancel(self.rpc_id);
+        }
+    }
+}
+
+async fn receive_loop(inner: Arc<Inner>) {
+    loop {
+        let received = match inner.transport.recv_message().await {
+            Ok(Some(received)) => received,
+            Ok(None) => continue,
+            Err(e) => {
+                log::warn!("failed to receive: {}", e);
+                continue;
+            }
+        };
+        let Received {
+            kind,
+            rpc_id,
+            src,
+            payload,
+            extensions,
+        } = received;
+        // A window update carries nothing else, and neither does a GOAWAY.
+        if let Some(update) = extensions.window {
+            if let Some(windows) = inner.windows() {
+                windows.update(inner.target, update);
+            }
+            continue;
+        }
+        if extensions.goaway {
+            if !inner.going_away.swap(true, Ordering::Relaxed) {
+                log::info!("server {} is shutting down", inner.target);
+            }
+            continue;
+        }
+        if let (PacketType::Response, Some(frame)) = (kind, extensions.stream) {
+            let payload = inner.decode(payload, extensions.encoding, extensions.accept);
+            let delivered = match payload {
+                Ok(payload) => {
+                    let message = (!frame.end).then_some(payload);
+                    inner.streams.deliver(frame.id, frame.seq, message)
+                }
+                Err(e) => inner.streams.fail(frame.id, e).is_ok(),
+            };
+            if !delivered {
+                log::debug!("ignoring frame of stream {} not open", frame.id);
+            }
+            continue;
+        }
+        let reply = match kind {
+            PacketType::Response => inner
+                .decode(payload, extensions.encoding, extensions.accept)
+                .map(|message| Response {
+                    message,
+                    metadata: extensions.metadata,
+                }),
+            PacketType::Error | PacketType::Unknown => {
+                let error = Error::Status(Status::parse(kind, &payload));
+                // A failed stream, or a failed call.
+                match inner.streams.fail(rpc_id, error) {
+                    Ok(()) => continue,
+                    Err(error) => Err(error),
+                }
+            }
+            PacketType::Request => {
+                log::debug!("ignoring request {} from {}", rpc_id, src);
+                continue;
+            }
+        };
+        if !inner.calls.complete(rpc_id, reply) {
+            log::debug!("ignoring response {} with no pending call", rpc_id);
+        }
+    }
+}
+
+/// The first IPv4 address `target` resolves to.
+pub(crate) async fn resolve(target: impl ToSocketAddrs) -> Result<SocketAddrV4, Error> {
+    let addrs: Vec<SocketAddr> = lookup_host(target).await?.collect();
+    addrs
+        .iter()
+        .find_map(|addr| match addr {
+            SocketAddr::V4(addr) => Some(*addr),
+            SocketAddr::V6(_) => None,
+        })
+        .ok_or_else(|| Error::Unresolved(format!("{:?}", addrs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::Code;
+    use symphony_wire::{DataPacket, ErrorPacket, Packet};
+    use tokio::net::UdpSocket;
+
+    /// Answers one request with `reply`, or with an Error packet if the
+    /// request is not for service 1, method 2.
+    async fn serve_one(server: UdpSocket, reply: &str) {
+        let mut buf = [0; 2048];
+        let (n, _) = server.recv_from(&mut buf).await.unwrap();
+        let Packet::Data(request) = symphony_wire::parse(&buf[..n]).unwrap() else {
+            panic!("expected a data packet");
+        };
+        let header = symphony_wire::parse_message_header(request.payload).unwrap();
+        let mut out = Vec::new();
+        if (header.service_id, header.method_id) == (1, 2) {
+            let payload = Text::new(reply).marshal_symphony();
+            Packet::Data(DataPacket {
+                kind: PacketType::Response,
+                dst: request.src,
+                src: request.dst,
+                payload: &payload,
+                ..request
+            })
+            .encode_into(&mut out);
+        } else {
+            Packet::Error(ErrorPacket {
+                kind: PacketType::Error,
+                rpc_id: request.rpc_id,
+                dst: request.src,
+                src: request.dst,
+                message: b"unknown service",
+            })
+            .encode_into(&mut out);
+        }
+        server.send_to(&out, request.src).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn call_round_trips() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let served = tokio::spawn(async move { serve_one(server, "pong").await });
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("Echo", 1, &[("Echo", 2), ("Other", 3)]);
+        let resp: Text = client
+            .call("Echo", "Echo", &Text::new("ping"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("pong"));
+        served.await.unwrap();
+
+        assert!(matches!(
+            client
+                .call::<_, Text>("Missing", "Echo", &Text::new(""))
+                .await,
+            Err(Error::UnknownService(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn call_reports_server_errors() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let served = tokio::spawn(async move { serve_one(server, "").await });
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("Echo", 1, &[("Other", 3)]);
+        let err = client
+            .call::<_, Text>("Echo", "Other", &Text::new(""))
+            .await
+            .unwrap_err();
+        served.await.unwrap();
+        match err {
+            // A plain error packet, as the Go server sends.
+            Error::Status(status) => {
+                assert_eq!(status, Status::new(Code::Aborted, "unknown service"));
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn call_times_out() {
+        // Bound but never answering.
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::connect(server.local_addr().unwrap()).await.unwrap();
+        client.register_service("Echo", 1, &[("Echo", 2)]);
+        client.set_call_timeout(Some(Duration::from_millis(50)));
+        let err = client
+            .call::<_, Text>("Echo", "Echo", &Text::new(""))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::TimedOut), "unexpected error: {}", err);
+        let stats = client.correlation_stats();
+        assert_eq!((stats.outstanding, stats.expired), (0, 1));
+    }
+}
@@ -0,0 +1,304 @@
+//! Compression of the messages of calls, with codecs the two sides
+//! negotiate.
+//!
+//! A client or server with [`Compression`] set lists the codecs it takes
+//! in the extension block of every message it sends. A client learns the
+//! server's from its first response, sending its requests as they are
+//! until then, and compresses later ones with the first of its codecs the
+//! server takes; [`crate::CallOptions::compression`] picks another for
+//! one call. A server compresses its responses with the first of its
+//! codecs the request listed. A compressed message carries its codec and
+//! its length once decompressed, so that one decompressing to more than
+//! [`Compression::max_decompressed_len`] is refused before it is.
+//!
+//! Codecs come with features: `zstd` and `lz4`.
+
+use crate::extensions::Extensions;
+use crate::{buffer, Code, Error, Status};
+
+/// How a message is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// Not compressed.
+    Identity,
+    #[cfg(feature = "zstd")]
+    Zstd,
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+/// The codec and decompressed length of a compressed message, from its
+/// extension block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Encoding {
+    /// A codec ID, which this side may not know.
+    pub codec: u8,
+    pub len: u32,
+}
+
+impl Codec {
+    /// The ID of the codec on the wire.
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Codec::Identity => 0,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => 1,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => 2,
+        }
+    }
+
+    /// The codec of `id`, if this build has it.
+    pub(crate) fn from_id(id: u8) -> Option<Codec> {
+        match id {
+            0 => Some(Codec::Identity),
+            #[cfg(feature = "zstd")]
+            1 => Some(Codec::Zstd),
+            #[cfg(feature = "lz4")]
+            2 => Some(Codec::Lz4),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Identity => "identity",
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => "zstd",
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => "lz4",
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+    fn compress(self, message: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Codec::Identity => None,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::bulk::compress(message, zstd::DEFAULT_COMPRESSION_LEVEL)
+                .map_err(|e| log::warn!("failed to compress with zstd: {}", e))
+                .ok(),
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => Some(lz4_flex::block::compress(message)),
+        }
+    }
+
+    /// Decompresses `data` into exactly `len` bytes, writing no more.
+    fn decompress(self, data: &[u8], len: usize) -> Result<Vec<u8>, String> {
+        let message = match self {
+            Codec::Identity => data.to_vec(),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::bulk::decompress(data, len).map_err(|e| e.to_string())?,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => lz4_flex::block::decompress(data, len).map_err(|e| e.to_string())?,
+        };
+        if message.len() != len {
+            return Err(format!("{} bytes, not {}", message.len(), len));
+        }
+        Ok(message)
+    }
+}
+
+/// Compression settings of a client or server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+    /// The codecs messages are taken in, and sent with by preference.
+    pub codecs: Vec<Codec>,
+    /// Messages shorter than this are sent as they are.
+    pub min_len: usize,
+    /// The longest a received message may decompress to. Longer ones are
+    /// refused with [`Code::ResourceExhausted`].
+    pub max_decompressed_len: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            codecs: vec![
+                #[cfg(feature = "zstd")]
+                Codec::Zstd,
+                #[cfg(feature = "lz4")]
+                Codec::Lz4,
+            ],
+            min_len: 512,
+            max_decompressed_len: 4 << 20,
+        }
+    }
+}
+
+impl Compression {
+    /// The first of the codecs the peer, taking `accepted`, takes too.
+    pub(crate) fn negotiate(&self, accepted: &[Codec]) -> Codec {
+        self.codecs
+            .iter()
+            .copied()
+            .find(|codec| *codec != Codec::Identity && accepted.contains(codec))
+            .unwrap_or(Codec::Identity)
+    }
+
+    /// Compresses `message` with `codec`, setting its encoding and the
+    /// codecs taken in `extensions`. Returns `message` itself if it is
+    /// short, or does not get shorter.
+    pub(crate) fn encode(
+        &self,
+        message: Vec<u8>,
+        codec: Codec,
+        extensions: &mut Extensions,
+    ) -> Vec<u8> {
+        extensions.accept = self.codecs.clone();
+        if message.len() < self.min_len {
+            return message;
+        }
+        let Ok(len) = u32::try_from(message.len()) else {
+            return message;
+        };
+        match codec.compress(&message) {
+            Some(compressed) if compressed.len() < message.len() => {
+                extensions.encoding = Some(Encoding {
+                    codec: codec.id(),
+                    len,
+                });
+                buffer::give(message);
+                compressed
+            }
+            _ => message,
+        }
+    }
+}
+
+/// Decompresses a message received with `encoding`, under the settings of
+/// the side receiving it.
+pub(crate) fn decode(
+    compression: Option<&Compression>,
+    message: Vec<u8>,
+    encoding: Option<Encoding>,
+) -> Result<Vec<u8>, Error> {
+    let Some(encoding) = encoding else {
+        return Ok(message);
+    };
+    let codec = Codec::from_id(encoding.codec)
+        .filter(|codec| compression.is_some_and(|c| c.codecs.contains(codec)))
+        .ok_or_else(|| {
+            Status::new(
+                Code::Unimplemented,
+                format!(
+                    "message compressed with unsupported codec {}",
+                    encoding.codec
+                ),
+            )
+        })?;
+    let max = compression.map_or(0, |c| c.max_decompressed_len);
+    let len = encoding.len as usize;
+    if len > max {
+        return Err(Status::new(
+            Code::ResourceExhausted,
+            format!("message decompresses to {} bytes, over {}", len, max),
+        )
+        .into());
+    }
+    let decompressed = codec.decompress(&message, len).map_err(|e| {
+        Status::new(
+            Code::InvalidArgument,
+            format!("failed to decompress {} message: {}", codec.name(), e),
+        )
+    })?;
+    buffer::give(message);
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_and_bounds_decompression() {
+        let compression = Compression::default();
+        assert_eq!(compression.negotiate(&[]), Codec::Identity);
+        let short = compression.encode(vec![7; 10], Codec::Identity, &mut Extensions::default());
+        assert_eq!(short, [7; 10]);
+
+        for codec in compression.codecs.clone() {
+            assert_eq!(compression.negotiate(&[Codec::Identity, codec]), codec);
+            let mut extensions = Extensions::default();
+            let message = b"compressible ".repeat(100);
+            let compressed = compression.encode(message.clone(), codec, &mut extensions);
+            assert!(compressed.len() < message.len(), "{}", codec.name());
+            assert_eq!(extensions.accept, compression.codecs);
+            let encoding = extensions.encoding;
+            assert_eq!(
+                decode(Some(&compression), compressed.clone(), encoding).unwrap(),
+                message
+            );
+
+            let small = Compression {
+                max_decompressed_len: 1000,
+                ..Compression::default()
+            };
+            let refused = [
+                (Some(&small), Code::ResourceExhausted),
+                (None, Code::Unimplemented),
+            ];
+            for (compression, code) in refused {
+                match decode(compression, compressed.clone(), encoding) {
+                    Err(Error::Status(status)) => assert_eq!(status.code(), code),
+                    other => panic!("unexpected result: {:?}", other),
+                }
+            }
+            let lying = Some(Encoding {
+                len: 5000,
+                ..encoding.unwrap()
+            });
+            match decode(Some(&compression), compressed, lying) {
+                Err(Error::Status(status)) => assert_eq!(status.code(), Code::InvalidArgument),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    #[tokio::test]
+    async fn compresses_once_negotiated() {
+        use crate::testing::Text;
+        use crate::{CallOptions, Client, Server, ServiceDesc};
+
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("EchoService", 1).method(
+            "Echo",
+            1,
+            |req: Text| async move { Ok::<_, Error>(Text(req.0.to_uppercase())) },
+        ));
+        server.set_compression(Some(Compression {
+            max_decompressed_len: 1000,
+            ..Compression::default()
+        }));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        client.set_compression(Some(Compression::default()));
+        let long = Text::new(&"squeeze ".repeat(1000));
+        let echo = |options: CallOptions| {
+            let (client, long) = (&client, &long);
+            async move {
+                client
+                    .call_with::<_, Text>("EchoService", "Echo", long, &options)
+                    .await
+            }
+        };
+        // Sent as is until the server's codecs are known; the response
+        // comes compressed.
+        let resp = echo(CallOptions::default()).await.unwrap();
+        assert_eq!(resp.0, long.0.to_uppercase());
+        // Now compressed, and refused by the server's limit.
+        match echo(CallOptions::default()).await {
+            Err(Error::Status(status)) => assert_eq!(status.code(), Code::ResourceExhausted),
+            other => panic!("unexpected result: {:?}", other.map(|t| t.0.len())),
+        }
+        let identity = CallOptions {
+            compression: Some(Codec::Identity),
+            ..CallOptions::default()
+        };
+        assert!(echo(identity).await.is_ok());
+    }
+}
@@ -0,0 +1,248 @@
+//! Congestion control of the reliability mode, which bounds the bytes a
+//! transport has in flight to each peer by a congestion window.
+//!
+//! Each peer gets a [`CongestionController`] of its own, made by the
+//! [`CongestionControl`] set on the transport. Packets beyond the window
+//! wait in the order they were sent, and go out as acknowledgements open
+//! it; retransmissions do not wait. The controller hears of every
+//! acknowledgement, with the round trip it measured, and of every
+//! retransmission timeout, which is the only loss signal of the
+//! reliability mode.
+//!
+//! [`NewReno`] (RFC 5681) and [`Cubic`] (RFC 9438) are built in; other
+//! algorithms plug in through [`CongestionControl::custom`].
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use symphony_wire::MAX_PACKET_LEN;
+
+/// The largest packet, the unit windows grow and shrink by.
+const MSS: usize = MAX_PACKET_LEN;
+
+/// The window before anything is acknowledged (RFC 6928).
+const INITIAL_WINDOW: usize = 10 * MSS;
+
+/// The smallest window after a loss.
+const MIN_WINDOW: usize = 2 * MSS;
+
+/// A congestion-control algorithm, governing what is in flight to one
+/// peer.
+pub trait CongestionController: Send + 'static {
+    /// The most bytes that may be in flight.
+    fn window(&self) -> usize;
+
+    /// Called as `bytes` are newly acknowledged, with the round trip they
+    /// measured; None if they were retransmitted (Karn's algorithm).
+    fn on_ack(&mut self, bytes: usize, rtt: Option<Duration>, now: Instant);
+
+    /// Called when packets first sent at `sent` went unacknowledged past
+    /// their retransmission timeout, with `in_flight` bytes in flight.
+    fn on_timeout(&mut self, sent: Instant, in_flight: usize, now: Instant);
+}
+
+/// Makes the congestion controller of each peer of a transport.
+#[derive(Clone)]
+pub struct CongestionControl(Arc<dyn Fn() -> Box<dyn CongestionController> + Send + Sync>);
+
+impl CongestionControl {
+    pub fn new_reno() -> CongestionControl {
+        CongestionControl::custom(|| Box::new(NewReno::default()))
+    }
+
+    pub fn cubic() -> CongestionControl {
+        CongestionControl::custom(|| Box::new(Cubic::default()))
+    }
+
+    /// Controls congestion with the controllers `new` makes.
+    pub fn custom(
+        new: impl Fn() -> Box<dyn CongestionController> + Send + Sync + 'static,
+    ) -> CongestionControl {
+        CongestionControl(Arc::new(new))
+    }
+
+    pub(crate) fn controller(&self) -> Box<dyn CongestionController> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for CongestionControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CongestionControl")
+    }
+}
+
+/// Slow start, then one packet more per window acknowledged, halving the
+/// window on loss (RFC 5681, without fast recovery, since losses are only
+/// seen through timeouts).
+#[derive(Debug, Clone)]
+pub struct NewReno {
+    window: usize,
+    ssthresh: usize,
+    // Losses of packets sent before the window was last cut belong to the
+    // same congestion event.
+    recovery: Option<Instant>,
+}
+
+impl Default for NewReno {
+    fn default() -> Self {
+        NewReno {
+            window: INITIAL_WINDOW,
+            ssthresh: usize::MAX,
+            recovery: None,
+        }
+    }
+}
+
+impl CongestionController for NewReno {
+    fn window(&self) -> usize {
+        self.window
+    }
+
+    fn on_ack(&mut self, bytes: usize, _rtt: Option<Duration>, _now: Instant) {
+        if self.window < self.ssthresh {
+            self.window += bytes;
+        } else {
+            self.window += (MSS * bytes / self.window).max(1);
+        }
+    }
+
+    fn on_timeout(&mut self, sent: Instant, in_flight: usize, now: Instant) {
+        if self.recovery.is_some_and(|recovery| sent <= recovery) {
+            return;
+        }
+        self.recovery = Some(now);
+        self.ssthresh = (in_flight / 2).max(MIN_WINDOW);
+        self.window = MSS;
+    }
+}
+
+/// Grows the window along a cubic function of the time since the last
+/// loss, centered on the window at that loss (RFC 9438), and at least as
+/// fast as [`NewReno`] would.
+#[derive(Debug, Clone)]
+pub struct Cubic {
+    window: usize,
+    ssthresh: usize,
+    recovery: Option<Instant>,
+    /// The window before the last loss, in packets.
+    w_max: f64,
+    /// When the current congestion-avoidance epoch began, and the window
+    /// the Reno-friendly estimate had then, in packets.
+    epoch: Option<(Instant, f64)>,
+    /// The smoothed round trip.
+    srtt: Option<Duration>,
+}
+
+/// Scales the cubic function (RFC 9438 §5.1).
+const CUBIC_C: f64 = 0.4;
+/// What is left of the window after a loss.
+const CUBIC_BETA: f64 = 0.7;
+
+impl Default for Cubic {
+    fn default() -> Self {
+        Cubic {
+            window: INITIAL_WINDOW,
+            ssthresh: usize::MAX,
+            recovery: None,
+            w_max: 0.0,
+            epoch: None,
+            srtt: None,
+        }
+    }
+}
+
+impl Cubic {
+    /// The window the cubic function targets `t` into the epoch, in
+    /// packets.
+    fn target(&self, t: Duration) -> f64 {
+        let k = (self.w_max * (1.0 - CUBIC_BETA) / CUBIC_C).cbrt();
+        CUBIC_C * (t.as_secs_f64() - k).powi(3) + self.w_max
+    }
+}
+
+impl CongestionController for Cubic {
+    fn window(&self) -> usize {
+        self.window
+    }
+
+    fn on_ack(&mut self, bytes: usize, rtt: Option<Duration>, now: Instant) {
+        if let Some(rtt) = rtt {
+            self.srtt = Some(self.srtt.map_or(rtt, |srtt| (srtt * 7 + rtt) / 8));
+        }
+        if self.window < self.ssthresh {
+            self.window += bytes;
+            return;
+        }
+        let window = self.window as f64 / MSS as f64;
+        let (start, w_est) = *self.epoch.get_or_insert((now, window));
+        let t = now.duration_since(start);
+        let acked = bytes as f64 / MSS as f64;
+        // The Reno-friendly estimate grows by this much per window acked.
+        let alpha = 3.0 * (1.0 - CUBIC_BETA) / (1.0 + CUBIC_BETA);
+        let w_est = w_est + alpha * acked / window;
+        self.epoch = Some((start, w_est));
+        let srtt = self.srtt.unwrap_or(Duration::from_millis(100));
+        let target = self.target(t + srtt).max(w_est).clamp(window, window * 1.5);
+        let grown = window + (target - window) / window * acked;
+        self.window = ((grown * MSS as f64) as usize).max(self.window);
+    }
+
+    fn on_timeout(&mut self, sent: Instant, _in_flight: usize, now: Instant) {
+        if self.recovery.is_some_and(|recovery| sent <= recovery) {
+            return;
+        }
+        self.recovery = Some(now);
+        let window = self.window as f64 / MSS as f64;
+        // Fast convergence: give up more to a flow that lost before
+        // reaching its last maximum.
+        self.w_max = if window < self.w_max {
+            window * (1.0 + CUBIC_BETA) / 2.0
+        } else {
+            window
+        };
+        self.ssthresh = ((self.window as f64 * CUBIC_BETA) as usize).max(MIN_WINDOW);
+        self.window = MSS;
+        self.epoch = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_and_backs_off() {
+        let now = Instant::now();
+        let rtt = Some(Duration::from_millis(10));
+        for control in [CongestionControl::new_reno(), CongestionControl::cubic()] {
+            let mut controller = control.controller();
+            assert_eq!(controller.window(), INITIAL_WINDOW);
+            // Slow start doubles the window per window acknowledged.
+            controller.on_ack(INITIAL_WINDOW, rtt, now);
+            assert_eq!(controller.window(), 2 * INITIAL_WINDOW);
+
+            let lost = now + Duration::from_millis(1);
+            controller.on_timeout(now, 2 * INITIAL_WINDOW, lost);
+            assert_eq!(controller.window(), MSS);
+            // Packets sent before the cut are the same loss.
+            controller.on_timeout(now, MSS, lost);
+            assert_eq!(controller.window(), MSS);
+
+            // Slow start up to the threshold, then slower.
+            let mut at = lost;
+            let mut acks = 0;
+            while controller.window() < INITIAL_WINDOW {
+                controller.on_ack(controller.window(), rtt, at);
+                at += Duration::from_millis(10);
+                acks += 1;
+            }
+            assert!(acks <= 5, "{} windows to recover", acks);
+            let window = controller.window();
+            controller.on_ack(window, rtt, at);
+            let growth = controller.window() - window;
+            assert!(growth > 0 && growth < window, "grew by {}", growth);
+        }
+    }
+}
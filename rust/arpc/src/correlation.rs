@@ -0,0 +1,225 @@
+//! RPC IDs of outgoing calls, and the table matching replies to the calls
+//! waiting for them (`pendingCalls` in `pkg/rpc/client.go`).
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::oneshot;
+
+use crate::interceptor::Response;
+use crate::Error;
+
+/// The outcome of a call as received: the response message, or the
+/// failure the server reported.
+pub(crate) type Reply = Result<Response, Error>;
+
+/// How calls get their RPC IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdStrategy {
+    /// Increasing from the clock at startup, like the Go client, so that a
+    /// restarted client does not reuse the IDs of its previous run.
+    #[default]
+    Sequential,
+    /// Random, so that IDs do not reveal the call rate or collide across
+    /// clients sharing a server.
+    Random,
+}
+
+/// Counts of the correlation table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CorrelationStats {
+    /// Calls waiting for their reply.
+    pub outstanding: usize,
+    /// Calls that received their reply.
+    pub completed: u64,
+    /// Calls that gave up waiting after the call timeout.
+    pub expired: u64,
+    /// Replies that matched no waiting call, e.g. late ones of expired
+    /// calls.
+    pub unmatched: u64,
+}
+
+pub(crate) struct Correlation {
+    state: Mutex<State>,
+}
+
+struct State {
+    strategy: IdStrategy,
+    next_id: u64,
+    // xorshift64 state of random IDs.
+    rng: u64,
+    timeout: Option<Duration>,
+    pending: HashMap<u64, oneshot::Sender<Reply>>,
+    stats: CorrelationStats,
+}
+
+/// How long calls wait for their reply by default.
+pub(crate) const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        loop {
+            let id = match self.strategy {
+                IdStrategy::Sequential => {
+                    let id = self.next_id;
+                    self.next_id = id.wrapping_add(1);
+                    id
+                }
+                IdStrategy::Random => {
+                    let mut x = self.rng;
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    self.rng = x;
+                    x
+                }
+            };
+            if id != 0 && !self.pending.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+}
+
+impl Correlation {
+    pub fn new() -> Correlation {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| d.as_nanos() as u64);
+        Correlation {
+            state: Mutex::new(State {
+                strategy: IdStrategy::default(),
+                next_id: now.max(1),
+                rng: now | 1,
+                timeout: Some(DEFAULT_CALL_TIMEOUT),
+                pending: HashMap::new(),
+                stats: CorrelationStats::default(),
+            }),
+        }
+    }
+
+    pub fn set_strategy(&self, strategy: IdStrategy) {
+        self.state.lock().unwrap().strategy = strategy;
+    }
+
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        self.state.lock().unwrap().timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.state.lock().unwrap().timeout
+    }
+
+    /// Registers a call under a fresh ID, never 0 nor the ID of another
+    /// waiting call. The reply arrives on the returned receiver.
+    pub fn register(&self) -> (u64, oneshot::Receiver<Reply>) {
+        let mut state = self.state.lock().unwrap();
+        let rpc_id = state.next_id();
+        let (tx, rx) = oneshot::channel();
+        state.pending.insert(rpc_id, tx);
+        state.stats.outstanding = state.pending.len();
+        (rpc_id, rx)
+    }
+
+    /// A fresh ID that no call waits on, for a message that expects no
+    /// reply of its own.
+    pub fn next_id(&self) -> u64 {
+        self.state.lock().unwrap().next_id()
+    }
+
+    /// Hands `reply` to the call waiting on `rpc_id`. Returns false if none
+    /// is.
+    pub fn complete(&self, rpc_id: u64, reply: Reply) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(tx) = state.pending.remove(&rpc_id) else {
+            state.stats.unmatched += 1;
+            return false;
+        };
+        state.stats.outstanding = state.pending.len();
+        state.stats.completed += 1;
+        // The caller may have given up in the meantime.
+        let _ = tx.send(reply);
+        true
+    }
+
+    /// Gives up on the call waiting on `rpc_id` after its timeout.
+    pub fn expire(&self, rpc_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.pending.remove(&rpc_id).is_some() {
+            state.stats.outstanding = state.pending.len();
+            state.stats.expired += 1;
+        }
+    }
+
+    /// Forgets the call waiting on `rpc_id`, e.g. because its future was
+    /// dropped. Returns false if it was not waiting.
+    pub fn cancel(&self, rpc_id: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let waiting = state.pending.remove(&rpc_id).is_some();
+        state.stats.outstanding = state.pending.len();
+        waiting
+    }
+
+    pub fn stats(&self) -> CorrelationStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_skip_calls_in_flight() {
+        let table = Correlation::new();
+        let (first, _rx) = table.register();
+        let (second, _rx) = table.register();
+        assert_eq!(second, first + 1);
+
+        // Wrapping around onto a waiting call skips it, and 0.
+        table.state.lock().unwrap().next_id = first;
+        let (third, _rx) = table.register();
+        assert_eq!(third, second + 1);
+        table.state.lock().unwrap().next_id = u64::MAX;
+        assert_eq!(table.register().0, u64::MAX);
+        assert_eq!(table.register().0, 1);
+
+        table.set_strategy(IdStrategy::Random);
+        let ids: Vec<_> = (0..100).map(|_| table.register().0).collect();
+        let mut unique = ids.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len());
+        assert!(!ids.contains(&0));
+    }
+
+    #[tokio::test]
+    async fn matches_replies_and_counts() {
+        let table = Correlation::new();
+        let (answered, rx) = table.register();
+        let (expired, _rx) = table.register();
+        let (cancelled, _rx) = table.register();
+        assert_eq!(table.stats().outstanding, 3);
+
+        let pong = Response {
+            message: b"pong".to_vec(),
+            ..Response::default()
+        };
+        assert!(table.complete(answered, Ok(pong)));
+        assert_eq!(rx.await.unwrap().unwrap().message, b"pong");
+        table.expire(expired);
+        table.cancel(cancelled);
+        assert!(!table.complete(expired, Ok(Response::default())));
+
+        assert_eq!(
+            table.stats(),
+            CorrelationStats {
+                outstanding: 0,
+                completed: 1,
+                expired: 1,
+                unmatched: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Deadlines of calls, propagated from client to server and on to the
+//! calls a handler makes, like `grpc-timeout`.
+//!
+//! A client sends the time left to its deadline with the request. The
+//! server takes the deadline as that much after the request arrived, stops
+//! the handler if it passes, and makes it the deadline of the handler's
+//! task, which the calls made from that task inherit.
+
+use std::future::Future;
+use std::time::Instant;
+
+tokio::task_local! {
+    static DEADLINE: Option<Instant>;
+}
+
+/// The deadline of the request the current task is handling, if it has
+/// one. Calls made from the task fail once it passes.
+pub fn current_deadline() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok().flatten()
+}
+
+/// The earlier of `deadline` and the current task's one.
+pub(crate) fn inherit(deadline: Option<Instant>) -> Option<Instant> {
+    match (deadline, current_deadline()) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Runs `f` with `deadline` as the task's deadline.
+pub(crate) fn enter<R>(deadline: Option<Instant>, f: impl FnOnce() -> R) -> R {
+    DEADLINE.sync_scope(deadline, f)
+}
+
+/// Runs `future` with `deadline` as the deadline of the task polling it.
+pub(crate) async fn scope<F: Future>(deadline: Option<Instant>, future: F) -> F::Output {
+    DEADLINE.scope(deadline, future).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn scopes_inherit_the_earliest_deadline() {
+        assert_eq!(current_deadline(), None);
+        let now = Instant::now();
+        let soon = now + Duration::from_secs(1);
+        let later = now + Duration::from_secs(2);
+        assert_eq!(inherit(Some(later)), Some(later));
+
+        let inner = scope(Some(soon), async {
+            (
+                current_deadline(),
+                inherit(Some(later)),
+                inherit(Some(now)),
+                inherit(None),
+            )
+        })
+        .await;
+        assert_eq!(inner, (Some(soon), Some(soon), Some(now), Some(soon)));
+        assert_eq!(enter(Some(later), current_deadline), Some(later));
+        assert_eq!(current_deadline(), None);
+    }
+}
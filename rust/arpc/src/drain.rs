@@ -0,0 +1,269 @@
+//! Graceful shutdown of a server, for rolling updates: once
+//! [`Shutdown::shutdown`] is called, the server turns new calls away as
+//! unavailable, tells the clients it heard from to go elsewhere, lets the
+//! handlers it started finish until the deadline, failing those still
+//! running then as unavailable, and stops serving.
+//!
+//! The notice is a GOAWAY message: an empty response, under a fresh RPC
+//! ID, whose extension block has the GOAWAY tag; see
+//! [`crate::Client::going_away`]. Frames of streams already open still
+//! reach their handlers while the server drains.
+
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::net::SocketAddrV4;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use symphony_wire::PacketType;
+use tokio::sync::watch;
+
+use crate::extensions::Extensions;
+use crate::stream::FrameIds;
+use crate::transport::Transport;
+use crate::{Code, Error, Status};
+
+/// Clients past this many are forgotten once idle for [`CLIENT_IDLE`].
+const MAX_CLIENTS: usize = 4096;
+const CLIENT_IDLE: Duration = Duration::from_secs(600);
+
+/// Shuts down the server it came from; see [`crate::Server::shutdown_handle`].
+#[derive(Clone)]
+pub struct Shutdown(Arc<Drain>);
+
+/// How a shutdown went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Drained {
+    /// Clients sent a GOAWAY.
+    pub notified: usize,
+    /// Handlers still running at the deadline, failed as unavailable.
+    pub cancelled: usize,
+}
+
+/// The shutdown state of a server, shared with its handlers.
+pub(crate) struct Drain {
+    frame_ids: Arc<FrameIds>,
+    draining: AtomicBool,
+    /// Handlers running.
+    calls: watch::Sender<usize>,
+    /// Set at the deadline, failing the handlers still running.
+    expired: watch::Sender<bool>,
+    /// Set once drained, stopping the server.
+    done: watch::Sender<Option<Drained>>,
+    /// Clients heard from.
+    clients: Mutex<HashMap<SocketAddrV4, Heard>>,
+}
+
+/// A client heard from: through which transport, and when last.
+struct Heard {
+    transport: Arc<dyn Transport>,
+    at: Instant,
+}
+
+/// Counts a handler as running until dropped.
+pub(crate) struct Call(Arc<Drain>);
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.0.calls.send_modify(|calls| *calls -= 1);
+    }
+}
+
+impl Drain {
+    pub fn new(frame_ids: Arc<FrameIds>) -> Drain {
+        Drain {
+            frame_ids,
+            draining: AtomicBool::new(false),
+            calls: watch::Sender::new(0),
+            expired: watch::Sender::new(false),
+            done: watch::Sender::new(None),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Records a request from `client`, to notify it on shutdown.
+    pub fn heard(&self, client: SocketAddrV4, transport: &Arc<dyn Transport>) {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_CLIENTS && !clients.contains_key(&client) {
+            clients.retain(|_, heard| now.duration_since(heard.at) < CLIENT_IDLE);
+        }
+        let transport = transport.clone();
+        clients.insert(client, Heard { transport, at: now });
+    }
+
+    pub fn call(self: &Arc<Self>) -> Call {
+        self.calls.send_modify(|calls| *calls += 1);
+        Call(self.clone())
+    }
+
+    /// Runs `f` until the deadline of a shutdown passes; None if it did
+    /// first.
+    pub async fn until_expired<T>(&self, f: impl Future<Output = T>) -> Option<T> {
+        let mut expired = self.expired.subscribe();
+        race(f, async move {
+            expired.wait_for(|expired| *expired).await.is_ok()
+        })
+        .await
+    }
+
+    /// Runs `f` until the server is drained; None if it was first.
+    pub async fn until_done<T>(&self, f: impl Future<Output = T>) -> Option<T> {
+        let mut done = self.done.subscribe();
+        race(
+            f,
+            async move { done.wait_for(Option::is_some).await.is_ok() },
+        )
+        .await
+    }
+}
+
+/// The failure of calls turned away or cut short by a shutdown.
+pub(crate) fn shutting_down() -> Error {
+    Status::new(Code::Unavailable, "server is shutting down").into()
+}
+
+/// Runs `f` until `stop` completes with true.
+pub(crate) async fn race<T>(
+    f: impl Future<Output = T>,
+    stop: impl Future<Output = bool>,
+) -> Option<T> {
+    let (mut f, mut stop) = (pin!(f), pin!(stop));
+    let mut stopping = true;
+    poll_fn(|cx| {
+        if let Poll::Ready(value) = f.as_mut().poll(cx) {
+            return Poll::Ready(Some(value));
+        }
+        if stopping {
+            match stop.as_mut().poll(cx) {
+                Poll::Ready(true) => return Poll::Ready(None),
+                Poll::Ready(false) => stopping = false,
+                Poll::Pending => {}
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+impl Shutdown {
+    pub(crate) fn new(drain: Arc<Drain>) -> Shutdown {
+        Shutdown(drain)
+    }
+
+    /// Stops taking new calls, sends the clients a GOAWAY, and waits for
+    /// the running handlers until `deadline`, then stops the server.
+    /// Calling it again waits for the first shutdown.
+    pub async fn shutdown(&self, deadline: Instant) -> Drained {
+        let drain = &self.0;
+        if drain.draining.swap(true, Ordering::Relaxed) {
+            let mut done = drain.done.subscribe();
+            let drained = done.wait_for(Option::is_some).await;
+            return drained.map_or_else(|_| Drained::default(), |drained| drained.unwrap());
+        }
+        let clients: Vec<_> = drain
+            .clients
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(client, heard)| (client, heard.transport))
+            .collect();
+        log::info!("shutting down, notifying {} clients", clients.len());
+        let mut drained = Drained::default();
+        let extensions = Extensions {
+            goaway: true,
+            ..Extensions::default()
+        };
+        for (client, transport) in clients {
+            let id = drain.frame_ids.next();
+            let sent = transport
+                .send_message(client, PacketType::Response, id, &[], &extensions)
+                .await;
+            match sent {
+                Ok(()) => drained.notified += 1,
+                Err(e) => log::debug!("failed to send GOAWAY to {}: {}", client, e),
+            }
+        }
+        let mut calls = drain.calls.subscribe();
+        let idle = tokio::time::timeout_at(deadline.into(), calls.wait_for(|calls| *calls == 0));
+        if idle.await.is_err() {
+            drained.cancelled = *drain.calls.borrow();
+            log::warn!("cancelling {} handlers at the deadline", drained.cancelled);
+            drain.expired.send_replace(true);
+            let _ = calls.wait_for(|calls| *calls == 0).await;
+        }
+        drain.done.send_replace(Some(drained));
+        drained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Server, ServiceDesc};
+
+    #[tokio::test]
+    async fn drains_calls_and_turns_new_ones_away() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("SleepService", 1).method(
+            "Sleep",
+            1,
+            |req: Text| async move {
+                let millis = req.0.parse().unwrap();
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                Ok::<_, Error>(req)
+            },
+        ));
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let served = tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("SleepService", 1, &[("Sleep", 1)]);
+        let client = Arc::new(client);
+        let call = |millis: &str| {
+            let (client, req) = (client.clone(), Text::new(millis));
+            tokio::spawn(async move { client.call::<_, Text>("SleepService", "Sleep", &req).await })
+        };
+        let quick = call("100");
+        let slow = call("5000");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let deadline = Instant::now() + Duration::from_millis(300);
+        let drained = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.shutdown(deadline).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(client.going_away());
+        let Err(Error::Status(status)) = call("0").await.unwrap() else {
+            panic!("served a call while draining");
+        };
+        assert_eq!(status.code(), Code::Unavailable);
+
+        assert_eq!(quick.await.unwrap().unwrap(), Text::new("100"));
+        let Err(Error::Status(status)) = slow.await.unwrap() else {
+            panic!("slow call outlived the deadline");
+        };
+        assert_eq!(status.code(), Code::Unavailable);
+        let drained = drained.await.unwrap();
+        assert_eq!(
+            drained,
+            Drained {
+                notified: 1,
+                cancelled: 1
+            }
+        );
+        served.await.unwrap().unwrap();
+        // Later calls wait for the first shutdown.
+        assert_eq!(shutdown.shutdown(Instant::now()).await, drained);
+    }
+}
@@ -0,0 +1,396 @@
+//! DTLS beneath Symphony, for deployments that terminate standard DTLS
+//! with their own PKI instead of the shared keys of [`crate::Encryption`].
+//!
+//! Every datagram a transport sends to a peer, packets, acknowledgements
+//! and rekeys alike, travels as a DTLS record of its session with that
+//! peer. A client connects to its server, and a server accepts any client
+//! that says hello; packets sent before the handshake completes wait for
+//! it. A handshake that has not completed after [`HANDSHAKE_TIMEOUT`] starts
+//! over on the next send.
+//!
+//! Sessions are OpenSSL's. The version is the newest both peers and the
+//! linked OpenSSL support, no older than DTLS 1.2; DTLS 1.3 takes an
+//! OpenSSL that implements it.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use openssl::pkey::PKey;
+use openssl::ssl::{
+    ErrorCode, Ssl, SslContext, SslContextBuilder, SslMethod, SslOptions, SslStream, SslVerifyMode,
+    SslVersion,
+};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::X509;
+
+use crate::Error;
+
+/// How long a handshake may take before the next send starts it over.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The largest DTLS datagram: an Ethernet frame less the IP and UDP
+/// headers. Packets of [`symphony_wire::MAX_PACKET_LEN`] fit with room for
+/// the record overhead; path MTU probes past it fail to seal.
+const DTLS_MTU: u32 = 1472;
+
+/// Packets queued per peer while its handshake runs.
+const MAX_QUEUED: usize = 1024;
+
+/// The DTLS side of a client or server: its OpenSSL context, and whether
+/// it connects or accepts.
+#[derive(Clone)]
+pub struct Dtls {
+    context: SslContext,
+    connect: bool,
+}
+
+impl Dtls {
+    /// Connects to servers whose certificate chains to a certificate of
+    /// `ca_pem` and names `server_name`, a DNS name or an IP address.
+    pub fn client(ca_pem: &[u8], server_name: &str) -> Result<Dtls, Error> {
+        let mut builder = context()?;
+        builder.set_cert_store(store(ca_pem)?);
+        builder.set_verify(SslVerifyMode::PEER);
+        let param = builder.verify_param_mut();
+        match server_name.parse::<IpAddr>() {
+            Ok(ip) => param.set_ip(ip)?,
+            Err(_) => param.set_host(server_name)?,
+        }
+        Ok(Dtls::from_context(builder.build(), true))
+    }
+
+    /// Accepts clients with the certificate chain `cert_chain_pem`, leaf
+    /// first, and its private key `key_pem`.
+    pub fn server(cert_chain_pem: &[u8], key_pem: &[u8]) -> Result<Dtls, Error> {
+        let mut builder = context()?;
+        let mut chain = X509::stack_from_pem(cert_chain_pem)?.into_iter();
+        let leaf = chain
+            .next()
+            .ok_or_else(|| Error::Dtls("no certificate in chain".to_string()))?;
+        builder.set_certificate(&leaf)?;
+        for cert in chain {
+            builder.add_extra_chain_cert(cert)?;
+        }
+        let key = PKey::private_key_from_pem(key_pem)?;
+        builder.set_private_key(&key)?;
+        builder.check_private_key()?;
+        Ok(Dtls::from_context(builder.build(), false))
+    }
+
+    /// Connects, or accepts, with a context configured by hand, e.g. for
+    /// client certificates or a choice of ciphers. It should be built on
+    /// [`SslMethod::dtls`] and set [`SslOptions::NO_QUERY_MTU`].
+    pub fn from_context(context: SslContext, connect: bool) -> Dtls {
+        Dtls { context, connect }
+    }
+}
+
+fn context() -> Result<SslContextBuilder, Error> {
+    let mut builder = SslContextBuilder::new(SslMethod::dtls())?;
+    builder.set_min_proto_version(Some(SslVersion::DTLS1_2))?;
+    // The MTU is set on each session instead.
+    builder.set_options(SslOptions::NO_QUERY_MTU);
+    Ok(builder)
+}
+
+fn store(ca_pem: &[u8]) -> Result<openssl::x509::store::X509Store, Error> {
+    let mut store = X509StoreBuilder::new()?;
+    for cert in X509::stack_from_pem(ca_pem)? {
+        store.add_cert(cert)?;
+    }
+    Ok(store.build())
+}
+
+/// The DTLS sessions of a transport, by peer.
+pub(crate) struct Sessions {
+    dtls: Dtls,
+    peers: HashMap<SocketAddrV4, Session>,
+}
+
+struct Session {
+    stream: SslStream<Channel>,
+    started: Instant,
+    established: bool,
+    /// The random of the ClientHello an accepted session began with.
+    hello: Option<Vec<u8>>,
+    /// Packets waiting for the handshake.
+    queued: Vec<Vec<u8>>,
+}
+
+/// Carries the datagrams of a session: those received, read by OpenSSL one
+/// at a time, and those it writes, to send.
+#[derive(Default)]
+struct Channel {
+    inbound: VecDeque<Vec<u8>>,
+    outbound: Vec<Vec<u8>>,
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let datagram = self.inbound.pop_front().ok_or(io::ErrorKind::WouldBlock)?;
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        Ok(n)
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outbound.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sessions {
+    pub fn new(dtls: Dtls) -> Sessions {
+        Sessions {
+            dtls,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// The datagrams to send `packet` to `dst` in, now; none while the
+    /// handshake runs.
+    pub fn seal(
+        &mut self,
+        dst: SocketAddrV4,
+        packet: &[u8],
+        now: Instant,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let stale = self.peers.get(&dst).is_some_and(|session| {
+            !session.established && now.duration_since(session.started) > HANDSHAKE_TIMEOUT
+        });
+        if stale || !self.peers.contains_key(&dst) {
+            if !self.dtls.connect {
+                return Err(Error::Dtls(format!("no DTLS session with {}", dst)));
+            }
+            let queued = self
+                .peers
+                .remove(&dst)
+                .map(|session| session.queued)
+                .unwrap_or_default();
+            let mut session = self.session(now)?;
+            session.queued = queued;
+            session.handshake(dst);
+            self.peers.insert(dst, session);
+        }
+        let session = self.peers.get_mut(&dst).unwrap();
+        if !session.established {
+            if session.queued.len() < MAX_QUEUED {
+                session.queued.push(packet.to_vec());
+            }
+            return Ok(std::mem::take(&mut session.stream.get_mut().outbound));
+        }
+        session.stream.ssl_write(packet)?;
+        Ok(std::mem::take(&mut session.stream.get_mut().outbound))
+    }
+
+    /// Drops the session with `peer`; the next packet sent to it starts
+    /// another.
+    pub fn remove(&mut self, peer: SocketAddrV4) {
+        self.peers.remove(&peer);
+    }
+
+    /// Takes in a datagram from `src`. Returns the datagrams to send back,
+    /// handshake messages and the packets that waited for it, and the
+    /// packet the datagram carried, if any.
+    pub fn open(
+        &mut self,
+        src: SocketAddrV4,
+        datagram: &[u8],
+        now: Instant,
+    ) -> (Vec<Vec<u8>>, Option<Vec<u8>>) {
+        // A client that lost its session, say by restarting, says hello
+        // again and starts a new one; a hello sent again, with the same
+        // random, belongs to the session it began.
+        let hello = client_random(datagram);
+        let fresh = hello.is_some_and(|random| {
+            self.peers
+                .get(&src)
+                .is_none_or(|session| session.hello.as_deref() != Some(random))
+        });
+        if !self.dtls.connect && fresh {
+            match self.session(now) {
+                Ok(mut session) => {
+                    session.hello = hello.map(<[u8]>::to_vec);
+                    self.peers.insert(src, session);
+                }
+                Err(e) => {
+                    log::warn!("failed to accept DTLS from {}: {}", src, e);
+                    return (Vec::new(), None);
+                }
+            }
+        }
+        let Some(session) = self.peers.get_mut(&src) else {
+            log::debug!("dropping DTLS record from {} without a session", src);
+            return (Vec::new(), None);
+        };
+        session
+            .stream
+            .get_mut()
+            .inbound
+            .push_back(datagram.to_vec());
+        if !session.established && !session.handshake(src) {
+            self.peers.remove(&src);
+            return (Vec::new(), None);
+        }
+        let mut buf = [0; DTLS_MTU as usize];
+        let packet = match session.stream.ssl_read(&mut buf) {
+            Ok(n) => Some(buf[..n].to_vec()),
+            Err(e) if e.code() == ErrorCode::WANT_READ => None,
+            Err(e) => {
+                log::warn!("closing DTLS session with {}: {}", src, e);
+                let outbound = std::mem::take(&mut session.stream.get_mut().outbound);
+                self.peers.remove(&src);
+                return (outbound, None);
+            }
+        };
+        (
+            std::mem::take(&mut session.stream.get_mut().outbound),
+            packet,
+        )
+    }
+
+    fn session(&self, now: Instant) -> Result<Session, Error> {
+        let mut ssl = Ssl::new(&self.dtls.context)?;
+        ssl.set_mtu(DTLS_MTU)?;
+        if self.dtls.connect {
+            ssl.set_connect_state();
+        } else {
+            ssl.set_accept_state();
+        }
+        Ok(Session {
+            stream: SslStream::new(ssl, Channel::default())?,
+            started: now,
+            established: false,
+            hello: None,
+            queued: Vec::new(),
+        })
+    }
+}
+
+impl Session {
+    /// Moves the handshake on, sending the waiting packets once it
+    /// completes. Returns false if it failed.
+    fn handshake(&mut self, peer: SocketAddrV4) -> bool {
+        match self.stream.do_handshake() {
+            Ok(()) => {
+                self.established = true;
+                for packet in std::mem::take(&mut self.queued) {
+                    if let Err(e) = self.stream.ssl_write(&packet) {
+                        log::warn!("failed to send over DTLS to {}: {}", peer, e);
+                    }
+                }
+                true
+            }
+            Err(e) if e.code() == ErrorCode::WANT_READ => true,
+            Err(e) => {
+                log::warn!("DTLS handshake with {} failed: {}", peer, e);
+                false
+            }
+        }
+    }
+}
+
+/// The random of the ClientHello `datagram` opens with, if it does: a
+/// handshake record of epoch 0 whose message is the first fragment of a
+/// ClientHello.
+fn client_random(datagram: &[u8]) -> Option<&[u8]> {
+    const HANDSHAKE: u8 = 22;
+    const CLIENT_HELLO: u8 = 1;
+    // Record header, handshake header, then the version before the random.
+    const RANDOM: usize = 13 + 12 + 2;
+    let hello = *datagram.first()? == HANDSHAKE
+        && datagram.get(3..5)? == [0, 0]
+        && *datagram.get(13)? == CLIENT_HELLO
+        && datagram.get(19..22)? == [0, 0, 0];
+    hello.then(|| datagram.get(RANDOM..RANDOM + 32)).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Server, ServiceDesc};
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::rsa::Rsa;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::X509NameBuilder;
+
+    /// A self-signed certificate for 127.0.0.1, and its key, as PEM.
+    fn certificate() -> (Vec<u8>, Vec<u8>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "arpc test").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        cert.set_serial_number(&serial).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let san = SubjectAlternativeName::new()
+            .ip("127.0.0.1")
+            .build(&cert.x509v3_context(None, None))
+            .unwrap();
+        cert.append_extension(san).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        (
+            cert.build().to_pem().unwrap(),
+            key.private_key_to_pem_pkcs8().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn serves_calls_over_dtls() {
+        let (cert, key) = certificate();
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("EchoService", 1).method(
+            "Echo",
+            1,
+            |req: Text| async move { Ok::<_, Error>(Text(req.0.to_uppercase())) },
+        ));
+        server.set_dtls(Dtls::server(&cert, &key).unwrap());
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        client.set_dtls(Dtls::client(&cert, "127.0.0.1").unwrap());
+        client.set_call_timeout(Some(Duration::from_secs(5)));
+        let long = "secret ".repeat(1000);
+        for _ in 0..2 {
+            let resp: Text = client
+                .call("EchoService", "Echo", &Text::new(&long))
+                .await
+                .unwrap();
+            assert_eq!(resp.0, long.to_uppercase());
+        }
+
+        // A client that does not trust the server's certificate gets no
+        // answer.
+        let (other, _) = certificate();
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        client.set_dtls(Dtls::client(&other, "127.0.0.1").unwrap());
+        client.set_call_timeout(Some(Duration::from_millis(300)));
+        let err = client
+            .call::<_, Text>("EchoService", "Echo", &Text::new("hi"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::TimedOut), "unexpected error: {}", err);
+    }
+}
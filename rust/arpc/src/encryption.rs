@@ -0,0 +1,909 @@
+//! AES-GCM encryption of the messages a transport sends
+//! (`pkg/transport/encryption.go`), with the segment layout of
+//! [`symphony_wire::crypto`]. ChaCha20-Poly1305 is offered for hosts
+//! without AES instructions. The suite is agreed with each peer: the side
+//! that speaks first seals in the first of its suites and names it in the
+//! extension block of its messages until the peer answers, and the peer
+//! answers in that suite if it is one of its own, or else refuses the call.
+//! A message that names no suite is sealed with AES-256-GCM, the only
+//! suite of the Go transport.
+//!
+//! The Go transport draws a random nonce per segment from the OS, which
+//! dominates its CPU time at high packet rates. Nonces here are derived
+//! instead, QUIC-style: a random IV drawn once per key epoch, XORed with
+//! the RPC ID and the sender's sequence number. The sequence number alone
+//! keeps nonces unique under the IV, even across peers that reuse RPC IDs.
+//!
+//! Keys rotate in epochs so that long-lived transports stay within the
+//! AEAD's limits. Each epoch's secrets are derived from the previous one's
+//! with HKDF-SHA256, like QUIC key updates (RFC 9001 §6), and the key phase
+//! bit of a data packet tells the receiver which epoch sealed it. Epochs
+//! are kept per peer: every peer starts from the configured keys in epoch
+//! 0, and rotating with one leaves the others where they are. After
+//! [`REKEY_AFTER`] segments to a peer a transport asks it to move to the
+//! next epoch with a [`Rekey`] packet, and switches once the peer
+//! acknowledges; a peer that sees the next epoch's phase first follows on
+//! its own. The previous epoch's keys still open packets for
+//! [`SWITCHOVER_WINDOW`] after a switch, so that packets in flight are not
+//! lost, and no request to switch again is taken until then. Rekey packets
+//! are sealed under the current epoch's keys like data, so only a peer
+//! holding them can rotate them.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddrV4;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+use symphony_wire::crypto::{self, CipherSuite, Keys, NONCE_LEN, TAG_LEN};
+
+use crate::Error;
+
+/// Segments sealed in one epoch before its nonces would repeat.
+const MAX_SEQ: u64 = 1 << 32;
+/// Segments sealed in one epoch before asking the peer to rekey; the
+/// AES-GCM confidentiality limit of RFC 9001 §6.6.
+const REKEY_AFTER: u64 = 1 << 23;
+/// How long the keys of the previous epoch keep opening packets.
+const SWITCHOVER_WINDOW: Duration = Duration::from_secs(5);
+/// How long to wait for the peer to acknowledge a rekey before asking
+/// again.
+const REKEY_RETRY: Duration = Duration::from_secs(1);
+
+const KEY_UPDATE_LABEL: &[u8] = b"arpc key update";
+
+/// The suites taken by default, after the one sealed in.
+const SUITES: [CipherSuite; 2] = [CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305];
+/// The suite of a peer that names none, as the Go transport does.
+const UNNAMED_SUITE: CipherSuite = CipherSuite::Aes256Gcm;
+
+/// Custom packet type of the rekey handshake, clear of the builtin types.
+pub(crate) const REKEY_PACKET_TYPE: u8 = 0x10;
+const REKEY_BODY_LEN: usize = 5;
+const REKEY_PACKET_LEN: usize = 2 + NONCE_LEN + REKEY_BODY_LEN + TAG_LEN;
+/// The RPC ID rekey nonces are derived from; the sequence number keeps them
+/// apart from the nonces of a data message with this ID.
+const REKEY_NONCE_ID: u64 = u64::MAX;
+
+/// The segment keys of a transport, rotated in epochs with each peer, and
+/// the state of their nonces.
+pub struct Encryption {
+    /// The suite new peers are sealed in, then the others taken from peers
+    /// that name them.
+    suites: RwLock<Vec<CipherSuite>>,
+    /// Public and private secrets of epoch 0.
+    secrets: [[u8; 32]; 2],
+    peers: Mutex<HashMap<SocketAddrV4, Arc<Mutex<Epochs>>>>,
+}
+
+/// The key epochs of one peer.
+struct Epochs {
+    suite: CipherSuite,
+    /// Whether anything from the peer has opened yet; until then messages
+    /// to it name their suite.
+    heard: bool,
+    current: Arc<Epoch>,
+    /// Derived ahead, to open the packets of a peer that switched first.
+    next: Arc<Epoch>,
+    /// The epoch before `current`, and when it stops opening packets.
+    previous: Option<(Arc<Epoch>, Instant)>,
+    /// When the peer was last asked to move to `next`.
+    rekey_sent: Option<Instant>,
+}
+
+struct Epoch {
+    number: u32,
+    /// Public and private segment secrets.
+    secrets: [[u8; 32]; 2],
+    keys: Keys,
+    iv: [u8; NONCE_LEN],
+    seq: AtomicU64,
+}
+
+/// A message sealed for sending.
+pub(crate) struct Sealed {
+    pub message: Vec<u8>,
+    pub key_phase: bool,
+    /// The epoch to ask the peer to move to, when it is time to rekey.
+    pub rekey: Option<u32>,
+    /// The suite to name in the extension block, until the peer answers.
+    pub suite: Option<CipherSuite>,
+}
+
+/// Asks the peer to move to `epoch`, or acknowledges that it has. It is
+/// sent sealed under the public key of the sender's current epoch:
+/// `[type(1B)][key phase(1B)][nonce(12B)][epoch(4B)][ack(1B)][tag(16B)]`,
+/// with the epoch and ack encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rekey {
+    pub epoch: u32,
+    pub ack: bool,
+}
+
+impl Rekey {
+    fn encode(&self) -> [u8; REKEY_BODY_LEN] {
+        let mut buf = [0; REKEY_BODY_LEN];
+        buf[..4].copy_from_slice(&self.epoch.to_le_bytes());
+        buf[4] = self.ack as u8;
+        buf
+    }
+
+    fn parse(buf: &[u8]) -> Option<Rekey> {
+        if buf.len() != REKEY_BODY_LEN {
+            return None;
+        }
+        Some(Rekey {
+            epoch: u32::from_le_bytes(buf[..4].try_into().unwrap()),
+            ack: buf[4] != 0,
+        })
+    }
+}
+
+impl Epoch {
+    fn new(suite: CipherSuite, number: u32, secrets: [[u8; 32]; 2]) -> Result<Epoch, Error> {
+        let mut iv = [0; NONCE_LEN];
+        getrandom::getrandom(&mut iv).map_err(io::Error::from)?;
+        Epoch::with_iv(suite, number, secrets, iv)
+    }
+
+    fn with_iv(
+        suite: CipherSuite,
+        number: u32,
+        secrets: [[u8; 32]; 2],
+        iv: [u8; NONCE_LEN],
+    ) -> Result<Epoch, Error> {
+        let keys = Keys::with_suite(suite, &secrets[0], &secrets[1]).map_err(Error::Encryption)?;
+        Ok(Epoch {
+            number,
+            secrets,
+            keys,
+            iv,
+            seq: AtomicU64::new(0),
+        })
+    }
+
+    fn next(&self, suite: CipherSuite) -> Result<Epoch, Error> {
+        let secrets = self.secrets.map(|secret| {
+            let mut next = [0; 32];
+            Hkdf::<Sha256>::from_prk(&secret)
+                .expect("secrets are as long as the hash")
+                .expand(KEY_UPDATE_LABEL, &mut next)
+                .expect("32 bytes is a valid HKDF output length");
+            next
+        });
+        Epoch::new(suite, self.number.wrapping_add(1), secrets)
+    }
+
+    fn phase(&self) -> bool {
+        self.number & 1 == 1
+    }
+
+    /// The nonces of the two segments of a message for `rpc_id`, or None
+    /// once the epoch has run out of them.
+    fn nonces(&self, rpc_id: u64) -> Option<[[u8; NONCE_LEN]; 2]> {
+        let seq = self.seq.fetch_add(2, Ordering::Relaxed);
+        if seq + 1 >= MAX_SEQ {
+            return None;
+        }
+        Some([self.nonce(rpc_id, seq), self.nonce(rpc_id, seq + 1)])
+    }
+
+    /// The nonce of a rekey packet, or None once the epoch has run out.
+    fn rekey_nonce(&self) -> Option<[u8; NONCE_LEN]> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        (seq < MAX_SEQ).then(|| self.nonce(REKEY_NONCE_ID, seq))
+    }
+
+    fn nonce(&self, rpc_id: u64, seq: u64) -> [u8; NONCE_LEN] {
+        let mut nonce = [0; NONCE_LEN];
+        nonce[..8].copy_from_slice(&rpc_id.to_le_bytes());
+        nonce[8..].copy_from_slice(&(seq as u32).to_le_bytes());
+        for (n, iv) in nonce.iter_mut().zip(self.iv) {
+            *n ^= iv;
+        }
+        nonce
+    }
+}
+
+impl Encryption {
+    /// Encrypts with 32-byte AES-256 keys for the public and private
+    /// segments (`SetEncryptionKeys` in Go).
+    pub fn new(public_key: &[u8], private_key: &[u8]) -> Result<Encryption, Error> {
+        Encryption::with_suite(CipherSuite::Aes256Gcm, public_key, private_key)
+    }
+
+    /// Encrypts with `suite` under 32-byte keys for the public and private
+    /// segments. Peers that name another suite are still taken; see
+    /// [`Encryption::set_suites`].
+    pub fn with_suite(
+        suite: CipherSuite,
+        public_key: &[u8],
+        private_key: &[u8],
+    ) -> Result<Encryption, Error> {
+        let secret = |key: &[u8]| {
+            <[u8; 32]>::try_from(key)
+                .map_err(|_| Error::Encryption(symphony_wire::Error::InvalidKey))
+        };
+        let secrets = [secret(public_key)?, secret(private_key)?];
+        // Checks the keys before any peer needs them.
+        Keys::with_suite(suite, &secrets[0], &secrets[1]).map_err(Error::Encryption)?;
+        let mut suites = vec![suite];
+        suites.extend(SUITES.into_iter().filter(|other| *other != suite));
+        Ok(Encryption {
+            suites: RwLock::new(suites),
+            secrets,
+            peers: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Encrypts with the development keys hardcoded in the Go transport
+    /// (`EnableEncryption`).
+    pub fn with_default_keys() -> Result<Encryption, Error> {
+        Encryption::new(&crypto::DEFAULT_PUBLIC_KEY, &crypto::DEFAULT_PRIVATE_KEY)
+    }
+
+    /// The suite new peers are sealed in.
+    pub fn suite(&self) -> CipherSuite {
+        self.suites.read().unwrap()[0]
+    }
+
+    /// Seals messages to new peers in the first of `suites`, and takes only
+    /// these from peers that name theirs. Peers already talked to keep
+    /// their suite. An empty list is ignored.
+    pub fn set_suites(&self, suites: &[CipherSuite]) {
+        if !suites.is_empty() {
+            *self.suites.write().unwrap() = suites.to_vec();
+        }
+    }
+
+    /// The suite with wire ID `id`, if it is taken.
+    fn taken(&self, id: u8) -> Result<CipherSuite, Error> {
+        CipherSuite::from_id(id)
+            .filter(|suite| self.suites.read().unwrap().contains(suite))
+            .ok_or(Error::Encryption(symphony_wire::Error::UnsupportedSuite(
+                id,
+            )))
+    }
+
+    /// The key epoch messages to `peer` are sealed in: 0 for the configured
+    /// keys, counting up with each rotation.
+    pub fn epoch(&self, peer: SocketAddrV4) -> u32 {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .map_or(0, |epochs| epochs.lock().unwrap().current.number)
+    }
+
+    /// Drops the epochs of `peer`, whose session is over; it starts from
+    /// epoch 0 again if it comes back.
+    pub(crate) fn forget(&self, peer: SocketAddrV4) {
+        self.peers.lock().unwrap().remove(&peer);
+    }
+
+    /// The epochs of `peer`, starting it in epoch 0 if it is new.
+    fn peer(&self, peer: SocketAddrV4) -> Result<Arc<Mutex<Epochs>>, Error> {
+        if let Some(epochs) = self.peers.lock().unwrap().get(&peer) {
+            return Ok(epochs.clone());
+        }
+        let epochs = Arc::new(Mutex::new(self.start(self.suite())?));
+        Ok(self
+            .peers
+            .lock()
+            .unwrap()
+            .entry(peer)
+            .or_insert(epochs)
+            .clone())
+    }
+
+    /// The epochs of `peer`, in `suite` if it named one, and whether they
+    /// are kept yet. Those of a new peer, or of one that names another
+    /// suite, are only kept once a packet opens under them, with
+    /// [`Encryption::keep`], so that forged sources do not pile up.
+    fn lookup(
+        &self,
+        peer: SocketAddrV4,
+        suite: Option<CipherSuite>,
+    ) -> Result<(Arc<Mutex<Epochs>>, bool), Error> {
+        if let Some(epochs) = self.peers.lock().unwrap().get(&peer) {
+            if suite.is_none_or(|suite| suite == epochs.lock().unwrap().suite) {
+                return Ok((epochs.clone(), true));
+            }
+        }
+        let epochs = self.start(suite.unwrap_or(UNNAMED_SUITE))?;
+        Ok((Arc::new(Mutex::new(epochs)), false))
+    }
+
+    /// Keeps the epochs of `peer` from a lookup, unless another packet
+    /// kept some in the same suite first.
+    fn keep(&self, peer: SocketAddrV4, epochs: Arc<Mutex<Epochs>>) -> Arc<Mutex<Epochs>> {
+        let suite = epochs.lock().unwrap().suite;
+        let mut peers = self.peers.lock().unwrap();
+        match peers.get(&peer) {
+            Some(kept) if kept.lock().unwrap().suite == suite => kept.clone(),
+            _ => {
+                peers.insert(peer, epochs.clone());
+                epochs
+            }
+        }
+    }
+
+    /// Epoch 0 of a new peer, under the configured keys.
+    fn start(&self, suite: CipherSuite) -> Result<Epochs, Error> {
+        let current = Epoch::new(suite, 0, self.secrets)?;
+        let next = current.next(suite)?;
+        Ok(Epochs {
+            suite,
+            heard: false,
+            current: Arc::new(current),
+            next: Arc::new(next),
+            previous: None,
+            rekey_sent: None,
+        })
+    }
+
+    pub(crate) fn encrypt(
+        &self,
+        peer: SocketAddrV4,
+        rpc_id: u64,
+        message: &[u8],
+        now: Instant,
+    ) -> Result<Sealed, Error> {
+        let epochs = self.peer(peer)?;
+        loop {
+            let epoch = epochs.lock().unwrap().current.clone();
+            let Some([public, private]) = epoch.nonces(rpc_id) else {
+                // Out of nonces before the peer acknowledged a rekey:
+                // switch anyway, and let the peer follow the key phase.
+                self.advance(peer, &mut epochs.lock().unwrap(), epoch.number, now)?;
+                continue;
+            };
+            let message = crypto::encrypt(&epoch.keys, message, &public, &private)
+                .map_err(Error::Encryption)?;
+            let rekey = if epoch.seq.load(Ordering::Relaxed) >= REKEY_AFTER {
+                Encryption::rekey_due(&mut epochs.lock().unwrap(), epoch.number, now)
+            } else {
+                None
+            };
+            let epochs = epochs.lock().unwrap();
+            return Ok(Sealed {
+                message,
+                key_phase: epoch.phase(),
+                rekey,
+                suite: (!epochs.heard && epochs.suite != UNNAMED_SUITE).then_some(epochs.suite),
+            });
+        }
+    }
+
+    /// Opens a message from `peer` sealed in the epoch of `key_phase`, and
+    /// in the suite with wire ID `suite` if the message names one. A packet
+    /// in the other phase is from the previous epoch while its keys are
+    /// kept, or else from the next one, which this transport then moves to
+    /// with the peer.
+    pub(crate) fn decrypt(
+        &self,
+        peer: SocketAddrV4,
+        suite: Option<u8>,
+        key_phase: bool,
+        message: &[u8],
+        now: Instant,
+    ) -> Result<Vec<u8>, Error> {
+        let suite = suite.map(|id| self.taken(id)).transpose()?;
+        let (epochs, kept) = self.lookup(peer, suite)?;
+        let candidates = Encryption::candidates(&mut epochs.lock().unwrap(), key_phase, now);
+        let mut err = symphony_wire::Error::Decrypt;
+        for epoch in candidates {
+            match crypto::decrypt(&epoch.keys, message) {
+                Ok(message) => {
+                    let epochs = if kept {
+                        epochs
+                    } else {
+                        self.keep(peer, epochs)
+                    };
+                    let mut epochs = epochs.lock().unwrap();
+                    epochs.heard = true;
+                    if epoch.number == epochs.next.number {
+                        let current = epochs.current.number;
+                        self.advance(peer, &mut epochs, current, now)?;
+                    }
+                    return Ok(message);
+                }
+                Err(e) => err = e,
+            }
+        }
+        Err(Error::Encryption(err))
+    }
+
+    /// The epochs that may have sealed a packet in `key_phase`, dropping
+    /// the previous epoch once its window is over.
+    fn candidates(epochs: &mut Epochs, key_phase: bool, now: Instant) -> Vec<Arc<Epoch>> {
+        if epochs
+            .previous
+            .as_ref()
+            .is_some_and(|(_, until)| *until <= now)
+        {
+            epochs.previous = None;
+        }
+        if key_phase == epochs.current.phase() {
+            vec![epochs.current.clone()]
+        } else if let Some((previous, _)) = &epochs.previous {
+            vec![previous.clone(), epochs.next.clone()]
+        } else {
+            vec![epochs.next.clone()]
+        }
+    }
+
+    /// Asks for a rekey with `peer` now, whatever the segment count.
+    /// Returns the epoch to ask the peer to move to.
+    pub(crate) fn start_rekey(&self, peer: SocketAddrV4, now: Instant) -> Result<u32, Error> {
+        let epochs = self.peer(peer)?;
+        let mut epochs = epochs.lock().unwrap();
+        epochs.rekey_sent = Some(now);
+        Ok(epochs.next.number)
+    }
+
+    /// Seals a rekey packet for `peer` under its current epoch's keys.
+    pub(crate) fn seal_rekey(
+        &self,
+        peer: SocketAddrV4,
+        rekey: &Rekey,
+        now: Instant,
+    ) -> Result<Vec<u8>, Error> {
+        let epochs = self.peer(peer)?;
+        loop {
+            let epoch = epochs.lock().unwrap().current.clone();
+            let Some(nonce) = epoch.rekey_nonce() else {
+                self.advance(peer, &mut epochs.lock().unwrap(), epoch.number, now)?;
+                continue;
+            };
+            let mut packet = Vec::with_capacity(REKEY_PACKET_LEN);
+            packet.push(REKEY_PACKET_TYPE);
+            packet.push(epoch.phase() as u8);
+            packet.extend_from_slice(&crypto::seal_packet(&epoch.keys, &nonce, &rekey.encode()));
+            return Ok(packet);
+        }
+    }
+
+    /// Opens a rekey packet from `peer` and handles it. Returns the epoch
+    /// to acknowledge if it was a request this transport took.
+    pub(crate) fn on_rekey(
+        &self,
+        peer: SocketAddrV4,
+        packet: &[u8],
+        now: Instant,
+    ) -> Result<Option<u32>, Error> {
+        if packet.len() != REKEY_PACKET_LEN || packet[0] != REKEY_PACKET_TYPE {
+            return Err(Error::Encryption(symphony_wire::Error::Incomplete {
+                needed: REKEY_PACKET_LEN,
+            }));
+        }
+        let (epochs, kept) = self.lookup(peer, None)?;
+        let rekey = Encryption::candidates(&mut epochs.lock().unwrap(), packet[1] != 0, now)
+            .iter()
+            .find_map(|epoch| crypto::open_packet(&epoch.keys, &packet[2..]).ok())
+            .and_then(|body| Rekey::parse(&body))
+            .ok_or(Error::Encryption(symphony_wire::Error::Decrypt))?;
+
+        let epochs = if kept {
+            epochs
+        } else {
+            self.keep(peer, epochs)
+        };
+        let mut epochs = epochs.lock().unwrap();
+        let current = epochs.current.number;
+        if rekey.epoch == epochs.next.number {
+            if rekey.ack && epochs.rekey_sent.is_none() {
+                log::debug!(
+                    "ignoring unrequested rekey ack from {} in epoch {}",
+                    peer,
+                    current
+                );
+                return Ok(None);
+            }
+            if !rekey.ack && epochs.previous.is_some() {
+                // Still switching over from the last rekey.
+                log::debug!(
+                    "ignoring rekey from {} to epoch {} while switching",
+                    peer,
+                    rekey.epoch
+                );
+                return Ok(None);
+            }
+            self.advance(peer, &mut epochs, current, now)?;
+        } else if rekey.epoch != current {
+            log::debug!(
+                "ignoring rekey from {} to epoch {} in epoch {}",
+                peer,
+                rekey.epoch,
+                current
+            );
+            return Ok(None);
+        }
+        Ok((!rekey.ack).then_some(rekey.epoch))
+    }
+
+    /// The epoch to ask the peer to move to, unless a request for it is
+    /// still awaiting its acknowledgement.
+    fn rekey_due(epochs: &mut Epochs, number: u32, now: Instant) -> Option<u32> {
+        if epochs.current.number != number
+            || epochs
+                .rekey_sent
+                .is_some_and(|sent| now.duration_since(sent) < REKEY_RETRY)
+        {
+            return None;
+        }
+        epochs.rekey_sent = Some(now);
+        Some(epochs.next.number)
+    }
+
+    /// Moves from epoch `from` to the next with `peer`, unless another
+    /// packet already did.
+    fn advance(
+        &self,
+        peer: SocketAddrV4,
+        epochs: &mut Epochs,
+        from: u32,
+        now: Instant,
+    ) -> Result<(), Error> {
+        if epochs.current.number != from {
+            return Ok(());
+        }
+        let next = Arc::new(epochs.next.next(epochs.suite)?);
+        let current = std::mem::replace(&mut epochs.next, next);
+        let previous = std::mem::replace(&mut epochs.current, current);
+        epochs.previous = Some((previous, now + SWITCHOVER_WINDOW));
+        epochs.rekey_sent = None;
+        log::info!(
+            "rotated encryption keys with {} to epoch {}",
+            peer,
+            epochs.current.number
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 4001);
+    const OTHER_CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 4002);
+    const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5000);
+
+    fn message() -> Vec<u8> {
+        let mut message = vec![1, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        message.extend_from_slice(b"pub!\x01private");
+        message
+    }
+
+    fn current(encryption: &Encryption, peer: SocketAddrV4) -> Arc<Epoch> {
+        encryption
+            .peer(peer)
+            .unwrap()
+            .lock()
+            .unwrap()
+            .current
+            .clone()
+    }
+
+    fn ack(encryption: &Encryption, peer: SocketAddrV4, epoch: u32, now: Instant) -> Vec<u8> {
+        encryption
+            .seal_rekey(peer, &Rekey { epoch, ack: true }, now)
+            .unwrap()
+    }
+
+    #[test]
+    fn nonces_follow_rpc_id_and_sequence() {
+        let secrets = [crypto::DEFAULT_PUBLIC_KEY, crypto::DEFAULT_PRIVATE_KEY];
+        let epoch = Epoch::with_iv(CipherSuite::Aes256Gcm, 0, secrets, [0xa5; NONCE_LEN]).unwrap();
+        let [first, second] = epoch.nonces(0x0102).unwrap();
+        assert_eq!(
+            first,
+            [0xa7, 0xa4, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5]
+        );
+        assert_eq!(
+            second,
+            [0xa7, 0xa4, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa4, 0xa5, 0xa5, 0xa5]
+        );
+
+        epoch.seq.store(MAX_SEQ - 1, Ordering::Relaxed);
+        assert!(epoch.nonces(1).is_none());
+    }
+
+    #[test]
+    fn round_trips_messages() {
+        let encryption = Encryption::with_default_keys().unwrap();
+        let now = Instant::now();
+        let sealed = encryption.encrypt(SERVER, 7, &message(), now).unwrap();
+        assert_ne!(sealed.message, message());
+        assert!(!sealed.key_phase);
+        assert_eq!(sealed.rekey, None);
+        assert_eq!(
+            encryption
+                .decrypt(CLIENT, None, false, &sealed.message, now)
+                .unwrap(),
+            message()
+        );
+    }
+
+    #[test]
+    fn agrees_on_the_suite_the_client_names() {
+        let client = Encryption::with_suite(
+            CipherSuite::ChaCha20Poly1305,
+            &crypto::DEFAULT_PUBLIC_KEY,
+            &crypto::DEFAULT_PRIVATE_KEY,
+        )
+        .unwrap();
+        let server = Encryption::with_default_keys().unwrap();
+        let now = Instant::now();
+        let request = client.encrypt(SERVER, 7, &message(), now).unwrap();
+        let suite = request.suite.map(CipherSuite::id);
+        assert_eq!(suite, Some(2));
+        // Unnamed, the request would be taken as AES-GCM.
+        assert!(matches!(
+            server.decrypt(CLIENT, None, false, &request.message, now),
+            Err(Error::Encryption(symphony_wire::Error::Decrypt))
+        ));
+        assert_eq!(
+            server
+                .decrypt(CLIENT, suite, false, &request.message, now)
+                .unwrap(),
+            message()
+        );
+
+        // The server answers in the client's suite without naming it, and
+        // the client stops naming it once it has heard back.
+        let reply = server.encrypt(CLIENT, 7, &message(), now).unwrap();
+        assert_eq!(reply.suite, None);
+        assert_eq!(
+            client
+                .decrypt(SERVER, None, false, &reply.message, now)
+                .unwrap(),
+            message()
+        );
+        assert_eq!(
+            client.encrypt(SERVER, 8, &message(), now).unwrap().suite,
+            None
+        );
+        // AES-GCM, the suite of the Go transport, is never named.
+        let sealed = server.encrypt(OTHER_CLIENT, 1, &message(), now).unwrap();
+        assert_eq!(sealed.suite, None);
+    }
+
+    #[test]
+    fn refuses_suites_not_taken() {
+        let client = Encryption::with_suite(
+            CipherSuite::ChaCha20Poly1305,
+            &crypto::DEFAULT_PUBLIC_KEY,
+            &crypto::DEFAULT_PRIVATE_KEY,
+        )
+        .unwrap();
+        let server = Encryption::with_default_keys().unwrap();
+        server.set_suites(&[CipherSuite::Aes256Gcm]);
+        let now = Instant::now();
+        let request = client.encrypt(SERVER, 7, &message(), now).unwrap();
+        let suite = request.suite.map(CipherSuite::id);
+        assert!(matches!(
+            server.decrypt(CLIENT, suite, false, &request.message, now),
+            Err(Error::Encryption(symphony_wire::Error::UnsupportedSuite(2)))
+        ));
+        assert!(matches!(
+            server.decrypt(CLIENT, Some(0x7f), false, &request.message, now),
+            Err(Error::Encryption(symphony_wire::Error::UnsupportedSuite(
+                0x7f
+            )))
+        ));
+        assert!(server.peers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rekey_handshake_keeps_old_keys_for_the_window() {
+        let client = Encryption::with_default_keys().unwrap();
+        let server = Encryption::with_default_keys().unwrap();
+        let now = Instant::now();
+        let in_flight = client.encrypt(SERVER, 1, &message(), now).unwrap();
+
+        let epoch = client.start_rekey(SERVER, now).unwrap();
+        let request = Rekey { epoch, ack: false };
+        let packet = client.seal_rekey(SERVER, &request, now).unwrap();
+        assert_eq!(packet.len(), REKEY_PACKET_LEN);
+        assert_eq!(server.on_rekey(CLIENT, &packet, now).unwrap(), Some(1));
+        assert_eq!(server.epoch(CLIENT), 1);
+        // The client still seals in epoch 0 until it sees the ack, and the
+        // server still opens epoch 0 during the switchover.
+        assert_eq!(
+            server
+                .decrypt(CLIENT, None, in_flight.key_phase, &in_flight.message, now)
+                .unwrap(),
+            message()
+        );
+        let reply = server.encrypt(CLIENT, 1, &message(), now).unwrap();
+        assert!(reply.key_phase);
+        // A retried request is acknowledged again, without moving on.
+        let retried = client.seal_rekey(SERVER, &request, now).unwrap();
+        assert_eq!(server.on_rekey(CLIENT, &retried, now).unwrap(), Some(1));
+        assert_eq!(server.epoch(CLIENT), 1);
+
+        let ack = ack(&server, CLIENT, 1, now);
+        assert_eq!(client.on_rekey(SERVER, &ack, now).unwrap(), None);
+        assert_eq!(client.epoch(SERVER), 1);
+        assert_eq!(
+            client
+                .decrypt(SERVER, None, true, &reply.message, now)
+                .unwrap(),
+            message()
+        );
+
+        let later = now + SWITCHOVER_WINDOW;
+        assert!(server
+            .decrypt(CLIENT, None, false, &in_flight.message, later)
+            .is_err());
+    }
+
+    #[test]
+    fn rekeys_need_the_current_keys() {
+        let peer = Encryption::with_default_keys().unwrap();
+        let other = Encryption::new(&[7; 32], &[8; 32]).unwrap();
+        let now = Instant::now();
+        let request = Rekey {
+            epoch: 1,
+            ack: false,
+        };
+        let forged = other.seal_rekey(SERVER, &request, now).unwrap();
+        assert!(matches!(
+            peer.on_rekey(CLIENT, &forged, now),
+            Err(Error::Encryption(symphony_wire::Error::Decrypt))
+        ));
+        let mut tampered = Encryption::with_default_keys()
+            .unwrap()
+            .seal_rekey(SERVER, &request, now)
+            .unwrap();
+        tampered[REKEY_PACKET_LEN - 1] ^= 1;
+        assert!(peer.on_rekey(CLIENT, &tampered, now).is_err());
+        assert!(peer.on_rekey(CLIENT, &tampered[..8], now).is_err());
+        assert_eq!(peer.epoch(CLIENT), 0);
+        // Packets that do not open leave no state behind.
+        assert!(peer.peers.lock().unwrap().is_empty());
+
+        // Nor is an ack taken without a request of its own.
+        let ack = ack(&Encryption::with_default_keys().unwrap(), SERVER, 1, now);
+        assert_eq!(peer.on_rekey(CLIENT, &ack, now).unwrap(), None);
+        assert_eq!(peer.epoch(CLIENT), 0);
+    }
+
+    #[test]
+    fn ignores_rekeys_while_switching() {
+        let client = Encryption::with_default_keys().unwrap();
+        let server = Encryption::with_default_keys().unwrap();
+        let now = Instant::now();
+        let request = Rekey {
+            epoch: 1,
+            ack: false,
+        };
+        let packet = client.seal_rekey(SERVER, &request, now).unwrap();
+        assert_eq!(server.on_rekey(CLIENT, &packet, now).unwrap(), Some(1));
+        let ack = ack(&server, CLIENT, 1, now);
+        client.start_rekey(SERVER, now).unwrap();
+        client.on_rekey(SERVER, &ack, now).unwrap();
+
+        // A request for epoch 2 right away would drop epoch 0 mid-switch.
+        let request = Rekey {
+            epoch: 2,
+            ack: false,
+        };
+        let packet = client.seal_rekey(SERVER, &request, now).unwrap();
+        assert_eq!(server.on_rekey(CLIENT, &packet, now).unwrap(), None);
+        assert_eq!(server.epoch(CLIENT), 1);
+
+        let later = now + SWITCHOVER_WINDOW;
+        let packet = client.seal_rekey(SERVER, &request, later).unwrap();
+        assert_eq!(server.on_rekey(CLIENT, &packet, later).unwrap(), Some(2));
+        assert_eq!(server.epoch(CLIENT), 2);
+    }
+
+    #[test]
+    fn follows_a_peer_that_switched_first() {
+        let server = Encryption::with_default_keys().unwrap();
+        let client = Encryption::with_default_keys().unwrap();
+        let now = Instant::now();
+        server.start_rekey(CLIENT, now).unwrap();
+        server
+            .on_rekey(CLIENT, &ack(&client, SERVER, 1, now), now)
+            .unwrap();
+        assert_eq!(server.epoch(CLIENT), 1);
+        let sealed = server.encrypt(CLIENT, 3, &message(), now).unwrap();
+        assert_eq!(
+            client
+                .decrypt(SERVER, None, sealed.key_phase, &sealed.message, now)
+                .unwrap(),
+            message()
+        );
+        assert_eq!(client.epoch(SERVER), 1);
+
+        // Running out of nonces switches without waiting for the peer.
+        current(&server, CLIENT)
+            .seq
+            .store(MAX_SEQ, Ordering::Relaxed);
+        let sealed = server.encrypt(CLIENT, 4, &message(), now).unwrap();
+        assert_eq!((server.epoch(CLIENT), sealed.key_phase), (2, false));
+        assert_eq!(
+            client
+                .decrypt(SERVER, None, sealed.key_phase, &sealed.message, now)
+                .unwrap(),
+            message()
+        );
+    }
+
+    #[test]
+    fn asks_to_rekey_after_the_limit() {
+        let encryption = Encryption::with_default_keys().unwrap();
+        let now = Instant::now();
+        current(&encryption, SERVER)
+            .seq
+            .store(REKEY_AFTER, Ordering::Relaxed);
+        let sealed = encryption.encrypt(SERVER, 1, &message(), now).unwrap();
+        assert_eq!(sealed.rekey, Some(1));
+        // Not again until the retry interval passes.
+        let sealed = encryption.encrypt(SERVER, 1, &message(), now).unwrap();
+        assert_eq!(sealed.rekey, None);
+        let sealed = encryption
+            .encrypt(SERVER, 1, &message(), now + REKEY_RETRY)
+            .unwrap();
+        assert_eq!(sealed.rekey, Some(1));
+    }
+
+    #[test]
+    fn rekeys_each_peer_on_its_own() {
+        let server = Encryption::with_default_keys().unwrap();
+        let busy = Encryption::with_default_keys().unwrap();
+        let idle = Encryption::with_default_keys().unwrap();
+        let now = Instant::now();
+
+        // Only the busy client crosses the limit and rekeys with the server.
+        current(&busy, SERVER)
+            .seq
+            .store(REKEY_AFTER, Ordering::Relaxed);
+        let sealed = busy.encrypt(SERVER, 1, &message(), now).unwrap();
+        let epoch = sealed.rekey.unwrap();
+        let packet = busy
+            .seal_rekey(SERVER, &Rekey { epoch, ack: false }, now)
+            .unwrap();
+        assert_eq!(server.on_rekey(CLIENT, &packet, now).unwrap(), Some(1));
+        busy.on_rekey(SERVER, &ack(&server, CLIENT, 1, now), now)
+            .unwrap();
+        assert_eq!((busy.epoch(SERVER), server.epoch(CLIENT)), (1, 1));
+        assert_eq!(server.epoch(OTHER_CLIENT), 0);
+
+        // The idle client comes back after the switchover window, still in
+        // epoch 0, and both directions still open.
+        let later = now + SWITCHOVER_WINDOW * 2;
+        let request = idle.encrypt(SERVER, 2, &message(), later).unwrap();
+        assert!(!request.key_phase);
+        assert_eq!(
+            server
+                .decrypt(
+                    OTHER_CLIENT,
+                    None,
+                    request.key_phase,
+                    &request.message,
+                    later
+                )
+                .unwrap(),
+            message()
+        );
+        let reply = server.encrypt(OTHER_CLIENT, 2, &message(), later).unwrap();
+        assert!(!reply.key_phase);
+        assert_eq!(
+            idle.decrypt(SERVER, None, reply.key_phase, &reply.message, later)
+                .unwrap(),
+            message()
+        );
+        assert_eq!((idle.epoch(SERVER), server.epoch(OTHER_CLIENT)), (0, 0));
+        assert_eq!(server.epoch(CLIENT), 1);
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::io;
+
+use crate::message::DecodeError;
+use crate::status::{Code, Status};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The target does not resolve to an IPv4 address; Symphony packet
+    /// headers only carry IPv4.
+    Unresolved(String),
+    /// The service is not in the client's registry.
+    UnknownService(String),
+    /// The method is not registered for the service.
+    UnknownMethod {
+        service: String,
+        method: String,
+    },
+    /// The marshaled message needs more packets than a packet header can
+    /// count.
+    TooLarge(usize),
+    /// The message is too short to hold the Symphony header, or its private
+    /// segment offset is past its end.
+    MissingHeader,
+    /// The response could not be unmarshaled.
+    Decode(DecodeError),
+    /// The server answered with an error packet. Handlers return it to
+    /// choose the status the caller sees.
+    Status(Status),
+    /// The client was dropped while the call was in flight.
+    Closed,
+    /// No reply arrived within the call timeout.
+    TimedOut,
+    /// A balanced client has no endpoint to send the call to.
+    NoEndpoints,
+    /// A message could not be encrypted, or an encryption key is invalid.
+    Encryption(symphony_wire::Error),
+    /// A DTLS session failed, or its configuration is invalid.
+    #[cfg(feature = "dtls")]
+    Dtls(String),
+    /// A QUIC connection or stream failed, or its configuration is
+    /// invalid.
+    #[cfg(feature = "quic")]
+    Quic(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Unresolved(target) => write!(f, "no IPv4 address for {}", target),
+            Error::UnknownService(service) => {
+                write!(f, "service not found in registry: {}", service)
+            }
+            Error::UnknownMethod { service, method } => {
+                write!(f, "method not found in registry: {}.{}", service, method)
+            }
+            Error::TooLarge(len) => {
+                write!(f, "message of {} bytes is too large to send", len)
+            }
+            Error::MissingHeader => write!(f, "message lacks a valid Symphony header"),
+            Error::Decode(e) => write!(f, "failed to unmarshal response: {}", e),
+            Error::Status(status) => write!(f, "{}", status),
+            Error::Closed => write!(f, "client closed"),
+            Error::TimedOut => write!(f, "rpc timed out"),
+            Error::NoEndpoints => write!(f, "no endpoints to call"),
+            Error::Encryption(e) => write!(f, "encryption failed: {}", e),
+            #[cfg(feature = "dtls")]
+            Error::Dtls(e) => write!(f, "DTLS failed: {}", e),
+            #[cfg(feature = "quic")]
+            Error::Quic(e) => write!(f, "QUIC failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            Error::Encryption(e) => Some(e),
+            Error::Status(status) => Some(status),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// The status of the failure: the one the server sent, or the canonical
+    /// code of a failure on this side.
+    pub fn status(&self) -> Status {
+        let code = match self {
+            Error::Status(status) => return status.clone(),
+            Error::Io(_) | Error::Unresolved(_) | Error::NoEndpoints => Code::Unavailable,
+            Error::UnknownService(_) | Error::UnknownMethod { .. } => Code::Unimplemented,
+            Error::TooLarge(_) => Code::ResourceExhausted,
+            Error::MissingHeader | Error::Decode(_) | Error::Encryption(_) => Code::Internal,
+            Error::Closed => Code::Cancelled,
+            Error::TimedOut => Code::DeadlineExceeded,
+            #[cfg(feature = "dtls")]
+            Error::Dtls(_) => Code::Unavailable,
+            #[cfg(feature = "quic")]
+            Error::Quic(_) => Code::Unavailable,
+        };
+        Status::new(code, self.to_string())
+    }
+}
+
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        Error::Status(status)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::Decode(e)
+    }
+}
+
+#[cfg(feature = "dtls")]
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        Error::Dtls(e.to_string())
+    }
+}
+
+#[cfg(feature = "dtls")]
+impl From<openssl::ssl::Error> for Error {
+    fn from(e: openssl::ssl::Error) -> Self {
+        Error::Dtls(e.to_string())
+    }
+}
@@ -0,0 +1,233 @@
+//! Per-message fields carried in the extension block of a message's first
+//! data packet; see `symphony_wire`.
+//!
+//! The block is a list of `[tag(1B)][length(2B)][value]` entries. Entries
+//! with unknown tags are skipped, so that newer peers can add fields.
+
+use std::time::Duration;
+
+use crate::compression::{Codec, Encoding};
+use crate::window::WindowUpdate;
+use crate::MetadataMap;
+
+/// The time left to handle a request, in microseconds (u64).
+const TAG_TIMEOUT: u8 = 1;
+/// The stream a message belongs to: `[stream RPC ID(8B)][seq(4B)][flags(1B)]`.
+const TAG_STREAM: u8 = 2;
+/// Metadata of the message; see [`crate::metadata`].
+const TAG_METADATA: u8 = 3;
+/// How the message is compressed: `[codec(1B)][decompressed length(4B)]`;
+/// see [`crate::compression`].
+const TAG_ENCODING: u8 = 4;
+/// The codecs the sender takes messages in, one byte each.
+const TAG_ACCEPT: u8 = 5;
+/// Credit granted a stream: `[stream RPC ID(8B)][limit(8B)]`; see
+/// [`crate::window`].
+const TAG_WINDOW: u8 = 6;
+/// The server is shutting down; see [`crate::drain`]. Empty.
+const TAG_GOAWAY: u8 = 7;
+/// The sender gave up on a call: `[RPC ID(8B)]`; see [`crate::cancel`].
+const TAG_CANCEL: u8 = 8;
+/// The cipher suite the message is sealed in, by its wire ID (1B); see
+/// [`crate::encryption`].
+const TAG_SUITE: u8 = 9;
+
+/// Flag of the last frame of a stream.
+const STREAM_END: u8 = 0x01;
+
+/// The fields of an extension block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Extensions {
+    /// How long the caller waits for the response, from when it sent the
+    /// request.
+    pub timeout: Option<Duration>,
+    /// Set on the frames of a stream; see [`crate::stream`].
+    pub stream: Option<StreamFrame>,
+    pub metadata: MetadataMap,
+    pub encoding: Option<Encoding>,
+    /// Codecs this side does not know are left out.
+    pub accept: Vec<Codec>,
+    pub window: Option<WindowUpdate>,
+    pub goaway: bool,
+    pub cancel: Option<u64>,
+    /// Suites are kept by wire ID, so that one this side lacks can be
+    /// refused by name.
+    pub suite: Option<u8>,
+}
+
+/// Where a message goes in a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StreamFrame {
+    /// The RPC ID of the stream, under which failures are reported.
+    pub id: u64,
+    /// The position of the frame in its direction of the stream.
+    pub seq: u32,
+    /// Whether this is the last frame, which carries no message.
+    pub end: bool,
+}
+
+impl Extensions {
+    /// The extension block, empty if no field is set so that the packets
+    /// stay readable by the Go transport.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if let Some(timeout) = self.timeout {
+            let micros = u64::try_from(timeout.as_micros()).unwrap_or(u64::MAX);
+            put(&mut buf, TAG_TIMEOUT, &micros.to_le_bytes());
+        }
+        if let Some(frame) = self.stream {
+            let mut value = [0; 13];
+            value[..8].copy_from_slice(&frame.id.to_le_bytes());
+            value[8..12].copy_from_slice(&frame.seq.to_le_bytes());
+            value[12] = if frame.end { STREAM_END } else { 0 };
+            put(&mut buf, TAG_STREAM, &value);
+        }
+        if !self.metadata.is_empty() {
+            put(&mut buf, TAG_METADATA, &self.metadata.encode());
+        }
+        if let Some(encoding) = self.encoding {
+            let mut value = [0; 5];
+            value[0] = encoding.codec;
+            value[1..].copy_from_slice(&encoding.len.to_le_bytes());
+            put(&mut buf, TAG_ENCODING, &value);
+        }
+        if !self.accept.is_empty() {
+            let codecs: Vec<u8> = self.accept.iter().map(|codec| codec.id()).collect();
+            put(&mut buf, TAG_ACCEPT, &codecs);
+        }
+        if let Some(update) = self.window {
+            let mut value = [0; 16];
+            value[..8].copy_from_slice(&update.id.to_le_bytes());
+            value[8..].copy_from_slice(&update.limit.to_le_bytes());
+            put(&mut buf, TAG_WINDOW, &value);
+        }
+        if self.goaway {
+            put(&mut buf, TAG_GOAWAY, &[]);
+        }
+        if let Some(rpc_id) = self.cancel {
+            put(&mut buf, TAG_CANCEL, &rpc_id.to_le_bytes());
+        }
+        if let Some(id) = self.suite {
+            put(&mut buf, TAG_SUITE, &[id]);
+        }
+        buf
+    }
+
+    /// Whether the message means something else without its block: a
+    /// stream frame, a compressed message, or a window update, GOAWAY or
+    /// cancel, which carry no message of their own.
+    pub fn required(&self) -> bool {
+        self.stream.is_some()
+            || self.encoding.is_some()
+            || self.window.is_some()
+            || self.goaway
+            || self.cancel.is_some()
+    }
+
+    /// Parses an extension block. Returns None if an entry runs past its
+    /// end or a known field has the wrong length.
+    pub fn parse(mut buf: &[u8]) -> Option<Extensions> {
+        let mut extensions = Extensions::default();
+        while !buf.is_empty() {
+            let (&tag, rest) = buf.split_first()?;
+            let len = u16::from_le_bytes(rest.get(..2)?.try_into().unwrap()) as usize;
+            let value = rest.get(2..2 + len)?;
+            buf = &rest[2 + len..];
+            match tag {
+                TAG_TIMEOUT => {
+                    let micros = u64::from_le_bytes(value.try_into().ok()?);
+                    extensions.timeout = Some(Duration::from_micros(micros));
+                }
+                TAG_STREAM => {
+                    if value.len() != 13 {
+                        return None;
+                    }
+                    extensions.stream = Some(StreamFrame {
+                        id: u64::from_le_bytes(value[..8].try_into().unwrap()),
+                        seq: u32::from_le_bytes(value[8..12].try_into().unwrap()),
+                        end: value[12] & STREAM_END != 0,
+                    });
+                }
+                TAG_METADATA => extensions.metadata = MetadataMap::parse(value)?,
+                TAG_ENCODING => {
+                    if value.len() != 5 {
+                        return None;
+                    }
+                    extensions.encoding = Some(Encoding {
+                        codec: value[0],
+                        len: u32::from_le_bytes(value[1..].try_into().unwrap()),
+                    });
+                }
+                TAG_ACCEPT => {
+                    extensions.accept = value.iter().copied().filter_map(Codec::from_id).collect();
+                }
+                TAG_WINDOW => {
+                    if value.len() != 16 {
+                        return None;
+                    }
+                    extensions.window = Some(WindowUpdate {
+                        id: u64::from_le_bytes(value[..8].try_into().unwrap()),
+                        limit: u64::from_le_bytes(value[8..].try_into().unwrap()),
+                    });
+                }
+                TAG_GOAWAY => extensions.goaway = true,
+                TAG_CANCEL => extensions.cancel = Some(u64::from_le_bytes(value.try_into().ok()?)),
+                TAG_SUITE => {
+                    let [id] = value else {
+                        return None;
+                    };
+                    extensions.suite = Some(*id);
+                }
+                _ => {}
+            }
+        }
+        Some(extensions)
+    }
+}
+
+fn put(buf: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    buf.push(tag);
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_skips_unknown_tags() {
+        assert!(Extensions::default().encode().is_empty());
+        assert_eq!(Extensions::parse(&[]), Some(Extensions::default()));
+
+        let mut extensions = Extensions {
+            timeout: Some(Duration::from_millis(1500)),
+            stream: Some(StreamFrame {
+                id: 7,
+                seq: 3,
+                end: true,
+            }),
+            metadata: MetadataMap::new(),
+            encoding: Some(Encoding {
+                codec: 9,
+                len: 1000,
+            }),
+            accept: vec![Codec::Identity],
+            window: Some(WindowUpdate {
+                id: 7,
+                limit: 1 << 40,
+            }),
+            goaway: true,
+            cancel: Some(42),
+            suite: Some(2),
+        };
+        extensions.metadata.insert_bin("token-bin", &[1, 2]);
+        let mut buf = vec![0x7f, 3, 0, 1, 2, 3];
+        buf.extend_from_slice(&extensions.encode());
+        assert_eq!(Extensions::parse(&buf), Some(extensions));
+
+        assert_eq!(Extensions::parse(&buf[..buf.len() - 1]), None);
+        assert_eq!(Extensions::parse(&[TAG_TIMEOUT, 1, 0, 0]), None);
+        assert_eq!(Extensions::parse(&[TAG_SUITE, 0, 0]), None);
+    }
+}
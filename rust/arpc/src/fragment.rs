@@ -0,0 +1,450 @@
+//! Splitting messages across packets and putting them back together
+//! (`pkg/transport/symphony_fragmentation.go` and `fragmentation.go`).
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddrV4;
+use std::time::{Duration, Instant};
+
+use symphony_wire::DataPacket;
+
+use crate::buffer;
+
+/// Splits `message` into chunks of at most `mtu` bytes, like `FragmentPackets`
+/// in Go: full chunks of the public segment, then one joining the rest of it
+/// to the head of the private segment, sized so that the remaining private
+/// data fills whole chunks. Returns None if the message is too short to
+/// hold the offset of its private segment, or the offset is past its end.
+pub(crate) fn split(message: &[u8], mtu: usize) -> Option<Vec<&[u8]>> {
+    if message.len() <= mtu {
+        return Some(vec![message]);
+    }
+    let offset = u32::from_le_bytes(message.get(1..5)?.try_into().unwrap()) as usize;
+    if offset > message.len() {
+        return None;
+    }
+
+    let mut chunks = Vec::with_capacity(message.len() / mtu + 2);
+    let mut start = 0;
+    while offset - start > mtu {
+        chunks.push(&message[start..start + mtu]);
+        start += mtu;
+    }
+    // The meeting chunk; it overflows into a short one when the public
+    // remainder and the private head do not fit together.
+    let end = offset + (message.len() - offset) % mtu;
+    if end - start > mtu {
+        chunks.push(&message[start..start + mtu]);
+        start += mtu;
+    }
+    if end > start {
+        chunks.push(&message[start..end]);
+    }
+    chunks.extend(message[end..].chunks(mtu));
+    Some(chunks)
+}
+
+/// Bounds on the messages being reassembled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReassemblyLimits {
+    /// How long the fragments of a message wait for the rest, from the
+    /// first one to arrive.
+    pub timeout: Duration,
+    /// The largest message accepted; its fragments are dropped past it.
+    pub max_message_len: usize,
+    /// The most bytes held across incomplete messages. The oldest messages
+    /// are dropped to make room for new fragments.
+    pub max_buffered: usize,
+}
+
+impl Default for ReassemblyLimits {
+    fn default() -> Self {
+        ReassemblyLimits {
+            timeout: Duration::from_secs(5),
+            max_message_len: 16 << 20,
+            max_buffered: 64 << 20,
+        }
+    }
+}
+
+/// Counts of messages split into several packets and joined back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FragmentStats {
+    /// Messages sent in more than one packet.
+    pub split: u64,
+    /// The packets of those.
+    pub fragments: u64,
+    /// Messages received in more than one packet, once joined.
+    pub reassembled: u64,
+    /// Messages received in part, then dropped by the reassembly limits.
+    pub dropped: u64,
+}
+
+impl std::ops::AddAssign for FragmentStats {
+    fn add_assign(&mut self, other: FragmentStats) {
+        self.split += other.split;
+        self.fragments += other.fragments;
+        self.reassembled += other.reassembled;
+        self.dropped += other.dropped;
+    }
+}
+
+/// Fragments of one message, by sender and RPC ID.
+type Key = (SocketAddrV4, u64);
+
+/// Collects fragments until their message is complete (`DataReassembler`
+/// in Go). Fragments may arrive in any order; each packet of a message may
+/// itself have been split by a proxy, into pieces numbered by
+/// `fragment_index` with the last one clearing `more_fragments`.
+pub(crate) struct Reassembler {
+    limits: ReassemblyLimits,
+    partial: HashMap<Key, Partial>,
+    // Incomplete messages by arrival, which is also the order they expire
+    // in. Entries of messages that completed since are skipped.
+    arrivals: VecDeque<(Instant, Key)>,
+    buffered: usize,
+    // Only `reassembled` and `dropped`.
+    stats: FragmentStats,
+}
+
+struct Partial {
+    started: Instant,
+    packets: Vec<Pieces>,
+    complete: usize,
+    len: usize,
+    extensions: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Pieces {
+    pieces: BTreeMap<u8, Vec<u8>>,
+    last: Option<u8>,
+}
+
+impl Pieces {
+    fn is_complete(&self) -> bool {
+        // Keys are unique, so having `last + 1` of them ending at `last`
+        // means none is missing.
+        self.last.is_some_and(|last| {
+            self.pieces.len() == last as usize + 1 && self.pieces.keys().next_back() == Some(&last)
+        })
+    }
+}
+
+impl Reassembler {
+    pub fn new(limits: ReassemblyLimits) -> Reassembler {
+        Reassembler {
+            limits,
+            partial: HashMap::new(),
+            arrivals: VecDeque::new(),
+            buffered: 0,
+            stats: FragmentStats::default(),
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: ReassemblyLimits) {
+        self.limits = limits;
+    }
+
+    /// Adds a fragment, returning its message and the extension block of
+    /// its first packet once every fragment arrived.
+    pub fn insert(&mut self, packet: &DataPacket, now: Instant) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.expire(now);
+        if packet.seq >= packet.total_packets {
+            log::warn!(
+                "dropping fragment {} of rpc {} from {}: message has {} packets",
+                packet.seq,
+                packet.rpc_id,
+                packet.src,
+                packet.total_packets
+            );
+            return None;
+        }
+        let key = (packet.src, packet.rpc_id);
+        let len = packet.payload.len();
+
+        if let Some(partial) = self.partial.get(&key) {
+            if partial.packets.len() != packet.total_packets as usize {
+                log::warn!(
+                    "dropping fragment of rpc {} from {}: message had {} packets, now {}",
+                    packet.rpc_id,
+                    packet.src,
+                    partial.packets.len(),
+                    packet.total_packets
+                );
+                return None;
+            }
+        }
+        let message_len = self.partial.get(&key).map_or(0, |p| p.len) + len;
+        if message_len > self.limits.max_message_len {
+            log::warn!(
+                "dropping rpc {} from {}: message exceeds {} bytes",
+                packet.rpc_id,
+                packet.src,
+                self.limits.max_message_len
+            );
+            self.remove(&key);
+            self.stats.dropped += 1;
+            return None;
+        }
+        while self.buffered + len > self.limits.max_buffered && self.evict_oldest(&key) {}
+        if self.buffered + len > self.limits.max_buffered {
+            log::warn!(
+                "dropping fragment of rpc {} from {}: reassembly buffer full",
+                packet.rpc_id,
+                packet.src
+            );
+            return None;
+        }
+
+        let partial = match self.partial.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                self.arrivals.push_back((now, key));
+                entry.insert(Partial {
+                    started: now,
+                    packets: (0..packet.total_packets)
+                        .map(|_| Pieces::default())
+                        .collect(),
+                    complete: 0,
+                    len: 0,
+                    extensions: Vec::new(),
+                })
+            }
+        };
+        let pieces = &mut partial.packets[packet.seq as usize];
+        if pieces.pieces.contains_key(&packet.fragment_index) {
+            log::debug!(
+                "dropping duplicate fragment {}.{} of rpc {}",
+                packet.seq,
+                packet.fragment_index,
+                packet.rpc_id
+            );
+            return None;
+        }
+        let was_complete = pieces.is_complete();
+        pieces
+            .pieces
+            .insert(packet.fragment_index, buffer::copy(packet.payload));
+        if !packet.more_fragments {
+            pieces.last = pieces.last.max(Some(packet.fragment_index));
+        }
+        match (was_complete, pieces.is_complete()) {
+            (false, true) => partial.complete += 1,
+            (true, false) => partial.complete -= 1,
+            _ => {}
+        }
+        if !packet.extensions.is_empty() {
+            partial.extensions = packet.extensions.to_vec();
+        }
+        partial.len += len;
+        self.buffered += len;
+
+        if partial.complete < partial.packets.len() {
+            return None;
+        }
+        let partial = self.remove(&key)?;
+        self.stats.reassembled += 1;
+        let mut message = buffer::take(partial.len);
+        for pieces in partial.packets {
+            for piece in pieces.pieces.into_values() {
+                message.extend_from_slice(&piece);
+                buffer::give(piece);
+            }
+        }
+        Some((message, partial.extensions))
+    }
+
+    /// Drops the oldest incomplete message other than `keep`, returning
+    /// false if there is none.
+    fn evict_oldest(&mut self, keep: &Key) -> bool {
+        let Some(index) = self.arrivals.iter().position(|(started, key)| {
+            key != keep && self.partial.get(key).is_some_and(|p| p.started == *started)
+        }) else {
+            return false;
+        };
+        let (_, key) = self.arrivals.remove(index).unwrap();
+        log::warn!(
+            "dropping rpc {} from {}: reassembly buffer full",
+            key.1,
+            key.0
+        );
+        self.remove(&key);
+        self.stats.dropped += 1;
+        true
+    }
+
+    /// Drops the messages whose fragments have waited too long.
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(started, key)) = self.arrivals.front() {
+            if now.duration_since(started) < self.limits.timeout {
+                break;
+            }
+            self.arrivals.pop_front();
+            if self.partial.get(&key).is_some_and(|p| p.started == started) {
+                log::debug!("reassembly of rpc {} from {} timed out", key.1, key.0);
+                self.remove(&key);
+                self.stats.dropped += 1;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> FragmentStats {
+        self.stats
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Partial> {
+        let partial = self.partial.remove(key)?;
+        self.buffered -= partial.len;
+        Some(partial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use symphony_wire::{PacketType, DATA_HEADER_LEN, MAX_PACKET_LEN};
+
+    const SRC: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9000);
+
+    /// A message of `len` bytes whose private segment starts at `offset`.
+    fn message(len: usize, offset: u32) -> Vec<u8> {
+        let mut message: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        message[1..5].copy_from_slice(&offset.to_le_bytes());
+        message
+    }
+
+    fn packet(rpc_id: u64, total: usize, seq: usize, payload: &[u8]) -> DataPacket<'_> {
+        DataPacket {
+            kind: PacketType::Request,
+            rpc_id,
+            total_packets: total as u16,
+            seq: seq as u16,
+            more_fragments: false,
+            fragment_index: 0,
+            key_phase: false,
+            dst: SRC,
+            src: SRC,
+            extensions: &[],
+            payload,
+        }
+    }
+
+    #[test]
+    fn split_aligns_private_segment() {
+        let message = message(100, 25);
+        let lens: Vec<_> = split(&message, 10)
+            .unwrap()
+            .iter()
+            .map(|c| c.len())
+            .collect();
+        // 20 public bytes, then 5 public and 5 private, then 70 private.
+        assert_eq!(lens, [10, 10, 10, 10, 10, 10, 10, 10, 10, 10]);
+
+        let message = self::message(100, 28);
+        let chunks = split(&message, 10).unwrap();
+        let lens: Vec<_> = chunks.iter().map(|c| c.len()).collect();
+        // The meeting chunk (8 public, 2 private) fits; the rest aligns.
+        assert_eq!(lens, [10, 10, 10, 10, 10, 10, 10, 10, 10, 10]);
+        assert_eq!(chunks.concat(), message);
+
+        let message = self::message(105, 19);
+        let chunks = split(&message, 10).unwrap();
+        let lens: Vec<_> = chunks.iter().map(|c| c.len()).collect();
+        // 9 public bytes and the 86 % 10 = 6 byte private head overflow the
+        // meeting chunk into a short one.
+        assert_eq!(lens, [10, 10, 5, 10, 10, 10, 10, 10, 10, 10, 10]);
+        assert_eq!(chunks.concat(), message);
+
+        let message = self::message(100, 38);
+        let chunks = split(&message, 12).unwrap();
+        let lens: Vec<_> = chunks.iter().map(|c| c.len()).collect();
+        // 2 public bytes remain after 36; with the 62 % 12 = 2 byte private
+        // head the meeting chunk is 4, and 60 private bytes follow.
+        assert_eq!(lens, [12, 12, 12, 4, 12, 12, 12, 12, 12]);
+        assert_eq!(chunks.concat(), message);
+
+        assert_eq!(split(&message[..4], 2), None);
+        assert_eq!(split(&self::message(20, 21), 10), None);
+    }
+
+    #[test]
+    fn reassembles_out_of_order() {
+        let message = message(5000, 1500);
+        let chunks = split(&message, MAX_PACKET_LEN - DATA_HEADER_LEN).unwrap();
+        let mut reassembler = Reassembler::new(ReassemblyLimits::default());
+        let now = Instant::now();
+        let mut order: Vec<_> = (0..chunks.len()).rev().collect();
+        order.swap(0, 1);
+        let last = order.pop().unwrap();
+        for &seq in &order {
+            assert_eq!(
+                reassembler.insert(&packet(1, chunks.len(), seq, chunks[seq]), now),
+                None
+            );
+        }
+        // A duplicate does not count twice.
+        assert_eq!(
+            reassembler.insert(&packet(1, chunks.len(), order[0], chunks[order[0]]), now),
+            None
+        );
+        let got = reassembler.insert(&packet(1, chunks.len(), last, chunks[last]), now);
+        assert_eq!(got, Some((message, Vec::new())));
+        assert_eq!(reassembler.buffered, 0);
+    }
+
+    #[test]
+    fn reassembles_proxy_pieces() {
+        let mut reassembler = Reassembler::new(ReassemblyLimits::default());
+        let now = Instant::now();
+        let mut pieces = [
+            packet(2, 2, 0, b"ab"),
+            packet(2, 2, 0, b"cd"),
+            packet(2, 2, 1, b"ef"),
+        ];
+        pieces[0].more_fragments = true;
+        pieces[0].extensions = b"ext";
+        pieces[1].fragment_index = 1;
+        assert_eq!(reassembler.insert(&pieces[1], now), None);
+        assert_eq!(reassembler.insert(&pieces[2], now), None);
+        assert_eq!(
+            reassembler.insert(&pieces[0], now),
+            Some((b"abcdef".to_vec(), b"ext".to_vec()))
+        );
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = ReassemblyLimits {
+            timeout: Duration::from_secs(1),
+            max_message_len: 10,
+            max_buffered: 12,
+        };
+        let mut reassembler = Reassembler::new(limits);
+        let now = Instant::now();
+
+        // Timed out fragments are dropped before new ones are added.
+        reassembler.insert(&packet(1, 2, 0, b"aaaa"), now);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(reassembler.insert(&packet(1, 2, 1, b"bbbb"), later), None);
+        assert_eq!(reassembler.buffered, 4);
+
+        // Oversized messages are dropped.
+        reassembler.insert(&packet(2, 3, 0, b"cccccc"), later);
+        assert_eq!(reassembler.insert(&packet(2, 3, 1, b"cccccc"), later), None);
+        assert!(!reassembler.partial.contains_key(&(SRC, 2)));
+
+        // A full buffer drops the oldest messages, but not the one the
+        // fragment belongs to.
+        reassembler.insert(&packet(3, 2, 0, b"dddddd"), later);
+        reassembler.insert(&packet(4, 2, 0, b"eeeeee"), later);
+        assert!(!reassembler.partial.contains_key(&(SRC, 1)));
+        assert!(reassembler.partial.contains_key(&(SRC, 3)));
+        assert_eq!(
+            reassembler.insert(&packet(4, 2, 1, b"ee"), later),
+            Some((b"eeeeeeee".to_vec(), Vec::new()))
+        );
+        assert!(!reassembler.partial.contains_key(&(SRC, 3)));
+    }
+}
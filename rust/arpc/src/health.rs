@@ -0,0 +1,350 @@
+//! The health-checking service, after `grpc.health.v1`, and its client.
+//!
+//! The messages and stubs are those `protoc-gen-symphony-rust` generates
+//! for `proto/health.proto`, with the messages written out by hand since
+//! this crate cannot use its own derive. A server registers a
+//! [`HealthReporter`] wrapped in a [`HealthServer`] and sets the status of
+//! each of its services on it; `Check` answers with a service's status, or
+//! fails with [`Code::NotFound`] for one never set, and `Watch` streams a
+//! service's status whenever it changes.
+//!
+//! The service is registered under [`HEALTH_ID`], a reserved ID out of the
+//! range the generators number services from. A [`crate::Balancer`] probes
+//! its endpoints with it to eject unhealthy ones; see
+//! [`crate::Balancer::check_health`].
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+use crate::symphony::{MessageReader, MessageWriter, Value};
+use crate::{BoxFuture, BoxStream, Client, Code, DecodeError, Error, Message, Status};
+
+/// Service ID of `Health`.
+pub const HEALTH_ID: u32 = 0xFFFF_0001;
+
+/// Method names and IDs of `Health`.
+pub const HEALTH_METHODS: &[(&str, u32)] = &[("Check", 1), ("Watch", 2)];
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HealthCheckRequest {
+    /// The service to check, by name; empty for the server as a whole.
+    pub service: String,
+}
+
+impl Message for HealthCheckRequest {
+    fn marshal_symphony(&self) -> Vec<u8> {
+        let mut message = MessageWriter::new(0);
+        Value::encode(&self.service, &mut message.private(String::TABLE_LEN), 0);
+        message.finish()
+    }
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError> {
+        let message = MessageReader::new(data)?;
+        Ok(HealthCheckRequest {
+            service: Value::decode(&message.private(), 0)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HealthCheckResponse {
+    /// A [`ServingStatus`].
+    pub status: i32,
+}
+
+impl Message for HealthCheckResponse {
+    fn marshal_symphony(&self) -> Vec<u8> {
+        let mut message = MessageWriter::new(0);
+        Value::encode(&self.status, &mut message.private(i32::TABLE_LEN), 0);
+        message.finish()
+    }
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError> {
+        let message = MessageReader::new(data)?;
+        Ok(HealthCheckResponse {
+            status: Value::decode(&message.private(), 0)?,
+        })
+    }
+}
+
+impl HealthCheckResponse {
+    /// The status, [`ServingStatus::Unknown`] for a value this side does
+    /// not know.
+    pub fn serving_status(&self) -> ServingStatus {
+        match self.status {
+            1 => ServingStatus::Serving,
+            2 => ServingStatus::NotServing,
+            3 => ServingStatus::ServiceUnknown,
+            _ => ServingStatus::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(i32)]
+pub enum ServingStatus {
+    Unknown = 0,
+    Serving = 1,
+    NotServing = 2,
+    /// Only sent by `Watch`, for a service the server does not know.
+    ServiceUnknown = 3,
+}
+
+impl From<ServingStatus> for HealthCheckResponse {
+    fn from(status: ServingStatus) -> Self {
+        HealthCheckResponse {
+            status: status as i32,
+        }
+    }
+}
+
+/// Client for `Health`.
+pub struct HealthClient {
+    client: Arc<Client>,
+}
+
+impl HealthClient {
+    /// Wraps `client`, registering the service's IDs with it.
+    pub fn new(client: Arc<Client>) -> HealthClient {
+        client.register_service("Health", HEALTH_ID, HEALTH_METHODS);
+        HealthClient { client }
+    }
+
+    pub async fn check(&self, req: &HealthCheckRequest) -> Result<HealthCheckResponse, Error> {
+        self.client.call("Health", "Check", req).await
+    }
+
+    pub async fn watch(
+        &self,
+        req: &HealthCheckRequest,
+    ) -> Result<crate::Streaming<HealthCheckResponse>, Error> {
+        self.client
+            .server_streaming("Health", "Watch", req, &crate::CallOptions::default())
+            .await
+    }
+}
+
+/// Server API for `Health`; register it wrapped in [`HealthServer`].
+pub trait Health: Send + Sync + 'static {
+    fn check(
+        self: Arc<Self>,
+        req: HealthCheckRequest,
+    ) -> BoxFuture<Result<HealthCheckResponse, Error>>;
+
+    fn watch(
+        self: Arc<Self>,
+        req: HealthCheckRequest,
+    ) -> BoxFuture<Result<BoxStream<Result<HealthCheckResponse, Error>>, Error>>;
+}
+
+/// Serves a [`Health`] implementation.
+pub struct HealthServer<T>(pub Arc<T>);
+
+impl<T: Health> HealthServer<T> {
+    pub fn new(service: T) -> HealthServer<T> {
+        HealthServer(Arc::new(service))
+    }
+}
+
+impl<T: Health> crate::Service for HealthServer<T> {
+    fn describe(self: Arc<Self>) -> crate::ServiceDesc {
+        crate::ServiceDesc::new("Health", HEALTH_ID)
+            .method("Check", 1, {
+                let service = self.0.clone();
+                move |req: HealthCheckRequest| service.clone().check(req)
+            })
+            .server_streaming("Watch", 2, {
+                let service = self.0.clone();
+                move |req: HealthCheckRequest| service.clone().watch(req)
+            })
+    }
+}
+
+/// The statuses a server reports, by service name. The server as a whole,
+/// the empty name, starts out serving.
+#[derive(Default)]
+pub struct HealthReporter {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    statuses: HashMap<String, ServingStatus>,
+    watchers: Vec<(String, mpsc::UnboundedSender<ServingStatus>)>,
+}
+
+impl HealthReporter {
+    pub fn new() -> HealthReporter {
+        let reporter = HealthReporter::default();
+        reporter.set_status("", ServingStatus::Serving);
+        reporter
+    }
+
+    /// Sets the status of `service`, telling those watching it if it
+    /// changed.
+    pub fn set_status(&self, service: &str, status: ServingStatus) {
+        let mut state = self.state.lock().unwrap();
+        if state.statuses.insert(service.to_string(), status) == Some(status) {
+            return;
+        }
+        state
+            .watchers
+            .retain(|(watched, tx)| watched != service || tx.send(status).is_ok());
+    }
+
+    /// Forgets `service`, which is reported unknown from now on.
+    pub fn clear(&self, service: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.statuses.remove(service).is_none() {
+            return;
+        }
+        state.watchers.retain(|(watched, tx)| {
+            watched != service || tx.send(ServingStatus::ServiceUnknown).is_ok()
+        });
+    }
+
+    /// Marks every service not serving, as a server does before it shuts
+    /// down.
+    pub fn shutdown(&self) {
+        let services: Vec<String> = self
+            .state
+            .lock()
+            .unwrap()
+            .statuses
+            .keys()
+            .cloned()
+            .collect();
+        for service in services {
+            self.set_status(&service, ServingStatus::NotServing);
+        }
+    }
+
+    pub fn status(&self, service: &str) -> Option<ServingStatus> {
+        self.state.lock().unwrap().statuses.get(service).copied()
+    }
+}
+
+impl Health for HealthReporter {
+    fn check(
+        self: Arc<Self>,
+        req: HealthCheckRequest,
+    ) -> BoxFuture<Result<HealthCheckResponse, Error>> {
+        let status = self.status(&req.service);
+        Box::pin(async move {
+            match status {
+                Some(status) => Ok(status.into()),
+                None => Err(Status::new(
+                    Code::NotFound,
+                    format!("unknown service {:?}", req.service),
+                )
+                .into()),
+            }
+        })
+    }
+
+    fn watch(
+        self: Arc<Self>,
+        req: HealthCheckRequest,
+    ) -> BoxFuture<Result<BoxStream<Result<HealthCheckResponse, Error>>, Error>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            let status = state.statuses.get(&req.service).copied();
+            let _ = tx.send(status.unwrap_or(ServingStatus::ServiceUnknown));
+            state.watchers.push((req.service, tx));
+        }
+        Box::pin(async move { Ok(Box::pin(Watch(rx)) as BoxStream<_>) })
+    }
+}
+
+/// The statuses a watcher is told, the current one first.
+struct Watch(mpsc::UnboundedReceiver<ServingStatus>);
+
+impl Stream for Watch {
+    type Item = Result<HealthCheckResponse, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0
+            .poll_recv(cx)
+            .map(|status| status.map(|status| Ok(status.into())))
+    }
+}
+
+/// How a [`crate::Balancer`] probes its endpoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// The service to check; empty for the server as a whole.
+    pub service: String,
+    pub interval: Duration,
+    /// How long a probe waits for its answer.
+    pub timeout: Duration,
+    /// Probes in a row without an answer after which an endpoint is
+    /// ejected. One answered with anything but serving ejects it at once,
+    /// and one answered serving brings it back.
+    pub max_failures: u32,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck {
+            service: String::new(),
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(1),
+            max_failures: 3,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Server;
+
+    #[tokio::test]
+    async fn reports_and_streams_statuses() {
+        let reporter = Arc::new(HealthReporter::new());
+        reporter.set_status("EchoService", ServingStatus::Serving);
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register_service(HealthServer(reporter.clone()));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let health = HealthClient::new(Arc::new(Client::connect(addr).await.unwrap()));
+        let check = |service: &str| {
+            let req = HealthCheckRequest {
+                service: service.to_string(),
+            };
+            let health = &health;
+            async move { health.check(&req).await }
+        };
+        let overall = check("").await.unwrap();
+        assert_eq!(overall.serving_status(), ServingStatus::Serving);
+        match check("Missing").await {
+            Err(Error::Status(status)) => assert_eq!(status.code(), Code::NotFound),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let req = HealthCheckRequest {
+            service: "EchoService".to_string(),
+        };
+        let mut watch = health.watch(&req).await.unwrap();
+        assert_eq!(next(&mut watch).await, ServingStatus::Serving);
+        // Only changes are streamed.
+        reporter.set_status("EchoService", ServingStatus::Serving);
+        reporter.shutdown();
+        assert_eq!(next(&mut watch).await, ServingStatus::NotServing);
+        reporter.clear("EchoService");
+        assert_eq!(next(&mut watch).await, ServingStatus::ServiceUnknown);
+    }
+
+    async fn next(watch: &mut crate::Streaming<HealthCheckResponse>) -> ServingStatus {
+        let resp = std::future::poll_fn(|cx| Pin::new(&mut *watch).poll_next(cx)).await;
+        resp.unwrap().unwrap().serving_status()
+    }
+}
@@ -0,0 +1,158 @@
+//! Interceptors wrapping unary calls on the client and handlers on the
+//! server, like gRPC interceptors or tower layers, for concerns such as
+//! auth, logging, metrics and retries.
+//!
+//! Interceptors run in the order they were added, each passing the request
+//! on to the next with [`Next::run`], the last one to the call or handler
+//! itself. One may answer without passing the request on, change it, or
+//! pass it on more than once. Streaming calls and handlers are not
+//! intercepted.
+
+use std::net::SocketAddrV4;
+use std::sync::Arc;
+
+use crate::{BoxFuture, CallOptions, Error, MetadataMap};
+
+/// A unary request as interceptors see it.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub service: String,
+    pub method: String,
+    /// The server called, on the client; the caller, on the server.
+    pub peer: SocketAddrV4,
+    /// The marshaled request.
+    pub message: Vec<u8>,
+    /// The options of the call; on the server, the timeout and metadata
+    /// sent with the request.
+    pub options: CallOptions,
+}
+
+/// A unary response as interceptors see it.
+#[derive(Debug, Clone, Default)]
+pub struct Response {
+    /// The marshaled response.
+    pub message: Vec<u8>,
+    /// The metadata sent with it; see [`crate::metadata`].
+    pub metadata: MetadataMap,
+}
+
+/// The response, or the failure, of a unary request.
+pub type Reply = BoxFuture<Result<Response, Error>>;
+
+/// Wraps unary calls or handlers; see the [module docs](self).
+pub trait Interceptor: Send + Sync + 'static {
+    fn intercept(&self, request: Request, next: Next) -> Reply;
+}
+
+impl<F> Interceptor for F
+where
+    F: Fn(Request, Next) -> Reply + Send + Sync + 'static,
+{
+    fn intercept(&self, request: Request, next: Next) -> Reply {
+        self(request, next)
+    }
+}
+
+/// The interceptors added to a client or server.
+pub(crate) type Chain = Arc<[Arc<dyn Interceptor>]>;
+
+/// The rest of the chain after an interceptor.
+#[derive(Clone)]
+pub struct Next {
+    chain: Chain,
+    index: usize,
+    terminal: Arc<dyn Fn(Request) -> Reply + Send + Sync>,
+}
+
+impl Next {
+    /// The whole of `chain`, ending in `terminal`.
+    pub(crate) fn new(
+        chain: Chain,
+        terminal: impl Fn(Request) -> Reply + Send + Sync + 'static,
+    ) -> Next {
+        Next {
+            chain,
+            index: 0,
+            terminal: Arc::new(terminal),
+        }
+    }
+
+    /// Passes `request` on to the next interceptor, or to the call or
+    /// handler after the last one.
+    pub fn run(self, request: Request) -> Reply {
+        match self.chain.get(self.index).cloned() {
+            Some(interceptor) => {
+                let next = Next {
+                    index: self.index + 1,
+                    ..self
+                };
+                interceptor.intercept(request, next)
+            }
+            None => (self.terminal)(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Code, Message, Server, ServiceDesc, Status};
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn chains_wrap_calls_and_handlers() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(
+            ServiceDesc::new("EchoService", 1)
+                .method("Echo", 1, |req: Text| async move { Ok::<_, Error>(req) })
+                .method("Hidden", 2, |req: Text| async move { Ok::<_, Error>(req) }),
+        );
+        // Rejects calls to Hidden before they reach the handler.
+        server.add_interceptor(|request: Request, next: Next| -> Reply {
+            if request.method == "Hidden" {
+                return Box::pin(async {
+                    Err(Status::new(Code::PermissionDenied, "denied").into())
+                });
+            }
+            next.run(request)
+        });
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1), ("Hidden", 2)]);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        client.add_interceptor(move |request: Request, next: Next| -> Reply {
+            log.lock()
+                .unwrap()
+                .push(format!("{}.{}", request.service, request.method));
+            next.run(request)
+        });
+        // Rewrites the request, after the logging one.
+        client.add_interceptor(|mut request: Request, next: Next| -> Reply {
+            request.message = Text::new("rewritten").marshal_symphony();
+            next.run(request)
+        });
+
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new("sent"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("rewritten"));
+        match client
+            .call::<_, Text>("EchoService", "Hidden", &Text::new(""))
+            .await
+        {
+            Err(Error::Status(status)) => {
+                assert_eq!(status, Status::new(Code::PermissionDenied, "denied"))
+            }
+            other => panic!("unexpected result: {:?}", other.map(|t| t.0)),
+        }
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["EchoService.Echo", "EchoService.Hidden"]
+        );
+    }
+}
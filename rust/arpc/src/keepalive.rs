@@ -0,0 +1,275 @@
+//! Keepalive pings between a transport and its peers.
+//!
+//! With [`Keepalive`] set, a transport tracks each peer it exchanges calls
+//! with as a session. It pings a peer it has sent nothing to, or heard
+//! nothing from, for [`Keepalive::interval`], which keeps the NAT binding
+//! of a quiet session open, and tears the session down when the peer has
+//! not been heard from for [`Keepalive::timeout`], or no call went either
+//! way for [`Keepalive::idle_timeout`]. Pings and their answers do not
+//! count as calls. Callbacks are told of every session torn down, with a
+//! [`SessionClosed`] saying why; a [`crate::SessionPool`] drops the client
+//! of such a session.
+//!
+//! Transports answer pings whether or not they send them, so only the side
+//! watching its peers needs it set; the Go transport does not answer them.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Custom packet type of pings and their answers, clear of the builtin
+/// types.
+pub(crate) const PING_PACKET_TYPE: u8 = 0x12;
+const PING_PACKET_LEN: usize = 8;
+
+/// Keepalive settings of a transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// How long a session goes quiet before its peer is pinged; under the
+    /// UDP timeout of the NATs on the path, often 30 seconds.
+    pub interval: Duration,
+    /// How long a peer goes unheard before its session is torn down.
+    pub timeout: Duration,
+    /// How long a session goes without calls before it is torn down.
+    pub idle_timeout: Duration,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Keepalive {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(45),
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Why a transport tore a session down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionClosed {
+    /// No call went either way for the idle timeout.
+    Idle,
+    /// The peer was not heard from, pings included, for the timeout.
+    Unresponsive,
+}
+
+/// A callback told of the sessions a transport tears down, by peer.
+pub(crate) type OnClosed = Arc<dyn Fn(SocketAddrV4, SessionClosed) + Send + Sync>;
+
+/// A ping, or the answer to one: `[type(1B)][pong(1B)][src ip(4B)]`
+/// `[src port(2B)]`. `src` is where the answer goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ping {
+    pub pong: bool,
+    pub src: SocketAddrV4,
+}
+
+impl Ping {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(PING_PACKET_LEN);
+        buf.push(PING_PACKET_TYPE);
+        buf.push(self.pong as u8);
+        buf.extend_from_slice(&self.src.ip().octets());
+        buf.extend_from_slice(&self.src.port().to_le_bytes());
+        buf
+    }
+
+    pub fn parse(buf: &[u8]) -> Option<Ping> {
+        if buf.len() < PING_PACKET_LEN || buf[0] != PING_PACKET_TYPE {
+            return None;
+        }
+        let ip = Ipv4Addr::new(buf[2], buf[3], buf[4], buf[5]);
+        Some(Ping {
+            pong: buf[1] != 0,
+            src: SocketAddrV4::new(ip, u16::from_le_bytes([buf[6], buf[7]])),
+        })
+    }
+}
+
+/// The sessions of a transport with keepalives, by peer.
+pub(crate) struct Sessions {
+    config: Keepalive,
+    peers: HashMap<SocketAddrV4, Session>,
+}
+
+struct Session {
+    last_sent: Instant,
+    last_heard: Instant,
+    /// When a call last went either way.
+    last_call: Instant,
+    last_ping: Option<Instant>,
+}
+
+impl Sessions {
+    pub fn new(config: Keepalive) -> Sessions {
+        Sessions {
+            config,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: Keepalive) {
+        self.config = config;
+    }
+
+    /// Records something sent to `peer`. A call starts a session if there
+    /// is none; anything else only keeps one going.
+    pub fn sent(&mut self, peer: SocketAddrV4, call: bool, now: Instant) {
+        if let Some(session) = self.session(peer, call, now) {
+            session.last_sent = now;
+        }
+    }
+
+    /// Records something heard from `peer`, like [`Sessions::sent`].
+    pub fn heard(&mut self, peer: SocketAddrV4, call: bool, now: Instant) {
+        if let Some(session) = self.session(peer, call, now) {
+            session.last_heard = now;
+        }
+    }
+
+    fn session(&mut self, peer: SocketAddrV4, call: bool, now: Instant) -> Option<&mut Session> {
+        if !call {
+            return self.peers.get_mut(&peer);
+        }
+        let session = self.peers.entry(peer).or_insert(Session {
+            last_sent: now,
+            last_heard: now,
+            last_call: now,
+            last_ping: None,
+        });
+        session.last_call = now;
+        Some(session)
+    }
+
+    /// Tears down the sessions that timed out, returning them, and the
+    /// peers to ping now.
+    pub fn due(&mut self, now: Instant) -> (Vec<(SocketAddrV4, SessionClosed)>, Vec<SocketAddrV4>) {
+        let config = self.config;
+        let mut closed = Vec::new();
+        let mut pings = Vec::new();
+        self.peers.retain(|peer, session| {
+            let reason = if now.duration_since(session.last_call) >= config.idle_timeout {
+                SessionClosed::Idle
+            } else if now.duration_since(session.last_heard) >= config.timeout {
+                SessionClosed::Unresponsive
+            } else {
+                let quiet = session.last_sent.min(session.last_heard);
+                let pinged = session
+                    .last_ping
+                    .is_some_and(|at| now.duration_since(at) < config.interval);
+                if now.duration_since(quiet) >= config.interval && !pinged {
+                    session.last_ping = Some(now);
+                    session.last_sent = now;
+                    pings.push(*peer);
+                }
+                return true;
+            };
+            closed.push((*peer, reason));
+            false
+        });
+        (closed, pings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Error, PoolLimits, Server, ServiceDesc, SessionPool};
+    use tokio::sync::mpsc;
+
+    const FAST: Keepalive = Keepalive {
+        interval: Duration::from_millis(20),
+        timeout: Duration::from_millis(150),
+        idle_timeout: Duration::from_millis(500),
+    };
+
+    #[test]
+    fn pings_then_tears_down() {
+        let ping = Ping {
+            pong: true,
+            src: "10.0.0.1:9000".parse().unwrap(),
+        };
+        assert_eq!(Ping::parse(&ping.encode()), Some(ping));
+
+        let peer = "10.0.0.2:9000".parse().unwrap();
+        let start = Instant::now();
+        let mut sessions = Sessions::new(FAST);
+        // Pings alone start no session.
+        sessions.heard(peer, false, start);
+        assert_eq!(sessions.due(start + FAST.interval), (vec![], vec![]));
+        sessions.sent(peer, true, start);
+        assert_eq!(sessions.due(start + FAST.interval), (vec![], vec![peer]));
+        // One ping per interval while the peer is quiet.
+        assert_eq!(
+            sessions.due(start + FAST.interval * 3 / 2),
+            (vec![], vec![])
+        );
+        sessions.heard(peer, false, start + FAST.interval * 3 / 2);
+        assert_eq!(
+            sessions.due(start + FAST.timeout),
+            (vec![], vec![peer]),
+            "answered pings keep the session"
+        );
+        let closed = sessions.due(start + FAST.interval * 3 / 2 + FAST.timeout);
+        assert_eq!(closed, (vec![(peer, SessionClosed::Unresponsive)], vec![]));
+
+        sessions.sent(peer, true, start);
+        sessions.heard(peer, false, start + FAST.idle_timeout);
+        let closed = sessions.due(start + FAST.idle_timeout);
+        assert_eq!(closed, (vec![(peer, SessionClosed::Idle)], vec![]));
+    }
+
+    #[tokio::test]
+    async fn closes_idle_and_unresponsive_sessions() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("EchoService", 1).method(
+            "Echo",
+            1,
+            |req: Text| async move { Ok::<_, Error>(req) },
+        ));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        client.set_keepalive(Some(FAST));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        client.on_session_closed(move |reason| {
+            let _ = tx.send(reason);
+        });
+        let started = Instant::now();
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new("hi"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("hi"));
+        // The server answers pings past the timeout, until the session
+        // idles out.
+        assert_eq!(rx.recv().await, Some(SessionClosed::Idle));
+        assert!(started.elapsed() >= FAST.idle_timeout);
+
+        // A pooled session with a peer that never answers is dropped.
+        let silent = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let pool = SessionPool::new(PoolLimits {
+            keepalive: Some(FAST),
+            ..PoolLimits::default()
+        });
+        pool.set_setup(|client| {
+            client.register_service("EchoService", 1, &[("Echo", 1)]);
+            client.set_call_timeout(Some(Duration::from_millis(10)));
+        });
+        let client = pool.get(silent.local_addr().unwrap()).await.unwrap();
+        assert!(client
+            .call::<_, Text>("EchoService", "Echo", &Text::new(""))
+            .await
+            .is_err());
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while pool.stats().sessions > 0 {
+            assert!(Instant::now() < deadline, "session not dropped");
+            tokio::time::sleep(FAST.interval).await;
+        }
+        assert_eq!(pool.stats().unhealthy, 1);
+    }
+}
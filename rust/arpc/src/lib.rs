@@ -0,0 +1,230 @@
+//! Rust client and server for aRPC, speaking the Symphony wire protocol
+//! over UDP like the Go `pkg/rpc` and `pkg/transport`.
+//!
+//! A call marshals the request with [`Message`], stamps the service and
+//! method IDs into the Symphony header, and sends it as a Request packet
+//! tagged with a fresh RPC ID. The server answers with a Response packet
+//! carrying the same ID, or with an Error (or Unknown) packet carrying the
+//! [`Status`] of the failure.
+//!
+//! On the server side, a [`Service`] describes its methods in a
+//! [`ServiceDesc`], and the [`Server`] dispatches each request to the
+//! method named by the IDs in its header. Server-streaming methods answer
+//! with a stream of responses instead, sent as frames tied to the request's
+//! RPC ID and read by the caller as a [`Streaming`]; client-streaming
+//! methods read a stream of request frames and answer once, and
+//! bidi-streaming methods do both, the caller sending through a
+//! [`StreamSender`].
+//!
+//! Messages implement [`Message`] with the building blocks in [`symphony`],
+//! usually through `#[derive(SymphonyMessage)]` (feature `derive`).
+//!
+//! Unary calls and handlers can be wrapped in [`interceptor`] chains,
+//! requests and responses carry [`metadata`], and both can be compressed
+//! with codecs the two sides negotiate; see [`compression`]. [`Retry`] is
+//! an interceptor retrying failed calls with backoff. Calls the client
+//! gives up on are cancelled on the server; see [`current_cancellation`].
+//! Feature `metrics` serves a server's metrics to Prometheus; see
+//! `metrics`. In the reliability mode of the UDP transport, a pluggable
+//! [`congestion`] controller paces what is sent.
+//!
+//! Calls and handlers are futures. The UDP client and server run on tokio
+//! (feature `tokio`, on by default); without it, [`ServiceDesc::handle`]
+//! serves requests on any executor. Feature `blocking` adds a client for
+//! programs without a runtime, [`blocking::Client`]; feature `dtls` runs
+//! the transport over DTLS sessions, `Dtls`, feature `quic` carries
+//! calls over QUIC streams instead, `Quic`, and feature `io-uring` drives
+//! the UDP socket with an io_uring on Linux, `IoUring`; otherwise the
+//! socket sends and receives datagrams in [`Batching`] batches there.
+//!
+//! Clients and servers move messages through a [`Transport`]: Symphony
+//! over UDP, [`UdpTransport`], by default, length-prefixed frames over TCP,
+//! [`TcpTransport`], where UDP is blocked, or a [`MemoryTransport`] in
+//! tests; see [`Client::with_transport`] and [`Server::with_transport`].
+//! A [`Balancer`] spreads calls over the endpoints of a service with a
+//! [`LoadBalancer`] policy, over endpoints a [`Resolver`] looks up, such
+//! as the pods of a Kubernetes headless service; feature `dns` adds SRV
+//! lookups, `SrvResolver`. Servers report their health through the
+//! [`health`] service, which balancers probe to eject unhealthy endpoints.
+//! Tools discover the services of a server with [`reflection`]. A server
+//! can shard its port over worker threads with `SO_REUSEPORT`; see
+//! [`Server::bind_sharded`]. [`Server::shutdown_handle`] drains it
+//! gracefully for rolling updates, telling clients to go elsewhere.
+//!
+//! ```no_run
+//! # #[cfg(feature = "tokio")]
+//! # async fn run<Req: arpc::Message, Resp: arpc::Message>(req: Req) -> Result<(), arpc::Error> {
+//! let client = arpc::Client::connect("server:9000").await?;
+//! client.register_service("EchoService", 1, &[("Echo", 1)]);
+//! let resp: Resp = client.call("EchoService", "Echo", &req).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "tokio")]
+mod balance;
+#[cfg(feature = "tokio")]
+mod batch;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod buffer;
+#[cfg(feature = "tokio")]
+mod cancel;
+#[cfg(feature = "tokio")]
+mod client;
+#[cfg(feature = "tokio")]
+pub mod compression;
+#[cfg(feature = "tokio")]
+pub mod congestion;
+#[cfg(feature = "tokio")]
+mod correlation;
+#[cfg(feature = "tokio")]
+mod deadline;
+#[cfg(feature = "tokio")]
+mod drain;
+#[cfg(feature = "dtls")]
+mod dtls;
+#[cfg(feature = "tokio")]
+mod encryption;
+mod error;
+#[cfg(feature = "tokio")]
+mod extensions;
+#[cfg(feature = "tokio")]
+mod fragment;
+#[cfg(feature = "tokio")]
+pub mod health;
+#[cfg(feature = "tokio")]
+pub mod interceptor;
+#[cfg(feature = "tokio")]
+mod keepalive;
+#[cfg(feature = "tokio")]
+mod memory;
+mod message;
+#[cfg(feature = "tokio")]
+pub mod metadata;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "tokio")]
+mod pmtu;
+#[cfg(feature = "tokio")]
+mod pool;
+#[cfg(feature = "quic")]
+mod quic;
+#[cfg(feature = "tokio")]
+pub mod reflection;
+mod registry;
+#[cfg(feature = "tokio")]
+mod reliability;
+#[cfg(feature = "tokio")]
+mod resolver;
+#[cfg(feature = "tokio")]
+mod retry;
+#[cfg(feature = "tokio")]
+mod server;
+mod service;
+#[cfg(feature = "tokio")]
+mod shard;
+pub mod status;
+#[cfg(feature = "tokio")]
+mod stream;
+pub mod symphony;
+#[cfg(feature = "tokio")]
+mod tcp;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "tokio")]
+mod trace;
+#[cfg(feature = "tokio")]
+mod transport;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+#[cfg(feature = "tokio")]
+mod window;
+
+#[cfg(feature = "tokio")]
+pub use balance::{
+    Balancer, EndpointLoad, Hedging, LeastOutstanding, LoadBalancer, Picked, PowerOfTwoChoices,
+    RoundRobin,
+};
+#[cfg(feature = "tokio")]
+pub use batch::{BatchStats, Batching};
+pub use buffer::{buffer_stats, BufferStats};
+#[cfg(feature = "tokio")]
+pub use cancel::{current_cancellation, CancellationToken};
+#[cfg(feature = "tokio")]
+pub use client::{CallOptions, Client, StreamSender};
+#[cfg(feature = "tokio")]
+pub use compression::{Codec, Compression};
+#[cfg(feature = "tokio")]
+pub use congestion::{CongestionControl, CongestionController, Cubic, NewReno};
+#[cfg(feature = "tokio")]
+pub use correlation::{CorrelationStats, IdStrategy};
+#[cfg(feature = "tokio")]
+pub use deadline::current_deadline;
+#[cfg(feature = "tokio")]
+pub use drain::{Drained, Shutdown};
+#[cfg(feature = "dtls")]
+pub use dtls::Dtls;
+#[cfg(feature = "tokio")]
+pub use encryption::Encryption;
+pub use error::Error;
+#[cfg(feature = "tokio")]
+pub use fragment::{FragmentStats, ReassemblyLimits};
+#[cfg(feature = "tokio")]
+pub use interceptor::Interceptor;
+#[cfg(feature = "tokio")]
+pub use keepalive::{Keepalive, SessionClosed};
+#[cfg(feature = "tokio")]
+pub use memory::{MemoryNetwork, MemoryTransport};
+pub use message::{DecodeError, Message};
+#[cfg(feature = "tokio")]
+pub use metadata::MetadataMap;
+#[cfg(feature = "metrics")]
+pub use metrics::Metrics;
+#[cfg(feature = "tokio")]
+pub use pmtu::PathMtu;
+#[cfg(feature = "tokio")]
+pub use pool::{PoolLimits, PoolStats, SessionPool};
+#[cfg(feature = "quic")]
+pub use quic::Quic;
+pub use registry::ServiceRegistry;
+#[cfg(feature = "tokio")]
+pub use reliability::{Reliability, ReliabilityStats};
+#[cfg(feature = "dns")]
+pub use resolver::SrvResolver;
+#[cfg(feature = "tokio")]
+pub use resolver::{DnsResolver, Refresh, Resolver};
+#[cfg(feature = "tokio")]
+pub use retry::{Retry, RetryPolicy};
+#[cfg(feature = "tokio")]
+pub use server::Server;
+pub use service::{
+    BoxFuture, BoxStream, MethodKind, RequestStream, ResponseStream, Service, ServiceDesc,
+};
+#[cfg(feature = "tokio")]
+pub use shard::{Sharding, WorkerStats, Workers};
+pub use status::{Code, Status};
+#[cfg(feature = "tokio")]
+pub use stream::{FlowControl, Streaming};
+#[cfg(feature = "tokio")]
+pub use tcp::TcpTransport;
+#[cfg(feature = "tokio")]
+pub use transport::{Frame, Transport, TransportFuture, UdpTransport};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use uring::IoUring;
+#[cfg(feature = "tokio")]
+pub use window::FlowWindows;
+
+/// The buffer type of zero-copy bytes fields; see [`symphony`].
+pub use bytes::Bytes;
+/// The stream trait of client-streaming calls and handlers.
+pub use futures_core::Stream;
+pub use symphony_wire::crypto::CipherSuite;
+pub use symphony_wire::PacketType;
+
+#[cfg(feature = "derive")]
+pub use arpc_derive::SymphonyMessage;
+
+// Lets the derive's `::arpc` paths resolve in this crate's tests.
+#[cfg(test)]
+extern crate self as arpc;
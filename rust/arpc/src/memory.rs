@@ -0,0 +1,156 @@
+//! Transports that hand messages over in memory, for tests that run a
+//! client and server in one process without sockets.
+//!
+//! A [`MemoryNetwork`] routes messages between the transports bound on it
+//! by their addresses, whole and in order, like a link that never drops
+//! anything.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+
+use symphony_wire::PacketType;
+use tokio::sync::mpsc;
+
+use crate::buffer;
+use crate::transport::{Frame, Transport, TransportFuture};
+use crate::Error;
+
+/// The first port handed out to transports bound to port 0.
+const FIRST_PORT: u16 = 49152;
+
+/// Addresses of in-memory transports, shared by cloning.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    bound: Arc<Mutex<HashMap<SocketAddrV4, mpsc::UnboundedSender<Frame>>>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> MemoryNetwork {
+        MemoryNetwork::default()
+    }
+
+    /// A transport at `addr` on this network; port 0 picks a free port.
+    pub fn bind(&self, mut addr: SocketAddrV4) -> io::Result<MemoryTransport> {
+        let mut bound = self.bound.lock().unwrap();
+        if addr.port() == 0 {
+            let port = (FIRST_PORT..=u16::MAX)
+                .find(|&port| !bound.contains_key(&SocketAddrV4::new(*addr.ip(), port)))
+                .ok_or_else(|| io::Error::from(io::ErrorKind::AddrInUse))?;
+            addr.set_port(port);
+        }
+        if bound.contains_key(&addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is bound", addr),
+            ));
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        bound.insert(addr, tx);
+        Ok(MemoryTransport {
+            addr,
+            network: self.clone(),
+            inbox: tokio::sync::Mutex::new(rx),
+        })
+    }
+
+    /// A transport at a free port of 127.0.0.1.
+    pub fn bind_any(&self) -> MemoryTransport {
+        self.bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .expect("a free port")
+    }
+}
+
+/// An endpoint of a [`MemoryNetwork`]. Sending to an address nothing is
+/// bound to fails with [`io::ErrorKind::ConnectionRefused`].
+pub struct MemoryTransport {
+    addr: SocketAddrV4,
+    network: MemoryNetwork,
+    inbox: tokio::sync::Mutex<mpsc::UnboundedReceiver<Frame>>,
+}
+
+impl Transport for MemoryTransport {
+    fn send<'a>(
+        &'a self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &'a [u8],
+        extensions: &'a [u8],
+    ) -> TransportFuture<'a, Result<(), Error>> {
+        let frame = Frame {
+            kind,
+            rpc_id,
+            src: self.addr,
+            message: buffer::copy(message),
+            extensions: extensions.to_vec(),
+        };
+        let sent = match self.network.bound.lock().unwrap().get(&dst) {
+            Some(inbox) => inbox.send(frame).is_ok(),
+            None => false,
+        };
+        Box::pin(async move {
+            if !sent {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("nothing bound to {}", dst),
+                )
+                .into());
+            }
+            Ok(())
+        })
+    }
+
+    fn recv(&self) -> TransportFuture<'_, io::Result<Option<Frame>>> {
+        // The network keeps the sender until the transport is dropped.
+        Box::pin(async move { Ok(self.inbox.lock().await.recv().await) })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr.into())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network.bound.lock().unwrap().remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Server, ServiceDesc};
+
+    #[tokio::test]
+    async fn serves_calls_in_memory() {
+        let network = MemoryNetwork::new();
+        let mut server = Server::with_transport(Arc::new(network.bind_any()));
+        server.register(ServiceDesc::new("EchoService", 1).method(
+            "Echo",
+            1,
+            |req: Text| async move { Ok::<_, Error>(Text(req.0.to_uppercase())) },
+        ));
+        let SocketAddr::V4(addr) = server.local_addr().unwrap() else {
+            unreachable!();
+        };
+        tokio::spawn(server.serve());
+
+        let client = Client::with_transport(Arc::new(network.bind_any()), addr);
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new("hi"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("HI"));
+
+        let stray = network.bind_any();
+        let nowhere = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1);
+        match stray.send(nowhere, PacketType::Request, 1, b"", b"").await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+/// A message in the Symphony format, the Rust side of the Go
+/// `SymphonyMessage` interface.
+///
+/// The marshaled bytes start with the 13-byte Symphony header; its service
+/// and method IDs are left zero and filled in by the client.
+pub trait Message: Sized {
+    fn marshal_symphony(&self) -> Vec<u8>;
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError>;
+
+    /// Unmarshals the message in `data`. Messages with
+    /// [`Bytes`](crate::Bytes) or [`ByteString`](crate::symphony::ByteString)
+    /// fields override it to share `data` instead of copying out of it, as
+    /// the derive does.
+    fn unmarshal_symphony_bytes(data: Bytes) -> Result<Self, DecodeError> {
+        Self::unmarshal_symphony(&data)
+    }
+}
+
+/// Why a message could not be unmarshaled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    reason: String,
+}
+
+impl DecodeError {
+    pub fn new(reason: impl Into<String>) -> DecodeError {
+        DecodeError {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid data: {}", self.reason)
+    }
+}
+
+impl std::error::Error for DecodeError {}
@@ -0,0 +1,259 @@
+//! Key/value metadata sent with requests and responses, like gRPC
+//! metadata, for trace context, auth tokens and the like.
+//!
+//! Metadata travels in the extension block of a message; see
+//! [`crate::extensions`]. A caller sets it in [`crate::CallOptions`], and a
+//! handler reads it with [`request_metadata`] and answers with
+//! [`set_response_metadata`]. Response metadata is sent with unary
+//! responses only.
+
+use std::sync::{Arc, Mutex};
+
+/// Key/value pairs whose values are text or bytes. Keys are ASCII and
+/// case-insensitive; they are kept lowercase.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataMap {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    key: String,
+    value: Vec<u8>,
+    binary: bool,
+}
+
+/// Flag of an entry with a binary value.
+const BINARY: u8 = 0x01;
+
+impl MetadataMap {
+    pub fn new() -> MetadataMap {
+        MetadataMap::default()
+    }
+
+    /// Sets `key` to the text `value`, replacing any earlier value.
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.put(key, value.as_bytes().to_vec(), false);
+    }
+
+    /// Sets `key` to the binary `value`, replacing any earlier value.
+    pub fn insert_bin(&mut self, key: &str, value: &[u8]) {
+        self.put(key, value.to_vec(), true);
+    }
+
+    fn put(&mut self, key: &str, value: Vec<u8>, binary: bool) {
+        let key = key.to_ascii_lowercase();
+        self.entries.retain(|entry| entry.key != key);
+        self.entries.push(Entry { key, value, binary });
+    }
+
+    /// The text value of `key`, if it has one.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let entry = self.entry(key).filter(|entry| !entry.binary)?;
+        // Text values are checked when parsed.
+        std::str::from_utf8(&entry.value).ok()
+    }
+
+    /// The binary value of `key`, if it has one.
+    pub fn get_bin(&self, key: &str) -> Option<&[u8]> {
+        let entry = self.entry(key).filter(|entry| entry.binary)?;
+        Some(&entry.value)
+    }
+
+    fn entry(&self, key: &str) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.key.eq_ignore_ascii_case(key))
+    }
+
+    /// Removes `key`, returning whether it was set.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let before = self.entries.len();
+        self.entries
+            .retain(|entry| !entry.key.eq_ignore_ascii_case(key));
+        self.entries.len() != before
+    }
+
+    /// The keys, in the order they were first set.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.key.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries as `[flags(1B)][key length(2B)][key][value length(2B)][value]`
+    /// each.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for entry in &self.entries {
+            buf.push(if entry.binary { BINARY } else { 0 });
+            buf.extend_from_slice(&(entry.key.len() as u16).to_le_bytes());
+            buf.extend_from_slice(entry.key.as_bytes());
+            buf.extend_from_slice(&(entry.value.len() as u16).to_le_bytes());
+            buf.extend_from_slice(&entry.value);
+        }
+        buf
+    }
+
+    /// Parses encoded entries. Returns None if one runs past the end, or a
+    /// key or text value is not what it should be.
+    pub(crate) fn parse(mut buf: &[u8]) -> Option<MetadataMap> {
+        let mut metadata = MetadataMap::new();
+        while !buf.is_empty() {
+            let binary = buf[0] & BINARY != 0;
+            let (key, rest) = field(&buf[1..])?;
+            let (value, rest) = field(rest)?;
+            buf = rest;
+            let key = std::str::from_utf8(key).ok().filter(|key| key.is_ascii())?;
+            if !binary {
+                std::str::from_utf8(value).ok()?;
+            }
+            metadata.put(key, value.to_vec(), binary);
+        }
+        Some(metadata)
+    }
+}
+
+/// Splits a `[length(2B)][bytes]` field off the front of `buf`.
+fn field(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u16::from_le_bytes(buf.get(..2)?.try_into().unwrap()) as usize;
+    let value = buf.get(2..2 + len)?;
+    Some((value, &buf[2 + len..]))
+}
+
+/// The metadata of the request a task is handling, and of its response.
+#[derive(Default)]
+pub(crate) struct CallMetadata {
+    request: MetadataMap,
+    response: MetadataMap,
+}
+
+type Shared = Arc<Mutex<CallMetadata>>;
+
+tokio::task_local! {
+    static CALL: Shared;
+}
+
+/// The metadata sent with the request the current task is handling; empty
+/// outside a handler.
+pub fn request_metadata() -> MetadataMap {
+    CALL.try_with(|call| call.lock().unwrap().request.clone())
+        .unwrap_or_default()
+}
+
+/// Sends `metadata` with the response to the request the current task is
+/// handling, replacing any set before. Does nothing outside a handler.
+pub fn set_response_metadata(metadata: MetadataMap) {
+    let _ = CALL.try_with(|call| call.lock().unwrap().response = metadata);
+}
+
+impl CallMetadata {
+    pub fn new(request: MetadataMap) -> Shared {
+        Arc::new(Mutex::new(CallMetadata {
+            request,
+            response: MetadataMap::new(),
+        }))
+    }
+}
+
+/// Takes the response metadata the current task's handler set.
+pub(crate) fn take_response() -> MetadataMap {
+    CALL.try_with(|call| std::mem::take(&mut call.lock().unwrap().response))
+        .unwrap_or_default()
+}
+
+/// Runs `f` with `call` as the task's metadata.
+pub(crate) fn enter<R>(call: &Shared, f: impl FnOnce() -> R) -> R {
+    CALL.sync_scope(call.clone(), f)
+}
+
+/// Runs `future` with `call` as the metadata of the task polling it.
+pub(crate) async fn scope<F: std::future::Future>(call: Shared, future: F) -> F::Output {
+    CALL.scope(call, future).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_text_and_binary_values() {
+        let mut metadata = MetadataMap::new();
+        metadata.insert("Trace-ID", "abc");
+        metadata.insert_bin("token-bin", &[0, 0xff, 7]);
+        metadata.insert("trace-id", "def");
+        assert_eq!(metadata.get("TRACE-id"), Some("def"));
+        assert_eq!(metadata.get_bin("trace-id"), None);
+        assert_eq!(metadata.get_bin("token-bin"), Some(&[0, 0xff, 7][..]));
+        assert_eq!(
+            metadata.keys().collect::<Vec<_>>(),
+            ["token-bin", "trace-id"]
+        );
+
+        let encoded = metadata.encode();
+        assert_eq!(MetadataMap::parse(&encoded), Some(metadata.clone()));
+        assert_eq!(MetadataMap::parse(&encoded[..encoded.len() - 1]), None);
+        // A text value must be UTF-8.
+        assert_eq!(MetadataMap::parse(&[0, 1, 0, b'k', 1, 0, 0xff]), None);
+
+        assert!(metadata.remove("TOKEN-BIN"));
+        assert!(!metadata.remove("token-bin"));
+        assert_eq!(metadata.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn travels_with_requests_and_responses() {
+        use crate::testing::Text;
+        use crate::{CallOptions, Client, Error, Server, ServiceDesc};
+
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("MetadataService", 1).method(
+            "Echo",
+            1,
+            |_: Text| async move {
+                let request = request_metadata();
+                let mut response = MetadataMap::new();
+                if let Some(token) = request.get_bin("token-bin") {
+                    response.insert_bin("token-bin", &[token, &[0]].concat());
+                }
+                set_response_metadata(response);
+                Ok::<_, Error>(Text(
+                    request.get("trace-id").unwrap_or_default().to_string(),
+                ))
+            },
+        ));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("MetadataService", 1, &[("Echo", 1)]);
+        let mut options = CallOptions::default();
+        options.metadata.insert("trace-id", "t-1");
+        options.metadata.insert_bin("token-bin", &[0xde, 0xad]);
+        let (resp, metadata): (Text, _) = client
+            .call_with_metadata("MetadataService", "Echo", &Text::new(""), &options)
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("t-1"));
+        assert_eq!(metadata.get_bin("token-bin"), Some(&[0xde, 0xad, 0][..]));
+        assert!(request_metadata().is_empty());
+
+        // Metadata too large for a packet is refused.
+        options.metadata.insert("big", &"x".repeat(2000));
+        let err = client
+            .call_with::<_, Text>("MetadataService", "Echo", &Text::new(""), &options)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::TooLarge(_)),
+            "unexpected error: {}",
+            err
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! Prometheus metrics of a server, with feature `metrics`, served over
+//! HTTP in the text exposition format; see
+//! [`crate::Server::enable_metrics`].
+//!
+//! By service and method: `arpc_server_requests_total`, the requests
+//! handled; `arpc_server_responses_total`, by status `code` too, `Ok` for
+//! a response and `Cancelled` for a call the client gave up on;
+//! `arpc_server_in_flight`, the handlers running; and
+//! `arpc_server_handling_seconds`, a histogram of how long they ran. Of the
+//! transport, over all workers: the retransmission counts of
+//! [`crate::ReliabilityStats`] and the fragmentation counts of
+//! [`crate::FragmentStats`].
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::transport::UdpTransport;
+use crate::{Code, Error, FragmentStats, ReliabilityStats};
+
+/// Upper bounds of the handling time buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+];
+/// The most of a scrape request read before answering it.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// The metrics of a server, as it records them; see the
+/// [module docs](self).
+#[derive(Clone)]
+pub struct Metrics(Arc<Inner>);
+
+struct Inner {
+    addr: SocketAddr,
+    udp: Vec<Arc<UdpTransport>>,
+    // By service, then method.
+    methods: Mutex<BTreeMap<(String, String), MethodMetrics>>,
+}
+
+#[derive(Default)]
+struct MethodMetrics {
+    requests: u64,
+    responses: BTreeMap<String, u64>,
+    in_flight: u64,
+    // Not cumulative; the last counts those past every bound.
+    buckets: [u64; BUCKETS.len() + 1],
+    seconds: f64,
+}
+
+/// Times a handler, recording its outcome once dropped.
+pub(crate) struct Timer {
+    metrics: Arc<Inner>,
+    key: (String, String),
+    started: Instant,
+    // Cancelled until finished.
+    code: Code,
+}
+
+impl Timer {
+    pub fn finish(&mut self, failure: Option<&Error>) {
+        self.code = match failure {
+            None => Code::Ok,
+            Some(Error::Status(status)) => status.code(),
+            Some(_) => Code::Unknown,
+        };
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let seconds = self.started.elapsed().as_secs_f64();
+        let mut methods = self.metrics.methods.lock().unwrap();
+        let method = methods.entry(std::mem::take(&mut self.key)).or_default();
+        method.in_flight -= 1;
+        *method
+            .responses
+            .entry(format!("{:?}", self.code))
+            .or_default() += 1;
+        let bucket = BUCKETS.partition_point(|&bound| bound < seconds);
+        method.buckets[bucket] += 1;
+        method.seconds += seconds;
+    }
+}
+
+impl Metrics {
+    /// Serves the metrics of the server over `udp` at `addr`, on
+    /// `GET /metrics`, until they are dropped.
+    pub(crate) async fn serve(
+        addr: SocketAddr,
+        udp: Vec<Arc<UdpTransport>>,
+    ) -> Result<Metrics, Error> {
+        let listener = TcpListener::bind(addr).await?;
+        let metrics = Metrics(Arc::new(Inner {
+            addr: listener.local_addr()?,
+            udp,
+            methods: Mutex::new(BTreeMap::new()),
+        }));
+        tokio::spawn(export(listener, Arc::downgrade(&metrics.0)));
+        Ok(metrics)
+    }
+
+    /// The address the metrics are served at.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.0.addr
+    }
+
+    /// Starts timing a request to `service.method`.
+    pub(crate) fn start(&self, service: &str, method: &str) -> Timer {
+        let key = (service.to_string(), method.to_string());
+        let mut methods = self.0.methods.lock().unwrap();
+        let metrics = methods.entry(key.clone()).or_default();
+        metrics.requests += 1;
+        metrics.in_flight += 1;
+        Timer {
+            metrics: self.0.clone(),
+            key,
+            started: Instant::now(),
+            code: Code::Cancelled,
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let methods = self.0.methods.lock().unwrap();
+        let labels = |(service, method): &(String, String)| {
+            format!(
+                "service=\"{}\",method=\"{}\"",
+                escape(service),
+                escape(method)
+            )
+        };
+
+        header(
+            &mut out,
+            "arpc_server_requests_total",
+            "counter",
+            "Requests handled.",
+        );
+        for (key, method) in methods.iter() {
+            let _ = writeln!(
+                out,
+                "arpc_server_requests_total{{{}}} {}",
+                labels(key),
+                method.requests
+            );
+        }
+        header(
+            &mut out,
+            "arpc_server_responses_total",
+            "counter",
+            "Requests finished, by status code.",
+        );
+        for (key, method) in methods.iter() {
+            for (code, count) in &method.responses {
+                let _ = writeln!(
+                    out,
+                    "arpc_server_responses_total{{{},code=\"{}\"}} {}",
+                    labels(key),
+                    code,
+                    count
+                );
+            }
+        }
+        header(
+            &mut out,
+            "arpc_server_in_flight",
+            "gauge",
+            "Handlers running.",
+        );
+        for (key, method) in methods.iter() {
+            let _ = writeln!(
+                out,
+                "arpc_server_in_flight{{{}}} {}",
+                labels(key),
+                method.in_flight
+            );
+        }
+        header(
+            &mut out,
+            "arpc_server_handling_seconds",
+            "histogram",
+            "How long handlers ran.",
+        );
+        for (key, method) in methods.iter() {
+            let labels = labels(key);
+            let mut count = 0;
+            for (i, bound) in BUCKETS
+                .iter()
+                .map(f64::to_string)
+                .chain(["+Inf".into()])
+                .enumerate()
+            {
+                count += method.buckets[i];
+                let _ = writeln!(
+                    out,
+                    "arpc_server_handling_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "arpc_server_handling_seconds_sum{{{}}} {}",
+                labels, method.seconds
+            );
+            let _ = writeln!(
+                out,
+                "arpc_server_handling_seconds_count{{{}}} {}",
+                labels, count
+            );
+        }
+        drop(methods);
+
+        let mut reliability = ReliabilityStats::default();
+        let mut fragments = FragmentStats::default();
+        for udp in &self.0.udp {
+            reliability += udp.reliability_stats();
+            fragments += udp.fragment_stats();
+        }
+        let transport = [
+            (
+                "arpc_retransmitted_packets_total",
+                "counter",
+                "Packets sent again after their timeout.",
+                reliability.retransmitted,
+            ),
+            (
+                "arpc_abandoned_messages_total",
+                "counter",
+                "Messages given up on after the last retry.",
+                reliability.abandoned,
+            ),
+            (
+                "arpc_duplicate_packets_total",
+                "counter",
+                "Packets received again and dropped.",
+                reliability.duplicates,
+            ),
+            (
+                "arpc_outstanding_messages",
+                "gauge",
+                "Messages sent and not yet acknowledged.",
+                reliability.outstanding as u64,
+            ),
+            (
+                "arpc_split_messages_total",
+                "counter",
+                "Messages sent in more than one packet.",
+                fragments.split,
+            ),
+            (
+                "arpc_fragments_sent_total",
+                "counter",
+                "Packets of the messages split.",
+                fragments.fragments,
+            ),
+            (
+                "arpc_reassembled_messages_total",
+                "counter",
+                "Messages received in more than one packet.",
+                fragments.reassembled,
+            ),
+            (
+                "arpc_reassembly_dropped_total",
+                "counter",
+                "Messages dropped before all of their packets arrived.",
+                fragments.dropped,
+            ),
+        ];
+        for (name, kind, help, value) in transport {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Answers scrapes on `listener` while `metrics` are alive.
+async fn export(listener: TcpListener, metrics: Weak<Inner>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::warn!("failed to accept a metrics scrape: {}", e);
+                continue;
+            }
+        };
+        let Some(metrics) = metrics.upgrade() else {
+            return;
+        };
+        tokio::spawn(async move {
+            if let Err(e) = scrape(stream, Metrics(metrics)).await {
+                log::debug!("failed to answer a metrics scrape: {}", e);
+            }
+        });
+    }
+}
+
+/// Answers one HTTP request, then closes the connection.
+async fn scrape(mut stream: TcpStream, metrics: Metrics) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_LEN {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = line.split(|&b| b == b' ');
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", metrics.render()),
+        (Some(b"GET"), _) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Server, ServiceDesc, Status};
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: arpc\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_request_and_transport_metrics() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(
+            ServiceDesc::new("EchoService", 1)
+                .method("Echo", 1, |req: Text| async move { Ok::<_, Error>(req) })
+                .method("Fail", 2, |_: Text| async move {
+                    Err::<Text, Error>(Status::new(Code::NotFound, "gone").into())
+                }),
+        );
+        let metrics = server.enable_metrics("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1), ("Fail", 2)]);
+        for _ in 0..2 {
+            let _: Text = client
+                .call("EchoService", "Echo", &Text::new("hi"))
+                .await
+                .unwrap();
+        }
+        // Split over several packets.
+        let big = Text("x".repeat(4000));
+        let resp: Text = client.call("EchoService", "Echo", &big).await.unwrap();
+        assert_eq!(resp, big);
+        let _ = client
+            .call::<_, Text>("EchoService", "Fail", &Text::new(""))
+            .await
+            .unwrap_err();
+        // Timers are dropped just after the responses are sent.
+        tokio::task::yield_now().await;
+
+        let response = get(metrics.local_addr(), "/metrics").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        let echo = "service=\"EchoService\",method=\"Echo\"";
+        for line in [
+            format!("arpc_server_requests_total{{{}}} 3", echo),
+            format!("arpc_server_responses_total{{{},code=\"Ok\"}} 3", echo),
+            "arpc_server_responses_total{service=\"EchoService\",method=\"Fail\",code=\"NotFound\"} 1"
+                .to_string(),
+            format!("arpc_server_in_flight{{{}}} 0", echo),
+            format!("arpc_server_handling_seconds_bucket{{{},le=\"+Inf\"}} 3", echo),
+            format!("arpc_server_handling_seconds_count{{{}}} 3", echo),
+            "# TYPE arpc_server_handling_seconds histogram".to_string(),
+            "arpc_split_messages_total 1".to_string(),
+            "arpc_reassembled_messages_total 1".to_string(),
+        ] {
+            assert!(body.lines().any(|l| l == line), "no {:?} in\n{}", line, body);
+        }
+        assert_eq!(body, metrics.render());
+
+        let response = get(metrics.local_addr(), "/").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
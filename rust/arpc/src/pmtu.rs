@@ -0,0 +1,384 @@
+//! Path MTU discovery by probing, like DPLPMTUD (RFC 8899), so that the
+//! packets to each peer are as large as its path carries without IP
+//! fragmentation, instead of the fixed size of the Go transport.
+//!
+//! With [`PathMtu`] set, a transport sends packets of [`PathMtu::base`]
+//! bytes to a peer it starts calling, and searches for a larger size with
+//! probes: padded packets the peer acknowledges if they arrive. It tries
+//! [`PathMtu::max`] first, then halves the range between the largest size
+//! acknowledged and the smallest lost, giving up on a size after
+//! [`PathMtu::max_probes`] probes go unanswered. Data packets grow as
+//! sizes are confirmed. Every [`PathMtu::raise_interval`] the search runs
+//! again, starting with a probe of the current size: if that is lost too,
+//! the path shrank, and packets fall back to the base size until a new one
+//! is found.
+//!
+//! Transports answer probes whether or not they send them; the Go
+//! transport does not, so packets to it stay at the base size.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use crate::buffer;
+
+/// Custom packet type of probes and their acknowledgements, clear of the
+/// builtin types.
+pub(crate) const PROBE_PACKET_TYPE: u8 = 0x13;
+const PROBE_HEADER_LEN: usize = 16;
+
+/// Searches stop once the largest size acknowledged is this close to the
+/// smallest lost.
+const SEARCH_PRECISION: usize = 8;
+
+/// Path MTU discovery settings of a transport. Sizes are of whole packets,
+/// the payload of the UDP datagrams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathMtu {
+    /// The size assumed to get through any path, sent at until a larger one
+    /// is confirmed.
+    pub base: usize,
+    /// The largest size searched for: by default an Ethernet frame less
+    /// the IP and UDP headers.
+    pub max: usize,
+    /// Probes of one size sent before it is taken not to get through.
+    pub max_probes: u32,
+    /// How long a probe waits for its acknowledgement.
+    pub probe_timeout: Duration,
+    /// How long a path keeps its size before the search runs again.
+    pub raise_interval: Duration,
+}
+
+impl Default for PathMtu {
+    fn default() -> Self {
+        PathMtu {
+            base: 1200,
+            max: 1472,
+            max_probes: 3,
+            probe_timeout: Duration::from_secs(1),
+            raise_interval: Duration::from_secs(600),
+        }
+    }
+}
+
+/// A probe, padded to `size` bytes, or its acknowledgement:
+/// `[type(1B)][ack(1B)][size(2B)][src ip(4B)][src port(2B)][dst ip(4B)]`
+/// `[dst port(2B)]`. `src` is where the acknowledgement goes; `dst` is the
+/// destination the probe was sent to, echoed so that the prober knows the
+/// path, like in [`crate::reliability`] acknowledgements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Probe {
+    pub ack: bool,
+    pub size: u16,
+    pub src: SocketAddrV4,
+    pub dst: SocketAddrV4,
+}
+
+impl Probe {
+    /// Encodes the probe padded to its size, or the acknowledgement bare.
+    pub fn encode(&self) -> Vec<u8> {
+        let len = if self.ack {
+            PROBE_HEADER_LEN
+        } else {
+            (self.size as usize).max(PROBE_HEADER_LEN)
+        };
+        let mut buf = buffer::take(len);
+        buf.push(PROBE_PACKET_TYPE);
+        buf.push(self.ack as u8);
+        buf.extend_from_slice(&self.size.to_le_bytes());
+        buf.extend_from_slice(&self.src.ip().octets());
+        buf.extend_from_slice(&self.src.port().to_le_bytes());
+        buf.extend_from_slice(&self.dst.ip().octets());
+        buf.extend_from_slice(&self.dst.port().to_le_bytes());
+        buf.resize(len, 0);
+        buf
+    }
+
+    /// Parses a probe, which must have arrived whole, or an
+    /// acknowledgement.
+    pub fn parse(buf: &[u8]) -> Option<Probe> {
+        if buf.len() < PROBE_HEADER_LEN || buf[0] != PROBE_PACKET_TYPE {
+            return None;
+        }
+        let addr = |at: usize| {
+            let ip = Ipv4Addr::new(buf[at], buf[at + 1], buf[at + 2], buf[at + 3]);
+            SocketAddrV4::new(ip, u16::from_le_bytes([buf[at + 4], buf[at + 5]]))
+        };
+        let probe = Probe {
+            ack: buf[1] != 0,
+            size: u16::from_le_bytes([buf[2], buf[3]]),
+            src: addr(4),
+            dst: addr(10),
+        };
+        (probe.ack || buf.len() == probe.size as usize).then_some(probe)
+    }
+}
+
+/// The paths of a transport with discovery, by peer.
+pub(crate) struct Paths {
+    config: PathMtu,
+    peers: HashMap<SocketAddrV4, Path>,
+}
+
+struct Path {
+    /// The size packets are sent at.
+    mtu: usize,
+    /// The largest size known to get through, and the smallest known not
+    /// to, during a search.
+    low: usize,
+    high: usize,
+    /// The probe waiting for its acknowledgement, or None between searches.
+    probe: Option<Pending>,
+    /// When the next search starts, between searches.
+    next_search: Instant,
+}
+
+struct Pending {
+    size: usize,
+    sent: Instant,
+    probes: u32,
+    /// Whether it probes the size packets are already sent at.
+    confirming: bool,
+}
+
+impl Paths {
+    pub fn new(config: PathMtu) -> Paths {
+        Paths {
+            config,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: PathMtu) {
+        self.config = config;
+    }
+
+    /// The size assumed to get through any path.
+    pub fn base(&self) -> usize {
+        self.config.base
+    }
+
+    /// The size of the packets to send `peer`, starting a search if it is
+    /// new.
+    pub fn mtu(&mut self, peer: SocketAddrV4, now: Instant) -> usize {
+        let config = self.config;
+        self.peers
+            .entry(peer)
+            .or_insert_with(|| Path {
+                mtu: config.base,
+                low: config.base,
+                high: config.max + 1,
+                probe: None,
+                next_search: now,
+            })
+            .mtu
+    }
+
+    /// The sizes to probe the paths with now, giving up on the probes that
+    /// went unanswered.
+    pub fn due(&mut self, now: Instant) -> Vec<(SocketAddrV4, usize)> {
+        let config = self.config;
+        let mut probes = Vec::new();
+        for (peer, path) in &mut self.peers {
+            let size = match &mut path.probe {
+                None if now < path.next_search => continue,
+                None => {
+                    // Confirm the size in use first, unless it is assumed.
+                    let confirming = path.mtu > config.base;
+                    path.low = config.base;
+                    path.high = config.max + 1;
+                    let size = if confirming { path.mtu } else { config.max };
+                    path.probe = Some(Pending {
+                        size,
+                        sent: now,
+                        probes: 1,
+                        confirming,
+                    });
+                    size
+                }
+                Some(pending) if now.duration_since(pending.sent) < config.probe_timeout => {
+                    continue
+                }
+                Some(pending) if pending.probes < config.max_probes => {
+                    pending.sent = now;
+                    pending.probes += 1;
+                    pending.size
+                }
+                Some(pending) => {
+                    if pending.confirming {
+                        log::debug!(
+                            "path to {} no longer carries {} bytes, back to {}",
+                            peer,
+                            pending.size,
+                            config.base
+                        );
+                        path.mtu = config.base;
+                    }
+                    path.high = pending.size;
+                    match path.next_probe(now, config) {
+                        Some(size) => size,
+                        None => continue,
+                    }
+                }
+            };
+            probes.push((*peer, size));
+        }
+        probes
+    }
+
+    /// Records that a probe of `size` bytes reached `peer`, returning the
+    /// next size to probe, if any.
+    pub fn acked(&mut self, peer: SocketAddrV4, size: usize, now: Instant) -> Option<usize> {
+        let config = self.config;
+        let path = self.peers.get_mut(&peer)?;
+        if path.probe.as_ref().map(|pending| pending.size) != Some(size) {
+            return None;
+        }
+        path.low = size;
+        path.mtu = path.mtu.max(size);
+        path.next_probe(now, config)
+    }
+
+    /// Forgets the path to `peer`, whose session was torn down.
+    pub fn remove(&mut self, peer: SocketAddrV4) {
+        self.peers.remove(&peer);
+    }
+}
+
+impl Path {
+    /// Probes the middle of what is left to search, or ends the search.
+    fn next_probe(&mut self, now: Instant, config: PathMtu) -> Option<usize> {
+        if self.high <= self.low + SEARCH_PRECISION {
+            self.probe = None;
+            self.next_search = now + config.raise_interval;
+            return None;
+        }
+        let size = (self.low + self.high) / 2;
+        self.probe = Some(Pending {
+            size,
+            sent: now,
+            probes: 1,
+            confirming: false,
+        });
+        Some(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Error, Server, ServiceDesc};
+    use tokio::net::UdpSocket;
+
+    const FAST: PathMtu = PathMtu {
+        base: 1200,
+        max: 1472,
+        max_probes: 2,
+        probe_timeout: Duration::from_millis(50),
+        raise_interval: Duration::from_millis(500),
+    };
+
+    /// Runs searches over a path carrying packets up to `limit` bytes.
+    fn search(paths: &mut Paths, limit: usize, mut now: Instant) -> Instant {
+        let mut probes = paths.due(now);
+        while !probes.is_empty() {
+            let mut next = Vec::new();
+            for (peer, size) in probes {
+                if size <= limit {
+                    next.extend(paths.acked(peer, size, now).map(|size| (peer, size)));
+                }
+            }
+            if next.is_empty() {
+                now += FAST.probe_timeout;
+                next = paths.due(now);
+            }
+            probes = next;
+        }
+        assert_eq!(paths.due(now), vec![]);
+        now
+    }
+
+    #[test]
+    fn searches_and_falls_back() {
+        let probe = Probe {
+            ack: false,
+            size: 1300,
+            src: "10.0.0.1:9000".parse().unwrap(),
+            dst: "10.0.0.3:9000".parse().unwrap(),
+        };
+        let encoded = probe.encode();
+        assert_eq!(encoded.len(), 1300);
+        assert_eq!(Probe::parse(&encoded), Some(probe));
+        // A probe cut short did not get through.
+        assert_eq!(Probe::parse(&encoded[..1000]), None);
+        let ack = Probe { ack: true, ..probe };
+        assert_eq!(Probe::parse(&ack.encode()), Some(ack));
+
+        let peer = "10.0.0.2:9000".parse().unwrap();
+        let start = Instant::now();
+        let mut paths = Paths::new(FAST);
+        assert_eq!(paths.mtu(peer, start), FAST.base);
+        // The largest size is tried first.
+        assert_eq!(paths.due(start), vec![(peer, FAST.max)]);
+        assert_eq!(paths.acked(peer, FAST.max, start), None);
+        assert_eq!(paths.mtu(peer, start), FAST.max);
+
+        // The path shrinks: the confirmation fails, and the search finds
+        // the new size from the base up.
+        let later = start + FAST.raise_interval;
+        let done = search(&mut paths, 1300, later);
+        let mtu = paths.mtu(peer, done);
+        assert!((1300 - SEARCH_PRECISION..=1300).contains(&mtu), "{}", mtu);
+
+        // Confirmed, it stays through the next search.
+        let done = search(&mut paths, 1300, done + FAST.raise_interval);
+        assert_eq!(paths.mtu(peer, done), mtu);
+    }
+
+    /// Relays datagrams to `server` up to `limit` bytes, dropping larger
+    /// ones like a link with a smaller MTU would.
+    async fn narrow_relay(server: std::net::SocketAddr, limit: usize) -> std::net::SocketAddr {
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = relay.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 2048];
+            loop {
+                let (n, _) = relay.recv_from(&mut buf).await.unwrap();
+                if n <= limit {
+                    relay.send_to(&buf[..n], server).await.unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn sizes_packets_to_the_path() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("EchoService", 1).method(
+            "Echo",
+            1,
+            |req: Text| async move { Ok::<_, Error>(req) },
+        ));
+        let relay = narrow_relay(server.local_addr().unwrap(), 1350).await;
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(relay).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        client.set_path_mtu(Some(FAST));
+        let long = Text::new(&"x".repeat(10_000));
+        let resp: Text = client.call("EchoService", "Echo", &long).await.unwrap();
+        assert_eq!(resp, long);
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while client.path_mtu() < 1350 - SEARCH_PRECISION {
+            assert!(Instant::now() < deadline, "stuck at {}", client.path_mtu());
+            tokio::time::sleep(FAST.probe_timeout).await;
+        }
+        assert!(client.path_mtu() <= 1350);
+        // Packets past the base size make it through.
+        let resp: Text = client.call("EchoService", "Echo", &long).await.unwrap();
+        assert_eq!(resp, long);
+    }
+}
@@ -0,0 +1,304 @@
+//! Clients shared across callers, one per target address, so that calls
+//! to the same server reuse its socket, receive task and encryption state.
+//! With keepalives, a session whose transport tears it down, its server
+//! unresponsive or the session idle, leaves the pool at once.
+
+use std::collections::HashMap;
+use std::net::SocketAddrV4;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use tokio::net::ToSocketAddrs;
+
+use crate::client::resolve;
+use crate::{Client, Error, Keepalive, SessionClosed};
+
+/// Bounds on the sessions a [`SessionPool`] keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolLimits {
+    /// The most sessions kept. The least recently used one is dropped from
+    /// the pool to make room; callers holding it may go on using it.
+    pub max_sessions: usize,
+    /// How long a session nobody holds stays in the pool after its last
+    /// use.
+    pub idle_timeout: Duration,
+    /// Calls in a row without a reply after which a session is replaced
+    /// with a fresh one; see [`Client::failures`].
+    pub max_failures: u32,
+    /// Keepalives of new sessions; see [`Client::set_keepalive`].
+    pub keepalive: Option<Keepalive>,
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        PoolLimits {
+            max_sessions: 64,
+            idle_timeout: Duration::from_secs(60),
+            max_failures: 3,
+            keepalive: None,
+        }
+    }
+}
+
+/// Counts of a session pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    /// Sessions in the pool.
+    pub sessions: usize,
+    /// Sessions set up.
+    pub created: u64,
+    /// Requests served by a pooled session.
+    pub reused: u64,
+    /// Sessions dropped for being idle or to make room.
+    pub evicted: u64,
+    /// Sessions replaced for failing, or dropped for an unresponsive
+    /// server.
+    pub unhealthy: u64,
+}
+
+type Setup = Arc<dyn Fn(&Client) + Send + Sync>;
+
+/// A pool of [`Client`] sessions keyed by target address.
+///
+/// New sessions go through the setup function before they are handed out,
+/// to register services or set the encryption keys of their target.
+pub struct SessionPool {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    limits: PoolLimits,
+    setup: Option<Setup>,
+    sessions: HashMap<SocketAddrV4, Session>,
+    stats: PoolStats,
+}
+
+struct Session {
+    client: Arc<Client>,
+    last_used: Instant,
+}
+
+impl SessionPool {
+    pub fn new(limits: PoolLimits) -> SessionPool {
+        SessionPool {
+            state: Arc::new(Mutex::new(State {
+                limits,
+                setup: None,
+                sessions: HashMap::new(),
+                stats: PoolStats::default(),
+            })),
+        }
+    }
+
+    pub fn set_limits(&self, limits: PoolLimits) {
+        self.state.lock().unwrap().limits = limits;
+    }
+
+    /// Runs `setup` on every session created from now on.
+    pub fn set_setup(&self, setup: impl Fn(&Client) + Send + Sync + 'static) {
+        self.state.lock().unwrap().setup = Some(Arc::new(setup));
+    }
+
+    /// The session of `target`, reused if a healthy one is pooled, or set
+    /// up otherwise.
+    pub async fn get(&self, target: impl ToSocketAddrs) -> Result<Arc<Client>, Error> {
+        let target = resolve(target).await?;
+        let (setup, keepalive) = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(client) = state.reuse(target, Instant::now()) {
+                return Ok(client);
+            }
+            (state.setup.clone(), state.limits.keepalive)
+        };
+        let client = Arc::new(Client::connect(target).await?);
+        if keepalive.is_some() {
+            client.set_keepalive(keepalive);
+            let (pool, session) = (Arc::downgrade(&self.state), Arc::downgrade(&client));
+            client.on_session_closed(move |reason| {
+                if let Some(pool) = pool.upgrade() {
+                    pool.lock().unwrap().closed(target, &session, reason);
+                }
+            });
+        }
+        if let Some(setup) = setup {
+            setup(&client);
+        }
+        let mut state = self.state.lock().unwrap();
+        // Another caller may have set one up meanwhile.
+        if let Some(client) = state.reuse(target, Instant::now()) {
+            return Ok(client);
+        }
+        Ok(state.insert(target, client, Instant::now()))
+    }
+
+    /// Drops the sessions idle for longer than the idle timeout.
+    pub fn evict_idle(&self) {
+        self.state.lock().unwrap().evict_idle(Instant::now());
+    }
+
+    /// Drops the session of `target`, e.g. after its server restarted.
+    pub fn remove(&self, target: SocketAddrV4) {
+        self.state.lock().unwrap().sessions.remove(&target);
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.state.lock().unwrap();
+        PoolStats {
+            sessions: state.sessions.len(),
+            ..state.stats
+        }
+    }
+}
+
+impl Default for SessionPool {
+    fn default() -> Self {
+        SessionPool::new(PoolLimits::default())
+    }
+}
+
+impl State {
+    /// The pooled session of `target` if it is healthy, dropping it if not.
+    fn reuse(&mut self, target: SocketAddrV4, now: Instant) -> Option<Arc<Client>> {
+        self.evict_idle(now);
+        let session = self.sessions.get_mut(&target)?;
+        if session.client.failures() >= self.limits.max_failures {
+            log::debug!("replacing session of {} after failed calls", target);
+            self.sessions.remove(&target);
+            self.stats.unhealthy += 1;
+            return None;
+        }
+        session.last_used = now;
+        self.stats.reused += 1;
+        Some(session.client.clone())
+    }
+
+    fn insert(&mut self, target: SocketAddrV4, client: Arc<Client>, now: Instant) -> Arc<Client> {
+        while self.sessions.len() >= self.limits.max_sessions.max(1) {
+            let oldest = self
+                .sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_used)
+                .map(|(target, _)| *target)
+                .unwrap();
+            self.sessions.remove(&oldest);
+            self.stats.evicted += 1;
+        }
+        self.sessions.insert(
+            target,
+            Session {
+                client: client.clone(),
+                last_used: now,
+            },
+        );
+        self.stats.created += 1;
+        client
+    }
+
+    /// Drops the session of `target` its transport tore down, if it is
+    /// still the pooled one.
+    fn closed(&mut self, target: SocketAddrV4, client: &Weak<Client>, reason: SessionClosed) {
+        let pooled = self.sessions.get(&target);
+        if pooled.is_none_or(|session| Arc::as_ptr(&session.client) != client.as_ptr()) {
+            return;
+        }
+        log::debug!("dropping session of {}: {:?}", target, reason);
+        self.sessions.remove(&target);
+        match reason {
+            SessionClosed::Idle => self.stats.evicted += 1,
+            SessionClosed::Unresponsive => self.stats.unhealthy += 1,
+        }
+    }
+
+    fn evict_idle(&mut self, now: Instant) {
+        let timeout = self.limits.idle_timeout;
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| {
+            Arc::strong_count(&session.client) > 1
+                || now.duration_since(session.last_used) < timeout
+        });
+        self.stats.evicted += (before - self.sessions.len()) as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Server, ServiceDesc};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn reuses_evicts_and_replaces_sessions() {
+        let mut addrs = Vec::new();
+        for _ in 0..2 {
+            let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+            server.register(ServiceDesc::new("EchoService", 1).method(
+                "Echo",
+                1,
+                |req: Text| async move { Ok::<_, Error>(req) },
+            ));
+            addrs.push(server.local_addr().unwrap());
+            tokio::spawn(server.serve());
+        }
+
+        let pool = SessionPool::new(PoolLimits {
+            max_sessions: 1,
+            ..PoolLimits::default()
+        });
+        let setups = Arc::new(AtomicUsize::new(0));
+        let counter = setups.clone();
+        pool.set_setup(move |client| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            client.register_service("EchoService", 1, &[("Echo", 1)]);
+        });
+
+        let client = pool.get(addrs[0]).await.unwrap();
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new("pooled"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("pooled"));
+        let again = pool.get(addrs[0]).await.unwrap();
+        assert!(Arc::ptr_eq(&client, &again));
+        assert_eq!(setups.load(Ordering::Relaxed), 1);
+
+        // Over the limit, the other target's session takes the place.
+        let other = pool.get(addrs[1]).await.unwrap();
+        assert!(!Arc::ptr_eq(&client, &other));
+        let stats = pool.stats();
+        assert_eq!(
+            (stats.sessions, stats.created, stats.reused, stats.evicted),
+            (1, 2, 1, 1)
+        );
+
+        // Sessions nobody holds expire.
+        drop((client, again));
+        let later = Instant::now() + PoolLimits::default().idle_timeout;
+        {
+            let mut state = pool.state.lock().unwrap();
+            state.evict_idle(later);
+            assert_eq!(state.sessions.len(), 1);
+            drop(other);
+            state.evict_idle(later);
+            assert!(state.sessions.is_empty());
+        }
+
+        // A session whose calls go unanswered is replaced.
+        let silent = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let silent = silent.local_addr().unwrap();
+        pool.set_limits(PoolLimits {
+            max_failures: 1,
+            ..PoolLimits::default()
+        });
+        let client = pool.get(silent).await.unwrap();
+        client.set_call_timeout(Some(Duration::from_millis(20)));
+        assert!(client
+            .call::<_, Text>("EchoService", "Echo", &Text::new(""))
+            .await
+            .is_err());
+        assert_eq!(client.failures(), 1);
+        let fresh = pool.get(silent).await.unwrap();
+        assert!(!Arc::ptr_eq(&client, &fresh));
+        assert_eq!(pool.stats().unhealthy, 1);
+    }
+}
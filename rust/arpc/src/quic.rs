@@ -0,0 +1,339 @@
+//! Symphony messages over QUIC streams, through quinn, for links where loss
+//! recovery, congestion control and connection migration are worth a
+//! handshake.
+//!
+//! Each message, a request, response, stream frame or failure, travels on
+//! a unidirectional stream of its own:
+//! `[type(1B)][rpc id(8B)][extensions length(2B)][extensions][message]`,
+//! the message running to the end of the stream. QUIC splits, orders and
+//! resends it, and encrypts it with TLS 1.3, so the fragmentation,
+//! reliability mode and encryption of the UDP transport do not apply. A
+//! client connects to its server on its first call, and the server answers
+//! on that connection.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+
+use quinn::rustls::pki_types::pem::PemObject;
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use quinn::rustls::RootCertStore;
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig};
+use symphony_wire::PacketType;
+use tokio::sync::mpsc;
+
+use crate::transport::{Frame, Transport, TransportFuture, FRAME_HEADER_LEN};
+use crate::{Error, ReassemblyLimits};
+
+/// The QUIC side of a client or server: a quinn client config and the name
+/// the server's certificate must have, or a quinn server config.
+pub struct Quic {
+    role: Role,
+}
+
+enum Role {
+    Client {
+        config: ClientConfig,
+        server_name: String,
+    },
+    Server(ServerConfig),
+}
+
+impl Quic {
+    /// Connects to servers whose certificate chains to a certificate of
+    /// `ca_pem` and names `server_name`, a DNS name or an IP address.
+    pub fn client(ca_pem: &[u8], server_name: &str) -> Result<Quic, Error> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(ca_pem) {
+            roots.add(cert.map_err(quic_error)?).map_err(quic_error)?;
+        }
+        let config = ClientConfig::with_root_certificates(Arc::new(roots)).map_err(quic_error)?;
+        Ok(Quic::from_client_config(config, server_name))
+    }
+
+    /// Accepts clients with the certificate chain `cert_chain_pem`, leaf
+    /// first, and its private key `key_pem`.
+    pub fn server(cert_chain_pem: &[u8], key_pem: &[u8]) -> Result<Quic, Error> {
+        let chain = CertificateDer::pem_slice_iter(cert_chain_pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(quic_error)?;
+        let key = PrivateKeyDer::from_pem_slice(key_pem).map_err(quic_error)?;
+        let config = ServerConfig::with_single_cert(chain, key).map_err(quic_error)?;
+        Ok(Quic::from_server_config(config))
+    }
+
+    /// Connects with a client config set up by hand, e.g. for client
+    /// certificates or transport parameters.
+    pub fn from_client_config(config: ClientConfig, server_name: &str) -> Quic {
+        Quic {
+            role: Role::Client {
+                config,
+                server_name: server_name.to_string(),
+            },
+        }
+    }
+
+    /// Accepts with a server config set up by hand.
+    pub fn from_server_config(config: ServerConfig) -> Quic {
+        Quic {
+            role: Role::Server(config),
+        }
+    }
+}
+
+fn quic_error(e: impl std::fmt::Display) -> Error {
+    Error::Quic(e.to_string())
+}
+
+/// Connections by peer, and the messages they carried in.
+struct Shared {
+    connections: Mutex<HashMap<SocketAddrV4, Connection>>,
+    received: mpsc::UnboundedSender<Frame>,
+    limits: Mutex<ReassemblyLimits>,
+}
+
+pub(crate) struct QuicTransport {
+    endpoint: Endpoint,
+    // The server name of a client; a server only answers.
+    server_name: Option<String>,
+    shared: Arc<Shared>,
+    received: tokio::sync::Mutex<mpsc::UnboundedReceiver<Frame>>,
+}
+
+impl QuicTransport {
+    /// Binds to `addr`; a server starts accepting connections.
+    pub fn bind(addr: SocketAddrV4, quic: Quic) -> io::Result<QuicTransport> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            connections: Mutex::new(HashMap::new()),
+            received: tx,
+            limits: Mutex::new(ReassemblyLimits::default()),
+        });
+        let (endpoint, server_name) = match quic.role {
+            Role::Client {
+                config,
+                server_name,
+            } => {
+                let mut endpoint = Endpoint::client(addr.into())?;
+                endpoint.set_default_client_config(config);
+                (endpoint, Some(server_name))
+            }
+            Role::Server(config) => {
+                let endpoint = Endpoint::server(config, addr.into())?;
+                tokio::spawn(accept_loop(endpoint.clone(), shared.clone()));
+                (endpoint, None)
+            }
+        };
+        Ok(QuicTransport {
+            endpoint,
+            server_name,
+            shared,
+            received: tokio::sync::Mutex::new(rx),
+        })
+    }
+
+    /// Sends `message` on a stream of its own to `dst`, connecting first if
+    /// this is a client without a connection to it.
+    async fn send_frame(
+        &self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &[u8],
+        extensions: &[u8],
+    ) -> Result<(), Error> {
+        let header = Frame::header(kind, rpc_id, extensions)?;
+        let connection = self.connection(dst).await?;
+        let mut stream = connection.open_uni().await.map_err(quic_error)?;
+        for part in [&header[..], extensions, message] {
+            stream.write_all(part).await.map_err(quic_error)?;
+        }
+        stream.finish().map_err(quic_error)?;
+        Ok(())
+    }
+
+    /// The open connection to `dst`, or a new one from a client.
+    async fn connection(&self, dst: SocketAddrV4) -> Result<Connection, Error> {
+        let open = self.shared.connections.lock().unwrap().get(&dst).cloned();
+        if let Some(connection) = open.filter(|c| c.close_reason().is_none()) {
+            return Ok(connection);
+        }
+        let Some(server_name) = &self.server_name else {
+            return Err(Error::Quic(format!("no connection to {}", dst)));
+        };
+        let connection = self
+            .endpoint
+            .connect(dst.into(), server_name)
+            .map_err(quic_error)?
+            .await
+            .map_err(quic_error)?;
+        self.shared
+            .connections
+            .lock()
+            .unwrap()
+            .insert(dst, connection.clone());
+        tokio::spawn(read_loop(connection.clone(), dst, self.shared.clone()));
+        Ok(connection)
+    }
+}
+
+impl Transport for QuicTransport {
+    fn send<'a>(
+        &'a self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &'a [u8],
+        extensions: &'a [u8],
+    ) -> TransportFuture<'a, Result<(), Error>> {
+        Box::pin(self.send_frame(dst, kind, rpc_id, message, extensions))
+    }
+
+    fn recv(&self) -> TransportFuture<'_, io::Result<Option<Frame>>> {
+        // The sender lives as long as the transport.
+        Box::pin(async move { Ok(self.received.lock().await.recv().await) })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Bounds the messages read off a stream.
+    fn set_reassembly_limits(&self, limits: ReassemblyLimits) {
+        *self.shared.limits.lock().unwrap() = limits;
+    }
+}
+
+impl Drop for QuicTransport {
+    fn drop(&mut self) {
+        self.endpoint.close(0u32.into(), b"closed");
+    }
+}
+
+async fn accept_loop(endpoint: Endpoint, shared: Arc<Shared>) {
+    while let Some(incoming) = endpoint.accept().await {
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    log::warn!("failed to accept QUIC connection: {}", e);
+                    return;
+                }
+            };
+            let SocketAddr::V4(peer) = to_v4(connection.remote_address()) else {
+                log::warn!("refusing QUIC connection from IPv6 peer");
+                return;
+            };
+            shared
+                .connections
+                .lock()
+                .unwrap()
+                .insert(peer, connection.clone());
+            read_loop(connection, peer, shared).await;
+        });
+    }
+}
+
+/// Reads the messages `peer` sends on `connection` until it closes.
+async fn read_loop(connection: Connection, peer: SocketAddrV4, shared: Arc<Shared>) {
+    loop {
+        let mut stream = match connection.accept_uni().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::debug!("QUIC connection with {} closed: {}", peer, e);
+                break;
+            }
+        };
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let limit = shared.limits.lock().unwrap().max_message_len;
+            let frame = match stream.read_to_end(FRAME_HEADER_LEN + 0xffff + limit).await {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::warn!("dropping message from {}: {}", peer, e);
+                    return;
+                }
+            };
+            match Frame::parse(&frame, peer) {
+                Some(received) => {
+                    let _ = shared.received.send(received);
+                }
+                None => log::warn!("dropping malformed message from {}", peer),
+            }
+        });
+    }
+    let mut connections = shared.connections.lock().unwrap();
+    if connections
+        .get(&peer)
+        .is_some_and(|open| open.stable_id() == connection.stable_id())
+    {
+        connections.remove(&peer);
+    }
+}
+
+/// `addr`, with an IPv4-mapped IPv6 address as IPv4.
+fn to_v4(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::V4(SocketAddrV4::new(ip, v6.port())),
+            None => addr,
+        },
+        addr => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Server, ServiceDesc, Status};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn serves_calls_over_quic_streams() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_pem = cert.cert.pem();
+        let key_pem = cert.signing_key.serialize_pem();
+
+        let mut server = Server::bind_quic(
+            "127.0.0.1:0",
+            Quic::server(cert_pem.as_bytes(), key_pem.as_bytes()).unwrap(),
+        )
+        .await
+        .unwrap();
+        server.register(
+            ServiceDesc::new("EchoService", 1)
+                .method("Echo", 1, |req: Text| async move {
+                    Ok::<_, Error>(Text(req.0.to_uppercase()))
+                })
+                .method("Fail", 2, |req: Text| async move {
+                    Err::<Text, _>(Status::new(crate::Code::NotFound, req.0).into())
+                }),
+        );
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let quic = Quic::client(cert_pem.as_bytes(), "localhost").unwrap();
+        let client = Client::connect_quic(addr, quic).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1), ("Fail", 2)]);
+        client.set_call_timeout(Some(Duration::from_secs(5)));
+        // Larger than the UDP transport's packets, in one stream.
+        let long = "stream ".repeat(10_000);
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new(&long))
+            .await
+            .unwrap();
+        assert_eq!(resp.0, long.to_uppercase());
+        match client
+            .call::<_, Text>("EchoService", "Fail", &Text::new("gone"))
+            .await
+        {
+            Err(Error::Status(status)) => {
+                assert_eq!(status, Status::new(crate::Code::NotFound, "gone"))
+            }
+            other => panic!("unexpected result: {:?}", other.map(|t| t.0)),
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! The reflection service, which tells tools such as CLIs and load
+//! generators what a server serves, so that they can encode requests
+//! without compiled stubs.
+//!
+//! The messages and stubs are those `protoc-gen-symphony-rust` generates
+//! for `proto/reflection.proto`, with the messages written out by hand like
+//! those of [`crate::health`]. `ListServices` answers with the name, ID and
+//! methods of each registered service, and `FileDescriptor` with the
+//! encoded `FileDescriptorProto` declaring a service, whose message
+//! descriptors give the fields of its requests in Symphony order. Services
+//! described by hand have no descriptor unless given one with
+//! [`crate::ServiceDesc::file_descriptor`]; for them, and for this service
+//! and health, `FileDescriptor` fails with [`Code::NotFound`].
+//!
+//! A server serves it under [`SERVER_REFLECTION_ID`] once
+//! [`crate::Server::enable_reflection`] is called.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::symphony::{MessageReader, MessageWriter, Nested, Value};
+use crate::{
+    BoxFuture, Client, Code, DecodeError, Error, Message, MethodKind, ServiceDesc, Status,
+};
+
+/// Service ID of `ServerReflection`.
+pub const SERVER_REFLECTION_ID: u32 = 0xFFFF_0002;
+
+/// Method names and IDs of `ServerReflection`.
+pub const SERVER_REFLECTION_METHODS: &[(&str, u32)] = &[("ListServices", 1), ("FileDescriptor", 2)];
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ListServicesRequest {}
+
+impl Message for ListServicesRequest {
+    fn marshal_symphony(&self) -> Vec<u8> {
+        let mut message = MessageWriter::new(0);
+        message.private(0);
+        message.finish()
+    }
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError> {
+        MessageReader::new(data)?;
+        Ok(ListServicesRequest {})
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ListServicesResponse {
+    pub services: Vec<ServiceInfo>,
+}
+
+impl Message for ListServicesResponse {
+    fn marshal_symphony(&self) -> Vec<u8> {
+        let mut message = MessageWriter::new(0);
+        let table_len = <Vec<ServiceInfo> as Nested>::TABLE_LEN;
+        Nested::encode(&self.services, &mut message.private(table_len), 0);
+        message.finish()
+    }
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError> {
+        let message = MessageReader::new(data)?;
+        Ok(ListServicesResponse {
+            services: Nested::decode(&message.private(), 0)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ServiceInfo {
+    /// The name calls are addressed to.
+    pub name: String,
+    pub id: u32,
+    pub methods: Vec<MethodInfo>,
+}
+
+impl Message for ServiceInfo {
+    fn marshal_symphony(&self) -> Vec<u8> {
+        let mut message = MessageWriter::new(0);
+        let segment = &mut message
+            .private(String::TABLE_LEN + u32::TABLE_LEN + <Vec<MethodInfo> as Nested>::TABLE_LEN);
+        Value::encode(&self.name, segment, 0);
+        Value::encode(&self.id, segment, String::TABLE_LEN);
+        Nested::encode(&self.methods, segment, String::TABLE_LEN + u32::TABLE_LEN);
+        message.finish()
+    }
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError> {
+        let message = MessageReader::new(data)?;
+        let segment = message.private();
+        Ok(ServiceInfo {
+            name: Value::decode(&segment, 0)?,
+            id: Value::decode(&segment, String::TABLE_LEN)?,
+            methods: Nested::decode(&segment, String::TABLE_LEN + u32::TABLE_LEN)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MethodInfo {
+    pub name: String,
+    pub id: u32,
+    /// A [`MethodKind`]; see [`MethodInfo::method_kind`].
+    pub kind: i32,
+}
+
+impl Message for MethodInfo {
+    fn marshal_symphony(&self) -> Vec<u8> {
+        let mut message = MessageWriter::new(0);
+        let segment = &mut message.private(String::TABLE_LEN + u32::TABLE_LEN + i32::TABLE_LEN);
+        Value::encode(&self.name, segment, 0);
+        Value::encode(&self.id, segment, String::TABLE_LEN);
+        Value::encode(&self.kind, segment, String::TABLE_LEN + u32::TABLE_LEN);
+        message.finish()
+    }
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError> {
+        let message = MessageReader::new(data)?;
+        let segment = message.private();
+        Ok(MethodInfo {
+            name: Value::decode(&segment, 0)?,
+            id: Value::decode(&segment, String::TABLE_LEN)?,
+            kind: Value::decode(&segment, String::TABLE_LEN + u32::TABLE_LEN)?,
+        })
+    }
+}
+
+impl MethodInfo {
+    /// How the method exchanges messages, or None for a kind this side
+    /// does not know.
+    pub fn method_kind(&self) -> Option<MethodKind> {
+        match self.kind {
+            0 => Some(MethodKind::Unary),
+            1 => Some(MethodKind::ServerStreaming),
+            2 => Some(MethodKind::ClientStreaming),
+            3 => Some(MethodKind::BidiStreaming),
+            _ => None,
+        }
+    }
+}
+
+fn kind_number(kind: MethodKind) -> i32 {
+    match kind {
+        MethodKind::Unary => 0,
+        MethodKind::ServerStreaming => 1,
+        MethodKind::ClientStreaming => 2,
+        MethodKind::BidiStreaming => 3,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FileDescriptorRequest {
+    /// A service, by the name calls are addressed to.
+    pub service: String,
+}
+
+impl Message for FileDescriptorRequest {
+    fn marshal_symphony(&self) -> Vec<u8> {
+        let mut message = MessageWriter::new(0);
+        Value::encode(&self.service, &mut message.private(String::TABLE_LEN), 0);
+        message.finish()
+    }
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError> {
+        let message = MessageReader::new(data)?;
+        Ok(FileDescriptorRequest {
+            service: Value::decode(&message.private(), 0)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FileDescriptorResponse {
+    /// The encoded `google.protobuf.FileDescriptorProto` declaring the
+    /// service and its messages.
+    pub file_descriptor_proto: Vec<u8>,
+}
+
+impl Message for FileDescriptorResponse {
+    fn marshal_symphony(&self) -> Vec<u8> {
+        let mut message = MessageWriter::new(0);
+        Value::encode(
+            &self.file_descriptor_proto,
+            &mut message.private(Vec::<u8>::TABLE_LEN),
+            0,
+        );
+        message.finish()
+    }
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError> {
+        let message = MessageReader::new(data)?;
+        Ok(FileDescriptorResponse {
+            file_descriptor_proto: Value::decode(&message.private(), 0)?,
+        })
+    }
+}
+
+/// Client for `ServerReflection`.
+pub struct ServerReflectionClient {
+    client: Arc<Client>,
+}
+
+impl ServerReflectionClient {
+    /// Wraps `client`, registering the service's IDs with it.
+    pub fn new(client: Arc<Client>) -> ServerReflectionClient {
+        client.register_service(
+            "ServerReflection",
+            SERVER_REFLECTION_ID,
+            SERVER_REFLECTION_METHODS,
+        );
+        ServerReflectionClient { client }
+    }
+
+    pub async fn list_services(
+        &self,
+        req: &ListServicesRequest,
+    ) -> Result<ListServicesResponse, Error> {
+        self.client
+            .call("ServerReflection", "ListServices", req)
+            .await
+    }
+
+    pub async fn file_descriptor(
+        &self,
+        req: &FileDescriptorRequest,
+    ) -> Result<FileDescriptorResponse, Error> {
+        self.client
+            .call("ServerReflection", "FileDescriptor", req)
+            .await
+    }
+}
+
+/// Server API for `ServerReflection`; register it wrapped in
+/// [`ServerReflectionServer`].
+pub trait ServerReflection: Send + Sync + 'static {
+    fn list_services(
+        self: Arc<Self>,
+        req: ListServicesRequest,
+    ) -> BoxFuture<Result<ListServicesResponse, Error>>;
+
+    fn file_descriptor(
+        self: Arc<Self>,
+        req: FileDescriptorRequest,
+    ) -> BoxFuture<Result<FileDescriptorResponse, Error>>;
+}
+
+/// Serves a [`ServerReflection`] implementation.
+pub struct ServerReflectionServer<T>(pub Arc<T>);
+
+impl<T: ServerReflection> ServerReflectionServer<T> {
+    pub fn new(service: T) -> ServerReflectionServer<T> {
+        ServerReflectionServer(Arc::new(service))
+    }
+}
+
+impl<T: ServerReflection> crate::Service for ServerReflectionServer<T> {
+    fn describe(self: Arc<Self>) -> ServiceDesc {
+        ServiceDesc::new("ServerReflection", SERVER_REFLECTION_ID)
+            .method("ListServices", 1, {
+                let service = self.0.clone();
+                move |req: ListServicesRequest| service.clone().list_services(req)
+            })
+            .method("FileDescriptor", 2, {
+                let service = self.0.clone();
+                move |req: FileDescriptorRequest| service.clone().file_descriptor(req)
+            })
+    }
+}
+
+/// What a server serves, as of when it was described.
+pub struct Reflection {
+    services: Vec<ServiceInfo>,
+    files: HashMap<String, &'static [u8]>,
+}
+
+impl Reflection {
+    /// Describes `services`, along with the reflection service itself.
+    pub fn new<'a>(services: impl IntoIterator<Item = &'a ServiceDesc>) -> Reflection {
+        let mut reflection = Reflection {
+            services: Vec::new(),
+            files: HashMap::new(),
+        };
+        for desc in services {
+            let methods = desc
+                .method_ids()
+                .into_iter()
+                .filter_map(|id| {
+                    Some(MethodInfo {
+                        name: desc.method_name(id)?.to_string(),
+                        id,
+                        kind: kind_number(desc.method_kind(id)?),
+                    })
+                })
+                .collect();
+            reflection.services.push(ServiceInfo {
+                name: desc.name().to_string(),
+                id: desc.id(),
+                methods,
+            });
+            if let Some(file) = desc.file() {
+                reflection.files.insert(desc.name().to_string(), file);
+            }
+        }
+        if !reflection
+            .services
+            .iter()
+            .any(|service| service.id == SERVER_REFLECTION_ID)
+        {
+            reflection.services.push(ServiceInfo {
+                name: "ServerReflection".to_string(),
+                id: SERVER_REFLECTION_ID,
+                methods: SERVER_REFLECTION_METHODS
+                    .iter()
+                    .map(|&(name, id)| MethodInfo {
+                        name: name.to_string(),
+                        id,
+                        kind: kind_number(MethodKind::Unary),
+                    })
+                    .collect(),
+            });
+        }
+        reflection.services.sort_by_key(|service| service.id);
+        reflection
+    }
+}
+
+impl ServerReflection for Reflection {
+    fn list_services(
+        self: Arc<Self>,
+        _req: ListServicesRequest,
+    ) -> BoxFuture<Result<ListServicesResponse, Error>> {
+        let services = self.services.clone();
+        Box::pin(async move { Ok(ListServicesResponse { services }) })
+    }
+
+    fn file_descriptor(
+        self: Arc<Self>,
+        req: FileDescriptorRequest,
+    ) -> BoxFuture<Result<FileDescriptorResponse, Error>> {
+        let file = self.files.get(&req.service).copied();
+        Box::pin(async move {
+            match file {
+                Some(file) => Ok(FileDescriptorResponse {
+                    file_descriptor_proto: file.to_vec(),
+                }),
+                None => Err(Status::new(
+                    Code::NotFound,
+                    format!("no file descriptor for service {:?}", req.service),
+                )
+                .into()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::Server;
+
+    #[tokio::test]
+    async fn describes_registered_services() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(
+            ServiceDesc::new("EchoService", 1)
+                .method("Echo", 1, |req: Text| async move { Ok::<_, Error>(req) })
+                .server_streaming("Repeat", 2, |_: Text| async move {
+                    Err::<crate::BoxStream<Result<Text, Error>>, _>(
+                        Status::new(Code::Unimplemented, "").into(),
+                    )
+                })
+                .file_descriptor(b"\x0a\x0aecho.proto"),
+        );
+        server.enable_reflection();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let reflection =
+            ServerReflectionClient::new(Arc::new(Client::connect(addr).await.unwrap()));
+        let resp = reflection
+            .list_services(&ListServicesRequest {})
+            .await
+            .unwrap();
+        let names: Vec<&str> = resp.services.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["EchoService", "ServerReflection"]);
+        let echo = &resp.services[0];
+        assert_eq!(echo.id, 1);
+        let methods: Vec<_> = echo
+            .methods
+            .iter()
+            .map(|m| (m.name.as_str(), m.id, m.method_kind()))
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                ("Echo", 1, Some(MethodKind::Unary)),
+                ("Repeat", 2, Some(MethodKind::ServerStreaming)),
+            ]
+        );
+
+        let file = |service: &str| {
+            let req = FileDescriptorRequest {
+                service: service.to_string(),
+            };
+            let reflection = &reflection;
+            async move { reflection.file_descriptor(&req).await }
+        };
+        let echo = file("EchoService").await.unwrap();
+        assert_eq!(echo.file_descriptor_proto, b"\x0a\x0aecho.proto");
+        match file("ServerReflection").await {
+            Err(Error::Status(status)) => assert_eq!(status.code(), Code::NotFound),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use std::collections::HashMap;
+
+/// Service and method IDs by name, used to stamp outgoing requests
+/// (`ServiceRegistry` in `pkg/rpc/service_registry.go`).
+#[derive(Debug, Default, Clone)]
+pub struct ServiceRegistry {
+    services: HashMap<String, (u32, HashMap<String, u32>)>,
+}
+
+impl ServiceRegistry {
+    pub fn new() -> ServiceRegistry {
+        ServiceRegistry::default()
+    }
+
+    /// Registers a service with its methods, replacing any earlier
+    /// registration under the same name.
+    pub fn register_service(&mut self, name: &str, id: u32, methods: &[(&str, u32)]) {
+        let methods = methods
+            .iter()
+            .map(|&(method, id)| (method.to_string(), id))
+            .collect();
+        self.services.insert(name.to_string(), (id, methods));
+    }
+
+    pub fn service_id(&self, service: &str) -> Option<u32> {
+        self.services.get(service).map(|&(id, _)| id)
+    }
+
+    pub fn method_id(&self, service: &str, method: &str) -> Option<u32> {
+        self.services.get(service)?.1.get(method).copied()
+    }
+}
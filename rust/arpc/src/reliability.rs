@@ -0,0 +1,717 @@
+//! Acknowledgements and retransmission of the packets a transport sends,
+//! for lossy links (`pkg/custom/reliable` in Go, which acknowledges whole
+//! messages instead of packets).
+//!
+//! The receiver acknowledges every packet with an [`Ack`] frame covering
+//! the packets of its message received so far: cumulatively up to the
+//! first gap, and selectively for the 64 packets after it. The sender keeps
+//! each message's packets until they are acknowledged, and resends the rest
+//! when its retransmission timeout passes, estimated from round trips like
+//! TCP (RFC 6298) and doubled on each retry. A message is abandoned after
+//! [`Reliability::max_retransmits`] retries; its call then times out.
+//!
+//! Receivers remember the messages they completed for a while, so that a
+//! retransmission whose acknowledgement was lost is acknowledged again
+//! instead of delivered twice.
+//!
+//! With a [`crate::CongestionControl`] set, packets past the congestion
+//! window of their peer wait for acknowledgements to open it; see
+//! [`crate::congestion`]. A message held back that way gives no round-trip
+//! sample, since its packets did not all go at once.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use crate::buffer;
+use crate::congestion::{CongestionControl, CongestionController};
+use crate::trace;
+
+/// Custom packet type of acknowledgements, clear of the builtin types.
+pub(crate) const ACK_PACKET_TYPE: u8 = 0x11;
+const ACK_PACKET_LEN: usize = 25;
+
+/// How long a receiver remembers a message, to acknowledge retransmissions
+/// of it (the connection timeout of the Go handlers).
+const RECEIVED_LINGER: Duration = Duration::from_secs(30);
+
+/// Settings of the reliability mode. Both peers must enable it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reliability {
+    /// Retries of a message before it is abandoned.
+    pub max_retransmits: u32,
+    /// The retransmission timeout before any round trip was measured.
+    pub initial_rto: Duration,
+    pub min_rto: Duration,
+    pub max_rto: Duration,
+}
+
+impl Default for Reliability {
+    fn default() -> Self {
+        Reliability {
+            max_retransmits: 5,
+            initial_rto: Duration::from_secs(1),
+            min_rto: Duration::from_millis(200),
+            max_rto: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Counts of the reliability layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReliabilityStats {
+    /// Messages sent and not yet fully acknowledged.
+    pub outstanding: usize,
+    /// Packets sent again after their timeout.
+    pub retransmitted: u64,
+    /// Messages given up on after the last retry.
+    pub abandoned: u64,
+    /// Packets received again and dropped.
+    pub duplicates: u64,
+    /// Bytes sent and not yet acknowledged, under congestion control.
+    pub in_flight: usize,
+    /// Messages with packets waiting for the congestion window.
+    pub queued: usize,
+}
+
+impl std::ops::AddAssign for ReliabilityStats {
+    fn add_assign(&mut self, other: ReliabilityStats) {
+        self.outstanding += other.outstanding;
+        self.retransmitted += other.retransmitted;
+        self.abandoned += other.abandoned;
+        self.duplicates += other.duplicates;
+        self.in_flight += other.in_flight;
+        self.queued += other.queued;
+    }
+}
+
+/// Acknowledges the packets of a message: `[type(1B)][rpc id(8B)]`
+/// `[cumulative(2B)][selective(8B)][dst ip(4B)][dst port(2B)]`. Packets
+/// below `cumulative` arrived, as did packet `cumulative + 1 + i` for each
+/// bit `i` of `selective`. `dst` echoes the destination the packets were
+/// sent to, which identifies the message to the sender along with the ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ack {
+    pub rpc_id: u64,
+    pub cumulative: u16,
+    pub selective: u64,
+    pub dst: SocketAddrV4,
+}
+
+impl Ack {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(ACK_PACKET_LEN);
+        buf.push(ACK_PACKET_TYPE);
+        buf.extend_from_slice(&self.rpc_id.to_le_bytes());
+        buf.extend_from_slice(&self.cumulative.to_le_bytes());
+        buf.extend_from_slice(&self.selective.to_le_bytes());
+        buf.extend_from_slice(&self.dst.ip().octets());
+        buf.extend_from_slice(&self.dst.port().to_le_bytes());
+        buf
+    }
+
+    pub fn parse(buf: &[u8]) -> Option<Ack> {
+        if buf.len() < ACK_PACKET_LEN || buf[0] != ACK_PACKET_TYPE {
+            return None;
+        }
+        let ip = Ipv4Addr::new(buf[19], buf[20], buf[21], buf[22]);
+        Some(Ack {
+            rpc_id: u64::from_le_bytes(buf[1..9].try_into().unwrap()),
+            cumulative: u16::from_le_bytes([buf[9], buf[10]]),
+            selective: u64::from_le_bytes(buf[11..19].try_into().unwrap()),
+            dst: SocketAddrV4::new(ip, u16::from_le_bytes([buf[23], buf[24]])),
+        })
+    }
+
+    /// Whether packet `seq` arrived.
+    fn covers(&self, seq: usize) -> bool {
+        let cumulative = self.cumulative as usize;
+        seq < cumulative
+            || (seq > cumulative && seq - cumulative <= 64 && {
+                self.selective & (1 << (seq - cumulative - 1)) != 0
+            })
+    }
+}
+
+/// A message by peer and RPC ID.
+type Key = (SocketAddrV4, u64);
+
+/// The acknowledgement and retransmission state of a transport.
+pub(crate) struct Reliable {
+    config: Reliability,
+    outstanding: HashMap<Key, Outstanding>,
+    rtt: HashMap<SocketAddrV4, RttEstimator>,
+    received: HashMap<Key, Received>,
+    // Received messages by arrival, the order they are forgotten in.
+    arrivals: VecDeque<(Instant, Key)>,
+    congestion: Option<CongestionControl>,
+    paths: HashMap<SocketAddrV4, Path>,
+    stats: ReliabilityStats,
+}
+
+/// A sent message waiting for acknowledgements.
+struct Outstanding {
+    /// The encoded packets, None once acknowledged.
+    packets: Vec<Option<Vec<u8>>>,
+    /// How many packets went out; the rest wait for the congestion window.
+    next: usize,
+    /// Whether the congestion window held packets back.
+    held: bool,
+    sent: Instant,
+    retransmits: u32,
+    deadline: Instant,
+}
+
+/// The congestion state of the messages to one peer.
+struct Path {
+    controller: Box<dyn CongestionController>,
+    in_flight: usize,
+    /// The messages with packets waiting for the window, oldest first.
+    queue: VecDeque<u64>,
+}
+
+struct Received {
+    started: Instant,
+    seen: Vec<bool>,
+    /// Packets before the first gap.
+    cumulative: usize,
+    complete: bool,
+}
+
+/// Smoothed round-trip time of a peer (RFC 6298).
+#[derive(Default)]
+struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+}
+
+impl RttEstimator {
+    fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = (self.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+    }
+
+    fn rto(&self, config: &Reliability) -> Duration {
+        match self.srtt {
+            Some(srtt) => (srtt + self.rttvar * 4).clamp(config.min_rto, config.max_rto),
+            None => config.initial_rto,
+        }
+    }
+}
+
+impl Reliable {
+    pub fn new(config: Reliability) -> Reliable {
+        Reliable {
+            config,
+            outstanding: HashMap::new(),
+            rtt: HashMap::new(),
+            received: HashMap::new(),
+            arrivals: VecDeque::new(),
+            congestion: None,
+            paths: HashMap::new(),
+            stats: ReliabilityStats::default(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: Reliability) {
+        self.config = config;
+    }
+
+    /// Bounds what is in flight to each peer by the windows of the
+    /// controllers `congestion` makes, starting afresh; None lets every
+    /// packet go at once, the waiting ones with the next [`Reliable::release`].
+    pub fn set_congestion(&mut self, congestion: Option<CongestionControl>) {
+        if let Some(congestion) = &congestion {
+            for path in self.paths.values_mut() {
+                path.controller = congestion.controller();
+            }
+        }
+        self.congestion = congestion;
+    }
+
+    pub fn stats(&self) -> ReliabilityStats {
+        ReliabilityStats {
+            outstanding: self.outstanding.len(),
+            in_flight: self.paths.values().map(|path| path.in_flight).sum(),
+            queued: self.paths.values().map(|path| path.queue.len()).sum(),
+            ..self.stats
+        }
+    }
+
+    /// Keeps the packets of a message sent to `dst` until they are
+    /// acknowledged, returning how many of them may be sent now; the rest
+    /// wait for the congestion window, behind the messages already waiting.
+    pub fn track(
+        &mut self,
+        dst: SocketAddrV4,
+        rpc_id: u64,
+        packets: Vec<Vec<u8>>,
+        now: Instant,
+    ) -> usize {
+        let rto = self.rto(dst);
+        let mut outstanding = Outstanding {
+            packets: packets.into_iter().map(Some).collect(),
+            next: 0,
+            held: false,
+            sent: now,
+            retransmits: 0,
+            deadline: now + rto,
+        };
+        match &self.congestion {
+            Some(congestion) => {
+                let path = self.paths.entry(dst).or_insert_with(|| Path {
+                    controller: congestion.controller(),
+                    in_flight: 0,
+                    queue: VecDeque::new(),
+                });
+                if path.queue.is_empty() {
+                    path.send(&mut outstanding);
+                }
+                if outstanding.next < outstanding.packets.len() {
+                    outstanding.held = true;
+                    path.queue.push_back(rpc_id);
+                }
+            }
+            None => outstanding.next = outstanding.packets.len(),
+        }
+        let next = outstanding.next;
+        self.outstanding.insert((dst, rpc_id), outstanding);
+        next
+    }
+
+    /// The packets the congestion windows let go now, oldest first.
+    pub fn release(&mut self, now: Instant) -> Vec<(SocketAddrV4, Vec<u8>)> {
+        let mut release = Vec::new();
+        for (&dst, path) in &mut self.paths {
+            while let Some(&rpc_id) = path.queue.front() {
+                let Some(outstanding) = self.outstanding.get_mut(&(dst, rpc_id)) else {
+                    path.queue.pop_front();
+                    continue;
+                };
+                let from = outstanding.next;
+                if self.congestion.is_some() {
+                    path.send(outstanding);
+                } else {
+                    outstanding.next = outstanding.packets.len();
+                }
+                if from == 0 && outstanding.next > 0 {
+                    let rto = self
+                        .rtt
+                        .get(&dst)
+                        .map_or(self.config.initial_rto, |rtt| rtt.rto(&self.config));
+                    outstanding.sent = now;
+                    outstanding.deadline = now + rto;
+                }
+                for packet in outstanding.packets[from..outstanding.next].iter().flatten() {
+                    release.push((dst, buffer::copy(packet)));
+                }
+                if outstanding.next < outstanding.packets.len() {
+                    break;
+                }
+                path.queue.pop_front();
+            }
+        }
+        release
+    }
+
+    /// Drops the acknowledged packets of a message, measuring the round
+    /// trip if none of them was resent (Karn's algorithm).
+    pub fn on_ack(&mut self, ack: &Ack, now: Instant) {
+        let key = (ack.dst, ack.rpc_id);
+        let Some(outstanding) = self.outstanding.get_mut(&key) else {
+            return;
+        };
+        let mut acked = 0;
+        let mut newly_acked = false;
+        for (seq, packet) in outstanding.packets[..outstanding.next]
+            .iter_mut()
+            .enumerate()
+        {
+            if packet.is_some() && ack.covers(seq) {
+                let packet = packet.take().unwrap();
+                acked += packet.len();
+                buffer::give(packet);
+                newly_acked = true;
+            }
+        }
+        if !newly_acked {
+            return;
+        }
+        let rtt = self.rtt.entry(ack.dst).or_default();
+        let sample = (outstanding.retransmits == 0 && !outstanding.held)
+            .then(|| now.duration_since(outstanding.sent));
+        if let Some(sample) = sample {
+            rtt.sample(sample);
+        }
+        if let Some(path) = self.paths.get_mut(&ack.dst) {
+            path.in_flight = path.in_flight.saturating_sub(acked);
+            path.controller.on_ack(acked, sample, now);
+        }
+        // The peer is getting through: wait a full timeout for the rest.
+        outstanding.deadline = now + rtt.rto(&self.config);
+        if outstanding.packets.iter().all(Option::is_none) {
+            self.outstanding.remove(&key);
+        }
+    }
+
+    /// The packets to send again now, abandoning the messages out of
+    /// retries.
+    pub fn due(&mut self, now: Instant) -> Vec<(SocketAddrV4, Vec<u8>)> {
+        let mut resend = Vec::new();
+        let mut abandoned = Vec::new();
+        for (&(dst, rpc_id), outstanding) in &mut self.outstanding {
+            // Packets waiting for the window were not sent to be lost.
+            if outstanding.deadline > now || outstanding.next == 0 {
+                continue;
+            }
+            if outstanding.retransmits >= self.config.max_retransmits {
+                abandoned.push((dst, rpc_id));
+                continue;
+            }
+            if let Some(path) = self.paths.get_mut(&dst) {
+                path.controller
+                    .on_timeout(outstanding.sent, path.in_flight, now);
+            }
+            outstanding.retransmits += 1;
+            outstanding.sent = now;
+            trace::retransmitted(dst, rpc_id, outstanding.retransmits);
+            let rto = self
+                .rtt
+                .get(&dst)
+                .map_or(self.config.initial_rto, |rtt| rtt.rto(&self.config));
+            let backoff = rto.saturating_mul(1 << outstanding.retransmits.min(16));
+            outstanding.deadline = now + backoff.min(self.config.max_rto);
+            for packet in outstanding.packets[..outstanding.next].iter().flatten() {
+                resend.push((dst, buffer::copy(packet)));
+            }
+        }
+        for key in abandoned {
+            log::warn!(
+                "abandoning rpc {} to {} after {} retransmissions",
+                key.1,
+                key.0,
+                self.config.max_retransmits
+            );
+            trace::abandoned(key.0, key.1);
+            if let Some(outstanding) = self.outstanding.remove(&key) {
+                if let Some(path) = self.paths.get_mut(&key.0) {
+                    path.abandon(key.1, outstanding);
+                }
+            }
+            self.stats.abandoned += 1;
+        }
+        self.stats.retransmitted += resend.len() as u64;
+        resend
+    }
+
+    /// Records the arrival of packet `seq` of `total` from `src`. Returns
+    /// false for a duplicate, which is dropped but still acknowledged.
+    ///
+    /// `whole` is false for a piece of a packet split by a proxy; such a
+    /// packet only counts as received once its message completes.
+    pub fn receive(
+        &mut self,
+        src: SocketAddrV4,
+        rpc_id: u64,
+        seq: u16,
+        total: u16,
+        whole: bool,
+        now: Instant,
+    ) -> bool {
+        self.forget(now);
+        let key = (src, rpc_id);
+        let received = self.received.entry(key).or_insert_with(|| {
+            self.arrivals.push_back((now, key));
+            Received {
+                started: now,
+                seen: vec![false; total as usize],
+                cumulative: 0,
+                complete: false,
+            }
+        });
+        let seq = seq as usize;
+        if received.complete || received.seen.get(seq).copied().unwrap_or(true) {
+            self.stats.duplicates += 1;
+            return false;
+        }
+        if whole {
+            received.mark(seq);
+        }
+        true
+    }
+
+    /// Records that the message of `rpc_id` from `src` was delivered.
+    pub fn complete(&mut self, src: SocketAddrV4, rpc_id: u64) {
+        if let Some(received) = self.received.get_mut(&(src, rpc_id)) {
+            for seq in 0..received.seen.len() {
+                received.mark(seq);
+            }
+            received.complete = true;
+        }
+    }
+
+    /// The acknowledgement of the message of `rpc_id` from `src`, sent to
+    /// this transport at `dst`.
+    pub fn ack(&self, src: SocketAddrV4, rpc_id: u64, dst: SocketAddrV4) -> Option<Ack> {
+        let received = self.received.get(&(src, rpc_id))?;
+        let cumulative = received.cumulative;
+        let mut selective = 0;
+        for (i, &seen) in received
+            .seen
+            .iter()
+            .skip(cumulative + 1)
+            .take(64)
+            .enumerate()
+        {
+            if seen {
+                selective |= 1 << i;
+            }
+        }
+        Some(Ack {
+            rpc_id,
+            cumulative: cumulative as u16,
+            selective,
+            dst,
+        })
+    }
+
+    /// Forgets everything about `peer`, whose session was torn down.
+    pub fn remove_peer(&mut self, peer: SocketAddrV4) {
+        self.outstanding.retain(|(dst, _), outstanding| {
+            if *dst == peer {
+                outstanding
+                    .packets
+                    .drain(..)
+                    .flatten()
+                    .for_each(buffer::give);
+            }
+            *dst != peer
+        });
+        self.rtt.remove(&peer);
+        self.paths.remove(&peer);
+        self.received.retain(|(src, _), _| *src != peer);
+    }
+
+    fn rto(&self, dst: SocketAddrV4) -> Duration {
+        self.rtt
+            .get(&dst)
+            .map_or(self.config.initial_rto, |rtt| rtt.rto(&self.config))
+    }
+
+    /// Forgets the messages received too long ago to be retransmitted.
+    fn forget(&mut self, now: Instant) {
+        while let Some(&(started, key)) = self.arrivals.front() {
+            if now.duration_since(started) < RECEIVED_LINGER {
+                break;
+            }
+            self.arrivals.pop_front();
+            if self
+                .received
+                .get(&key)
+                .is_some_and(|r| r.started == started)
+            {
+                self.received.remove(&key);
+            }
+        }
+    }
+}
+
+impl Path {
+    /// Sends the packets of `outstanding` that fit in the window, at least
+    /// one while nothing is in flight.
+    fn send(&mut self, outstanding: &mut Outstanding) {
+        let window = self.controller.window();
+        while outstanding.next < outstanding.packets.len()
+            && (self.in_flight == 0 || self.in_flight < window)
+        {
+            self.in_flight += outstanding.packets[outstanding.next]
+                .as_ref()
+                .map_or(0, Vec::len);
+            outstanding.next += 1;
+        }
+    }
+
+    /// Takes a message given up on out of flight and out of the queue.
+    fn abandon(&mut self, rpc_id: u64, outstanding: Outstanding) {
+        let unacked: usize = outstanding.packets[..outstanding.next]
+            .iter()
+            .flatten()
+            .map(Vec::len)
+            .sum();
+        self.in_flight = self.in_flight.saturating_sub(unacked);
+        self.queue.retain(|queued| *queued != rpc_id);
+        outstanding
+            .packets
+            .into_iter()
+            .flatten()
+            .for_each(buffer::give);
+    }
+}
+
+impl Received {
+    fn mark(&mut self, seq: usize) {
+        self.seen[seq] = true;
+        while self.seen.get(self.cumulative) == Some(&true) {
+            self.cumulative += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const PEER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9000);
+    const LOCAL: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5000);
+
+    #[test]
+    fn acks_cumulatively_and_selectively() {
+        let mut receiver = Reliable::new(Reliability::default());
+        let now = Instant::now();
+        for seq in [0, 2, 3] {
+            assert!(receiver.receive(PEER, 7, seq, 5, true, now));
+        }
+        let ack = receiver.ack(PEER, 7, LOCAL).unwrap();
+        assert_eq!((ack.cumulative, ack.selective), (1, 0b11));
+        assert_eq!(Ack::parse(&ack.encode()), Some(ack));
+        assert!(ack.covers(0) && !ack.covers(1) && ack.covers(2) && !ack.covers(4));
+
+        // A duplicate is dropped, as is anything after completion.
+        assert!(!receiver.receive(PEER, 7, 2, 5, true, now));
+        assert!(receiver.receive(PEER, 7, 1, 5, true, now));
+        receiver.complete(PEER, 7);
+        assert_eq!(receiver.ack(PEER, 7, LOCAL).unwrap().cumulative, 5);
+        assert!(!receiver.receive(PEER, 7, 4, 5, true, now));
+        assert_eq!(receiver.stats().duplicates, 2);
+
+        let later = now + RECEIVED_LINGER;
+        assert!(receiver.receive(PEER, 8, 0, 1, true, later));
+        assert!(receiver.ack(PEER, 7, LOCAL).is_none());
+    }
+
+    #[test]
+    fn retransmits_unacked_packets_with_backoff() {
+        let config = Reliability {
+            max_retransmits: 2,
+            ..Reliability::default()
+        };
+        let mut sender = Reliable::new(config);
+        let now = Instant::now();
+        sender.track(PEER, 7, vec![vec![0], vec![1], vec![2]], now);
+        assert!(sender.due(now).is_empty());
+
+        let ack = Ack {
+            rpc_id: 7,
+            cumulative: 1,
+            selective: 0b1,
+            dst: PEER,
+        };
+        let acked = now + Duration::from_millis(100);
+        sender.on_ack(&ack, acked);
+        let rto = sender.rto(PEER);
+        assert_eq!(rto, Duration::from_millis(300));
+
+        let first = acked + rto;
+        assert_eq!(sender.due(first), vec![(PEER, vec![1])]);
+        // Backed off to twice the timeout.
+        assert!(sender.due(first + rto).is_empty());
+        let second = first + rto * 2;
+        assert_eq!(sender.due(second), vec![(PEER, vec![1])]);
+
+        assert!(sender.due(second + config.max_rto).is_empty());
+        let stats = sender.stats();
+        assert_eq!(
+            (stats.outstanding, stats.retransmitted, stats.abandoned),
+            (0, 2, 1)
+        );
+    }
+
+    /// A window of two bytes, counting the calls it gets.
+    struct Fixed(Arc<Mutex<(usize, usize)>>);
+
+    impl CongestionController for Fixed {
+        fn window(&self) -> usize {
+            2
+        }
+
+        fn on_ack(&mut self, bytes: usize, _rtt: Option<Duration>, _now: Instant) {
+            self.0.lock().unwrap().0 += bytes;
+        }
+
+        fn on_timeout(&mut self, _sent: Instant, _in_flight: usize, _now: Instant) {
+            self.0.lock().unwrap().1 += 1;
+        }
+    }
+
+    #[test]
+    fn holds_packets_past_the_window() {
+        let calls = Arc::new(Mutex::new((0, 0)));
+        let counted = calls.clone();
+        let mut sender = Reliable::new(Reliability::default());
+        sender.set_congestion(Some(CongestionControl::custom(move || {
+            Box::new(Fixed(counted.clone()))
+        })));
+        let now = Instant::now();
+        assert_eq!(
+            sender.track(PEER, 1, vec![vec![0], vec![1], vec![2]], now),
+            2
+        );
+        // Queued behind the first message, though the window has room.
+        assert_eq!(sender.track(PEER, 2, vec![vec![3]], now), 0);
+        assert!(sender.release(now).is_empty());
+        let stats = sender.stats();
+        assert_eq!((stats.in_flight, stats.queued), (2, 2));
+
+        let ack = |rpc_id, cumulative| Ack {
+            rpc_id,
+            cumulative,
+            selective: 0,
+            dst: PEER,
+        };
+        sender.on_ack(&ack(1, 1), now);
+        assert_eq!(sender.release(now), vec![(PEER, vec![2])]);
+        // Only sent packets time out, not the message still waiting.
+        let late = now + Reliability::default().initial_rto;
+        assert_eq!(sender.due(late), vec![(PEER, vec![1]), (PEER, vec![2])]);
+        sender.on_ack(&ack(1, 3), late);
+        assert_eq!(sender.release(late), vec![(PEER, vec![3])]);
+        sender.on_ack(&ack(2, 1), late);
+        let stats = sender.stats();
+        assert_eq!(
+            (stats.outstanding, stats.in_flight, stats.queued),
+            (0, 0, 0)
+        );
+        assert_eq!(*calls.lock().unwrap(), (4, 1));
+
+        // Lifting the window lets the waiting packets go.
+        sender.track(PEER, 3, vec![vec![4], vec![5], vec![6]], now);
+        sender.set_congestion(None);
+        assert_eq!(sender.release(now), vec![(PEER, vec![6])]);
+    }
+
+    #[test]
+    fn completes_on_the_last_ack() {
+        let mut sender = Reliable::new(Reliability::default());
+        let now = Instant::now();
+        sender.track(PEER, 1, vec![vec![0], vec![1]], now);
+        sender.on_ack(
+            &Ack {
+                rpc_id: 1,
+                cumulative: 2,
+                selective: 0,
+                dst: PEER,
+            },
+            now,
+        );
+        assert_eq!(sender.stats().outstanding, 0);
+    }
+}
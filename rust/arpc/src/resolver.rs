@@ -0,0 +1,174 @@
+//! Endpoint sets looked up by name, for a [`crate::Balancer`] to refresh
+//! its endpoints from; see [`crate::Balancer::watch`].
+//!
+//! [`DnsResolver`] takes every address of a host name, its A and AAAA
+//! records through the system resolver, which for a Kubernetes headless
+//! service is one per ready pod. [`SrvResolver`] (feature `dns`) takes the
+//! targets and ports of SRV records, like the named ports of a headless
+//! service, `_port._udp.service.namespace.svc.cluster.local`. Symphony
+//! headers only carry IPv4, so IPv6 addresses are left out.
+
+use std::net::{SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+use tokio::net::lookup_host;
+
+use crate::{BoxFuture, Error};
+
+/// A lookup of the endpoints serving a service.
+pub trait Resolver: Send + Sync + 'static {
+    /// The endpoints serving now. Fails rather than returning none, so
+    /// that a bad answer does not empty the endpoint set.
+    fn resolve(&self) -> BoxFuture<Result<Vec<SocketAddrV4>, Error>>;
+}
+
+/// How often endpoints are looked up again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Refresh {
+    pub interval: Duration,
+    /// Up to this much is added at random to each interval, so that the
+    /// clients of a service do not all look up at once.
+    pub jitter: Duration,
+}
+
+impl Default for Refresh {
+    fn default() -> Self {
+        Refresh {
+            interval: Duration::from_secs(30),
+            jitter: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Refresh {
+    /// The wait before the next lookup.
+    pub(crate) fn delay(&self) -> Duration {
+        let mut seed = [0; 8];
+        if let Err(e) = getrandom::getrandom(&mut seed) {
+            log::warn!("no random jitter for refresh: {}", e);
+        }
+        let fraction = u64::from_le_bytes(seed) as f64 / u64::MAX as f64;
+        self.interval + self.jitter.mul_f64(fraction)
+    }
+}
+
+/// The addresses of a host name, on one port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsResolver {
+    host: String,
+    port: u16,
+}
+
+impl DnsResolver {
+    pub fn new(host: &str, port: u16) -> DnsResolver {
+        DnsResolver {
+            host: host.to_string(),
+            port,
+        }
+    }
+}
+
+impl Resolver for DnsResolver {
+    fn resolve(&self) -> BoxFuture<Result<Vec<SocketAddrV4>, Error>> {
+        let (host, port) = (self.host.clone(), self.port);
+        Box::pin(async move {
+            let mut addrs: Vec<SocketAddrV4> = lookup_host((host.as_str(), port))
+                .await?
+                .filter_map(|addr| match addr {
+                    SocketAddr::V4(addr) => Some(addr),
+                    SocketAddr::V6(_) => None,
+                })
+                .collect();
+            addrs.sort();
+            addrs.dedup();
+            if addrs.is_empty() {
+                return Err(Error::Unresolved(host));
+            }
+            Ok(addrs)
+        })
+    }
+}
+
+/// The targets and ports of the SRV records of a name.
+#[cfg(feature = "dns")]
+#[derive(Clone)]
+pub struct SrvResolver {
+    name: String,
+    resolver: hickory_resolver::TokioAsyncResolver,
+}
+
+#[cfg(feature = "dns")]
+impl SrvResolver {
+    /// Looks `name` up through the resolvers of the system configuration,
+    /// `/etc/resolv.conf` on Unix.
+    pub fn new(name: &str) -> Result<SrvResolver, Error> {
+        let resolver = hickory_resolver::TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(|e| Error::Unresolved(format!("{}: {}", name, e)))?;
+        Ok(SrvResolver::with_resolver(name, resolver))
+    }
+
+    /// Looks `name` up through `resolver`.
+    pub fn with_resolver(
+        name: &str,
+        resolver: hickory_resolver::TokioAsyncResolver,
+    ) -> SrvResolver {
+        SrvResolver {
+            name: name.to_string(),
+            resolver,
+        }
+    }
+}
+
+#[cfg(feature = "dns")]
+impl Resolver for SrvResolver {
+    fn resolve(&self) -> BoxFuture<Result<Vec<SocketAddrV4>, Error>> {
+        let SrvResolver { name, resolver } = self.clone();
+        Box::pin(async move {
+            let unresolved =
+                |e: &dyn std::fmt::Display| Error::Unresolved(format!("{}: {}", name, e));
+            let records = resolver
+                .srv_lookup(name.as_str())
+                .await
+                .map_err(|e| unresolved(&e))?;
+            let mut addrs = Vec::new();
+            for srv in records.iter() {
+                let target = srv.target().to_utf8();
+                match resolver.lookup_ip(target.as_str()).await {
+                    Ok(ips) => addrs.extend(ips.iter().filter_map(|ip| match ip {
+                        std::net::IpAddr::V4(ip) => Some(SocketAddrV4::new(ip, srv.port())),
+                        std::net::IpAddr::V6(_) => None,
+                    })),
+                    Err(e) => log::warn!("no address for SRV target {}: {}", target, e),
+                }
+            }
+            addrs.sort();
+            addrs.dedup();
+            if addrs.is_empty() {
+                return Err(unresolved(&"no IPv4 targets"));
+            }
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[tokio::test]
+    async fn resolves_host_names() {
+        let addrs = DnsResolver::new("localhost", 9000).resolve().await.unwrap();
+        assert!(addrs.contains(&SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9000)));
+        assert!(DnsResolver::new("no-such-host.invalid", 9000)
+            .resolve()
+            .await
+            .is_err());
+
+        let refresh = Refresh::default();
+        for _ in 0..10 {
+            let delay = refresh.delay();
+            assert!(delay >= refresh.interval && delay <= refresh.interval + refresh.jitter);
+        }
+    }
+}
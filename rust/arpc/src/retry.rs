@@ -0,0 +1,241 @@
+//! Retries of unary calls that failed with a status worth another try,
+//! such as unavailable, backing off exponentially with jitter in between.
+//!
+//! [`Retry`] is an interceptor: added to a client with
+//! [`crate::Client::add_interceptor`], it retries what the interceptors
+//! added after it and the call itself do, so these run once per attempt,
+//! while those added before it see the call once. The attempts share the
+//! deadline of the call: each is sent with the time left to it, and no
+//! retry is made that would start past it.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::deadline;
+use crate::interceptor::{Interceptor, Next, Reply, Request, Response};
+use crate::{Code, Error};
+
+/// When and how often to retry a call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts in all, the first included.
+    pub max_attempts: u32,
+    /// The backoff before the first retry, growing `multiplier` times after
+    /// each up to `max_backoff`. Each wait is drawn at random below it.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// The status codes of the failures retried; other failures are not.
+    pub codes: Vec<Code>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            codes: vec![Code::Unavailable],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The backoff after `backoff`.
+    fn grow(&self, backoff: Duration) -> Duration {
+        Duration::try_from_secs_f64(backoff.as_secs_f64() * self.multiplier)
+            .map_or(self.max_backoff, |grown| grown.min(self.max_backoff))
+    }
+}
+
+/// The retry interceptor, with a policy for every method or some of them;
+/// see the [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct Retry {
+    policy: Option<RetryPolicy>,
+    // By service, then method.
+    methods: HashMap<String, HashMap<String, RetryPolicy>>,
+}
+
+impl Retry {
+    /// Retries the calls of every method by `policy`. [`Retry::default`]
+    /// retries only the methods given theirs.
+    pub fn new(policy: RetryPolicy) -> Retry {
+        Retry {
+            policy: Some(policy),
+            methods: HashMap::new(),
+        }
+    }
+
+    /// Retries the calls of `service.method` by `policy` instead.
+    pub fn method(mut self, service: &str, method: &str, policy: RetryPolicy) -> Retry {
+        let methods = self.methods.entry(service.to_string()).or_default();
+        methods.insert(method.to_string(), policy);
+        self
+    }
+
+    fn policy(&self, service: &str, method: &str) -> Option<&RetryPolicy> {
+        let methods = self.methods.get(service);
+        methods
+            .and_then(|methods| methods.get(method))
+            .or(self.policy.as_ref())
+    }
+}
+
+impl Interceptor for Retry {
+    fn intercept(&self, request: Request, next: Next) -> Reply {
+        match self.policy(&request.service, &request.method) {
+            Some(policy) => Box::pin(retry(policy.clone(), request, next)),
+            None => next.run(request),
+        }
+    }
+}
+
+async fn retry(policy: RetryPolicy, mut request: Request, next: Next) -> Result<Response, Error> {
+    let timeout = request.options.timeout;
+    let deadline = deadline::inherit(timeout.map(|timeout| Instant::now() + timeout));
+    let mut backoff = policy.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        let reply = next.clone().run(request.clone()).await;
+        let retryable = match &reply {
+            Err(Error::Status(status)) => policy.codes.contains(&status.code()),
+            _ => false,
+        };
+        if !retryable || attempt >= policy.max_attempts {
+            return reply;
+        }
+        let wait = jitter(backoff);
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if wait >= left {
+                return reply;
+            }
+            request.options.timeout = Some(left - wait);
+        }
+        log::debug!(
+            "retrying {}.{} in {:?}, attempt {} of {}",
+            request.service,
+            request.method,
+            wait,
+            attempt + 1,
+            policy.max_attempts
+        );
+        tokio::time::sleep(wait).await;
+        backoff = policy.grow(backoff);
+        attempt += 1;
+    }
+}
+
+/// A wait drawn at random below `backoff`.
+fn jitter(backoff: Duration) -> Duration {
+    let mut seed = [0; 8];
+    if let Err(e) = getrandom::getrandom(&mut seed) {
+        log::warn!("no random jitter for retries: {}", e);
+    }
+    let fraction = u64::from_le_bytes(seed) as f64 / u64::MAX as f64;
+    backoff.mul_f64(fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{CallOptions, Client, Server, ServiceDesc, Status};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn backoff_grows_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        let backoffs: Vec<_> =
+            std::iter::successors(Some(policy.initial_backoff), |&b| Some(policy.grow(b)))
+                .take(5)
+                .map(|b| b.as_millis())
+                .collect();
+        assert_eq!(backoffs, [100, 200, 400, 800, 1000]);
+        assert!(jitter(Duration::from_millis(10)) <= Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn retries_failures_worth_it_within_the_deadline() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let seen = calls.clone();
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        // Unavailable twice, then answers; Invalid always fails.
+        server.register(
+            ServiceDesc::new("FlakyService", 1)
+                .method("Flaky", 1, move |req: Text| {
+                    let calls = seen.clone();
+                    async move {
+                        if calls.fetch_add(1, Ordering::Relaxed) % 3 < 2 {
+                            return Err(Status::new(Code::Unavailable, "busy").into());
+                        }
+                        Ok::<_, Error>(req)
+                    }
+                })
+                .method("Invalid", 2, |_: Text| async move {
+                    Err::<Text, Error>(Status::new(Code::InvalidArgument, "no").into())
+                }),
+        );
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("FlakyService", 1, &[("Flaky", 1), ("Invalid", 2)]);
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(5),
+            ..RetryPolicy::default()
+        };
+        let invalid = RetryPolicy {
+            max_attempts: 2,
+            codes: vec![Code::InvalidArgument],
+            ..policy.clone()
+        };
+        client.add_interceptor(Retry::new(policy).method("FlakyService", "Invalid", invalid));
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counted = attempts.clone();
+        client.add_interceptor(move |request: Request, next: Next| -> Reply {
+            counted.fetch_add(1, Ordering::Relaxed);
+            next.run(request)
+        });
+
+        let resp: Text = client
+            .call("FlakyService", "Flaky", &Text::new("hi"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("hi"));
+        assert_eq!(attempts.swap(0, Ordering::Relaxed), 3);
+
+        let err = client
+            .call::<_, Text>("FlakyService", "Invalid", &Text::new(""))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Status(s) if s.code() == Code::InvalidArgument));
+        assert_eq!(attempts.swap(0, Ordering::Relaxed), 2);
+
+        // A backoff past the deadline gives up at once.
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("FlakyService", 1, &[("Flaky", 1)]);
+        let slow = RetryPolicy {
+            initial_backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(10),
+            ..RetryPolicy::default()
+        };
+        client.add_interceptor(Retry::new(slow));
+        let options = CallOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..CallOptions::default()
+        };
+        let started = Instant::now();
+        let err = client
+            .call_with::<_, Text>("FlakyService", "Flaky", &Text::new(""), &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Status(s) if s.code() == Code::Unavailable));
+        // Jitter may rarely draw a wait short enough for a retry, but never
+        // one past the deadline.
+        assert!(started.elapsed() < Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,1358 @@
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use symphony_wire::PacketType;
+use tokio::net::ToSocketAddrs;
+
+use crate::buffer;
+use crate::cancel::{self, CancellationToken, Handlers};
+use crate::compression::{self, Compression};
+use crate::deadline;
+use crate::drain::{shutting_down, Drain};
+use crate::extensions::{Extensions, StreamFrame};
+use crate::interceptor::{Chain, Next, Reply, Request, Response};
+use crate::metadata::{self, CallMetadata};
+#[cfg(feature = "metrics")]
+use crate::metrics::{Metrics, Timer};
+#[cfg(feature = "quic")]
+use crate::quic::QuicTransport;
+use crate::reflection::{Reflection, ServerReflectionServer};
+use crate::shard::Workers;
+use crate::stream::{FrameIds, Inflow, Streams};
+use crate::tcp::TcpTransport;
+use crate::trace;
+use crate::transport::{Received, Transport, UdpTransport};
+use crate::window::{WindowUpdate, Windows};
+use crate::{
+    BatchStats, Batching, BoxFuture, CallOptions, CipherSuite, Code, CongestionControl, Encryption,
+    Error, FlowControl, FlowWindows, FragmentStats, Interceptor, Keepalive, MethodKind, PathMtu,
+    ReassemblyLimits, Reliability, ReliabilityStats, RequestStream, ResponseStream, Service,
+    ServiceDesc, SessionClosed, Sharding, Shutdown, Status,
+};
+
+/// Where the host routes to the internet; used like the Go transport to
+/// pick the source IP of a server bound to an unspecified address.
+const DEFAULT_ROUTE: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(8, 8, 8, 8), 80);
+
+/// An aRPC server (`Server` in `pkg/rpc/server.go`).
+///
+/// Requests are dispatched on the service and method IDs of their Symphony
+/// header, and each runs on its own task, so a slow handler does not hold
+/// up the others. The response goes to the source address in the request
+/// packet's header, under the request's RPC ID.
+///
+/// The requests of a client- or bidi-streaming call go to one handler,
+/// started by whichever of its frames arrives first.
+///
+/// A request sent with a timeout is failed once it passes, dropping its
+/// handler; see [`crate::current_deadline`].
+pub struct Server {
+    transport: Arc<dyn Transport>,
+    // The same transport, for the settings only Symphony over UDP has, and
+    // those of the other workers of a sharded server.
+    udp: Vec<Arc<UdpTransport>>,
+    workers: Option<Workers>,
+    drain: Arc<Drain>,
+    handlers: Arc<Handlers>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+    services: HashMap<u32, Arc<ServiceDesc>>,
+    frame_ids: Arc<FrameIds>,
+    // The request streams being received, by client and stream RPC ID.
+    inbound: Arc<Streams<(SocketAddrV4, u64)>>,
+    flow: Option<Arc<dyn FlowControl>>,
+    windows: Option<Arc<Windows>>,
+    interceptors: Chain,
+    compression: Option<Arc<Compression>>,
+    reflection: bool,
+}
+
+impl Server {
+    pub async fn bind(addr: impl ToSocketAddrs) -> Result<Server, Error> {
+        let addr = bind_addr(addr).await?;
+        let udp = Arc::new(UdpTransport::bind(addr, DEFAULT_ROUTE).await?);
+        Ok(Server::start(udp.clone(), vec![udp]))
+    }
+
+    /// Binds a server whose port is shared by worker threads, each with a
+    /// socket and a runtime of its own, across which the kernel spreads
+    /// clients; see [`crate::Sharding`].
+    pub async fn bind_sharded(
+        addr: impl ToSocketAddrs,
+        sharding: Sharding,
+    ) -> Result<Server, Error> {
+        let addr = bind_addr(addr).await?;
+        let workers = Workers::bind(addr, DEFAULT_ROUTE, sharding).await?;
+        let udp = workers.transports();
+        let mut server = Server::start(udp[0].clone(), udp);
+        server.workers = Some(workers);
+        Ok(server)
+    }
+
+    /// Binds a server answering calls sent over TCP, for networks that
+    /// block UDP; see [`crate::TcpTransport`].
+    pub async fn bind_tcp(addr: impl ToSocketAddrs) -> Result<Server, Error> {
+        let addr = bind_addr(addr).await?;
+        let transport = TcpTransport::bind(addr).await?;
+        Ok(Server::with_transport(Arc::new(transport)))
+    }
+
+    /// Binds a server answering calls sent over QUIC streams; see
+    /// [`crate::Quic`].
+    #[cfg(feature = "quic")]
+    pub async fn bind_quic(addr: impl ToSocketAddrs, quic: crate::Quic) -> Result<Server, Error> {
+        let addr = bind_addr(addr).await?;
+        let transport = QuicTransport::bind(addr, quic)?;
+        Ok(Server::with_transport(Arc::new(transport)))
+    }
+
+    /// A server answering calls that arrive over `transport`, such as a
+    /// [`crate::MemoryTransport`] in tests. The settings of the UDP
+    /// transport are ignored.
+    pub fn with_transport(transport: Arc<dyn Transport>) -> Server {
+        Server::start(transport, Vec::new())
+    }
+
+    fn start(transport: Arc<dyn Transport>, udp: Vec<Arc<UdpTransport>>) -> Server {
+        let frame_ids = Arc::new(FrameIds::new());
+        Server {
+            transport,
+            udp,
+            workers: None,
+            drain: Arc::new(Drain::new(frame_ids.clone())),
+            handlers: Arc::new(Handlers::default()),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            services: HashMap::new(),
+            frame_ids,
+            inbound: Arc::new(Streams::new()),
+            flow: None,
+            windows: None,
+            interceptors: Arc::new([]),
+            compression: None,
+            reflection: false,
+        }
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.transport.local_addr()?)
+    }
+
+    /// Expects encrypted requests and encrypts responses; clients must use
+    /// the same keys. Ignored by other transports than UDP.
+    pub fn set_encryption(&self, encryption: Encryption) {
+        let encryption = Arc::new(encryption);
+        for udp in &self.udp {
+            udp.share_encryption(encryption.clone());
+        }
+    }
+
+    /// Takes requests sealed in any of `suites`, by default every suite
+    /// built, and refuses those in another with [`Code::Unimplemented`].
+    /// Requests that name no suite are taken as AES-256-GCM. Ignored by
+    /// other transports than UDP. See [`Encryption`].
+    pub fn set_cipher_suites(&self, suites: &[CipherSuite]) {
+        for udp in &self.udp {
+            udp.set_cipher_suites(suites);
+        }
+    }
+
+    /// Talks to each client over a DTLS session the client starts; clients
+    /// must use DTLS too. Ignored by other transports than UDP.
+    #[cfg(feature = "dtls")]
+    pub fn set_dtls(&self, dtls: crate::Dtls) {
+        for udp in &self.udp {
+            udp.set_dtls(dtls.clone());
+        }
+    }
+
+    /// Acknowledges and retransmits packets so that calls survive a lossy
+    /// link; None turns it off. The clients must enable it too. Ignored
+    /// by other transports than UDP.
+    pub fn set_reliability(&self, reliability: Option<Reliability>) {
+        for udp in &self.udp {
+            udp.set_reliability(reliability);
+        }
+    }
+
+    /// Paces what is sent in reliability mode with a congestion window per
+    /// peer; None sends every packet at once. Only this side needs it set.
+    /// Ignored by other transports than UDP. See [`crate::congestion`].
+    pub fn set_congestion_control(&self, congestion: Option<CongestionControl>) {
+        for udp in &self.udp {
+            udp.set_congestion_control(congestion.clone());
+        }
+    }
+
+    /// Sizes packets by the path MTU probed for, instead of the fixed size
+    /// of the Go transport; None turns it off. Ignored by other transports
+    /// than UDP. See [`crate::pmtu`].
+    pub fn set_path_mtu(&self, path_mtu: Option<PathMtu>) {
+        for udp in &self.udp {
+            udp.set_path_mtu(path_mtu);
+        }
+    }
+
+    /// Whether responses carry extension blocks to clients that have not
+    /// sent one; on by default. Turn it off to serve Go clients, which
+    /// reject packets with one; Rust clients get them once they send one.
+    /// Ignored by other transports than UDP.
+    pub fn set_extensions(&self, on: bool) {
+        for udp in &self.udp {
+            udp.set_extensions(on);
+        }
+    }
+
+    /// Drives the UDP socket with an io_uring instead of tokio, or with
+    /// tokio again with None. Fails, staying on tokio, where the kernel has
+    /// no io_uring. Ignored by other transports than UDP.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn set_io_uring(&self, io_uring: Option<crate::IoUring>) -> Result<(), Error> {
+        for udp in &self.udp {
+            udp.set_io_uring(io_uring)?;
+        }
+        Ok(())
+    }
+
+    /// Sets how datagrams are batched into system calls on Linux, or sends
+    /// and receives them one at a time with None; batching is on by
+    /// default. Ignored by other transports than UDP. See
+    /// [`crate::Batching`].
+    pub fn set_batching(&self, batching: Option<Batching>) {
+        for udp in &self.udp {
+            udp.set_batching(batching);
+        }
+    }
+
+    /// Counts of the batched system calls, over all workers.
+    pub fn batch_stats(&self) -> BatchStats {
+        let mut stats = BatchStats::default();
+        for udp in &self.udp {
+            stats += udp.batch_stats();
+        }
+        stats
+    }
+
+    /// Counts of messages split into packets and joined back, over all
+    /// workers.
+    pub fn fragment_stats(&self) -> FragmentStats {
+        let mut stats = FragmentStats::default();
+        for udp in &self.udp {
+            stats += udp.fragment_stats();
+        }
+        stats
+    }
+
+    /// Counts of the reliability mode, over all workers.
+    pub fn reliability_stats(&self) -> ReliabilityStats {
+        let mut stats = ReliabilityStats::default();
+        for udp in &self.udp {
+            stats += udp.reliability_stats();
+        }
+        stats
+    }
+
+    /// A handle that shuts the server down gracefully, usable once it is
+    /// serving; see [`crate::Shutdown`].
+    pub fn shutdown_handle(&self) -> Shutdown {
+        Shutdown::new(self.drain.clone())
+    }
+
+    /// Serves Prometheus metrics of the server at `addr`, on
+    /// `GET /metrics`, until the server and the returned handle are
+    /// dropped; see [`crate::metrics`].
+    #[cfg(feature = "metrics")]
+    pub async fn enable_metrics(&mut self, addr: impl ToSocketAddrs) -> Result<Metrics, Error> {
+        let addr = bind_addr(addr).await?;
+        let metrics = Metrics::serve(addr.into(), self.udp.clone()).await?;
+        self.metrics = Some(metrics.clone());
+        Ok(metrics)
+    }
+
+    /// The workers of a server from [`Server::bind_sharded`], whose stats
+    /// stay readable while it serves.
+    pub fn workers(&self) -> Option<Workers> {
+        self.workers.clone()
+    }
+
+    /// Pings clients while their sessions are quiet, and tears down the
+    /// sessions of those that stop answering or go without calls; None
+    /// turns it off. Ignored by other transports than UDP. See
+    /// [`crate::keepalive`].
+    pub fn set_keepalive(&self, keepalive: Option<Keepalive>) {
+        for udp in &self.udp {
+            udp.set_keepalive(keepalive);
+        }
+    }
+
+    /// Calls `f` with the client of every session keepalives tear down.
+    pub fn on_session_closed(
+        &self,
+        f: impl Fn(SocketAddrV4, SessionClosed) + Send + Sync + 'static,
+    ) {
+        let f = Arc::new(f);
+        for udp in &self.udp {
+            let f = f.clone();
+            udp.on_session_closed(move |peer, closed| f(peer, closed));
+        }
+    }
+
+    /// Bounds the requests being reassembled from fragments.
+    pub fn set_reassembly_limits(&self, limits: ReassemblyLimits) {
+        if self.udp.is_empty() {
+            self.transport.set_reassembly_limits(limits);
+        }
+        for udp in &self.udp {
+            udp.set_reassembly_limits(limits);
+        }
+    }
+
+    /// Paces the frames of streams with `control`; None sends and takes
+    /// them as fast as they come.
+    pub fn set_flow_control(&mut self, control: Option<Arc<dyn FlowControl>>) {
+        self.flow = control;
+    }
+
+    /// Paces the frames of streams with flow-control windows, so that a
+    /// slow reader on either side bounds what is buffered for it; None
+    /// sends them as fast as they come. Responses are only paced for
+    /// clients that enabled windows too. See [`crate::window`].
+    pub fn set_flow_windows(&mut self, windows: Option<FlowWindows>) {
+        self.windows = windows.map(|config| Arc::new(Windows::new(config)));
+    }
+
+    /// Takes compressed requests, and compresses responses with a codec
+    /// the client takes; None sends and takes them as they are. See
+    /// [`crate::compression`].
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compression = compression.map(Arc::new);
+    }
+
+    /// Adds `interceptor` to those wrapping unary handlers, after the ones
+    /// added before; see [`crate::interceptor`].
+    pub fn add_interceptor(&mut self, interceptor: impl Interceptor) {
+        let mut interceptors = self.interceptors.to_vec();
+        interceptors.push(Arc::new(interceptor));
+        self.interceptors = interceptors.into();
+    }
+
+    /// Registers a service, replacing any earlier one with the same ID.
+    pub fn register_service(&mut self, service: impl Service) {
+        self.register(Arc::new(service).describe());
+    }
+
+    /// Registers a service from its description.
+    pub fn register(&mut self, desc: ServiceDesc) {
+        log::info!("registered service {} ({})", desc.name, desc.id);
+        self.services.insert(desc.id, Arc::new(desc));
+    }
+
+    /// Serves [`crate::reflection`], describing the services registered
+    /// by the time the server starts serving.
+    pub fn enable_reflection(&mut self) {
+        self.reflection = true;
+    }
+
+    /// Serves requests until receiving fails, on any worker of a sharded
+    /// server.
+    pub async fn serve(mut self) -> Result<(), Error> {
+        if self.reflection {
+            let reflection = Reflection::new(self.services.values().map(|desc| &**desc));
+            self.register_service(ServerReflectionServer::new(reflection));
+        }
+        log::info!("serving on {}", self.local_addr()?);
+        match self.workers.clone() {
+            Some(workers) => workers.serve(Arc::new(self)).await,
+            None => {
+                let transport = self.transport.clone();
+                self.serve_on(&transport, None).await
+            }
+        }
+    }
+
+    /// Serves the requests arriving over `transport`, counting them in
+    /// `requests`.
+    pub(crate) async fn serve_on(
+        &self,
+        transport: &Arc<dyn Transport>,
+        requests: Option<&AtomicU64>,
+    ) -> Result<(), Error> {
+        loop {
+            let Some(received) = self.drain.until_done(transport.recv_message()).await else {
+                return Ok(());
+            };
+            let Some(mut received) = received? else {
+                continue;
+            };
+            if received.kind != PacketType::Request {
+                log::debug!(
+                    "ignoring {} packet {} from {}",
+                    received.kind.name(),
+                    received.rpc_id,
+                    received.src
+                );
+                continue;
+            }
+            if let Some(requests) = requests {
+                requests.fetch_add(1, Ordering::Relaxed);
+            }
+            self.drain.heard(received.src, transport);
+            if let Some(update) = received.extensions.window {
+                if let Some(windows) = &self.windows {
+                    windows.update(received.src, update);
+                }
+                // A window update carries nothing else.
+                if received.payload.is_empty() {
+                    continue;
+                }
+            }
+            if let Some(rpc_id) = received.extensions.cancel {
+                self.handlers.cancel((received.src, rpc_id));
+                continue;
+            }
+            let frame = received.extensions.stream;
+            let compression = self.compression.as_deref();
+            let payload = std::mem::take(&mut received.payload);
+            match compression::decode(compression, payload, received.extensions.encoding) {
+                Ok(payload) => received.payload = payload,
+                Err(e) => {
+                    // Reported on the stream, if the message is a frame.
+                    if let Some(frame) = frame {
+                        received.rpc_id = frame.id;
+                    }
+                    respond(&**transport, compression, &received, Err(e)).await;
+                    continue;
+                }
+            }
+            if let Some(frame) = frame {
+                let key = (received.src, frame.id);
+                if self.inbound.knows(key) {
+                    let message = (!frame.end).then_some(received.payload);
+                    self.inbound.deliver(key, frame.seq, message);
+                    continue;
+                }
+            }
+            if self.drain.is_draining() {
+                if let Some(frame) = frame {
+                    received.rpc_id = frame.id;
+                }
+                respond(&**transport, compression, &received, Err(shutting_down())).await;
+                continue;
+            }
+            let deadline = received
+                .extensions
+                .timeout
+                .map(|timeout| Instant::now() + timeout);
+            let call_metadata = CallMetadata::new(received.extensions.metadata.clone());
+            let token = CancellationToken::new();
+            let dispatched = deadline::enter(deadline, || {
+                metadata::enter(&call_metadata, || {
+                    cancel::enter(&token, || self.dispatch(transport, &mut received))
+                })
+            });
+            if let Some(frame) = frame {
+                // Answered under the stream's RPC ID, once the handler has
+                // the frame.
+                received.rpc_id = frame.id;
+                let message = (!frame.end).then(|| std::mem::take(&mut received.payload));
+                self.inbound
+                    .deliver((received.src, frame.id), frame.seq, message);
+            }
+            match dispatched {
+                Ok((call, mut observed)) => {
+                    let transport = transport.clone();
+                    let frame_ids = self.frame_ids.clone();
+                    let flow = self.flow.clone();
+                    let windows = self.windows.clone();
+                    let compression = self.compression.clone();
+                    let drain = self.drain.clone();
+                    let running = drain.call();
+                    let handler = self
+                        .handlers
+                        .run((received.src, received.rpc_id), token.clone());
+                    tokio::spawn(async move {
+                        let (_running, _handler) = (running, handler);
+                        let compression = compression.as_deref();
+                        let handled = async {
+                            match call {
+                                Call::Unary(call) => {
+                                    Some(within(deadline, call_metadata, call).await)
+                                }
+                                Call::Streaming(call) => {
+                                    let streamed = async {
+                                        let responses = call.await?;
+                                        let flow = flow.as_deref();
+                                        send_stream(
+                                            &*transport,
+                                            &frame_ids,
+                                            flow,
+                                            windows.as_deref(),
+                                            compression,
+                                            &received,
+                                            responses,
+                                        )
+                                        .await
+                                    };
+                                    match within(deadline, call_metadata, streamed).await {
+                                        Ok(()) => None,
+                                        Err(e) => Some(Err(e)),
+                                    }
+                                }
+                            }
+                        };
+                        let handled = drain.until_expired(cancel::scope(token, handled));
+                        let result = match observed.rpc.run(handled).await {
+                            None => Some(Err(shutting_down())),
+                            // The client gave up on the call.
+                            Some(None) => {
+                                observed
+                                    .finish(Err(&Status::new(Code::Cancelled, "cancelled").into()));
+                                return;
+                            }
+                            Some(Some(result)) => result,
+                        };
+                        match result {
+                            Some(result) => {
+                                observed.finish(result.as_ref().map(|resp| resp.message.len()));
+                                respond(&*transport, compression, &received, result).await;
+                            }
+                            // Streamed.
+                            None => observed.finish(Ok(0)),
+                        }
+                    });
+                }
+                Err(e) => {
+                    let compression = self.compression.as_deref();
+                    respond(&**transport, compression, &received, Err(e)).await
+                }
+            }
+        }
+    }
+
+    /// Starts the handler of the request's method. A unary or
+    /// server-streaming handler takes the payload, which its request may
+    /// share; a streaming request's first frame is left for the stream.
+    fn dispatch(
+        &self,
+        transport: &Arc<dyn Transport>,
+        request: &mut Received,
+    ) -> Result<(Call, Observed), Error> {
+        let bytes = request.payload.len();
+        let header = symphony_wire::parse_message_header(&request.payload)
+            .map_err(|e| Status::new(Code::InvalidArgument, format!("invalid request: {}", e)))?;
+        let fail = |message: String| Error::from(Status::new(Code::Unimplemented, message));
+        let service = self
+            .services
+            .get(&header.service_id)
+            .ok_or_else(|| fail("unknown service".to_string()))?;
+        let id = header.method_id;
+        let call = match (service.method_kind(id), request.extensions.stream) {
+            (Some(MethodKind::Unary), None) => self.handle(service, id, request).map(Call::Unary),
+            (Some(MethodKind::ServerStreaming), None) => service
+                .handle_server_streaming(id, buffer::share(std::mem::take(&mut request.payload)))
+                .map(Call::Streaming),
+            (Some(MethodKind::ClientStreaming), Some(frame)) => service
+                .handle_client_streaming(id, self.requests(transport, request.src, frame.id))
+                .map(answered)
+                .map(Call::Unary),
+            (Some(MethodKind::BidiStreaming), Some(frame)) => service
+                .handle_bidi_streaming(id, self.requests(transport, request.src, frame.id))
+                .map(Call::Streaming),
+            (Some(kind), _) => {
+                return Err(fail(format!(
+                    "{}.{} is a {:?} method",
+                    service.name,
+                    service.method_name(id).unwrap_or_default(),
+                    kind
+                )))
+            }
+            (None, _) => None,
+        }
+        .ok_or_else(|| fail(format!("unknown method of {}", service.name)))?;
+        let method = service.method_name(header.method_id).unwrap_or_default();
+        log::debug!(
+            "rpc {} from {}: {}.{}",
+            request.rpc_id,
+            request.src,
+            service.name,
+            method
+        );
+        let rpc_id = request
+            .extensions
+            .stream
+            .map_or(request.rpc_id, |frame| frame.id);
+        let observed = Observed {
+            rpc: trace::Rpc::server(&service.name, method, rpc_id, request.src, bytes),
+            #[cfg(feature = "metrics")]
+            timer: self
+                .metrics
+                .as_ref()
+                .map(|metrics| metrics.start(&service.name, method)),
+        };
+        Ok((call, observed))
+    }
+
+    /// Starts unary method `id` of `service` on the request's payload,
+    /// through the interceptors.
+    fn handle(&self, service: &Arc<ServiceDesc>, id: u32, request: &mut Received) -> Option<Reply> {
+        let payload = std::mem::take(&mut request.payload);
+        if self.interceptors.is_empty() {
+            return service.handle(id, buffer::share(payload)).map(answered);
+        }
+        let desc = service.clone();
+        let next = Next::new(self.interceptors.clone(), move |request: Request| {
+            answered(
+                desc.handle(id, buffer::share(request.message))
+                    .expect("unary method"),
+            )
+        });
+        Some(next.run(Request {
+            service: service.name.clone(),
+            method: service.method_name(id)?.to_string(),
+            peer: request.src,
+            message: payload,
+            options: CallOptions {
+                timeout: request.extensions.timeout,
+                metadata: request.extensions.metadata.clone(),
+                ..CallOptions::default()
+            },
+        }))
+    }
+
+    /// Starts receiving the stream of requests `stream` from `src`,
+    /// granting it its first credit over `transport`.
+    fn requests(
+        &self,
+        transport: &Arc<dyn Transport>,
+        src: SocketAddrV4,
+        stream: u64,
+    ) -> RequestStream {
+        let key = (src, stream);
+        let inbound = self.inbound.clone();
+        let (transport, frame_ids) = (transport.clone(), self.frame_ids.clone());
+        let send = move |update| {
+            let (transport, frame_ids) = (transport.clone(), frame_ids.clone());
+            tokio::spawn(async move {
+                let extensions = Extensions {
+                    window: Some(update),
+                    ..Extensions::default()
+                };
+                let id = frame_ids.next();
+                let sent = transport
+                    .send_message(src, PacketType::Response, id, &[], &extensions)
+                    .await;
+                if let Err(e) = sent {
+                    log::debug!("failed to send window update to {}: {}", src, e);
+                }
+            });
+        };
+        if let Some(windows) = &self.windows {
+            let limit = windows.open(src, stream);
+            if limit > 0 {
+                send(WindowUpdate { id: stream, limit });
+            }
+        }
+        let requests = Inflow::new(self.inbound.open(key), move || inbound.close(key))
+            .with_flow_control(stream, self.flow.clone())
+            .with_window(self.windows.clone(), src, stream, send);
+        Box::pin(requests)
+    }
+}
+
+/// What is recorded of a request being handled: its span and, with metrics
+/// on, its timer.
+struct Observed {
+    rpc: trace::Rpc,
+    #[cfg(feature = "metrics")]
+    timer: Option<Timer>,
+}
+
+impl Observed {
+    /// Records the length of the response, or the failure.
+    fn finish(&mut self, outcome: Result<usize, &Error>) {
+        self.rpc.finish(outcome);
+        #[cfg(feature = "metrics")]
+        if let Some(timer) = &mut self.timer {
+            timer.finish(outcome.err());
+        }
+    }
+}
+
+/// The IPv4 address `addr` resolves to.
+async fn bind_addr(addr: impl ToSocketAddrs) -> Result<SocketAddrV4, Error> {
+    tokio::net::lookup_host(addr)
+        .await?
+        .find_map(|addr| match addr {
+            SocketAddr::V4(addr) => Some(addr),
+            SocketAddr::V6(_) => None,
+        })
+        .ok_or_else(|| Error::Unresolved("server address".to_string()))
+}
+
+/// A started handler, answering once or with a stream.
+enum Call {
+    Unary(Reply),
+    Streaming(BoxFuture<Result<ResponseStream, Error>>),
+}
+
+/// Adds the response metadata the handler set to its response.
+fn answered(call: BoxFuture<Result<Vec<u8>, Error>>) -> Reply {
+    Box::pin(async move {
+        let message = call.await?;
+        Ok(Response {
+            message,
+            metadata: metadata::take_response(),
+        })
+    })
+}
+
+/// Runs `call` with `deadline` as the task's deadline and `call_metadata`
+/// as its metadata, failing it once the deadline passes.
+async fn within<T>(
+    deadline: Option<Instant>,
+    call_metadata: Arc<Mutex<CallMetadata>>,
+    call: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let call = deadline::scope(deadline, metadata::scope(call_metadata, call));
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), call)
+            .await
+            .unwrap_or_else(|_| {
+                trace::deadline_exceeded();
+                Err(Status::new(Code::DeadlineExceeded, "deadline exceeded").into())
+            }),
+        None => call.await,
+    }
+}
+
+/// Sends `responses` as the frames of the stream answering `request`, then
+/// the frame ending it, each once `flow` lets it. An error from the stream
+/// is returned unsent.
+async fn send_stream(
+    transport: &dyn Transport,
+    frame_ids: &FrameIds,
+    flow: Option<&dyn FlowControl>,
+    windows: Option<&Windows>,
+    compression: Option<&Compression>,
+    request: &Received,
+    mut responses: ResponseStream,
+) -> Result<(), Error> {
+    // Paced with the credit the client granted, if it did.
+    let paced = windows
+        .zip(request.extensions.window)
+        .map(|(windows, update)| Paced::start(windows, request.src, request.rpc_id, update.limit));
+    for seq in 0.. {
+        let response = poll_fn(|cx| responses.as_mut().poll_next(cx)).await;
+        let end = response.is_none();
+        let message = response.transpose()?.unwrap_or_default();
+        if let (false, Some(flow)) = (end, flow) {
+            flow.ready(request.rpc_id, seq).await;
+        }
+        if let (false, Some(paced)) = (end, &paced) {
+            paced.reserve(message.len()).await;
+        }
+        let mut extensions = Extensions {
+            stream: Some(StreamFrame {
+                id: request.rpc_id,
+                seq,
+                end,
+            }),
+            ..Extensions::default()
+        };
+        let message = encode(compression, request, message, &mut extensions);
+        transport
+            .send_message(
+                request.src,
+                PacketType::Response,
+                frame_ids.next(),
+                &message,
+                &extensions,
+            )
+            .await?;
+        buffer::give(message);
+        if end {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// A stream of responses paced by its window, until dropped.
+struct Paced<'a> {
+    windows: &'a Windows,
+    peer: SocketAddrV4,
+    id: u64,
+}
+
+impl Paced<'_> {
+    fn start(windows: &Windows, peer: SocketAddrV4, id: u64, limit: u64) -> Paced<'_> {
+        windows.start(peer, id, limit);
+        Paced { windows, peer, id }
+    }
+
+    async fn reserve(&self, len: usize) {
+        self.windows.reserve(self.peer, self.id, len).await;
+    }
+}
+
+impl Drop for Paced<'_> {
+    fn drop(&mut self) {
+        self.windows.finish(self.peer, self.id);
+    }
+}
+
+/// Sends the response of `request`, or the error packet for its failure.
+async fn respond(
+    transport: &dyn Transport,
+    compression: Option<&Compression>,
+    request: &Received,
+    result: Result<Response, Error>,
+) {
+    let sent = match result {
+        Ok(response) => {
+            let mut extensions = Extensions {
+                metadata: response.metadata,
+                ..Extensions::default()
+            };
+            let message = encode(compression, request, response.message, &mut extensions);
+            let sent = transport
+                .send_message(
+                    request.src,
+                    PacketType::Response,
+                    request.rpc_id,
+                    &message,
+                    &extensions,
+                )
+                .await;
+            buffer::give(message);
+            sent
+        }
+        Err(e) => {
+            let status = match e {
+                Error::Status(status) => status,
+                e => {
+                    log::error!("rpc {} failed: {}", request.rpc_id, e);
+                    Status::new(Code::Unknown, e.to_string())
+                }
+            };
+            transport
+                .send_status(request.src, request.rpc_id, &status)
+                .await
+        }
+    };
+    if let Err(e) = sent {
+        log::error!("failed to respond to rpc {}: {}", request.rpc_id, e);
+    }
+}
+
+/// Compresses a response to `request` with the first codec the client
+/// takes.
+fn encode(
+    compression: Option<&Compression>,
+    request: &Received,
+    message: Vec<u8>,
+    extensions: &mut Extensions,
+) -> Vec<u8> {
+    match compression {
+        Some(compression) => {
+            let codec = compression.negotiate(&request.extensions.accept);
+            compression.encode(message, codec, extensions)
+        }
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{current_deadline, BoxStream, Client};
+    use futures_util::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    struct Echo;
+
+    impl Service for Echo {
+        fn describe(self: Arc<Self>) -> ServiceDesc {
+            ServiceDesc::new("EchoService", 1)
+                .method("Echo", 1, |req: Text| async move {
+                    Ok::<_, Error>(Text(req.0.to_uppercase()))
+                })
+                .method("Fail", 2, |req: Text| async move {
+                    Err::<Text, _>(
+                        Status::new(Code::NotFound, req.0)
+                            .with_detail("t/Hint", b"h")
+                            .into(),
+                    )
+                })
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_registered_methods() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register_service(Echo);
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service(
+            "EchoService",
+            1,
+            &[("Echo", 1), ("Fail", 2), ("Missing", 3)],
+        );
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new("hello"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("HELLO"));
+
+        // Large enough to be split across packets both ways.
+        let long = "fragmented ".repeat(1000);
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new(&long))
+            .await
+            .unwrap();
+        assert_eq!(resp.0, long.to_uppercase());
+
+        for (method, expected) in [
+            (
+                "Fail",
+                Status::new(Code::NotFound, "rejected").with_detail("t/Hint", b"h"),
+            ),
+            (
+                "Missing",
+                Status::new(Code::Unimplemented, "unknown method of EchoService"),
+            ),
+        ] {
+            match client
+                .call::<_, Text>("EchoService", method, &Text::new("rejected"))
+                .await
+            {
+                Err(Error::Status(status)) => assert_eq!(status, expected),
+                other => panic!("unexpected result: {:?}", other.map(|t| t.0)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_encrypted_requests() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register_service(Echo);
+        server.set_encryption(Encryption::with_default_keys().unwrap());
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        client.set_encryption(Encryption::with_default_keys().unwrap());
+        let long = "sealed ".repeat(1000);
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new(&long))
+            .await
+            .unwrap();
+        assert_eq!(resp.0, long.to_uppercase());
+
+        // Calls go on across a key rotation.
+        client.rekey().await.unwrap();
+        for _ in 0..3 {
+            let resp: Text = client
+                .call("EchoService", "Echo", &Text::new("rotated"))
+                .await
+                .unwrap();
+            assert_eq!(resp, Text::new("ROTATED"));
+        }
+    }
+
+    #[tokio::test]
+    async fn negotiates_the_cipher_suite() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register_service(Echo);
+        server.set_encryption(Encryption::with_default_keys().unwrap());
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        client.set_cipher_suite(CipherSuite::ChaCha20Poly1305);
+        client.set_encryption(Encryption::with_default_keys().unwrap());
+        for _ in 0..2 {
+            let resp: Text = client
+                .call("EchoService", "Echo", &Text::new("chacha"))
+                .await
+                .unwrap();
+            assert_eq!(resp, Text::new("CHACHA"));
+        }
+
+        // A server that only takes AES-GCM refuses the call.
+        let mut strict = Server::bind("127.0.0.1:0").await.unwrap();
+        strict.register_service(Echo);
+        strict.set_cipher_suites(&[CipherSuite::Aes256Gcm]);
+        strict.set_encryption(Encryption::with_default_keys().unwrap());
+        let addr = strict.local_addr().unwrap();
+        tokio::spawn(strict.serve());
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        client.set_cipher_suite(CipherSuite::ChaCha20Poly1305);
+        client.set_encryption(Encryption::with_default_keys().unwrap());
+        let err = client
+            .call::<_, Text>("EchoService", "Echo", &Text::new("chacha"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Status(s) if s.code() == Code::Unimplemented),
+            "{:?}",
+            err
+        );
+    }
+
+    /// Relays datagrams to `server`, dropping the first `drop` and sending
+    /// the rest twice. Replies bypass it, going to the source in the packet
+    /// header.
+    async fn lossy_relay(server: SocketAddr, drop: usize) -> SocketAddr {
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = relay.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 2048];
+            for seen in 1.. {
+                let (n, _) = relay.recv_from(&mut buf).await.unwrap();
+                if seen > drop {
+                    for _ in 0..2 {
+                        relay.send_to(&buf[..n], server).await.unwrap();
+                    }
+                }
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn reliability_survives_loss_and_duplicates() {
+        let reliability = Reliability {
+            initial_rto: Duration::from_millis(50),
+            ..Reliability::default()
+        };
+        let handled = Arc::new(AtomicUsize::new(0));
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        let counter = handled.clone();
+        server.register(
+            ServiceDesc::new("EchoService", 1).method("Echo", 1, move |req: Text| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                async move { Ok::<_, Error>(Text(req.0.to_uppercase())) }
+            }),
+        );
+        server.set_reliability(Some(reliability));
+        let relay = lossy_relay(server.local_addr().unwrap(), 1).await;
+        let server_stats = server.udp[0].clone();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(relay).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        client.set_reliability(Some(reliability));
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new("lost"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("LOST"));
+        assert_eq!(handled.load(Ordering::Relaxed), 1);
+        assert!(client.reliability_stats().retransmitted >= 1);
+        assert!(server_stats.reliability_stats().duplicates >= 1);
+
+        // Past the initial congestion window, packets wait for acks.
+        client.set_congestion_control(Some(CongestionControl::cubic()));
+        let long = "x".repeat(64 << 10);
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new(&long))
+            .await
+            .unwrap();
+        assert_eq!(resp.0, long.to_uppercase());
+        let stats = client.reliability_stats();
+        assert_eq!((stats.in_flight, stats.queued), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn enforces_and_propagates_deadlines() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        // Calls back into the same server, from a handler.
+        let downstream = Arc::new(Client::connect(addr).await.unwrap());
+        downstream.register_service("DeadlineService", 1, &[("Left", 1)]);
+        let finished = Arc::new(AtomicUsize::new(0));
+        let counter = finished.clone();
+        server.register(
+            ServiceDesc::new("DeadlineService", 1)
+                .method("Left", 1, |_: Text| async move {
+                    let left = current_deadline()
+                        .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                    Ok::<_, Error>(Text(format!("{:?}", left)))
+                })
+                .method("Forward", 2, move |req: Text| {
+                    let downstream = downstream.clone();
+                    async move {
+                        downstream
+                            .call::<_, Text>("DeadlineService", "Left", &req)
+                            .await
+                    }
+                })
+                .method("Slow", 3, move |_: Text| {
+                    let counter = counter.clone();
+                    async move {
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        counter.fetch_add(1, Ordering::Relaxed);
+                        Ok::<_, Error>(Text::new(""))
+                    }
+                }),
+        );
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service(
+            "DeadlineService",
+            1,
+            &[("Left", 1), ("Forward", 2), ("Slow", 3)],
+        );
+        let resp: Text = client
+            .call("DeadlineService", "Left", &Text::new(""))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("None"));
+
+        let options = CallOptions {
+            timeout: Some(Duration::from_secs(5)),
+            ..CallOptions::default()
+        };
+        for method in ["Left", "Forward"] {
+            let resp: Text = client
+                .call_with("DeadlineService", method, &Text::new(""), &options)
+                .await
+                .unwrap();
+            assert!(resp.0.starts_with("Some("), "{}: {}", method, resp.0);
+        }
+
+        let options = CallOptions {
+            timeout: Some(Duration::from_millis(50)),
+            ..CallOptions::default()
+        };
+        let err = client
+            .call_with::<_, Text>("DeadlineService", "Slow", &Text::new(""), &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::TimedOut), "unexpected error: {}", err);
+        // The handler was dropped at the deadline.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(finished.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn sends_extensions_only_to_peers_that_read_them() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("DeadlineService", 1).method(
+            "Left",
+            1,
+            |_: Text| async move { Ok::<_, Error>(Text(current_deadline().is_some().to_string())) },
+        ));
+        server.set_extensions(false);
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+        let options = CallOptions {
+            timeout: Some(Duration::from_secs(5)),
+            ..CallOptions::default()
+        };
+
+        // As for a Go server: the deadline stays behind, and streams,
+        // which need their block, fail.
+        let plain = Client::connect(addr).await.unwrap();
+        plain.register_service("DeadlineService", 1, &[("Left", 1), ("Collect", 2)]);
+        plain.set_extensions(false);
+        let resp: Text = plain
+            .call_with("DeadlineService", "Left", &Text::new(""), &options)
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("false"));
+        let requests = futures_util::stream::iter([Text::new("")]);
+        let err = plain
+            .client_streaming::<_, Text>("DeadlineService", "Collect", requests, &options)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Status(s) if s.code() == Code::Unimplemented),
+            "unexpected error: {}",
+            err
+        );
+
+        // The server reads blocks whatever it sends.
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("DeadlineService", 1, &[("Left", 1)]);
+        let resp: Text = client
+            .call_with("DeadlineService", "Left", &Text::new(""), &options)
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("true"));
+    }
+
+    #[tokio::test]
+    async fn streams_responses() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("CountService", 1).server_streaming(
+            "Count",
+            1,
+            |req: Text| async move {
+                let mut responses: Vec<_> = (0..3)
+                    // The second is split across packets.
+                    .map(|i| Ok(Text(format!("{}{}", i, " ".repeat(i * 1500)))))
+                    .collect();
+                if req.0 == "fail" {
+                    responses.push(Err(Status::new(Code::OutOfRange, "no more").into()));
+                }
+                Ok(futures_util::stream::iter(responses))
+            },
+        ));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("CountService", 1, &[("Count", 1)]);
+        for (req, failed) in [("", false), ("fail", true)] {
+            let responses: Vec<Result<Text, Error>> = client
+                .server_streaming(
+                    "CountService",
+                    "Count",
+                    &Text::new(req),
+                    &CallOptions::default(),
+                )
+                .await
+                .unwrap()
+                .collect()
+                .await;
+            assert_eq!(responses.len(), 3 + failed as usize);
+            for (i, response) in responses.iter().take(3).enumerate() {
+                assert_eq!(response.as_ref().unwrap().0.trim_end(), i.to_string());
+            }
+            if failed {
+                assert!(
+                    matches!(&responses[3], Err(Error::Status(status)) if status.message() == "no more")
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn collects_streamed_requests() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(
+            ServiceDesc::new("JoinService", 1)
+                .client_streaming(
+                    "Join",
+                    1,
+                    |requests: BoxStream<Result<Text, Error>>| async move {
+                        let parts: Vec<_> = requests.collect().await;
+                        let parts = parts.into_iter().collect::<Result<Vec<_>, _>>()?;
+                        Ok::<_, Error>(Text(parts.iter().map(|part| part.0.trim_end()).collect()))
+                    },
+                )
+                .method("Echo", 2, |req: Text| async move { Ok::<_, Error>(req) }),
+        );
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("JoinService", 1, &[("Join", 1), ("Echo", 2)]);
+        // The second is split across packets.
+        let parts = (0..3).map(|i| Text(format!("{}{}", i, " ".repeat(i * 1500))));
+        let resp: Text = client
+            .client_streaming(
+                "JoinService",
+                "Join",
+                futures_util::stream::iter(parts),
+                &CallOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("012"));
+
+        let empty = futures_util::stream::iter(Vec::<Text>::new());
+        let resp: Text = client
+            .client_streaming("JoinService", "Join", empty, &CallOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new(""));
+
+        // A unary method is not called with a stream.
+        let once = futures_util::stream::iter([Text::new("x")]);
+        let err = client
+            .client_streaming::<_, Text>("JoinService", "Echo", once, &CallOptions::default())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::Status(ref status) if status.code() == Code::Unimplemented
+            ),
+            "{}",
+            err
+        );
+    }
+
+    /// Counts the frames it lets through and those taken.
+    #[derive(Default)]
+    struct Counted {
+        ready: AtomicUsize,
+        consumed: AtomicUsize,
+    }
+
+    impl FlowControl for Counted {
+        fn ready(&self, _: u64, _: u32) -> BoxFuture<()> {
+            self.ready.fetch_add(1, Ordering::Relaxed);
+            Box::pin(async {})
+        }
+
+        fn consumed(&self, _: u64, _: u32) {
+            self.consumed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn exchanges_bidi_streams() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("ChatService", 1).bidi_streaming(
+            "Shout",
+            1,
+            |requests: BoxStream<Result<Text, Error>>| async move {
+                Ok(requests.map(|req| Ok(Text(req?.0.to_uppercase()))))
+            },
+        ));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("ChatService", 1, &[("Shout", 1)]);
+        let counted = Arc::new(Counted::default());
+        client.set_flow_control(Some(counted.clone()));
+        let (mut requests, mut responses) = client
+            .bidi_streaming::<Text, Text>("ChatService", "Shout", &CallOptions::default())
+            .unwrap();
+        // Each response comes back before the next request is sent.
+        for text in ["one", "two"] {
+            requests.send(&Text::new(text)).await.unwrap();
+            let resp = responses.next().await.unwrap().unwrap();
+            assert_eq!(resp, Text(text.to_uppercase()));
+        }
+        requests.finish().await.unwrap();
+        assert!(responses.next().await.is_none());
+        assert_eq!(counted.ready.load(Ordering::Relaxed), 2);
+        assert_eq!(counted.consumed.load(Ordering::Relaxed), 2);
+
+        // Dropping the sender ends the requests, and so the responses.
+        let (requests, mut responses) = client
+            .bidi_streaming::<Text, Text>("ChatService", "Shout", &CallOptions::default())
+            .unwrap();
+        drop(requests);
+        assert!(responses.next().await.is_none());
+    }
+}
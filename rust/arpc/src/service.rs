@@ -0,0 +1,358 @@
+//! Services and their methods, as the server dispatches to them
+//! (`ServiceDesc` in `pkg/rpc/server.go`).
+//!
+//! Nothing here depends on an async runtime: a handler is a future, or a
+//! stream of them, that whatever executor polls it drives, so that
+//! [`ServiceDesc::handle`] can serve requests read from any socket.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_core::Stream;
+
+use crate::{buffer, Code, Error, Message, Status};
+
+/// A boxed future that handlers and services return.
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// A boxed stream that streaming handlers return.
+pub type BoxStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
+
+/// The marshaled responses of a server-streaming method.
+pub type ResponseStream = BoxStream<Result<Vec<u8>, Error>>;
+
+/// The marshaled requests of a client- or bidi-streaming method, as they
+/// arrive.
+pub type RequestStream = BoxStream<Result<Vec<u8>, Error>>;
+
+/// How a method exchanges messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodKind {
+    /// One request, one response.
+    Unary,
+    /// One request, a stream of responses.
+    ServerStreaming,
+    /// A stream of requests, one response.
+    ClientStreaming,
+    /// A stream of requests and one of responses, each ending on its own.
+    BidiStreaming,
+}
+
+/// Decodes a request and starts the method on it.
+type Handle<T> = Arc<dyn Fn(Bytes) -> BoxFuture<Result<T, Error>> + Send + Sync>;
+
+/// Starts a method on a stream of requests, which it decodes as they come.
+type HandleStream<T> = Arc<dyn Fn(RequestStream) -> BoxFuture<Result<T, Error>> + Send + Sync>;
+
+/// A method's handler, which encodes its response, or its stream of
+/// responses.
+enum Handler {
+    Unary(Handle<Vec<u8>>),
+    ServerStreaming(Handle<ResponseStream>),
+    ClientStreaming(HandleStream<Vec<u8>>),
+    BidiStreaming(HandleStream<ResponseStream>),
+}
+
+/// A service implementation the server dispatches to.
+///
+/// Implementations describe their methods once, at registration; generated
+/// stubs implement it for the user's handler type.
+pub trait Service: Send + Sync + 'static {
+    fn describe(self: Arc<Self>) -> ServiceDesc;
+}
+
+/// A service's name, ID and methods (`ServiceDesc` in `pkg/rpc/server.go`).
+pub struct ServiceDesc {
+    pub(crate) name: String,
+    pub(crate) id: u32,
+    methods: HashMap<u32, MethodDesc>,
+    file: Option<&'static [u8]>,
+}
+
+struct MethodDesc {
+    name: String,
+    handler: Handler,
+}
+
+impl ServiceDesc {
+    pub fn new(name: &str, id: u32) -> ServiceDesc {
+        ServiceDesc {
+            name: name.to_string(),
+            id,
+            methods: HashMap::new(),
+            file: None,
+        }
+    }
+
+    /// Sets the encoded `FileDescriptorProto` of the proto file declaring
+    /// the service, which [`crate::reflection`] hands to tools. Generated
+    /// stubs set the `<FILE>_FILE_DESCRIPTOR` generated with them.
+    pub fn file_descriptor(mut self, file: &'static [u8]) -> ServiceDesc {
+        self.file = Some(file);
+        self
+    }
+
+    /// Adds a method. The request is unmarshaled before `handler` runs, and
+    /// a request that does not unmarshal fails with
+    /// [`Code::InvalidArgument`].
+    pub fn method<Req, Resp, F, Fut>(self, name: &str, id: u32, handler: F) -> ServiceDesc
+    where
+        Req: Message + Send + 'static,
+        Resp: Message + Send + 'static,
+        F: Fn(Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Resp, Error>> + Send + 'static,
+    {
+        let handler = Arc::new(move |data: Bytes| -> BoxFuture<_> {
+            let call = unmarshal::<Req>(data).map(&handler);
+            Box::pin(async move { Ok(call?.await?.marshal_symphony()) })
+        });
+        self.add(name, id, Handler::Unary(handler))
+    }
+
+    /// Adds a server-streaming method, whose handler answers with a stream
+    /// of responses. An error from the stream ends it and is sent to the
+    /// caller.
+    pub fn server_streaming<Req, Resp, S, F, Fut>(
+        self,
+        name: &str,
+        id: u32,
+        handler: F,
+    ) -> ServiceDesc
+    where
+        Req: Message + Send + 'static,
+        Resp: Message + Send + 'static,
+        S: Stream<Item = Result<Resp, Error>> + Send + 'static,
+        F: Fn(Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<S, Error>> + Send + 'static,
+    {
+        let handler = Arc::new(move |data: Bytes| -> BoxFuture<_> {
+            let call = unmarshal::<Req>(data).map(&handler);
+            Box::pin(async move {
+                let responses = call?.await?;
+                Ok(Box::pin(Marshal(Box::pin(responses))) as ResponseStream)
+            })
+        });
+        self.add(name, id, Handler::ServerStreaming(handler))
+    }
+
+    /// Adds a client-streaming method, whose handler reads a stream of
+    /// requests and answers once. A request that does not unmarshal comes
+    /// out of the stream as a [`Code::InvalidArgument`] error.
+    pub fn client_streaming<Req, Resp, F, Fut>(self, name: &str, id: u32, handler: F) -> ServiceDesc
+    where
+        Req: Message + Send + 'static,
+        Resp: Message + Send + 'static,
+        F: Fn(BoxStream<Result<Req, Error>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Resp, Error>> + Send + 'static,
+    {
+        let handler = Arc::new(move |requests: RequestStream| -> BoxFuture<_> {
+            let call = handler(Box::pin(Unmarshal::<Req>(requests, PhantomData)));
+            Box::pin(async move { Ok(call.await?.marshal_symphony()) })
+        });
+        self.add(name, id, Handler::ClientStreaming(handler))
+    }
+
+    /// Adds a bidi-streaming method, whose handler reads a stream of
+    /// requests and answers with a stream of responses, which may end
+    /// before or after the requests do.
+    pub fn bidi_streaming<Req, Resp, S, F, Fut>(
+        self,
+        name: &str,
+        id: u32,
+        handler: F,
+    ) -> ServiceDesc
+    where
+        Req: Message + Send + 'static,
+        Resp: Message + Send + 'static,
+        S: Stream<Item = Result<Resp, Error>> + Send + 'static,
+        F: Fn(BoxStream<Result<Req, Error>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<S, Error>> + Send + 'static,
+    {
+        let handler = Arc::new(move |requests: RequestStream| -> BoxFuture<_> {
+            let call = handler(Box::pin(Unmarshal::<Req>(requests, PhantomData)));
+            Box::pin(async move {
+                let responses = call.await?;
+                Ok(Box::pin(Marshal(Box::pin(responses))) as ResponseStream)
+            })
+        });
+        self.add(name, id, Handler::BidiStreaming(handler))
+    }
+
+    fn add(mut self, name: &str, id: u32, handler: Handler) -> ServiceDesc {
+        self.methods.insert(
+            id,
+            MethodDesc {
+                name: name.to_string(),
+                handler,
+            },
+        );
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The encoded `FileDescriptorProto` set by
+    /// [`ServiceDesc::file_descriptor`].
+    pub fn file(&self) -> Option<&'static [u8]> {
+        self.file
+    }
+
+    /// The IDs of the service's methods, in order.
+    pub fn method_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.methods.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// The name of method `id`, if the service has it.
+    pub fn method_name(&self, id: u32) -> Option<&str> {
+        self.methods.get(&id).map(|method| method.name.as_str())
+    }
+
+    /// How method `id` exchanges messages, if the service has it.
+    pub fn method_kind(&self, id: u32) -> Option<MethodKind> {
+        Some(match self.methods.get(&id)?.handler {
+            Handler::Unary(_) => MethodKind::Unary,
+            Handler::ServerStreaming(_) => MethodKind::ServerStreaming,
+            Handler::ClientStreaming(_) => MethodKind::ClientStreaming,
+            Handler::BidiStreaming(_) => MethodKind::BidiStreaming,
+        })
+    }
+
+    /// Starts unary method `id` on a marshaled request, returning the
+    /// future of its marshaled response, or None if the service has no such
+    /// unary method. Bytes and string fields of the request may share
+    /// `request`; see [`crate::symphony`].
+    pub fn handle(
+        &self,
+        id: u32,
+        request: impl Into<Bytes>,
+    ) -> Option<BoxFuture<Result<Vec<u8>, Error>>> {
+        match &self.methods.get(&id)?.handler {
+            Handler::Unary(handler) => Some(handler(request.into())),
+            _ => None,
+        }
+    }
+
+    /// Starts server-streaming method `id` on a marshaled request, like
+    /// [`ServiceDesc::handle`].
+    pub fn handle_server_streaming(
+        &self,
+        id: u32,
+        request: impl Into<Bytes>,
+    ) -> Option<BoxFuture<Result<ResponseStream, Error>>> {
+        match &self.methods.get(&id)?.handler {
+            Handler::ServerStreaming(handler) => Some(handler(request.into())),
+            _ => None,
+        }
+    }
+
+    /// Starts client-streaming method `id` on its stream of marshaled
+    /// requests, like [`ServiceDesc::handle`].
+    pub fn handle_client_streaming(
+        &self,
+        id: u32,
+        requests: RequestStream,
+    ) -> Option<BoxFuture<Result<Vec<u8>, Error>>> {
+        match &self.methods.get(&id)?.handler {
+            Handler::ClientStreaming(handler) => Some(handler(requests)),
+            _ => None,
+        }
+    }
+
+    /// Starts bidi-streaming method `id` on its stream of marshaled
+    /// requests, like [`ServiceDesc::handle`].
+    pub fn handle_bidi_streaming(
+        &self,
+        id: u32,
+        requests: RequestStream,
+    ) -> Option<BoxFuture<Result<ResponseStream, Error>>> {
+        match &self.methods.get(&id)?.handler {
+            Handler::BidiStreaming(handler) => Some(handler(requests)),
+            _ => None,
+        }
+    }
+}
+
+/// Unmarshals a request, failing with [`Code::InvalidArgument`].
+fn unmarshal<Req: Message>(data: Bytes) -> Result<Req, Error> {
+    Req::unmarshal_symphony_bytes(data).map_err(|e| {
+        let message = format!("failed to unmarshal request: {}", e);
+        Status::new(Code::InvalidArgument, message).into()
+    })
+}
+
+/// Marshals the messages of a stream.
+struct Marshal<T>(BoxStream<Result<T, Error>>);
+
+impl<T: Message> Stream for Marshal<T> {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0
+            .as_mut()
+            .poll_next(cx)
+            .map(|item| item.map(|item| Ok(item?.marshal_symphony())))
+    }
+}
+
+/// Unmarshals the requests of a stream.
+struct Unmarshal<T>(RequestStream, PhantomData<fn() -> T>);
+
+impl<T: Message> Stream for Unmarshal<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0
+            .as_mut()
+            .poll_next(cx)
+            .map(|item| item.map(|item| unmarshal(buffer::share(item?))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+
+    #[test]
+    fn handles_without_a_runtime() {
+        let desc = ServiceDesc::new("EchoService", 1).method("Echo", 1, |req: Text| async move {
+            Ok::<_, Error>(Text(req.0.to_uppercase()))
+        });
+        assert!(desc.handle(2, Bytes::new()).is_none());
+        assert_eq!(desc.method_name(1), Some("Echo"));
+
+        let call = desc.handle(1, Text::new("hi").marshal_symphony()).unwrap();
+        let resp = poll_ready(call).unwrap();
+        assert_eq!(Text::unmarshal_symphony(&resp).unwrap(), Text::new("HI"));
+
+        match poll_ready(desc.handle(1, Bytes::new()).unwrap()) {
+            Err(Error::Status(status)) => assert_eq!(status.code(), Code::InvalidArgument),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    /// Polls a future that completes without waiting.
+    fn poll_ready<T>(mut future: BoxFuture<T>) -> T {
+        use std::task::{Context, Poll, Waker};
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future is pending"),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! A UDP server sharded over worker threads: each worker binds a socket of
+//! its own to the server's port with `SO_REUSEPORT`, so that the kernel
+//! spreads arriving datagrams across them by flow, and serves what its
+//! socket receives on a runtime of its own, handlers included. With
+//! [`Sharding::pin_cpus`], worker `i` runs on the `i`-th CPU the process
+//! may use, round robin.
+//!
+//! All the datagrams of a client land on the same worker, which keeps the
+//! reassembly, reliability and session state of that client; settings of
+//! the server apply to every worker alike. [`Workers::stats`] reports
+//! what each worker served.
+
+use std::io;
+use std::net::SocketAddrV4;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::server::Server;
+use crate::transport::{Transport, UdpTransport};
+use crate::{BatchStats, BoxFuture, Error, ReliabilityStats};
+
+/// How a server spreads its port over workers; see
+/// [`crate::Server::bind_sharded`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sharding {
+    pub workers: usize,
+    /// Whether each worker stays on a CPU of its own.
+    pub pin_cpus: bool,
+}
+
+impl Default for Sharding {
+    fn default() -> Self {
+        Sharding {
+            workers: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            pin_cpus: false,
+        }
+    }
+}
+
+/// What one worker of a sharded server served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WorkerStats {
+    /// The CPU the worker is pinned to.
+    pub cpu: Option<usize>,
+    /// Requests received, streaming frames included.
+    pub requests: u64,
+    pub batches: BatchStats,
+    pub reliability: ReliabilityStats,
+}
+
+/// The workers of a sharded server, for their stats.
+#[derive(Clone)]
+pub struct Workers(Arc<[Worker]>);
+
+struct Worker {
+    udp: Arc<UdpTransport>,
+    cpu: Option<usize>,
+    requests: AtomicU64,
+    // Hands the worker its serving loop, once.
+    start: Mutex<Option<oneshot::Sender<BoxFuture<()>>>>,
+}
+
+impl Workers {
+    /// Starts `sharding.workers` workers, each with a socket bound to
+    /// `addr`, the first one picking the port if `addr` has none.
+    pub(crate) async fn bind(
+        mut addr: SocketAddrV4,
+        towards: SocketAddrV4,
+        sharding: Sharding,
+    ) -> Result<Workers, Error> {
+        let cpus = if sharding.pin_cpus {
+            cpus()
+        } else {
+            Vec::new()
+        };
+        let mut workers = Vec::new();
+        for i in 0..sharding.workers.max(1) {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_reuse_port(true)?;
+            socket.bind(&addr.into())?;
+            let socket = std::net::UdpSocket::from(socket);
+            if addr.port() == 0 {
+                addr.set_port(socket.local_addr()?.port());
+            }
+            let cpu = (!cpus.is_empty()).then(|| cpus[i % cpus.len()]);
+            let (ready, bound) = oneshot::channel();
+            let (start, serve) = oneshot::channel();
+            thread::Builder::new()
+                .name(format!("arpc-worker-{}", i))
+                .spawn(move || run(socket, towards, cpu, ready, serve))?;
+            let udp = bound
+                .await
+                .map_err(|_| io::Error::other("worker failed to start"))??;
+            workers.push(Worker {
+                udp,
+                cpu,
+                requests: AtomicU64::new(0),
+                start: Mutex::new(Some(start)),
+            });
+        }
+        Ok(Workers(workers.into()))
+    }
+
+    /// The transports of the workers.
+    pub(crate) fn transports(&self) -> Vec<Arc<UdpTransport>> {
+        self.0.iter().map(|worker| worker.udp.clone()).collect()
+    }
+
+    /// Serves with `server` on every worker until receiving fails on one.
+    pub(crate) async fn serve(&self, server: Arc<Server>) -> Result<(), Error> {
+        let (done, mut failed) = mpsc::channel(self.0.len());
+        for i in 0..self.0.len() {
+            let start = self.0[i].start.lock().unwrap().take();
+            let (workers, server, done) = (self.clone(), server.clone(), done.clone());
+            let serve: BoxFuture<()> = Box::pin(async move {
+                let worker = &workers.0[i];
+                let transport: Arc<dyn Transport> = worker.udp.clone();
+                let served = server.serve_on(&transport, Some(&worker.requests)).await;
+                let _ = done.send(served).await;
+            });
+            if start.is_none_or(|start| start.send(serve).is_err()) {
+                return Err(io::Error::other("worker already serving or gone").into());
+            }
+        }
+        drop(done);
+        failed
+            .recv()
+            .await
+            .unwrap_or_else(|| Err(io::Error::other("workers stopped").into()))
+    }
+
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        self.0
+            .iter()
+            .map(|worker| WorkerStats {
+                cpu: worker.cpu,
+                requests: worker.requests.load(Ordering::Relaxed),
+                batches: worker.udp.batch_stats(),
+                reliability: worker.udp.reliability_stats(),
+            })
+            .collect()
+    }
+}
+
+/// The body of a worker thread: takes `socket` over on a runtime of its
+/// own, then serves with whatever `serve` hands it.
+fn run(
+    socket: std::net::UdpSocket,
+    towards: SocketAddrV4,
+    cpu: Option<usize>,
+    ready: oneshot::Sender<io::Result<Arc<UdpTransport>>>,
+    serve: oneshot::Receiver<BoxFuture<()>>,
+) {
+    if let Some(cpu) = cpu {
+        if let Err(e) = pin(cpu) {
+            log::warn!("failed to pin worker to CPU {}: {}", cpu, e);
+        }
+    }
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+    runtime.block_on(async move {
+        let udp = UdpTransport::from_std(socket, towards).map(Arc::new);
+        if ready.send(udp).is_err() {
+            return;
+        }
+        // Dropped without serving when the server is.
+        if let Ok(serve) = serve.await {
+            serve.await;
+        }
+    });
+}
+
+/// The CPUs the process may run on.
+#[cfg(target_os = "linux")]
+fn cpus() -> Vec<usize> {
+    // SAFETY: the set is plain data, zeroed then filled by the kernel.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) != 0 {
+            return Vec::new();
+        }
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpus() -> Vec<usize> {
+    (0..thread::available_parallelism().map_or(1, NonZeroUsize::get)).collect()
+}
+
+/// Keeps the calling thread on `cpu`.
+#[cfg(target_os = "linux")]
+fn pin(cpu: usize) -> io::Result<()> {
+    // SAFETY: the set is plain data, zeroed then given one CPU below
+    // CPU_SETSIZE, and only read by the kernel.
+    let pinned = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set)
+    };
+    if pinned != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin(_cpu: usize) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, ServiceDesc};
+
+    #[tokio::test]
+    async fn shards_clients_over_workers() {
+        let sharding = Sharding {
+            workers: 4,
+            pin_cpus: true,
+        };
+        let mut server = Server::bind_sharded("127.0.0.1:0", sharding).await.unwrap();
+        server.register(ServiceDesc::new("EchoService", 1).method(
+            "Echo",
+            1,
+            |req: Text| async move { Ok::<_, Error>(Text(req.0.to_uppercase())) },
+        ));
+        let addr = server.local_addr().unwrap();
+        let workers = server.workers().unwrap();
+        tokio::spawn(server.serve());
+
+        let clients = 32;
+        for i in 0..clients {
+            let client = Client::connect(addr).await.unwrap();
+            client.register_service("EchoService", 1, &[("Echo", 1)]);
+            let resp: Text = client
+                .call("EchoService", "Echo", &Text::new(&format!("hi {}", i)))
+                .await
+                .unwrap();
+            assert_eq!(resp, Text::new(&format!("HI {}", i)));
+        }
+        let stats = workers.stats();
+        assert_eq!(stats.len(), 4);
+        assert_eq!(stats.iter().map(|s| s.requests).sum::<u64>(), clients);
+        // The kernel hashes each client's flow to a worker; 32 flows all
+        // on one of four is out of the question.
+        assert!(stats.iter().filter(|s| s.requests > 0).count() > 1);
+        assert!(stats.iter().all(|s| s.cpu.is_some()));
+    }
+}
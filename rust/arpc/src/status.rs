@@ -0,0 +1,269 @@
+//! The status of a failed RPC: a canonical code, a message, and structured
+//! details, like `google.rpc.Status`.
+//!
+//! A status goes back in an error packet. The Go implementation knows two
+//! kinds of failure, by packet type: an Error packet for failures the
+//! server handled (`RPCFailError`), and an Unknown packet for unexpected
+//! ones (`RPCUnknownError`). [`Code::Unknown`], [`Code::Internal`] and
+//! [`Code::DataLoss`] are sent in Unknown packets, and every other code in
+//! Error packets, so Go peers tell them apart as before.
+//!
+//! The packet's message is the status message. When the code is not the
+//! one its packet type implies, [`Code::Aborted`] for an Error packet and
+//! [`Code::Unknown`] for an Unknown one, or the status has details, a NUL
+//! and a trailer follow: `[code(1B)]` and then
+//! `[type URL length(2B)][type URL][value length(2B)][value]` per detail.
+
+use std::fmt;
+
+use symphony_wire::PacketType;
+
+/// The canonical status codes, numbered as in gRPC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    Ok = 0,
+    Cancelled = 1,
+    /// An unexpected failure; what a Go server reports as `RPCUnknownError`.
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    /// The call was refused or abandoned; what a Go server reports as
+    /// `RPCFailError`, e.g. when an element rejects the request.
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+    Unauthenticated = 16,
+}
+
+impl Code {
+    /// The code numbered `value`, or [`Code::Unknown`] for numbers past the
+    /// canonical ones.
+    pub fn from_u8(value: u8) -> Code {
+        const CODES: [Code; 17] = [
+            Code::Ok,
+            Code::Cancelled,
+            Code::Unknown,
+            Code::InvalidArgument,
+            Code::DeadlineExceeded,
+            Code::NotFound,
+            Code::AlreadyExists,
+            Code::PermissionDenied,
+            Code::ResourceExhausted,
+            Code::FailedPrecondition,
+            Code::Aborted,
+            Code::OutOfRange,
+            Code::Unimplemented,
+            Code::Internal,
+            Code::Unavailable,
+            Code::DataLoss,
+            Code::Unauthenticated,
+        ];
+        CODES.get(value as usize).copied().unwrap_or(Code::Unknown)
+    }
+
+    /// The packet type that reports a failure with this code.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn packet_type(self) -> PacketType {
+        match self {
+            Code::Unknown | Code::Internal | Code::DataLoss => PacketType::Unknown,
+            _ => PacketType::Error,
+        }
+    }
+}
+
+/// A typed detail of a status, like a `google.protobuf.Any`: a type URL
+/// naming the message, and the message encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detail {
+    pub type_url: String,
+    pub value: Vec<u8>,
+}
+
+/// Why an RPC failed; see the [module docs](self).
+///
+/// Handlers return it, as [`crate::Error::Status`], to choose the code the
+/// caller sees; clients return the status the server sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    code: Code,
+    message: String,
+    details: Vec<Detail>,
+}
+
+impl Status {
+    pub fn new(code: Code, message: impl Into<String>) -> Status {
+        Status {
+            code,
+            message: message.into(),
+            details: Vec::new(),
+        }
+    }
+
+    /// Adds a detail, `value` encoded as the message `type_url` names.
+    pub fn with_detail(mut self, type_url: &str, value: &[u8]) -> Status {
+        self.details.push(Detail {
+            type_url: type_url.to_string(),
+            value: value.to_vec(),
+        });
+        self
+    }
+
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn details(&self) -> &[Detail] {
+        &self.details
+    }
+
+    /// The packet type that reports this status.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn packet_type(&self) -> PacketType {
+        self.code.packet_type()
+    }
+
+    /// The message of the error packet reporting this status, at most
+    /// `max` bytes long. Details that do not fit are left out, and then the
+    /// message is cut short.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn encode(&self, max: usize) -> Vec<u8> {
+        // A NUL would end the message early.
+        let mut buf = self.message.replace('\0', "\u{fffd}").into_bytes();
+        let implied = implied_code(self.packet_type());
+        if self.code == implied && self.details.is_empty() {
+            buf.truncate(max);
+            return buf;
+        }
+        let mut trailer = vec![0, self.code as u8];
+        for detail in &self.details {
+            let mut entry = Vec::new();
+            put_field(&mut entry, detail.type_url.as_bytes());
+            put_field(&mut entry, &detail.value);
+            if buf.len() + trailer.len() + entry.len() > max {
+                break;
+            }
+            trailer.extend_from_slice(&entry);
+        }
+        if self.code == implied && trailer.len() == 2 {
+            trailer.clear();
+        }
+        buf.truncate(max.saturating_sub(trailer.len()));
+        buf.extend_from_slice(&trailer);
+        buf
+    }
+
+    /// The status an error packet of type `kind` reports. A message without
+    /// a trailer, or whose trailer does not parse, is all status message.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn parse(kind: PacketType, message: &[u8]) -> Status {
+        let plain = || Status::new(implied_code(kind), String::from_utf8_lossy(message));
+        let Some(nul) = message.iter().position(|&b| b == 0) else {
+            return plain();
+        };
+        let Some((&code, mut rest)) = message[nul + 1..].split_first() else {
+            return plain();
+        };
+        let mut status = Status::new(
+            Code::from_u8(code),
+            String::from_utf8_lossy(&message[..nul]),
+        );
+        while !rest.is_empty() {
+            let Some((type_url, tail)) = field(rest) else {
+                return plain();
+            };
+            let Some((value, tail)) = field(tail) else {
+                return plain();
+            };
+            status = status.with_detail(&String::from_utf8_lossy(type_url), value);
+            rest = tail;
+        }
+        status
+    }
+}
+
+/// The code an error packet of type `kind` implies without a trailer.
+fn implied_code(kind: PacketType) -> Code {
+    match kind {
+        PacketType::Error => Code::Aborted,
+        _ => Code::Unknown,
+    }
+}
+
+fn put_field(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+}
+
+/// Splits a `[length(2B)][bytes]` field off the front of `buf`.
+fn field(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u16::from_le_bytes(buf.get(..2)?.try_into().unwrap()) as usize;
+    let value = buf.get(2..2 + len)?;
+    Some((value, &buf[2 + len..]))
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for Status {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_what_the_packet_type_does_not_say() {
+        // Plain failures read the same as the Go implementation's.
+        let fail = Status::new(Code::Aborted, "acl");
+        assert_eq!(fail.packet_type(), PacketType::Error);
+        assert_eq!(fail.encode(100), b"acl");
+        assert_eq!(Status::parse(PacketType::Error, b"acl"), fail);
+        let unknown = Status::parse(PacketType::Unknown, b"boom");
+        assert_eq!(unknown.code(), Code::Unknown);
+
+        let status = Status::new(Code::NotFound, "no key")
+            .with_detail("type.example/Key", &[1, 2])
+            .with_detail("type.example/Hint", b"try again");
+        assert_eq!(status.packet_type(), PacketType::Error);
+        let encoded = status.encode(100);
+        assert_eq!(&encoded[..8], b"no key\0\x05");
+        assert_eq!(Status::parse(PacketType::Error, &encoded), status);
+
+        // Details that do not fit are left out before the message is cut.
+        let encoded = status.encode(30);
+        assert_eq!(
+            Status::parse(PacketType::Error, &encoded),
+            Status::new(Code::NotFound, "no key").with_detail("type.example/Key", &[1, 2])
+        );
+        let encoded = status.encode(5);
+        assert_eq!(
+            Status::parse(PacketType::Error, &encoded),
+            Status::new(Code::NotFound, "no ")
+        );
+
+        assert_eq!(
+            Status::new(Code::Internal, "bug").packet_type(),
+            PacketType::Unknown
+        );
+        assert_eq!(Code::from_u8(200), Code::Unknown);
+        // A trailer that does not parse is part of the message.
+        assert_eq!(
+            Status::parse(PacketType::Error, b"a\0\x05\x09\x00b").message(),
+            "a\0\x05\t\0b"
+        );
+    }
+}
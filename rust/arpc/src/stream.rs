@@ -0,0 +1,386 @@
+//! Streaming RPCs: a sequence of messages under one RPC ID.
+//!
+//! Each message of a stream, a frame, is sent as a message of its own
+//! under a fresh RPC ID, so that fragmentation, reliability and encryption
+//! handle it like any other. Its extension block ties it to the stream: the
+//! stream's RPC ID, the frame's position, and whether it ends the stream.
+//! The receiver puts frames back in order, since datagrams may overtake
+//! each other. The frame ending a stream carries no message, only the
+//! Symphony header when it goes to a server, which dispatches on it; a
+//! stream that fails ends with an error packet under the stream's RPC ID
+//! instead.
+//!
+//! The two directions of a bidi stream are streams of their own under the
+//! same RPC ID, each with its own frame sequence and end. With flow-control
+//! windows, a reader grants the sender credit as it takes frames; see
+//! [`crate::window`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::net::SocketAddrV4;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+use tokio::time::Sleep;
+
+use crate::window::{WindowUpdate, Windows};
+use crate::{buffer, BoxFuture, Code, Error, Message, Status};
+
+/// The most frames a stream holds while waiting for an earlier one.
+const MAX_REORDERED: usize = 1024;
+
+/// How long frames of a closed stream are told apart from those of a new
+/// one, and dropped.
+const CLOSED_LINGER: Duration = Duration::from_secs(30);
+
+type Frames = mpsc::UnboundedReceiver<Result<Vec<u8>, Error>>;
+
+/// Hooks pacing the frames of streams, e.g. to bound what a slow reader
+/// has queued. Streams are named by their RPC ID, and frames by their
+/// position in their direction of the stream.
+pub trait FlowControl: Send + Sync + 'static {
+    /// Resolves once frame `seq` of `stream` may be sent. The frame ending
+    /// a stream does not wait.
+    fn ready(&self, stream: u64, seq: u32) -> BoxFuture<()> {
+        let _ = (stream, seq);
+        Box::pin(std::future::ready(()))
+    }
+
+    /// Called as the application takes frame `seq` of `stream`.
+    fn consumed(&self, stream: u64, seq: u32) {
+        let _ = (stream, seq);
+    }
+}
+
+/// The streams being received, by `K`.
+pub(crate) struct Streams<K> {
+    table: Mutex<Table<K>>,
+}
+
+struct Table<K> {
+    inbound: HashMap<K, Inbound>,
+    // Streams that ended or were dropped, by when.
+    closed: HashMap<K, Instant>,
+}
+
+struct Inbound {
+    tx: mpsc::UnboundedSender<Result<Vec<u8>, Error>>,
+    // The next frame to hand over, and those that arrived ahead of it; the
+    // end of the stream is None.
+    next: u32,
+    ahead: BTreeMap<u32, Option<Vec<u8>>>,
+}
+
+impl<K: Hash + Eq + Copy> Streams<K> {
+    pub fn new() -> Streams<K> {
+        Streams {
+            table: Mutex::new(Table {
+                inbound: HashMap::new(),
+                closed: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Starts receiving the stream `key`, whose messages arrive in order on
+    /// the returned receiver.
+    pub fn open(&self, key: K) -> Frames {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let inbound = Inbound {
+            tx,
+            next: 0,
+            ahead: BTreeMap::new(),
+        };
+        let mut table = self.table.lock().unwrap();
+        let now = Instant::now();
+        table
+            .closed
+            .retain(|_, closed| now.duration_since(*closed) < CLOSED_LINGER);
+        table.closed.remove(&key);
+        table.inbound.insert(key, inbound);
+        rx
+    }
+
+    /// Whether stream `key` is open or recently closed.
+    pub fn knows(&self, key: K) -> bool {
+        let table = self.table.lock().unwrap();
+        table.inbound.contains_key(&key) || table.closed.contains_key(&key)
+    }
+
+    /// Adds frame `seq` of stream `key`, its message or None for the end.
+    /// Returns false if no such stream is open; frames of recently closed
+    /// streams are dropped.
+    pub fn deliver(&self, key: K, seq: u32, message: Option<Vec<u8>>) -> bool {
+        let mut table = self.table.lock().unwrap();
+        let Some(inbound) = table.inbound.get_mut(&key) else {
+            return table.closed.contains_key(&key);
+        };
+        if seq < inbound.next {
+            return true;
+        }
+        if inbound.ahead.len() >= MAX_REORDERED {
+            let _ = inbound.tx.send(Err(Status::new(
+                Code::ResourceExhausted,
+                "too many stream frames out of order",
+            )
+            .into()));
+            table.close(key);
+            return true;
+        }
+        inbound.ahead.insert(seq, message);
+        while let Some(message) = inbound.ahead.remove(&inbound.next) {
+            inbound.next += 1;
+            match message {
+                // The receiver may have been dropped; the stream is closed
+                // with it.
+                Some(message) => {
+                    let _ = inbound.tx.send(Ok(message));
+                }
+                None => {
+                    table.close(key);
+                    break;
+                }
+            }
+        }
+        true
+    }
+
+    /// Ends stream `key` with `error`, handing it back if no such stream is
+    /// open.
+    pub fn fail(&self, key: K, error: Error) -> Result<(), Error> {
+        match self.table.lock().unwrap().close(key) {
+            Some(inbound) => {
+                let _ = inbound.tx.send(Err(error));
+                Ok(())
+            }
+            None => Err(error),
+        }
+    }
+
+    /// Stops receiving stream `key`.
+    pub fn close(&self, key: K) {
+        self.table.lock().unwrap().close(key);
+    }
+}
+
+impl<K: Hash + Eq + Copy> Table<K> {
+    fn close(&mut self, key: K) -> Option<Inbound> {
+        let inbound = self.inbound.remove(&key)?;
+        self.closed.insert(key, Instant::now());
+        Some(inbound)
+    }
+}
+
+/// RPC IDs of the frames a server sends; random, so that they do not
+/// collide with those of other streams to the same client.
+pub(crate) struct FrameIds(AtomicU64);
+
+impl FrameIds {
+    pub fn new() -> FrameIds {
+        let mut seed = [0; 8];
+        if let Err(e) = getrandom::getrandom(&mut seed) {
+            log::warn!("no random seed for frame IDs: {}", e);
+        }
+        FrameIds(AtomicU64::new(u64::from_le_bytes(seed)))
+    }
+
+    pub fn next(&self) -> u64 {
+        match self.0.fetch_add(1, Ordering::Relaxed) {
+            0 => self.next(),
+            id => id,
+        }
+    }
+}
+
+/// The messages of a stream as they arrive, still marshaled.
+pub(crate) struct Inflow {
+    frames: Frames,
+    done: bool,
+    close: Option<Box<dyn FnOnce() + Send + Sync>>,
+    flow: Option<Flow>,
+    window: Option<Window>,
+}
+
+/// The flow control told of the frames taken from a stream.
+struct Flow {
+    control: Arc<dyn FlowControl>,
+    stream: u64,
+    seq: u32,
+}
+
+/// The window of a stream, whose updates `send` sends to `peer`.
+struct Window {
+    windows: Arc<Windows>,
+    peer: SocketAddrV4,
+    stream: u64,
+    send: Box<dyn Fn(WindowUpdate) + Send + Sync>,
+}
+
+impl Inflow {
+    /// `close` runs when the stream is dropped.
+    pub fn new(frames: Frames, close: impl FnOnce() + Send + Sync + 'static) -> Inflow {
+        Inflow {
+            frames,
+            done: false,
+            close: Some(Box::new(close)),
+            flow: None,
+            window: None,
+        }
+    }
+
+    /// Tells `control`, if any, of the frames taken from `stream`.
+    pub fn with_flow_control(
+        mut self,
+        stream: u64,
+        control: Option<Arc<dyn FlowControl>>,
+    ) -> Inflow {
+        self.flow = control.map(|control| Flow {
+            control,
+            stream,
+            seq: 0,
+        });
+        self
+    }
+
+    /// Grants the sender of `stream`, which `windows` opened for `peer`,
+    /// credit as frames are taken, sending the updates with `send`. The
+    /// sender is released from the window if the stream is dropped before
+    /// it ends.
+    pub fn with_window(
+        mut self,
+        windows: Option<Arc<Windows>>,
+        peer: SocketAddrV4,
+        stream: u64,
+        send: impl Fn(WindowUpdate) + Send + Sync + 'static,
+    ) -> Inflow {
+        self.window = windows.map(|windows| Window {
+            windows,
+            peer,
+            stream,
+            send: Box::new(send),
+        });
+        self
+    }
+}
+
+impl Stream for Inflow {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let frame = ready!(self.frames.poll_recv(cx));
+        self.done = !matches!(frame, Some(Ok(_)));
+        if let (false, Some(flow)) = (self.done, &mut self.flow) {
+            flow.control.consumed(flow.stream, flow.seq);
+            flow.seq += 1;
+        }
+        if let (Some(Ok(message)), Some(window)) = (&frame, &self.window) {
+            let updates = window
+                .windows
+                .consumed(window.peer, window.stream, message.len());
+            updates.into_iter().for_each(&window.send);
+        }
+        Poll::Ready(frame)
+    }
+}
+
+impl Drop for Inflow {
+    fn drop(&mut self) {
+        if let Some(close) = self.close.take() {
+            close();
+        }
+        if let Some(window) = self.window.take() {
+            let mut updates = window.windows.close(window.peer, window.stream);
+            if !self.done {
+                updates.push(WindowUpdate::release(window.stream));
+            }
+            updates.into_iter().for_each(window.send);
+        }
+    }
+}
+
+/// The messages of a stream as they arrive, decoded as `T`.
+///
+/// The stream ends after the last message, or after the error that failed
+/// it. Dropping it stops receiving the rest.
+pub struct Streaming<T> {
+    inflow: Inflow,
+    deadline: Option<Pin<Box<Sleep>>>,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T: Message> Streaming<T> {
+    pub(crate) fn new(inflow: Inflow, deadline: Option<Instant>) -> Streaming<T> {
+        Streaming {
+            inflow,
+            deadline: deadline.map(|deadline| Box::pin(tokio::time::sleep_until(deadline.into()))),
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<T: Message> Stream for Streaming<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let frame = match Pin::new(&mut self.inflow).poll_next(cx) {
+            Poll::Ready(frame) => frame,
+            Poll::Pending => {
+                let expired = self
+                    .deadline
+                    .as_mut()
+                    .is_some_and(|deadline| deadline.as_mut().poll(cx).is_ready());
+                if !expired {
+                    return Poll::Pending;
+                }
+                self.inflow.done = true;
+                Some(Err(Error::TimedOut))
+            }
+        };
+        Poll::Ready(frame.map(|frame| Ok(T::unmarshal_symphony_bytes(buffer::share(frame?))?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reorders_frames_until_the_end() {
+        let streams = Streams::new();
+        let mut rx = streams.open(1);
+        assert!(!streams.deliver(2, 0, None));
+
+        assert!(streams.deliver(1, 1, Some(b"b".to_vec())));
+        assert!(rx.try_recv().is_err());
+        assert!(streams.deliver(1, 0, Some(b"a".to_vec())));
+        // A duplicate of a frame handed over is dropped.
+        assert!(streams.deliver(1, 0, Some(b"a".to_vec())));
+        assert!(streams.deliver(1, 3, None));
+        assert!(streams.deliver(1, 2, Some(b"c".to_vec())));
+        for expected in [b"a", b"b", b"c"] {
+            assert_eq!(rx.try_recv().unwrap().unwrap(), expected);
+        }
+        assert!(matches!(
+            rx.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        ));
+        // Frames of a closed stream are told apart from a new one's.
+        assert!(streams.knows(1));
+        assert!(streams.deliver(1, 4, None));
+        assert!(!streams.knows(2));
+
+        let mut rx = streams.open(3);
+        assert!(streams.fail(3, Error::Closed).is_ok());
+        assert!(matches!(rx.try_recv(), Ok(Err(Error::Closed))));
+        assert!(streams.fail(3, Error::Closed).is_err());
+    }
+}
@@ -0,0 +1,657 @@
+//! Building blocks of the Symphony message format, for code that
+//! implements [`Message`](crate::Message) by hand or through
+//! `#[derive(SymphonyMessage)]`.
+//!
+//! The layout matches `protoc-gen-symphony`:
+//!
+//! ```text
+//! [version(1B)][offset to private(4B)][service id(4B)][method id(4B)]
+//! [public table][public payload]
+//! [version(1B)][private table][private payload]
+//! ```
+//!
+//! Each field owns a table slot. Fixed-length values (bool, 32 and 64-bit
+//! integers and floats) are stored in the slot; everything else is stored
+//! in the payload, and the slot holds its 32-bit offset. Offsets in the
+//! public segment count from the start of the message, those in the private
+//! segment from its version byte. Payloads are `[len][bytes]` for strings
+//! and bytes, `[count][elements]` for repeated fixed-length values,
+//! `[count]([len][bytes])*` for repeated strings and bytes, and
+//! `[size][message]` (repeated: `[count]([size][message])*`) for nested
+//! messages, which carry their own header. All integers are little-endian.
+//!
+//! Bytes and string fields may be [`Bytes`] and [`ByteString`] instead of
+//! `Vec<u8>` and `String`. Read by [`MessageReader::shared`], as
+//! [`Message::unmarshal_symphony_bytes`] does, they share the buffer the
+//! message arrived in rather than copying out of it.
+
+use bytes::Bytes;
+pub use bytestring::ByteString;
+use symphony_wire::{MESSAGE_HEADER_LEN, SYMPHONY_VERSION};
+
+use crate::{buffer, DecodeError, Message};
+
+/// A field type stored in a Symphony table slot.
+pub trait Value: Sized {
+    /// Bytes the value takes in the table.
+    const TABLE_LEN: usize;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize);
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError>;
+}
+
+/// A field holding nested messages: `Option<M>` or `Vec<M>`.
+///
+/// Kept apart from [`Value`] so that `Vec<M>` does not collide with the
+/// repeated scalars; the derive selects it with `#[symphony(message)]`.
+pub trait Nested: Sized {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize);
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError>;
+}
+
+/// Assembles a message segment by segment: the public one first, then the
+/// private one, in a buffer from the pool of [`crate::buffer_stats`].
+pub struct MessageWriter {
+    buf: Vec<u8>,
+}
+
+impl MessageWriter {
+    /// Starts a message whose public table takes `public_table_len` bytes.
+    pub fn new(public_table_len: usize) -> MessageWriter {
+        let mut buf = buffer::take(MESSAGE_HEADER_LEN + public_table_len);
+        buf.resize(MESSAGE_HEADER_LEN + public_table_len, 0);
+        buf[0] = SYMPHONY_VERSION;
+        MessageWriter { buf }
+    }
+
+    pub fn public(&mut self) -> SegmentWriter<'_> {
+        SegmentWriter {
+            buf: &mut self.buf,
+            table: MESSAGE_HEADER_LEN,
+            base: 0,
+        }
+    }
+
+    /// Ends the public segment and starts the private one, whose table
+    /// takes `table_len` bytes.
+    pub fn private(&mut self, table_len: usize) -> SegmentWriter<'_> {
+        let start = self.buf.len();
+        self.buf[1..5].copy_from_slice(&(start as u32).to_le_bytes());
+        self.buf.push(SYMPHONY_VERSION);
+        self.buf.resize(start + 1 + table_len, 0);
+        SegmentWriter {
+            buf: &mut self.buf,
+            table: start + 1,
+            base: start,
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Writes the fields of one segment; payloads are appended in field order.
+pub struct SegmentWriter<'a> {
+    buf: &'a mut Vec<u8>,
+    table: usize,
+    // Offsets are stored relative to this position.
+    base: usize,
+}
+
+impl SegmentWriter<'_> {
+    /// Stores a fixed-length value in its slot.
+    pub fn put_fixed(&mut self, slot: usize, bytes: &[u8]) {
+        let at = self.table + slot;
+        self.buf[at..at + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Points the slot at the end of the message and returns the buffer to
+    /// append the payload to.
+    pub fn payload(&mut self, slot: usize) -> &mut Vec<u8> {
+        let offset = (self.buf.len() - self.base) as u32;
+        self.put_fixed(slot, &offset.to_le_bytes());
+        self.buf
+    }
+}
+
+/// Reads a message produced by [`MessageWriter`] or the Go generator.
+pub struct MessageReader<'a> {
+    data: &'a [u8],
+    shared: Option<&'a Bytes>,
+    private: usize,
+}
+
+impl<'a> MessageReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<MessageReader<'a>, DecodeError> {
+        if data.len() < MESSAGE_HEADER_LEN {
+            return Err(DecodeError::new("too short"));
+        }
+        if data[0] != SYMPHONY_VERSION {
+            return Err(DecodeError::new("wrong public version"));
+        }
+        let private = u32_at(data, 1) as usize;
+        if data.get(private) != Some(&SYMPHONY_VERSION) {
+            return Err(DecodeError::new("missing private segment"));
+        }
+        Ok(MessageReader {
+            data,
+            shared: None,
+            private,
+        })
+    }
+
+    /// Reads the message in `data`, whose [`Bytes`] and [`ByteString`]
+    /// fields then share its buffer.
+    pub fn shared(data: &'a Bytes) -> Result<MessageReader<'a>, DecodeError> {
+        Ok(MessageReader {
+            shared: Some(data),
+            ..MessageReader::new(data)?
+        })
+    }
+
+    pub fn public(&self) -> SegmentReader<'a> {
+        SegmentReader {
+            data: self.data,
+            shared: self.shared,
+            table: MESSAGE_HEADER_LEN,
+            base: 0,
+        }
+    }
+
+    pub fn private(&self) -> SegmentReader<'a> {
+        SegmentReader {
+            data: self.data,
+            shared: self.shared,
+            table: self.private + 1,
+            base: self.private,
+        }
+    }
+}
+
+pub struct SegmentReader<'a> {
+    data: &'a [u8],
+    shared: Option<&'a Bytes>,
+    table: usize,
+    base: usize,
+}
+
+impl<'a> SegmentReader<'a> {
+    /// The value stored in a slot.
+    pub fn fixed<const N: usize>(&self, slot: usize) -> Result<[u8; N], DecodeError> {
+        let at = self.table + slot;
+        self.data
+            .get(at..at + N)
+            .map(|bytes| bytes.try_into().unwrap())
+            .ok_or_else(|| DecodeError::new("too short for field"))
+    }
+
+    /// The payload a slot points to, or None if the field was not written
+    /// (offset 0, or a table that ends before the slot).
+    pub fn payload(&self, slot: usize) -> Result<Option<Cursor<'a>>, DecodeError> {
+        let Ok(offset) = self.fixed::<4>(slot) else {
+            return Ok(None);
+        };
+        let offset = u32::from_le_bytes(offset) as usize;
+        if offset == 0 {
+            return Ok(None);
+        }
+        match self.data.get(self.base + offset..) {
+            Some(rest) => Ok(Some(Cursor { rest })),
+            None => Err(DecodeError::new("field offset out of bounds")),
+        }
+    }
+
+    /// `bytes`, read from this message, as [`Bytes`]: a view of the
+    /// message's buffer if it is [shared](MessageReader::shared), a copy
+    /// otherwise.
+    pub fn share(&self, bytes: &[u8]) -> Bytes {
+        match self.shared {
+            Some(shared) => shared.slice_ref(bytes),
+            None => Bytes::copy_from_slice(bytes),
+        }
+    }
+
+    /// Unmarshals a nested message read from this one, sharing its buffer
+    /// if this one does.
+    fn nested<M: Message>(&self, bytes: &[u8]) -> Result<M, DecodeError> {
+        match self.shared {
+            Some(_) => M::unmarshal_symphony_bytes(self.share(bytes)),
+            None => M::unmarshal_symphony(bytes),
+        }
+    }
+}
+
+/// Reads a payload front to back.
+pub struct Cursor<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32_at(bytes, 0))
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.rest.len() < len {
+            return Err(DecodeError::new("payload out of bounds"));
+        }
+        let (bytes, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Ok(bytes)
+    }
+
+    /// A `[len][bytes]` item.
+    pub fn sized(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn put_sized(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// A fixed-length value, stored in the table and in repeated payloads.
+pub trait Scalar: Copy {
+    const LEN: usize;
+
+    fn put(self, buf: &mut Vec<u8>);
+
+    /// Reads the value from the first `LEN` bytes.
+    fn get(bytes: &[u8]) -> Self;
+}
+
+macro_rules! scalar {
+    ($($ty:ty),*) => {$(
+        impl Scalar for $ty {
+            const LEN: usize = std::mem::size_of::<$ty>();
+
+            fn put(self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn get(bytes: &[u8]) -> Self {
+                <$ty>::from_le_bytes(bytes[..Self::LEN].try_into().unwrap())
+            }
+        }
+    )*};
+}
+
+scalar!(i32, u32, i64, u64, f32, f64);
+
+impl Scalar for bool {
+    const LEN: usize = 1;
+
+    fn put(self, buf: &mut Vec<u8>) {
+        buf.push(self as u8);
+    }
+
+    fn get(bytes: &[u8]) -> Self {
+        bytes[0] != 0
+    }
+}
+
+impl<T: Scalar> Value for T {
+    const TABLE_LEN: usize = T::LEN;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        let mut bytes = Vec::with_capacity(T::LEN);
+        self.put(&mut bytes);
+        segment.put_fixed(slot, &bytes);
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        let at = segment.table + slot;
+        segment
+            .data
+            .get(at..at + T::LEN)
+            .map(T::get)
+            .ok_or_else(|| DecodeError::new("too short for field"))
+    }
+}
+
+impl Value for Vec<u8> {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        put_sized(segment.payload(slot), self);
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        match segment.payload(slot)? {
+            Some(mut cursor) => Ok(cursor.sized()?.to_vec()),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Value for String {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        put_sized(segment.payload(slot), self.as_bytes());
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        String::from_utf8(<Vec<u8> as Value>::decode(segment, slot)?)
+            .map_err(|_| DecodeError::new("string is not valid UTF-8"))
+    }
+}
+
+impl Value for Bytes {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        put_sized(segment.payload(slot), self);
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        match segment.payload(slot)? {
+            Some(mut cursor) => Ok(segment.share(cursor.sized()?)),
+            None => Ok(Bytes::new()),
+        }
+    }
+}
+
+impl Value for ByteString {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        put_sized(segment.payload(slot), self.as_bytes());
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        byte_string(<Bytes as Value>::decode(segment, slot)?)
+    }
+}
+
+fn byte_string(bytes: Bytes) -> Result<ByteString, DecodeError> {
+    ByteString::try_from(bytes).map_err(|_| DecodeError::new("string is not valid UTF-8"))
+}
+
+impl<T: Scalar> Value for Vec<T> {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        let buf = segment.payload(slot);
+        buf.extend_from_slice(&(self.len() as u32).to_le_bytes());
+        for item in self {
+            item.put(buf);
+        }
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        let Some(mut cursor) = segment.payload(slot)? else {
+            return Ok(Vec::new());
+        };
+        let count = cursor.u32()? as usize;
+        let bytes = cursor.bytes(count.saturating_mul(T::LEN))?;
+        Ok(bytes.chunks_exact(T::LEN).map(T::get).collect())
+    }
+}
+
+/// Repeated strings and bytes share the `[count]([len][bytes])*` payload.
+fn encode_items<'b>(
+    segment: &mut SegmentWriter<'_>,
+    slot: usize,
+    items: impl ExactSizeIterator<Item = &'b [u8]>,
+) {
+    let buf = segment.payload(slot);
+    buf.extend_from_slice(&(items.len() as u32).to_le_bytes());
+    for item in items {
+        put_sized(buf, item);
+    }
+}
+
+fn decode_items<'a, T>(
+    segment: &SegmentReader<'a>,
+    slot: usize,
+    mut item: impl FnMut(&'a [u8]) -> Result<T, DecodeError>,
+) -> Result<Vec<T>, DecodeError> {
+    let Some(mut cursor) = segment.payload(slot)? else {
+        return Ok(Vec::new());
+    };
+    let count = cursor.u32()? as usize;
+    // The count is untrusted; each item takes at least four bytes.
+    let mut items = Vec::with_capacity(count.min(cursor.rest.len() / 4));
+    for _ in 0..count {
+        items.push(item(cursor.sized()?)?);
+    }
+    Ok(items)
+}
+
+impl Value for Vec<Vec<u8>> {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        encode_items(segment, slot, self.iter().map(Vec::as_slice));
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        decode_items(segment, slot, |bytes| Ok(bytes.to_vec()))
+    }
+}
+
+impl Value for Vec<String> {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        encode_items(segment, slot, self.iter().map(String::as_bytes));
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        decode_items(segment, slot, |bytes| {
+            String::from_utf8(bytes.to_vec())
+                .map_err(|_| DecodeError::new("string is not valid UTF-8"))
+        })
+    }
+}
+
+impl Value for Vec<Bytes> {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        encode_items(segment, slot, self.iter().map(|bytes| &bytes[..]));
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        decode_items(segment, slot, |bytes| Ok(segment.share(bytes)))
+    }
+}
+
+impl Value for Vec<ByteString> {
+    const TABLE_LEN: usize = 4;
+
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        encode_items(segment, slot, self.iter().map(|s| &s.as_bytes()[..]));
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        decode_items(segment, slot, |bytes| byte_string(segment.share(bytes)))
+    }
+}
+
+impl<M: Message> Nested for Option<M> {
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        // An absent message leaves its offset at 0.
+        if let Some(message) = self {
+            put_sized(segment.payload(slot), &message.marshal_symphony());
+        }
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        match segment.payload(slot)? {
+            Some(mut cursor) => Ok(Some(segment.nested(cursor.sized()?)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<M: Message> Nested for Vec<M> {
+    fn encode(&self, segment: &mut SegmentWriter<'_>, slot: usize) {
+        let messages: Vec<Vec<u8>> = self.iter().map(M::marshal_symphony).collect();
+        encode_items(segment, slot, messages.iter().map(Vec::as_slice));
+    }
+
+    fn decode(segment: &SegmentReader<'_>, slot: usize) -> Result<Self, DecodeError> {
+        decode_items(segment, slot, |bytes| segment.nested(bytes))
+    }
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use super::*;
+    use crate::SymphonyMessage;
+
+    #[derive(Debug, Default, PartialEq, SymphonyMessage)]
+    struct EchoRequest {
+        id: i32,
+        score: i32,
+        username: String,
+        content: String,
+    }
+
+    #[derive(Debug, Default, PartialEq, SymphonyMessage)]
+    struct Leaf {
+        #[symphony(tag = 2)]
+        value: String,
+        #[symphony(tag = 1, public)]
+        key: Vec<u8>,
+    }
+
+    #[derive(Debug, Default, PartialEq, SymphonyMessage)]
+    struct Everything {
+        flag: bool,
+        #[symphony(public)]
+        id: u64,
+        ratio: f64,
+        counts: Vec<u32>,
+        names: Vec<String>,
+        blobs: Vec<Vec<u8>>,
+        #[symphony(message)]
+        leaf: Option<Leaf>,
+        #[symphony(message)]
+        leaves: Vec<Leaf>,
+    }
+
+    #[test]
+    fn matches_go_layout() {
+        let request = EchoRequest {
+            id: 1,
+            score: 2,
+            username: "ab".to_string(),
+            content: "c".to_string(),
+        };
+        // What protoc-gen-symphony produces for echo.EchoRequest: no public
+        // fields, so the private segment starts at 13 and its offsets count
+        // from there.
+        let mut expected = vec![1, 13, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        expected.extend_from_slice(&1u32.to_le_bytes());
+        expected.extend_from_slice(&2u32.to_le_bytes());
+        expected.extend_from_slice(&17u32.to_le_bytes());
+        expected.extend_from_slice(&23u32.to_le_bytes());
+        expected.extend_from_slice(&2u32.to_le_bytes());
+        expected.extend_from_slice(b"ab");
+        expected.extend_from_slice(&1u32.to_le_bytes());
+        expected.extend_from_slice(b"c");
+
+        let data = request.marshal_symphony();
+        assert_eq!(data, expected);
+        assert_eq!(EchoRequest::unmarshal_symphony(&data).unwrap(), request);
+    }
+
+    #[test]
+    fn round_trips_every_field_kind() {
+        let leaf = |key: &str, value: &str| Leaf {
+            key: key.as_bytes().to_vec(),
+            value: value.to_string(),
+        };
+        let message = Everything {
+            flag: true,
+            id: u64::MAX,
+            ratio: 0.5,
+            counts: vec![1, 2, 3],
+            names: vec!["a".to_string(), String::new()],
+            blobs: vec![vec![0xff], vec![]],
+            leaf: Some(leaf("k", "v")),
+            leaves: vec![leaf("a", "1"), leaf("b", "2")],
+        };
+        let data = message.marshal_symphony();
+        assert_eq!(Everything::unmarshal_symphony(&data).unwrap(), message);
+
+        // The public segment holds only `id`, right after the header.
+        let header = symphony_wire::parse_message_header(&data).unwrap();
+        assert_eq!(header.offset_to_private, 13 + 8);
+        assert_eq!(data[13..21], u64::MAX.to_le_bytes());
+
+        let empty = Everything::default();
+        let data = empty.marshal_symphony();
+        assert_eq!(Everything::unmarshal_symphony(&data).unwrap(), empty);
+    }
+
+    #[derive(Debug, Default, PartialEq, SymphonyMessage)]
+    struct Shared {
+        #[symphony(public)]
+        key: ByteString,
+        value: Bytes,
+        parts: Vec<Bytes>,
+        #[symphony(message)]
+        leaf: Option<SharedLeaf>,
+    }
+
+    #[derive(Debug, Default, PartialEq, SymphonyMessage)]
+    struct SharedLeaf {
+        names: Vec<ByteString>,
+    }
+
+    #[test]
+    fn shares_the_buffer_of_bytes_fields() {
+        let message = Shared {
+            key: ByteString::from("k"),
+            value: Bytes::from(vec![7; 4096]),
+            parts: vec![Bytes::from_static(b"a"), Bytes::new()],
+            leaf: Some(SharedLeaf {
+                names: vec![ByteString::from("n")],
+            }),
+        };
+        let data = Bytes::from(message.marshal_symphony());
+        let within = |bytes: &[u8]| data.as_ptr_range().contains(&bytes.as_ptr());
+
+        let shared = Shared::unmarshal_symphony_bytes(data.clone()).unwrap();
+        assert_eq!(shared, message);
+        assert!(within(&shared.value) && within(shared.key.as_bytes()));
+        assert!(within(&shared.parts[0]));
+        assert!(within(shared.leaf.as_ref().unwrap().names[0].as_bytes()));
+
+        let copied = Shared::unmarshal_symphony(&data).unwrap();
+        assert_eq!(copied, message);
+        assert!(!within(&copied.value));
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let mut data = Leaf::default().marshal_symphony();
+        data[0] = 2;
+        assert!(Leaf::unmarshal_symphony(&data).is_err());
+
+        let mut data = EchoRequest {
+            username: "ab".to_string(),
+            ..Default::default()
+        }
+        .marshal_symphony();
+        let len = data.len();
+        data.truncate(len - 4);
+        assert!(EchoRequest::unmarshal_symphony(&data).is_err());
+    }
+}
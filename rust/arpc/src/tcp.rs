@@ -0,0 +1,286 @@
+//! Symphony messages over TCP, for networks that block UDP.
+//!
+//! Each message travels as one length-prefixed frame,
+//! `[length(4B)][type(1B)][rpc id(8B)][extensions length(2B)][extensions][message]`,
+//! the length counting what follows it, little-endian like the rest of the
+//! protocol. TCP orders and resends the bytes, so the fragmentation and
+//! reliability mode of the UDP transport do not apply, and nothing is
+//! encrypted. A client connects to its server on its first call, and the
+//! server answers on that connection.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex, Weak};
+
+use symphony_wire::PacketType;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::buffer;
+use crate::transport::{Frame, Transport, TransportFuture, FRAME_HEADER_LEN};
+use crate::{Error, ReassemblyLimits};
+
+/// Bytes of the length prefix.
+const LENGTH_LEN: usize = 4;
+
+type Writer = Arc<tokio::sync::Mutex<OwnedWriteHalf>>;
+
+/// Connections by peer, and the messages they carried in.
+struct Shared {
+    connections: Mutex<HashMap<SocketAddrV4, Writer>>,
+    received: mpsc::UnboundedSender<Frame>,
+    limits: Mutex<ReassemblyLimits>,
+}
+
+/// Length-prefixed Symphony messages over TCP connections.
+pub struct TcpTransport {
+    local: SocketAddr,
+    shared: Arc<Shared>,
+    received: tokio::sync::Mutex<mpsc::UnboundedReceiver<Frame>>,
+    // Accepts the connections of a server.
+    listener: Option<JoinHandle<()>>,
+}
+
+impl TcpTransport {
+    /// Listens on `addr` for the connections of clients, as a server.
+    pub async fn bind(addr: SocketAddrV4) -> io::Result<TcpTransport> {
+        let listener = TcpListener::bind(addr).await?;
+        let local = listener.local_addr()?;
+        let mut transport = TcpTransport::with_local(local);
+        let shared = transport.shared.clone();
+        transport.listener = Some(tokio::spawn(accept_loop(listener, shared)));
+        Ok(transport)
+    }
+
+    /// Connects to servers as it first sends to them, as a client.
+    pub fn connect() -> TcpTransport {
+        TcpTransport::with_local(SocketAddr::from(([0, 0, 0, 0], 0)))
+    }
+
+    fn with_local(local: SocketAddr) -> TcpTransport {
+        let (tx, rx) = mpsc::unbounded_channel();
+        TcpTransport {
+            local,
+            shared: Arc::new(Shared {
+                connections: Mutex::new(HashMap::new()),
+                received: tx,
+                limits: Mutex::new(ReassemblyLimits::default()),
+            }),
+            received: tokio::sync::Mutex::new(rx),
+            listener: None,
+        }
+    }
+
+    /// The open connection to `dst`, or a new one from a client.
+    async fn connection(&self, dst: SocketAddrV4) -> io::Result<Writer> {
+        if let Some(writer) = self.shared.connections.lock().unwrap().get(&dst) {
+            return Ok(writer.clone());
+        }
+        if self.listener.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("no connection to {}", dst),
+            ));
+        }
+        let stream = TcpStream::connect(dst).await?;
+        Ok(open(stream, dst, &self.shared))
+    }
+
+    async fn send_frame(
+        &self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &[u8],
+        extensions: &[u8],
+    ) -> Result<(), Error> {
+        let header = Frame::header(kind, rpc_id, extensions)?;
+        let len = FRAME_HEADER_LEN + extensions.len() + message.len();
+        let prefix = u32::try_from(len).map_err(|_| Error::TooLarge(message.len()))?;
+        let mut frame = buffer::take(LENGTH_LEN + len);
+        frame.extend_from_slice(&prefix.to_le_bytes());
+        frame.extend_from_slice(&header);
+        frame.extend_from_slice(extensions);
+        frame.extend_from_slice(message);
+
+        let writer = self.connection(dst).await?;
+        let sent = writer.lock().await.write_all(&frame).await;
+        buffer::give(frame);
+        if sent.is_err() {
+            forget(&self.shared, dst, &Arc::downgrade(&writer));
+        }
+        Ok(sent?)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send<'a>(
+        &'a self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &'a [u8],
+        extensions: &'a [u8],
+    ) -> TransportFuture<'a, Result<(), Error>> {
+        Box::pin(self.send_frame(dst, kind, rpc_id, message, extensions))
+    }
+
+    fn recv(&self) -> TransportFuture<'_, io::Result<Option<Frame>>> {
+        // The sender lives as long as the transport.
+        Box::pin(async move { Ok(self.received.lock().await.recv().await) })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local)
+    }
+
+    /// Bounds the messages read off a connection; a peer sending a longer
+    /// one is disconnected.
+    fn set_reassembly_limits(&self, limits: ReassemblyLimits) {
+        *self.shared.limits.lock().unwrap() = limits;
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        if let Some(listener) = &self.listener {
+            listener.abort();
+        }
+        // Dropping the write halves shuts the connections down, which ends
+        // their read loops.
+        self.shared.connections.lock().unwrap().clear();
+    }
+}
+
+async fn accept_loop(listener: TcpListener, shared: Arc<Shared>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::warn!("failed to accept TCP connection: {}", e);
+                continue;
+            }
+        };
+        let SocketAddr::V4(peer) = peer else {
+            log::warn!("refusing TCP connection from IPv6 peer");
+            continue;
+        };
+        open(stream, peer, &shared);
+    }
+}
+
+/// Registers the connection `stream` with `peer` and starts reading it.
+fn open(stream: TcpStream, peer: SocketAddrV4, shared: &Arc<Shared>) -> Writer {
+    if let Err(e) = stream.set_nodelay(true) {
+        log::debug!("failed to disable Nagle with {}: {}", peer, e);
+    }
+    let (reader, writer) = stream.into_split();
+    let writer = Arc::new(tokio::sync::Mutex::new(writer));
+    shared
+        .connections
+        .lock()
+        .unwrap()
+        .insert(peer, writer.clone());
+    tokio::spawn(read_loop(
+        reader,
+        peer,
+        Arc::downgrade(&writer),
+        shared.clone(),
+    ));
+    writer
+}
+
+/// Reads the frames `peer` sends until the connection closes. The loop
+/// only keeps a weak reference to `writer`, so that dropping the transport
+/// closes the connection.
+async fn read_loop(
+    mut reader: OwnedReadHalf,
+    peer: SocketAddrV4,
+    writer: Weak<tokio::sync::Mutex<OwnedWriteHalf>>,
+    shared: Arc<Shared>,
+) {
+    loop {
+        let mut prefix = [0; LENGTH_LEN];
+        if let Err(e) = reader.read_exact(&mut prefix).await {
+            log::debug!("TCP connection with {} closed: {}", peer, e);
+            break;
+        }
+        let len = u32::from_le_bytes(prefix) as usize;
+        let limit = shared.limits.lock().unwrap().max_message_len;
+        if len > FRAME_HEADER_LEN + 0xffff + limit {
+            log::warn!("closing connection with {}: {}-byte frame", peer, len);
+            break;
+        }
+        let mut frame = buffer::take(len);
+        frame.resize(len, 0);
+        if let Err(e) = reader.read_exact(&mut frame).await {
+            log::debug!("TCP connection with {} closed: {}", peer, e);
+            break;
+        }
+        match Frame::parse(&frame, peer) {
+            Some(parsed) => {
+                let _ = shared.received.send(parsed);
+            }
+            None => log::warn!("dropping malformed message from {}", peer),
+        }
+        buffer::give(frame);
+    }
+    forget(&shared, peer, &writer);
+}
+
+/// Drops the connection with `peer` if it is still `writer`'s.
+fn forget(shared: &Shared, peer: SocketAddrV4, writer: &Weak<tokio::sync::Mutex<OwnedWriteHalf>>) {
+    let mut connections = shared.connections.lock().unwrap();
+    if connections
+        .get(&peer)
+        .is_some_and(|open| std::ptr::eq(Arc::as_ptr(open), writer.as_ptr()))
+    {
+        connections.remove(&peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Code, Server, ServiceDesc, Status};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn serves_calls_over_tcp() {
+        let mut server = Server::bind_tcp("127.0.0.1:0").await.unwrap();
+        server.register(
+            ServiceDesc::new("EchoService", 1)
+                .method("Echo", 1, |req: Text| async move {
+                    Ok::<_, Error>(Text(req.0.to_uppercase()))
+                })
+                .method("Fail", 2, |req: Text| async move {
+                    Err::<Text, _>(Status::new(Code::NotFound, req.0).into())
+                }),
+        );
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect_tcp(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1), ("Fail", 2)]);
+        client.set_call_timeout(Some(Duration::from_secs(5)));
+        // Larger than the UDP transport's packets, in one frame.
+        let long = "frame ".repeat(10_000);
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new(&long))
+            .await
+            .unwrap();
+        assert_eq!(resp.0, long.to_uppercase());
+        match client
+            .call::<_, Text>("EchoService", "Fail", &Text::new("gone"))
+            .await
+        {
+            Err(Error::Status(status)) => assert_eq!(status, Status::new(Code::NotFound, "gone")),
+            other => panic!("unexpected result: {:?}", other.map(|t| t.0)),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! Helpers shared by the unit tests.
+
+use symphony_wire::{MESSAGE_HEADER_LEN, SYMPHONY_VERSION};
+
+use crate::{DecodeError, Message};
+
+/// A message whose private segment is its text.
+#[derive(Debug, PartialEq)]
+pub struct Text(pub String);
+
+impl Text {
+    pub fn new(text: &str) -> Text {
+        Text(text.to_string())
+    }
+}
+
+impl Message for Text {
+    fn marshal_symphony(&self) -> Vec<u8> {
+        let mut buf = vec![SYMPHONY_VERSION];
+        buf.extend_from_slice(&(MESSAGE_HEADER_LEN as u32).to_le_bytes());
+        buf.extend_from_slice(&[0; 8]);
+        buf.push(SYMPHONY_VERSION);
+        buf.extend_from_slice(self.0.as_bytes());
+        buf
+    }
+
+    fn unmarshal_symphony(data: &[u8]) -> Result<Self, DecodeError> {
+        let text = data
+            .get(MESSAGE_HEADER_LEN + 1..)
+            .ok_or_else(|| DecodeError::new("too short"))?;
+        Ok(Text(String::from_utf8_lossy(text).into_owned()))
+    }
+}
@@ -0,0 +1,220 @@
+//! Diagnostics through the `tracing` crate, with feature `tracing`; without
+//! it, all of this compiles to nothing.
+//!
+//! Each unary call a client makes, and each request a server handles, runs
+//! in an `rpc` span with the fields `side` (client or server), `service`,
+//! `method`, `rpc_id`, `peer`, `bytes`, the marshaled request and response
+//! together, and `status`, ok or the failure, recorded once it finishes.
+//! Retransmissions, abandoned messages, timeouts and exceeded deadlines are
+//! events.
+#![cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+
+use std::future::Future;
+use std::net::SocketAddrV4;
+
+use crate::Error;
+
+/// The span of one call or request.
+pub(crate) struct Rpc {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    bytes: usize,
+}
+
+impl Rpc {
+    /// The span of a call to `peer`, whose request is `bytes` long.
+    pub fn client(
+        service: &str,
+        method: &str,
+        rpc_id: u64,
+        peer: SocketAddrV4,
+        bytes: usize,
+    ) -> Rpc {
+        Rpc::new("client", service, method, rpc_id, peer, bytes)
+    }
+
+    /// The span of a request from `peer`, `bytes` long.
+    pub fn server(
+        service: &str,
+        method: &str,
+        rpc_id: u64,
+        peer: SocketAddrV4,
+        bytes: usize,
+    ) -> Rpc {
+        Rpc::new("server", service, method, rpc_id, peer, bytes)
+    }
+
+    fn new(
+        side: &'static str,
+        service: &str,
+        method: &str,
+        rpc_id: u64,
+        peer: SocketAddrV4,
+        bytes: usize,
+    ) -> Rpc {
+        Rpc {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "rpc",
+                side,
+                service,
+                method,
+                rpc_id,
+                %peer,
+                bytes,
+                status = tracing::field::Empty,
+            ),
+            #[cfg(feature = "tracing")]
+            bytes,
+        }
+    }
+
+    /// Runs `f` in the span.
+    pub async fn run<F: Future>(&self, f: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        let f = tracing::Instrument::instrument(f, self.span.clone());
+        f.await
+    }
+
+    /// Records how the call went: the length of the response, or the
+    /// failure.
+    pub fn finish(&self, outcome: Result<usize, &Error>) {
+        #[cfg(feature = "tracing")]
+        match outcome {
+            Ok(len) => {
+                self.span.record("bytes", self.bytes + len);
+                self.span.record("status", "ok");
+            }
+            Err(Error::Status(status)) => {
+                self.span
+                    .record("status", tracing::field::debug(status.code()));
+            }
+            Err(e) => {
+                self.span.record("status", tracing::field::display(e));
+            }
+        }
+    }
+}
+
+/// Packets of message `rpc_id` to `dst` were sent again, for the
+/// `attempt`-th time.
+pub(crate) fn retransmitted(dst: SocketAddrV4, rpc_id: u64, attempt: u32) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(%dst, rpc_id, attempt, "retransmitting");
+}
+
+/// Message `rpc_id` to `dst` went unacknowledged through every retry.
+pub(crate) fn abandoned(dst: SocketAddrV4, rpc_id: u64) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(%dst, rpc_id, "abandoned after retransmissions");
+}
+
+/// Call `rpc_id` to `peer` got no reply in time.
+pub(crate) fn timed_out(peer: SocketAddrV4, rpc_id: u64) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(%peer, rpc_id, "timed out");
+}
+
+/// A handler was stopped at the deadline of its request.
+pub(crate) fn deadline_exceeded() {
+    #[cfg(feature = "tracing")]
+    tracing::info!("deadline exceeded");
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Server, ServiceDesc};
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata};
+
+    #[derive(Default)]
+    struct Fields(HashMap<&'static str, String>);
+
+    /// Keeps the fields of every span.
+    #[derive(Default)]
+    struct Spans {
+        next: AtomicU64,
+        spans: Mutex<Vec<Fields>>,
+    }
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name(), value.to_string());
+        }
+    }
+
+    impl tracing::Subscriber for Spans {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            self.spans.lock().unwrap().push(fields);
+            Id::from_u64(self.next.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut spans[span.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[tokio::test]
+    async fn spans_calls_on_both_sides() {
+        let spans = Arc::new(Spans::default());
+        let _default = tracing::subscriber::set_default(spans.clone());
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("EchoService", 1).method(
+            "Echo",
+            1,
+            |req: Text| async move { Ok::<_, Error>(req) },
+        ));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new("hi"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("hi"));
+        // The server records its span just after responding.
+        tokio::task::yield_now().await;
+
+        let spans = spans.spans.lock().unwrap();
+        let rpcs: Vec<_> = spans
+            .iter()
+            .map(|span| &span.0)
+            .filter(|span| span.contains_key("rpc_id"))
+            .collect();
+        assert_eq!(rpcs.len(), 2);
+        for (span, side) in rpcs.iter().zip(["client", "server"]) {
+            assert_eq!(span["side"], side);
+            assert_eq!(span["service"], "EchoService");
+            assert_eq!(span["method"], "Echo");
+            assert_eq!(span["status"], "ok");
+            assert!(span["bytes"].parse::<usize>().unwrap() > 4);
+        }
+        assert_eq!(rpcs[0]["rpc_id"], rpcs[1]["rpc_id"]);
+    }
+}
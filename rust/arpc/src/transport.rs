@@ -0,0 +1,1211 @@
+//! The [`Transport`] trait clients and servers move messages through, and
+//! its main implementation, Symphony packets over a UDP socket
+//! (`UDPTransport` in `pkg/transport/transport.go`). The TCP fallback is
+//! [`crate::TcpTransport`], the in-memory transport of tests
+//! [`crate::MemoryTransport`].
+//!
+//! Messages larger than a packet are split on send and reassembled on
+//! receive; see [`crate::fragment`]. With an [`Encryption`] set, messages
+//! are sealed before they are split and opened once reassembled, like
+//! `EncryptSymphonyData` in Go; the transport also answers and starts the
+//! rekey handshakes of [`crate::encryption`]. In reliability mode it
+//! acknowledges what it receives and resends what goes unacknowledged; see
+//! [`crate::reliability`], and [`crate::congestion`] for the windows that
+//! pace it. With a [`Keepalive`] set, it pings its peers while quiet and
+//! tears down the sessions of those that stop answering or go idle; see
+//! [`crate::keepalive`]. With a [`PathMtu`] set, it sizes the packets to
+//! each peer by the path MTU it probes for; see [`crate::pmtu`]. Fields
+//! like the deadline of a request travel in the extension block of its
+//! first packet; see [`crate::extensions`]. Go peers reject packets with
+//! one, so with extensions turned off blocks go only to the peers that have
+//! sent one.
+//!
+//! With feature `dtls` and a [`crate::Dtls`] set, every datagram travels in
+//! a DTLS session with its peer instead; see [`crate::dtls`]. With feature
+//! `io-uring` on Linux, an io_uring can drive the socket instead of tokio;
+//! see [`crate::uring`]. Otherwise, on Linux, the packets of a message go
+//! out in batches of one system call, and arrivals come in likewise; see
+//! [`crate::batch`].
+//!
+//! Packets are encoded into, and messages received into, buffers from the
+//! pool of [`crate::buffer_stats`], given back once sent or handled.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::pin::Pin;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use symphony_wire::{
+    DataPacket, ErrorPacket, Packet, PacketType, DATA_HEADER_LEN, ERROR_HEADER_LEN, MAX_PACKET_LEN,
+    MESSAGE_HEADER_LEN,
+};
+use tokio::net::UdpSocket;
+
+use crate::batch::{self, Counters};
+use crate::buffer;
+#[cfg(feature = "dtls")]
+use crate::dtls::{Dtls, Sessions};
+use crate::encryption::{Rekey, REKEY_PACKET_TYPE};
+use crate::extensions::Extensions;
+use crate::fragment::{self, FragmentStats, Reassembler, ReassemblyLimits};
+use crate::keepalive::{self, OnClosed, Ping, PING_PACKET_TYPE};
+use crate::pmtu::{self, Probe, PROBE_PACKET_TYPE};
+use crate::reliability::{Ack, Reliable, ACK_PACKET_TYPE};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::uring::{IoUring, Ring};
+use crate::{
+    BatchStats, Batching, CipherSuite, Code, CongestionControl, Encryption, Error, Keepalive,
+    PathMtu, Reliability, ReliabilityStats, SessionClosed, Status,
+};
+
+/// How often a transport in reliability mode, with keepalives, or with
+/// path MTU discovery looks for packets to resend and peers to ping or
+/// probe while none arrive.
+const TIMER_TICK: Duration = Duration::from_millis(50);
+
+/// Room for a packet, or for a DTLS record holding one, up to the largest
+/// path MTU probed for: a jumbo frame less the IP and UDP headers.
+const MAX_DATAGRAM_LEN: usize = 9000 - 28;
+
+/// A packet addressed to this endpoint.
+pub(crate) struct Received {
+    pub kind: PacketType,
+    pub rpc_id: u64,
+    /// The sender, from the packet header.
+    pub src: SocketAddrV4,
+    /// The message of a data packet, or the reason of an error packet.
+    pub payload: Vec<u8>,
+    pub extensions: Extensions,
+}
+
+/// A message as a [`Transport`] hands it over: a data packet's message, or
+/// an error packet's reason, with the encoded extension block of its first
+/// packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: PacketType,
+    pub rpc_id: u64,
+    /// The sender, which answers go back to.
+    pub src: SocketAddrV4,
+    pub message: Vec<u8>,
+    pub extensions: Vec<u8>,
+}
+
+/// The future of a [`Transport`] method.
+pub type TransportFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// How a client or server moves messages: Symphony packets over UDP,
+/// [`UdpTransport`], by default, or a [`crate::TcpTransport`] where UDP is
+/// blocked, a [`crate::MemoryTransport`] in tests, or messages over QUIC
+/// streams (feature `quic`).
+///
+/// A transport addresses peers by IPv4 socket address, and takes care of
+/// splitting, ordering and securing messages itself; the client and server
+/// only see whole messages.
+pub trait Transport: Send + Sync + 'static {
+    /// Sends `message` to `dst`, with the encoded extension block
+    /// `extensions`. Error and Unknown messages carry the encoded
+    /// [`Status`] of a failure, at most [`Transport::max_status_len`]
+    /// bytes, and no extensions.
+    fn send<'a>(
+        &'a self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &'a [u8],
+        extensions: &'a [u8],
+    ) -> TransportFuture<'a, Result<(), Error>>;
+
+    /// Waits for the next message. Returns None for input that completes
+    /// none, such as a fragment or an acknowledgement.
+    fn recv(&self) -> TransportFuture<'_, io::Result<Option<Frame>>>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// The longest status message sent, which is cut to fit.
+    fn max_status_len(&self) -> usize {
+        64 << 10
+    }
+
+    /// Bounds the messages being put together from what arrives. Ignored
+    /// by default.
+    fn set_reassembly_limits(&self, limits: ReassemblyLimits) {
+        let _ = limits;
+    }
+
+    /// Whether `peer` reads extension blocks. Only Symphony over UDP has
+    /// peers that may not, those of the Go transport.
+    fn reads_extensions(&self, peer: SocketAddrV4) -> bool {
+        let _ = peer;
+        true
+    }
+}
+
+impl dyn Transport {
+    /// Sends `message` with the fields of `extensions`. A peer that does
+    /// not read extension blocks gets the message without its optional
+    /// fields, and none that needs its block.
+    pub(crate) async fn send_message(
+        &self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &[u8],
+        extensions: &Extensions,
+    ) -> Result<(), Error> {
+        let block = extensions.encode();
+        if block.is_empty() || self.reads_extensions(dst) {
+            return self.send(dst, kind, rpc_id, message, &block).await;
+        }
+        if extensions.required() {
+            return Err(Status::new(
+                Code::Unimplemented,
+                format!("{} does not read extension blocks", dst),
+            )
+            .into());
+        }
+        self.send(dst, kind, rpc_id, message, &[]).await
+    }
+
+    /// Sends the Error or Unknown message reporting `status`.
+    pub(crate) async fn send_status(
+        &self,
+        dst: SocketAddrV4,
+        rpc_id: u64,
+        status: &Status,
+    ) -> Result<(), Error> {
+        let message = status.encode(self.max_status_len());
+        self.send(dst, status.packet_type(), rpc_id, &message, &[])
+            .await
+    }
+
+    /// Waits for the next message, with its extension block parsed.
+    pub(crate) async fn recv_message(&self) -> io::Result<Option<Received>> {
+        let Some(frame) = self.recv().await? else {
+            return Ok(None);
+        };
+        let Some(extensions) = Extensions::parse(&frame.extensions) else {
+            log::warn!(
+                "dropping rpc {} from {}: malformed extensions",
+                frame.rpc_id,
+                frame.src
+            );
+            return Ok(None);
+        };
+        Ok(Some(Received {
+            kind: frame.kind,
+            rpc_id: frame.rpc_id,
+            src: frame.src,
+            payload: frame.message,
+            extensions,
+        }))
+    }
+}
+
+/// Bytes before the extension block of a frame on a stream transport:
+/// `[type(1B)][rpc id(8B)][extensions length(2B)]`.
+pub(crate) const FRAME_HEADER_LEN: usize = 11;
+
+impl Frame {
+    /// The header of a frame on a stream transport, followed there by
+    /// `extensions` and the message.
+    pub(crate) fn header(
+        kind: PacketType,
+        rpc_id: u64,
+        extensions: &[u8],
+    ) -> Result<[u8; FRAME_HEADER_LEN], Error> {
+        let len = u16::try_from(extensions.len()).map_err(|_| Error::TooLarge(extensions.len()))?;
+        let mut header = [0; FRAME_HEADER_LEN];
+        header[0] = kind.id();
+        header[1..9].copy_from_slice(&rpc_id.to_le_bytes());
+        header[9..].copy_from_slice(&len.to_le_bytes());
+        Ok(header)
+    }
+
+    /// Parses a frame read off a stream transport from `src`.
+    pub(crate) fn parse(buf: &[u8], src: SocketAddrV4) -> Option<Frame> {
+        let kind = PacketType::from_id(*buf.first()?)?;
+        let rpc_id = u64::from_le_bytes(buf.get(1..9)?.try_into().unwrap());
+        let len = u16::from_le_bytes(buf.get(9..11)?.try_into().unwrap()) as usize;
+        let extensions = buf.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len)?;
+        Some(Frame {
+            kind,
+            rpc_id,
+            src,
+            message: buffer::copy(&buf[FRAME_HEADER_LEN + len..]),
+            extensions: extensions.to_vec(),
+        })
+    }
+}
+
+/// Symphony packets over a UDP socket; the transport of
+/// [`crate::Client::connect`] and [`crate::Server::bind`].
+pub struct UdpTransport {
+    socket: UdpSocket,
+    // Source address written into packet headers; peers answer to it.
+    local: SocketAddrV4,
+    reassembler: Mutex<Reassembler>,
+    encryption: RwLock<Option<Arc<Encryption>>>,
+    // Applied to the encryption whenever it is set.
+    suites: RwLock<Option<Vec<CipherSuite>>>,
+    reliable: Mutex<Option<Reliable>>,
+    congestion: RwLock<Option<CongestionControl>>,
+    sessions: Mutex<Option<keepalive::Sessions>>,
+    on_closed: RwLock<Vec<OnClosed>>,
+    paths: Mutex<Option<pmtu::Paths>>,
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    ring: RwLock<Option<Arc<Ring>>>,
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    batching: RwLock<Option<Batching>>,
+    #[cfg(target_os = "linux")]
+    inbox: Mutex<batch::Inbox>,
+    // Cleared once the kernel fails to segment.
+    #[cfg(target_os = "linux")]
+    gso: AtomicBool,
+    batches: Counters,
+    // Messages sent in several packets, and those packets.
+    split: AtomicU64,
+    fragments: AtomicU64,
+    #[cfg(feature = "dtls")]
+    dtls: Mutex<Option<Sessions>>,
+    // Whether every peer gets extension blocks, or only those in
+    // `extended`, which have sent one.
+    extensions: AtomicBool,
+    extended: Mutex<HashSet<SocketAddrV4>>,
+}
+
+impl UdpTransport {
+    /// Binds to `addr`. An unspecified IP is replaced in packet headers by
+    /// the one the host routes `towards` through.
+    pub async fn bind(addr: SocketAddrV4, towards: SocketAddrV4) -> io::Result<UdpTransport> {
+        UdpTransport::with_socket(UdpSocket::bind(addr).await?, towards)
+    }
+
+    /// Takes over a bound socket, registering it with the runtime it is
+    /// called on; see [`UdpTransport::bind`].
+    pub(crate) fn from_std(
+        socket: std::net::UdpSocket,
+        towards: SocketAddrV4,
+    ) -> io::Result<UdpTransport> {
+        socket.set_nonblocking(true)?;
+        UdpTransport::with_socket(UdpSocket::from_std(socket)?, towards)
+    }
+
+    fn with_socket(socket: UdpSocket, towards: SocketAddrV4) -> io::Result<UdpTransport> {
+        let SocketAddr::V4(mut local) = socket.local_addr()? else {
+            unreachable!("bound to an IPv4 address");
+        };
+        if local.ip().is_unspecified() {
+            match route_ip(towards) {
+                Ok(ip) => local.set_ip(ip),
+                Err(e) => log::warn!("no route towards {} to pick a source IP: {}", towards, e),
+            }
+        }
+        Ok(UdpTransport {
+            socket,
+            local,
+            reassembler: Mutex::new(Reassembler::new(ReassemblyLimits::default())),
+            encryption: RwLock::new(None),
+            suites: RwLock::new(None),
+            reliable: Mutex::new(None),
+            congestion: RwLock::new(None),
+            sessions: Mutex::new(None),
+            on_closed: RwLock::new(Vec::new()),
+            paths: Mutex::new(None),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            ring: RwLock::new(None),
+            batching: RwLock::new(Some(Batching::default())),
+            #[cfg(target_os = "linux")]
+            inbox: Mutex::new(batch::Inbox::new()),
+            #[cfg(target_os = "linux")]
+            gso: AtomicBool::new(true),
+            batches: Counters::default(),
+            split: AtomicU64::new(0),
+            fragments: AtomicU64::new(0),
+            #[cfg(feature = "dtls")]
+            dtls: Mutex::new(None),
+            extensions: AtomicBool::new(true),
+            extended: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Sends extension blocks to every peer, as by default, or only to the
+    /// peers that have sent one, for Go peers, which reject them.
+    pub fn set_extensions(&self, on: bool) {
+        self.extensions.store(on, Ordering::Relaxed);
+        if on {
+            self.extended.lock().unwrap().clear();
+        }
+    }
+
+    /// Sends `message` in as many data packets as it takes, the first one
+    /// carrying `extensions`.
+    async fn send_data(
+        &self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &[u8],
+        extensions: &[u8],
+    ) -> Result<(), Error> {
+        let sealed = self
+            .encryption
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|encryption| encryption.encrypt(dst, rpc_id, message, Instant::now()))
+            .transpose()?;
+        let (message, key_phase) = match &sealed {
+            Some(sealed) => (&sealed.message[..], sealed.key_phase),
+            None => (message, false),
+        };
+        // Names the suite to a peer that has not answered in it yet, if the
+        // peer reads extension blocks; one that does not takes AES-GCM.
+        let mut named = Vec::new();
+        let extensions = match sealed.as_ref().and_then(|sealed| sealed.suite) {
+            Some(suite) if self.reads_extensions(dst) => {
+                let suite = Extensions {
+                    suite: Some(suite.id()),
+                    ..Extensions::default()
+                };
+                named.extend_from_slice(extensions);
+                named.extend_from_slice(&suite.encode());
+                &named[..]
+            }
+            _ => extensions,
+        };
+        // Every chunk makes room for the extension block, though only the
+        // first carries it, so that the split stays aligned.
+        let max_fragment_len = self.max_packet_len(dst) - DATA_HEADER_LEN;
+        let mtu = match extensions.len() {
+            0 => max_fragment_len,
+            len => max_fragment_len
+                .checked_sub(2 + len)
+                .filter(|mtu| *mtu >= MESSAGE_HEADER_LEN)
+                .ok_or(Error::TooLarge(len))?,
+        };
+        let chunks = fragment::split(message, mtu).ok_or(Error::MissingHeader)?;
+        let total_packets =
+            u16::try_from(chunks.len()).map_err(|_| Error::TooLarge(message.len()))?;
+        if total_packets > 1 {
+            self.split.fetch_add(1, Ordering::Relaxed);
+            self.fragments
+                .fetch_add(total_packets.into(), Ordering::Relaxed);
+        }
+        let packets = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(seq, chunk)| {
+                encode(Packet::Data(DataPacket {
+                    kind,
+                    rpc_id,
+                    total_packets,
+                    seq: seq as u16,
+                    more_fragments: false,
+                    fragment_index: 0,
+                    key_phase,
+                    dst,
+                    src: self.local,
+                    extensions: if seq == 0 { extensions } else { &[] },
+                    payload: chunk,
+                }))
+            })
+            .collect();
+        let rekey = sealed.and_then(|sealed| {
+            buffer::give(sealed.message);
+            sealed.rekey
+        });
+        self.send_message(dst, rpc_id, packets).await?;
+        if let Some(epoch) = rekey {
+            self.send_rekey(dst, epoch, false).await?;
+        }
+        Ok(())
+    }
+
+    /// Asks `dst` to move to the next key epoch. Does nothing without
+    /// encryption.
+    pub async fn rekey(&self, dst: SocketAddrV4) -> Result<(), Error> {
+        let epoch = match &*self.encryption.read().unwrap() {
+            Some(encryption) => encryption.start_rekey(dst, Instant::now())?,
+            None => return Ok(()),
+        };
+        self.send_rekey(dst, epoch, false).await
+    }
+
+    async fn send_rekey(&self, dst: SocketAddrV4, epoch: u32, ack: bool) -> Result<(), Error> {
+        let packet = match &*self.encryption.read().unwrap() {
+            Some(encryption) => {
+                encryption.seal_rekey(dst, &Rekey { epoch, ack }, Instant::now())?
+            }
+            None => return Ok(()),
+        };
+        self.send_datagram(&packet, dst).await
+    }
+
+    /// Applies a rekey packet, acknowledging requests to the address it
+    /// came from.
+    async fn on_rekey(&self, buf: &[u8], peer: SocketAddr) {
+        let SocketAddr::V4(peer) = peer else {
+            return;
+        };
+        let acked = match &*self.encryption.read().unwrap() {
+            Some(encryption) => encryption.on_rekey(peer, buf, Instant::now()),
+            None => {
+                log::debug!("ignoring rekey from {} without encryption", peer);
+                return;
+            }
+        };
+        let sent = match acked {
+            Ok(Some(epoch)) => self.send_rekey(peer, epoch, true).await,
+            Ok(None) => Ok(()),
+            Err(e) => {
+                log::warn!("dropping rekey packet from {}: {}", peer, e);
+                return;
+            }
+        };
+        if let Err(e) = sent {
+            log::warn!("failed to rekey with {}: {}", peer, e);
+        }
+    }
+
+    /// Sends an Error or Unknown packet with the reason `message`.
+    async fn send_error(
+        &self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &[u8],
+    ) -> Result<(), Error> {
+        let packet = Packet::Error(ErrorPacket {
+            kind,
+            rpc_id,
+            dst,
+            src: self.local,
+            message,
+        });
+        self.send_message(dst, rpc_id, vec![encode(packet)]).await
+    }
+
+    /// Sends the packets of a message, keeping them for retransmission in
+    /// reliability mode.
+    async fn send_message(
+        &self,
+        dst: SocketAddrV4,
+        rpc_id: u64,
+        packets: Vec<Vec<u8>>,
+    ) -> Result<(), Error> {
+        self.touch(dst, true, true);
+        let mut allowed = packets.len();
+        if let Some(reliable) = &mut *self.reliable.lock().unwrap() {
+            let kept = packets.iter().map(|packet| buffer::copy(packet)).collect();
+            allowed = reliable.track(dst, rpc_id, kept, Instant::now());
+        }
+        let datagrams: Vec<_> = packets[..allowed].iter().map(|p| (dst, &p[..])).collect();
+        let sent = self.send_datagrams(&datagrams).await;
+        for packet in packets {
+            buffer::give(packet);
+        }
+        sent
+    }
+
+    /// Sends one datagram, through the DTLS session with `dst` if there
+    /// are sessions.
+    async fn send_datagram(&self, datagram: &[u8], dst: SocketAddrV4) -> Result<(), Error> {
+        self.send_datagrams(&[(dst, datagram)]).await
+    }
+
+    /// Sends datagrams, batched, through the DTLS sessions with their
+    /// destinations if there are sessions.
+    async fn send_datagrams(&self, datagrams: &[(SocketAddrV4, &[u8])]) -> Result<(), Error> {
+        #[cfg(feature = "dtls")]
+        {
+            let sealed = self
+                .dtls
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|sessions| {
+                    let now = Instant::now();
+                    let mut records = Vec::new();
+                    for &(dst, datagram) in datagrams {
+                        let sealed = sessions.seal(dst, datagram, now)?;
+                        records.extend(sealed.into_iter().map(|record| (dst, record)));
+                    }
+                    Ok::<_, Error>(records)
+                })
+                .transpose()?;
+            if let Some(records) = sealed {
+                let records: Vec<_> = records.iter().map(|(dst, r)| (*dst, &r[..])).collect();
+                self.send_batch(&records).await?;
+                return Ok(());
+            }
+        }
+        self.send_batch(datagrams).await?;
+        Ok(())
+    }
+
+    /// Sends datagrams through the ring if there is one, else in batches
+    /// if batching is on, else one at a time.
+    async fn send_batch(&self, datagrams: &[(SocketAddrV4, &[u8])]) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if datagrams.len() > 1 && !self.has_ring() {
+            let batching = *self.batching.read().unwrap();
+            if let Some(batching) = batching {
+                return batch::send(&self.socket, datagrams, batching, &self.gso, &self.batches)
+                    .await;
+            }
+        }
+        let mut sent = Ok(());
+        for &(dst, datagram) in datagrams {
+            if let Err(e) = self.send_to(datagram, dst).await {
+                sent = sent.and(Err(e));
+            }
+        }
+        sent
+    }
+
+    /// Sends a datagram through the ring if there is one, else the tokio
+    /// socket.
+    async fn send_to(&self, datagram: &[u8], dst: SocketAddrV4) -> io::Result<()> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(ring) = self.ring() {
+            return ring.send_to(datagram, dst).await;
+        }
+        self.socket.send_to(datagram, dst).await?;
+        Ok(())
+    }
+
+    /// Receives a datagram through the ring if there is one, else the
+    /// tokio socket, in batches if batching is on.
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(ring) = self.ring() {
+            if let Some(received) = ring.recv_from(buf).await {
+                return Ok(received);
+            }
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let pending = self.inbox.lock().unwrap().pop(buf);
+            if let Some(received) = pending {
+                return Ok(received);
+            }
+            let batching = *self.batching.read().unwrap();
+            if let Some(batching) = batching {
+                return batch::recv(&self.socket, &self.inbox, buf, batching, &self.batches).await;
+            }
+        }
+        self.socket.recv_from(buf).await
+    }
+
+    /// Whether an io_uring drives the socket.
+    #[cfg(target_os = "linux")]
+    fn has_ring(&self) -> bool {
+        #[cfg(feature = "io-uring")]
+        return self.ring().is_some();
+        #[cfg(not(feature = "io-uring"))]
+        false
+    }
+
+    /// Sets how datagrams are batched into system calls, or sends and
+    /// receives them one at a time with None. Batching is on by default,
+    /// and only has an effect on Linux; see [`crate::Batching`].
+    pub fn set_batching(&self, batching: Option<Batching>) {
+        #[cfg(target_os = "linux")]
+        {
+            let offload = batching.is_some_and(|batching| batching.offload);
+            match batch::set_gro(&self.socket, offload) {
+                Err(e) if offload => log::warn!("no UDP receive offload: {}", e),
+                _ => {}
+            }
+            self.gso.store(true, Ordering::Relaxed);
+        }
+        *self.batching.write().unwrap() = batching;
+    }
+
+    pub fn batch_stats(&self) -> BatchStats {
+        self.batches.stats()
+    }
+
+    /// Drives the socket with an io_uring from now on, or with tokio again
+    /// with None. Fails, staying on tokio, if the kernel has no io_uring;
+    /// see [`crate::IoUring`].
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn set_io_uring(&self, io_uring: Option<IoUring>) -> Result<(), Error> {
+        let ring = io_uring
+            .map(|config| Ring::start(&self.socket, config, MAX_DATAGRAM_LEN))
+            .transpose()?;
+        let old = std::mem::replace(&mut *self.ring.write().unwrap(), ring.map(Arc::new));
+        if let Some(old) = old {
+            old.stop();
+        }
+        Ok(())
+    }
+
+    /// The ring driving the socket, forgetting it once it stopped.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn ring(&self) -> Option<Arc<Ring>> {
+        let ring = self.ring.read().unwrap().clone()?;
+        if !ring.is_stopped() {
+            return Some(ring);
+        }
+        let mut current = self.ring.write().unwrap();
+        if current
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, &ring))
+        {
+            log::warn!("io_uring backend stopped, back to tokio");
+            *current = None;
+        }
+        None
+    }
+
+    /// Encrypts the messages of data packets from now on.
+    pub fn set_encryption(&self, encryption: Encryption) {
+        self.share_encryption(Arc::new(encryption));
+    }
+
+    /// Encrypts with keys, and per-peer epochs, shared with other
+    /// transports, so that a peer keeps its epoch whichever one it reaches.
+    pub(crate) fn share_encryption(&self, encryption: Arc<Encryption>) {
+        if let Some(suites) = &*self.suites.read().unwrap() {
+            encryption.set_suites(suites);
+        }
+        *self.encryption.write().unwrap() = Some(encryption);
+    }
+
+    /// Seals messages to new peers in the first of `suites`, and takes only
+    /// these from peers that name theirs, whether the encryption is set
+    /// before or after; see [`Encryption::set_suites`].
+    pub fn set_cipher_suites(&self, suites: &[CipherSuite]) {
+        *self.suites.write().unwrap() = Some(suites.to_vec());
+        if let Some(encryption) = &*self.encryption.read().unwrap() {
+            encryption.set_suites(suites);
+        }
+    }
+
+    /// Sends and receives every datagram over DTLS sessions from now on.
+    #[cfg(feature = "dtls")]
+    pub fn set_dtls(&self, dtls: Dtls) {
+        *self.dtls.lock().unwrap() = Some(Sessions::new(dtls));
+    }
+
+    /// Takes a datagram in through the DTLS session with `peer`, sending
+    /// what the session answers. Returns None without sessions, and
+    /// `Some(None)` for a datagram that carried no packet.
+    #[cfg(feature = "dtls")]
+    async fn open_dtls(&self, datagram: &[u8], peer: SocketAddr) -> Option<Option<Vec<u8>>> {
+        let SocketAddr::V4(peer) = peer else {
+            return self.dtls.lock().unwrap().is_some().then_some(None);
+        };
+        let (replies, packet) =
+            self.dtls
+                .lock()
+                .unwrap()
+                .as_mut()?
+                .open(peer, datagram, Instant::now());
+        for reply in replies {
+            if let Err(e) = self.send_to(&reply, peer).await {
+                log::warn!("failed to send DTLS record to {}: {}", peer, e);
+            }
+        }
+        Some(packet)
+    }
+
+    /// Turns reliability mode on, or off with None.
+    pub fn set_reliability(&self, reliability: Option<Reliability>) {
+        let mut reliable = self.reliable.lock().unwrap();
+        match (reliable.as_mut(), reliability) {
+            (Some(reliable), Some(config)) => reliable.set_config(config),
+            (_, config) => {
+                *reliable = config.map(|config| {
+                    let mut reliable = Reliable::new(config);
+                    reliable.set_congestion(self.congestion.read().unwrap().clone());
+                    reliable
+                })
+            }
+        }
+    }
+
+    /// Bounds what is in flight to each peer in reliability mode by a
+    /// congestion window, or lifts the bound with None.
+    pub fn set_congestion_control(&self, congestion: Option<CongestionControl>) {
+        *self.congestion.write().unwrap() = congestion.clone();
+        if let Some(reliable) = &mut *self.reliable.lock().unwrap() {
+            reliable.set_congestion(congestion);
+        }
+    }
+
+    pub fn fragment_stats(&self) -> FragmentStats {
+        FragmentStats {
+            split: self.split.load(Ordering::Relaxed),
+            fragments: self.fragments.load(Ordering::Relaxed),
+            ..self.reassembler.lock().unwrap().stats()
+        }
+    }
+
+    pub fn reliability_stats(&self) -> ReliabilityStats {
+        self.reliable
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(Reliable::stats)
+            .unwrap_or_default()
+    }
+
+    /// Resends the packets whose acknowledgement is overdue, then sends
+    /// those the congestion windows let go. Returns false outside
+    /// reliability mode.
+    async fn retransmit(&self) -> bool {
+        let (due, released) = match &mut *self.reliable.lock().unwrap() {
+            Some(reliable) => {
+                let now = Instant::now();
+                (reliable.due(now), reliable.release(now))
+            }
+            None => return false,
+        };
+        let datagrams: Vec<_> = due.iter().map(|(dst, p)| (*dst, &p[..])).collect();
+        if let Err(e) = self.send_datagrams(&datagrams).await {
+            log::warn!("failed to retransmit: {}", e);
+        }
+        for (_, packet) in due {
+            buffer::give(packet);
+        }
+        self.send_released(released).await;
+        true
+    }
+
+    /// Sends the packets a congestion window held back.
+    async fn send_released(&self, released: Vec<(SocketAddrV4, Vec<u8>)>) {
+        let datagrams: Vec<_> = released.iter().map(|(dst, p)| (*dst, &p[..])).collect();
+        if let Err(e) = self.send_datagrams(&datagrams).await {
+            log::warn!("failed to send released packets: {}", e);
+        }
+        for (_, packet) in released {
+            buffer::give(packet);
+        }
+    }
+
+    /// Records the arrival of a packet in reliability mode. Returns false
+    /// for a duplicate to drop.
+    fn admit(&self, src: SocketAddrV4, rpc_id: u64, seq: u16, total: u16, whole: bool) -> bool {
+        match &mut *self.reliable.lock().unwrap() {
+            Some(reliable) => reliable.receive(src, rpc_id, seq, total, whole, Instant::now()),
+            None => true,
+        }
+    }
+
+    /// Acknowledges the packets received of a message, in reliability
+    /// mode. `dst` is the destination they were sent to.
+    async fn acknowledge(&self, src: SocketAddrV4, rpc_id: u64, dst: SocketAddrV4, complete: bool) {
+        let ack = match &mut *self.reliable.lock().unwrap() {
+            Some(reliable) => {
+                if complete {
+                    reliable.complete(src, rpc_id);
+                }
+                reliable.ack(src, rpc_id, dst)
+            }
+            None => return,
+        };
+        if let Some(ack) = ack {
+            if let Err(e) = self.send_datagram(&ack.encode(), src).await {
+                log::warn!("failed to acknowledge rpc {} to {}: {}", rpc_id, src, e);
+            }
+        }
+    }
+
+    async fn on_ack(&self, buf: &[u8], peer: SocketAddr) {
+        let Some(ack) = Ack::parse(buf) else {
+            log::warn!("dropping malformed ack from {}", peer);
+            return;
+        };
+        let released = match &mut *self.reliable.lock().unwrap() {
+            Some(reliable) => {
+                let now = Instant::now();
+                reliable.on_ack(&ack, now);
+                reliable.release(now)
+            }
+            None => return,
+        };
+        self.send_released(released).await;
+    }
+
+    /// Turns keepalives on, or off with None, forgetting the sessions.
+    pub fn set_keepalive(&self, keepalive: Option<Keepalive>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        match (sessions.as_mut(), keepalive) {
+            (Some(sessions), Some(config)) => sessions.set_config(config),
+            (_, config) => *sessions = config.map(keepalive::Sessions::new),
+        }
+    }
+
+    /// Calls `f` with the peer of every session torn down from now on.
+    pub fn on_session_closed(
+        &self,
+        f: impl Fn(SocketAddrV4, SessionClosed) + Send + Sync + 'static,
+    ) {
+        self.on_closed.write().unwrap().push(Arc::new(f));
+    }
+
+    /// Records something sent to or heard from `peer`, with keepalives.
+    fn touch(&self, peer: SocketAddrV4, call: bool, sent: bool) {
+        if let Some(sessions) = &mut *self.sessions.lock().unwrap() {
+            if sent {
+                sessions.sent(peer, call, Instant::now());
+            } else {
+                sessions.heard(peer, call, Instant::now());
+            }
+        }
+    }
+
+    /// Pings the peers of quiet sessions and tears down those that timed
+    /// out. Returns false without keepalives.
+    async fn keep_alive(&self) -> bool {
+        let (closed, pings) = match &mut *self.sessions.lock().unwrap() {
+            Some(sessions) => sessions.due(Instant::now()),
+            None => return false,
+        };
+        for peer in pings {
+            if let Err(e) = self.send_ping(peer, false).await {
+                log::warn!("failed to ping {}: {}", peer, e);
+            }
+        }
+        if closed.is_empty() {
+            return true;
+        }
+        let callbacks = self.on_closed.read().unwrap().clone();
+        for (peer, reason) in closed {
+            log::debug!("closing session with {}: {:?}", peer, reason);
+            if let Some(reliable) = &mut *self.reliable.lock().unwrap() {
+                reliable.remove_peer(peer);
+            }
+            #[cfg(feature = "dtls")]
+            if let Some(sessions) = &mut *self.dtls.lock().unwrap() {
+                sessions.remove(peer);
+            }
+            if let Some(paths) = &mut *self.paths.lock().unwrap() {
+                paths.remove(peer);
+            }
+            if let Some(encryption) = &*self.encryption.read().unwrap() {
+                encryption.forget(peer);
+            }
+            for callback in &callbacks {
+                callback(peer, reason);
+            }
+        }
+        true
+    }
+
+    async fn send_ping(&self, dst: SocketAddrV4, pong: bool) -> Result<(), Error> {
+        let ping = Ping {
+            pong,
+            src: self.local,
+        };
+        self.send_datagram(&ping.encode(), dst).await
+    }
+
+    /// Answers a ping, or records the answer to one.
+    async fn on_ping(&self, buf: &[u8], peer: SocketAddr) {
+        let Some(ping) = Ping::parse(buf) else {
+            log::warn!("dropping malformed ping from {}", peer);
+            return;
+        };
+        self.touch(ping.src, false, false);
+        if !ping.pong {
+            if let Err(e) = self.send_ping(ping.src, true).await {
+                log::warn!("failed to answer ping from {}: {}", ping.src, e);
+            }
+        }
+    }
+
+    /// Turns path MTU discovery on, or off with None, forgetting the
+    /// paths. Off, packets are at most [`MAX_PACKET_LEN`] bytes.
+    pub fn set_path_mtu(&self, path_mtu: Option<PathMtu>) {
+        let path_mtu = path_mtu.map(|config| PathMtu {
+            max: config.max.min(MAX_DATAGRAM_LEN),
+            ..config
+        });
+        let mut paths = self.paths.lock().unwrap();
+        match (paths.as_mut(), path_mtu) {
+            (Some(paths), Some(config)) => paths.set_config(config),
+            (_, config) => *paths = config.map(pmtu::Paths::new),
+        }
+    }
+
+    /// The size of the packets sent to `peer`.
+    pub fn path_mtu(&self, peer: SocketAddrV4) -> usize {
+        self.max_packet_len(peer)
+    }
+
+    fn max_packet_len(&self, peer: SocketAddrV4) -> usize {
+        match &mut *self.paths.lock().unwrap() {
+            Some(paths) => paths.mtu(peer, Instant::now()),
+            None => MAX_PACKET_LEN,
+        }
+    }
+
+    /// Sends the probes due, giving up on those unanswered. Returns false
+    /// without path MTU discovery.
+    async fn probe_paths(&self) -> bool {
+        let probes = match &mut *self.paths.lock().unwrap() {
+            Some(paths) => paths.due(Instant::now()),
+            None => return false,
+        };
+        for (peer, size) in probes {
+            self.send_probe(peer, size).await;
+        }
+        true
+    }
+
+    /// Sends a probe of `size` bytes to `dst`.
+    async fn send_probe(&self, dst: SocketAddrV4, size: usize) {
+        let probe = Probe {
+            ack: false,
+            size: size as u16,
+            src: self.local,
+            dst,
+        };
+        self.send_probe_packet(probe, dst).await;
+    }
+
+    async fn send_probe_packet(&self, probe: Probe, to: SocketAddrV4) {
+        let datagram = probe.encode();
+        // A probe too large to send is as good as lost.
+        if let Err(e) = self.send_datagram(&datagram, to).await {
+            log::debug!("failed to send {} byte probe to {}: {}", probe.size, to, e);
+        }
+        buffer::give(datagram);
+    }
+
+    /// Acknowledges a probe, or records the acknowledgement of one and
+    /// probes on.
+    async fn on_probe(&self, buf: &[u8], peer: SocketAddr) {
+        let Some(probe) = Probe::parse(buf) else {
+            log::warn!("dropping malformed probe from {}", peer);
+            return;
+        };
+        if !probe.ack {
+            let ack = Probe { ack: true, ..probe };
+            self.send_probe_packet(ack, probe.src).await;
+            return;
+        }
+        let next = self
+            .paths
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|paths| paths.acked(probe.dst, probe.size as usize, Instant::now()));
+        if let Some(next) = next {
+            self.send_probe(probe.dst, next).await;
+        }
+    }
+
+    /// Waits for the next packet. Returns None for datagrams that do not
+    /// complete a message.
+    async fn recv_frame(&self) -> io::Result<Option<Frame>> {
+        let reliable = self.retransmit().await;
+        let keepalive = self.keep_alive().await;
+        let probing = self.probe_paths().await;
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        let (n, peer) = if reliable || keepalive || probing {
+            match tokio::time::timeout(TIMER_TICK, self.recv_from(&mut buf)).await {
+                Ok(received) => received?,
+                Err(_) => return Ok(None),
+            }
+        } else {
+            self.recv_from(&mut buf).await?
+        };
+        #[cfg_attr(not(feature = "dtls"), allow(unused_mut))]
+        let mut datagram = &buf[..n];
+        #[cfg(feature = "dtls")]
+        let opened = self.open_dtls(datagram, peer).await;
+        #[cfg(feature = "dtls")]
+        match &opened {
+            Some(Some(packet)) => datagram = packet,
+            Some(None) => return Ok(None),
+            None => {}
+        }
+        match datagram.first() {
+            Some(&REKEY_PACKET_TYPE) => {
+                self.on_rekey(datagram, peer).await;
+                return Ok(None);
+            }
+            Some(&ACK_PACKET_TYPE) => {
+                self.on_ack(datagram, peer).await;
+                return Ok(None);
+            }
+            Some(&PING_PACKET_TYPE) => {
+                self.on_ping(datagram, peer).await;
+                return Ok(None);
+            }
+            Some(&PROBE_PACKET_TYPE) => {
+                self.on_probe(datagram, peer).await;
+                return Ok(None);
+            }
+            _ => {}
+        }
+        let (mut received, key_phase) = match symphony_wire::parse(datagram) {
+            Ok(Packet::Data(p)) => {
+                self.touch(p.src, true, false);
+                let whole = p.fragment_index == 0 && !p.more_fragments;
+                let payload = if !self.admit(p.src, p.rpc_id, p.seq, p.total_packets, whole) {
+                    None
+                } else if p.total_packets == 1 && p.is_first() && whole {
+                    Some((buffer::copy(p.payload), p.extensions.to_vec()))
+                } else {
+                    self.reassembler.lock().unwrap().insert(&p, Instant::now())
+                };
+                self.acknowledge(p.src, p.rpc_id, p.dst, payload.is_some())
+                    .await;
+                let Some((message, extensions)) = payload else {
+                    return Ok(None);
+                };
+                if !extensions.is_empty() && !self.extensions.load(Ordering::Relaxed) {
+                    self.extended.lock().unwrap().insert(p.src);
+                }
+                let received = Frame {
+                    kind: p.kind,
+                    rpc_id: p.rpc_id,
+                    src: p.src,
+                    message,
+                    extensions,
+                };
+                (received, p.key_phase)
+            }
+            Ok(Packet::Error(p)) => {
+                self.touch(p.src, true, false);
+                let fresh = self.admit(p.src, p.rpc_id, 0, 1, true);
+                self.acknowledge(p.src, p.rpc_id, p.dst, fresh).await;
+                if !fresh {
+                    return Ok(None);
+                }
+                let received = Frame {
+                    kind: p.kind,
+                    rpc_id: p.rpc_id,
+                    src: p.src,
+                    message: buffer::copy(p.message),
+                    extensions: Vec::new(),
+                };
+                (received, false)
+            }
+            Err(e) => {
+                log::warn!("dropping packet from {}: {}", peer, e);
+                return Ok(None);
+            }
+        };
+        if matches!(received.kind, PacketType::Request | PacketType::Response) {
+            let opened = self.encryption.read().unwrap().as_ref().map(|encryption| {
+                let suite = Extensions::parse(&received.extensions).and_then(|e| e.suite);
+                encryption.decrypt(
+                    received.src,
+                    suite,
+                    key_phase,
+                    &received.message,
+                    Instant::now(),
+                )
+            });
+            match opened {
+                Some(Ok(message)) => {
+                    buffer::give(std::mem::replace(&mut received.message, message))
+                }
+                Some(Err(Error::Encryption(symphony_wire::Error::UnsupportedSuite(id))))
+                    if received.kind == PacketType::Request =>
+                {
+                    // Refuses the call outright rather than let it time out.
+                    let status = Status::new(
+                        Code::Unimplemented,
+                        format!("cipher suite {} is not supported", id),
+                    );
+                    let message = status.encode(self.max_status_len());
+                    let sent = self
+                        .send_error(
+                            received.src,
+                            status.packet_type(),
+                            received.rpc_id,
+                            &message,
+                        )
+                        .await;
+                    if let Err(e) = sent {
+                        log::warn!("failed to refuse rpc {}: {}", received.rpc_id, e);
+                    }
+                    return Ok(None);
+                }
+                Some(Err(e)) => {
+                    log::warn!("dropping rpc {} from {}: {}", received.rpc_id, peer, e);
+                    return Ok(None);
+                }
+                None => {}
+            }
+        }
+        Ok(Some(received))
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send<'a>(
+        &'a self,
+        dst: SocketAddrV4,
+        kind: PacketType,
+        rpc_id: u64,
+        message: &'a [u8],
+        extensions: &'a [u8],
+    ) -> TransportFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            match kind {
+                PacketType::Error | PacketType::Unknown => {
+                    self.send_error(dst, kind, rpc_id, message).await
+                }
+                _ => self.send_data(dst, kind, rpc_id, message, extensions).await,
+            }
+        })
+    }
+
+    fn recv(&self) -> TransportFuture<'_, io::Result<Option<Frame>>> {
+        Box::pin(self.recv_frame())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// What fits one datagram.
+    fn max_status_len(&self) -> usize {
+        // Status packets are not split, so they fit the base size of any
+        // path.
+        let max_packet_len = match &*self.paths.lock().unwrap() {
+            Some(paths) => paths.base(),
+            None => MAX_PACKET_LEN,
+        };
+        max_packet_len - ERROR_HEADER_LEN
+    }
+
+    fn set_reassembly_limits(&self, limits: ReassemblyLimits) {
+        self.reassembler.lock().unwrap().set_limits(limits);
+    }
+
+    fn reads_extensions(&self, peer: SocketAddrV4) -> bool {
+        self.extensions.load(Ordering::Relaxed) || self.extended.lock().unwrap().contains(&peer)
+    }
+}
+
+fn encode(packet: Packet<'_>) -> Vec<u8> {
+    let mut buf = buffer::take(packet.encoded_len());
+    packet.encode_into(&mut buf);
+    buf
+}
+
+/// The local IP the host routes `towards` through. Connecting a UDP socket
+/// sends nothing; it only picks the route.
+fn route_ip(towards: SocketAddrV4) -> io::Result<Ipv4Addr> {
+    let probe = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    probe.connect(towards)?;
+    match probe.local_addr()? {
+        SocketAddr::V4(addr) => Ok(*addr.ip()),
+        SocketAddr::V6(_) => unreachable!("bound to an IPv4 address"),
+    }
+}
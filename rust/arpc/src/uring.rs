@@ -0,0 +1,534 @@
+//! An io_uring backend for the UDP socket of a transport (feature
+//! `io-uring`, Linux only), to cut the syscalls per packet at high rates.
+//!
+//! With [`IoUring`] set, a thread of the transport's own drives a ring on
+//! the socket: one multishot receive takes in datagrams into a group of
+//! buffers provided to the ring up front, and sends go in as they are
+//! queued, many completions per system call. The transport reads what
+//! arrives from the thread instead of the socket, and queues its sends to
+//! it.
+//!
+//! It falls back to the tokio socket whenever the ring is unavailable:
+//! setting it fails when the kernel has no io_uring, or forbids it, and a
+//! ring that stops, say on a kernel without multishot receives, hands the
+//! socket back to tokio.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use io_uring::{cqueue, opcode, squeue, types};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::buffer;
+
+/// io_uring settings of a transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoUring {
+    /// Entries of the submission queue.
+    pub entries: u32,
+    /// Buffers provided to the ring for received datagrams; once they are
+    /// all full, datagrams wait in the socket.
+    pub buffers: u16,
+}
+
+impl Default for IoUring {
+    fn default() -> Self {
+        IoUring {
+            entries: 256,
+            buffers: 256,
+        }
+    }
+}
+
+/// The group of buffers provided for receives.
+const BUFFER_GROUP: u16 = 0;
+
+// The user data of the operations other than sends, which count up from
+// FIRST_SEND.
+const RECV: u64 = 0;
+const WAKE: u64 = 1;
+const PROVIDE: u64 = 2;
+const CANCEL: u64 = 3;
+const FIRST_SEND: u64 = 16;
+
+/// The room before a received datagram in its buffer: the header of a
+/// multishot receive, then the source address.
+const RECV_HEADER_LEN: usize = 16 + std::mem::size_of::<libc::sockaddr_in>();
+
+/// A ring driving a socket, from the side of the transport.
+pub(crate) struct Ring {
+    shared: Arc<Shared>,
+    received: tokio::sync::Mutex<mpsc::Receiver<(Vec<u8>, SocketAddrV4)>>,
+}
+
+/// What the transport and the thread of a ring share.
+struct Shared {
+    sends: Mutex<VecDeque<Outgoing>>,
+    /// An eventfd the thread waits on for sends, and to stop.
+    wake: File,
+    stopped: AtomicBool,
+}
+
+struct Outgoing {
+    datagram: Vec<u8>,
+    dst: SocketAddrV4,
+    done: oneshot::Sender<io::Result<()>>,
+}
+
+impl Ring {
+    /// Starts a ring on `socket` for datagrams of up to `max_datagram_len`
+    /// bytes.
+    pub fn start(socket: &UdpSocket, config: IoUring, max_datagram_len: usize) -> io::Result<Ring> {
+        let fd = socket.as_fd().try_clone_to_owned()?;
+        // SAFETY: eventfd takes no pointers, and returns a new descriptor
+        // or -1.
+        let wake = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if wake < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `wake` is a descriptor of ours, owned by nothing else.
+        let wake = File::from(unsafe { OwnedFd::from_raw_fd(wake) });
+        let ring = io_uring::IoUring::new(config.entries)?;
+        let shared = Arc::new(Shared {
+            sends: Mutex::new(VecDeque::new()),
+            wake,
+            stopped: AtomicBool::new(false),
+        });
+        let (received, rx) = mpsc::channel(config.buffers.max(1) as usize);
+        let mut driver = Driver::new(ring, fd, shared.clone(), received, config, max_datagram_len);
+        driver.provide_all()?;
+        std::thread::Builder::new()
+            .name("arpc-io-uring".to_string())
+            .spawn(move || driver.run())?;
+        Ok(Ring {
+            shared,
+            received: tokio::sync::Mutex::new(rx),
+        })
+    }
+
+    /// Whether the thread stopped, and the socket is tokio's again.
+    pub fn is_stopped(&self) -> bool {
+        self.shared.stopped.load(Ordering::Acquire)
+    }
+
+    /// Stops the thread; receives end once what arrived is read.
+    pub fn stop(&self) {
+        self.shared.stopped.store(true, Ordering::Release);
+        let _ = self.shared.wake();
+    }
+
+    /// Sends `datagram` to `dst` through the ring.
+    pub async fn send_to(&self, datagram: &[u8], dst: SocketAddrV4) -> io::Result<()> {
+        let (done, result) = oneshot::channel();
+        self.shared.sends.lock().unwrap().push_back(Outgoing {
+            datagram: buffer::copy(datagram),
+            dst,
+            done,
+        });
+        self.shared.wake()?;
+        result
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::BrokenPipe.into()))
+    }
+
+    /// Receives a datagram into `buf`, like [`UdpSocket::recv_from`], or
+    /// None once the thread stopped.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        let (datagram, src) = self.received.lock().await.recv().await?;
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        buffer::give(datagram);
+        Some((n, SocketAddr::V4(src)))
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Shared {
+    fn wake(&self) -> io::Result<()> {
+        (&self.wake).write_all(&1u64.to_ne_bytes())
+    }
+}
+
+/// The thread of a ring, which owns every buffer the kernel reads or
+/// writes, and keeps them until their operations complete.
+struct Driver {
+    ring: io_uring::IoUring,
+    socket: OwnedFd,
+    shared: Arc<Shared>,
+    received: mpsc::Sender<(Vec<u8>, SocketAddrV4)>,
+    config: IoUring,
+    buffer_len: usize,
+    /// The provided buffers, one after another.
+    buffers: Vec<u8>,
+    recv_msg: Box<libc::msghdr>,
+    wake_buf: Box<[u8; 8]>,
+    sends: HashMap<u64, InFlight>,
+    next_send: u64,
+    /// Operations submitted and not yet completed for good.
+    pending: usize,
+    recv_armed: bool,
+}
+
+// SAFETY: the pointers of the driver point into memory it owns, which
+// moves to its thread along with it.
+unsafe impl Send for Driver {}
+
+/// A send the kernel may still read.
+struct InFlight {
+    datagram: Vec<u8>,
+    _addr: Box<libc::sockaddr_in>,
+    _iov: Box<libc::iovec>,
+    _msg: Box<libc::msghdr>,
+    done: oneshot::Sender<io::Result<()>>,
+}
+
+impl Driver {
+    fn new(
+        ring: io_uring::IoUring,
+        socket: OwnedFd,
+        shared: Arc<Shared>,
+        received: mpsc::Sender<(Vec<u8>, SocketAddrV4)>,
+        config: IoUring,
+        max_datagram_len: usize,
+    ) -> Driver {
+        let buffer_len = RECV_HEADER_LEN + max_datagram_len;
+        let mut recv_msg = Box::new(zeroed_msghdr());
+        recv_msg.msg_namelen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+        Driver {
+            ring,
+            socket,
+            shared,
+            received,
+            config,
+            buffer_len,
+            buffers: vec![0; buffer_len * config.buffers as usize],
+            recv_msg,
+            wake_buf: Box::new([0; 8]),
+            sends: HashMap::new(),
+            next_send: FIRST_SEND,
+            pending: 0,
+            recv_armed: false,
+        }
+    }
+
+    /// Provides every buffer, failing if the kernel cannot.
+    fn provide_all(&mut self) -> io::Result<()> {
+        self.provide(0, self.config.buffers)?;
+        self.ring.submit_and_wait(1)?;
+        let cqe = self.ring.completion().next();
+        self.pending -= 1;
+        match cqe.map(|cqe| cqe.result()) {
+            Some(result) if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+            _ => Ok(()),
+        }
+    }
+
+    fn run(mut self) {
+        if let Err(e) = self.serve() {
+            log::warn!("io_uring backend failed, back to tokio: {}", e);
+        }
+        self.shared.stopped.store(true, Ordering::Release);
+        if let Err(e) = self.drain() {
+            // The buffers may still be in use; leak them rather than free
+            // them under the kernel.
+            log::error!("failed to drain io_uring: {}", e);
+            std::mem::forget(self);
+        }
+    }
+
+    fn serve(&mut self) -> io::Result<()> {
+        self.arm_recv()?;
+        self.arm_wake()?;
+        while !self.shared.stopped.load(Ordering::Acquire) && !self.received.is_closed() {
+            self.ring.submit_and_wait(1)?;
+            let completions: Vec<cqueue::Entry> = self.ring.completion().collect();
+            for cqe in completions {
+                self.complete(cqe)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self, cqe: cqueue::Entry) -> io::Result<()> {
+        let result = cqe.result();
+        match cqe.user_data() {
+            RECV => {
+                if !cqueue::more(cqe.flags()) {
+                    self.pending -= 1;
+                    self.recv_armed = false;
+                }
+                if let Some(bid) = cqueue::buffer_select(cqe.flags()) {
+                    if result >= 0 {
+                        self.deliver(bid, result as usize);
+                    }
+                    self.provide(bid, 1)?;
+                } else if result < 0 && result != -libc::ENOBUFS {
+                    return Err(io::Error::from_raw_os_error(-result));
+                }
+                if !self.recv_armed {
+                    self.arm_recv()?;
+                }
+            }
+            WAKE => {
+                self.pending -= 1;
+                let queued: Vec<Outgoing> = self.shared.sends.lock().unwrap().drain(..).collect();
+                for send in queued {
+                    self.send(send)?;
+                }
+                self.arm_wake()?;
+            }
+            PROVIDE | CANCEL => {
+                self.pending -= 1;
+                if result < 0 && cqe.user_data() == PROVIDE {
+                    log::warn!(
+                        "failed to provide io_uring buffer: {}",
+                        io::Error::from_raw_os_error(-result)
+                    );
+                }
+            }
+            id => {
+                self.pending -= 1;
+                if let Some(send) = self.sends.remove(&id) {
+                    let sent = match result {
+                        result if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+                        _ => Ok(()),
+                    };
+                    let _ = send.done.send(sent);
+                    buffer::give(send.datagram);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands the datagram in buffer `bid`, `len` bytes with its header,
+    /// to the transport.
+    fn deliver(&mut self, bid: u16, len: usize) {
+        let start = bid as usize * self.buffer_len;
+        let buf = &self.buffers[start..start + len.min(self.buffer_len)];
+        let Ok(out) = types::RecvMsgOut::parse(buf, &self.recv_msg) else {
+            return;
+        };
+        if out.is_payload_truncated() {
+            log::debug!("dropping datagram past {} bytes", self.buffer_len);
+            return;
+        }
+        let Some(src) = sockaddr_v4(out.name_data()) else {
+            return;
+        };
+        if self
+            .received
+            .try_send((buffer::copy(out.payload_data()), src))
+            .is_err()
+        {
+            log::debug!("dropping datagram from {}: transport behind", src);
+        }
+    }
+
+    fn send(&mut self, send: Outgoing) -> io::Result<()> {
+        let addr = Box::new(libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: send.dst.port().to_be(),
+            sin_addr: libc::in_addr {
+                s_addr: u32::from_ne_bytes(send.dst.ip().octets()),
+            },
+            sin_zero: [0; 8],
+        });
+        let mut iov = Box::new(libc::iovec {
+            iov_base: send.datagram.as_ptr() as *mut libc::c_void,
+            iov_len: send.datagram.len(),
+        });
+        let mut msg = Box::new(zeroed_msghdr());
+        msg.msg_name = &*addr as *const libc::sockaddr_in as *mut libc::c_void;
+        msg.msg_namelen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+        msg.msg_iov = &mut *iov;
+        msg.msg_iovlen = 1;
+        let id = self.next_send;
+        self.next_send += 1;
+        let entry = opcode::SendMsg::new(types::Fd(self.socket.as_raw_fd()), &*msg)
+            .build()
+            .user_data(id);
+        self.sends.insert(
+            id,
+            InFlight {
+                datagram: send.datagram,
+                _addr: addr,
+                _iov: iov,
+                _msg: msg,
+                done: send.done,
+            },
+        );
+        // SAFETY: the datagram, address, iovec and msghdr live in
+        // `self.sends` until the send completes.
+        unsafe { self.push(&entry) }
+    }
+
+    fn arm_recv(&mut self) -> io::Result<()> {
+        let entry = opcode::RecvMsgMulti::new(
+            types::Fd(self.socket.as_raw_fd()),
+            &*self.recv_msg,
+            BUFFER_GROUP,
+        )
+        .build()
+        .user_data(RECV);
+        self.recv_armed = true;
+        // SAFETY: the msghdr lives as long as the driver, and the driver
+        // drains the ring before it goes.
+        unsafe { self.push(&entry) }
+    }
+
+    fn arm_wake(&mut self) -> io::Result<()> {
+        let entry = opcode::Read::new(
+            types::Fd(self.shared.wake.as_raw_fd()),
+            self.wake_buf.as_mut_ptr(),
+            8,
+        )
+        .build()
+        .user_data(WAKE);
+        // SAFETY: as for the receives.
+        unsafe { self.push(&entry) }
+    }
+
+    /// Provides `count` buffers from buffer `bid` on.
+    fn provide(&mut self, bid: u16, count: u16) -> io::Result<()> {
+        let start = bid as usize * self.buffer_len;
+        let entry = opcode::ProvideBuffers::new(
+            self.buffers[start..].as_mut_ptr(),
+            self.buffer_len as i32,
+            count,
+            BUFFER_GROUP,
+            bid,
+        )
+        .build()
+        .user_data(PROVIDE);
+        // SAFETY: as for the receives; the buffers are never reallocated.
+        unsafe { self.push(&entry) }
+    }
+
+    /// Pushes `entry`, submitting what is queued first if the queue is
+    /// full.
+    ///
+    /// # Safety
+    ///
+    /// What `entry` points to must stay put until it completes.
+    unsafe fn push(&mut self, entry: &squeue::Entry) -> io::Result<()> {
+        // SAFETY: up to the caller.
+        while unsafe { self.ring.submission().push(entry) }.is_err() {
+            self.ring.submit()?;
+        }
+        self.pending += 1;
+        Ok(())
+    }
+
+    /// Cancels everything in flight and waits for it, so that the buffers
+    /// can go.
+    fn drain(&mut self) -> io::Result<()> {
+        let entry = opcode::AsyncCancel2::new(types::CancelBuilder::any().all())
+            .build()
+            .user_data(CANCEL);
+        // SAFETY: cancelling points to nothing.
+        unsafe { self.push(&entry) }?;
+        while self.pending > 0 {
+            self.ring.submit_and_wait(1)?;
+            let completions: Vec<cqueue::Entry> = self.ring.completion().collect();
+            for cqe in completions {
+                let more = cqe.user_data() == RECV && cqueue::more(cqe.flags());
+                if !more {
+                    self.pending -= 1;
+                }
+                if let Some(send) = self.sends.remove(&cqe.user_data()) {
+                    let _ = send.done.send(Err(io::ErrorKind::BrokenPipe.into()));
+                }
+            }
+        }
+        for send in self.shared.sends.lock().unwrap().drain(..) {
+            let _ = send.done.send(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        Ok(())
+    }
+}
+
+fn zeroed_msghdr() -> libc::msghdr {
+    // SAFETY: a msghdr of null pointers and zero lengths is valid; some
+    // targets pad it with private fields, so it cannot be written out.
+    unsafe { std::mem::zeroed() }
+}
+
+/// The IPv4 address of a `sockaddr_in`, from its bytes.
+fn sockaddr_v4(name: &[u8]) -> Option<SocketAddrV4> {
+    let family = u16::from_ne_bytes(name.get(0..2)?.try_into().unwrap());
+    if family != libc::AF_INET as u16 {
+        return None;
+    }
+    let port = u16::from_be_bytes(name.get(2..4)?.try_into().unwrap());
+    let ip: [u8; 4] = name.get(4..8)?.try_into().unwrap();
+    Some(SocketAddrV4::new(Ipv4Addr::from(ip), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Text;
+    use crate::{Client, Error, Server, ServiceDesc};
+
+    #[tokio::test]
+    async fn serves_calls_through_the_ring() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(ServiceDesc::new("EchoService", 1).method(
+            "Echo",
+            1,
+            |req: Text| async move { Ok::<_, Error>(Text(req.0.to_uppercase())) },
+        ));
+        // Where the kernel forbids io_uring, both sides stay on tokio.
+        let ring = server.set_io_uring(Some(IoUring::default()));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("EchoService", 1, &[("Echo", 1)]);
+        if let Err(e) = ring.and(client.set_io_uring(Some(IoUring::default()))) {
+            log::warn!("running without io_uring: {}", e);
+        }
+        for len in [1, 10_000] {
+            let req = Text::new(&"x".repeat(len));
+            let resp: Text = client.call("EchoService", "Echo", &req).await.unwrap();
+            assert_eq!(resp.0, req.0.to_uppercase());
+        }
+
+        // Back to tokio at runtime.
+        client.set_io_uring(None).unwrap();
+        let resp: Text = client
+            .call("EchoService", "Echo", &Text::new("tokio"))
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("TOKIO"));
+    }
+
+    #[test]
+    fn parses_ipv4_names() {
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: 9000u16.to_be(),
+            sin_addr: libc::in_addr {
+                s_addr: u32::from_ne_bytes([10, 0, 0, 1]),
+            },
+            sin_zero: [0; 8],
+        };
+        let mut name = addr.sin_family.to_ne_bytes().to_vec();
+        name.extend_from_slice(&addr.sin_port.to_ne_bytes());
+        name.extend_from_slice(&addr.sin_addr.s_addr.to_ne_bytes());
+        assert_eq!(sockaddr_v4(&name), Some("10.0.0.1:9000".parse().unwrap()));
+        assert_eq!(sockaddr_v4(&name[..4]), None);
+    }
+}
@@ -0,0 +1,348 @@
+//! Flow-control windows of streams, so that a slow reader bounds what is
+//! buffered for it, like HTTP/2 windows.
+//!
+//! The receiver of a stream grants its sender credit: a limit on the bytes
+//! of messages it may send on the stream, carried in window updates. Each
+//! stream is granted up to [`FlowWindows::stream`] bytes past what the
+//! application took, and the streams from one peer together no more than
+//! [`FlowWindows::session`], so a stream starved by the others gets credit
+//! back as they are read. Updates go once half a window is used up, or to
+//! a starved stream as soon as there is room.
+//!
+//! A sender sends a frame while the stream has credit left, and waits for
+//! an update otherwise, overshooting by at most one frame. The frame
+//! opening a stream of requests goes out without credit, since the server
+//! grants it once that frame has started its handler; a server-streaming
+//! request, and every frame of a bidi stream of requests, carries the
+//! credit of the responses instead. A reader that drops a stream releases
+//! the sender from the window.
+//!
+//! Window updates are messages of their own with an empty body, under a
+//! fresh RPC ID like the frames. Both sides must enable windows; a server
+//! only paces the responses of a caller that granted them credit.
+
+use std::collections::HashMap;
+use std::net::SocketAddrV4;
+use std::pin::pin;
+use std::sync::Mutex;
+
+use tokio::sync::Notify;
+
+/// Flow-control windows, in bytes of messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowWindows {
+    /// The most one stream has in flight or waiting to be read.
+    pub stream: u32,
+    /// The most the streams from one peer have together.
+    pub session: u32,
+}
+
+impl Default for FlowWindows {
+    fn default() -> Self {
+        FlowWindows {
+            stream: 256 << 10,
+            session: 1 << 20,
+        }
+    }
+}
+
+/// A grant of credit: the sender may send messages of stream `id` until
+/// their bytes reach `limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WindowUpdate {
+    pub id: u64,
+    pub limit: u64,
+}
+
+impl WindowUpdate {
+    /// Releases the sender of stream `id` from its window, once the reader
+    /// stopped reading.
+    pub fn release(id: u64) -> WindowUpdate {
+        WindowUpdate {
+            id,
+            limit: u64::MAX,
+        }
+    }
+}
+
+/// The windows of the streams a client or server sends and receives.
+pub(crate) struct Windows {
+    config: FlowWindows,
+    state: Mutex<State>,
+    // Woken on every update, for senders to look at their credit again.
+    credit: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    sending: HashMap<(SocketAddrV4, u64), Sending>,
+    // The streams received, by peer and then stream.
+    receiving: HashMap<SocketAddrV4, HashMap<u64, Receiving>>,
+}
+
+#[derive(Default)]
+struct Sending {
+    sent: u64,
+    limit: u64,
+}
+
+#[derive(Default)]
+struct Receiving {
+    consumed: u64,
+    limit: u64,
+}
+
+impl Windows {
+    pub fn new(config: FlowWindows) -> Windows {
+        Windows {
+            config,
+            state: Mutex::new(State::default()),
+            credit: Notify::new(),
+        }
+    }
+
+    /// Starts pacing stream `id` to `peer`, with the credit `limit`.
+    pub fn start(&self, peer: SocketAddrV4, id: u64, limit: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.sending.insert((peer, id), Sending { sent: 0, limit });
+    }
+
+    /// Stops pacing stream `id` to `peer`.
+    pub fn finish(&self, peer: SocketAddrV4, id: u64) {
+        self.state.lock().unwrap().sending.remove(&(peer, id));
+    }
+
+    /// Waits until a message of `len` bytes may be sent on stream `id` to
+    /// `peer`, and counts it. Streams not paced go at once.
+    pub async fn reserve(&self, peer: SocketAddrV4, id: u64, len: usize) {
+        loop {
+            let mut credit = pin!(self.credit.notified());
+            credit.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                let Some(sending) = state.sending.get_mut(&(peer, id)) else {
+                    return;
+                };
+                if sending.sent == 0 || sending.sent < sending.limit {
+                    sending.sent += len as u64;
+                    return;
+                }
+            }
+            credit.await;
+        }
+    }
+
+    /// Applies an update from `peer` to the stream it names, if paced.
+    pub fn update(&self, peer: SocketAddrV4, update: WindowUpdate) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(sending) = state.sending.get_mut(&(peer, update.id)) {
+            if update.limit > sending.limit {
+                sending.limit = update.limit;
+                self.credit.notify_waiters();
+            }
+        }
+    }
+
+    /// Starts receiving stream `id` from `peer`, returning the credit it is
+    /// granted; none while the peer's other streams use up the session.
+    pub fn open(&self, peer: SocketAddrV4, id: u64) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let streams = state.receiving.entry(peer).or_default();
+        streams.insert(id, Receiving::default());
+        self.grant(streams, id);
+        streams[&id].limit
+    }
+
+    /// The credit granted stream `id` from `peer` so far, if it is being
+    /// received.
+    pub fn limit(&self, peer: SocketAddrV4, id: u64) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        Some(state.receiving.get(&peer)?.get(&id)?.limit)
+    }
+
+    /// Counts `len` bytes the application took from stream `id` of
+    /// `peer`, returning the updates due for the peer's streams, starved
+    /// ones first.
+    pub fn consumed(&self, peer: SocketAddrV4, id: u64, len: usize) -> Vec<WindowUpdate> {
+        let mut state = self.state.lock().unwrap();
+        let Some(streams) = state.receiving.get_mut(&peer) else {
+            return Vec::new();
+        };
+        let Some(stream) = streams.get_mut(&id) else {
+            return Vec::new();
+        };
+        stream.consumed += len as u64;
+        self.regrant(streams, Some(id))
+    }
+
+    /// Grants credit to the starved streams, then to stream `id`.
+    fn regrant(&self, streams: &mut HashMap<u64, Receiving>, id: Option<u64>) -> Vec<WindowUpdate> {
+        let starved: Vec<u64> = streams
+            .iter()
+            .filter(|(other, stream)| Some(**other) != id && stream.limit <= stream.consumed)
+            .map(|(other, _)| *other)
+            .collect();
+        starved
+            .into_iter()
+            .chain(id)
+            .filter_map(|id| self.grant(streams, id))
+            .collect()
+    }
+
+    /// Stops receiving stream `id` from `peer`, returning the updates of
+    /// the peer's starved streams its credit goes to.
+    pub fn close(&self, peer: SocketAddrV4, id: u64) -> Vec<WindowUpdate> {
+        let mut state = self.state.lock().unwrap();
+        let Some(streams) = state.receiving.get_mut(&peer) else {
+            return Vec::new();
+        };
+        streams.remove(&id);
+        if streams.is_empty() {
+            state.receiving.remove(&peer);
+            return Vec::new();
+        }
+        self.regrant(streams, None)
+    }
+
+    /// Raises the credit of stream `id` as far as the session allows, once
+    /// it has half its window or less left.
+    fn grant(&self, streams: &mut HashMap<u64, Receiving>, id: u64) -> Option<WindowUpdate> {
+        let others: u64 = streams
+            .iter()
+            .filter(|(other, _)| **other != id)
+            .map(|(_, stream)| stream.limit.saturating_sub(stream.consumed))
+            .sum();
+        let room = u64::from(self.config.session).saturating_sub(others);
+        let stream = streams.get_mut(&id)?;
+        let limit = stream.consumed + room.min(u64::from(self.config.stream));
+        let left = stream.limit.saturating_sub(stream.consumed);
+        if limit <= stream.limit || left > u64::from(self.config.stream / 2) {
+            return None;
+        }
+        stream.limit = limit;
+        Some(WindowUpdate { id, limit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+
+    use crate::testing::Text;
+    use crate::{BoxStream, CallOptions, Client, Error, Server, ServiceDesc};
+
+    const SMALL: FlowWindows = FlowWindows {
+        stream: 4000,
+        session: 6000,
+    };
+
+    #[tokio::test]
+    async fn grants_credit_within_the_session() {
+        let peer = "10.0.0.1:9000".parse().unwrap();
+        let windows = Windows::new(SMALL);
+        assert_eq!(windows.open(peer, 1), 4000);
+        assert_eq!(windows.open(peer, 2), 2000);
+        // The session is used up.
+        assert_eq!(windows.open(peer, 3), 0);
+        // What stream 1 frees goes to the starved stream 3 first.
+        let updates = windows.consumed(peer, 1, 1000);
+        assert_eq!(updates, [WindowUpdate { id: 3, limit: 1000 }]);
+        // Half its window used, stream 1 gets what the session has left.
+        let updates = windows.consumed(peer, 1, 1000);
+        assert_eq!(updates, [WindowUpdate { id: 1, limit: 5000 }]);
+        assert!(windows.close(peer, 2).is_empty());
+        let updates = windows.consumed(peer, 1, 2000);
+        assert_eq!(updates, [WindowUpdate { id: 1, limit: 8000 }]);
+        assert_eq!(windows.limit(peer, 3), Some(1000));
+        windows.close(peer, 1);
+        assert!(windows.close(peer, 3).is_empty());
+        assert_eq!(windows.limit(peer, 3), None);
+
+        // The first frame goes without credit, the rest wait for it.
+        windows.start(peer, 7, 0);
+        windows.reserve(peer, 7, 100).await;
+        let blocked = tokio::time::timeout(Duration::from_millis(20), windows.reserve(peer, 7, 1));
+        assert!(blocked.await.is_err());
+        windows.update(peer, WindowUpdate { id: 7, limit: 101 });
+        windows.reserve(peer, 7, 5000).await;
+        windows.update(peer, WindowUpdate::release(7));
+        windows.reserve(peer, 7, 1).await;
+        windows.finish(peer, 7);
+        // Streams not paced never wait.
+        windows.reserve(peer, 7, 1 << 30).await;
+    }
+
+    #[tokio::test]
+    async fn slow_reader_bounds_what_is_sent() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let counter = produced.clone();
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        server.register(
+            ServiceDesc::new("CountService", 1)
+                .server_streaming("Count", 1, move |_: Text| {
+                    let counter = counter.clone();
+                    async move {
+                        let responses = futures_util::stream::iter(0..100).map(move |i| {
+                            counter.fetch_add(1, Ordering::Relaxed);
+                            Ok::<_, Error>(Text(format!("{:<1000}", i)))
+                        });
+                        Ok::<_, Error>(responses)
+                    }
+                })
+                .client_streaming(
+                    "Sum",
+                    2,
+                    |requests: BoxStream<Result<Text, Error>>| async move {
+                        let parts: Vec<_> = requests.collect().await;
+                        let len: usize = parts.into_iter().map(|part| part.unwrap().0.len()).sum();
+                        Ok::<_, Error>(Text(len.to_string()))
+                    },
+                ),
+        );
+        server.set_flow_windows(Some(SMALL));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = Client::connect(addr).await.unwrap();
+        client.register_service("CountService", 1, &[("Count", 1), ("Sum", 2)]);
+        client.set_flow_windows(Some(SMALL));
+        let mut responses = client
+            .server_streaming::<_, Text>(
+                "CountService",
+                "Count",
+                &Text::new(""),
+                &CallOptions::default(),
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // A window of four frames, one over, and one waiting to be sent.
+        let sent = produced.load(Ordering::Relaxed);
+        assert!((4..=6).contains(&sent), "{} responses produced", sent);
+
+        let mut received = 0;
+        while let Some(response) = responses.next().await {
+            assert_eq!(response.unwrap().0.trim_end(), received.to_string());
+            received += 1;
+        }
+        assert_eq!(received, 100);
+
+        // Requests go as the server grants credit.
+        let parts = (0..50).map(|_| Text(" ".repeat(1000)));
+        let resp: Text = client
+            .client_streaming(
+                "CountService",
+                "Sum",
+                futures_util::stream::iter(parts),
+                &CallOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp, Text::new("50000"));
+    }
+}
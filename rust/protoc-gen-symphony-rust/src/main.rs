@@ -0,0 +1,305 @@
+//! protoc plugin generating Rust Symphony messages and aRPC stubs.
+//!
+//! ```bash
+//! protoc --plugin=protoc-gen-symphony-rust --symphony-rust_out=src/ echo.proto
+//! ```
+//!
+//! writes `echo.syn.rs`, which needs `arpc` with its `derive` feature.
+//! `--symphony-rust_opt=zero_copy` generates bytes and string fields that
+//! share the buffer a message is received in.
+
+use std::io::{self, Read, Write};
+
+use prost::Message;
+
+use symphony_build::descriptor::{
+    self, CodeGeneratorRequest, CodeGeneratorResponse, GeneratedFile,
+};
+use symphony_build::Generator;
+
+fn main() -> io::Result<()> {
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+    let request = CodeGeneratorRequest::decode(input.as_slice())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let response = run(&request);
+    io::stdout().write_all(&response.encode_to_vec())
+}
+
+fn run(request: &CodeGeneratorRequest) -> CodeGeneratorResponse {
+    let mut generator = Generator::new(&request.proto_file);
+    let mut response = CodeGeneratorResponse {
+        supported_features: Some(descriptor::FEATURE_PROTO3_OPTIONAL),
+        ..Default::default()
+    };
+    for option in request.parameter.iter().flat_map(|p| p.split(',')) {
+        match option.trim() {
+            "" => {}
+            "zero_copy" => {
+                generator.zero_copy(true);
+            }
+            other => {
+                response.error = Some(format!("unknown option {:?}", other));
+                return response;
+            }
+        }
+    }
+    for file in &request.proto_file {
+        if !request.file_to_generate.contains(&file.name().to_string()) {
+            continue;
+        }
+        match generator.generate(file) {
+            Ok((name, content)) => response.file.push(GeneratedFile {
+                name: Some(name),
+                content: Some(content),
+            }),
+            Err(e) => {
+                response.error = Some(format!("{}: {}", file.name(), e));
+                response.file.clear();
+                break;
+            }
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphony_build::descriptor::{
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, IS_PUBLIC_EXTENSION,
+        LABEL_REPEATED,
+    };
+
+    /// What this plugin generates for `test_proto()`, checked in so changes
+    /// to the output show up in review.
+    const GOLDEN: &str = include_str!("../testdata/test.syn.rs");
+
+    mod golden {
+        include!("../testdata/test.syn.rs");
+    }
+
+    // FieldDescriptorProto.Type values.
+    const DOUBLE: i32 = 1;
+    const FLOAT: i32 = 2;
+    const INT64: i32 = 3;
+    const UINT64: i32 = 4;
+    const INT32: i32 = 5;
+    const BOOL: i32 = 8;
+    const STRING: i32 = 9;
+    const MESSAGE: i32 = 11;
+    const BYTES: i32 = 12;
+    const UINT32: i32 = 13;
+
+    fn field(name: &str, ty: i32) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            r#type: Some(ty),
+            ..Default::default()
+        }
+    }
+
+    fn message(name: &str, type_name: &str) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            type_name: Some(type_name.to_string()),
+            ..field(name, MESSAGE)
+        }
+    }
+
+    fn public(mut field: FieldDescriptorProto) -> FieldDescriptorProto {
+        // is_public = true: key (50001 << 3 | varint), then 1.
+        let mut options = Vec::new();
+        prost::encoding::encode_key(
+            IS_PUBLIC_EXTENSION,
+            prost::encoding::WireType::Varint,
+            &mut options,
+        );
+        options.push(1);
+        field.options = Some(options);
+        field
+    }
+
+    fn repeated(mut field: FieldDescriptorProto) -> FieldDescriptorProto {
+        field.label = Some(LABEL_REPEATED);
+        field
+    }
+
+    fn descriptor(name: &str, field: Vec<FieldDescriptorProto>) -> DescriptorProto {
+        DescriptorProto {
+            name: Some(name.to_string()),
+            field,
+            ..Default::default()
+        }
+    }
+
+    /// The messages of protoc-gen-symphony's test.proto.
+    fn test_proto() -> FileDescriptorProto {
+        FileDescriptorProto {
+            name: Some("test.proto".to_string()),
+            package: Some("Test".to_string()),
+            message_type: vec![
+                descriptor(
+                    "Fixed",
+                    vec![
+                        public(field("f_int32", INT32)),
+                        field("f_int64", INT64),
+                        public(field("f_uint32", UINT32)),
+                        field("f_uint64", UINT64),
+                        public(field("f_bool", BOOL)),
+                        field("f_float", FLOAT),
+                        public(field("f_double", DOUBLE)),
+                    ],
+                ),
+                descriptor(
+                    "Var",
+                    vec![public(field("v_string", STRING)), field("v_bytes", BYTES)],
+                ),
+                descriptor(
+                    "RepeatedFixed",
+                    vec![
+                        repeated(field("r_int32", INT32)),
+                        public(repeated(field("r_int64", INT64))),
+                        repeated(field("r_uint32", UINT32)),
+                        public(repeated(field("r_uint64", UINT64))),
+                        repeated(field("r_float", FLOAT)),
+                        public(repeated(field("r_double", DOUBLE))),
+                        repeated(field("r_bool", BOOL)),
+                    ],
+                ),
+                descriptor(
+                    "RepeatedVar",
+                    vec![
+                        public(repeated(field("r_string", STRING))),
+                        repeated(field("r_bytes", BYTES)),
+                    ],
+                ),
+                descriptor(
+                    "Leaf",
+                    vec![public(field("leaf_id", INT32)), field("leaf_val", STRING)],
+                ),
+                descriptor("Level2", vec![public(message("leaf", ".Test.Leaf"))]),
+                descriptor(
+                    "Level1",
+                    vec![
+                        message("l2", ".Test.Level2"),
+                        public(field("l1_data", STRING)),
+                    ],
+                ),
+                descriptor(
+                    "Root",
+                    vec![
+                        public(message("l1", ".Test.Level1")),
+                        field("root_id", INT32),
+                    ],
+                ),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn generate() -> String {
+        let request = CodeGeneratorRequest {
+            file_to_generate: vec!["test.proto".to_string()],
+            proto_file: vec![test_proto()],
+            ..Default::default()
+        };
+        let response = run(&request);
+        assert_eq!(response.error, None);
+        assert_eq!(response.file.len(), 1);
+        assert_eq!(response.file[0].name.as_deref(), Some("test.syn.rs"));
+        response.file[0].content.clone().unwrap()
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Marshals `message` to `go`, the hex of what protoc-gen-symphony's
+    /// test.syn.go writes for it, and unmarshals it back.
+    fn assert_wire<M: arpc::Message + PartialEq + std::fmt::Debug>(message: M, go: &str) {
+        let data = message.marshal_symphony();
+        assert_eq!(data, hex(go));
+        assert_eq!(M::unmarshal_symphony(&data).unwrap(), message);
+    }
+
+    #[test]
+    fn generates_the_golden_file() {
+        assert_eq!(generate(), GOLDEN);
+        assert_eq!(
+            FileDescriptorProto::decode(golden::TEST_FILE_DESCRIPTOR).unwrap(),
+            test_proto()
+        );
+    }
+
+    #[test]
+    fn scalars_match_go() {
+        assert_wire(
+            golden::Fixed {
+                f_int32: -2,
+                f_int64: -3,
+                f_uint32: 4,
+                f_uint64: 5,
+                f_bool: true,
+                f_float: 1.5,
+                f_double: -0.25,
+            },
+            "011e0000000000000000000000feffffff0400000001000000000000d0bf01fdffffffffffffff05000000000000000000c03f",
+        );
+        assert_wire(
+            golden::Var {
+                v_string: "hi".to_string(),
+                v_bytes: vec![0xff, 0x00],
+            },
+            "0117000000000000000000000011000000020000006869010500000002000000ff00",
+        );
+    }
+
+    #[test]
+    fn repeated_fields_match_go() {
+        assert_wire(
+            golden::RepeatedFixed {
+                r_int32: vec![1, -1],
+                r_int64: vec![7],
+                r_uint32: vec![],
+                r_uint64: vec![],
+                r_float: vec![0.5],
+                r_double: vec![2.0],
+                r_bool: vec![true, false],
+            },
+            "013500000000000000000000001900000025000000290000000100000007000000000000000000000001000000000000000000004001110000001d00000021000000290000000200000001000000ffffffff00000000010000000000003f020000000100",
+        );
+        assert_wire(
+            golden::RepeatedVar {
+                r_string: vec!["a".to_string(), String::new()],
+                r_bytes: vec![vec![1], vec![2, 3]],
+            },
+            "011e000000000000000000000011000000020000000100000061000000000105000000020000000100000001020000000203",
+        );
+    }
+
+    #[test]
+    fn nested_messages_match_go() {
+        let leaf = golden::Leaf {
+            leaf_id: 9,
+            leaf_val: "x".to_string(),
+        };
+        assert_wire(
+            golden::Root {
+                l1: Some(golden::Level1 {
+                    l2: Some(golden::Level2 { leaf: Some(leaf) }),
+                    l1_data: "d".to_string(),
+                }),
+                root_id: 3,
+            },
+            "0165000000000000000000000011000000500000000116000000000000000000000011000000010000006401050000003100000001300000000000000000000000110000001b000000011100000000000000000000000900000001050000000100000078010103000000",
+        );
+        // An unset message has offset 0 and no payload.
+        assert_wire(
+            golden::Root::default(),
+            "01110000000000000000000000000000000100000000",
+        );
+    }
+}
@@ -0,0 +1,93 @@
+// Code generated by protoc-gen-symphony-rust. DO NOT EDIT.
+// source: test.proto
+
+/// The encoded descriptor of `test.proto`.
+pub const TEST_FILE_DESCRIPTOR: &[u8] = b"\x0a\x0a\x74\x65\x73\x74\x2e\x70\x72\x6f\x74\x6f\x12\x04\x54\x65\x73\x74\x22\x7c\x0a\x05\x46\x69\x78\x65\x64\x12\x11\x0a\x07\x66\
+    \x5f\x69\x6e\x74\x33\x32\x28\x05\x42\x04\x88\xb5\x18\x01\x12\x0b\x0a\x07\x66\x5f\x69\x6e\x74\x36\x34\x28\x03\x12\x12\x0a\x08\x66\
+    \x5f\x75\x69\x6e\x74\x33\x32\x28\x0d\x42\x04\x88\xb5\x18\x01\x12\x0c\x0a\x08\x66\x5f\x75\x69\x6e\x74\x36\x34\x28\x04\x12\x10\x0a\
+    \x06\x66\x5f\x62\x6f\x6f\x6c\x28\x08\x42\x04\x88\xb5\x18\x01\x12\x0b\x0a\x07\x66\x5f\x66\x6c\x6f\x61\x74\x28\x02\x12\x12\x0a\x08\
+    \x66\x5f\x64\x6f\x75\x62\x6c\x65\x28\x01\x42\x04\x88\xb5\x18\x01\x22\x26\x0a\x03\x56\x61\x72\x12\x12\x0a\x08\x76\x5f\x73\x74\x72\
+    \x69\x6e\x67\x28\x09\x42\x04\x88\xb5\x18\x01\x12\x0b\x0a\x07\x76\x5f\x62\x79\x74\x65\x73\x28\x0c\x22\x8c\x01\x0a\x0d\x52\x65\x70\
+    \x65\x61\x74\x65\x64\x46\x69\x78\x65\x64\x12\x0d\x0a\x07\x72\x5f\x69\x6e\x74\x33\x32\x20\x03\x28\x05\x12\x13\x0a\x07\x72\x5f\x69\
+    \x6e\x74\x36\x34\x20\x03\x28\x03\x42\x04\x88\xb5\x18\x01\x12\x0e\x0a\x08\x72\x5f\x75\x69\x6e\x74\x33\x32\x20\x03\x28\x0d\x12\x14\
+    \x0a\x08\x72\x5f\x75\x69\x6e\x74\x36\x34\x20\x03\x28\x04\x42\x04\x88\xb5\x18\x01\x12\x0d\x0a\x07\x72\x5f\x66\x6c\x6f\x61\x74\x20\
+    \x03\x28\x02\x12\x14\x0a\x08\x72\x5f\x64\x6f\x75\x62\x6c\x65\x20\x03\x28\x01\x42\x04\x88\xb5\x18\x01\x12\x0c\x0a\x06\x72\x5f\x62\
+    \x6f\x6f\x6c\x20\x03\x28\x08\x22\x32\x0a\x0b\x52\x65\x70\x65\x61\x74\x65\x64\x56\x61\x72\x12\x14\x0a\x08\x72\x5f\x73\x74\x72\x69\
+    \x6e\x67\x20\x03\x28\x09\x42\x04\x88\xb5\x18\x01\x12\x0d\x0a\x07\x72\x5f\x62\x79\x74\x65\x73\x20\x03\x28\x0c\x22\x27\x0a\x04\x4c\
+    \x65\x61\x66\x12\x11\x0a\x07\x6c\x65\x61\x66\x5f\x69\x64\x28\x05\x42\x04\x88\xb5\x18\x01\x12\x0c\x0a\x08\x6c\x65\x61\x66\x5f\x76\
+    \x61\x6c\x28\x09\x22\x24\x0a\x06\x4c\x65\x76\x65\x6c\x32\x12\x1a\x0a\x04\x6c\x65\x61\x66\x28\x0b\x32\x0a\x2e\x54\x65\x73\x74\x2e\
+    \x4c\x65\x61\x66\x42\x04\x88\xb5\x18\x01\x22\x31\x0a\x06\x4c\x65\x76\x65\x6c\x31\x12\x14\x0a\x02\x6c\x32\x28\x0b\x32\x0c\x2e\x54\
+    \x65\x73\x74\x2e\x4c\x65\x76\x65\x6c\x32\x12\x11\x0a\x07\x6c\x31\x5f\x64\x61\x74\x61\x28\x09\x42\x04\x88\xb5\x18\x01\x22\x2f\x0a\
+    \x04\x52\x6f\x6f\x74\x12\x1a\x0a\x02\x6c\x31\x28\x0b\x32\x0c\x2e\x54\x65\x73\x74\x2e\x4c\x65\x76\x65\x6c\x31\x42\x04\x88\xb5\x18\
+    \x01\x12\x0b\x0a\x07\x72\x6f\x6f\x74\x5f\x69\x64\x28\x05";
+
+#[derive(Debug, Clone, PartialEq, Default, ::arpc::SymphonyMessage)]
+pub struct Fixed {
+    #[symphony(public)]
+    pub f_int32: i32,
+    pub f_int64: i64,
+    #[symphony(public)]
+    pub f_uint32: u32,
+    pub f_uint64: u64,
+    #[symphony(public)]
+    pub f_bool: bool,
+    pub f_float: f32,
+    #[symphony(public)]
+    pub f_double: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, ::arpc::SymphonyMessage)]
+pub struct Var {
+    #[symphony(public)]
+    pub v_string: String,
+    pub v_bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, ::arpc::SymphonyMessage)]
+pub struct RepeatedFixed {
+    pub r_int32: Vec<i32>,
+    #[symphony(public)]
+    pub r_int64: Vec<i64>,
+    pub r_uint32: Vec<u32>,
+    #[symphony(public)]
+    pub r_uint64: Vec<u64>,
+    pub r_float: Vec<f32>,
+    #[symphony(public)]
+    pub r_double: Vec<f64>,
+    pub r_bool: Vec<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, ::arpc::SymphonyMessage)]
+pub struct RepeatedVar {
+    #[symphony(public)]
+    pub r_string: Vec<String>,
+    pub r_bytes: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, ::arpc::SymphonyMessage)]
+pub struct Leaf {
+    #[symphony(public)]
+    pub leaf_id: i32,
+    pub leaf_val: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, ::arpc::SymphonyMessage)]
+pub struct Level2 {
+    #[symphony(public, message)]
+    pub leaf: Option<Leaf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, ::arpc::SymphonyMessage)]
+pub struct Level1 {
+    #[symphony(message)]
+    pub l2: Option<Level2>,
+    #[symphony(public)]
+    pub l1_data: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, ::arpc::SymphonyMessage)]
+pub struct Root {
+    #[symphony(public, message)]
+    pub l1: Option<Level1>,
+    pub root_id: i32,
+}
@@ -0,0 +1,174 @@
+//! The parts of `descriptor.proto` and `plugin.proto` the generator reads.
+//!
+//! Options are kept as raw bytes: `is_public` is an extension, which a
+//! typed `FieldOptions` would drop as an unknown field.
+
+use prost::encoding::{decode_key, decode_varint, skip_field, DecodeContext, WireType};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CodeGeneratorRequest {
+    #[prost(string, repeated, tag = "1")]
+    pub file_to_generate: Vec<String>,
+    /// The options after `--symphony-rust_opt=`, comma-separated.
+    #[prost(string, optional, tag = "2")]
+    pub parameter: Option<String>,
+    #[prost(message, repeated, tag = "15")]
+    pub proto_file: Vec<FileDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CodeGeneratorResponse {
+    #[prost(string, optional, tag = "1")]
+    pub error: Option<String>,
+    #[prost(uint64, optional, tag = "2")]
+    pub supported_features: Option<u64>,
+    #[prost(message, repeated, tag = "15")]
+    pub file: Vec<GeneratedFile>,
+}
+
+/// `CodeGeneratorResponse.FEATURE_PROTO3_OPTIONAL`.
+pub const FEATURE_PROTO3_OPTIONAL: u64 = 1;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GeneratedFile {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(string, optional, tag = "15")]
+    pub content: Option<String>,
+}
+
+/// What `protoc --descriptor_set_out` writes.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FileDescriptorSet {
+    #[prost(message, repeated, tag = "1")]
+    pub file: Vec<FileDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FileDescriptorProto {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub package: Option<String>,
+    #[prost(message, repeated, tag = "4")]
+    pub message_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag = "5")]
+    pub enum_type: Vec<EnumDescriptorProto>,
+    #[prost(message, repeated, tag = "6")]
+    pub service: Vec<ServiceDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DescriptorProto {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(message, repeated, tag = "2")]
+    pub field: Vec<FieldDescriptorProto>,
+    #[prost(message, repeated, tag = "3")]
+    pub nested_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag = "4")]
+    pub enum_type: Vec<EnumDescriptorProto>,
+    #[prost(bytes = "vec", optional, tag = "7")]
+    pub options: Option<Vec<u8>>,
+}
+
+impl DescriptorProto {
+    /// Whether this is the entry type protoc synthesizes for a map field.
+    pub fn is_map_entry(&self) -> bool {
+        // MessageOptions.map_entry
+        option_flag(self.options.as_deref(), 7)
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FieldDescriptorProto {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(int32, optional, tag = "3")]
+    pub number: Option<i32>,
+    #[prost(int32, optional, tag = "4")]
+    pub label: Option<i32>,
+    #[prost(int32, optional, tag = "5")]
+    pub r#type: Option<i32>,
+    #[prost(string, optional, tag = "6")]
+    pub type_name: Option<String>,
+    #[prost(bytes = "vec", optional, tag = "8")]
+    pub options: Option<Vec<u8>>,
+}
+
+/// `FieldDescriptorProto.Label.LABEL_REPEATED`.
+pub const LABEL_REPEATED: i32 = 3;
+
+/// The `is_public` field option, extension 50001 of `FieldOptions`.
+pub const IS_PUBLIC_EXTENSION: u32 = 50001;
+
+impl FieldDescriptorProto {
+    pub fn is_repeated(&self) -> bool {
+        self.label == Some(LABEL_REPEATED)
+    }
+
+    pub fn is_public(&self) -> bool {
+        option_flag(self.options.as_deref(), IS_PUBLIC_EXTENSION)
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EnumDescriptorProto {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(message, repeated, tag = "2")]
+    pub value: Vec<EnumValueDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EnumValueDescriptorProto {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(int32, optional, tag = "2")]
+    pub number: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ServiceDescriptorProto {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(message, repeated, tag = "2")]
+    pub method: Vec<MethodDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MethodDescriptorProto {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub input_type: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub output_type: Option<String>,
+    #[prost(bool, optional, tag = "5")]
+    pub client_streaming: Option<bool>,
+    #[prost(bool, optional, tag = "6")]
+    pub server_streaming: Option<bool>,
+}
+
+/// Whether the encoded options message sets the bool field `number`; the
+/// last occurrence wins, as when protobuf parses it.
+pub fn option_flag(options: Option<&[u8]>, number: u32) -> bool {
+    let Some(mut buf) = options else {
+        return false;
+    };
+    let mut set = false;
+    while !buf.is_empty() {
+        let Ok((tag, wire_type)) = decode_key(&mut buf) else {
+            return false;
+        };
+        if tag == number && wire_type == WireType::Varint {
+            match decode_varint(&mut buf) {
+                Ok(value) => set = value != 0,
+                Err(_) => return false,
+            }
+        } else if skip_field(wire_type, tag, &mut buf, DecodeContext::default()).is_err() {
+            return false;
+        }
+    }
+    set
+}
@@ -0,0 +1,744 @@
+//! Generates the Rust counterpart of what `protoc-gen-symphony` and
+//! `protoc-gen-arpc` generate for Go.
+//!
+//! Messages become structs deriving `arpc::SymphonyMessage`, with fields in
+//! declaration order like the Go marshaler, so the two interoperate. Each
+//! service gets its IDs (numbered from 1 in declaration order, as in Go), a
+//! typed client, and a trait for the server with a wrapper implementing
+//! `arpc::Service`. Server-streaming methods return `arpc::Streaming` to
+//! the caller and take a stream of responses from the server;
+//! client-streaming methods take a stream of requests from the caller and
+//! hand one to the server; bidi-streaming methods give the caller an
+//! `arpc::StreamSender` and an `arpc::Streaming`, and the server both
+//! streams.
+//!
+//! Each file also gets its encoded descriptor, `<FILE>_FILE_DESCRIPTOR`,
+//! which its services hand to `arpc::reflection`. It is re-encoded from the
+//! parts of the descriptor the generator reads, which are those tools need
+//! to encode the messages.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use heck::{ToShoutySnakeCase, ToSnakeCase, ToUpperCamelCase};
+use prost::Message;
+
+use crate::descriptor::{
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    ServiceDescriptorProto,
+};
+
+/// `FieldDescriptorProto.Type` values.
+mod field_type {
+    pub const DOUBLE: i32 = 1;
+    pub const FLOAT: i32 = 2;
+    pub const INT64: i32 = 3;
+    pub const UINT64: i32 = 4;
+    pub const INT32: i32 = 5;
+    pub const BOOL: i32 = 8;
+    pub const STRING: i32 = 9;
+    pub const MESSAGE: i32 = 11;
+    pub const BYTES: i32 = 12;
+    pub const UINT32: i32 = 13;
+    pub const ENUM: i32 = 14;
+}
+
+/// How a method exchanges messages, from the streaming flags of its
+/// descriptor.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MethodKind {
+    Unary,
+    ServerStreaming,
+    ClientStreaming,
+    BidiStreaming,
+}
+
+/// A message or enum type any input file declares.
+struct TypeInfo {
+    package: String,
+    ident: String,
+    map_entry: bool,
+}
+
+/// Generates the `.syn.rs` files of the files to generate.
+pub struct Generator {
+    /// By fully qualified name, with the leading dot.
+    types: HashMap<String, TypeInfo>,
+    zero_copy: bool,
+}
+
+impl Generator {
+    /// Indexes the types of `files`, which include the dependencies of the
+    /// files to generate.
+    pub fn new(files: &[FileDescriptorProto]) -> Generator {
+        let mut types = HashMap::new();
+        for file in files {
+            let package = file.package();
+            let scope = if package.is_empty() {
+                String::new()
+            } else {
+                format!(".{}", package)
+            };
+            for message in &file.message_type {
+                index_message(&mut types, package, &scope, "", message);
+            }
+            for e in &file.enum_type {
+                types.insert(
+                    format!("{}.{}", scope, e.name()),
+                    TypeInfo {
+                        package: package.to_string(),
+                        ident: e.name().to_upper_camel_case(),
+                        map_entry: false,
+                    },
+                );
+            }
+        }
+        Generator {
+            types,
+            zero_copy: false,
+        }
+    }
+
+    /// Generates bytes and string fields as `arpc::Bytes` and
+    /// `arpc::symphony::ByteString`, which share the buffer a message is
+    /// received in instead of being copied out of it.
+    pub fn zero_copy(&mut self, on: bool) -> &mut Generator {
+        self.zero_copy = on;
+        self
+    }
+
+    /// Returns the name and content of the file generated for `file`.
+    pub fn generate(&self, file: &FileDescriptorProto) -> Result<(String, String), String> {
+        let name = file.name.as_deref().unwrap_or_default();
+        let mut out = String::new();
+        writeln!(
+            out,
+            "// Code generated by protoc-gen-symphony-rust. DO NOT EDIT."
+        )
+        .unwrap();
+        writeln!(out, "// source: {}", name).unwrap();
+
+        let stem = name.strip_suffix(".proto").unwrap_or(name);
+        let base = stem.rsplit('/').next().unwrap_or(stem);
+        let descriptor = format!("{}_FILE_DESCRIPTOR", base.to_shouty_snake_case());
+        writeln!(out).unwrap();
+        writeln!(out, "/// The encoded descriptor of `{}`.", name).unwrap();
+        writeln!(
+            out,
+            "pub const {}: &[u8] = {};",
+            descriptor,
+            byte_string(&file.encode_to_vec())
+        )
+        .unwrap();
+
+        for message in &file.message_type {
+            self.message(&mut out, file.package(), "", message)?;
+        }
+        for e in &file.enum_type {
+            enumeration(&mut out, "", e);
+        }
+        for (index, service) in file.service.iter().enumerate() {
+            let id = index as u32 + 1;
+            self.service(&mut out, file.package(), id, &descriptor, service)?;
+        }
+
+        Ok((format!("{}.syn.rs", stem), out))
+    }
+
+    fn message(
+        &self,
+        out: &mut String,
+        package: &str,
+        parent: &str,
+        message: &DescriptorProto,
+    ) -> Result<(), String> {
+        let ident = format!("{}{}", parent, message.name().to_upper_camel_case());
+        if message.is_map_entry() {
+            return Ok(());
+        }
+
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "#[derive(Debug, Clone, PartialEq, Default, ::arpc::SymphonyMessage)]"
+        )
+        .unwrap();
+        writeln!(out, "pub struct {} {{", ident).unwrap();
+        for field in &message.field {
+            let (ty, nested) = self
+                .field_type(package, field)
+                .map_err(|e| format!("{}.{}: {}", ident, field.name(), e))?;
+            match (field.is_public(), nested) {
+                (true, true) => writeln!(out, "    #[symphony(public, message)]").unwrap(),
+                (true, false) => writeln!(out, "    #[symphony(public)]").unwrap(),
+                (false, true) => writeln!(out, "    #[symphony(message)]").unwrap(),
+                (false, false) => {}
+            }
+            writeln!(out, "    pub {}: {},", field_ident(field.name()), ty).unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+
+        for nested in &message.nested_type {
+            self.message(out, package, &ident, nested)?;
+        }
+        for e in &message.enum_type {
+            enumeration(out, &ident, e);
+        }
+        Ok(())
+    }
+
+    /// The Rust type of a field, and whether it holds nested messages.
+    fn field_type(
+        &self,
+        package: &str,
+        field: &FieldDescriptorProto,
+    ) -> Result<(String, bool), String> {
+        let scalar = match field.r#type() {
+            field_type::DOUBLE => "f64",
+            field_type::FLOAT => "f32",
+            field_type::INT64 => "i64",
+            field_type::UINT64 => "u64",
+            field_type::INT32 | field_type::ENUM => "i32",
+            field_type::BOOL => "bool",
+            field_type::STRING if self.zero_copy => "::arpc::symphony::ByteString",
+            field_type::STRING => "String",
+            field_type::BYTES if self.zero_copy => "::arpc::Bytes",
+            field_type::BYTES => "Vec<u8>",
+            field_type::UINT32 => "u32",
+            field_type::MESSAGE => {
+                let info = self.resolve(package, field.type_name())?;
+                if info.map_entry {
+                    return Err("map fields are not supported".to_string());
+                }
+                return Ok(if field.is_repeated() {
+                    (format!("Vec<{}>", info.ident), true)
+                } else {
+                    (format!("Option<{}>", info.ident), true)
+                });
+            }
+            other => return Err(format!("field type {} is not supported", other)),
+        };
+        Ok(if field.is_repeated() {
+            (format!("Vec<{}>", scalar), false)
+        } else {
+            (scalar.to_string(), false)
+        })
+    }
+
+    fn resolve(&self, package: &str, type_name: &str) -> Result<&TypeInfo, String> {
+        let info = self
+            .types
+            .get(type_name)
+            .ok_or_else(|| format!("unknown type {}", type_name))?;
+        if info.package != package {
+            return Err(format!(
+                "{} is in another package, which is not supported",
+                type_name
+            ));
+        }
+        Ok(info)
+    }
+
+    fn service(
+        &self,
+        out: &mut String,
+        package: &str,
+        id: u32,
+        descriptor: &str,
+        service: &ServiceDescriptorProto,
+    ) -> Result<(), String> {
+        // Registered under the Go names (`GoName`), which camel-case the
+        // proto names.
+        let name = service.name().to_upper_camel_case();
+        let ident = name.clone();
+        let consts = name.to_shouty_snake_case();
+
+        let mut methods = Vec::new();
+        for method in &service.method {
+            let kind = match (method.client_streaming(), method.server_streaming()) {
+                (false, false) => MethodKind::Unary,
+                (false, true) => MethodKind::ServerStreaming,
+                (true, false) => MethodKind::ClientStreaming,
+                (true, true) => MethodKind::BidiStreaming,
+            };
+            let input = &self.resolve(package, method.input_type())?.ident;
+            let output = &self.resolve(package, method.output_type())?.ident;
+            methods.push((
+                method.name().to_upper_camel_case(),
+                field_ident(method.name()),
+                input,
+                output,
+                kind,
+            ));
+        }
+
+        writeln!(out).unwrap();
+        writeln!(out, "/// Service ID of `{}`.", name).unwrap();
+        writeln!(out, "pub const {}_ID: u32 = {};", consts, id).unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "/// Method names and IDs of `{}`.", name).unwrap();
+        writeln!(out, "pub const {}_METHODS: &[(&str, u32)] = &[", consts).unwrap();
+        for (index, (method, ..)) in methods.iter().enumerate() {
+            writeln!(out, "    (\"{}\", {}),", method, index + 1).unwrap();
+        }
+        writeln!(out, "];").unwrap();
+
+        writeln!(out).unwrap();
+        writeln!(out, "/// Client for `{}`.", name).unwrap();
+        writeln!(out, "pub struct {}Client {{", ident).unwrap();
+        writeln!(out, "    client: ::std::sync::Arc<::arpc::Client>,").unwrap();
+        writeln!(out, "}}").unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "impl {}Client {{", ident).unwrap();
+        writeln!(
+            out,
+            "    /// Wraps `client`, registering the service's IDs with it."
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "    pub fn new(client: ::std::sync::Arc<::arpc::Client>) -> {}Client {{",
+            ident
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "        client.register_service(\"{}\", {}_ID, {}_METHODS);",
+            name, consts, consts
+        )
+        .unwrap();
+        writeln!(out, "        {}Client {{ client }}", ident).unwrap();
+        writeln!(out, "    }}").unwrap();
+        for (method, fn_ident, input, output, kind) in &methods {
+            writeln!(out).unwrap();
+            match kind {
+                MethodKind::Unary => {
+                    writeln!(
+                        out,
+                        "    pub async fn {}(&self, req: &{}) -> ::std::result::Result<{}, ::arpc::Error> {{",
+                        fn_ident, input, output
+                    )
+                    .unwrap();
+                    writeln!(
+                        out,
+                        "        self.client.call(\"{}\", \"{}\", req).await",
+                        name, method
+                    )
+                    .unwrap();
+                }
+                MethodKind::ServerStreaming => {
+                    writeln!(
+                        out,
+                        "    pub async fn {}(&self, req: &{}) -> ::std::result::Result<::arpc::Streaming<{}>, ::arpc::Error> {{",
+                        fn_ident, input, output
+                    )
+                    .unwrap();
+                    writeln!(
+                        out,
+                        "        self.client.server_streaming(\"{}\", \"{}\", req, &::arpc::CallOptions::default()).await",
+                        name, method
+                    )
+                    .unwrap();
+                }
+                MethodKind::ClientStreaming => {
+                    writeln!(
+                        out,
+                        "    pub async fn {}(&self, requests: impl ::arpc::Stream<Item = {}>) -> ::std::result::Result<{}, ::arpc::Error> {{",
+                        fn_ident, input, output
+                    )
+                    .unwrap();
+                    writeln!(
+                        out,
+                        "        self.client.client_streaming(\"{}\", \"{}\", requests, &::arpc::CallOptions::default()).await",
+                        name, method
+                    )
+                    .unwrap();
+                }
+                MethodKind::BidiStreaming => {
+                    writeln!(
+                        out,
+                        "    pub fn {}(&self) -> ::std::result::Result<(::arpc::StreamSender<{}>, ::arpc::Streaming<{}>), ::arpc::Error> {{",
+                        fn_ident, input, output
+                    )
+                    .unwrap();
+                    writeln!(
+                        out,
+                        "        self.client.bidi_streaming(\"{}\", \"{}\", &::arpc::CallOptions::default())",
+                        name, method
+                    )
+                    .unwrap();
+                }
+            }
+            writeln!(out, "    }}").unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "/// Server API for `{}`; register it wrapped in [`{}Server`].",
+            name, ident
+        )
+        .unwrap();
+        writeln!(out, "pub trait {}: Send + Sync + 'static {{", ident).unwrap();
+        for (_, fn_ident, input, output, kind) in &methods {
+            let (param, output) = match kind {
+                MethodKind::Unary => (format!("req: {}", input), output.to_string()),
+                MethodKind::ServerStreaming => (format!("req: {}", input), stream_of(output)),
+                MethodKind::ClientStreaming => (
+                    format!("requests: {}", stream_of(input)),
+                    output.to_string(),
+                ),
+                MethodKind::BidiStreaming => {
+                    (format!("requests: {}", stream_of(input)), stream_of(output))
+                }
+            };
+            writeln!(
+                out,
+                "    fn {}(self: ::std::sync::Arc<Self>, {}) -> ::arpc::BoxFuture<::std::result::Result<{}, ::arpc::Error>>;",
+                fn_ident, param, output
+            )
+            .unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+
+        writeln!(out).unwrap();
+        writeln!(out, "/// Serves a [`{}`] implementation.", ident).unwrap();
+        writeln!(
+            out,
+            "pub struct {}Server<T>(pub ::std::sync::Arc<T>);",
+            ident
+        )
+        .unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "impl<T: {}> {}Server<T> {{", ident, ident).unwrap();
+        writeln!(out, "    pub fn new(service: T) -> {}Server<T> {{", ident).unwrap();
+        writeln!(
+            out,
+            "        {}Server(::std::sync::Arc::new(service))",
+            ident
+        )
+        .unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out, "}}").unwrap();
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "impl<T: {}> ::arpc::Service for {}Server<T> {{",
+            ident, ident
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "    fn describe(self: ::std::sync::Arc<Self>) -> ::arpc::ServiceDesc {{"
+        )
+        .unwrap();
+        if methods.is_empty() {
+            writeln!(out, "        let _ = self;").unwrap();
+        }
+        writeln!(
+            out,
+            "        ::arpc::ServiceDesc::new(\"{}\", {}_ID)",
+            name, consts
+        )
+        .unwrap();
+        for (index, (method, fn_ident, input, _, kind)) in methods.iter().enumerate() {
+            let (register, param) = match kind {
+                MethodKind::Unary => ("method", format!("req: {}", input)),
+                MethodKind::ServerStreaming => ("server_streaming", format!("req: {}", input)),
+                MethodKind::ClientStreaming => {
+                    ("client_streaming", format!("req: {}", stream_of(input)))
+                }
+                MethodKind::BidiStreaming => {
+                    ("bidi_streaming", format!("req: {}", stream_of(input)))
+                }
+            };
+            writeln!(
+                out,
+                "            .{}(\"{}\", {}, {{",
+                register,
+                method,
+                index + 1
+            )
+            .unwrap();
+            writeln!(out, "                let service = self.0.clone();").unwrap();
+            writeln!(
+                out,
+                "                move |{}| service.clone().{}(req)",
+                param, fn_ident
+            )
+            .unwrap();
+            writeln!(out, "            }})").unwrap();
+        }
+        writeln!(out, "            .file_descriptor({})", descriptor).unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out, "}}").unwrap();
+        Ok(())
+    }
+}
+
+fn index_message(
+    types: &mut HashMap<String, TypeInfo>,
+    package: &str,
+    scope: &str,
+    parent: &str,
+    message: &DescriptorProto,
+) {
+    let scope = format!("{}.{}", scope, message.name());
+    let ident = format!("{}{}", parent, message.name().to_upper_camel_case());
+    for nested in &message.nested_type {
+        index_message(types, package, &scope, &ident, nested);
+    }
+    for e in &message.enum_type {
+        types.insert(
+            format!("{}.{}", scope, e.name()),
+            TypeInfo {
+                package: package.to_string(),
+                ident: format!("{}{}", ident, e.name().to_upper_camel_case()),
+                map_entry: false,
+            },
+        );
+    }
+    types.insert(
+        scope,
+        TypeInfo {
+            package: package.to_string(),
+            ident,
+            map_entry: message.is_map_entry(),
+        },
+    );
+}
+
+/// Enums are carried as `i32` fields, as in Go; the generated enum names
+/// their values.
+fn enumeration(out: &mut String, parent: &str, e: &EnumDescriptorProto) {
+    let ident = format!("{}{}", parent, e.name().to_upper_camel_case());
+    writeln!(out).unwrap();
+    writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]").unwrap();
+    writeln!(out, "#[repr(i32)]").unwrap();
+    writeln!(out, "pub enum {} {{", ident).unwrap();
+    let mut seen = Vec::new();
+    for value in &e.value {
+        // Aliases (`allow_alias`) keep the first name of a number.
+        if seen.contains(&value.number()) {
+            continue;
+        }
+        seen.push(value.number());
+        writeln!(
+            out,
+            "    {} = {},",
+            value.name().to_upper_camel_case(),
+            value.number()
+        )
+        .unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+}
+
+/// A byte string literal of `bytes`, 32 to a line.
+fn byte_string(bytes: &[u8]) -> String {
+    let mut literal = String::from("b\"");
+    for (index, byte) in bytes.iter().enumerate() {
+        if index > 0 && index % 32 == 0 {
+            literal.push_str("\\\n    ");
+        }
+        write!(literal, "\\x{:02x}", byte).unwrap();
+    }
+    literal.push('"');
+    literal
+}
+
+/// The stream type a handler takes or returns for messages of `ident`.
+fn stream_of(ident: &str) -> String {
+    format!(
+        "::arpc::BoxStream<::std::result::Result<{}, ::arpc::Error>>",
+        ident
+    )
+}
+
+/// A Rust identifier for a proto field or method name.
+fn field_ident(name: &str) -> String {
+    let ident = name.to_snake_case();
+    match ident.as_str() {
+        "self" | "super" | "crate" => format!("{}_", ident),
+        "as" | "async" | "await" | "break" | "const" | "continue" | "dyn" | "else" | "enum"
+        | "extern" | "false" | "fn" | "for" | "if" | "impl" | "in" | "let" | "loop" | "match"
+        | "mod" | "move" | "mut" | "pub" | "ref" | "return" | "static" | "struct" | "trait"
+        | "true" | "type" | "unsafe" | "use" | "where" | "while" | "abstract" | "become"
+        | "box" | "do" | "final" | "macro" | "override" | "priv" | "try" | "typeof" | "unsized"
+        | "virtual" | "yield" => format!("r#{}", ident),
+        _ => ident,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::{
+        FieldDescriptorProto, MethodDescriptorProto, IS_PUBLIC_EXTENSION, LABEL_REPEATED,
+    };
+
+    fn field(name: &str, ty: i32, type_name: Option<&str>) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            r#type: Some(ty),
+            type_name: type_name.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn public(mut field: FieldDescriptorProto) -> FieldDescriptorProto {
+        // is_public = true: key (50001 << 3 | varint), then 1.
+        let mut options = Vec::new();
+        prost::encoding::encode_key(
+            IS_PUBLIC_EXTENSION,
+            prost::encoding::WireType::Varint,
+            &mut options,
+        );
+        options.push(1);
+        field.options = Some(options);
+        field
+    }
+
+    fn repeated(mut field: FieldDescriptorProto) -> FieldDescriptorProto {
+        field.label = Some(LABEL_REPEATED);
+        field
+    }
+
+    fn echo_proto() -> FileDescriptorProto {
+        let request = DescriptorProto {
+            name: Some("EchoRequest".to_string()),
+            field: vec![
+                public(field("id", field_type::INT32, None)),
+                field("content", field_type::STRING, None),
+                repeated(field("tags", field_type::STRING, None)),
+                public(field(
+                    "leaf",
+                    field_type::MESSAGE,
+                    Some(".echo.EchoRequest.Leaf"),
+                )),
+                field("type", field_type::BYTES, None),
+            ],
+            nested_type: vec![DescriptorProto {
+                name: Some("Leaf".to_string()),
+                field: vec![field("value", field_type::DOUBLE, None)],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let response = DescriptorProto {
+            name: Some("EchoResponse".to_string()),
+            field: vec![field("content", field_type::STRING, None)],
+            ..Default::default()
+        };
+        FileDescriptorProto {
+            name: Some("echo.proto".to_string()),
+            package: Some("echo".to_string()),
+            message_type: vec![request, response],
+            service: vec![ServiceDescriptorProto {
+                name: Some("EchoService".to_string()),
+                method: vec![
+                    MethodDescriptorProto {
+                        name: Some("Echo".to_string()),
+                        input_type: Some(".echo.EchoRequest".to_string()),
+                        output_type: Some(".echo.EchoResponse".to_string()),
+                        ..Default::default()
+                    },
+                    MethodDescriptorProto {
+                        name: Some("Repeat".to_string()),
+                        input_type: Some(".echo.EchoRequest".to_string()),
+                        output_type: Some(".echo.EchoResponse".to_string()),
+                        server_streaming: Some(true),
+                        ..Default::default()
+                    },
+                    MethodDescriptorProto {
+                        name: Some("Collect".to_string()),
+                        input_type: Some(".echo.EchoRequest".to_string()),
+                        output_type: Some(".echo.EchoResponse".to_string()),
+                        client_streaming: Some(true),
+                        ..Default::default()
+                    },
+                    MethodDescriptorProto {
+                        name: Some("Chat".to_string()),
+                        input_type: Some(".echo.EchoRequest".to_string()),
+                        output_type: Some(".echo.EchoResponse".to_string()),
+                        client_streaming: Some(true),
+                        server_streaming: Some(true),
+                    },
+                ],
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn generates_messages_and_stubs() {
+        let file = echo_proto();
+        let (name, code) = Generator::new(std::slice::from_ref(&file))
+            .generate(&file)
+            .unwrap();
+        assert_eq!(name, "echo.syn.rs");
+        for expected in [
+            "pub struct EchoRequest {\n    #[symphony(public)]\n    pub id: i32,\n    pub content: String,\n    pub tags: Vec<String>,\n    #[symphony(public, message)]\n    pub leaf: Option<EchoRequestLeaf>,\n    pub r#type: Vec<u8>,\n}",
+            "pub struct EchoRequestLeaf {\n    pub value: f64,\n}",
+            "pub const ECHO_SERVICE_ID: u32 = 1;",
+            "(\"Echo\", 1),",
+            "pub async fn echo(&self, req: &EchoRequest) -> ::std::result::Result<EchoResponse, ::arpc::Error> {",
+            "fn echo(self: ::std::sync::Arc<Self>, req: EchoRequest)",
+            "impl<T: EchoService> ::arpc::Service for EchoServiceServer<T> {",
+            "pub async fn repeat(&self, req: &EchoRequest) -> ::std::result::Result<::arpc::Streaming<EchoResponse>, ::arpc::Error> {",
+            "fn repeat(self: ::std::sync::Arc<Self>, req: EchoRequest) -> ::arpc::BoxFuture<::std::result::Result<::arpc::BoxStream<::std::result::Result<EchoResponse, ::arpc::Error>>, ::arpc::Error>>;",
+            ".server_streaming(\"Repeat\", 2, {",
+            "pub async fn collect(&self, requests: impl ::arpc::Stream<Item = EchoRequest>) -> ::std::result::Result<EchoResponse, ::arpc::Error> {",
+            "fn collect(self: ::std::sync::Arc<Self>, requests: ::arpc::BoxStream<::std::result::Result<EchoRequest, ::arpc::Error>>) -> ::arpc::BoxFuture<::std::result::Result<EchoResponse, ::arpc::Error>>;",
+            ".client_streaming(\"Collect\", 3, {",
+            "pub fn chat(&self) -> ::std::result::Result<(::arpc::StreamSender<EchoRequest>, ::arpc::Streaming<EchoResponse>), ::arpc::Error> {",
+            "fn chat(self: ::std::sync::Arc<Self>, requests: ::arpc::BoxStream<::std::result::Result<EchoRequest, ::arpc::Error>>) -> ::arpc::BoxFuture<::std::result::Result<::arpc::BoxStream<::std::result::Result<EchoResponse, ::arpc::Error>>, ::arpc::Error>>;",
+            ".bidi_streaming(\"Chat\", 4, {",
+            "            .file_descriptor(ECHO_FILE_DESCRIPTOR)\n    }",
+        ] {
+            assert!(code.contains(expected), "missing {:?} in:\n{}", expected, code);
+        }
+
+        // The descriptor decodes back to the file.
+        let start = code.find("ECHO_FILE_DESCRIPTOR: &[u8] = b\"").unwrap();
+        let literal = &code[start..];
+        let literal = &literal[literal.find('"').unwrap() + 1..literal.find("\";").unwrap()];
+        let bytes: Vec<u8> = literal
+            .split("\\x")
+            .skip(1)
+            .map(|hex| u8::from_str_radix(&hex[..2], 16).unwrap())
+            .collect();
+        assert_eq!(FileDescriptorProto::decode(bytes.as_slice()).unwrap(), file);
+    }
+
+    #[test]
+    fn shares_bytes_and_strings_with_zero_copy() {
+        let file = echo_proto();
+        let mut generator = Generator::new(std::slice::from_ref(&file));
+        let (_, code) = generator.zero_copy(true).generate(&file).unwrap();
+        assert!(code.contains(
+            "    pub content: ::arpc::symphony::ByteString,\n    pub tags: Vec<::arpc::symphony::ByteString>,"
+        ));
+        assert!(code.contains("    pub r#type: ::arpc::Bytes,\n"));
+    }
+
+    #[test]
+    fn rejects_unsupported_fields() {
+        let mut file = echo_proto();
+        file.message_type[1]
+            .field
+            .push(field("other", field_type::MESSAGE, Some(".other.Thing")));
+        let err = Generator::new(std::slice::from_ref(&file))
+            .generate(&file)
+            .unwrap_err();
+        assert_eq!(err, "EchoResponse.other: unknown type .other.Thing");
+
+        let mut file = echo_proto();
+        file.message_type[1].field.push(field("signed", 17, None));
+        let err = Generator::new(std::slice::from_ref(&file))
+            .generate(&file)
+            .unwrap_err();
+        assert_eq!(err, "EchoResponse.signed: field type 17 is not supported");
+    }
+}
@@ -0,0 +1,199 @@
+//! Generates Symphony messages and aRPC stubs from `.proto` files in a
+//! `build.rs`, as `prost-build` does for protobuf:
+//!
+//! ```no_run
+//! // build.rs
+//! fn main() -> std::io::Result<()> {
+//!     symphony_build::compile_protos(&["proto/echo.proto"], &["proto"])
+//! }
+//! ```
+//!
+//! ```ignore
+//! // src/lib.rs
+//! mod echo {
+//!     include!(concat!(env!("OUT_DIR"), "/echo.syn.rs"));
+//! }
+//! ```
+//!
+//! The generated code is what `protoc-gen-symphony-rust` writes, and needs
+//! `arpc` with its `derive` feature. `protoc` is looked up like
+//! `prost-build` does: `$PROTOC`, or `protoc` on the `PATH`.
+
+pub mod descriptor;
+mod generate;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use prost::Message;
+
+use descriptor::FileDescriptorSet;
+pub use generate::Generator;
+
+/// Compiles `protos`, found under `includes`, into `$OUT_DIR`.
+pub fn compile_protos(
+    protos: &[impl AsRef<Path>],
+    includes: &[impl AsRef<Path>],
+) -> io::Result<()> {
+    Config::new().compile_protos(protos, includes)
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    out_dir: Option<PathBuf>,
+    zero_copy: bool,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Where to write the generated files, instead of `$OUT_DIR`.
+    pub fn out_dir(&mut self, path: impl Into<PathBuf>) -> &mut Config {
+        self.out_dir = Some(path.into());
+        self
+    }
+
+    /// Generates bytes and string fields that share the buffer a message
+    /// is received in; see [`Generator::zero_copy`].
+    pub fn zero_copy(&mut self, on: bool) -> &mut Config {
+        self.zero_copy = on;
+        self
+    }
+
+    /// Compiles `protos`, found under `includes`. Each proto becomes
+    /// `<name>.syn.rs`, its path relative to the include directory it is
+    /// in, as `protoc` names it.
+    pub fn compile_protos(
+        &self,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+    ) -> io::Result<()> {
+        let out_dir = match &self.out_dir {
+            Some(dir) => dir.clone(),
+            None => env::var_os("OUT_DIR")
+                .map(PathBuf::from)
+                .ok_or_else(|| io::Error::other("OUT_DIR is not set"))?,
+        };
+
+        println!("cargo:rerun-if-env-changed=PROTOC");
+        for path in protos
+            .iter()
+            .map(AsRef::as_ref)
+            .chain(includes.iter().map(AsRef::as_ref))
+        {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+
+        let set = run_protoc(&out_dir, protos, includes)?;
+        let mut generator = Generator::new(&set.file);
+        generator.zero_copy(self.zero_copy);
+        for proto in protos {
+            let name = proto_name(proto.as_ref(), includes);
+            let file = set
+                .file
+                .iter()
+                .find(|file| file.name() == name)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("protoc did not describe {}", name),
+                    )
+                })?;
+            let (generated, content) = generator
+                .generate(file)
+                .map_err(|e| io::Error::other(format!("{}: {}", name, e)))?;
+            let path = out_dir.join(generated);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, content)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `protoc` over `protos` and decodes the descriptors it writes,
+/// imports included so that their types resolve.
+fn run_protoc(
+    out_dir: &Path,
+    protos: &[impl AsRef<Path>],
+    includes: &[impl AsRef<Path>],
+) -> io::Result<FileDescriptorSet> {
+    let protoc = env::var_os("PROTOC").unwrap_or_else(|| "protoc".into());
+    let descriptors = out_dir.join("symphony-descriptors.bin");
+    let mut cmd = Command::new(&protoc);
+    cmd.arg("--include_imports").arg("-o").arg(&descriptors);
+    for include in includes {
+        cmd.arg("-I").arg(include.as_ref());
+    }
+    for proto in protos {
+        cmd.arg(proto.as_ref());
+    }
+
+    let output = cmd.output().map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to run {}: {}", Path::new(&protoc).display(), e),
+        )
+    })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "protoc failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let buf = fs::read(&descriptors)?;
+    FileDescriptorSet::decode(buf.as_slice())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The name `protoc` gives `proto`: its path relative to the first include
+/// directory containing it, with `/` separators.
+fn proto_name(proto: &Path, includes: &[impl AsRef<Path>]) -> String {
+    let canonical = fs::canonicalize(proto).unwrap_or_else(|_| proto.to_path_buf());
+    let relative = includes
+        .iter()
+        .filter_map(|include| fs::canonicalize(include.as_ref()).ok())
+        .find_map(|include| canonical.strip_prefix(include).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| proto.to_path_buf());
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PROTOS: &str = "../../cmd/symphony-gen-arpc/test";
+
+    #[test]
+    fn compiles_protos_into_out_dir() {
+        let out_dir = env::temp_dir().join(format!("symphony-build-{}", std::process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+        Config::new()
+            .out_dir(&out_dir)
+            .compile_protos(&[format!("{}/test.proto", TEST_PROTOS)], &[TEST_PROTOS])
+            .unwrap();
+
+        let code = fs::read_to_string(out_dir.join("test.syn.rs")).unwrap();
+        // Imports are described but not generated.
+        let generated: Vec<_> = fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().ends_with(".syn.rs"))
+            .collect();
+        fs::remove_dir_all(&out_dir).unwrap();
+        assert_eq!(generated, ["test.syn.rs"]);
+        assert!(code.contains(
+            "pub struct Leaf {\n    #[symphony(public)]\n    pub leaf_id: i32,\n    pub leaf_val: String,\n}"
+        ));
+    }
+}